@@ -0,0 +1,19 @@
+/* tslint:disable */
+/* eslint-disable */
+
+/* auto-generated by NAPI-RS */
+
+export interface LookupOptions {
+  /** Path of the SQLite DB for ASCII lookups. */
+  db?: string
+}
+/**
+ * Looks up `domain` (ASCII or IDN, auto-detected) and resolves to the same
+ * object as `spotspoof lookup`.
+ */
+export function lookup(domain: string, options?: LookupOptions | undefined | null): Promise<object>
+/**
+ * Lookalike permutations of `domain` (`[{domain, technique, unicode?}]`), by
+ * the named techniques or all of them, as `spotspoof export-rpz` generates.
+ */
+export function generate(domain: string, techniques?: Array<string> | undefined | null): Promise<Array<{ domain: string; technique: string; unicode?: string }>>
@@ -0,0 +1,106 @@
+/*
+Node.js bindings for the detection engine, so services written in Node can run
+lookups in-process instead of spawning `spotspoof` per request. Lookups and
+permutation runs execute on the libuv thread pool and resolve to the same JSON
+objects the CLI prints; bad input rejects the promise. ASCII lookups use the
+SQLite DB given as `db` (or named by `SPOTSPOOF_DB`), and only the most-phished
+list without one; IDN lookups check registration over DoH like the CLI.
+*/
+use anyhow::Result;
+use clap::ValueEnum;
+use napi::bindgen_prelude::AsyncTask;
+use napi::{Env, JsUnknown, Task};
+use napi_derive::napi;
+
+use spotspoof_cli::ascii_spoof::{self, AsciiOptions};
+use spotspoof_cli::idn;
+use spotspoof_cli::permute::{self, Permutation, Technique};
+use spotspoof_cli::types::{self, LookupResponse};
+
+/// The environment variable naming the SQLite DB when `db` is not given.
+const DB_ENV: &str = "SPOTSPOOF_DB";
+
+#[napi(object)]
+pub struct LookupOptions {
+    /// Path of the SQLite DB for ASCII lookups.
+    pub db: Option<String>,
+}
+
+pub struct Lookup {
+    domain: String,
+    db: Option<String>,
+}
+
+impl Task for Lookup {
+    type Output = LookupResponse;
+    type JsValue = JsUnknown;
+
+    fn compute(&mut self) -> napi::Result<LookupResponse> {
+        lookup_response(&self.domain, self.db.as_deref())
+            .map_err(|err| napi::Error::from_reason(err.to_string()))
+    }
+
+    fn resolve(&mut self, env: Env, output: LookupResponse) -> napi::Result<JsUnknown> {
+        env.to_js_value(&output)
+    }
+}
+
+/// Looks up `domain` (ASCII or IDN, auto-detected) and resolves to the same
+/// object as `spotspoof lookup`.
+#[napi(ts_return_type = "Promise<object>")]
+pub fn lookup(domain: String, options: Option<LookupOptions>) -> AsyncTask<Lookup> {
+    let db = options
+        .and_then(|options| options.db)
+        .or_else(|| std::env::var(DB_ENV).ok());
+    AsyncTask::new(Lookup { domain, db })
+}
+
+pub struct Generate {
+    domain: String,
+    techniques: Vec<String>,
+}
+
+impl Task for Generate {
+    type Output = Vec<Permutation>;
+    type JsValue = JsUnknown;
+
+    fn compute(&mut self) -> napi::Result<Vec<Permutation>> {
+        let techniques = self
+            .techniques
+            .iter()
+            .map(|name| {
+                Technique::from_str(name, true)
+                    .map_err(|_| napi::Error::from_reason(format!("unknown technique: {name}")))
+            })
+            .collect::<napi::Result<Vec<_>>>()?;
+        Ok(permute::permutations(&self.domain, &techniques))
+    }
+
+    fn resolve(&mut self, env: Env, output: Vec<Permutation>) -> napi::Result<JsUnknown> {
+        env.to_js_value(&output)
+    }
+}
+
+/// Lookalike permutations of `domain` (`[{domain, technique, unicode?}]`), by
+/// the named techniques or all of them, as `spotspoof export-rpz` generates.
+#[napi(ts_return_type = "Promise<Array<{ domain: string; technique: string; unicode?: string }>>")]
+pub fn generate(domain: String, techniques: Option<Vec<String>>) -> AsyncTask<Generate> {
+    AsyncTask::new(Generate {
+        domain,
+        techniques: techniques.unwrap_or_default(),
+    })
+}
+
+fn lookup_response(domain: &str, db: Option<&str>) -> Result<LookupResponse> {
+    let domain = types::parse_domain_input(domain)?;
+    Ok(if types::is_idn(&domain) {
+        LookupResponse::Idn(idn::lookup_idn(&domain)?)
+    } else {
+        LookupResponse::Ascii(match db {
+            Some(db) => {
+                ascii_spoof::lookup_ascii_with_options(&domain, db, &AsciiOptions::default())?
+            }
+            None => ascii_spoof::lookup_most_phished(&domain, &AsciiOptions::default()),
+        })
+    })
+}
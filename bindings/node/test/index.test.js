@@ -0,0 +1,18 @@
+const test = require('node:test')
+const assert = require('node:assert')
+
+const { lookup, generate } = require('..')
+
+test('lookup resolves to the CLI response', async () => {
+  const response = await lookup('https://WWW.PayPa1.com/login')
+  assert.strictEqual(response.q, 'paypa1.com')
+  assert.strictEqual(response.results[0].domain, 'paypal.com')
+  await assert.rejects(lookup('not a domain'), /invalid domain/)
+})
+
+test('generate resolves to permutations', async () => {
+  const permutations = await generate('paypal.com', ['omission', 'tld'])
+  assert.ok(permutations.some((p) => p.domain === 'paypl.com' && p.technique === 'omission'))
+  assert.ok(permutations.some((p) => p.domain === 'paypal.shop' && p.technique === 'tld'))
+  await assert.rejects(generate('paypal.com', ['soundex']), /unknown technique: soundex/)
+})
@@ -0,0 +1,31 @@
+fn main() {
+    // `self-update` picks the release asset built for this target.
+    println!(
+        "cargo:rustc-env=SPOTSPOOF_TARGET={}",
+        std::env::var("TARGET").expect("TARGET")
+    );
+
+    #[cfg(feature = "grpc")]
+    {
+        let protoc = protoc_bin_vendored::protoc_bin_path().expect("vendored protoc");
+        std::env::set_var("PROTOC", protoc);
+        tonic_build::compile_protos("proto/spotspoof.proto").expect("compile spotspoof.proto");
+    }
+    println!("cargo:rerun-if-changed=proto/spotspoof.proto");
+
+    #[cfg(feature = "ffi")]
+    {
+        let crate_dir = std::env::var("CARGO_MANIFEST_DIR").expect("CARGO_MANIFEST_DIR");
+        let config = cbindgen::Config::from_file(format!("{crate_dir}/cbindgen.toml"))
+            .expect("read cbindgen.toml");
+        // Only the ABI module is parsed; the rest of the crate is Rust-only.
+        cbindgen::Builder::new()
+            .with_src(format!("{crate_dir}/src/ffi.rs"))
+            .with_config(config)
+            .generate()
+            .expect("generate the C header")
+            .write_to_file(format!("{crate_dir}/include/spotspoof.h"));
+        println!("cargo:rerun-if-changed=src/ffi.rs");
+        println!("cargo:rerun-if-changed=cbindgen.toml");
+    }
+}
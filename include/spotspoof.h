@@ -0,0 +1,35 @@
+/* SpotSpoof C API. Generated by cbindgen from src/ffi.rs; do not edit. */
+
+#ifndef SPOTSPOOF_H
+#define SPOTSPOOF_H
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * Looks up `domain` (ASCII or IDN, auto-detected) and returns the response as
+ * JSON. Returns NULL only when `domain` is NULL. Free the result with
+ * `spotspoof_string_free`.
+ *
+ * # Safety
+ *
+ * `domain` must be NULL or point to a NUL-terminated string.
+ */
+char *spotspoof_lookup_json(const char *domain);
+
+/**
+ * Releases a string returned by this library. NULL is ignored.
+ *
+ * # Safety
+ *
+ * `value` must be NULL or a string returned by this library that has not
+ * been freed yet.
+ */
+void spotspoof_string_free(char *value);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* SPOTSPOOF_H */
@@ -0,0 +1,829 @@
+/*
+Alert delivery for monitoring runs. The lookalikes that `tail` and the scan
+commands report are sent to the webhooks, Slack incoming webhooks, and email
+addresses configured under `[alerts]`, through a durable outbox: every
+notification is first written to a SQLite table, one row per channel, then
+delivered. A failed delivery is retried with exponential backoff and, after
+`max_attempts`, moved to a dead-letter state, so an endpoint outage during a
+run delays alerts instead of dropping them. `spotspoof alerts retry` re-sends
+what is still queued (and, with `--dead`, what was given up on).
+Email goes to an SMTP relay (by default the local MTA) without TLS or auth.
+With `screenshots = true`, each alert carries a PNG of the live page, taken
+when it is queued: webhooks get it base64-encoded, emails as an attachment.
+*/
+use anyhow::{Context, Result};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use reqwest::header;
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::{info, warn};
+
+use crate::db;
+use crate::detections::Severity;
+use crate::net;
+use crate::scan::{self, ScanReport};
+use crate::screenshot::{self, ScreenshotConfig};
+use crate::types::LookupResponse;
+
+pub const DEFAULT_ALERTS_FILE: &str = "spotspoof-alerts.sqlite";
+pub const DEFAULT_SMTP_RELAY: &str = "127.0.0.1:25";
+pub const DEFAULT_SMTP_FROM: &str = "spotspoof@localhost";
+pub const DEFAULT_MAX_ATTEMPTS: u32 = 8;
+/// How often long-running commands retry due deliveries on their own.
+pub const RETRY_INTERVAL: Duration = Duration::from_secs(30);
+/// The delay before the first retry; each further one doubles it.
+const BASE_BACKOFF: Duration = Duration::from_secs(30);
+const MAX_BACKOFF: Duration = Duration::from_secs(3600);
+const SEND_TIMEOUT: Duration = Duration::from_secs(10);
+/// Entries delivered per pass, so a long outage drains gradually.
+const DELIVERY_BATCH: usize = 100;
+const SELECT: &str = "SELECT id, channel, target, state, attempts,
+	strftime('%Y-%m-%dT%H:%M:%SZ', created, 'unixepoch'),
+	strftime('%Y-%m-%dT%H:%M:%SZ', next_attempt, 'unixepoch'), last_error, payload
+	FROM alert_outbox";
+
+static INSTALLED: OnceLock<Alerts> = OnceLock::new();
+
+/// The `[alerts]` section of the config file.
+#[derive(Debug, Clone, Default, Deserialize, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct AlertsConfig {
+    /// URLs that receive each notification as a JSON POST.
+    pub webhook: Vec<String>,
+    /// Slack incoming webhook URLs.
+    pub slack: Vec<String>,
+    /// Email recipients.
+    pub email: Vec<String>,
+    /// `host:port` of the SMTP relay for `email` (default `127.0.0.1:25`).
+    pub smtp_relay: Option<String>,
+    /// Sender address of alert emails (default `spotspoof@localhost`).
+    pub smtp_from: Option<String>,
+    /// Deliveries tried before an entry is dead-lettered (default 8).
+    pub max_attempts: Option<u32>,
+    /// Only notify findings of this severity or above (default `low`).
+    pub min_severity: Option<Severity>,
+    /// Attach a screenshot of the live page (needs the `screenshot` feature).
+    pub screenshots: bool,
+}
+
+impl AlertsConfig {
+    /// The configured destinations.
+    pub fn channels(&self) -> Vec<Channel> {
+        let webhooks = self.webhook.iter().cloned().map(Channel::Webhook);
+        let slack = self.slack.iter().cloned().map(Channel::Slack);
+        let email = self.email.iter().cloned().map(Channel::Email);
+        webhooks.chain(slack).chain(email).collect()
+    }
+}
+
+/// Where a notification goes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Channel {
+    Webhook(String),
+    Slack(String),
+    Email(String),
+}
+
+impl Channel {
+    pub(crate) fn kind(&self) -> &'static str {
+        match self {
+            Channel::Webhook(_) => "webhook",
+            Channel::Slack(_) => "slack",
+            Channel::Email(_) => "email",
+        }
+    }
+
+    fn target(&self) -> &str {
+        match self {
+            Channel::Webhook(target) | Channel::Slack(target) | Channel::Email(target) => target,
+        }
+    }
+
+    fn from_row(kind: &str, target: String) -> Result<Self> {
+        match kind {
+            "webhook" => Ok(Channel::Webhook(target)),
+            "slack" => Ok(Channel::Slack(target)),
+            "email" => Ok(Channel::Email(target)),
+            other => anyhow::bail!("unknown alert channel {other}"),
+        }
+    }
+}
+
+/// What is sent about one lookalike.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Notification {
+    pub domain: String,
+    /// "resembles paypal.com (similarity 90)".
+    pub summary: String,
+    pub risk: u8,
+    pub severity: Severity,
+    /// The command that found it (`tail`, `scan`).
+    pub source: String,
+    /// Base64 PNG of the live page, with `screenshots = true`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub screenshot: Option<String>,
+}
+
+impl Notification {
+    pub fn new(source: &str, domain: &str, lookup: &LookupResponse) -> Self {
+        let risk = scan::risk(domain, lookup);
+        Self {
+            domain: domain.to_string(),
+            summary: scan::resemblance(lookup),
+            risk,
+            severity: Severity::from_risk(risk),
+            source: source.to_string(),
+            screenshot: None,
+        }
+    }
+
+    fn subject(&self) -> String {
+        format!(
+            "[spotspoof] {} lookalike: {}",
+            self.severity.as_str(),
+            self.domain
+        )
+    }
+
+    fn text(&self) -> String {
+        format!(
+            "{} {} (risk {}, {}, found by {})",
+            self.domain,
+            self.summary,
+            self.risk,
+            self.severity.as_str(),
+            self.source
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum DeliveryState {
+    /// Not delivered yet; retried once `next_attempt` passes.
+    Pending,
+    Delivered,
+    /// Gave up after `max_attempts`.
+    Dead,
+}
+
+impl DeliveryState {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            DeliveryState::Pending => "pending",
+            DeliveryState::Delivered => "delivered",
+            DeliveryState::Dead => "dead",
+        }
+    }
+
+    fn parse(state: &str) -> Self {
+        match state {
+            "delivered" => DeliveryState::Delivered,
+            "dead" => DeliveryState::Dead,
+            _ => DeliveryState::Pending,
+        }
+    }
+}
+
+/// One notification to one channel.
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct OutboxEntry {
+    pub id: i64,
+    pub channel: String,
+    pub target: String,
+    pub state: DeliveryState,
+    pub attempts: u32,
+    pub created: String,
+    pub next_attempt: String,
+    pub last_error: Option<String>,
+    pub notification: Notification,
+}
+
+/// The outcome of one delivery pass.
+#[derive(Debug, Clone, Copy, Default, Serialize, PartialEq, Eq)]
+pub struct DeliverySummary {
+    pub delivered: usize,
+    /// Failed and scheduled for another attempt.
+    pub retrying: usize,
+    /// Failed for the last time.
+    pub dead: usize,
+}
+
+pub struct Outbox {
+    conn: Mutex<Connection>,
+    max_attempts: u32,
+}
+
+impl Outbox {
+    /// Opens (creating if needed) the outbox at `path`.
+    pub fn open(path: &str, max_attempts: u32) -> Result<Self> {
+        Self::init(db::open_shared_store(path)?, max_attempts)
+    }
+
+    #[cfg(test)]
+    pub fn open_in_memory(max_attempts: u32) -> Result<Self> {
+        Self::init(Connection::open_in_memory()?, max_attempts)
+    }
+
+    fn init(conn: Connection, max_attempts: u32) -> Result<Self> {
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS alert_outbox (
+				id INTEGER PRIMARY KEY,
+				channel TEXT NOT NULL,
+				target TEXT NOT NULL,
+				payload TEXT NOT NULL,
+				state TEXT NOT NULL DEFAULT 'pending',
+				attempts INTEGER NOT NULL DEFAULT 0,
+				created INTEGER NOT NULL,
+				next_attempt INTEGER NOT NULL,
+				last_error TEXT
+			);
+			CREATE INDEX IF NOT EXISTS alert_outbox_due ON alert_outbox (state, next_attempt);",
+        )?;
+        Ok(Self {
+            conn: Mutex::new(conn),
+            max_attempts: max_attempts.max(1),
+        })
+    }
+
+    /// Queues `notification` for each of `channels`, due now.
+    pub fn enqueue(&self, channels: &[Channel], notification: &Notification) -> Result<()> {
+        let payload = serde_json::to_string(notification)?;
+        let now = now();
+        let mut conn = self.conn.lock().unwrap_or_else(|p| p.into_inner());
+        let tx = conn.transaction()?;
+        for channel in channels {
+            tx.prepare_cached(
+                "INSERT INTO alert_outbox (channel, target, payload, created, next_attempt)
+				VALUES (?1, ?2, ?3, ?4, ?4)",
+            )?
+            .execute(params![channel.kind(), channel.target(), payload, now])?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// The newest entries, optionally only those in `state`.
+    pub fn list(&self, state: Option<DeliveryState>, limit: usize) -> Result<Vec<OutboxEntry>> {
+        let conn = self.conn.lock().unwrap_or_else(|p| p.into_inner());
+        let mut stmt = conn.prepare_cached(&format!(
+            "{SELECT} WHERE ?1 IS NULL OR state = ?1 ORDER BY id DESC LIMIT ?2"
+        ))?;
+        let rows = stmt.query_map(params![state.map(DeliveryState::as_str), limit], entry)?;
+        rows.map(|row| row?).collect()
+    }
+
+    /// Pending entries whose next attempt is due at `now`, oldest first.
+    fn due(&self, now: i64) -> Result<Vec<OutboxEntry>> {
+        let conn = self.conn.lock().unwrap_or_else(|p| p.into_inner());
+        let mut stmt = conn.prepare_cached(&format!(
+            "{SELECT} WHERE state = 'pending' AND next_attempt <= ?1 ORDER BY id LIMIT ?2"
+        ))?;
+        let rows = stmt.query_map(params![now, DELIVERY_BATCH], entry)?;
+        rows.map(|row| row?).collect()
+    }
+
+    fn delivered(&self, id: i64) -> Result<()> {
+        let conn = self.conn.lock().unwrap_or_else(|p| p.into_inner());
+        conn.prepare_cached(
+            "UPDATE alert_outbox SET state = 'delivered', attempts = attempts + 1,
+				last_error = NULL WHERE id = ?1",
+        )?
+        .execute([id])?;
+        Ok(())
+    }
+
+    /// Records a failed attempt: the entry is retried after a backoff, or
+    /// dead-lettered once it has had `max_attempts`.
+    fn failed(&self, id: i64, error: &str, now: i64) -> Result<DeliveryState> {
+        let conn = self.conn.lock().unwrap_or_else(|p| p.into_inner());
+        let attempts: u32 = conn
+            .prepare_cached("SELECT attempts FROM alert_outbox WHERE id = ?1")?
+            .query_row([id], |row| row.get::<_, u32>(0))
+            .optional()?
+            .unwrap_or_default()
+            + 1;
+        let state = if attempts >= self.max_attempts {
+            DeliveryState::Dead
+        } else {
+            DeliveryState::Pending
+        };
+        let next_attempt = now + backoff(attempts).as_secs() as i64;
+        conn.prepare_cached(
+            "UPDATE alert_outbox SET state = ?2, attempts = ?3, next_attempt = ?4,
+				last_error = ?5 WHERE id = ?1",
+        )?
+        .execute(params![id, state.as_str(), attempts, next_attempt, error])?;
+        Ok(state)
+    }
+
+    /// Makes every pending entry due now and, with `dead`, gives the
+    /// dead-lettered ones a fresh set of attempts. Returns how many entries
+    /// were requeued.
+    pub fn requeue(&self, dead: bool) -> Result<usize> {
+        let conn = self.conn.lock().unwrap_or_else(|p| p.into_inner());
+        let mut requeued = conn
+            .prepare_cached("UPDATE alert_outbox SET next_attempt = ?1 WHERE state = 'pending'")?
+            .execute([now()])?;
+        if dead {
+            requeued += conn
+                .prepare_cached(
+                    "UPDATE alert_outbox SET state = 'pending', attempts = 0, next_attempt = ?1
+					WHERE state = 'dead'",
+                )?
+                .execute([now()])?;
+        }
+        Ok(requeued)
+    }
+
+    /// Tries every due entry once with `send`.
+    pub fn deliver_with(
+        &self,
+        mut send: impl FnMut(&Channel, &Notification) -> Result<()>,
+    ) -> Result<DeliverySummary> {
+        let now = now();
+        let mut summary = DeliverySummary::default();
+        for due in self.due(now)? {
+            let sent = Channel::from_row(&due.channel, due.target.clone())
+                .and_then(|channel| send(&channel, &due.notification));
+            match sent {
+                Ok(()) => {
+                    self.delivered(due.id)?;
+                    summary.delivered += 1;
+                }
+                Err(err) => {
+                    let state = self.failed(due.id, &format!("{err:#}"), now)?;
+                    warn!(
+                        kind = "alerts",
+                        id = due.id,
+                        channel = %due.channel,
+                        state = state.as_str(),
+                        "delivery failed: {err:#}"
+                    );
+                    match state {
+                        DeliveryState::Dead => summary.dead += 1,
+                        _ => summary.retrying += 1,
+                    }
+                }
+            }
+        }
+        Ok(summary)
+    }
+}
+
+fn entry(row: &rusqlite::Row) -> rusqlite::Result<Result<OutboxEntry>> {
+    let payload: String = row.get(8)?;
+    let notification = match serde_json::from_str(&payload) {
+        Ok(notification) => notification,
+        Err(err) => {
+            return Ok(Err(
+                anyhow::Error::new(err).context("invalid outbox payload")
+            ))
+        }
+    };
+    let state: String = row.get(3)?;
+    Ok(Ok(OutboxEntry {
+        id: row.get(0)?,
+        channel: row.get(1)?,
+        target: row.get(2)?,
+        state: DeliveryState::parse(&state),
+        attempts: row.get(4)?,
+        created: row.get(5)?,
+        next_attempt: row.get(6)?,
+        last_error: row.get(7)?,
+        notification,
+    }))
+}
+
+/// Sends notifications over HTTP and SMTP.
+pub struct Sender {
+    client: reqwest::blocking::Client,
+    smtp_relay: String,
+    smtp_from: String,
+}
+
+impl Sender {
+    pub fn new(config: &AlertsConfig) -> Result<Self> {
+        Ok(Self {
+            client: net::client_builder().timeout(SEND_TIMEOUT).build()?,
+            smtp_relay: config
+                .smtp_relay
+                .clone()
+                .unwrap_or_else(|| DEFAULT_SMTP_RELAY.to_string()),
+            smtp_from: config
+                .smtp_from
+                .clone()
+                .unwrap_or_else(|| DEFAULT_SMTP_FROM.to_string()),
+        })
+    }
+
+    /// Delivers `notification` once; retrying is the outbox's job.
+    pub fn send(&self, channel: &Channel, notification: &Notification) -> Result<()> {
+        let request = match channel {
+            Channel::Webhook(url) => self.client.post(url).json(notification),
+            Channel::Slack(url) => self
+                .client
+                .post(url)
+                .json(&serde_json::json!({ "text": notification.text() })),
+            Channel::Email(to) => return self.send_email(to, notification),
+        };
+        let response = request
+            .header(
+                header::USER_AGENT,
+                concat!("spotspoof/", env!("CARGO_PKG_VERSION")),
+            )
+            .send()?;
+        if !response.status().is_success() {
+            anyhow::bail!("HTTP {}", response.status());
+        }
+        Ok(())
+    }
+
+    fn send_email(&self, to: &str, notification: &Notification) -> Result<()> {
+        let addr = self
+            .smtp_relay
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| anyhow::anyhow!("cannot resolve {}", self.smtp_relay))?;
+        let stream = TcpStream::connect_timeout(&addr, SEND_TIMEOUT)
+            .with_context(|| format!("cannot connect to {}", self.smtp_relay))?;
+        stream.set_read_timeout(Some(SEND_TIMEOUT))?;
+        stream.set_write_timeout(Some(SEND_TIMEOUT))?;
+        let mut smtp = Smtp {
+            reader: BufReader::new(stream.try_clone()?),
+            writer: stream,
+        };
+        smtp.expect(None, 220)?;
+        smtp.expect(Some("EHLO spotspoof"), 250)?;
+        smtp.expect(Some(&format!("MAIL FROM:<{}>", self.smtp_from)), 250)?;
+        smtp.expect(Some(&format!("RCPT TO:<{to}>")), 250)?;
+        smtp.expect(Some("DATA"), 354)?;
+        let message = format!(
+            "{}\r\n.",
+            dot_stuff(&email_message(&self.smtp_from, to, notification))
+        );
+        smtp.expect(Some(&message), 250)?;
+        let _ = smtp.expect(Some("QUIT"), 221);
+        Ok(())
+    }
+}
+
+/// The headers and body of an alert email; `multipart/mixed` with the PNG
+/// attached when there is a screenshot.
+fn email_message(from: &str, to: &str, notification: &Notification) -> String {
+    let headers = format!(
+        "From: {from}\nTo: {to}\nSubject: {}\nMIME-Version: 1.0",
+        notification.subject()
+    );
+    let text = notification.text();
+    let Some(png) = &notification.screenshot else {
+        return format!("{headers}\nContent-Type: text/plain; charset=utf-8\n\n{text}");
+    };
+    const BOUNDARY: &str = "spotspoof-screenshot";
+    let png = png
+        .as_bytes()
+        .chunks(76)
+        .map(|line| String::from_utf8_lossy(line).into_owned())
+        .collect::<Vec<_>>()
+        .join("\n");
+    format!(
+        "{headers}\nContent-Type: multipart/mixed; boundary=\"{BOUNDARY}\"\n\n\
+        --{BOUNDARY}\nContent-Type: text/plain; charset=utf-8\n\n{text}\n\
+        --{BOUNDARY}\nContent-Type: image/png\nContent-Transfer-Encoding: base64\n\
+        Content-Disposition: attachment; filename=\"{}.png\"\n\n{png}\n--{BOUNDARY}--",
+        notification.domain
+    )
+}
+
+struct Smtp {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+}
+
+impl Smtp {
+    /// Sends `command` (if any) and fails unless the reply code is `code`.
+    fn expect(&mut self, command: Option<&str>, code: u16) -> Result<()> {
+        if let Some(command) = command {
+            write!(self.writer, "{command}\r\n")?;
+            self.writer.flush()?;
+        }
+        loop {
+            let mut line = String::new();
+            if self.reader.read_line(&mut line)? == 0 {
+                anyhow::bail!("SMTP relay closed the connection");
+            }
+            // Multi-line replies continue with `250-`.
+            if line.as_bytes().get(3) == Some(&b'-') {
+                continue;
+            }
+            let reply: u16 = line.get(..3).and_then(|c| c.parse().ok()).unwrap_or(0);
+            if reply != code {
+                anyhow::bail!("SMTP relay replied {}", line.trim_end());
+            }
+            return Ok(());
+        }
+    }
+}
+
+/// `text` with CRLF line ends and leading dots doubled, as DATA requires.
+fn dot_stuff(text: &str) -> String {
+    text.lines()
+        .map(|line| match line.starts_with('.') {
+            true => format!(".{line}"),
+            false => line.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join("\r\n")
+}
+
+/// The delay after the `attempts`-th failure.
+fn backoff(attempts: u32) -> Duration {
+    BASE_BACKOFF
+        .saturating_mul(2u32.saturating_pow(attempts.saturating_sub(1)))
+        .min(MAX_BACKOFF)
+}
+
+/// The configured channels with their outbox and sender.
+pub struct Alerts {
+    pub outbox: Outbox,
+    channels: Vec<Channel>,
+    sender: Sender,
+    min_severity: Severity,
+    screenshot: Option<ScreenshotConfig>,
+}
+
+impl Alerts {
+    pub fn new(outbox: Outbox, config: &AlertsConfig) -> Result<Self> {
+        Ok(Self {
+            outbox,
+            channels: config.channels(),
+            sender: Sender::new(config)?,
+            min_severity: config.min_severity.unwrap_or(Severity::Low),
+            screenshot: None,
+        })
+    }
+
+    /// Attaches a screenshot taken with `config` to every alert queued from
+    /// now on. Fails when this build cannot take screenshots.
+    pub fn with_screenshots(mut self, config: &ScreenshotConfig) -> Result<Self> {
+        screenshot::check_supported()?;
+        self.screenshot = Some(config.clone());
+        Ok(self)
+    }
+
+    /// Queues `notification` for every channel, unless it is below
+    /// `min_severity`.
+    pub fn enqueue(&self, notification: &Notification) -> Result<()> {
+        if notification.severity < self.min_severity {
+            return Ok(());
+        }
+        match &self.screenshot {
+            Some(config) if notification.screenshot.is_none() && !net::offline() => {
+                let mut notification = notification.clone();
+                match screenshot::capture(&notification.domain, config) {
+                    Ok(png) => notification.screenshot = Some(STANDARD.encode(png)),
+                    Err(err) => {
+                        warn!(kind = "alerts", domain = %notification.domain, "no screenshot: {err:#}")
+                    }
+                }
+                self.outbox.enqueue(&self.channels, &notification)
+            }
+            _ => self.outbox.enqueue(&self.channels, notification),
+        }
+    }
+
+    /// Tries every due entry once.
+    pub fn deliver(&self) -> Result<DeliverySummary> {
+        net::ensure_online("Sending alerts")?;
+        self.outbox
+            .deliver_with(|channel, notification| self.sender.send(channel, notification))
+    }
+}
+
+/// Makes `alerts` the process-wide alert queue. Only the first call takes
+/// effect.
+pub fn install(alerts: Alerts) {
+    let _ = INSTALLED.set(alerts);
+}
+
+pub fn installed() -> Option<&'static Alerts> {
+    INSTALLED.get()
+}
+
+/// Queues and tries to deliver a notification about a lookalike `source`
+/// found, if alerts are configured. Errors are logged, never returned.
+pub fn notify(source: &str, domain: &str, lookup: &LookupResponse) {
+    let Some(alerts) = installed() else {
+        return;
+    };
+    if let Err(err) = alerts.enqueue(&Notification::new(source, domain, lookup)) {
+        warn!(kind = "alerts", domain = %domain, "could not queue alert: {err:#}");
+    }
+    deliver();
+}
+
+/// Notifies every finding of a scan, then delivers them in one pass.
+pub fn notify_findings(report: &ScanReport) {
+    let Some(alerts) = installed() else {
+        return;
+    };
+    for finding in &report.findings {
+        let notification = Notification::new("scan", &finding.domain, &finding.lookup);
+        if let Err(err) = alerts.enqueue(&notification) {
+            warn!(kind = "alerts", domain = %finding.domain, "could not queue alert: {err:#}");
+        }
+    }
+    deliver();
+}
+
+/// Tries every due entry of the installed queue once. Offline, entries stay
+/// queued for `alerts retry`.
+pub fn deliver() {
+    let Some(alerts) = installed() else {
+        return;
+    };
+    if net::offline() {
+        return;
+    }
+    match alerts.deliver() {
+        Ok(summary) if summary == DeliverySummary::default() => {}
+        Ok(summary) => info!(
+            kind = "alerts",
+            delivered = summary.delivered,
+            retrying = summary.retrying,
+            dead = summary.dead
+        ),
+        Err(err) => warn!(kind = "alerts", "delivery pass failed: {err:#}"),
+    }
+}
+
+/// Retries due deliveries every `interval` in the background, for
+/// long-running commands that may go quiet after an outage.
+pub fn spawn_retries(interval: Duration) {
+    if installed().is_none() {
+        return;
+    }
+    std::thread::spawn(move || loop {
+        std::thread::sleep(interval);
+        deliver();
+    });
+}
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs() as i64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    fn notification(domain: &str) -> Notification {
+        Notification {
+            domain: domain.to_string(),
+            summary: "resembles paypal.com (similarity 90)".to_string(),
+            risk: 90,
+            severity: Severity::High,
+            source: "tail".to_string(),
+            screenshot: None,
+        }
+    }
+
+    #[test]
+    fn failed_deliveries_back_off_then_dead_letter() {
+        let outbox = Outbox::open_in_memory(2).unwrap();
+        let channels = [
+            Channel::Webhook("https://hooks.example/a".to_string()),
+            Channel::Email("soc@example.com".to_string()),
+        ];
+        outbox
+            .enqueue(&channels, &notification("paypa1.com"))
+            .unwrap();
+
+        let summary = outbox
+            .deliver_with(|channel, _| match channel {
+                Channel::Email(_) => Ok(()),
+                _ => anyhow::bail!("HTTP 503"),
+            })
+            .unwrap();
+        assert_eq!(
+            summary,
+            DeliverySummary {
+                delivered: 1,
+                retrying: 1,
+                dead: 0
+            }
+        );
+        let pending = outbox.list(Some(DeliveryState::Pending), 10).unwrap();
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].last_error.as_deref(), Some("HTTP 503"));
+        assert_eq!(pending[0].notification, notification("paypa1.com"));
+
+        // Backing off: nothing is due until requeued.
+        let idle = outbox.deliver_with(|_, _| Ok(())).unwrap();
+        assert_eq!(idle, DeliverySummary::default());
+        assert_eq!(outbox.requeue(false).unwrap(), 1);
+        let summary = outbox.deliver_with(|_, _| anyhow::bail!("down")).unwrap();
+        assert_eq!(summary.dead, 1);
+        assert_eq!(outbox.list(Some(DeliveryState::Dead), 10).unwrap().len(), 1);
+
+        assert_eq!(outbox.requeue(true).unwrap(), 1);
+        let summary = outbox.deliver_with(|_, _| Ok(())).unwrap();
+        assert_eq!(summary.delivered, 1);
+        assert_eq!(
+            outbox
+                .list(Some(DeliveryState::Delivered), 10)
+                .unwrap()
+                .len(),
+            2
+        );
+        assert_eq!(backoff(1), BASE_BACKOFF);
+        assert_eq!(backoff(20), MAX_BACKOFF);
+    }
+
+    #[test]
+    fn emails_go_through_the_smtp_relay() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let relay = listener.local_addr().unwrap().to_string();
+        let server = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut writer = stream;
+            let mut transcript = Vec::new();
+            writer.write_all(b"220 relay ready\r\n").unwrap();
+            let mut in_data = false;
+            loop {
+                let mut line = String::new();
+                if reader.read_line(&mut line).unwrap() == 0 {
+                    break;
+                }
+                let line = line.trim_end().to_string();
+                let reply: &[u8] = if in_data {
+                    if line != "." {
+                        transcript.push(line);
+                        continue;
+                    }
+                    in_data = false;
+                    b"250 queued\r\n"
+                } else if line.starts_with("EHLO") {
+                    b"250-relay\r\n250 8BITMIME\r\n"
+                } else if line == "DATA" {
+                    in_data = true;
+                    b"354 go ahead\r\n"
+                } else if line == "QUIT" {
+                    writer.write_all(b"221 bye\r\n").unwrap();
+                    break;
+                } else {
+                    b"250 ok\r\n"
+                };
+                transcript.push(line);
+                writer.write_all(reply).unwrap();
+            }
+            transcript
+        });
+
+        let sender = Sender::new(&AlertsConfig {
+            smtp_relay: Some(relay),
+            ..Default::default()
+        })
+        .unwrap();
+        sender
+            .send(
+                &Channel::Email("soc@example.com".to_string()),
+                &notification("paypa1.com"),
+            )
+            .unwrap();
+        let transcript = server.join().unwrap();
+        assert!(transcript.contains(&"RCPT TO:<soc@example.com>".to_string()));
+        assert!(transcript.contains(&"Subject: [spotspoof] high lookalike: paypa1.com".to_string()));
+        assert!(transcript
+            .iter()
+            .any(|line| line.starts_with("paypa1.com resembles")));
+    }
+
+    #[test]
+    fn screenshots_are_attached_to_emails() {
+        let notification = Notification {
+            screenshot: Some("iVBORw0KGgo".repeat(10)),
+            ..notification("paypa1.com")
+        };
+        let message = email_message("spotspoof@example.com", "soc@example.com", &notification);
+        assert!(
+            message.contains("Content-Type: multipart/mixed; boundary=\"spotspoof-screenshot\"")
+        );
+        assert!(message.contains("Content-Disposition: attachment; filename=\"paypa1.com.png\""));
+        assert!(message.lines().all(|line| line.len() <= 998));
+        assert!(message
+            .lines()
+            .any(|line| line.len() == 76 && line.starts_with("iVBORw0KGgo")));
+        assert!(message.ends_with("--spotspoof-screenshot--"));
+    }
+}
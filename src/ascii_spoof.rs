@@ -1,15 +1,23 @@
 /*
 ASCII lookalike detection logic. Normalises input domains, scores similarity,
-checks a curated most-phished list, and queries the local SQLite domain database
-to return likely spoof candidates.
+checks a curated most-phished list (and its plain brand names, see `brand`), and
+queries the local SQLite domain database to return likely spoof candidates.
 */
 use anyhow::Result;
 use once_cell::sync::Lazy;
 use serde::Deserialize;
 use std::collections::HashSet;
+use std::ops::Deref;
 
+use crate::brand;
+use crate::candidates::{shares_affix, CandidateQuery, CandidateSource, CandidateStrategies};
+#[cfg(feature = "native")]
 use crate::db;
-use crate::types::{AsciiResponse, AsciiResult};
+use crate::ensemble;
+#[cfg(feature = "native")]
+use crate::index::{FstIndex, SqlIndex};
+use crate::phonetic;
+use crate::types::{self, Algorithm, AsciiResponse, AsciiResult};
 
 const LENGTH_BAND: usize = 2;
 const MAX_CANDIDATES: usize = 5000;
@@ -22,6 +30,9 @@ struct MostPhishedEntry {
     base: String,
     #[serde(default)]
     aliases: Vec<String>,
+    /// Plain brand names, matched against the domain's second-level label.
+    #[serde(default)]
+    brands: Vec<String>,
 }
 
 static MOST_PHISHED: Lazy<Vec<MostPhishedEntry>> = Lazy::new(|| {
@@ -29,57 +40,368 @@ static MOST_PHISHED: Lazy<Vec<MostPhishedEntry>> = Lazy::new(|| {
     serde_json::from_str(data).expect("most-phished.json must be valid JSON")
 });
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct AsciiOptions {
+    pub min_similarity: u8,
+    pub max_results: usize,
+    pub algorithm: Algorithm,
+    pub strategies: CandidateStrategies,
+    /// How far candidate lengths may differ from the query's.
+    pub length_band: usize,
+    /// Most candidates pulled from the DB or an index per lookup.
+    pub max_candidates: usize,
+    /// Also score the query with leet substitutions undone (`g00g1e.com` as
+    /// `google.com`), keeping the better of the two scores.
+    pub leet: bool,
+    /// Blend in how alike the names sound (`gugel.com` and `google.com`), so
+    /// sound-alikes a few edits away still reach the threshold.
+    pub phonetic: bool,
+    /// Attach each result's per-scorer breakdown.
+    pub explain: bool,
+    /// Skip DB candidates whose name shares neither a prefix nor a suffix
+    /// this long with the query's, before any edit distance is computed
+    /// (see `candidates::shares_affix`). 0 turns the prefilter off.
+    pub min_affix: usize,
+}
+
+impl Default for AsciiOptions {
+    fn default() -> Self {
+        Self {
+            min_similarity: MIN_SIMILARITY,
+            max_results: MAX_RESULTS,
+            algorithm: Algorithm::Levenshtein,
+            strategies: CandidateStrategies::default(),
+            length_band: LENGTH_BAND,
+            max_candidates: MAX_CANDIDATES,
+            leet: false,
+            phonetic: false,
+            explain: false,
+            min_affix: 0,
+        }
+    }
+}
+
+impl AsciiOptions {
+    /// The edit-distance similarity a candidate needs to end up at `min`:
+    /// with a perfect phonetic match, the blend reaches `min` from
+    /// `2 * min - 100`, and other ensemble scorers lower the bar further.
+    fn edit_floor(&self, min: u8) -> u8 {
+        let min = if self.phonetic {
+            (u16::from(min) * 2).saturating_sub(100) as u8
+        } else {
+            min
+        };
+        ensemble::installed().edit_floor(min)
+    }
+}
+
+#[cfg(all(test, feature = "native"))]
 pub fn lookup_ascii(domain: &str, db_path: &str) -> Result<AsciiResponse> {
-    let results = detect_impersonation(domain, db_path)?;
+    lookup_ascii_with_options(domain, db_path, &AsciiOptions::default())
+}
+
+#[cfg(feature = "native")]
+pub fn lookup_ascii_with_options(
+    domain: &str,
+    db_path: &str,
+    options: &AsciiOptions,
+) -> Result<AsciiResponse> {
+    lookup_ascii_with(domain, || open_candidate_source(db_path), options)
+}
+
+/// The best candidate source available for `db_path`: an up-to-date FST, then
+/// an index table inside the DB, then the plain band scan.
+#[cfg(feature = "native")]
+pub fn open_candidate_source(db_path: &str) -> Result<Box<dyn CandidateSource>> {
+    if let Some(index) = FstIndex::open_for_db(db_path)? {
+        return Ok(Box::new(index));
+    }
+    let conn = db::open(db_path, false)?;
+    if let Some(sql_index) = SqlIndex::detect(&conn)? {
+        return Ok(Box::new(sql_index.with(Box::new(conn))));
+    }
+    Ok(Box::new(conn))
+}
+
+/// Like `lookup_ascii_with_options`, but takes its candidate source from
+/// `connect` (e.g. a pool checkout or the in-memory index), which is only
+/// called when the DB is consulted.
+pub fn lookup_ascii_with<C, S, F>(
+    domain: &str,
+    connect: F,
+    options: &AsciiOptions,
+) -> Result<AsciiResponse>
+where
+    C: Deref<Target = S>,
+    S: CandidateSource + ?Sized,
+    F: FnOnce() -> Result<C>,
+{
+    let (mut results, exact_match, considered) = detect_impersonation(domain, connect, options)?;
+    explain(domain, &mut results, options);
     Ok(AsciiResponse {
         q: domain.to_string(),
         ascii: true,
         puny: false,
+        exact_match,
+        protected_brand: protected_brand(domain),
         results,
+        dataset: None,
+        total_candidates_considered: Some(considered),
+        passive_dns: None,
     })
 }
 
-fn detect_impersonation(domain: &str, db_path: &str) -> Result<Vec<AsciiResult>> {
-    let most_phished_results = detect_from_most_phished(domain);
-    if !most_phished_results.is_empty() {
-        return Ok(most_phished_results);
+/// Like `lookup_ascii_with`, for many domains at once: their candidate
+/// queries go to `source` together (see `CandidateSource::candidates_batch`).
+pub fn lookup_ascii_batch<S: CandidateSource + ?Sized>(
+    domains: &[String],
+    source: &S,
+    options: &AsciiOptions,
+) -> Result<Vec<AsciiResponse>> {
+    let plans: Vec<Plan> = domains.iter().map(|domain| plan(domain, options)).collect();
+    let queries: Vec<CandidateQuery> = plans
+        .iter()
+        .flat_map(|plan| match plan {
+            Plan::Settled(..) => Vec::new(),
+            Plan::Fetch { normalized, leet } => candidate_queries(normalized, leet, options),
+        })
+        .collect();
+    let mut fetched = source.candidates_batch(&queries)?.into_iter();
+
+    let mut responses = Vec::with_capacity(domains.len());
+    for (domain, plan) in domains.iter().zip(&plans) {
+        let (mut results, exact_match, considered) = match plan {
+            Plan::Settled(results, exact_match, considered) => {
+                (results.clone(), *exact_match, *considered)
+            }
+            Plan::Fetch { normalized, leet } => {
+                let lists = fetched.by_ref().take(1 + usize::from(leet.is_some()));
+                score_candidates(normalized, leet, merge(lists), options)
+            }
+        };
+        explain(domain, &mut results, options);
+        responses.push(AsciiResponse {
+            q: domain.clone(),
+            ascii: true,
+            puny: false,
+            exact_match,
+            protected_brand: protected_brand(domain),
+            results,
+            dataset: None,
+            total_candidates_considered: Some(considered),
+            passive_dns: None,
+        });
     }
+    Ok(responses)
+}
 
+/// What detecting `domain` needs: nothing more when the most-phished list
+/// settles it, otherwise candidates for its normalized and leet forms.
+enum Plan {
+    Settled(Vec<AsciiResult>, bool, usize),
+    Fetch {
+        normalized: String,
+        leet: Option<String>,
+    },
+}
+
+fn plan(domain: &str, options: &AsciiOptions) -> Plan {
     let normalized = normalize(domain);
-    let first_char = normalized.chars().next();
+    let (most_phished_results, most_phished) = most_phished(domain, options);
+    if most_phished || !most_phished_results.is_empty() {
+        return Plan::Settled(most_phished_results, most_phished, MOST_PHISHED.len());
+    }
+    if normalized.is_empty() {
+        return Plan::Settled(Vec::new(), false, MOST_PHISHED.len());
+    }
+    // Leet substitutions are one character each, so the de-leeted form has
+    // the same length but may start with another letter (`4pple.com`).
+    let leet = leet_form(&normalized, options);
+    Plan::Fetch { normalized, leet }
+}
+
+/// The candidate query for `normalized`, followed by one for `leet` if any.
+fn candidate_queries<'a>(
+    normalized: &'a str,
+    leet: &'a Option<String>,
+    options: &AsciiOptions,
+) -> Vec<CandidateQuery<'a>> {
     let length = normalized.len();
+    let query = |domain: &'a str| CandidateQuery {
+        domain,
+        first_char: domain.chars().next().unwrap_or_default(),
+        min_len: length.saturating_sub(options.length_band),
+        max_len: length + options.length_band,
+        max_distance: max_distance(length, options),
+        limit: options.max_candidates,
+        strategies: options.strategies,
+        min_affix: options.min_affix,
+    };
+    std::iter::once(normalized)
+        .chain(leet.as_deref())
+        .map(query)
+        .collect()
+}
 
-    if first_char.is_none() {
-        return Ok(Vec::new());
-    }
+/// The candidate lists concatenated in order, without duplicates.
+fn merge(lists: impl IntoIterator<Item = Vec<String>>) -> Vec<String> {
+    let mut seen = HashSet::new();
+    lists
+        .into_iter()
+        .flatten()
+        .filter(|candidate| seen.insert(candidate.clone()))
+        .collect()
+}
 
-    let conn = db::open(db_path)?;
-    let candidates = db::fetch_candidates(
-        &conn,
-        first_char.unwrap(),
-        length.saturating_sub(LENGTH_BAND),
-        length + LENGTH_BAND,
-        MAX_CANDIDATES,
-    )?;
+/// Candidates scored against `domain`, whether `domain` itself is a known
+/// legitimate domain, and how many domains were scored. The query is never
+/// listed as a spoof of itself.
+fn detect_impersonation<C, S, F>(
+    domain: &str,
+    connect: F,
+    options: &AsciiOptions,
+) -> Result<(Vec<AsciiResult>, bool, usize)>
+where
+    C: Deref<Target = S>,
+    S: CandidateSource + ?Sized,
+    F: FnOnce() -> Result<C>,
+{
+    let (normalized, leet) = match plan(domain, options) {
+        Plan::Settled(results, exact_match, considered) => {
+            return Ok((results, exact_match, considered));
+        }
+        Plan::Fetch { normalized, leet } => (normalized, leet),
+    };
+    let source = connect()?;
+    let mut lists = Vec::new();
+    for query in candidate_queries(&normalized, &leet, options) {
+        lists.push(source.candidates(&query)?);
+    }
+    Ok(score_candidates(&normalized, &leet, merge(lists), options))
+}
 
-    let mut scored: Vec<AsciiResult> = candidates
+fn score_candidates(
+    normalized: &str,
+    leet: &Option<String>,
+    candidates: Vec<String>,
+    options: &AsciiOptions,
+) -> (Vec<AsciiResult>, bool, usize) {
+    let considered = MOST_PHISHED.len() + candidates.len();
+    let mut exact_match = false;
+    // The DB carries no popularity rank, so its candidates tie on rank.
+    let scored: Vec<(usize, AsciiResult)> = candidates
         .into_iter()
-        .map(|candidate| {
-            let similarity = similarity_ratio(&normalized, &candidate);
-            AsciiResult {
-                domain: candidate,
-                similarity,
+        .filter_map(|candidate| {
+            if candidate == normalized {
+                exact_match = true;
+                return None;
+            }
+            // Fuzzy indexes do not apply the prefilter themselves.
+            let affix = |input: &str| shares_affix(input, &candidate, options.min_affix);
+            if !affix(normalized) && !leet.as_deref().is_some_and(affix) {
+                return None;
             }
+            let similarity =
+                score(options, normalized, &candidate, options.min_similarity)
+                    .max(leet.as_deref().and_then(|leet| {
+                        score(options, leet, &candidate, options.min_similarity)
+                    }))?;
+            Some((
+                0,
+                AsciiResult {
+                    domain: candidate,
+                    similarity,
+                    breakdown: None,
+                },
+            ))
         })
-        .filter(|result| result.similarity >= MIN_SIMILARITY)
         .collect();
 
-    scored.sort_by(|a, b| b.similarity.cmp(&a.similarity));
-    scored.truncate(MAX_RESULTS);
-    Ok(scored)
+    (
+        rank_results(scored, options.max_results),
+        exact_match,
+        considered,
+    )
 }
 
-fn detect_from_most_phished(domain: &str) -> Vec<AsciiResult> {
+/// Attaches the per-scorer breakdown of each result when `options.explain` is
+/// on. Breakdowns compare full domains, the query's de-leeted form when that
+/// scores higher, so a result matched on its base name or boosted by
+/// `options.phonetic` can be more similar than its breakdown's mean.
+fn explain(domain: &str, results: &mut [AsciiResult], options: &AsciiOptions) {
+    if !options.explain {
+        return;
+    }
+    let weights = ensemble::installed();
+    let input = normalize(domain);
+    let leet = leet_form(&input, options);
+    for result in results {
+        result.breakdown = std::iter::once(&input)
+            .chain(&leet)
+            .map(|input| weights.breakdown(options.algorithm, input, &result.domain))
+            .max_by_key(|breakdown| ensemble::Weights::mean_of(breakdown));
+    }
+}
+
+/// Largest edit distance that can still reach `min_similarity` for a query of
+/// `length` bytes against a candidate at the top of the length band.
+fn max_distance(length: usize, options: &AsciiOptions) -> u32 {
+    let longest = (length + options.length_band) as f32;
+    let allowed = longest * (100.5 - f32::from(options.edit_floor(options.min_similarity))) / 100.0;
+    allowed.floor() as u32
+}
+
+/// Lookup against the built-in most-phished list alone, for builds without a
+/// DB: the same response `lookup_ascii_with` gives whenever that list matches,
+/// and no results otherwise.
+pub fn lookup_most_phished(domain: &str, options: &AsciiOptions) -> AsciiResponse {
+    let (mut results, exact_match) = most_phished(domain, options);
+    explain(domain, &mut results, options);
+    AsciiResponse {
+        q: domain.to_string(),
+        ascii: true,
+        puny: false,
+        exact_match,
+        protected_brand: protected_brand(domain),
+        results,
+        dataset: None,
+        total_candidates_considered: Some(MOST_PHISHED.len()),
+        passive_dns: None,
+    }
+}
+
+/// Most-phished domains resembling `domain`, plus whether it is one of them.
+fn most_phished(domain: &str, options: &AsciiOptions) -> (Vec<AsciiResult>, bool) {
+    let results = detect_from_most_phished(domain, options);
+    let listed = owning_entry(&normalize(domain)).is_some();
+    (results, listed)
+}
+
+/// The most-phished domain `domain` belongs to: the brand's own domain (or a
+/// dotted alias, e.g. `anz.com` of `anz.com.au`) or a host under it. Such a
+/// query is the protected brand rather than an impersonation of it.
+pub fn protected_brand(domain: &str) -> Option<String> {
+    owning_entry(&normalize(domain)).map(|entry| entry.domain.clone())
+}
+
+fn owning_entry(normalized: &str) -> Option<&'static MostPhishedEntry> {
+    let host = normalized.trim_end_matches('.');
+    let owns = |owned: &str| {
+        host == owned
+            || host
+                .strip_suffix(owned)
+                .is_some_and(|prefix| prefix.ends_with('.'))
+    };
+    MOST_PHISHED.iter().find(|entry| {
+        owns(&entry.domain)
+            || entry
+                .aliases
+                .iter()
+                .any(|alias| alias.contains('.') && owns(alias))
+    })
+}
+
+fn detect_from_most_phished(domain: &str, options: &AsciiOptions) -> Vec<AsciiResult> {
     let input = normalize(domain);
     let input_base = get_base_domain(&input);
     let mut candidates = HashSet::new();
@@ -87,10 +409,15 @@ fn detect_from_most_phished(domain: &str) -> Vec<AsciiResult> {
     candidates.insert(input_base.clone());
     candidates.insert(strip_non_alnum(&input));
     candidates.insert(strip_non_alnum(&input_base));
+    for candidate in candidates.clone() {
+        candidates.extend(leet_form(&candidate, options));
+    }
+    let runs = brand_runs(&input, options);
+    let own = owning_entry(&input).map(|entry| entry.domain.as_str());
 
     let mut results = Vec::new();
 
-    for entry in MOST_PHISHED.iter() {
+    for (rank, entry) in MOST_PHISHED.iter().enumerate() {
         let mut entry_targets = HashSet::new();
         entry_targets.insert(entry.domain.as_str());
         entry_targets.insert(entry.base.as_str());
@@ -101,28 +428,148 @@ fn detect_from_most_phished(domain: &str) -> Vec<AsciiResult> {
         let mut best: u8 = 0;
         for candidate in candidates.iter() {
             for target in entry_targets.iter() {
-                let score = similarity_ratio(candidate, target);
-                if score > best {
-                    best = score;
+                let floor = best.max(options.min_similarity);
+                if let Some(score) = score(options, candidate, target, floor) {
+                    best = best.max(score);
                 }
             }
         }
+        for name in &entry.brands {
+            let floor = best.max(options.min_similarity);
+            if let Some(score) = score_brand(options, &runs, &brand::fold(name), floor) {
+                best = best.max(score);
+            }
+        }
 
-        if best >= MIN_SIMILARITY {
-            results.push(AsciiResult {
-                domain: entry.domain.clone(),
-                similarity: best,
-            });
+        if best >= options.min_similarity && own != Some(entry.domain.as_str()) {
+            results.push((
+                rank,
+                AsciiResult {
+                    domain: entry.domain.clone(),
+                    similarity: best,
+                    breakdown: None,
+                },
+            ));
         }
     }
 
-    results.sort_by(|a, b| b.similarity.cmp(&a.similarity));
-    results.truncate(MAX_RESULTS);
-    results
+    rank_results(results, options.max_results)
 }
 
+/// Scores a domain against caller-supplied targets (e.g. a tenant's brands),
+/// comparing both full domains and their bases as the most-phished check does.
+/// Targets without a dot are brand names (see `brand`).
+pub fn score_targets(domain: &str, targets: &[String], options: &AsciiOptions) -> Vec<AsciiResult> {
+    let input = normalize(domain);
+    let mut inputs = vec![(input.clone(), get_base_domain(&input))];
+    if let Some(leet) = leet_form(&input, options) {
+        let leet_base = get_base_domain(&leet);
+        inputs.push((leet, leet_base));
+    }
+    let runs = brand_runs(&input, options);
+
+    let results: Vec<(usize, AsciiResult)> = targets
+        .iter()
+        .enumerate()
+        .filter_map(|(rank, target)| {
+            let target = normalize(target);
+            let min = options.min_similarity;
+            let similarity = if brand::is_brand(&target) {
+                score_brand(options, &runs, &brand::fold(&target), min)?
+            } else {
+                let target_base = get_base_domain(&target);
+                inputs
+                    .iter()
+                    .flat_map(|(input, input_base)| {
+                        [
+                            score(options, input, &target, min),
+                            score(options, input_base, &target_base, min),
+                        ]
+                    })
+                    .max()
+                    .flatten()?
+            };
+            Some((
+                rank,
+                AsciiResult {
+                    domain: target,
+                    similarity,
+                    breakdown: None,
+                },
+            ))
+        })
+        .collect();
+
+    rank_results(results, options.max_results)
+}
+
+/// Orders results best first and keeps the top `max_results`: similarity
+/// descending, then rank ascending (position in the most-phished list or the
+/// caller's targets), then domain, so ties never depend on the order a
+/// candidate source happened to return them in.
+pub fn rank_results(mut ranked: Vec<(usize, AsciiResult)>, max_results: usize) -> Vec<AsciiResult> {
+    ranked.sort_by(|(rank_a, a), (rank_b, b)| {
+        b.similarity
+            .cmp(&a.similarity)
+            .then(rank_a.cmp(rank_b))
+            .then_with(|| a.domain.cmp(&b.domain))
+    });
+    ranked
+        .into_iter()
+        .take(max_results)
+        .map(|(_, result)| result)
+        .collect()
+}
+
+/// `value` lowercased, with an internationalized TLD in punycode (the form
+/// domain lists carry).
 fn normalize(value: &str) -> String {
-    value.to_lowercase()
+    types::ascii_tld(&value.to_lowercase())
+}
+
+/// `domain` with common leet substitutions undone: `0`→`o`, `1`→`l`, `3`→`e`,
+/// `4`→`a`, `@`→`a`, `$`→`s`.
+pub fn deleet(domain: &str) -> String {
+    domain
+        .chars()
+        .map(|c| match c {
+            '0' => 'o',
+            '1' => 'l',
+            '3' => 'e',
+            '4' | '@' => 'a',
+            '$' => 's',
+            _ => c,
+        })
+        .collect()
+}
+
+/// The de-leeted form of `domain` to score as well, when `options.leet` is on
+/// and it differs.
+fn leet_form(domain: &str, options: &AsciiOptions) -> Option<String> {
+    options
+        .leet
+        .then(|| deleet(domain))
+        .filter(|leet| leet != domain)
+}
+
+/// The label runs of `domain` that brand names are scored against (see
+/// `brand::label_runs`), plus their de-leeted forms when `options.leet` is on.
+fn brand_runs(domain: &str, options: &AsciiOptions) -> Vec<String> {
+    let mut runs = brand::label_runs(domain);
+    let leet: Vec<String> = runs
+        .iter()
+        .filter_map(|run| leet_form(run, options))
+        .collect();
+    runs.extend(leet);
+    runs
+}
+
+/// The best score of the folded brand name `brand` against `runs`, or `None`
+/// below `min`.
+fn score_brand(options: &AsciiOptions, runs: &[String], brand: &str, min: u8) -> Option<u8> {
+    runs.iter()
+        .filter_map(|run| score(options, run, brand, min))
+        .max()
 }
 
 fn strip_non_alnum(value: &str) -> String {
@@ -139,47 +586,153 @@ fn get_base_domain(domain: &str) -> String {
     }
 }
 
-fn similarity_ratio(a: &str, b: &str) -> u8 {
+/// Similarity percentage of `a` and `b` under `algorithm`.
+pub fn similarity_with(algorithm: Algorithm, a: &str, b: &str) -> u8 {
+    similarity_at_least(algorithm, a, b, 0).unwrap_or(0)
+}
+
+/// The similarity of `a` and `b` under `options`, or `None` below `min`: the
+/// installed ensemble of scorers (edit distance alone by default), raised
+/// toward the phonetic similarity (the mean of the two, when higher) when
+/// `options.phonetic` is on.
+fn score(options: &AsciiOptions, a: &str, b: &str, min: u8) -> Option<u8> {
+    let edit = similarity_at_least(options.algorithm, a, b, options.edit_floor(min))?;
+    let mut similarity = ensemble::installed().combine(options.algorithm, edit, a, b);
+    if options.phonetic {
+        let blended = (u16::from(similarity) + u16::from(phonetic::similarity(a, b))) / 2;
+        similarity = similarity.max(blended as u8);
+    }
+    (similarity >= min).then_some(similarity)
+}
+
+/// Similarity percentage (100 minus edits per character of the longer string),
+/// or `None` as soon as the edit distance shows the pair cannot reach
+/// `min_similarity`, which skips most of the work for unrelated candidates.
+fn similarity_at_least(algorithm: Algorithm, a: &str, b: &str, min_similarity: u8) -> Option<u8> {
     let max_len = a.len().max(b.len());
     if max_len == 0 {
-        return 100;
+        return Some(100);
     }
-    let distance = levenshtein_distance(a, b);
+    // One edit of slack absorbs float rounding; the final check is exact.
+    let allowed = max_len as f32 * (100.5 - f32::from(min_similarity)) / 100.0;
+    let bound = (allowed.floor() as usize + 1).min(max_len);
+    let distance = match algorithm {
+        Algorithm::Levenshtein => levenshtein_within(a, b, bound),
+        Algorithm::DamerauLevenshtein => damerau_levenshtein_within(a, b, bound),
+    }?;
     let ratio = 1.0 - (distance as f32 / max_len as f32);
-    (100.0 * ratio).round().max(0.0) as u8
+    let similarity = (100.0 * ratio).round().max(0.0) as u8;
+    (similarity >= min_similarity).then_some(similarity)
 }
 
+#[cfg(test)]
 fn levenshtein_distance(a: &str, b: &str) -> usize {
-    if a == b {
-        return 0;
+    levenshtein_within(a, b, a.len().max(b.len()))
+        .expect("distance never exceeds the longer length")
+}
+
+/// Levenshtein distance if it is at most `max`. Only cells within `max` of the
+/// diagonal are computed, two rows at a time, and the scan stops once a whole
+/// row exceeds `max`.
+fn levenshtein_within(a: &str, b: &str, max: usize) -> Option<usize> {
+    let (short, long) = by_length(a.as_bytes(), b.as_bytes());
+    if long.len() - short.len() > max {
+        return None;
     }
-    let a_len = a.len();
-    let b_len = b.len();
-    if a_len == 0 {
-        return b_len;
+    if short.is_empty() {
+        return Some(long.len());
     }
-    if b_len == 0 {
-        return a_len;
+
+    let over = max + 1;
+    let mut prev: Vec<usize> = (0..=short.len()).map(|j| j.min(over)).collect();
+    let mut curr = vec![over; short.len() + 1];
+    for i in 1..=long.len() {
+        let (lo, hi) = band(i, max, short.len());
+        curr[0] = i.min(over);
+        curr[lo - 1] = if lo == 1 { curr[0] } else { over };
+        let mut row_min = curr[lo - 1];
+        for j in lo..=hi {
+            let cost = usize::from(long[i - 1] != short[j - 1]);
+            let cell = (prev[j] + 1)
+                .min(curr[j - 1] + 1)
+                .min(prev[j - 1] + cost)
+                .min(over);
+            curr[j] = cell;
+            row_min = row_min.min(cell);
+        }
+        if hi < short.len() {
+            curr[hi + 1] = over;
+        }
+        if row_min > max {
+            return None;
+        }
+        std::mem::swap(&mut prev, &mut curr);
     }
+    let distance = prev[short.len()];
+    (distance <= max).then_some(distance)
+}
 
-    let mut matrix = vec![vec![0usize; b_len + 1]; a_len + 1];
-    for (i, row) in matrix.iter_mut().enumerate().take(a_len + 1) {
-        row[0] = i;
+#[cfg(test)]
+fn damerau_levenshtein_distance(a: &str, b: &str) -> usize {
+    damerau_levenshtein_within(a, b, a.len().max(b.len()))
+        .expect("distance never exceeds the longer length")
+}
+
+/// Optimal string alignment distance if it is at most `max`: Levenshtein plus
+/// adjacent transpositions counted as a single edit, so `googel` is one edit
+/// from `google`. Banded like `levenshtein_within`, with a third row for the
+/// transposition lookback.
+pub(crate) fn damerau_levenshtein_within(a: &str, b: &str, max: usize) -> Option<usize> {
+    let (short, long) = by_length(a.as_bytes(), b.as_bytes());
+    if long.len() - short.len() > max {
+        return None;
     }
-    for (j, cell) in matrix[0].iter_mut().enumerate().take(b_len + 1) {
-        *cell = j;
+    if short.is_empty() {
+        return Some(long.len());
     }
 
-    for (i, ca) in a.as_bytes().iter().enumerate() {
-        for (j, cb) in b.as_bytes().iter().enumerate() {
-            let cost = if ca == cb { 0 } else { 1 };
-            matrix[i + 1][j + 1] = (matrix[i][j + 1] + 1)
-                .min(matrix[i + 1][j] + 1)
-                .min(matrix[i][j] + cost);
+    let over = max + 1;
+    let mut before = vec![over; short.len() + 1];
+    let mut prev: Vec<usize> = (0..=short.len()).map(|j| j.min(over)).collect();
+    let mut curr = vec![over; short.len() + 1];
+    for i in 1..=long.len() {
+        let (lo, hi) = band(i, max, short.len());
+        curr[0] = i.min(over);
+        curr[lo - 1] = if lo == 1 { curr[0] } else { over };
+        let mut row_min = curr[lo - 1];
+        for j in lo..=hi {
+            let cost = usize::from(long[i - 1] != short[j - 1]);
+            let mut cell = (prev[j] + 1).min(curr[j - 1] + 1).min(prev[j - 1] + cost);
+            if i > 1 && j > 1 && long[i - 1] == short[j - 2] && long[i - 2] == short[j - 1] {
+                cell = cell.min(before[j - 2] + 1);
+            }
+            curr[j] = cell.min(over);
+            row_min = row_min.min(curr[j]);
+        }
+        if hi < short.len() {
+            curr[hi + 1] = over;
         }
+        if row_min > max {
+            return None;
+        }
+        std::mem::swap(&mut before, &mut prev);
+        std::mem::swap(&mut prev, &mut curr);
     }
+    let distance = prev[short.len()];
+    (distance <= max).then_some(distance)
+}
 
-    matrix[a_len][b_len]
+fn by_length<'a>(a: &'a [u8], b: &'a [u8]) -> (&'a [u8], &'a [u8]) {
+    if a.len() <= b.len() {
+        (a, b)
+    } else {
+        (b, a)
+    }
+}
+
+/// Columns of row `i` within `max` of the diagonal, clamped to `1..=len`.
+fn band(i: usize, max: usize, len: usize) -> (usize, usize) {
+    (i.saturating_sub(max).max(1), (i + max).min(len))
 }
 
 #[cfg(test)]
@@ -187,6 +740,54 @@ mod tests {
     use super::*;
     use serde_json::json;
 
+    #[test]
+    fn ties_break_on_rank_then_domain() {
+        let result = |domain: &str, similarity| AsciiResult {
+            domain: domain.to_string(),
+            similarity,
+            breakdown: None,
+        };
+        let ranked = vec![
+            (0, result("zeta.com", 90)),
+            (2, result("beta.com", 95)),
+            (1, result("gamma.com", 95)),
+            (0, result("alpha.com", 90)),
+        ];
+        let domains: Vec<String> = rank_results(ranked, 3)
+            .into_iter()
+            .map(|result| result.domain)
+            .collect();
+        assert_eq!(domains, ["gamma.com", "beta.com", "alpha.com"]);
+
+        // Targets that tie keep the caller's order, whatever their spelling.
+        let targets = vec!["paypal.net".to_string(), "paypal.com".to_string()];
+        let scored = score_targets("paypa1.org", &targets, &AsciiOptions::default());
+        assert_eq!(scored[0].domain, "paypal.net");
+    }
+
+    #[test]
+    fn brand_names_match_runs_of_label_tokens() {
+        let options = AsciiOptions::default();
+        let results = lookup_most_phished("bankofamerica-secure.net", &options).results;
+        assert_eq!(results[0].domain, "bankofamerica.com");
+        assert_eq!(results[0].similarity, 100);
+        let results = lookup_most_phished("societe-generale-login.fr", &options).results;
+        assert_eq!(results[0].domain, "societegenerale.com");
+
+        let targets = vec!["Acme Bank".to_string(), "acme.com".to_string()];
+        let scored = score_targets("secure-acmebank.net", &targets, &options);
+        assert_eq!(scored.len(), 1);
+        assert_eq!(
+            (scored[0].domain.as_str(), scored[0].similarity),
+            ("acme bank", 100)
+        );
+        assert_eq!(
+            score_targets("acme-bamk.org", &targets, &options)[0].similarity,
+            88
+        );
+        assert!(score_targets("acmebanking.com", &targets, &options).is_empty());
+    }
+
     #[test]
     fn lookup_ascii_returns_expected_for_gooble() {
         let result = lookup_ascii("gooble.com", "unused.sqlite").expect("lookup should succeed");
@@ -194,21 +795,87 @@ mod tests {
             "q": "gooble.com",
             "ascii": true,
             "puny": false,
+            "exact_match": false,
             "results": [
                 {
                     "domain": "google.com",
                     "similarity": 90
                 }
-            ]
+            ],
+            "total_candidates_considered": MOST_PHISHED.len()
         });
         assert_eq!(serde_json::to_value(result).unwrap(), expected);
     }
 
+    #[test]
+    fn max_distance_follows_similarity_threshold() {
+        let threshold = |min_similarity| AsciiOptions {
+            min_similarity,
+            ..AsciiOptions::default()
+        };
+        assert_eq!(max_distance(10, &threshold(80)), 2);
+        assert_eq!(max_distance(4, &threshold(80)), 1);
+        assert_eq!(max_distance(10, &threshold(100)), 0);
+        assert_eq!(max_distance(40, &threshold(50)), 21);
+        let wide = AsciiOptions {
+            length_band: 10,
+            ..AsciiOptions::default()
+        };
+        assert_eq!(max_distance(10, &wide), 4);
+    }
+
     #[test]
     fn similarity_ratio_handles_edge_cases() {
-        assert_eq!(similarity_ratio("", ""), 100);
-        assert_eq!(similarity_ratio("a", ""), 0);
-        assert_eq!(similarity_ratio("same", "same"), 100);
+        let lev = Algorithm::Levenshtein;
+        assert_eq!(similarity_with(lev, "", ""), 100);
+        assert_eq!(similarity_with(lev, "a", ""), 0);
+        assert_eq!(similarity_with(lev, "same", "same"), 100);
+    }
+
+    #[test]
+    fn banded_distances_stop_past_the_bound() {
+        assert_eq!(levenshtein_within("kitten", "sitting", 3), Some(3));
+        assert_eq!(levenshtein_within("kitten", "sitting", 2), None);
+        assert_eq!(levenshtein_within("google.com", "example.com", 1), None);
+        assert_eq!(
+            damerau_levenshtein_within("googel.com", "google.com", 1),
+            Some(1)
+        );
+        assert_eq!(damerau_levenshtein_within("ab", "abcd", 1), None);
+
+        let words = [
+            "", "a", "ab", "ba", "abc", "acb", "google", "goolge", "gogle", "kitten",
+        ];
+        for a in words {
+            for b in words {
+                let full = levenshtein_distance(a, b);
+                let osa = damerau_levenshtein_distance(a, b);
+                for max in 0..=7 {
+                    assert_eq!(levenshtein_within(a, b, max), (full <= max).then_some(full));
+                    assert_eq!(
+                        damerau_levenshtein_within(a, b, max),
+                        (osa <= max).then_some(osa)
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn similarity_at_least_matches_the_unbounded_score() {
+        let lev = Algorithm::Levenshtein;
+        assert_eq!(
+            similarity_at_least(lev, "gooble.com", "google.com", 80),
+            Some(90)
+        );
+        assert_eq!(
+            similarity_at_least(lev, "gooble.com", "google.com", 91),
+            None
+        );
+        assert_eq!(
+            similarity_at_least(lev, "example.com", "google.com", 80),
+            None
+        );
     }
 
     #[test]
@@ -220,10 +887,203 @@ mod tests {
 
     #[test]
     fn detect_from_most_phished_matches_base_domain() {
-        let results = detect_from_most_phished("gooble");
+        let results = detect_from_most_phished("gooble", &AsciiOptions::default());
         assert!(results.iter().any(|r| r.domain == "google.com"));
     }
 
+    #[test]
+    fn exact_matches_are_flagged_not_listed() {
+        let conn = crate::db::test_db(&["example.com", "examples.com"]);
+        let options = AsciiOptions::default();
+
+        let response = lookup_ascii_with("example.com", || Ok(&conn), &options).unwrap();
+        assert!(response.exact_match);
+        assert_eq!(response.results.len(), 1);
+        assert_eq!(response.results[0].domain, "examples.com");
+
+        let response = lookup_ascii_with("exampl3.com", || Ok(&conn), &options).unwrap();
+        assert!(!response.exact_match);
+
+        let response = lookup_ascii_with("google.com", || Ok(&conn), &options).unwrap();
+        assert!(response.exact_match);
+        assert!(response.results.iter().all(|r| r.domain != "google.com"));
+    }
+
+    #[test]
+    fn protected_brands_are_not_reported_as_their_own_spoofs() {
+        let options = AsciiOptions::default();
+        for query in [
+            "google.com",
+            "GOOGLE.COM",
+            "google.com.",
+            "accounts.google.com",
+        ] {
+            let response = lookup_most_phished(query, &options);
+            assert!(response.exact_match, "{query}");
+            assert_eq!(response.protected_brand.as_deref(), Some("google.com"));
+            assert!(response.results.iter().all(|r| r.domain != "google.com"));
+        }
+        let alias = lookup_most_phished("anz.com", &options);
+        assert_eq!(alias.protected_brand.as_deref(), Some("anz.com.au"));
+        assert!(alias.results.iter().all(|r| r.domain != "anz.com.au"));
+
+        // Impersonations name the brand in `results` instead.
+        for query in ["gooogle.com", "google.com.evil.net", "evilgoogle.com"] {
+            let response = lookup_most_phished(query, &options);
+            assert_eq!(response.protected_brand, None, "{query}");
+            assert!(!response.exact_match, "{query}");
+        }
+        let spoof = lookup_most_phished("gooogle.com", &options);
+        assert_eq!(spoof.results[0].domain, "google.com");
+    }
+
+    #[test]
+    fn affix_prefilter_skips_candidates_without_a_shared_end() {
+        assert!(shares_affix("paypa1.com", "paypal.net", 5));
+        assert!(shares_affix("rnicrosoft.com", "microsoft.com", 5));
+        assert!(!shares_affix("axcdefghiy.com", "abcdefghij.com", 2));
+        // Capped at half the shorter name, so one edit always passes.
+        assert!(shares_affix("abcxefghij.com", "abcdefghij.com", 8));
+
+        let conn = crate::db::test_db(&["abcdefghij.com"]);
+        let lookup = |min_affix| {
+            let options = AsciiOptions {
+                min_affix,
+                ..AsciiOptions::default()
+            };
+            lookup_ascii_with("axcdefghiy.com", || Ok(&conn), &options)
+                .unwrap()
+                .results
+        };
+        assert_eq!(lookup(0)[0].similarity, 86);
+        assert!(lookup(3).is_empty());
+    }
+
+    #[test]
+    fn batched_lookups_match_single_lookups() {
+        let conn = crate::db::test_db(&["example.com", "exemplar.com", "bexample.com"]);
+        let options = AsciiOptions {
+            leet: true,
+            ..AsciiOptions::default()
+        };
+        let domains: Vec<String> = ["exampie.com", "3xample.com", "example.com", "paypa1.com"]
+            .map(String::from)
+            .into();
+        let batched = lookup_ascii_batch(&domains, &conn, &options).unwrap();
+        for (domain, batched) in domains.iter().zip(&batched) {
+            let single = lookup_ascii_with(domain, || Ok(&conn), &options).unwrap();
+            assert_eq!(
+                serde_json::to_value(batched).unwrap(),
+                serde_json::to_value(single).unwrap()
+            );
+        }
+        assert_eq!(batched[1].results[0].domain, "example.com");
+        assert!(batched[2].exact_match);
+    }
+
+    #[test]
+    fn leet_scores_the_deleeted_form_too() {
+        let leet = AsciiOptions {
+            leet: true,
+            ..AsciiOptions::default()
+        };
+        assert_eq!(deleet("g00g1e.c0m"), "google.com");
+        assert!(lookup_most_phished("g00g1e.com", &AsciiOptions::default())
+            .results
+            .is_empty());
+        let results = lookup_most_phished("g00g1e.com", &leet).results;
+        assert_eq!(results[0].domain, "google.com");
+        assert_eq!(results[0].similarity, 100);
+
+        // The de-leeted form pulls its own candidates, even under another
+        // first letter.
+        let conn = crate::db::test_db(&["azeltor.com"]);
+        let plain = lookup_ascii_with("4zelt0r.com", || Ok(&conn), &AsciiOptions::default());
+        assert!(plain.unwrap().results.is_empty());
+        let response = lookup_ascii_with("4zelt0r.com", || Ok(&conn), &leet).unwrap();
+        assert!(!response.exact_match);
+        assert_eq!(response.results[0].domain, "azeltor.com");
+
+        let targets = vec!["paypal.com".to_string()];
+        assert!(score_targets("p4yp4l.org", &targets, &AsciiOptions::default()).is_empty());
+        assert_eq!(
+            score_targets("p4yp4l.org", &targets, &leet)[0].similarity,
+            100
+        );
+    }
+
+    #[test]
+    fn phonetic_scoring_boosts_sound_alikes() {
+        let phonetic = AsciiOptions {
+            phonetic: true,
+            ..AsciiOptions::default()
+        };
+        let google = |options: &AsciiOptions| {
+            lookup_most_phished("gugel.com", options)
+                .results
+                .into_iter()
+                .find(|result| result.domain == "google.com")
+        };
+        assert_eq!(google(&AsciiOptions::default()), None);
+        assert_eq!(
+            similarity_with(Algorithm::Levenshtein, "gugel.com", "google.com"),
+            60
+        );
+        assert_eq!(google(&phonetic).unwrap().similarity, 80);
+
+        let plain = similarity_with(Algorithm::Levenshtein, "fasebook.com", "facebook.com");
+        let boosted = score(&phonetic, "fasebook.com", "facebook.com", 0).unwrap();
+        assert!(boosted > plain);
+        // Never lowers a score: `paypai` sounds unlike `paypal` but keeps its
+        // edit similarity.
+        let edit = similarity_with(Algorithm::Levenshtein, "paypai.com", "paypal.com");
+        assert_eq!(score(&phonetic, "paypai.com", "paypal.com", 0), Some(edit));
+    }
+
+    #[test]
+    fn explain_attaches_a_breakdown_per_result() {
+        let results = lookup_most_phished("g00gle.com", &AsciiOptions::default()).results;
+        assert!(results.iter().all(|result| result.breakdown.is_none()));
+
+        let options = AsciiOptions {
+            explain: true,
+            leet: true,
+            ..AsciiOptions::default()
+        };
+        let results = lookup_most_phished("g00gle.com", &options).results;
+        let breakdown = results[0].breakdown.as_ref().unwrap();
+        // Scored on the de-leeted form, which matches outright.
+        assert_eq!(breakdown[0].scorer, "edit_distance");
+        assert_eq!(breakdown[0].similarity, 100);
+        assert_eq!(breakdown[0].weight, 1);
+        assert!(breakdown[1..].iter().all(|part| part.weight == 0));
+    }
+
+    #[test]
+    fn damerau_levenshtein_counts_transpositions_once() {
+        assert_eq!(levenshtein_distance("googel", "google"), 2);
+        assert_eq!(damerau_levenshtein_distance("googel", "google"), 1);
+        assert_eq!(damerau_levenshtein_distance("kitten", "sitting"), 3);
+        assert_eq!(damerau_levenshtein_distance("", "ab"), 2);
+    }
+
+    #[test]
+    fn options_bound_most_phished_results() {
+        let strict = AsciiOptions {
+            min_similarity: 95,
+            ..AsciiOptions::default()
+        };
+        assert!(detect_from_most_phished("gooble", &strict).is_empty());
+
+        let transposed = AsciiOptions {
+            algorithm: Algorithm::DamerauLevenshtein,
+            ..AsciiOptions::default()
+        };
+        let results = detect_from_most_phished("googel.com", &transposed);
+        assert_eq!(results[0].domain, "google.com");
+        assert_eq!(results[0].similarity, 90);
+    }
+
     #[test]
     fn normalize_and_helpers() {
         assert_eq!(normalize("TeSt.COM"), "test.com");
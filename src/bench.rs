@@ -0,0 +1,185 @@
+/*
+Lookup benchmarking for `spotspoof bench`. Replays a file of queries against
+one candidate source with fixed ASCII options and reports throughput and
+latency percentiles, so tuning choices can be compared on real hardware.
+*/
+use anyhow::Result;
+use serde::Serialize;
+use std::time::{Duration, Instant};
+
+use crate::ascii_spoof::{self, AsciiOptions};
+use crate::db::{self, CandidateSource};
+use crate::index::{self, FstIndex, SqlIndex};
+use crate::types::Algorithm;
+
+/// Which candidate source the benchmark drives.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum IndexChoice {
+    /// Whatever a normal lookup would use
+    Auto,
+    /// The first-character/length band scan
+    Scan,
+    /// The FST next to the DB
+    Fst,
+    /// The SymSpell deletes table
+    Symspell,
+    /// The trigram table
+    Trigram,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BenchReport {
+    pub index: IndexChoice,
+    pub algorithm: Algorithm,
+    pub length_band: usize,
+    pub max_candidates: usize,
+    pub min_affix: usize,
+    pub queries: usize,
+    pub iterations: usize,
+    pub lookups: usize,
+    /// Lookups that returned at least one result.
+    pub hits: usize,
+    pub total_ms: f64,
+    pub lookups_per_sec: f64,
+    pub latency_ms: Latency,
+}
+
+#[derive(Debug, Serialize, PartialEq)]
+pub struct Latency {
+    pub min: f64,
+    pub mean: f64,
+    pub p50: f64,
+    pub p90: f64,
+    pub p99: f64,
+    pub max: f64,
+}
+
+/// Reads one domain per line, skipping blanks and `#` comments.
+pub fn read_queries(path: &str) -> Result<Vec<String>> {
+    let queries: Vec<String> = std::fs::read_to_string(path)?
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(crate::types::normalize_domain_input)
+        .collect();
+    if queries.is_empty() {
+        return Err(anyhow::anyhow!("no queries found in {path}"));
+    }
+    Ok(queries)
+}
+
+pub fn open_source(db_path: &str, choice: IndexChoice) -> Result<Box<dyn CandidateSource>> {
+    let sql_index = |kind: SqlIndex, name: &str| -> Result<Box<dyn CandidateSource>> {
+        let conn = db::open(db_path, false)?;
+        if SqlIndex::detect_all(&conn)?.contains(&kind) {
+            return Ok(Box::new(kind.with(Box::new(conn))));
+        }
+        Err(anyhow::anyhow!(
+            "{db_path} has no {name} index; build it with `spotspoof db index --kind {name}`"
+        ))
+    };
+    match choice {
+        IndexChoice::Auto => ascii_spoof::open_candidate_source(db_path),
+        IndexChoice::Scan => Ok(Box::new(db::open(db_path, false)?)),
+        IndexChoice::Fst => Ok(Box::new(FstIndex::open(&index::fst_path(db_path))?)),
+        IndexChoice::Symspell => sql_index(SqlIndex::SymSpell, "symspell"),
+        IndexChoice::Trigram => sql_index(SqlIndex::Trigram, "trigram"),
+    }
+}
+
+/// Runs every query `iterations` times against `source`, timing each lookup.
+pub fn run(
+    source: &dyn CandidateSource,
+    queries: &[String],
+    iterations: usize,
+    index: IndexChoice,
+    options: &AsciiOptions,
+) -> Result<BenchReport> {
+    let mut samples = Vec::with_capacity(queries.len() * iterations);
+    let mut hits = 0;
+    let started = Instant::now();
+    for _ in 0..iterations {
+        for query in queries {
+            let lookup_started = Instant::now();
+            let response = ascii_spoof::lookup_ascii_with(query, || Ok(source), options)?;
+            samples.push(lookup_started.elapsed());
+            if !response.results.is_empty() {
+                hits += 1;
+            }
+        }
+    }
+    let total = started.elapsed();
+
+    Ok(BenchReport {
+        index,
+        algorithm: options.algorithm,
+        length_band: options.length_band,
+        max_candidates: options.max_candidates,
+        min_affix: options.min_affix,
+        queries: queries.len(),
+        iterations,
+        lookups: samples.len(),
+        hits,
+        total_ms: millis(total),
+        lookups_per_sec: samples.len() as f64 / total.as_secs_f64().max(f64::EPSILON),
+        latency_ms: latency(&mut samples),
+    })
+}
+
+fn latency(samples: &mut [Duration]) -> Latency {
+    samples.sort_unstable();
+    let total: Duration = samples.iter().sum();
+    Latency {
+        min: millis(samples[0]),
+        mean: millis(total) / samples.len() as f64,
+        p50: millis(percentile(samples, 50)),
+        p90: millis(percentile(samples, 90)),
+        p99: millis(percentile(samples, 99)),
+        max: millis(samples[samples.len() - 1]),
+    }
+}
+
+/// Nearest-rank percentile of sorted, non-empty `samples`.
+fn percentile(samples: &[Duration], pct: usize) -> Duration {
+    let rank = (pct * samples.len()).div_ceil(100).max(1);
+    samples[rank - 1]
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn percentiles_use_nearest_rank() {
+        let mut samples: Vec<Duration> = (1..=100).rev().map(Duration::from_millis).collect();
+        let latency = latency(&mut samples);
+        assert_eq!(latency.min, 1.0);
+        assert_eq!(latency.p50, 50.0);
+        assert_eq!(latency.p90, 90.0);
+        assert_eq!(latency.p99, 99.0);
+        assert_eq!(latency.max, 100.0);
+        assert_eq!(latency.mean, 50.5);
+    }
+
+    #[test]
+    fn run_counts_lookups_and_hits() {
+        let conn = db::test_db(&["example.com"]);
+        let queries = vec!["exampl3.com".to_string(), "zzzzzzzz.com".to_string()];
+        let report = run(
+            &conn,
+            &queries,
+            3,
+            IndexChoice::Scan,
+            &AsciiOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(report.lookups, 6);
+        assert_eq!(report.hits, 3);
+        assert!(report.latency_ms.min <= report.latency_ms.max);
+    }
+}
@@ -0,0 +1,84 @@
+/*
+Plain brand names as targets. A most-phished entry's `brands`, or a watched
+target without a dot ("Bank of America", "Société Générale"), names the
+business rather than its domain, so it is compared against the second-level
+label of a domain instead of the whole name. Both sides are folded to bare
+lowercase letters and digits (accents dropped, spaces and hyphens removed),
+and the label is split on hyphens so that any run of its tokens can match:
+`bankofamerica-secure.net` and `bank-of-america-login.com` both contain
+`bankofamerica`, although the bank's own domain is elsewhere.
+*/
+use unicode_normalization::char::is_combining_mark;
+use unicode_normalization::UnicodeNormalization;
+
+/// Labels with more hyphen-separated tokens are cut to this many, which
+/// bounds the runs scored per brand.
+const MAX_TOKENS: usize = 8;
+
+/// Whether `target` is a brand name rather than a domain.
+pub fn is_brand(target: &str) -> bool {
+    !target.contains('.') && !fold(target).is_empty()
+}
+
+/// `name` as bare lowercase letters and digits, accents dropped:
+/// "Société Générale" becomes `societegenerale`.
+pub fn fold(name: &str) -> String {
+    name.nfd()
+        .filter(|c| !is_combining_mark(*c))
+        .flat_map(char::to_lowercase)
+        .filter(|c| c.is_alphanumeric())
+        .collect()
+}
+
+/// The folded runs of consecutive hyphen-separated tokens in the
+/// second-level label of `domain` (punycode decoded), longest first.
+pub fn label_runs(domain: &str) -> Vec<String> {
+    let labels: Vec<&str> = domain.trim_end_matches('.').split('.').collect();
+    let label = labels[labels.len().saturating_sub(2)];
+    let label = if label.starts_with("xn--") {
+        idna::domain_to_unicode(label).0
+    } else {
+        label.to_string()
+    };
+    let tokens: Vec<String> = label
+        .split(['-', '_'])
+        .map(fold)
+        .filter(|token| !token.is_empty())
+        .take(MAX_TOKENS)
+        .collect();
+    let mut runs = Vec::new();
+    for len in (1..=tokens.len()).rev() {
+        for window in tokens.windows(len) {
+            let run = window.concat();
+            if !runs.contains(&run) {
+                runs.push(run);
+            }
+        }
+    }
+    runs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn labels_fold_into_runs_of_tokens() {
+        assert!(is_brand("Bank of America"));
+        assert!(!is_brand("bankofamerica.com"));
+        assert!(!is_brand(" - "));
+        assert_eq!(fold("Société Générale"), "societegenerale");
+        assert_eq!(
+            label_runs("www.bank-of-america.secure-login.net"),
+            ["securelogin", "secure", "login"]
+        );
+        assert_eq!(
+            label_runs("bankofamerica-secure.net"),
+            ["bankofamericasecure", "bankofamerica", "secure"]
+        );
+        assert_eq!(
+            label_runs("xn--socit-gnrale-eebbcb.com"),
+            ["societegenerale", "societe", "generale"]
+        );
+    }
+}
@@ -0,0 +1,305 @@
+/*
+Browser profile sweeps for `scan-browser`, for incident response on a suspected
+compromised workstation. Reads the history and bookmarks of a Chrome-family
+profile (`History`, `Bookmarks`; also Chromium, Edge, and Brave) or a Firefox
+profile (`places.sqlite`). Each host's occurrences are its visits, so the
+lookalikes the user actually went to stand out. Browsers keep their databases
+locked while running, so they are read from a private copy.
+*/
+use anyhow::Result;
+use rusqlite::{Connection, OpenFlags};
+use serde_json::Value;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::scan::Observations;
+
+/// Seconds between 1601-01-01, where Chrome's timestamps start, and the Unix
+/// epoch.
+const CHROME_EPOCH_OFFSET_SECS: u64 = 11_644_473_600;
+
+/// What a profile sweep read.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ProfileSummary {
+    /// History entries (URLs) read.
+    pub history: usize,
+    /// Bookmarks read.
+    pub bookmarks: usize,
+}
+
+/// Adds the hosts of the profile at `profile` (a profile directory, or its
+/// `History`, `Bookmarks`, or `places.sqlite` file). With `since`, only URLs
+/// visited that recently count and bookmarks are skipped.
+pub fn read_profile(
+    profile: &str,
+    since: Option<Duration>,
+    observations: &mut Observations,
+) -> Result<ProfileSummary> {
+    let path = Path::new(profile);
+    let files: Vec<PathBuf> = if path.is_dir() {
+        ["History", "Bookmarks", "places.sqlite"]
+            .iter()
+            .map(|name| path.join(name))
+            .filter(|file| file.is_file())
+            .collect()
+    } else {
+        vec![path.to_path_buf()]
+    };
+    if files.is_empty() {
+        anyhow::bail!("{profile}: no Chrome History/Bookmarks or Firefox places.sqlite");
+    }
+    let cutoff = since.map(|since| SystemTime::now() - since);
+    let mut summary = ProfileSummary::default();
+    for file in files {
+        let name = file
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or("");
+        match name {
+            "History" => summary.history += chrome_history(&file, cutoff, observations)?,
+            "Bookmarks" if cutoff.is_none() => {
+                let text = fs::read_to_string(&file)
+                    .map_err(|err| anyhow::anyhow!("cannot read {}: {err}", file.display()))?;
+                summary.bookmarks += chrome_bookmarks(&text, observations)?;
+            }
+            "Bookmarks" => {}
+            "places.sqlite" => {
+                let (history, bookmarks) = firefox_places(&file, cutoff, observations)?;
+                summary.history += history;
+                summary.bookmarks += bookmarks;
+            }
+            _ => anyhow::bail!(
+                "{}: expected a profile directory, History, Bookmarks, or places.sqlite",
+                file.display()
+            ),
+        }
+    }
+    Ok(summary)
+}
+
+fn chrome_history(
+    file: &Path,
+    cutoff: Option<SystemTime>,
+    observations: &mut Observations,
+) -> Result<usize> {
+    let cutoff = cutoff.map_or(0, |cutoff| {
+        let secs = cutoff
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        ((secs + CHROME_EPOCH_OFFSET_SECS) * 1_000_000) as i64
+    });
+    let copy = SnapshotCopy::of(file)?;
+    let conn = copy.open()?;
+    let mut stmt = conn.prepare(
+        "SELECT url, visit_count FROM urls
+		WHERE last_visit_time >= ?1
+		ORDER BY last_visit_time",
+    )?;
+    let rows = stmt.query_map([cutoff], |row| {
+        Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?))
+    })?;
+    add_rows(rows, observations)
+}
+
+fn chrome_bookmarks(text: &str, observations: &mut Observations) -> Result<usize> {
+    fn walk(node: &Value, observations: &mut Observations, count: &mut usize) {
+        if let Some(url) = node.get("url").and_then(Value::as_str) {
+            add_url(observations, url, 1);
+            *count += 1;
+        }
+        for child in node
+            .get("children")
+            .and_then(Value::as_array)
+            .into_iter()
+            .flatten()
+        {
+            walk(child, observations, count);
+        }
+    }
+    let bookmarks: Value = serde_json::from_str(text)
+        .map_err(|err| anyhow::anyhow!("not a Chrome Bookmarks file: {err}"))?;
+    let mut count = 0;
+    for root in bookmarks["roots"]
+        .as_object()
+        .into_iter()
+        .flat_map(|roots| roots.values())
+    {
+        walk(root, observations, &mut count);
+    }
+    Ok(count)
+}
+
+fn firefox_places(
+    file: &Path,
+    cutoff: Option<SystemTime>,
+    observations: &mut Observations,
+) -> Result<(usize, usize)> {
+    let copy = SnapshotCopy::of(file)?;
+    let conn = copy.open()?;
+    let history = {
+        let cutoff = cutoff.map_or(0, |cutoff| {
+            cutoff
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_micros() as i64
+        });
+        let mut stmt = conn.prepare(
+            "SELECT url, visit_count FROM moz_places
+			WHERE visit_count > 0 AND last_visit_date >= ?1
+			ORDER BY last_visit_date",
+        )?;
+        let rows = stmt.query_map([cutoff], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?))
+        })?;
+        add_rows(rows, observations)?
+    };
+    if cutoff.is_some() {
+        return Ok((history, 0));
+    }
+    let mut stmt = conn.prepare(
+        "SELECT moz_places.url FROM moz_bookmarks
+		JOIN moz_places ON moz_places.id = moz_bookmarks.fk
+		WHERE moz_bookmarks.type = 1",
+    )?;
+    let rows = stmt.query_map([], |row| Ok((row.get::<_, String>(0)?, 1)))?;
+    let bookmarks = add_rows(rows, observations)?;
+    Ok((history, bookmarks))
+}
+
+fn add_rows(
+    rows: impl Iterator<Item = rusqlite::Result<(String, i64)>>,
+    observations: &mut Observations,
+) -> Result<usize> {
+    let mut count = 0;
+    for row in rows {
+        let (url, visits) = row?;
+        add_url(observations, &url, visits.max(1) as usize);
+        count += 1;
+    }
+    Ok(count)
+}
+
+fn add_url(observations: &mut Observations, url: &str, visits: usize) {
+    if let Some(host) = url::Url::parse(url)
+        .ok()
+        .and_then(|url| url.host_str().map(str::to_string))
+    {
+        observations.add_count(&host, visits);
+    }
+}
+
+/// A private copy of a browser database (with its WAL) in a temp directory
+/// only the current user can read, removed on drop, so a running browser's
+/// lock does not get in the way.
+struct SnapshotCopy {
+    dir: tempfile::TempDir,
+    db: PathBuf,
+}
+
+impl SnapshotCopy {
+    fn of(file: &Path) -> Result<Self> {
+        let dir = tempfile::Builder::new()
+            .prefix("spotspoof-browser-")
+            .tempdir()?;
+        let copy = Self {
+            db: dir.path().join("profile.sqlite"),
+            dir,
+        };
+        fs::copy(file, &copy.db)
+            .map_err(|err| anyhow::anyhow!("cannot read {}: {err}", file.display()))?;
+        let mut wal = file.as_os_str().to_os_string();
+        wal.push("-wal");
+        if Path::new(&wal).is_file() {
+            fs::copy(&wal, copy.dir.path().join("profile.sqlite-wal"))?;
+        }
+        Ok(copy)
+    }
+
+    fn open(&self) -> Result<Connection> {
+        Ok(Connection::open_with_flags(
+            &self.db,
+            OpenFlags::SQLITE_OPEN_READ_WRITE | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+        )?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chrome_and_firefox_profiles_yield_visited_hosts() {
+        let dir = std::env::temp_dir().join(format!("spotspoof-profile-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        Connection::open(dir.join("History"))
+            .unwrap()
+            .execute_batch(
+                "CREATE TABLE urls (id INTEGER PRIMARY KEY, url TEXT, visit_count INTEGER, last_visit_time INTEGER);
+				INSERT INTO urls (url, visit_count, last_visit_time) VALUES
+					('https://www.paypa1.com/signin', 3, 13350000000000000),
+					('https://mail.example.com/', 1, 13200000000000000);",
+            )
+            .unwrap();
+        fs::write(
+            dir.join("Bookmarks"),
+            r#"{"roots": {"bookmark_bar": {"children": [{"type": "url", "url": "https://intranet.example.org/"}]}}}"#,
+        )
+        .unwrap();
+        let mut observations = Observations::default();
+        let summary = read_profile(dir.to_str().unwrap(), None, &mut observations).unwrap();
+        assert_eq!(
+            summary,
+            ProfileSummary {
+                history: 2,
+                bookmarks: 1
+            }
+        );
+        let hosts: Vec<(String, usize)> = observations
+            .into_vec()
+            .into_iter()
+            .map(|observation| (observation.domain, observation.occurrences))
+            .collect();
+        assert_eq!(
+            hosts,
+            [
+                ("example.com".to_string(), 1),
+                ("paypa1.com".to_string(), 3),
+                ("example.org".to_string(), 1),
+            ]
+        );
+
+        let places = dir.join("places.sqlite");
+        Connection::open(&places)
+            .unwrap()
+            .execute_batch(
+                "CREATE TABLE moz_places (id INTEGER PRIMARY KEY, url TEXT, visit_count INTEGER, last_visit_date INTEGER);
+				CREATE TABLE moz_bookmarks (id INTEGER PRIMARY KEY, type INTEGER, fk INTEGER);
+				INSERT INTO moz_places VALUES (1, 'https://login.examp1e.com/', 2, 1700000000000000);
+				INSERT INTO moz_places VALUES (2, 'https://docs.example.net/', 0, NULL);
+				INSERT INTO moz_bookmarks VALUES (1, 1, 2);",
+            )
+            .unwrap();
+        let mut observations = Observations::default();
+        let summary = read_profile(places.to_str().unwrap(), None, &mut observations).unwrap();
+        assert_eq!(
+            summary,
+            ProfileSummary {
+                history: 1,
+                bookmarks: 1
+            }
+        );
+        assert_eq!(observations.len(), 2);
+        let mut observations = Observations::default();
+        read_profile(
+            places.to_str().unwrap(),
+            Some(Duration::from_secs(60)),
+            &mut observations,
+        )
+        .unwrap();
+        assert!(observations.is_empty());
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
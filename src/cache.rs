@@ -0,0 +1,231 @@
+/*
+In-memory lookup result cache used by the HTTP server. Keeps the most recently
+used responses per (analysis kind, domain + options) with a TTL, and counts hits/misses
+for the metrics endpoint. With a shared Redis cache (`--redis-url`), local
+misses fall back to Redis and new responses are written to both.
+*/
+use lru::LruCache;
+use std::num::NonZeroUsize;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+use crate::redis_cache::RedisCache;
+use crate::types::LookupResponse;
+
+pub const DEFAULT_CACHE_SIZE: usize = 1024;
+pub const DEFAULT_CACHE_TTL: Duration = Duration::from_secs(300);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CacheKind {
+    Ascii,
+    Idn,
+}
+
+impl CacheKind {
+    fn as_str(self) -> &'static str {
+        match self {
+            CacheKind::Ascii => "ascii",
+            CacheKind::Idn => "idn",
+        }
+    }
+}
+
+type CacheKey = (CacheKind, String);
+
+pub struct LookupCache {
+    entries: Option<Mutex<LruCache<CacheKey, (Instant, LookupResponse)>>>,
+    ttl: Duration,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    shared: Option<RedisCache>,
+    shared_hits: AtomicU64,
+}
+
+impl LookupCache {
+    /// A `capacity` of zero disables the local cache; without a shared one,
+    /// every lookup then counts as a miss.
+    pub fn new(capacity: usize, ttl: Duration) -> Self {
+        Self {
+            entries: NonZeroUsize::new(capacity).map(|cap| Mutex::new(LruCache::new(cap))),
+            ttl,
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            shared: None,
+            shared_hits: AtomicU64::new(0),
+        }
+    }
+
+    /// Backs the cache with `shared`, which outlives reloads: keys must
+    /// identify the DB they were answered from.
+    pub fn with_shared(self, shared: RedisCache) -> Self {
+        Self {
+            shared: Some(shared),
+            ..self
+        }
+    }
+
+    /// Whether lookups may reach Redis, and so block.
+    pub fn is_shared(&self) -> bool {
+        self.shared.is_some()
+    }
+
+    pub fn get(&self, kind: CacheKind, key: &str) -> Option<LookupResponse> {
+        let hit = self.local(kind, key).or_else(|| {
+            let value = self.get_shared(kind, key)?;
+            self.shared_hits.fetch_add(1, Ordering::Relaxed);
+            self.insert_local(kind, key, value.clone());
+            Some(value)
+        });
+        let counter = if hit.is_some() {
+            &self.hits
+        } else {
+            &self.misses
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        hit
+    }
+
+    fn local(&self, kind: CacheKind, key: &str) -> Option<LookupResponse> {
+        self.entries.as_ref().and_then(|entries| {
+            let mut entries = entries.lock().unwrap_or_else(|p| p.into_inner());
+            let key = (kind, key.to_string());
+            match entries.get(&key) {
+                Some((stored, value)) if stored.elapsed() < self.ttl => Some(value.clone()),
+                Some(_) => {
+                    entries.pop(&key);
+                    None
+                }
+                None => None,
+            }
+        })
+    }
+
+    fn get_shared(&self, kind: CacheKind, key: &str) -> Option<LookupResponse> {
+        let shared = self.shared.as_ref()?;
+        let json = match shared.get(&shared_key(kind, key)) {
+            Ok(json) => json?,
+            Err(err) => {
+                warn!(kind = "redis", "cache read failed: {err:#}");
+                return None;
+            }
+        };
+        serde_json::from_str(&json).ok()
+    }
+
+    pub fn insert(&self, kind: CacheKind, key: &str, value: LookupResponse) {
+        if let Some(shared) = &self.shared {
+            let stored = serde_json::to_string(&value)
+                .map_err(anyhow::Error::from)
+                .and_then(|json| shared.set(&shared_key(kind, key), &json, self.ttl));
+            if let Err(err) = stored {
+                warn!(kind = "redis", "cache write failed: {err:#}");
+            }
+        }
+        self.insert_local(kind, key, value);
+    }
+
+    fn insert_local(&self, kind: CacheKind, key: &str, value: LookupResponse) {
+        if let Some(entries) = &self.entries {
+            let mut entries = entries.lock().unwrap_or_else(|p| p.into_inner());
+            entries.put((kind, key.to_string()), (Instant::now(), value));
+        }
+    }
+
+    pub fn clear(&self) {
+        if let Some(entries) = &self.entries {
+            entries.lock().unwrap_or_else(|p| p.into_inner()).clear();
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries
+            .as_ref()
+            .map(|entries| entries.lock().unwrap_or_else(|p| p.into_inner()).len())
+            .unwrap_or(0)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn hits(&self) -> u64 {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    pub fn misses(&self) -> u64 {
+        self.misses.load(Ordering::Relaxed)
+    }
+
+    /// Hits answered by the shared cache rather than the local one.
+    pub fn shared_hits(&self) -> u64 {
+        self.shared_hits.load(Ordering::Relaxed)
+    }
+}
+
+fn shared_key(kind: CacheKind, key: &str) -> String {
+    format!("lookup:{}:{key}", kind.as_str())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response(domain: &str) -> LookupResponse {
+        LookupResponse::Ascii(crate::types::empty_ascii_response(domain))
+    }
+
+    #[test]
+    fn cache_counts_hits_and_misses() {
+        let cache = LookupCache::new(2, Duration::from_secs(60));
+        assert!(cache.get(CacheKind::Ascii, "a.com").is_none());
+        cache.insert(CacheKind::Ascii, "a.com", response("a.com"));
+        assert_eq!(
+            cache.get(CacheKind::Ascii, "a.com"),
+            Some(response("a.com"))
+        );
+        assert!(cache.get(CacheKind::Idn, "a.com").is_none());
+        assert_eq!((cache.hits(), cache.misses()), (1, 2));
+    }
+
+    #[test]
+    fn cache_evicts_expired_and_least_recent_entries() {
+        let cache = LookupCache::new(2, Duration::ZERO);
+        cache.insert(CacheKind::Ascii, "a.com", response("a.com"));
+        assert!(cache.get(CacheKind::Ascii, "a.com").is_none());
+        assert_eq!(cache.len(), 0);
+
+        let cache = LookupCache::new(2, Duration::from_secs(60));
+        for domain in ["a.com", "b.com", "c.com"] {
+            cache.insert(CacheKind::Ascii, domain, response(domain));
+        }
+        assert_eq!(cache.len(), 2);
+        assert!(cache.get(CacheKind::Ascii, "a.com").is_none());
+    }
+
+    #[test]
+    fn instances_share_responses_through_redis() {
+        let url = crate::redis_cache::fake_server();
+        let shared = || RedisCache::connect(&url).unwrap();
+        let first = LookupCache::new(2, Duration::from_secs(60)).with_shared(shared());
+        let second = LookupCache::new(0, Duration::from_secs(60)).with_shared(shared());
+        assert!(second.get(CacheKind::Ascii, "a.com").is_none());
+        first.insert(CacheKind::Ascii, "a.com", response("a.com"));
+        assert_eq!(
+            second.get(CacheKind::Ascii, "a.com"),
+            Some(response("a.com"))
+        );
+        assert!(second.get(CacheKind::Idn, "a.com").is_none());
+        assert_eq!((second.hits(), second.shared_hits()), (1, 1));
+        assert_eq!(second.misses(), 2);
+    }
+
+    #[test]
+    fn zero_capacity_disables_cache() {
+        let cache = LookupCache::new(0, Duration::from_secs(60));
+        cache.insert(CacheKind::Ascii, "a.com", response("a.com"));
+        assert!(cache.get(CacheKind::Ascii, "a.com").is_none());
+        assert_eq!(cache.misses(), 1);
+    }
+}
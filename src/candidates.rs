@@ -0,0 +1,140 @@
+/*
+What ASCII detection asks candidate sources for, and the confusable skeleton
+the `skeleton` strategy buckets domains by. Kept free of SQLite so the
+string-analysis core also builds for WebAssembly.
+*/
+use anyhow::Result;
+
+/// What ASCII detection asks a candidate source for. Band-based sources use
+/// `first_char`, the length range and `strategies`, keeping only domains within
+/// `max_distance` edits, and skip domains failing the `min_affix` prefilter
+/// before computing any distance; fuzzy indexes search around `domain`
+/// directly.
+pub struct CandidateQuery<'a> {
+    pub domain: &'a str,
+    pub first_char: char,
+    pub min_len: usize,
+    pub max_len: usize,
+    pub max_distance: u32,
+    pub limit: usize,
+    pub strategies: CandidateStrategies,
+    /// The shared prefix or suffix a candidate needs (see `shares_affix`);
+    /// 0 turns the prefilter off.
+    pub min_affix: usize,
+}
+
+/// How the band scan picks candidates when no fuzzy index is available.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, clap::ValueEnum)]
+pub enum CandidateStrategy {
+    /// Same first character (indexed; the historical behaviour)
+    FirstChar,
+    /// A character shared between the first two positions of each domain
+    FirstOrSecond,
+    /// Same last character of the first label
+    LastChar,
+    /// Same ASCII confusable skeleton (`rn`/`m`, `0`/`o`, `1`/`l`, ...)
+    Skeleton,
+    /// Any domain in the length band, used only when the others find nothing
+    LengthOnly,
+}
+
+/// A set of `CandidateStrategy` values; results are combined and deduped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CandidateStrategies(u8);
+
+impl CandidateStrategies {
+    pub fn contains(self, strategy: CandidateStrategy) -> bool {
+        self.0 & Self::bit(strategy) != 0
+    }
+
+    /// Whether any strategy needs a scan of the whole length band.
+    #[cfg(feature = "native")]
+    pub(crate) fn scans_band(self) -> bool {
+        [
+            CandidateStrategy::FirstOrSecond,
+            CandidateStrategy::LastChar,
+            CandidateStrategy::Skeleton,
+        ]
+        .into_iter()
+        .any(|strategy| self.contains(strategy))
+    }
+
+    fn bit(strategy: CandidateStrategy) -> u8 {
+        1 << strategy as u8
+    }
+}
+
+impl Default for CandidateStrategies {
+    fn default() -> Self {
+        Self::from_iter([CandidateStrategy::FirstChar])
+    }
+}
+
+impl FromIterator<CandidateStrategy> for CandidateStrategies {
+    fn from_iter<I: IntoIterator<Item = CandidateStrategy>>(iter: I) -> Self {
+        Self(
+            iter.into_iter()
+                .fold(0, |bits, strategy| bits | Self::bit(strategy)),
+        )
+    }
+}
+
+/// Folds ASCII lookalikes together so `rnicrosoft.com` and `microsoft.com`
+/// land in the same bucket.
+pub fn skeleton(domain: &str) -> String {
+    let folded = domain
+        .to_lowercase()
+        .replace("rn", "m")
+        .replace("vv", "w")
+        .replace("cl", "d");
+    folded
+        .chars()
+        .map(|c| match c {
+            '0' => 'o',
+            '1' | 'i' | '|' => 'l',
+            '3' => 'e',
+            '5' | '$' => 's',
+            '@' => 'a',
+            _ => c,
+        })
+        .collect()
+}
+
+/// Whether the names of `a` and `b` (without their TLDs) share a prefix or a
+/// suffix of `min` characters. The requirement is capped at half the shorter
+/// name, the longest affix a single edit is sure to leave, so one typo never
+/// fails it however high `min` is set.
+pub fn shares_affix(a: &str, b: &str, min: usize) -> bool {
+    if min == 0 {
+        return true;
+    }
+    let (a, b) = (name(a), name(b));
+    let needed = min.min(a.chars().count().min(b.chars().count()) / 2);
+    let prefix = a.chars().zip(b.chars()).take_while(|(x, y)| x == y).count();
+    let suffix = a
+        .chars()
+        .rev()
+        .zip(b.chars().rev())
+        .take_while(|(x, y)| x == y)
+        .count();
+    prefix.max(suffix) >= needed
+}
+
+/// The domain without its TLD.
+fn name(domain: &str) -> &str {
+    domain.rsplit_once('.').map_or(domain, |(name, _)| name)
+}
+
+/// Anything ASCII detection can pull candidates from: a SQLite connection, the
+/// in-memory table used by `serve --in-memory`, or a fuzzy index. Sources are
+/// `Send` so a `Scanner` can be shared behind a mutex.
+pub trait CandidateSource: Send {
+    fn candidates(&self, query: &CandidateQuery) -> Result<Vec<String>>;
+
+    /// The candidates of each of `queries`, in order. Sources that can share
+    /// work between similar queries (one scan per first character and length
+    /// band) override this; the rest answer them one at a time.
+    fn candidates_batch(&self, queries: &[CandidateQuery]) -> Result<Vec<Vec<String>>> {
+        queries.iter().map(|query| self.candidates(query)).collect()
+    }
+}
@@ -0,0 +1,167 @@
+/*
+Encryption at rest with SQLCipher (`--db-key`/`SPOTSPOOF_DB_KEY`, needs the
+`sqlcipher` feature). With a key installed, every SQLite file spotspoof opens
+(the domain DB, tenant brand lists, API keys, history, detections, the alert
+outbox, and the DNS cache) is keyed right after opening, and downloaded DBs are
+encrypted before they are renamed into place. `db encrypt` converts existing
+plaintext files.
+*/
+use anyhow::Result;
+use rusqlite::Connection;
+use std::fs;
+use std::io::Read;
+use std::sync::OnceLock;
+
+static KEY: OnceLock<String> = OnceLock::new();
+
+/// Keys every SQLite file opened from now on with `key`.
+pub fn install(key: String) -> Result<()> {
+    if !cfg!(feature = "sqlcipher") {
+        anyhow::bail!("--db-key needs a build with the `sqlcipher` feature");
+    }
+    if key.is_empty() {
+        anyhow::bail!("--db-key must not be empty");
+    }
+    let _ = KEY.set(key);
+    Ok(())
+}
+
+/// Whether SQLite files are encrypted.
+pub fn enabled() -> bool {
+    KEY.get().is_some()
+}
+
+/// Keys a freshly opened connection, if a key is installed. This must run
+/// before anything else touches the connection.
+pub fn apply(conn: &Connection) -> rusqlite::Result<()> {
+    match KEY.get() {
+        Some(key) => apply_key(conn, key),
+        None => Ok(()),
+    }
+}
+
+fn apply_key(conn: &Connection, key: &str) -> rusqlite::Result<()> {
+    conn.pragma_update(None, "key", key)
+}
+
+/// Opens (creating if needed) the SQLite file at `path`, keyed if a key is
+/// installed. A key that does not decrypt the file fails here rather than on
+/// the first query.
+pub fn open(path: &str) -> Result<Connection> {
+    let conn = Connection::open(path)?;
+    if enabled() {
+        apply(&conn)?;
+        check(&conn, path)?;
+    }
+    Ok(conn)
+}
+
+/// Fails unless the connection can read its schema.
+pub fn check(conn: &Connection, path: &str) -> Result<()> {
+    conn.query_row("SELECT count(*) FROM sqlite_master", [], |_| Ok(()))
+        .map_err(|_| match enabled() {
+            true => anyhow::anyhow!(
+                "cannot read {path}: wrong --db-key, or the file is not encrypted (see `db encrypt`)"
+            ),
+            false => anyhow::anyhow!("cannot read {path}: it is encrypted (pass --db-key) or not a SQLite DB"),
+        })
+}
+
+/// Whether a DB at `path` that cannot be opened must be kept rather than
+/// replaced by a fresh download: it may hold data encrypted with another key.
+pub fn keeps(path: &str) -> bool {
+    enabled() || (cfg!(feature = "sqlcipher") && !is_plaintext(path))
+}
+
+/// Whether `path` is empty or starts with the plaintext SQLite header.
+fn is_plaintext(path: &str) -> bool {
+    let mut header = Vec::with_capacity(16);
+    match fs::File::open(path) {
+        Ok(file) => {
+            let _ = file.take(16).read_to_end(&mut header);
+        }
+        Err(_) => return true,
+    }
+    header.is_empty() || header == b"SQLite format 3\0"
+}
+
+/// Encrypts the plaintext SQLite file at `path` in place with the installed
+/// key.
+pub fn encrypt(path: &str) -> Result<()> {
+    let key = KEY
+        .get()
+        .ok_or_else(|| anyhow::anyhow!("encrypting needs --db-key"))?;
+    encrypt_with(path, key)
+}
+
+fn encrypt_with(path: &str, key: &str) -> Result<()> {
+    if !std::path::Path::new(path).is_file() {
+        anyhow::bail!("{path} does not exist");
+    }
+    let tmp_path = format!("{path}.encrypting");
+    let _ = fs::remove_file(&tmp_path);
+    let result = (|| -> Result<()> {
+        let conn = Connection::open(path)?;
+        conn.query_row("SELECT count(*) FROM sqlite_master", [], |_| Ok(()))
+            .map_err(|_| anyhow::anyhow!("{path} is already encrypted or is not a SQLite DB"))?;
+        // Folds any WAL into the file, so no plaintext -wal is left behind to
+        // be replayed over the encrypted copy.
+        conn.pragma_update_and_check(None, "journal_mode", "DELETE", |row| {
+            row.get::<_, String>(0)
+        })?;
+        conn.execute("ATTACH DATABASE ?1 AS encrypted KEY ?2", [&tmp_path, key])?;
+        conn.query_row("SELECT sqlcipher_export('encrypted')", [], |_| Ok(()))?;
+        conn.execute("DETACH DATABASE encrypted", [])?;
+        Ok(())
+    })();
+    if let Err(err) = result {
+        let _ = fs::remove_file(&tmp_path);
+        return Err(err);
+    }
+    fs::rename(&tmp_path, path)?;
+    let conn = Connection::open(path)?;
+    apply_key(&conn, key)?;
+    conn.pragma_update_and_check(None, "journal_mode", "WAL", |row| row.get::<_, String>(0))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys_need_the_sqlcipher_feature() {
+        if cfg!(feature = "sqlcipher") {
+            assert!(install(String::new()).is_err());
+        } else {
+            assert!(install("secret".to_string()).is_err());
+            assert!(!enabled());
+        }
+    }
+
+    #[cfg(feature = "sqlcipher")]
+    #[test]
+    fn encrypted_files_need_the_key() {
+        let path = std::env::temp_dir()
+            .join(format!("spotspoof-cipher-{}.sqlite", std::process::id()))
+            .to_string_lossy()
+            .to_string();
+        let _ = fs::remove_file(&path);
+        Connection::open(&path)
+            .unwrap()
+            .execute_batch("CREATE TABLE brands (name TEXT); INSERT INTO brands VALUES ('paypal');")
+            .unwrap();
+        encrypt_with(&path, "secret").unwrap();
+        assert!(encrypt_with(&path, "secret").is_err());
+
+        let plain = Connection::open(&path).unwrap();
+        assert!(check(&plain, &path).is_err());
+        let keyed = Connection::open(&path).unwrap();
+        apply_key(&keyed, "secret").unwrap();
+        let name: String = keyed
+            .query_row("SELECT name FROM brands", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(name, "paypal");
+        let _ = fs::remove_file(&path);
+    }
+}
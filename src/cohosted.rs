@@ -0,0 +1,128 @@
+/*
+Reverse-IP co-hosting discovery for `cohosted`. Phishing kits are usually
+deployed many brands at a time, so the box behind one confirmed spoof tends to
+serve more. The domain's A records are resolved, the other names on each
+address are listed by the configured passive DNS provider (`[passive_dns]`) or
+by HackerTarget's reverse-IP API, and the caller scores them like any scan.
+*/
+use anyhow::Result;
+use serde::Serialize;
+use std::net::Ipv4Addr;
+use std::time::Duration;
+use utoipa::ToSchema;
+
+use crate::net;
+use crate::passive_dns;
+use crate::scan::{self, Observations, ScanReport};
+
+const HACKERTARGET_URL: &str = "https://api.hackertarget.com/reverseiplookup/";
+/// Env var with a HackerTarget API key, for more than the free daily quota.
+const HACKERTARGET_KEY_ENV: &str = "SPOTSPOOF_HACKERTARGET_KEY";
+const TIMEOUT: Duration = Duration::from_secs(15);
+
+/// Where the names on an address come from.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, ToSchema, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum Source {
+    /// Passive DNS when `[passive_dns]` is configured, HackerTarget otherwise.
+    #[default]
+    Auto,
+    PassiveDns,
+    Hackertarget,
+}
+
+/// The domains sharing a host with a spoof domain, and the lookalikes among
+/// them.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct CohostedReport {
+    pub domain: String,
+    /// The domain's A records.
+    pub ips: Vec<String>,
+    /// The reverse-IP source that was queried (never `auto`).
+    pub source: Source,
+    /// The other registrable domains seen on those addresses, sorted.
+    pub cohosted: Vec<String>,
+    /// `cohosted` scored like a scan.
+    pub scan: ScanReport,
+}
+
+/// The registrable domains other than `domain`'s on `ips`, one occurrence per
+/// shared address, and the source that listed them.
+pub fn discover(domain: &str, ips: &[Ipv4Addr], source: Source) -> Result<(Source, Observations)> {
+    net::ensure_online("reverse-IP lookups")?;
+    let source = match source {
+        Source::Auto if passive_dns::installed() => Source::PassiveDns,
+        Source::Auto => Source::Hackertarget,
+        Source::PassiveDns if !passive_dns::installed() => {
+            anyhow::bail!("--source passive-dns needs a [passive_dns] section in the config")
+        }
+        source => source,
+    };
+    let own = scan::domain_token(domain);
+    let client = net::client_builder().timeout(TIMEOUT).build()?;
+    let mut observations = Observations::default();
+    for ip in ips {
+        let ip = ip.to_string();
+        let names = match source {
+            Source::PassiveDns => passive_dns::reverse(&ip).unwrap_or_else(|| Ok(Vec::new()))?,
+            _ => hackertarget(&client, &ip)?,
+        };
+        for name in names {
+            if scan::domain_token(&name) != own {
+                observations.add(&name, None);
+            }
+        }
+    }
+    Ok((source, observations))
+}
+
+fn hackertarget(client: &reqwest::blocking::Client, ip: &str) -> Result<Vec<String>> {
+    let mut query = vec![("q", ip.to_string())];
+    if let Ok(key) = std::env::var(HACKERTARGET_KEY_ENV) {
+        query.push(("apikey", key));
+    }
+    let response =
+        net::send_with_retry("reverse IP", || client.get(HACKERTARGET_URL).query(&query))?;
+    if !response.status().is_success() {
+        anyhow::bail!("HackerTarget: HTTP {}", response.status());
+    }
+    parse_hackertarget(&response.text()?)
+}
+
+/// One name per line; errors and "no records" come back as a sentence.
+fn parse_hackertarget(body: &str) -> Result<Vec<String>> {
+    let lines: Vec<&str> = body
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .collect();
+    if let Some(message) = lines.iter().find(|line| line.contains(char::is_whitespace)) {
+        if message.starts_with("No DNS A records found") {
+            return Ok(Vec::new());
+        }
+        anyhow::bail!("HackerTarget: {message}");
+    }
+    Ok(lines.into_iter().map(str::to_ascii_lowercase).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hackertarget_answers_are_names_or_a_message() {
+        assert_eq!(
+            parse_hackertarget("paypa1.com\nWWW.Amaz0n-Login.com\n").unwrap(),
+            ["paypa1.com", "www.amaz0n-login.com"]
+        );
+        assert!(
+            parse_hackertarget("No DNS A records found for 203.0.113.7\n")
+                .unwrap()
+                .is_empty()
+        );
+        let err = parse_hackertarget("API count exceeded - Increase Quota with Membership")
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("API count exceeded"));
+    }
+}
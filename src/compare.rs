@@ -0,0 +1,477 @@
+/*
+`spotspoof compare`: a deep look at one suspect pair, for when an analyst
+already knows both domains and needs no dataset search. It aligns the two names
+character by character (IDNs in their Unicode form), lists the edit operations,
+flags the substitutions that are visually confusable, compares confusable
+skeletons, checks both registrations, and sums it up in a confidence verdict.
+With `--visual` it also renders both names and scores how alike they look.
+*/
+use anyhow::Result;
+use serde::Serialize;
+use tracing::warn;
+
+use crate::skeleton;
+use crate::types::{self, RegistrationStatus};
+use crate::visual;
+use crate::whois;
+
+/// Similarities at or above these make a high / medium / low confidence.
+const HIGH_SIMILARITY: u8 = 90;
+const MEDIUM_SIMILARITY: u8 = 75;
+const LOW_SIMILARITY: u8 = 60;
+
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct Comparison {
+    pub first: DomainInfo,
+    pub second: DomainInfo,
+    /// 100 minus edits per character of the longer name, an adjacent swap
+    /// counting as one edit.
+    pub similarity: u8,
+    pub distance: usize,
+    /// The edits turning `first` into `second`, left to right.
+    pub operations: Vec<EditOp>,
+    /// Substitutions whose characters look alike.
+    pub confusables: Vec<Confusable>,
+    /// Whether both names fold to the same confusable skeleton.
+    pub skeleton_match: bool,
+    /// How alike the rendered names look (0-100), with `--visual`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub visual_similarity: Option<u8>,
+    pub confidence: Confidence,
+    pub reasons: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct DomainInfo {
+    /// The name as looked up (punycode for IDNs).
+    pub domain: String,
+    /// The Unicode form, for IDNs.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub unicode: Option<String>,
+    pub skeleton: String,
+    /// `None` when registration was not checked.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub registration: Option<RegistrationStatus>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum EditKind {
+    Substitute,
+    Insert,
+    Delete,
+    Transpose,
+}
+
+impl EditKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            EditKind::Substitute => "substitute",
+            EditKind::Insert => "insert",
+            EditKind::Delete => "delete",
+            EditKind::Transpose => "transpose",
+        }
+    }
+}
+
+/// One edit at character `position` of the first name.
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct EditOp {
+    pub op: EditKind,
+    pub position: usize,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub from: String,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub to: String,
+    /// Set on substitutions of lookalike characters.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub confusable: Option<ConfusableKind>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ConfusableKind {
+    /// ASCII lookalikes such as `1`/`l` or `0`/`o`.
+    Ascii,
+    /// A non-ASCII character passing for an ASCII one.
+    Homoglyph,
+}
+
+impl ConfusableKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ConfusableKind::Ascii => "ascii",
+            ConfusableKind::Homoglyph => "homoglyph",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct Confusable {
+    pub from: char,
+    pub to: char,
+    pub kind: ConfusableKind,
+}
+
+/// How likely one name imitates the other.
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum Confidence {
+    None,
+    Low,
+    Medium,
+    High,
+}
+
+impl Confidence {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Confidence::None => "none",
+            Confidence::Low => "low",
+            Confidence::Medium => "medium",
+            Confidence::High => "high",
+        }
+    }
+}
+
+/// Compares `first` and `second`. With `registration_timeout_ms`, both are
+/// checked for registration over DoH; with `visual`, their renderings are
+/// compared too.
+pub fn compare(
+    first: &str,
+    second: &str,
+    registration_timeout_ms: Option<u64>,
+    visual: bool,
+) -> Result<Comparison> {
+    let first = describe(first, registration_timeout_ms)?;
+    let second = describe(second, registration_timeout_ms)?;
+    let (a, b) = (display_form(&first), display_form(&second));
+    let (a, b): (Vec<char>, Vec<char>) = (a.chars().collect(), b.chars().collect());
+
+    let mut operations = align(&a, &b);
+    for op in &mut operations {
+        if let (EditKind::Substitute, Some(from), Some(to)) =
+            (op.op, op.from.chars().next(), op.to.chars().next())
+        {
+            op.confusable = confusable_kind(from, to);
+        }
+    }
+    let distance = operations.len();
+    let longest = a.len().max(b.len());
+    let similarity = if longest == 0 {
+        100
+    } else {
+        (100.0 * (1.0 - distance as f32 / longest as f32))
+            .round()
+            .max(0.0) as u8
+    };
+    let confusables: Vec<Confusable> = operations
+        .iter()
+        .filter_map(|op| {
+            Some(Confusable {
+                from: op.from.chars().next()?,
+                to: op.to.chars().next()?,
+                kind: op.confusable?,
+            })
+        })
+        .collect();
+    let skeleton_match = first.skeleton == second.skeleton;
+    let visual_similarity =
+        visual.then(|| visual::similarity(display_form(&first), display_form(&second)));
+
+    let mut comparison = Comparison {
+        first,
+        second,
+        similarity,
+        distance,
+        operations,
+        confusables,
+        skeleton_match,
+        visual_similarity,
+        confidence: Confidence::None,
+        reasons: Vec::new(),
+    };
+    (comparison.confidence, comparison.reasons) = verdict(&comparison);
+    Ok(comparison)
+}
+
+fn describe(input: &str, registration_timeout_ms: Option<u64>) -> Result<DomainInfo> {
+    let domain = types::parse_domain_input(input)?;
+    let unicode = skeleton::unicode_form(&domain);
+    let skeleton = skeleton::fold(unicode.as_deref().unwrap_or(&domain));
+    // Registration is context for the verdict; without it (offline, DoH down)
+    // the comparison still stands.
+    let registration = registration_timeout_ms.and_then(|timeout_ms| {
+        whois::check_domain_registration(&domain, timeout_ms)
+            .map(|check| check.status)
+            .map_err(
+                |err| warn!(kind = "compare", domain = %domain, "registration check failed: {err}"),
+            )
+            .ok()
+    });
+    Ok(DomainInfo {
+        domain,
+        unicode,
+        skeleton,
+        registration,
+    })
+}
+
+fn display_form(info: &DomainInfo) -> &str {
+    info.unicode.as_deref().unwrap_or(&info.domain)
+}
+
+fn confusable_kind(from: char, to: char) -> Option<ConfusableKind> {
+    if skeleton::fold(&from.to_string()) != skeleton::fold(&to.to_string()) {
+        return None;
+    }
+    Some(if from.is_ascii() && to.is_ascii() {
+        ConfusableKind::Ascii
+    } else {
+        ConfusableKind::Homoglyph
+    })
+}
+
+/// The optimal-string-alignment edits turning `a` into `b`.
+fn align(a: &[char], b: &[char]) -> Vec<EditOp> {
+    let (n, m) = (a.len(), b.len());
+    let mut d = vec![vec![0usize; m + 1]; n + 1];
+    for (i, row) in d.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    d[0] = (0..=m).collect();
+    for i in 1..=n {
+        for j in 1..=m {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            let mut cell = (d[i - 1][j] + 1)
+                .min(d[i][j - 1] + 1)
+                .min(d[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                cell = cell.min(d[i - 2][j - 2] + 1);
+            }
+            d[i][j] = cell;
+        }
+    }
+
+    let mut ops = Vec::new();
+    let (mut i, mut j) = (n, m);
+    while i > 0 || j > 0 {
+        if i > 0 && j > 0 && a[i - 1] == b[j - 1] && d[i][j] == d[i - 1][j - 1] {
+            i -= 1;
+            j -= 1;
+        } else if i > 1
+            && j > 1
+            && a[i - 1] == b[j - 2]
+            && a[i - 2] == b[j - 1]
+            && d[i][j] == d[i - 2][j - 2] + 1
+        {
+            ops.push(EditOp {
+                op: EditKind::Transpose,
+                position: i - 2,
+                from: a[i - 2..i].iter().collect(),
+                to: b[j - 2..j].iter().collect(),
+                confusable: None,
+            });
+            i -= 2;
+            j -= 2;
+        } else if i > 0 && j > 0 && d[i][j] == d[i - 1][j - 1] + 1 {
+            ops.push(EditOp {
+                op: EditKind::Substitute,
+                position: i - 1,
+                from: a[i - 1].to_string(),
+                to: b[j - 1].to_string(),
+                confusable: None,
+            });
+            i -= 1;
+            j -= 1;
+        } else if i > 0 && d[i][j] == d[i - 1][j] + 1 {
+            ops.push(EditOp {
+                op: EditKind::Delete,
+                position: i - 1,
+                from: a[i - 1].to_string(),
+                to: String::new(),
+                confusable: None,
+            });
+            i -= 1;
+        } else {
+            ops.push(EditOp {
+                op: EditKind::Insert,
+                position: i,
+                from: String::new(),
+                to: b[j - 1].to_string(),
+                confusable: None,
+            });
+            j -= 1;
+        }
+    }
+    ops.reverse();
+    ops
+}
+
+fn verdict(comparison: &Comparison) -> (Confidence, Vec<String>) {
+    let Comparison {
+        first,
+        second,
+        similarity,
+        distance,
+        operations,
+        confusables,
+        skeleton_match,
+        visual_similarity,
+        ..
+    } = comparison;
+    if *distance == 0 {
+        return (
+            Confidence::None,
+            vec!["the domains are identical".to_string()],
+        );
+    }
+
+    let mut confidence = match *similarity {
+        s if s >= HIGH_SIMILARITY => Confidence::High,
+        s if s >= MEDIUM_SIMILARITY => Confidence::Medium,
+        s if s >= LOW_SIMILARITY => Confidence::Low,
+        _ => Confidence::None,
+    };
+    let mut reasons = vec![format!(
+        "similarity {similarity} ({distance} edit{})",
+        if *distance == 1 { "" } else { "s" }
+    )];
+    if *skeleton_match {
+        confidence = Confidence::High;
+        reasons.push(format!(
+            "both read as {} once confusable characters are folded",
+            first.skeleton
+        ));
+    }
+    if !confusables.is_empty() && confusables.len() == operations.len() {
+        confidence = Confidence::High;
+        let pairs: Vec<String> = confusables
+            .iter()
+            .map(|confusable| format!("{} -> {}", confusable.from, confusable.to))
+            .collect();
+        reasons.push(format!(
+            "every difference is a confusable character ({})",
+            pairs.join(", ")
+        ));
+    }
+    if let Some(visual) = *visual_similarity {
+        let looks = match visual {
+            v if v >= HIGH_SIMILARITY => Confidence::High,
+            v if v >= MEDIUM_SIMILARITY => Confidence::Medium,
+            _ => Confidence::None,
+        };
+        if looks > Confidence::None {
+            confidence = confidence.max(looks);
+            reasons.push(format!("rendered, the names look {visual}% alike"));
+        }
+    }
+    if operations.iter().any(|op| op.op == EditKind::Transpose) {
+        confidence = confidence.max(Confidence::Medium);
+        reasons.push("adjacent letters are swapped".to_string());
+    }
+    if confidence == Confidence::None {
+        reasons.push("the names are too different to pass for each other".to_string());
+    }
+    for info in [first, second] {
+        match info.registration {
+            Some(RegistrationStatus::Registered) => {
+                reasons.push(format!("{} is registered", info.domain))
+            }
+            Some(RegistrationStatus::Unregistered) => {
+                reasons.push(format!("{} is not registered", info.domain))
+            }
+            Some(RegistrationStatus::Unknown) => reasons.push(format!(
+                "registration of {} could not be determined",
+                info.domain
+            )),
+            None => {}
+        }
+    }
+    (confidence, reasons)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chars(text: &str) -> Vec<char> {
+        text.chars().collect()
+    }
+
+    #[test]
+    fn alignment_lists_each_edit() {
+        let op = |op, position, from: &str, to: &str| EditOp {
+            op,
+            position,
+            from: from.to_string(),
+            to: to.to_string(),
+            confusable: None,
+        };
+        assert_eq!(
+            align(&chars("paypal.com"), &chars("paypa1.com")),
+            [op(EditKind::Substitute, 5, "l", "1")]
+        );
+        assert_eq!(
+            align(&chars("google.com"), &chars("googel.com")),
+            [op(EditKind::Transpose, 4, "le", "el")]
+        );
+        assert_eq!(
+            align(&chars("example.com"), &chars("exampple.co")),
+            [
+                op(EditKind::Insert, 4, "", "p"),
+                op(EditKind::Delete, 10, "m", "")
+            ]
+        );
+        assert!(align(&chars("same"), &chars("same")).is_empty());
+    }
+
+    #[test]
+    fn lookalike_renderings_raise_the_verdict() {
+        let plain = compare("chase.com", "cnase.com", None, false).unwrap();
+        assert_eq!(plain.visual_similarity, None);
+        assert_eq!(plain.confidence, Confidence::Medium);
+        let rendered = compare("chase.com", "cnase.com", None, true).unwrap();
+        assert!(rendered.visual_similarity.unwrap() >= HIGH_SIMILARITY);
+        assert_eq!(rendered.confidence, Confidence::High);
+        assert!(rendered
+            .reasons
+            .iter()
+            .any(|reason| reason.contains("look")));
+    }
+
+    #[test]
+    fn confusable_pairs_score_high() {
+        let comparison = compare("paypal.com", "paypa1.com", None, false).unwrap();
+        assert_eq!(comparison.similarity, 90);
+        assert!(comparison.skeleton_match);
+        assert_eq!(
+            comparison.confusables,
+            [Confusable {
+                from: 'l',
+                to: '1',
+                kind: ConfusableKind::Ascii
+            }]
+        );
+        assert_eq!(comparison.confidence, Confidence::High);
+        assert_eq!(comparison.second.registration, None);
+
+        let idn = compare("paypal.com", "xn--pypal-4ve.com", None, false).unwrap();
+        assert_eq!(idn.second.unicode.as_deref(), Some("pаypal.com"));
+        assert!(idn.skeleton_match);
+        assert_eq!(idn.confusables[0].kind, ConfusableKind::Homoglyph);
+        assert_eq!(idn.confidence, Confidence::High);
+    }
+
+    #[test]
+    fn unrelated_and_identical_pairs_score_none() {
+        let unrelated = compare("paypal.com", "example.org", None, false).unwrap();
+        assert_eq!(unrelated.confidence, Confidence::None);
+        assert!(!unrelated.skeleton_match);
+
+        let same = compare("PayPal.com", "https://paypal.com/login", None, false).unwrap();
+        assert_eq!(same.distance, 0);
+        assert_eq!(same.confidence, Confidence::None);
+        assert_eq!(same.reasons, ["the domains are identical"]);
+    }
+}
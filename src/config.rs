@@ -0,0 +1,208 @@
+/*
+Optional TOML configuration file. Read from `--config`, `SPOTSPOOF_CONFIG`, or
+the per-user config directory; a missing default file simply means defaults.
+*/
+use anyhow::Result;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use crate::alerts::AlertsConfig;
+use crate::ensemble::Weights;
+use crate::geoip::GeoIpConfig;
+use crate::kafka::KafkaConfig;
+use crate::passive_dns::PassiveDnsConfig;
+use crate::screenshot::ScreenshotConfig;
+
+const CONFIG_FILE: &str = "config.toml";
+
+#[derive(Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub network: NetworkConfig,
+    /// Named domain DBs (`[datasets.<name>]`), selected with `--dataset` on
+    /// the CLI and the `dataset` request field of `serve`.
+    pub datasets: BTreeMap<String, DatasetConfig>,
+    /// TLD risk weights in percent (`top = 150`, `"gov.uk" = 60`), replacing
+    /// or adding to the built-in table.
+    pub tld_risk: BTreeMap<String, u16>,
+    /// Relative weights of the ASCII similarity scorers (`edit_distance`,
+    /// `confusable`, `phonetic`, `ngram`, `keyboard`).
+    pub scoring: Weights,
+    /// Where `tail` and the scan commands send their findings.
+    pub alerts: AlertsConfig,
+    /// The Kafka topic detections (and optionally lookups) are published to.
+    pub kafka: KafkaConfig,
+    /// The passive DNS provider lookalike lookups and scan findings are
+    /// enriched from.
+    pub passive_dns: PassiveDnsConfig,
+    /// The MaxMind DBs `--resolve` locates addresses with.
+    pub geoip: GeoIpConfig,
+    /// How `screenshot` and alert screenshots load pages.
+    pub screenshot: ScreenshotConfig,
+}
+
+impl Config {
+    /// The DB path of the dataset called `name`.
+    pub fn dataset_path(&self, name: &str) -> Result<String> {
+        match self.datasets.get(name) {
+            Some(dataset) => Ok(dataset.db.clone()),
+            None if self.datasets.is_empty() => {
+                anyhow::bail!("unknown dataset {name}: the config defines no [datasets]")
+            }
+            None => anyhow::bail!(
+                "unknown dataset {name} (configured: {})",
+                self.datasets.keys().cloned().collect::<Vec<_>>().join(", ")
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct NetworkConfig {
+    /// Proxy for all outbound traffic (`http://`, `https://`, `socks5://`, or
+    /// `socks5h://`). Overrides the `HTTPS_PROXY`/`ALL_PROXY` env vars.
+    pub proxy: Option<String>,
+    /// Comma-separated hosts that bypass `proxy` (same syntax as `NO_PROXY`).
+    pub no_proxy: Option<String>,
+    /// Make no network calls at all (same as `--offline`).
+    pub offline: bool,
+}
+
+#[derive(Debug, Deserialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct DatasetConfig {
+    /// Path of the dataset's SQLite DB.
+    pub db: String,
+}
+
+/// Loads `path` if given (it must exist), else the default config file if
+/// there is one.
+pub fn load(path: Option<&str>) -> Result<Config> {
+    let path = match path {
+        Some(path) => PathBuf::from(path),
+        None => match default_path() {
+            Some(path) if path.is_file() => path,
+            _ => return Ok(Config::default()),
+        },
+    };
+    let text = std::fs::read_to_string(&path)
+        .map_err(|err| anyhow::anyhow!("cannot read config {}: {err}", path.display()))?;
+    parse(&text).map_err(|err| anyhow::anyhow!("invalid config {}: {err}", path.display()))
+}
+
+fn parse(text: &str) -> Result<Config> {
+    let config: Config = toml::from_str(text)?;
+    config.scoring.validate()?;
+    Ok(config)
+}
+
+fn default_path() -> Option<PathBuf> {
+    Some(crate::paths::config_dir()?.join(CONFIG_FILE))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_network_section_and_rejects_unknown_keys() {
+        let config = parse(
+            "[network]\nproxy = \"socks5h://127.0.0.1:1080\"\nno_proxy = \"localhost,.corp\"\noffline = true\n",
+        )
+        .unwrap();
+        assert_eq!(
+            config.network.proxy.as_deref(),
+            Some("socks5h://127.0.0.1:1080")
+        );
+        assert_eq!(config.network.no_proxy.as_deref(), Some("localhost,.corp"));
+        assert!(config.network.offline);
+        assert_eq!(parse("").unwrap(), Config::default());
+        assert!(parse("[network]\nproxxy = \"x\"\n").is_err());
+    }
+
+    #[test]
+    fn datasets_resolve_by_name() {
+        let config = parse(
+            "[datasets.global]\ndb = \"/srv/spotspoof.sqlite\"\n\n[datasets.finance]\ndb = \"finance.sqlite\"\n",
+        )
+        .unwrap();
+        assert_eq!(config.dataset_path("finance").unwrap(), "finance.sqlite");
+        assert_eq!(
+            config.dataset_path("internal").unwrap_err().to_string(),
+            "unknown dataset internal (configured: finance, global)"
+        );
+        assert!(Config::default().dataset_path("global").is_err());
+        assert!(parse("[datasets.global]\npath = \"x\"\n").is_err());
+    }
+
+    #[test]
+    fn parses_tld_risk_weights() {
+        let config = parse("[tld_risk]\ntop = 150\n\"gov.uk\" = 60\n").unwrap();
+        assert_eq!(config.tld_risk.get("top"), Some(&150));
+        assert_eq!(config.tld_risk.get("gov.uk"), Some(&60));
+        assert!(parse("[tld_risk]\ntop = \"high\"\n").is_err());
+    }
+
+    #[test]
+    fn parses_scoring_weights() {
+        let config = parse("[scoring]\nedit_distance = 2\nphonetic = 1\n").unwrap();
+        assert_eq!(config.scoring.edit_distance, 2);
+        assert_eq!(config.scoring.phonetic, 1);
+        assert_eq!(config.scoring.keyboard, 0);
+        assert_eq!(parse("").unwrap().scoring, Weights::default());
+        assert!(parse("[scoring]\nedit_distance = 0\n").is_err());
+        assert!(parse("[scoring]\nsoundex = 1\n").is_err());
+    }
+
+    #[test]
+    fn parses_alert_channels() {
+        let config = parse(
+            "[alerts]\nwebhook = [\"https://hooks.example/spotspoof\"]\nemail = [\"soc@example.com\"]\nmin_severity = \"high\"\n",
+        )
+        .unwrap();
+        assert_eq!(config.alerts.channels().len(), 2);
+        assert_eq!(
+            config.alerts.min_severity,
+            Some(crate::detections::Severity::High)
+        );
+        assert!(parse("[alerts]\npagerduty = []\n").is_err());
+    }
+
+    #[test]
+    fn parses_passive_dns_provider() {
+        let config = parse(
+            "[passive_dns]\nurl = \"https://api.dnsdb.info/dnsdb/v2\"\nprovider = \"dnsdb\"\napi_key = \"k\"\n",
+        )
+        .unwrap();
+        assert!(config.passive_dns.enabled());
+        assert_eq!(
+            config.passive_dns.provider,
+            crate::passive_dns::Provider::Dnsdb
+        );
+        assert!(!parse("").unwrap().passive_dns.enabled());
+        assert!(parse("[passive_dns]\nprovider = \"virustotal\"\n").is_err());
+    }
+
+    #[test]
+    fn parses_geoip_databases() {
+        let config = parse("[geoip]\ndatabases = [\"/srv/GeoLite2-ASN.mmdb\"]\n").unwrap();
+        assert_eq!(config.geoip.databases, ["/srv/GeoLite2-ASN.mmdb"]);
+        assert!(parse("[geoip]\ncity = \"x\"\n").is_err());
+    }
+
+    #[test]
+    fn parses_screenshot_settings() {
+        let config = parse(
+            "[alerts]\nwebhook = [\"https://hooks.example/a\"]\nscreenshots = true\n\n[screenshot]\nchrome = \"/usr/bin/chromium\"\nno_sandbox = true\n",
+        )
+        .unwrap();
+        assert!(config.alerts.screenshots);
+        assert_eq!(
+            config.screenshot.chrome.as_deref(),
+            Some("/usr/bin/chromium")
+        );
+        assert!(config.screenshot.no_sandbox);
+    }
+}
@@ -1,40 +1,160 @@
 /*
-SQLite database utilities. Handles opening the local DB, verifying the downloaded
-compressed DB via SHA-256, and downloading/unpacking the release database when needed.
+SQLite database utilities. Handles opening and verifying the local DB, candidate
+selection, the dataset version in the `meta` table, and fetching the release
+database (see `download`) when it is missing.
 */
 use anyhow::Result;
-use rusqlite::Connection;
+use r2d2_sqlite::SqliteConnectionManager;
+use rusqlite::functions::FunctionFlags;
+use rusqlite::{Connection, OpenFlags};
 use sha2::{Digest, Sha256};
-use std::fs::{self, File};
-use std::io::{Read, Write};
+use std::fs::File;
+use std::time::Duration;
+
+use crate::candidates::{shares_affix, skeleton};
+use crate::cipher;
+use crate::download;
+
+pub use crate::candidates::{
+    CandidateQuery, CandidateSource, CandidateStrategies, CandidateStrategy,
+};
 
-const DEFAULT_DB_SHA256_PATH: &str = "config/db_sha256.txt";
-const EMBEDDED_DB_SHA256: &str = include_str!("../config/db_sha256.txt");
 pub const DEFAULT_DB_URL: &str =
     "https://github.com/slimpagey/spotspoof-cli/releases/latest/download/spotspoof.sqlite.zst";
-const ZSTD_WINDOW_LOG_MAX: u32 = 31;
+pub const DEFAULT_POOL_SIZE: u32 = 8;
+const MMAP_SIZE: i64 = 256 * 1024 * 1024;
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+pub type Pool = r2d2::Pool<SqliteConnectionManager>;
 
-pub fn open(path: &str) -> Result<Connection> {
-    let conn = Connection::open(path)?;
+/// Opens the DB for lookups: read-only, memory-mapped, and waiting out brief
+/// locks. `immutable` additionally skips all locking and change detection, which
+/// is only safe when nothing rewrites the file in place (replacing it by rename
+/// is fine).
+pub fn open(path: &str, immutable: bool) -> Result<Connection> {
+    let conn = Connection::open_with_flags(read_only_uri(path, immutable), read_only_flags())?;
+    if cfg!(feature = "sqlcipher") {
+        cipher::apply(&conn)?;
+        cipher::check(&conn, path)?;
+    }
+    tune_read_only(&conn)?;
+    register_functions(&conn)?;
     Ok(conn)
 }
 
-pub fn fetch_candidates(
-    conn: &Connection,
-    first_char: char,
-    min_len: usize,
-    max_len: usize,
-    limit: usize,
-) -> Result<Vec<String>> {
-    let mut stmt = conn.prepare(
-		"SELECT domain FROM legit_domains WHERE first_char = ?1 AND length >= ?2 AND length <= ?3 LIMIT ?4",
-	)?;
+/// Opens (creating it and its directory if needed) a writable store such as
+/// the history, detections, alert outbox, or DNS cache. The CLI and a running
+/// server may share the file, so it is put in WAL mode and waits out brief
+/// locks.
+pub fn open_shared_store(path: &str) -> Result<Connection> {
+    if let Some(parent) = std::path::Path::new(path).parent() {
+        if !parent.as_os_str().is_empty() {
+            std::fs::create_dir_all(parent)?;
+        }
+    }
+    let conn = cipher::open(path)?;
+    conn.busy_timeout(BUSY_TIMEOUT)?;
+    conn.pragma_update_and_check(None, "journal_mode", "WAL", |row| row.get::<_, String>(0))?;
+    Ok(conn)
+}
+
+/// Builds a pool of read-only connections (see `open`). Connections are opened
+/// lazily on first checkout, so a missing file surfaces as a lookup error
+/// rather than at startup.
+pub fn pool(path: &str, max_size: u32, immutable: bool) -> Pool {
+    let manager = SqliteConnectionManager::file(read_only_uri(path, immutable))
+        .with_flags(read_only_flags())
+        .with_init(|conn| {
+            cipher::apply(conn)?;
+            tune_read_only(conn)?;
+            register_functions(conn)
+        });
+    r2d2::Pool::builder()
+        .max_size(max_size)
+        .min_idle(Some(0))
+        .build_unchecked(manager)
+}
+
+fn read_only_flags() -> OpenFlags {
+    OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_URI | OpenFlags::SQLITE_OPEN_NO_MUTEX
+}
+
+fn read_only_uri(path: &str, immutable: bool) -> String {
+    let mut escaped = String::with_capacity(path.len());
+    for c in path.chars() {
+        match c {
+            '%' => escaped.push_str("%25"),
+            '?' => escaped.push_str("%3f"),
+            '#' => escaped.push_str("%23"),
+            _ => escaped.push(c),
+        }
+    }
+    let mode = if immutable { "immutable=1" } else { "mode=ro" };
+    format!("file:{escaped}?{mode}")
+}
+
+fn tune_read_only(conn: &Connection) -> rusqlite::Result<()> {
+    conn.busy_timeout(BUSY_TIMEOUT)?;
+    conn.pragma_update(None, "mmap_size", MMAP_SIZE)?;
+    conn.pragma_update(None, "query_only", true)
+}
+
+/// Registers `editdist(a, b, max)`, the optimal string alignment distance, or
+/// NULL once it exceeds `max` (computing stops there), so scans can drop
+/// far-off domains inside SQLite. It never exceeds the Levenshtein distance, so
+/// filtering on it keeps every candidate either algorithm accepts.
+/// `sharesaffix(a, b, min)` is the cheaper prefilter checked before it (see
+/// `candidates::shares_affix`).
+pub fn register_functions(conn: &Connection) -> rusqlite::Result<()> {
+    conn.create_scalar_function(
+        "sharesaffix",
+        3,
+        FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC,
+        |ctx| {
+            let arg = |i| {
+                ctx.get_raw(i)
+                    .as_str()
+                    .map_err(|err| rusqlite::Error::UserFunctionError(err.into()))
+            };
+            let min: i64 = ctx.get(2)?;
+            Ok(shares_affix(arg(0)?, arg(1)?, min.max(0) as usize))
+        },
+    )?;
+    conn.create_scalar_function(
+        "editdist",
+        3,
+        FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC,
+        |ctx| {
+            let arg = |i| {
+                ctx.get_raw(i)
+                    .as_str()
+                    .map_err(|err| rusqlite::Error::UserFunctionError(err.into()))
+            };
+            let max: i64 = ctx.get(2)?;
+            let distance = crate::ascii_spoof::damerau_levenshtein_within(
+                arg(0)?,
+                arg(1)?,
+                max.max(0) as usize,
+            );
+            Ok(distance.map(|distance| distance as i64))
+        },
+    )
+}
+
+pub fn fetch_candidates(conn: &Connection, query: &CandidateQuery) -> Result<Vec<String>> {
+    let mut stmt = conn.prepare_cached(
+        "SELECT domain FROM legit_domains WHERE first_char = ?1 AND length >= ?2 AND length <= ?3
+			AND sharesaffix(domain, ?4, ?7) AND editdist(domain, ?4, ?5) IS NOT NULL LIMIT ?6",
+    )?;
     let rows = stmt.query_map(
         (
-            first_char.to_string(),
-            min_len as i64,
-            max_len as i64,
-            limit as i64,
+            query.first_char.to_string(),
+            query.min_len as i64,
+            query.max_len as i64,
+            query.domain,
+            query.max_distance,
+            query.limit as i64,
+            query.min_affix as i64,
         ),
         |row| row.get::<_, String>(0),
     )?;
@@ -46,211 +166,536 @@ pub fn fetch_candidates(
     Ok(out)
 }
 
-pub fn download_db(url: &str, db_path: &str) -> Result<()> {
-    if let Some(parent) = std::path::Path::new(db_path).parent() {
-        if !parent.as_os_str().is_empty() {
-            fs::create_dir_all(parent)?;
+/// Collects candidates in strategy order, skipping duplicates, up to a limit.
+struct Picked {
+    seen: std::collections::HashSet<String>,
+    out: Vec<String>,
+    limit: usize,
+}
+
+impl Picked {
+    fn new(limit: usize) -> Self {
+        Self {
+            seen: std::collections::HashSet::new(),
+            out: Vec::new(),
+            limit,
         }
     }
 
-    let response = reqwest::blocking::get(url)?;
-    if !response.status().is_success() {
-        return Err(anyhow::anyhow!(
-            "Download failed: HTTP {}",
-            response.status()
-        ));
+    fn push(&mut self, domain: String) {
+        if !self.is_full() && self.seen.insert(domain.clone()) {
+            self.out.push(domain);
+        }
     }
 
-    let compressed = response.bytes()?;
-    verify_db_sha256(&compressed)?;
+    fn is_full(&self) -> bool {
+        self.out.len() >= self.limit
+    }
+}
 
-    let tmp_path = format!("{db_path}.tmp");
-    let mut decoder = zstd::stream::read::Decoder::new(std::io::Cursor::new(compressed))?;
-    decoder.window_log_max(ZSTD_WINDOW_LOG_MAX)?;
-    let mut out = File::create(&tmp_path)?;
-    std::io::copy(&mut decoder, &mut out)?;
-    out.flush()?;
-    fs::rename(tmp_path, db_path)?;
-    Ok(())
+/// Whether `domain` is picked by one of the band-scanning strategies.
+fn band_matches(query: &CandidateQuery, domain: &str) -> bool {
+    let strategies = query.strategies;
+    if strategies.contains(CandidateStrategy::FirstOrSecond) {
+        let query_head: Vec<char> = query.domain.chars().take(2).collect();
+        if domain.chars().take(2).any(|c| query_head.contains(&c)) {
+            return true;
+        }
+    }
+    if strategies.contains(CandidateStrategy::LastChar)
+        && label_last_char(domain).is_some()
+        && label_last_char(domain) == label_last_char(query.domain)
+    {
+        return true;
+    }
+    strategies.contains(CandidateStrategy::Skeleton) && skeleton(domain) == skeleton(query.domain)
+}
+
+fn label_last_char(domain: &str) -> Option<char> {
+    domain.split('.').next()?.chars().last()
+}
+
+impl CandidateSource for Connection {
+    fn candidates(&self, query: &CandidateQuery) -> Result<Vec<String>> {
+        let mut picked = Picked::new(query.limit);
+        if query.strategies.contains(CandidateStrategy::FirstChar) {
+            for domain in fetch_candidates(self, query)? {
+                picked.push(domain);
+            }
+        }
+
+        let length_only =
+            picked.out.is_empty() && query.strategies.contains(CandidateStrategy::LengthOnly);
+        if query.strategies.scans_band() || length_only {
+            let mut stmt = self.prepare_cached(
+                "SELECT domain FROM legit_domains WHERE length >= ?1 AND length <= ?2
+				AND sharesaffix(domain, ?3, ?5) AND editdist(domain, ?3, ?4) IS NOT NULL",
+            )?;
+            let mut rows = stmt.query((
+                query.min_len as i64,
+                query.max_len as i64,
+                query.domain,
+                query.max_distance,
+                query.min_affix as i64,
+            ))?;
+            let mut fallback = Vec::new();
+            while let Some(row) = rows.next()? {
+                if picked.is_full() {
+                    break;
+                }
+                let domain: String = row.get(0)?;
+                if band_matches(query, &domain) {
+                    picked.push(domain);
+                } else if length_only && fallback.len() < query.limit {
+                    fallback.push(domain);
+                }
+            }
+            if picked.out.is_empty() {
+                picked.out = fallback;
+            }
+        }
+        Ok(picked.out)
+    }
+
+    /// Queries using only the first-character strategy that share a first
+    /// character are answered by one scan over the union of their length
+    /// bands, with each row filtered per query in Rust, rather than one scan
+    /// each. Rows come in table order, so every query picks what its own
+    /// scan would have.
+    fn candidates_batch(&self, queries: &[CandidateQuery]) -> Result<Vec<Vec<String>>> {
+        let mut out = vec![Vec::new(); queries.len()];
+        let mut buckets: std::collections::BTreeMap<char, Vec<usize>> =
+            std::collections::BTreeMap::new();
+        for (i, query) in queries.iter().enumerate() {
+            if query.strategies == CandidateStrategies::default() {
+                buckets.entry(query.first_char).or_default().push(i);
+            } else {
+                out[i] = self.candidates(query)?;
+            }
+        }
+
+        let mut stmt = self.prepare_cached(
+            "SELECT domain, length FROM legit_domains WHERE first_char = ?1 AND length >= ?2
+				AND length <= ?3",
+        )?;
+        for (first_char, members) in buckets {
+            if let [i] = members[..] {
+                out[i] = self.candidates(&queries[i])?;
+                continue;
+            }
+            let min_len = members.iter().map(|&i| queries[i].min_len).min();
+            let max_len = members.iter().map(|&i| queries[i].max_len).max();
+            let mut picked: Vec<Picked> = members
+                .iter()
+                .map(|&i| Picked::new(queries[i].limit))
+                .collect();
+            let mut rows = stmt.query((
+                first_char.to_string(),
+                min_len.unwrap_or_default() as i64,
+                max_len.unwrap_or_default() as i64,
+            ))?;
+            while let Some(row) = rows.next()? {
+                if picked.iter().all(Picked::is_full) {
+                    break;
+                }
+                let domain: String = row.get(0)?;
+                let length = row.get::<_, i64>(1)? as usize;
+                for (&i, picked) in members.iter().zip(&mut picked) {
+                    let query = &queries[i];
+                    if !picked.is_full()
+                        && (query.min_len..=query.max_len).contains(&length)
+                        && shares_affix(&domain, query.domain, query.min_affix)
+                        && crate::ascii_spoof::damerau_levenshtein_within(
+                            &domain,
+                            query.domain,
+                            query.max_distance as usize,
+                        )
+                        .is_some()
+                    {
+                        picked.push(domain.clone());
+                    }
+                }
+            }
+            for (i, picked) in members.into_iter().zip(picked) {
+                out[i] = picked.out;
+            }
+        }
+        Ok(out)
+    }
+}
+
+/// One `legit_domains` row held by `MemoryIndex`; `position` is the row's
+/// place in table order.
+struct MemoryEntry {
+    position: u32,
+    length: usize,
+    domain: String,
+}
+
+/// `legit_domains` loaded into RAM, grouped by first character and kept in
+/// table order so candidate selection matches the SQL query.
+pub struct MemoryIndex {
+    by_first_char: std::collections::HashMap<char, Vec<MemoryEntry>>,
+    len: usize,
+}
+
+impl MemoryIndex {
+    pub fn load(conn: &Connection) -> Result<Self> {
+        let mut stmt = conn.prepare("SELECT domain, first_char, length FROM legit_domains")?;
+        let mut rows = stmt.query([])?;
+        let mut by_first_char: std::collections::HashMap<char, Vec<MemoryEntry>> =
+            std::collections::HashMap::new();
+        let mut len = 0;
+        while let Some(row) = rows.next()? {
+            let domain: String = row.get(0)?;
+            let first_char: String = row.get(1)?;
+            let length: i64 = row.get(2)?;
+            if let Some(first_char) = first_char.chars().next() {
+                by_first_char
+                    .entry(first_char)
+                    .or_default()
+                    .push(MemoryEntry {
+                        position: len as u32,
+                        length: length as usize,
+                        domain,
+                    });
+                len += 1;
+            }
+        }
+        Ok(Self { by_first_char, len })
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+impl CandidateSource for MemoryIndex {
+    fn candidates(&self, query: &CandidateQuery) -> Result<Vec<String>> {
+        let in_band = |entry: &&MemoryEntry| {
+            (query.min_len..=query.max_len).contains(&entry.length)
+                && shares_affix(&entry.domain, query.domain, query.min_affix)
+                && crate::ascii_spoof::damerau_levenshtein_within(
+                    &entry.domain,
+                    query.domain,
+                    query.max_distance as usize,
+                )
+                .is_some()
+        };
+        let mut picked = Picked::new(query.limit);
+        if query.strategies.contains(CandidateStrategy::FirstChar) {
+            if let Some(entries) = self.by_first_char.get(&query.first_char) {
+                for entry in entries.iter().filter(in_band) {
+                    if picked.is_full() {
+                        break;
+                    }
+                    picked.push(entry.domain.clone());
+                }
+            }
+        }
+
+        let length_only =
+            picked.out.is_empty() && query.strategies.contains(CandidateStrategy::LengthOnly);
+        if query.strategies.scans_band() || length_only {
+            // Walk the band in table order, as the SQL scan does.
+            let mut band: Vec<&MemoryEntry> = self
+                .by_first_char
+                .values()
+                .flatten()
+                .filter(in_band)
+                .collect();
+            band.sort_unstable_by_key(|entry| entry.position);
+            let mut fallback = Vec::new();
+            for entry in band {
+                if picked.is_full() {
+                    break;
+                }
+                if band_matches(query, &entry.domain) {
+                    picked.push(entry.domain.clone());
+                } else if length_only && fallback.len() < query.limit {
+                    fallback.push(entry.domain.clone());
+                }
+            }
+            if picked.out.is_empty() {
+                picked.out = fallback;
+            }
+        }
+        Ok(picked.out)
+    }
 }
 
 pub fn ensure_db(db_path: &str, url: &str) -> Result<()> {
     let path = std::path::Path::new(db_path);
     if !path.exists() {
-        return download_db(url, db_path);
+        return download::download_db(url, db_path).map(|_| ());
     }
 
-    let conn = match open(db_path) {
+    let conn = match open(db_path, false) {
         Ok(conn) => conn,
-        Err(_) => return download_db(url, db_path),
+        Err(err) if cipher::keeps(db_path) => return Err(err),
+        Err(_) => return download::download_db(url, db_path).map(|_| ()),
     };
-    let mut stmt = match conn.prepare(
+    if verify_schema(&conn).is_err() {
+        return download::download_db(url, db_path).map(|_| ());
+    }
+
+    Ok(())
+}
+
+pub fn verify_schema(conn: &Connection) -> Result<()> {
+    let mut stmt = conn.prepare(
         "SELECT name FROM sqlite_master WHERE type='table' AND name='legit_domains' LIMIT 1",
-    ) {
-        Ok(stmt) => stmt,
-        Err(_) => return download_db(url, db_path),
-    };
-    let mut rows = match stmt.query([]) {
-        Ok(rows) => rows,
-        Err(_) => return download_db(url, db_path),
-    };
+    )?;
+    let mut rows = stmt.query([])?;
     if rows.next()?.is_none() {
-        return download_db(url, db_path);
+        return Err(anyhow::anyhow!("DB is missing the legit_domains table"));
     }
-
     Ok(())
 }
 
-fn read_expected_db_sha256() -> Result<String> {
-    let mut candidates = Vec::new();
-    if let Ok(path) = std::env::var("SPOTSPOOF_DB_SHA256_PATH") {
-        candidates.push(path);
+/// Whether `domain` is one of the dataset's legitimate domains.
+pub fn is_legit_domain(conn: &Connection, domain: &str) -> Result<bool> {
+    let Some(first_char) = domain.chars().next() else {
+        return Ok(false);
+    };
+    let mut stmt = conn.prepare_cached(
+        "SELECT 1 FROM legit_domains WHERE first_char = ?1 AND length = ?2 AND domain = ?3 LIMIT 1",
+    )?;
+    Ok(stmt.exists((
+        first_char.to_string(),
+        domain.chars().count() as i64,
+        domain,
+    ))?)
+}
+
+/// The dataset version recorded in the DB's `meta` table, if it has one.
+pub fn dataset_version(conn: &Connection) -> Result<Option<String>> {
+    meta(conn, "version")
+}
+
+/// Records `version` as the dataset version.
+pub fn set_dataset_version(conn: &Connection, version: &str) -> Result<()> {
+    set_meta(conn, "version", version)
+}
+
+/// A value from the `meta` table, if the DB has the table and the key.
+pub fn meta(conn: &Connection, key: &str) -> Result<Option<String>> {
+    let has_meta = conn
+        .prepare("SELECT name FROM sqlite_master WHERE type='table' AND name='meta' LIMIT 1")?
+        .exists([])?;
+    if !has_meta {
+        return Ok(None);
     }
-    if let Ok(exe) = std::env::current_exe() {
-        if let Some(dir) = exe.parent() {
-            candidates.push(
-                dir.join(DEFAULT_DB_SHA256_PATH)
-                    .to_string_lossy()
-                    .to_string(),
-            );
-        }
+    let mut stmt = conn.prepare("SELECT value FROM meta WHERE key = ?1")?;
+    let mut rows = stmt.query([key])?;
+    Ok(match rows.next()? {
+        Some(row) => Some(row.get(0)?),
+        None => None,
+    })
+}
+
+/// Sets a `meta` value, creating the table if needed.
+pub fn set_meta(conn: &Connection, key: &str, value: &str) -> Result<()> {
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS meta (key TEXT PRIMARY KEY, value TEXT NOT NULL)",
+    )?;
+    conn.execute(
+        "INSERT INTO meta (key, value) VALUES (?1, ?2)
+		 ON CONFLICT (key) DO UPDATE SET value = excluded.value",
+        [key, value],
+    )?;
+    Ok(())
+}
+
+pub fn verify_db_file(path: &str, expected_sha256: Option<&str>) -> Result<()> {
+    if !std::path::Path::new(path).is_file() {
+        return Err(anyhow::anyhow!("DB file not found: {path}"));
     }
-    candidates.push(DEFAULT_DB_SHA256_PATH.to_string());
-
-    let mut last_err = None;
-    let mut contents = String::new();
-    for path in candidates {
-        match File::open(&path) {
-            Ok(mut file) => {
-                file.read_to_string(&mut contents)?;
-                return Ok(contents.trim().to_lowercase());
-            }
-            Err(err) => last_err = Some(err),
+    if let Some(expected) = expected_sha256 {
+        let expected = expected.trim().to_lowercase();
+        let actual = file_sha256(path)?;
+        if actual != expected {
+            return Err(anyhow::anyhow!(
+                "DB checksum mismatch: expected {expected}, got {actual}"
+            ));
         }
     }
-    if !EMBEDDED_DB_SHA256.trim().is_empty() {
-        return Ok(EMBEDDED_DB_SHA256.trim().to_lowercase());
-    }
-    let err = last_err.unwrap_or_else(|| {
-        std::io::Error::new(std::io::ErrorKind::NotFound, "DB checksum file not found")
-    });
-    Err(err.into())
+    let conn = open(path, false)?;
+    verify_schema(&conn)
 }
 
-fn verify_db_sha256(compressed: &[u8]) -> Result<()> {
-    let expected = read_expected_db_sha256()?;
+pub fn file_sha256(path: &str) -> Result<String> {
+    let mut file = File::open(path)?;
     let mut hasher = Sha256::new();
-    hasher.update(compressed);
-    let actual = format!("{:x}", hasher.finalize());
-    if actual != expected {
-        return Err(anyhow::anyhow!(
-            "DB checksum mismatch: expected {expected}, got {actual}"
-        ));
+    std::io::copy(&mut file, &mut hasher)?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// An in-memory DB with the SQL functions registered and `domains` in
+/// `legit_domains`, in order, for tests.
+#[cfg(test)]
+pub(crate) fn test_db(domains: &[&str]) -> Connection {
+    let conn = Connection::open_in_memory().unwrap();
+    register_functions(&conn).unwrap();
+    conn.execute_batch(
+        "CREATE TABLE legit_domains (domain TEXT, first_char TEXT, length INTEGER);",
+    )
+    .unwrap();
+    for domain in domains {
+        conn.execute(
+            "INSERT INTO legit_domains VALUES (?1, substr(?1, 1, 1), length(?1))",
+            [domain],
+        )
+        .unwrap();
     }
-    Ok(())
+    conn
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::io::Read;
-    use std::net::TcpListener;
-    use std::sync::Mutex;
-    use std::thread;
-    use std::time::{SystemTime, UNIX_EPOCH};
-
-    static ENV_LOCK: Mutex<()> = Mutex::new(());
-
-    fn tmp_path(name: &str) -> String {
-        let now = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap_or_default()
-            .as_millis();
-        let mut path = std::env::temp_dir();
-        path.push(format!("spotspoof-cli-{name}-{now}.sqlite"));
-        path.to_string_lossy().to_string()
-    }
 
-    fn start_server(response: Vec<u8>, expected_method: &str, expected_path: &str) -> String {
-        let listener = TcpListener::bind("127.0.0.1:0").expect("bind");
-        let addr = listener.local_addr().expect("addr");
-        let expected_method = expected_method.to_string();
-        let expected_path = expected_path.to_string();
-        thread::spawn(move || {
-            if let Ok((mut stream, _)) = listener.accept() {
-                let mut buf = [0u8; 1024];
-                let read = stream.read(&mut buf).unwrap_or(0);
-                let request = String::from_utf8_lossy(&buf[..read]);
-                let ok = request
-                    .lines()
-                    .next()
-                    .map(|line| line.starts_with(&format!("{expected_method} {expected_path} ")))
-                    .unwrap_or(false);
-                if ok {
-                    let _ = stream.write_all(&response);
-                } else {
-                    let _ =
-                        stream.write_all(b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n");
-                }
-            }
-        });
-        format!("http://{addr}/db.zst")
+    #[test]
+    fn memory_index_matches_sql_candidates() {
+        let conn = test_db(&["ab.com", "abcdef.com", "abc.com", "abd.com", "bcd.com"]);
+        let index = MemoryIndex::load(&conn).unwrap();
+        assert_eq!(index.len(), 5);
+        let query = |first_char, limit| CandidateQuery {
+            domain: "abc.com",
+            first_char,
+            min_len: 6,
+            max_len: 8,
+            max_distance: 1,
+            limit,
+            strategies: CandidateStrategies::default(),
+            min_affix: 0,
+        };
+        for limit in [1, 5] {
+            assert_eq!(
+                index.candidates(&query('a', limit)).unwrap(),
+                conn.candidates(&query('a', limit)).unwrap()
+            );
+        }
+        assert!(index.candidates(&query('z', 5)).unwrap().is_empty());
     }
 
     #[test]
-    fn download_db_writes_decompressed_file() {
-        let _guard = ENV_LOCK.lock().unwrap();
-        let db_path = tmp_path("download");
-        let body = zstd::stream::encode_all("hello".as_bytes(), 0).expect("compress");
-        let mut hasher = Sha256::new();
-        hasher.update(&body);
-        let hash = format!("{:x}", hasher.finalize());
-        let sha_path = tmp_path("sha256");
-        fs::write(&sha_path, hash).expect("write sha");
-        std::env::set_var("SPOTSPOOF_DB_SHA256_PATH", &sha_path);
-
-        let response = [
-            format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n", body.len()).into_bytes(),
-            body,
-        ]
-        .concat();
-        let url = start_server(response, "GET", "/db.zst");
-
-        download_db(&url, &db_path).expect("download should succeed");
-        let contents = fs::read(&db_path).expect("read db");
-        assert_eq!(contents, b"hello");
-
-        let _ = fs::remove_file(&db_path);
-        let _ = fs::remove_file(&sha_path);
-        std::env::remove_var("SPOTSPOOF_DB_SHA256_PATH");
+    fn batched_candidates_match_single_queries() {
+        let conn = test_db(&["abc.com", "abd.com", "axe.com", "abcd.com", "bcd.com"]);
+        let query = |domain, first_char, limit, min_affix| CandidateQuery {
+            domain,
+            first_char,
+            min_len: 6,
+            max_len: 8,
+            max_distance: 1,
+            limit,
+            strategies: CandidateStrategies::default(),
+            min_affix,
+        };
+        let queries = [
+            query("abx.com", 'a', 5, 0),
+            query("axc.com", 'a', 1, 0),
+            query("abe.com", 'a', 5, 2),
+            query("bcx.com", 'b', 5, 0),
+            CandidateQuery {
+                min_len: 8,
+                max_len: 9,
+                ..query("abce.com", 'a', 5, 0)
+            },
+            CandidateQuery {
+                strategies: CandidateStrategies::from_iter([CandidateStrategy::LastChar]),
+                ..query("xbc.com", 'x', 5, 0)
+            },
+        ];
+        let single: Vec<Vec<String>> = queries
+            .iter()
+            .map(|query| conn.candidates(query).unwrap())
+            .collect();
+        assert_eq!(conn.candidates_batch(&queries).unwrap(), single);
+        assert_eq!(single[0], ["abc.com", "abd.com"]);
+        assert_eq!(single[4], ["abcd.com"]);
     }
 
     #[test]
-    #[ignore = "slow: downloads the release DB and requires network access"]
-    fn release_db_download_matches_sha256() {
-        let _guard = ENV_LOCK.lock().unwrap();
-        std::env::remove_var("SPOTSPOOF_DB_SHA256_PATH");
-
-        let mut response = reqwest::blocking::get(DEFAULT_DB_URL).expect("download release db");
-        if !response.status().is_success() {
-            panic!("Download failed: HTTP {}", response.status());
+    fn candidate_strategies_reach_past_the_first_character() {
+        use CandidateStrategy::*;
+        let conn = test_db(&["google.com", "microsoft.com", "example.com"]);
+        let index = MemoryIndex::load(&conn).unwrap();
+
+        let cases: [(&str, &[CandidateStrategy], &[&str]); 5] = [
+            ("qoogle.com", &[FirstChar], &[]),
+            ("qoogle.com", &[FirstChar, FirstOrSecond], &["google.com"]),
+            ("foogle.com", &[LastChar], &["google.com"]),
+            ("rnicrosoft.com", &[Skeleton], &["microsoft.com"]),
+            ("qoogie.com", &[FirstChar, LengthOnly], &["google.com"]),
+        ];
+        for (domain, strategies, expected) in cases {
+            let query = CandidateQuery {
+                domain,
+                first_char: domain.chars().next().unwrap(),
+                min_len: domain.len().saturating_sub(2),
+                max_len: domain.len() + 2,
+                max_distance: 2,
+                limit: 10,
+                strategies: strategies.iter().copied().collect(),
+                min_affix: 0,
+            };
+            assert_eq!(conn.candidates(&query).unwrap(), expected, "{domain}");
+            assert_eq!(index.candidates(&query).unwrap(), expected, "{domain}");
         }
+    }
 
-        let mut hasher = Sha256::new();
-        let mut buf = [0u8; 64 * 1024];
-        let mut total = 0usize;
-        loop {
-            let read = response.read(&mut buf).expect("read response");
-            if read == 0 {
-                break;
-            }
-            total += read;
-            hasher.update(&buf[..read]);
+    #[test]
+    fn affix_prefilter_applies_to_band_scans() {
+        let conn = test_db(&["abcdefghij.com"]);
+        let index = MemoryIndex::load(&conn).unwrap();
+        for strategies in [
+            &[CandidateStrategy::FirstChar],
+            &[CandidateStrategy::LengthOnly],
+        ] {
+            let query = |min_affix| CandidateQuery {
+                domain: "axcdefghiy.com",
+                first_char: 'a',
+                min_len: 12,
+                max_len: 16,
+                max_distance: 2,
+                limit: 10,
+                strategies: strategies.iter().copied().collect(),
+                min_affix,
+            };
+            assert_eq!(conn.candidates(&query(0)).unwrap(), ["abcdefghij.com"]);
+            assert_eq!(index.candidates(&query(0)).unwrap(), ["abcdefghij.com"]);
+            assert!(conn.candidates(&query(3)).unwrap().is_empty());
+            assert!(index.candidates(&query(3)).unwrap().is_empty());
         }
+    }
 
-        let expected = read_expected_db_sha256().expect("read expected sha");
-        let actual = format!("{:x}", hasher.finalize());
-        assert!(total > 0, "expected response body, downloaded 0 bytes");
-        assert_eq!(actual, expected, "release DB checksum mismatch");
+    #[test]
+    fn editdist_filters_inside_sqlite() {
+        let conn = test_db(&[]);
+        let distance = |a: &str, b: &str, max: i64| -> Option<i64> {
+            conn.query_row("SELECT editdist(?1, ?2, ?3)", (a, b, max), |row| row.get(0))
+                .unwrap()
+        };
+        assert_eq!(distance("google.com", "google.com", 2), Some(0));
+        assert_eq!(distance("googel.com", "google.com", 2), Some(1));
+        assert_eq!(distance("example.com", "google.com", 5), Some(5));
+        assert_eq!(distance("example.com", "google.com", 4), None);
+        assert!(conn
+            .query_row("SELECT editdist(1, 'a', 2)", [], |row| row.get::<_, i64>(0))
+            .is_err());
+    }
+
+    #[test]
+    fn read_only_uri_escapes_path() {
+        assert_eq!(
+            read_only_uri("/srv/a?b%c#d.sqlite", true),
+            "file:/srv/a%3fb%25c%23d.sqlite?immutable=1"
+        );
+        assert_eq!(read_only_uri("db.sqlite", false), "file:db.sqlite?mode=ro");
     }
 }
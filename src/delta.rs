@@ -0,0 +1,482 @@
+/*
+Delta DB updates. Each release can publish zstd-compressed delta files listing
+the domains added (`+domain`) and removed (`-domain`) since an earlier dataset
+version, indexed by a `spotspoof.deltas.json` manifest next to the DB artifact.
+`spotspoof update --delta` chains the deltas from the local DB's version to the
+latest one and applies them to a copy of the DB, falling back to a full
+download whenever the chain cannot be built or applied.
+*/
+use anyhow::Result;
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::Path;
+use tracing::{info, warn};
+
+use crate::{cipher, db, download, index, net, pack};
+
+pub const MANIFEST_FILE: &str = "spotspoof.deltas.json";
+const HEADER: &str = "spotspoof-delta";
+/// Longer chains cost more than downloading the full DB again.
+const MAX_CHAIN: usize = 64;
+
+/// The published list of deltas and the version they lead up to.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Manifest {
+    pub latest: String,
+    #[serde(default)]
+    pub deltas: Vec<DeltaEntry>,
+}
+
+/// One published delta file. `url` may be relative to the manifest.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeltaEntry {
+    pub from: String,
+    pub to: String,
+    pub url: String,
+    pub sha256: String,
+}
+
+/// A parsed delta file.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Delta {
+    pub from: String,
+    pub to: String,
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+}
+
+/// What `update --delta` did.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UpdateOutcome {
+    /// The local DB already has the latest version.
+    UpToDate(String),
+    /// Deltas were applied in place of a download.
+    Applied {
+        from: String,
+        to: String,
+        added: usize,
+        removed: usize,
+    },
+    /// The deltas could not be used, so the full DB was downloaded.
+    Downloaded(download::DownloadOutcome),
+}
+
+/// The manifest URL for a DB artifact URL: same directory, `MANIFEST_FILE`.
+pub fn manifest_url(db_url: &str) -> String {
+    resolve_url(db_url, MANIFEST_FILE)
+}
+
+fn resolve_url(base: &str, url: &str) -> String {
+    if url.contains("://") {
+        return url.to_string();
+    }
+    match base.rfind('/') {
+        Some(idx) => format!("{}/{url}", &base[..idx]),
+        None => url.to_string(),
+    }
+}
+
+/// Brings `db_path` up to date through deltas, else with a full download.
+pub fn update(db_url: &str, manifest_url: &str, db_path: &str) -> Result<UpdateOutcome> {
+    net::ensure_online("Updating the DB")?;
+    match try_deltas(manifest_url, db_path) {
+        Ok(outcome) => Ok(outcome),
+        Err(err) => {
+            warn!(kind = "db_delta", "falling back to a full download: {err}");
+            Ok(UpdateOutcome::Downloaded(download::download_db(
+                db_url, db_path,
+            )?))
+        }
+    }
+}
+
+fn try_deltas(manifest_url: &str, db_path: &str) -> Result<UpdateOutcome> {
+    if !Path::new(db_path).is_file() {
+        return Err(anyhow::anyhow!("no local DB at {db_path}"));
+    }
+    let current = db::dataset_version(&db::open(db_path, false)?)?
+        .ok_or_else(|| anyhow::anyhow!("the local DB records no dataset version"))?;
+    let client = net::client_builder().build()?;
+    let manifest: Manifest = {
+        let response = net::send_with_retry("db_delta", || client.get(manifest_url))?;
+        if !response.status().is_success() {
+            return Err(anyhow::anyhow!(
+                "delta manifest {manifest_url}: HTTP {}",
+                response.status()
+            ));
+        }
+        response.json()?
+    };
+    let chain = plan(&manifest, &current)
+        .ok_or_else(|| anyhow::anyhow!("no delta chain from {current} to {}", manifest.latest))?;
+    if chain.is_empty() {
+        return Ok(UpdateOutcome::UpToDate(current));
+    }
+
+    let mut deltas = Vec::with_capacity(chain.len());
+    for entry in chain {
+        let url = resolve_url(manifest_url, &entry.url);
+        info!(kind = "db_delta", from = %entry.from, to = %entry.to, url = %url, "fetching delta");
+        let response = net::send_with_retry("db_delta", || client.get(&url))?;
+        if !response.status().is_success() {
+            return Err(anyhow::anyhow!("delta {url}: HTTP {}", response.status()));
+        }
+        let bytes = response.bytes()?;
+        let actual = format!("{:x}", Sha256::digest(&bytes));
+        if actual != entry.sha256.trim().to_lowercase() {
+            return Err(anyhow::anyhow!(
+                "delta {url} checksum mismatch: expected {}, got {actual}",
+                entry.sha256
+            ));
+        }
+        let delta = read_delta(zstd::stream::read::Decoder::new(&bytes[..])?)?;
+        if delta.from != entry.from || delta.to != entry.to {
+            return Err(anyhow::anyhow!(
+                "delta {url} is {} -> {}, the manifest says {} -> {}",
+                delta.from,
+                delta.to,
+                entry.from,
+                entry.to
+            ));
+        }
+        deltas.push(delta);
+    }
+    let (added, removed) = apply(db_path, &deltas)?;
+    Ok(UpdateOutcome::Applied {
+        from: current,
+        to: manifest.latest,
+        added,
+        removed,
+    })
+}
+
+/// The deltas leading from `current` to the manifest's latest version, in
+/// order; `None` when they do not chain.
+pub fn plan<'a>(manifest: &'a Manifest, current: &str) -> Option<Vec<&'a DeltaEntry>> {
+    let mut chain = Vec::new();
+    let mut version = current;
+    while version != manifest.latest {
+        if chain.len() >= MAX_CHAIN {
+            return None;
+        }
+        // Prefer the delta that skips furthest ahead: the one published last.
+        let next = manifest.deltas.iter().rev().find(|d| d.from == version)?;
+        chain.push(next);
+        version = &next.to;
+    }
+    Some(chain)
+}
+
+/// Applies `deltas` to a copy of the DB and renames it into place, rebuilding
+/// any candidate indexes. Returns the domains added and removed.
+pub fn apply(db_path: &str, deltas: &[Delta]) -> Result<(usize, usize)> {
+    let tmp_path = format!("{db_path}.tmp");
+    let _ = fs::remove_file(&tmp_path);
+    cipher::open(db_path)?.execute("VACUUM INTO ?1", [&tmp_path])?;
+
+    let (mut added, mut removed) = (0, 0);
+    let result = (|| -> Result<()> {
+        let mut conn = cipher::open(&tmp_path)?;
+        db::verify_schema(&conn)?;
+        let tx = conn.transaction()?;
+        for delta in deltas {
+            let version = db::dataset_version(&tx)?;
+            if version.as_deref() != Some(delta.from.as_str()) {
+                return Err(anyhow::anyhow!(
+                    "delta {} -> {} does not apply to version {}",
+                    delta.from,
+                    delta.to,
+                    version.as_deref().unwrap_or("(none)")
+                ));
+            }
+            // Stage both lists so each statement is one pass over the table,
+            // whether or not the published DB indexes `domain`.
+            tx.execute_batch(
+                "CREATE TEMP TABLE IF NOT EXISTS delta_domains (domain TEXT PRIMARY KEY, added INTEGER NOT NULL);
+				 DELETE FROM temp.delta_domains;",
+            )?;
+            {
+                let mut stage = tx.prepare(
+                    "INSERT OR REPLACE INTO temp.delta_domains (domain, added) VALUES (?1, ?2)",
+                )?;
+                for domain in &delta.removed {
+                    stage.execute((domain, false))?;
+                }
+                for domain in &delta.added {
+                    stage.execute((domain, true))?;
+                }
+            }
+            removed += tx.execute(
+                "DELETE FROM legit_domains
+				 WHERE domain IN (SELECT domain FROM temp.delta_domains WHERE NOT added)",
+                [],
+            )?;
+            added += tx.execute(
+                "INSERT INTO legit_domains (domain, first_char, length)
+				 SELECT domain, substr(domain, 1, 1), length(domain) FROM temp.delta_domains
+				 WHERE added AND domain NOT IN (SELECT domain FROM legit_domains)",
+                [],
+            )?;
+            db::set_dataset_version(&tx, &delta.to)?;
+        }
+        if db::meta(&tx, pack::DOMAINS_KEY)?.is_some() {
+            let domains: u64 =
+                tx.query_row("SELECT COUNT(*) FROM legit_domains", [], |row| row.get(0))?;
+            db::set_meta(&tx, pack::DOMAINS_KEY, &domains.to_string())?;
+        }
+        tx.commit()?;
+        // Index tables reference rowids, so rebuild whichever ones the DB had.
+        for kind in index::SqlIndex::detect_all(&conn)? {
+            match kind {
+                index::SqlIndex::SymSpell => index::build_symspell(&tmp_path)?,
+                index::SqlIndex::Trigram => index::build_trigrams(&tmp_path)?,
+            };
+        }
+        conn.pragma_update_and_check(None, "journal_mode", "WAL", |row| row.get::<_, String>(0))?;
+        Ok(())
+    })();
+    if let Err(err) = result {
+        let _ = fs::remove_file(&tmp_path);
+        return Err(err);
+    }
+    fs::rename(&tmp_path, db_path)?;
+    if Path::new(&index::fst_path(db_path)).is_file() {
+        index::build_fst(db_path)?;
+    }
+    // The DB no longer matches the artifact those validators describe.
+    download::discard_validators(db_path)?;
+    Ok((added, removed))
+}
+
+/// Parses a decompressed delta file.
+pub fn read_delta(reader: impl Read) -> Result<Delta> {
+    let mut lines = BufReader::new(reader).lines();
+    let header = lines
+        .next()
+        .transpose()?
+        .ok_or_else(|| anyhow::anyhow!("empty delta file"))?;
+    let mut fields = header.split_whitespace();
+    let (Some(HEADER), Some(from), Some(to), None) =
+        (fields.next(), fields.next(), fields.next(), fields.next())
+    else {
+        return Err(anyhow::anyhow!("not a delta file (header: {header})"));
+    };
+    let mut delta = Delta {
+        from: from.to_string(),
+        to: to.to_string(),
+        ..Delta::default()
+    };
+    for line in lines {
+        let line = line?;
+        match line.split_at_checked(1) {
+            Some(("+", domain)) => delta.added.push(domain.to_string()),
+            Some(("-", domain)) => delta.removed.push(domain.to_string()),
+            _ if line.trim().is_empty() => {}
+            _ => return Err(anyhow::anyhow!("malformed delta line: {line}")),
+        }
+    }
+    Ok(delta)
+}
+
+fn write_delta(out: impl Write, delta: &Delta) -> Result<()> {
+    let mut out = std::io::BufWriter::new(out);
+    writeln!(out, "{HEADER} {} {}", delta.from, delta.to)?;
+    for domain in &delta.removed {
+        writeln!(out, "-{domain}")?;
+    }
+    for domain in &delta.added {
+        writeln!(out, "+{domain}")?;
+    }
+    out.flush()?;
+    Ok(())
+}
+
+/// Compares two DBs and returns the delta leading from `old` to `new`; each
+/// version comes from the override, else the DB's `meta` table.
+pub fn diff(
+    old_path: &str,
+    new_path: &str,
+    from: Option<String>,
+    to: Option<String>,
+) -> Result<Delta> {
+    let version = |conn: &Connection, version: Option<String>, path: &str| -> Result<String> {
+        match version {
+            Some(version) => Ok(version),
+            None => db::dataset_version(conn)?
+                .ok_or_else(|| anyhow::anyhow!("{path} records no dataset version")),
+        }
+    };
+    let old = db::open(old_path, false)?;
+    let new = db::open(new_path, false)?;
+    let mut delta = Delta {
+        from: version(&old, from, old_path)?,
+        to: version(&new, to, new_path)?,
+        ..Delta::default()
+    };
+    let mut old_stmt = old.prepare("SELECT DISTINCT domain FROM legit_domains ORDER BY domain")?;
+    let mut new_stmt = new.prepare("SELECT DISTINCT domain FROM legit_domains ORDER BY domain")?;
+    let mut old_rows = old_stmt.query_map([], |row| row.get::<_, String>(0))?;
+    let mut new_rows = new_stmt.query_map([], |row| row.get::<_, String>(0))?;
+    // Both sides are sorted, so one merge pass finds the differences.
+    let (mut a, mut b) = (old_rows.next().transpose()?, new_rows.next().transpose()?);
+    loop {
+        match (a.take(), b.take()) {
+            (None, None) => break,
+            (Some(x), None) => {
+                delta.removed.push(x);
+                a = old_rows.next().transpose()?;
+            }
+            (None, Some(y)) => {
+                delta.added.push(y);
+                b = new_rows.next().transpose()?;
+            }
+            (Some(x), Some(y)) => match x.cmp(&y) {
+                std::cmp::Ordering::Less => {
+                    delta.removed.push(x);
+                    a = old_rows.next().transpose()?;
+                    b = Some(y);
+                }
+                std::cmp::Ordering::Greater => {
+                    delta.added.push(y);
+                    a = Some(x);
+                    b = new_rows.next().transpose()?;
+                }
+                std::cmp::Ordering::Equal => {
+                    a = old_rows.next().transpose()?;
+                    b = new_rows.next().transpose()?;
+                }
+            },
+        }
+    }
+    Ok(delta)
+}
+
+/// Writes `delta` zstd-compressed to `out_path` and returns its SHA-256. With
+/// `manifest_path`, also records it there and makes its target the latest.
+pub fn publish(delta: &Delta, out_path: &str, manifest_path: Option<&str>) -> Result<String> {
+    let mut encoder = zstd::stream::write::Encoder::new(Vec::new(), 19)?;
+    write_delta(&mut encoder, delta)?;
+    let bytes = encoder.finish()?;
+    fs::write(out_path, &bytes)?;
+    let sha256 = format!("{:x}", Sha256::digest(&bytes));
+
+    if let Some(manifest_path) = manifest_path {
+        let mut manifest: Manifest = match fs::read_to_string(manifest_path) {
+            Ok(text) => serde_json::from_str(&text)?,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Manifest::default(),
+            Err(err) => return Err(err.into()),
+        };
+        let file_name = Path::new(out_path)
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_else(|| out_path.to_string());
+        manifest
+            .deltas
+            .retain(|entry| !(entry.from == delta.from && entry.to == delta.to));
+        manifest.deltas.push(DeltaEntry {
+            from: delta.from.clone(),
+            to: delta.to.clone(),
+            url: file_name,
+            sha256: sha256.clone(),
+        });
+        manifest.latest = delta.to.clone();
+        fs::write(manifest_path, serde_json::to_string_pretty(&manifest)?)?;
+    }
+    Ok(sha256)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(from: &str, to: &str) -> DeltaEntry {
+        DeltaEntry {
+            from: from.into(),
+            to: to.into(),
+            url: format!("{from}-{to}.delta.zst"),
+            sha256: String::new(),
+        }
+    }
+
+    fn tmp_db(name: &str, version: &str, domains: &[&str]) -> String {
+        let path = std::env::temp_dir()
+            .join(format!(
+                "spotspoof-delta-{name}-{}.sqlite",
+                std::process::id()
+            ))
+            .to_string_lossy()
+            .to_string();
+        let _ = fs::remove_file(&path);
+        let conn = Connection::open(&path).unwrap();
+        conn.execute_batch(
+            "CREATE TABLE legit_domains (domain TEXT, first_char TEXT, length INTEGER);",
+        )
+        .unwrap();
+        for domain in domains {
+            conn.execute(
+                "INSERT INTO legit_domains VALUES (?1, substr(?1, 1, 1), length(?1))",
+                [domain],
+            )
+            .unwrap();
+        }
+        db::set_dataset_version(&conn, version).unwrap();
+        path
+    }
+
+    #[test]
+    fn plan_chains_deltas_or_gives_up() {
+        let manifest = Manifest {
+            latest: "3".into(),
+            deltas: vec![entry("1", "2"), entry("2", "3"), entry("0", "1")],
+        };
+        let versions =
+            |chain: Vec<&DeltaEntry>| chain.iter().map(|d| d.to.clone()).collect::<Vec<_>>();
+        assert_eq!(versions(plan(&manifest, "0").unwrap()), ["1", "2", "3"]);
+        assert!(plan(&manifest, "3").unwrap().is_empty());
+        assert!(plan(&manifest, "9").is_none());
+
+        let gap = Manifest {
+            latest: "3".into(),
+            deltas: vec![entry("1", "2")],
+        };
+        assert!(plan(&gap, "1").is_none());
+    }
+
+    #[test]
+    fn diffed_deltas_apply_to_the_old_db() {
+        let old = tmp_db("old", "1", &["a.com", "b.com", "c.com"]);
+        let new = tmp_db("new", "2", &["b.com", "c.com", "d.com", "e.com"]);
+        let delta = diff(&old, &new, None, None).unwrap();
+        assert_eq!(delta.removed, ["a.com"]);
+        assert_eq!(delta.added, ["d.com", "e.com"]);
+
+        let mut encoded = Vec::new();
+        write_delta(&mut encoded, &delta).unwrap();
+        assert_eq!(read_delta(&encoded[..]).unwrap(), delta);
+
+        assert_eq!(apply(&old, std::slice::from_ref(&delta)).unwrap(), (2, 1));
+        assert!(diff(&old, &new, None, None).unwrap().added.is_empty());
+        let conn = db::open(&old, false).unwrap();
+        assert_eq!(db::dataset_version(&conn).unwrap().as_deref(), Some("2"));
+        drop(conn);
+
+        // The DB is at version 2 now, so the 1 -> 2 delta no longer applies.
+        assert!(apply(&old, &[delta]).is_err());
+        for path in [&old, &new] {
+            let _ = fs::remove_file(path);
+        }
+    }
+
+    #[test]
+    fn malformed_delta_files_are_rejected() {
+        assert!(read_delta(&b"garbage 1 2\n"[..]).is_err());
+        assert!(read_delta(&b"spotspoof-delta 1 2\n*a.com\n"[..]).is_err());
+        assert_eq!(
+            manifest_url("https://example.com/releases/latest/download/spotspoof.sqlite.zst"),
+            "https://example.com/releases/latest/download/spotspoof.deltas.json"
+        );
+    }
+}
@@ -0,0 +1,431 @@
+/*
+Optional detections store. With `--detections`, every lookalike that the scan
+commands, `tail`, or the server find is upserted into a small SQLite file next
+to the default DB, keyed by domain. Each detection keeps its highest risk, a
+severity derived from it, and a triage state (`new`, `acknowledged`,
+`false-positive`, `escalated`) that `spotspoof detections` and
+`POST /detections/{id}` change. Scans and `tail` stop reporting domains that
+have been triaged, so repeated runs only alert on what nobody has looked at.
+Like the history, recording never fails a lookup; errors are only logged.
+*/
+use anyhow::Result;
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+use std::sync::{Mutex, OnceLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::warn;
+use utoipa::ToSchema;
+
+use crate::db;
+use crate::scan::{self, ScanReport};
+use crate::types::LookupResponse;
+
+pub const DEFAULT_DETECTIONS_FILE: &str = "spotspoof-detections.sqlite";
+const SELECT: &str = "SELECT id, domain, target, risk, state,
+	strftime('%Y-%m-%dT%H:%M:%SZ', first_seen, 'unixepoch'),
+	strftime('%Y-%m-%dT%H:%M:%SZ', last_seen, 'unixepoch'), sightings, source, note
+	FROM detections";
+
+static INSTALLED: OnceLock<DetectionStore> = OnceLock::new();
+
+/// What found a detection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Source {
+    Scan,
+    Tail,
+    Http,
+}
+
+impl Source {
+    fn as_str(self) -> &'static str {
+        match self {
+            Source::Scan => "scan",
+            Source::Tail => "tail",
+            Source::Http => "http",
+        }
+    }
+}
+
+/// How urgent a detection is, from its risk.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize, ToSchema,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Low,
+    Medium,
+    High,
+    Critical,
+}
+
+impl Severity {
+    /// `critical` from risk 95, `high` from 85, `medium` from 70.
+    pub fn from_risk(risk: u8) -> Self {
+        match risk {
+            95.. => Severity::Critical,
+            85..=94 => Severity::High,
+            70..=84 => Severity::Medium,
+            _ => Severity::Low,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Severity::Low => "low",
+            Severity::Medium => "medium",
+            Severity::High => "high",
+            Severity::Critical => "critical",
+        }
+    }
+}
+
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, ToSchema, clap::ValueEnum,
+)]
+#[serde(rename_all = "kebab-case")]
+pub enum TriageState {
+    /// Not looked at yet; still alerted on
+    New,
+    /// Seen and being handled
+    Acknowledged,
+    /// Not a threat
+    FalsePositive,
+    /// Handed on (takedown, incident response)
+    Escalated,
+}
+
+impl TriageState {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            TriageState::New => "new",
+            TriageState::Acknowledged => "acknowledged",
+            TriageState::FalsePositive => "false-positive",
+            TriageState::Escalated => "escalated",
+        }
+    }
+
+    fn parse(value: &str) -> Self {
+        match value {
+            "acknowledged" => TriageState::Acknowledged,
+            "false-positive" => TriageState::FalsePositive,
+            "escalated" => TriageState::Escalated,
+            _ => TriageState::New,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema, PartialEq, Eq)]
+pub struct Detection {
+    pub id: i64,
+    pub domain: String,
+    /// The best match: the domain being impersonated.
+    pub target: Option<String>,
+    /// The highest risk seen.
+    pub risk: u8,
+    pub severity: Severity,
+    pub state: TriageState,
+    /// RFC 3339, UTC.
+    pub first_seen: String,
+    /// RFC 3339, UTC.
+    pub last_seen: String,
+    /// How many times it was found.
+    pub sightings: u64,
+    /// What found it first: `scan`, `tail`, or `http`.
+    pub source: String,
+    pub note: Option<String>,
+}
+
+pub struct DetectionStore {
+    conn: Mutex<Connection>,
+}
+
+impl DetectionStore {
+    /// Opens (creating if needed) the store at `path`.
+    pub fn open(path: &str) -> Result<Self> {
+        Self::init(db::open_shared_store(path)?)
+    }
+
+    #[cfg(test)]
+    pub fn open_in_memory() -> Result<Self> {
+        Self::init(Connection::open_in_memory()?)
+    }
+
+    fn init(conn: Connection) -> Result<Self> {
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS detections (
+				id INTEGER PRIMARY KEY,
+				domain TEXT NOT NULL UNIQUE,
+				target TEXT,
+				risk INTEGER NOT NULL,
+				state TEXT NOT NULL DEFAULT 'new',
+				first_seen INTEGER NOT NULL,
+				last_seen INTEGER NOT NULL,
+				sightings INTEGER NOT NULL DEFAULT 1,
+				source TEXT NOT NULL,
+				note TEXT
+			);
+			CREATE INDEX IF NOT EXISTS detections_state ON detections (state, last_seen);",
+        )?;
+        Ok(Self {
+            conn: Mutex::new(conn),
+        })
+    }
+
+    /// Records a sighting of `domain`, keeping its triage state, and returns
+    /// the updated detection.
+    pub fn record(
+        &self,
+        source: Source,
+        domain: &str,
+        lookup: &LookupResponse,
+    ) -> Result<Detection> {
+        let risk = scan::risk(domain, lookup);
+        let now = now();
+        {
+            let conn = self.conn.lock().unwrap_or_else(|p| p.into_inner());
+            conn.prepare_cached(
+                "INSERT INTO detections (domain, target, risk, first_seen, last_seen, source)
+				VALUES (?1, ?2, ?3, ?4, ?4, ?5)
+				ON CONFLICT (domain) DO UPDATE SET target = coalesce(excluded.target, target),
+					risk = max(risk, excluded.risk), last_seen = excluded.last_seen,
+					sightings = sightings + 1",
+            )?
+            .execute((domain, scan::top_match(lookup), risk, now, source.as_str()))?;
+        }
+        self.find(domain)?
+            .ok_or_else(|| anyhow::anyhow!("detection of {domain} vanished"))
+    }
+
+    /// The newest `limit` detections (in `state`, if given), most recently
+    /// seen first.
+    pub fn list(&self, state: Option<TriageState>, limit: usize) -> Result<Vec<Detection>> {
+        self.query(
+            &format!(
+                "{SELECT} WHERE ?1 IS NULL OR state = ?1 ORDER BY last_seen DESC, id DESC LIMIT ?2"
+            ),
+            (state.map(TriageState::as_str), limit as i64),
+        )
+    }
+
+    pub fn get(&self, id: i64) -> Result<Option<Detection>> {
+        Ok(self
+            .query(&format!("{SELECT} WHERE id = ?1"), (id,))?
+            .into_iter()
+            .next())
+    }
+
+    fn find(&self, domain: &str) -> Result<Option<Detection>> {
+        Ok(self
+            .query(&format!("{SELECT} WHERE domain = ?1"), (domain,))?
+            .into_iter()
+            .next())
+    }
+
+    /// Moves detection `id` to `state`, replacing its note when one is given.
+    /// `None` when there is no such detection.
+    pub fn triage(
+        &self,
+        id: i64,
+        state: TriageState,
+        note: Option<&str>,
+    ) -> Result<Option<Detection>> {
+        let updated = {
+            let conn = self.conn.lock().unwrap_or_else(|p| p.into_inner());
+            conn.execute(
+                "UPDATE detections SET state = ?2, note = coalesce(?3, note) WHERE id = ?1",
+                (id, state.as_str(), note),
+            )?
+        };
+        if updated == 0 {
+            return Ok(None);
+        }
+        self.get(id)
+    }
+
+    fn query(&self, sql: &str, params: impl rusqlite::Params) -> Result<Vec<Detection>> {
+        let conn = self.conn.lock().unwrap_or_else(|p| p.into_inner());
+        let mut stmt = conn.prepare(sql)?;
+        let rows = stmt.query_map(params, |row| {
+            let risk: u8 = row.get(3)?;
+            Ok(Detection {
+                id: row.get(0)?,
+                domain: row.get(1)?,
+                target: row.get(2)?,
+                risk,
+                severity: Severity::from_risk(risk),
+                state: TriageState::parse(&row.get::<_, String>(4)?),
+                first_seen: row.get(5)?,
+                last_seen: row.get(6)?,
+                sightings: row.get(7)?,
+                source: row.get(8)?,
+                note: row.get(9)?,
+            })
+        })?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+}
+
+/// Makes `store` the process-wide detections store. Only the first call takes
+/// effect.
+pub fn install(store: DetectionStore) {
+    let _ = INSTALLED.set(store);
+}
+
+/// Whether findings are being recorded.
+pub fn enabled() -> bool {
+    INSTALLED.get().is_some()
+}
+
+pub fn installed() -> Option<&'static DetectionStore> {
+    INSTALLED.get()
+}
+
+/// Records a sighting if the store is enabled, and says whether it should
+/// still be alerted on: true unless it has been triaged. A failed write is
+/// logged and alerts, so nothing is lost to a store error.
+pub fn record(source: Source, domain: &str, lookup: &LookupResponse) -> bool {
+    sighting(source, domain, lookup).is_none_or(|detection| detection.state == TriageState::New)
+}
+
+/// Records a sighting if the store is enabled, returning the updated
+/// detection; `None` when disabled or the write failed (logged).
+fn sighting(source: Source, domain: &str, lookup: &LookupResponse) -> Option<Detection> {
+    let store = INSTALLED.get()?;
+    store
+        .record(source, domain, lookup)
+        .inspect_err(
+            |err| warn!(kind = "detections", domain = %domain, "could not record detection: {err}"),
+        )
+        .ok()
+}
+
+/// Records the findings of a scan and drops the triaged ones, counting them
+/// in `suppressed`. The rest get their first sighting.
+pub fn suppress_triaged(report: &mut ScanReport) {
+    let before = report.findings.len();
+    report.findings.retain_mut(|finding| {
+        let Some(detection) = sighting(Source::Scan, &finding.domain, &finding.lookup) else {
+            return true;
+        };
+        finding.first_seen = Some(detection.first_seen);
+        detection.state == TriageState::New
+    });
+    report.suppressed += before - report.findings.len();
+}
+
+/// `detections` grouped by their target, for `detections list --group-by
+/// brand`. Detections without a target are left out.
+pub fn group_by_brand(detections: &[Detection]) -> Vec<scan::BrandGroup> {
+    let mut groups = scan::BrandGroups::default();
+    for detection in detections {
+        if let Some(target) = &detection.target {
+            groups.add(
+                target,
+                &detection.domain,
+                detection.risk,
+                detection.sightings,
+                Some(&detection.first_seen),
+            );
+        }
+    }
+    groups.into_vec()
+}
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs() as i64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::AsciiResult;
+
+    fn lookup(q: &str, target: &str, similarity: u8) -> LookupResponse {
+        let mut response = crate::types::empty_ascii_response(q);
+        response.results.push(AsciiResult {
+            domain: target.to_string(),
+            similarity,
+            breakdown: None,
+        });
+        LookupResponse::Ascii(response)
+    }
+
+    #[test]
+    fn sightings_accumulate_and_keep_their_triage_state() {
+        let store = DetectionStore::open_in_memory().unwrap();
+        let first = store
+            .record(
+                Source::Scan,
+                "paypa1.com",
+                &lookup("paypa1.com", "paypal.com", 90),
+            )
+            .unwrap();
+        assert_eq!(first.state, TriageState::New);
+        assert_eq!(first.severity, Severity::High);
+        assert_eq!(first.target.as_deref(), Some("paypal.com"));
+        assert_eq!(first.sightings, 1);
+
+        let acked = store
+            .triage(first.id, TriageState::Acknowledged, Some("ticket 42"))
+            .unwrap()
+            .unwrap();
+        assert_eq!(acked.state, TriageState::Acknowledged);
+        assert!(store
+            .triage(999, TriageState::Escalated, None)
+            .unwrap()
+            .is_none());
+
+        let again = store
+            .record(
+                Source::Tail,
+                "paypa1.com",
+                &lookup("paypa1.com", "paypal.com", 96),
+            )
+            .unwrap();
+        assert_eq!(again.id, first.id);
+        assert_eq!(again.state, TriageState::Acknowledged);
+        assert_eq!(again.note.as_deref(), Some("ticket 42"));
+        assert_eq!((again.risk, again.severity), (96, Severity::Critical));
+        assert_eq!((again.sightings, again.source.as_str()), (2, "scan"));
+
+        store
+            .record(
+                Source::Http,
+                "gooogle.com",
+                &lookup("gooogle.com", "google.com", 75),
+            )
+            .unwrap();
+        let new = store.list(Some(TriageState::New), 10).unwrap();
+        assert_eq!(new.len(), 1);
+        assert_eq!(new[0].domain, "gooogle.com");
+        assert_eq!(new[0].severity, Severity::Medium);
+        assert_eq!(store.list(None, 10).unwrap().len(), 2);
+        assert_eq!(Severity::from_risk(40), Severity::Low);
+    }
+
+    #[test]
+    fn detections_group_by_their_target() {
+        let store = DetectionStore::open_in_memory().unwrap();
+        for (domain, target, similarity) in [
+            ("paypa1.com", "paypal.com", 90),
+            ("gooogle.com", "google.com", 75),
+            ("paypai.com", "paypal.com", 80),
+            ("paypa1.com", "paypal.com", 90),
+        ] {
+            store
+                .record(Source::Scan, domain, &lookup(domain, target, similarity))
+                .unwrap();
+        }
+        let groups = group_by_brand(&store.list(None, 10).unwrap());
+        let brands: Vec<(&str, usize, u64)> = groups
+            .iter()
+            .map(|group| (group.brand.as_str(), group.count, group.occurrences))
+            .collect();
+        assert_eq!(brands, [("paypal.com", 2, 3), ("google.com", 1, 1)]);
+        assert_eq!(groups[0].severity, Severity::High);
+        assert!(groups[0].first_seen.is_some());
+    }
+}
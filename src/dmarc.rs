@@ -0,0 +1,307 @@
+/*
+DMARC aggregate (rua) report analysis for `scan-dmarc`. Reads the XML reports
+receivers send to a domain's rua address, plain or as the `.gz`/`.zip` they are
+mailed as, and scores every domain each source used (header From, envelope
+From, DKIM and SPF identities) against the protected domains. A source that
+fails DMARC while using a confusable domain is flagged.
+*/
+use anyhow::Result;
+use quick_xml::events::Event;
+use quick_xml::Reader;
+use serde::Serialize;
+use std::collections::BTreeSet;
+use std::io::{Cursor, Read};
+
+use crate::scan::{self, Scanner};
+use crate::types::{self, LookupResponse};
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+const ZIP_MAGIC: [u8; 4] = *b"PK\x03\x04";
+
+/// One `<feedback>` document.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AggregateReport {
+    pub org_name: Option<String>,
+    pub report_id: Option<String>,
+    /// The domain whose policy the report covers.
+    pub domain: Option<String>,
+    pub records: Vec<Record>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Record {
+    pub source_ip: String,
+    pub count: u64,
+    pub header_from: String,
+    pub envelope_from: Option<String>,
+    pub dkim_domains: Vec<String>,
+    pub spf_domains: Vec<String>,
+    /// DMARC-aligned results from `policy_evaluated`.
+    pub dkim: Option<String>,
+    pub spf: Option<String>,
+}
+
+impl Record {
+    /// DMARC passes when either aligned mechanism passes.
+    pub fn passes(&self) -> bool {
+        self.dkim.as_deref() == Some("pass") || self.spf.as_deref() == Some("pass")
+    }
+
+    /// The registrable domains this source used, deduped.
+    fn domains(&self) -> Vec<String> {
+        let mut seen = BTreeSet::new();
+        std::iter::once(&self.header_from)
+            .chain(&self.envelope_from)
+            .chain(&self.dkim_domains)
+            .chain(&self.spf_domains)
+            .filter_map(|domain| types::parse_domain_input(domain).ok())
+            .filter(|domain| seen.insert(domain.clone()))
+            .collect()
+    }
+}
+
+/// Reads the reports in a file's bytes: XML, gzip, or a zip of XML files.
+pub fn read(bytes: &[u8]) -> Result<Vec<AggregateReport>> {
+    if bytes.starts_with(&GZIP_MAGIC) {
+        let mut xml = String::new();
+        flate2::read::MultiGzDecoder::new(bytes).read_to_string(&mut xml)?;
+        return Ok(vec![parse_xml(&xml)?]);
+    }
+    if bytes.starts_with(&ZIP_MAGIC) {
+        let mut archive = zip::ZipArchive::new(Cursor::new(bytes))?;
+        let mut reports = Vec::new();
+        for idx in 0..archive.len() {
+            let mut file = archive.by_index(idx)?;
+            if file.is_dir() {
+                continue;
+            }
+            let mut xml = String::new();
+            file.read_to_string(&mut xml)?;
+            reports.push(parse_xml(&xml)?);
+        }
+        return Ok(reports);
+    }
+    Ok(vec![parse_xml(&String::from_utf8_lossy(bytes))?])
+}
+
+fn parse_xml(xml: &str) -> Result<AggregateReport> {
+    let mut reader = Reader::from_str(xml);
+    reader.config_mut().trim_text(true);
+    let mut report = AggregateReport::default();
+    let mut path: Vec<String> = Vec::new();
+    let mut record = Record::default();
+    let mut feedback = false;
+    loop {
+        match reader.read_event()? {
+            Event::Start(element) => {
+                let name = String::from_utf8_lossy(element.local_name().as_ref()).into_owned();
+                feedback |= name == "feedback";
+                if name == "record" {
+                    record = Record::default();
+                }
+                path.push(name);
+            }
+            Event::End(_) => {
+                let closed = path.pop();
+                if closed.as_deref() == Some("record") {
+                    report.records.push(std::mem::take(&mut record));
+                }
+            }
+            Event::Text(text) => {
+                let text = text.unescape()?.into_owned();
+                let path: Vec<&str> = path.iter().skip(1).map(String::as_str).collect();
+                match path.as_slice() {
+                    ["report_metadata", "org_name"] => report.org_name = Some(text),
+                    ["report_metadata", "report_id"] => report.report_id = Some(text),
+                    ["policy_published", "domain"] => report.domain = Some(text),
+                    ["record", "row", "source_ip"] => record.source_ip = text,
+                    ["record", "row", "count"] => record.count = text.parse().unwrap_or(0),
+                    ["record", "row", "policy_evaluated", "dkim"] => record.dkim = Some(text),
+                    ["record", "row", "policy_evaluated", "spf"] => record.spf = Some(text),
+                    ["record", "identifiers", "header_from"] => record.header_from = text,
+                    ["record", "identifiers", "envelope_from"] => record.envelope_from = Some(text),
+                    ["record", "auth_results", "dkim", "domain"] => record.dkim_domains.push(text),
+                    ["record", "auth_results", "spf", "domain"] => record.spf_domains.push(text),
+                    _ => {}
+                }
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+    if !feedback {
+        return Err(anyhow::anyhow!("not a DMARC aggregate report"));
+    }
+    Ok(report)
+}
+
+/// The registrable domains the reports cover, deduped.
+pub fn protected_domains(reports: &[AggregateReport]) -> Vec<String> {
+    let domains: BTreeSet<String> = reports
+        .iter()
+        .filter_map(|report| report.domain.as_deref())
+        .filter_map(|domain| types::parse_domain_input(domain).ok())
+        .collect();
+    domains.into_iter().collect()
+}
+
+/// A sending source and what it looked like.
+#[derive(Debug, Clone, Serialize)]
+pub struct DmarcSource {
+    pub source_ip: String,
+    /// Messages from this source.
+    pub count: u64,
+    pub header_from: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub envelope_from: Option<String>,
+    /// `pass` or `fail`.
+    pub dmarc: &'static str,
+    /// Domains it used that resemble a protected one.
+    pub lookalikes: Vec<Lookalike>,
+    /// Failing DMARC and confusable with a protected domain.
+    pub flagged: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Lookalike {
+    pub domain: String,
+    pub lookup: LookupResponse,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DmarcReport {
+    pub reports: usize,
+    pub messages: u64,
+    pub protected: Vec<String>,
+    pub flagged: usize,
+    /// Flagged sources first, then those with lookalikes, by message count.
+    pub sources: Vec<DmarcSource>,
+}
+
+/// Scores every source in `reports`; `scanner` should watch the protected
+/// domains.
+pub fn analyze(
+    reports: Vec<AggregateReport>,
+    protected: Vec<String>,
+    scanner: &Scanner,
+) -> Result<DmarcReport> {
+    let report_count = reports.len();
+    let mut sources = Vec::new();
+    for record in reports.into_iter().flat_map(|report| report.records) {
+        let mut lookalikes = Vec::new();
+        for domain in record.domains() {
+            if let Some(lookup) = scanner.lookup(&domain)?.filter(scan::is_lookalike) {
+                lookalikes.push(Lookalike { domain, lookup });
+            }
+        }
+        let passes = record.passes();
+        sources.push(DmarcSource {
+            flagged: !passes && !lookalikes.is_empty(),
+            dmarc: if passes { "pass" } else { "fail" },
+            source_ip: record.source_ip,
+            count: record.count,
+            header_from: record.header_from,
+            envelope_from: record.envelope_from,
+            lookalikes,
+        });
+    }
+    sources.sort_by_key(|source| {
+        (
+            !source.flagged,
+            source.lookalikes.is_empty(),
+            std::cmp::Reverse(source.count),
+        )
+    });
+    Ok(DmarcReport {
+        reports: report_count,
+        messages: sources.iter().map(|source| source.count).sum(),
+        protected,
+        flagged: sources.iter().filter(|source| source.flagged).count(),
+        sources,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ascii_spoof::AsciiOptions;
+    use std::io::Write;
+
+    const REPORT: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<feedback>
+  <report_metadata>
+    <org_name>google.com</org_name>
+    <report_id>1234567890</report_id>
+  </report_metadata>
+  <policy_published><domain>paypal.com</domain><p>reject</p></policy_published>
+  <record>
+    <row>
+      <source_ip>198.51.100.7</source_ip>
+      <count>3</count>
+      <policy_evaluated><disposition>none</disposition><dkim>pass</dkim><spf>pass</spf></policy_evaluated>
+    </row>
+    <identifiers><header_from>paypal.com</header_from></identifiers>
+    <auth_results>
+      <dkim><domain>paypal.com</domain><result>pass</result></dkim>
+      <spf><domain>mail.paypal.com</domain><result>pass</result></spf>
+    </auth_results>
+  </record>
+  <record>
+    <row>
+      <source_ip>203.0.113.9</source_ip>
+      <count>12</count>
+      <policy_evaluated><disposition>reject</disposition><dkim>fail</dkim><spf>fail</spf></policy_evaluated>
+    </row>
+    <identifiers><header_from>paypal.com</header_from><envelope_from>bounce.paypa1.com</envelope_from></identifiers>
+    <auth_results>
+      <dkim><domain>paypa1.com</domain><result>pass</result></dkim>
+      <spf><domain>bounce.paypa1.com</domain><result>pass</result></spf>
+    </auth_results>
+  </record>
+</feedback>"#;
+
+    #[test]
+    fn reads_plain_gzip_and_zip_reports() {
+        let plain = read(REPORT.as_bytes()).unwrap();
+        assert_eq!(plain.len(), 1);
+        assert_eq!(plain[0].domain.as_deref(), Some("paypal.com"));
+        assert_eq!(plain[0].org_name.as_deref(), Some("google.com"));
+        assert_eq!(plain[0].records.len(), 2);
+        let record = &plain[0].records[1];
+        assert_eq!(record.count, 12);
+        assert!(!record.passes());
+        assert_eq!(record.domains(), ["paypal.com", "paypa1.com"]);
+
+        let mut gzip = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::fast());
+        gzip.write_all(REPORT.as_bytes()).unwrap();
+        assert_eq!(read(&gzip.finish().unwrap()).unwrap(), plain);
+
+        let mut zipped = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        zipped
+            .start_file("google.com!paypal.com!1!2.xml", Default::default())
+            .unwrap();
+        zipped.write_all(REPORT.as_bytes()).unwrap();
+        let zipped = zipped.finish().unwrap().into_inner();
+        assert_eq!(read(&zipped).unwrap(), plain);
+
+        assert!(read(b"<html><body>nope</body></html>").is_err());
+    }
+
+    #[test]
+    fn failing_confusable_sources_are_flagged_first() {
+        let reports = read(REPORT.as_bytes()).unwrap();
+        let protected = protected_domains(&reports);
+        let scanner =
+            Scanner::new(None, AsciiOptions::default(), None).with_targets(protected.clone());
+        let report = analyze(reports, protected, &scanner).unwrap();
+        assert_eq!(report.messages, 15);
+        assert_eq!(report.flagged, 1);
+        let flagged = &report.sources[0];
+        assert_eq!(flagged.source_ip, "203.0.113.9");
+        assert_eq!(flagged.dmarc, "fail");
+        assert_eq!(flagged.lookalikes.len(), 1);
+        assert_eq!(flagged.lookalikes[0].domain, "paypa1.com");
+        assert!(!report.sources[1].flagged);
+        assert!(report.sources[1].lookalikes.is_empty());
+    }
+}
@@ -0,0 +1,154 @@
+/*
+Persistent cache of DNS registration checks. Answers from the DoH resolver are
+kept in a small writable SQLite file (separate from the read-only domain DB)
+for a TTL, so repeated IDN lookups from the CLI and the server skip re-querying
+the same variants. `serve --redis-url` keeps them in Redis instead, shared by
+every instance.
+*/
+use anyhow::Result;
+use rusqlite::{Connection, OptionalExtension};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::db;
+use crate::redis_cache::RedisCache;
+
+pub const DEFAULT_CACHE_FILE: &str = "spotspoof-dns-cache.sqlite";
+
+static INSTALLED: OnceLock<RegistrationCache> = OnceLock::new();
+
+pub struct RegistrationCache {
+    backend: Backend,
+    ttl: Duration,
+}
+
+enum Backend {
+    Sqlite(Mutex<Connection>),
+    Redis(RedisCache),
+}
+
+impl RegistrationCache {
+    /// Opens (creating if needed) the cache at `path` and drops expired rows.
+    pub fn open(path: &str, ttl: Duration) -> Result<Self> {
+        Self::init(db::open_shared_store(path)?, ttl)
+    }
+
+    #[cfg(test)]
+    pub fn open_in_memory(ttl: Duration) -> Result<Self> {
+        Self::init(Connection::open_in_memory()?, ttl)
+    }
+
+    fn init(conn: Connection, ttl: Duration) -> Result<Self> {
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS registration_checks (
+				domain TEXT PRIMARY KEY,
+				registered INTEGER NOT NULL,
+				checked_at INTEGER NOT NULL,
+				ttl_secs INTEGER NOT NULL
+			) WITHOUT ROWID;",
+        )?;
+        conn.execute(
+            "DELETE FROM registration_checks WHERE checked_at + ttl_secs <= ?1",
+            [now()],
+        )?;
+        Ok(Self {
+            backend: Backend::Sqlite(Mutex::new(conn)),
+            ttl,
+        })
+    }
+
+    /// Keeps the answers in `redis`, which expires them itself.
+    pub fn redis(redis: RedisCache, ttl: Duration) -> Self {
+        Self {
+            backend: Backend::Redis(redis),
+            ttl,
+        }
+    }
+
+    /// The cached answer for `domain`, if one is still within its TTL.
+    pub fn get(&self, domain: &str) -> Result<Option<bool>> {
+        let conn = match &self.backend {
+            Backend::Sqlite(conn) => conn,
+            Backend::Redis(redis) => {
+                return Ok(redis.get(&redis_key(domain))?.map(|value| value == "1"));
+            }
+        };
+        let conn = conn.lock().unwrap_or_else(|p| p.into_inner());
+        let registered = conn
+            .prepare_cached(
+                "SELECT registered FROM registration_checks
+				WHERE domain = ?1 AND checked_at + ttl_secs > ?2",
+            )?
+            .query_row((domain, now()), |row| row.get(0))
+            .optional()?;
+        Ok(registered)
+    }
+
+    pub fn put(&self, domain: &str, registered: bool) -> Result<()> {
+        let conn = match &self.backend {
+            Backend::Sqlite(conn) => conn,
+            Backend::Redis(redis) => {
+                let value = if registered { "1" } else { "0" };
+                return redis.set(&redis_key(domain), value, self.ttl);
+            }
+        };
+        let conn = conn.lock().unwrap_or_else(|p| p.into_inner());
+        conn.prepare_cached(
+            "INSERT OR REPLACE INTO registration_checks (domain, registered, checked_at, ttl_secs)
+			VALUES (?1, ?2, ?3, ?4)",
+        )?
+        .execute((domain, registered, now(), self.ttl.as_secs() as i64))?;
+        Ok(())
+    }
+}
+
+/// Makes `cache` the process-wide registration cache. Only the first call
+/// takes effect.
+pub fn install(cache: RegistrationCache) {
+    let _ = INSTALLED.set(cache);
+}
+
+pub fn installed() -> Option<&'static RegistrationCache> {
+    INSTALLED.get()
+}
+
+fn redis_key(domain: &str) -> String {
+    format!("dns:{domain}")
+}
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs() as i64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn answers_expire_after_their_ttl() {
+        let cache = RegistrationCache::open_in_memory(Duration::from_secs(60)).unwrap();
+        assert_eq!(cache.get("example.com").unwrap(), None);
+        cache.put("example.com", true).unwrap();
+        cache.put("examp1e.com", false).unwrap();
+        assert_eq!(cache.get("example.com").unwrap(), Some(true));
+        assert_eq!(cache.get("examp1e.com").unwrap(), Some(false));
+
+        let expired = RegistrationCache::open_in_memory(Duration::ZERO).unwrap();
+        expired.put("example.com", true).unwrap();
+        assert_eq!(expired.get("example.com").unwrap(), None);
+    }
+
+    #[test]
+    fn redis_backend_shares_answers() {
+        let url = crate::redis_cache::fake_server();
+        let open = || RegistrationCache::redis(RedisCache::connect(&url).unwrap(), Duration::ZERO);
+        open().put("example.com", true).unwrap();
+        open().put("examp1e.com", false).unwrap();
+        assert_eq!(open().get("example.com").unwrap(), Some(true));
+        assert_eq!(open().get("examp1e.com").unwrap(), Some(false));
+        assert_eq!(open().get("exannple.com").unwrap(), None);
+    }
+}
@@ -0,0 +1,180 @@
+/*
+DNS telemetry readers for `scan-zeek`. Zeek `dns.log` comes as TSV (with its
+`#fields` header) or as JSON lines; Suricata writes `eve.json` with one event
+per line. Each yields the queried names and the client that asked, which are
+deduped into `scan::Observations`.
+*/
+use anyhow::Result;
+use serde_json::Value;
+
+use crate::scan::Observations;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum LogFormat {
+    /// Zeek dns.log, TSV or JSON (detected)
+    Zeek,
+    /// Suricata eve.json (DNS events)
+    Eve,
+}
+
+/// Reads the queried names and their clients from a DNS log.
+pub fn read(text: &str, format: LogFormat) -> Result<Observations> {
+    let mut observations = Observations::default();
+    let mut lines = text
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .peekable();
+    let json = lines
+        .peek()
+        .is_some_and(|line| line.trim_start().starts_with('{'));
+    match format {
+        LogFormat::Zeek if json => {
+            for line in lines {
+                let event: Value = serde_json::from_str(line)?;
+                if let Some(query) = event.get("query").and_then(Value::as_str) {
+                    observations.add(query, event.get("id.orig_h").and_then(Value::as_str));
+                }
+            }
+        }
+        LogFormat::Zeek => read_zeek_tsv(lines, &mut observations)?,
+        LogFormat::Eve => {
+            for line in lines {
+                let event: Value = serde_json::from_str(line)?;
+                if event.get("event_type").and_then(Value::as_str) != Some("dns") {
+                    continue;
+                }
+                let client = event.get("src_ip").and_then(Value::as_str);
+                let dns = &event["dns"];
+                // Answers repeat the query's name (with the server as source).
+                if matches!(
+                    dns.get("type").and_then(Value::as_str),
+                    Some("answer" | "response")
+                ) {
+                    continue;
+                }
+                // Version 1 events carry `rrname`; version 2 requests list
+                // `queries`.
+                if let Some(name) = dns.get("rrname").and_then(Value::as_str) {
+                    observations.add(name, client);
+                }
+                for query in dns
+                    .get("queries")
+                    .and_then(Value::as_array)
+                    .into_iter()
+                    .flatten()
+                {
+                    if let Some(name) = query.get("rrname").and_then(Value::as_str) {
+                        observations.add(name, client);
+                    }
+                }
+            }
+        }
+    }
+    Ok(observations)
+}
+
+fn read_zeek_tsv<'a>(
+    lines: impl Iterator<Item = &'a str>,
+    observations: &mut Observations,
+) -> Result<()> {
+    let mut separator = "\t".to_string();
+    let mut columns: Option<(usize, Option<usize>)> = None;
+    for line in lines {
+        if let Some(value) = line.strip_prefix("#separator ") {
+            separator = unescape(value.trim());
+            continue;
+        }
+        if let Some(fields) = line.strip_prefix("#fields") {
+            let fields: Vec<&str> = fields
+                .trim_start_matches(separator.as_str())
+                .split(separator.as_str())
+                .collect();
+            let query = fields
+                .iter()
+                .position(|field| *field == "query")
+                .ok_or_else(|| anyhow::anyhow!("Zeek log has no query field"))?;
+            let client = fields.iter().position(|field| *field == "id.orig_h");
+            columns = Some((query, client));
+            continue;
+        }
+        if line.starts_with('#') {
+            continue;
+        }
+        let (query, client) =
+            columns.ok_or_else(|| anyhow::anyhow!("Zeek log is missing its #fields header"))?;
+        let values: Vec<&str> = line.split(separator.as_str()).collect();
+        // Zeek writes `-` for unset fields.
+        let value = |idx: usize| values.get(idx).copied().filter(|value| *value != "-");
+        if let Some(name) = value(query) {
+            observations.add(name, client.and_then(value));
+        }
+    }
+    Ok(())
+}
+
+/// Decodes Zeek's `\x09`-style separator declaration.
+fn unescape(value: &str) -> String {
+    match value.strip_prefix("\\x") {
+        Some(hex) => u8::from_str_radix(hex, 16)
+            .map(|byte| (byte as char).to_string())
+            .unwrap_or_else(|_| value.to_string()),
+        None => value.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn summary(observations: Observations) -> Vec<(String, usize, Vec<String>)> {
+        observations
+            .into_vec()
+            .into_iter()
+            .map(|o| (o.domain, o.occurrences, o.clients.into_iter().collect()))
+            .collect()
+    }
+
+    #[test]
+    fn reads_zeek_tsv_and_json() {
+        let tsv = "#separator \\x09\n\
+            #set_separator\t,\n\
+            #fields\tts\tuid\tid.orig_h\tid.orig_p\tid.resp_h\tid.resp_p\tproto\ttrans_id\tquery\n\
+            #types\ttime\tstring\taddr\tport\taddr\tport\tenum\tcount\tstring\n\
+            1700000000.1\tC1\t10.0.0.5\t5353\t10.0.0.1\t53\tudp\t1\tlogin.paypa1.com\n\
+            1700000000.2\tC2\t10.0.0.6\t5353\t10.0.0.1\t53\tudp\t2\tpaypa1.com\n\
+            1700000000.3\tC3\t10.0.0.5\t5353\t10.0.0.1\t53\tudp\t3\t-\n\
+            #close\t2024-01-01-00-00-00\n";
+        assert_eq!(
+            summary(read(tsv, LogFormat::Zeek).unwrap()),
+            vec![(
+                "paypa1.com".to_string(),
+                2,
+                vec!["10.0.0.5".to_string(), "10.0.0.6".to_string()]
+            )]
+        );
+
+        let json = r#"{"ts":1700000000.1,"id.orig_h":"10.0.0.7","query":"examp1e.com"}
+{"ts":1700000000.2,"id.orig_h":"10.0.0.7","query":"1.0.0.10.in-addr.arpa"}"#;
+        assert_eq!(
+            summary(read(json, LogFormat::Zeek).unwrap()),
+            vec![("examp1e.com".to_string(), 1, vec!["10.0.0.7".to_string()])]
+        );
+        assert!(read("1\t2\n", LogFormat::Zeek).is_err());
+    }
+
+    #[test]
+    fn reads_suricata_eve_dns_events() {
+        let eve = r#"{"event_type":"dns","src_ip":"192.168.1.4","dns":{"type":"query","rrname":"micros0ft.com"}}
+{"event_type":"alert","src_ip":"192.168.1.4","alert":{"signature":"x"}}
+{"event_type":"dns","src_ip":"10.0.0.53","dns":{"type":"answer","rrname":"micros0ft.com","rcode":"NOERROR"}}
+{"event_type":"dns","src_ip":"192.168.1.9","dns":{"version":2,"type":"request","queries":[{"rrname":"www.micros0ft.com","rrtype":"A"}]}}"#;
+        assert_eq!(
+            summary(read(eve, LogFormat::Eve).unwrap()),
+            vec![(
+                "micros0ft.com".to_string(),
+                2,
+                vec!["192.168.1.4".to_string(), "192.168.1.9".to_string()]
+            )]
+        );
+    }
+}
@@ -0,0 +1,181 @@
+/*
+DNS wire format, as far as SpotSpoof needs it: question names (with
+compression pointers) for `scan-pcap`, and for `doh-proxy` the first question of
+a query plus the NXDOMAIN and sinkhole answers synthesized for it.
+*/
+use std::net::IpAddr;
+
+/// Compression pointers followed per name before it is treated as malformed.
+const MAX_POINTERS: usize = 16;
+const HEADER_LEN: usize = 12;
+const FLAG_RESPONSE: u16 = 0x8000;
+/// The opcode and RD bits a response echoes from its query.
+const FLAGS_ECHOED: u16 = 0x7900;
+const FLAG_RECURSION_AVAILABLE: u16 = 0x0080;
+const RCODE_NXDOMAIN: u16 = 3;
+const TYPE_A: u16 = 1;
+const TYPE_AAAA: u16 = 28;
+const CLASS_IN: u16 = 1;
+/// A pointer to the question name, which always starts right after the header.
+const QUESTION_NAME_POINTER: [u8; 2] = [0xc0, HEADER_LEN as u8];
+
+/// The first question of a query.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Query {
+    pub id: u16,
+    pub name: String,
+    pub qtype: u16,
+    /// Offset just past the question (name, QTYPE, QCLASS).
+    question_end: usize,
+}
+
+/// Parses a query message; `None` for responses, malformed messages, and
+/// messages without a question.
+pub fn parse_query(message: &[u8]) -> Option<Query> {
+    let id = be16(message, 0)?;
+    if be16(message, 2)? & FLAG_RESPONSE != 0 || be16(message, 4)? == 0 {
+        return None;
+    }
+    let (name, end) = read_name(message, HEADER_LEN)?;
+    let qtype = be16(message, end)?;
+    be16(message, end + 2)?;
+    Some(Query {
+        id,
+        name: name.to_ascii_lowercase(),
+        qtype,
+        question_end: end + 4,
+    })
+}
+
+/// An NXDOMAIN response to `query`.
+pub fn nxdomain(message: &[u8], query: &Query) -> Vec<u8> {
+    response(message, query, RCODE_NXDOMAIN, None)
+}
+
+/// A response to `query` pointing the name at `ip`: an answer for an A (IPv4)
+/// or AAAA (IPv6) query, an empty answer for any other type.
+pub fn sinkhole(message: &[u8], query: &Query, ip: IpAddr, ttl: u32) -> Vec<u8> {
+    let rdata = match (query.qtype, ip) {
+        (TYPE_A, IpAddr::V4(ip)) => Some(ip.octets().to_vec()),
+        (TYPE_AAAA, IpAddr::V6(ip)) => Some(ip.octets().to_vec()),
+        _ => None,
+    };
+    response(
+        message,
+        query,
+        0,
+        rdata.map(|rdata| (query.qtype, ttl, rdata)),
+    )
+}
+
+fn response(
+    message: &[u8],
+    query: &Query,
+    rcode: u16,
+    answer: Option<(u16, u32, Vec<u8>)>,
+) -> Vec<u8> {
+    let flags = FLAG_RESPONSE
+        | (be16(message, 2).unwrap_or_default() & FLAGS_ECHOED)
+        | FLAG_RECURSION_AVAILABLE
+        | rcode;
+    let mut out = Vec::with_capacity(query.question_end + 32);
+    out.extend_from_slice(&query.id.to_be_bytes());
+    out.extend_from_slice(&flags.to_be_bytes());
+    out.extend_from_slice(&1u16.to_be_bytes());
+    out.extend_from_slice(&u16::from(answer.is_some()).to_be_bytes());
+    out.extend_from_slice(&[0, 0, 0, 0]);
+    out.extend_from_slice(&message[HEADER_LEN..query.question_end]);
+    if let Some((rtype, ttl, rdata)) = answer {
+        out.extend_from_slice(&QUESTION_NAME_POINTER);
+        out.extend_from_slice(&rtype.to_be_bytes());
+        out.extend_from_slice(&CLASS_IN.to_be_bytes());
+        out.extend_from_slice(&ttl.to_be_bytes());
+        out.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+        out.extend_from_slice(&rdata);
+    }
+    out
+}
+
+/// Reads the (possibly compressed) name at `offset`; returns it and the offset
+/// just past it.
+pub fn read_name(message: &[u8], mut offset: usize) -> Option<(String, usize)> {
+    let mut labels: Vec<String> = Vec::new();
+    let mut end = None;
+    let mut pointers = 0;
+    loop {
+        let len = *message.get(offset)?;
+        match len & 0xc0 {
+            0x00 if len == 0 => break,
+            0x00 => {
+                let label = message.get(offset + 1..offset + 1 + usize::from(len))?;
+                labels.push(String::from_utf8_lossy(label).into_owned());
+                offset += 1 + usize::from(len);
+            }
+            0xc0 => {
+                pointers += 1;
+                if pointers > MAX_POINTERS {
+                    return None;
+                }
+                end.get_or_insert(offset + 2);
+                offset = usize::from(be16(message, offset)? & 0x3fff);
+            }
+            _ => return None,
+        }
+    }
+    Some((labels.join("."), end.unwrap_or(offset + 1)))
+}
+
+pub fn be16(data: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_be_bytes(
+        data.get(offset..offset + 2)?.try_into().ok()?,
+    ))
+}
+
+/// A query for `name` (RD set), for tests.
+#[cfg(test)]
+pub fn query(id: u16, name: &str, qtype: u16) -> Vec<u8> {
+    let mut message = id.to_be_bytes().to_vec();
+    message.extend_from_slice(&[0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0]);
+    for label in name.split('.') {
+        message.push(label.len() as u8);
+        message.extend_from_slice(label.as_bytes());
+    }
+    message.push(0);
+    message.extend_from_slice(&qtype.to_be_bytes());
+    message.extend_from_slice(&CLASS_IN.to_be_bytes());
+    message
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    #[test]
+    fn synthesized_responses_echo_the_question() {
+        let message = query(0xbeef, "PayPa1.com", TYPE_A);
+        let parsed = parse_query(&message).unwrap();
+        assert_eq!(parsed.id, 0xbeef);
+        assert_eq!(parsed.name, "paypa1.com");
+        assert_eq!(parsed.qtype, TYPE_A);
+
+        let nx = nxdomain(&message, &parsed);
+        assert_eq!(&nx[..12], &[0xbe, 0xef, 0x81, 0x83, 0, 1, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(&nx[12..], &message[12..]);
+
+        let sink = sinkhole(&message, &parsed, Ipv4Addr::UNSPECIFIED.into(), 60);
+        assert_eq!(&sink[2..8], &[0x81, 0x80, 0, 1, 0, 1]);
+        assert_eq!(
+            &sink[message.len()..],
+            &[0xc0, 12, 0, 1, 0, 1, 0, 0, 0, 60, 0, 4, 0, 0, 0, 0]
+        );
+        // An IPv6 sinkhole leaves A queries without an answer.
+        let empty = sinkhole(&message, &parsed, Ipv6Addr::UNSPECIFIED.into(), 60);
+        assert_eq!(empty.len(), message.len());
+        assert_eq!(&empty[6..8], &[0, 0]);
+
+        // Responses and truncated messages are not queries.
+        assert!(parse_query(&nx).is_none());
+        assert!(parse_query(&message[..message.len() - 1]).is_none());
+    }
+}
@@ -0,0 +1,274 @@
+/*
+DNS-over-HTTPS forwarding proxy (`spotspoof doh-proxy`). Serves RFC 8484 DoH
+(GET with `?dns=`, or POST of an `application/dns-message`) on `/dns-query` and
+forwards every query to an upstream DoH resolver, after checking the queried
+name against the watched brands. Queries for lookalikes are logged, answered
+with NXDOMAIN, or pointed at a sinkhole address, depending on `--action`.
+Verdicts are cached per registrable domain. A failed lookup never blocks
+resolution.
+*/
+use anyhow::Result;
+use axum::{
+    body::Bytes,
+    extract::{Query, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    routing::get,
+    Router,
+};
+use base64::Engine;
+use lru::LruCache;
+use once_cell::sync::OnceCell;
+use serde::Deserialize;
+use std::net::{IpAddr, SocketAddr};
+use std::num::NonZeroUsize;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+use tracing::{info, warn};
+
+use crate::scan::{self, Scanner};
+use crate::{dnswire, net, types};
+
+const DNS_MESSAGE: &str = "application/dns-message";
+const MIN_MESSAGE: usize = 12;
+const MAX_MESSAGE: usize = 65_535;
+const VERDICT_CACHE_SIZE: usize = 10_000;
+/// TTL of synthesized sinkhole answers.
+const SINKHOLE_TTL: u32 = 60;
+
+/// What happens to a query for a lookalike.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum DohAction {
+    /// Forward it as usual and log a warning
+    Log,
+    /// Answer NXDOMAIN
+    Nxdomain,
+    /// Answer A/AAAA queries with the --redirect-to address
+    Redirect,
+}
+
+pub struct DohProxyOptions {
+    pub upstream: String,
+    pub action: DohAction,
+    pub redirect_to: IpAddr,
+    pub timeout: Duration,
+}
+
+pub struct DohProxy {
+    options: DohProxyOptions,
+    scanner: Mutex<Scanner>,
+    /// Resemblance of each looked-up domain, `None` when it is not a lookalike.
+    verdicts: Mutex<LruCache<String, Option<String>>>,
+    /// Built on first use, on a blocking thread.
+    client: OnceCell<reqwest::blocking::Client>,
+}
+
+impl DohProxy {
+    pub fn new(options: DohProxyOptions, scanner: Scanner) -> Self {
+        Self {
+            options,
+            scanner: Mutex::new(scanner),
+            verdicts: Mutex::new(LruCache::new(
+                NonZeroUsize::new(VERDICT_CACHE_SIZE).expect("cache size is not zero"),
+            )),
+            client: OnceCell::new(),
+        }
+    }
+
+    /// Why queries for `name` are flagged, if its registrable domain resembles
+    /// a watched one.
+    fn verdict(&self, name: &str) -> Option<String> {
+        let domain = types::parse_domain_input(name).ok()?;
+        if let Some(verdict) = lock(&self.verdicts).get(&domain) {
+            return verdict.clone();
+        }
+        let verdict = match lock(&self.scanner).lookup(&domain) {
+            Ok(lookup) => lookup
+                .filter(scan::is_lookalike)
+                .map(|lookup| scan::resemblance(&lookup)),
+            Err(err) => {
+                warn!(kind = "doh", domain = %domain, "lookup failed: {err}");
+                return None;
+            }
+        };
+        lock(&self.verdicts).put(domain, verdict.clone());
+        verdict
+    }
+
+    /// Answers one query message, passing it to `forward` unless it is for a
+    /// lookalike and the action blocks it.
+    fn resolve(
+        &self,
+        message: &[u8],
+        forward: impl FnOnce(&[u8]) -> Result<Vec<u8>>,
+    ) -> Result<Vec<u8>> {
+        let Some(query) = dnswire::parse_query(message) else {
+            return forward(message);
+        };
+        let Some(reason) = self.verdict(&query.name) else {
+            return forward(message);
+        };
+        warn!(
+            kind = "doh",
+            name = %query.name,
+            action = ?self.options.action,
+            "lookalike query: {} {reason}",
+            query.name
+        );
+        match self.options.action {
+            DohAction::Log => forward(message),
+            DohAction::Nxdomain => Ok(dnswire::nxdomain(message, &query)),
+            DohAction::Redirect => Ok(dnswire::sinkhole(
+                message,
+                &query,
+                self.options.redirect_to,
+                SINKHOLE_TTL,
+            )),
+        }
+    }
+
+    fn forward(&self, message: &[u8]) -> Result<Vec<u8>> {
+        let client = self
+            .client
+            .get_or_try_init(|| net::client_builder().timeout(self.options.timeout).build())?;
+        let response = client
+            .post(&self.options.upstream)
+            .header(header::CONTENT_TYPE, DNS_MESSAGE)
+            .header(header::ACCEPT, DNS_MESSAGE)
+            .body(message.to_vec())
+            .send()?
+            .error_for_status()?;
+        Ok(response.bytes()?.to_vec())
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|p| p.into_inner())
+}
+
+pub async fn serve(host: &str, port: u16, proxy: DohProxy) -> Result<()> {
+    let app = Router::new()
+        .route("/dns-query", get(get_query).post(post_query))
+        .with_state(Arc::new(proxy));
+    let addr: SocketAddr = format!("{host}:{port}").parse()?;
+    info!(kind = "doh", listen = %addr, "listening");
+    let listener = tokio::net::TcpListener::bind(addr).await?;
+    axum::serve(listener, app).await?;
+    Ok(())
+}
+
+#[derive(Deserialize)]
+struct DnsParam {
+    dns: String,
+}
+
+async fn get_query(State(proxy): State<Arc<DohProxy>>, Query(param): Query<DnsParam>) -> Response {
+    // RFC 8484 drops the padding, but some clients send it anyway.
+    match base64::engine::general_purpose::URL_SAFE_NO_PAD.decode(param.dns.trim_end_matches('=')) {
+        Ok(message) => answer(proxy, message).await,
+        Err(_) => (StatusCode::BAD_REQUEST, "dns is not base64url").into_response(),
+    }
+}
+
+async fn post_query(
+    State(proxy): State<Arc<DohProxy>>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    let content_type = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok());
+    if content_type != Some(DNS_MESSAGE) {
+        return (
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            "expected application/dns-message",
+        )
+            .into_response();
+    }
+    answer(proxy, body.to_vec()).await
+}
+
+async fn answer(proxy: Arc<DohProxy>, message: Vec<u8>) -> Response {
+    if !(MIN_MESSAGE..=MAX_MESSAGE).contains(&message.len()) {
+        return (StatusCode::BAD_REQUEST, "not a DNS message").into_response();
+    }
+    let resolved = tokio::task::spawn_blocking(move || {
+        proxy.resolve(&message, |message| proxy.forward(message))
+    })
+    .await;
+    match resolved {
+        Ok(Ok(response)) => ([(header::CONTENT_TYPE, DNS_MESSAGE)], response).into_response(),
+        Ok(Err(err)) => {
+            warn!(kind = "doh", "upstream query failed: {err}");
+            StatusCode::BAD_GATEWAY.into_response()
+        }
+        Err(err) => {
+            warn!(kind = "doh", "query task failed: {err}");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ascii_spoof::AsciiOptions;
+    use std::net::Ipv4Addr;
+
+    const TYPE_A: u16 = 1;
+
+    fn proxy(action: DohAction) -> DohProxy {
+        let scanner = Scanner::new(None, AsciiOptions::default(), None)
+            .with_targets(vec!["paypal.com".to_string()]);
+        DohProxy::new(
+            DohProxyOptions {
+                upstream: "https://upstream.invalid/dns-query".to_string(),
+                action,
+                redirect_to: Ipv4Addr::UNSPECIFIED.into(),
+                timeout: Duration::from_secs(1),
+            },
+            scanner,
+        )
+    }
+
+    fn upstream(message: &[u8]) -> Result<Vec<u8>> {
+        let mut response = message.to_vec();
+        response[2] |= 0x80;
+        Ok(response)
+    }
+
+    #[test]
+    fn lookalike_queries_follow_the_action() {
+        let lookalike = dnswire::query(7, "login.paypa1.com", TYPE_A);
+        let clean = dnswire::query(8, "www.example.org", TYPE_A);
+
+        let nxdomain = proxy(DohAction::Nxdomain);
+        let blocked = nxdomain.resolve(&lookalike, |_| unreachable!()).unwrap();
+        assert_eq!(blocked[3] & 0x0f, 3);
+        assert_eq!(
+            nxdomain.resolve(&clean, upstream).unwrap(),
+            upstream(&clean).unwrap()
+        );
+        // The watched domain itself is not a lookalike.
+        let own = dnswire::query(9, "paypal.com", TYPE_A);
+        assert_eq!(
+            nxdomain.resolve(&own, upstream).unwrap(),
+            upstream(&own).unwrap()
+        );
+        assert_eq!(
+            lock(&nxdomain.verdicts).len(),
+            3,
+            "every looked-up domain is cached"
+        );
+
+        let redirect = proxy(DohAction::Redirect);
+        let sinkholed = redirect.resolve(&lookalike, |_| unreachable!()).unwrap();
+        assert_eq!(&sinkholed[sinkholed.len() - 4..], &[0, 0, 0, 0]);
+
+        let log = proxy(DohAction::Log);
+        assert_eq!(
+            log.resolve(&lookalike, upstream).unwrap(),
+            upstream(&lookalike).unwrap()
+        );
+    }
+}
@@ -0,0 +1,936 @@
+/*
+Release DB downloads. Streams the compressed artifact to `<db>.zst.part`,
+resuming an interrupted transfer with an HTTP Range request, verifies its
+SHA-256 (from `--sha256`, `--sha256-url`, the published `<url>.sha256`, a
+`db_sha256.txt` in the data directory, or the bundled checksum, in that order)
+and its minisign signature (`<url>.minisig`), then unpacks it to a temp file and renames it over the DB.
+The HTTP validators of the installed artifact are kept so updates can be
+conditional.
+*/
+use anyhow::Result;
+use minisign_verify::{PublicKey, Signature};
+use reqwest::blocking::Client;
+use reqwest::header::{self, HeaderMap};
+use reqwest::StatusCode;
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufReader, Read, Write};
+use std::path::Path;
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+use tempfile::NamedTempFile;
+use tracing::{debug, info, warn};
+
+use crate::{cipher, db, net, paths};
+
+const DEFAULT_DB_SHA256_PATH: &str = "config/db_sha256.txt";
+/// The checksum file looked for in the data directory.
+const DB_SHA256_FILE: &str = "db_sha256.txt";
+const EMBEDDED_DB_SHA256: &str = include_str!("../config/db_sha256.txt");
+/// Minisign public key of the release artifacts. When empty, DB downloads are
+/// refused unless `--pubkey` is given or `--allow-unsigned-db` opts out.
+const EMBEDDED_DB_PUBKEY: &str = include_str!("../config/db_minisign.pub");
+const ZSTD_WINDOW_LOG_MAX: u32 = 31;
+const CHUNK_SIZE: usize = 64 * 1024;
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(2);
+
+static CHECKSUM: OnceLock<ChecksumSource> = OnceLock::new();
+static PUBKEY: OnceLock<Option<PublicKey>> = OnceLock::new();
+static ALLOW_UNSIGNED: OnceLock<bool> = OnceLock::new();
+
+/// Overrides for the expected SHA-256 of downloaded artifacts.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ChecksumSource {
+    /// The checksum itself (lowercase hex).
+    pub sha256: Option<String>,
+    /// A `.sha256` file to fetch it from, instead of `<artifact-url>.sha256`.
+    pub sha256_url: Option<String>,
+}
+
+impl ChecksumSource {
+    pub fn new(sha256: Option<String>, sha256_url: Option<String>) -> Result<Self> {
+        Ok(Self {
+            sha256: sha256.as_deref().map(parse_sha256).transpose()?,
+            sha256_url,
+        })
+    }
+}
+
+/// Makes `source` the process-wide checksum override. Only the first call
+/// takes effect.
+pub fn install_checksum(source: ChecksumSource) {
+    let _ = CHECKSUM.set(source);
+}
+
+/// Sets the key downloads must be signed with: `key` (a base64 minisign key or
+/// the path of a `.pub` file) if given, else the embedded release key, if any.
+/// Only the first call takes effect.
+pub fn install_pubkey(key: Option<&str>) -> Result<()> {
+    let key = match key {
+        Some(key) if std::path::Path::new(key).is_file() => {
+            Some(parse_pubkey(&fs::read_to_string(key).map_err(|err| {
+                anyhow::anyhow!("cannot read public key {key}: {err}")
+            })?)?)
+        }
+        Some(key) => Some(parse_pubkey(key)?),
+        None if EMBEDDED_DB_PUBKEY.trim().is_empty() => None,
+        None => Some(parse_pubkey(EMBEDDED_DB_PUBKEY)?),
+    };
+    let _ = PUBKEY.set(key);
+    Ok(())
+}
+
+/// Lets DB downloads through on their checksum alone when no public key is
+/// configured (`--allow-unsigned-db`). Only the first call takes effect.
+pub fn install_allow_unsigned(allow: bool) {
+    let _ = ALLOW_UNSIGNED.set(allow);
+}
+
+/// The key a DB download must be signed with. Without one the download fails
+/// closed, unless unsigned downloads were allowed explicitly.
+fn db_signing_key(key: Option<&PublicKey>, allow_unsigned: bool) -> Result<Option<&PublicKey>> {
+    match key {
+        Some(key) => Ok(Some(key)),
+        None if allow_unsigned => Ok(None),
+        None => Err(anyhow::anyhow!(
+            "DB signature cannot be verified: no minisign public key is configured (pass --pubkey, or --allow-unsigned-db to trust the checksum alone)"
+        )),
+    }
+}
+
+/// Accepts a bare base64 key or the contents of a minisign `.pub` file.
+fn parse_pubkey(text: &str) -> Result<PublicKey> {
+    let text = text.trim();
+    let key = if text.lines().count() > 1 {
+        PublicKey::decode(text)
+    } else {
+        PublicKey::from_base64(text)
+    };
+    key.map_err(|err| anyhow::anyhow!("invalid minisign public key: {err}"))
+}
+
+/// What a DB download did.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DownloadOutcome {
+    /// A new artifact was installed; carries its ETag (else Last-Modified).
+    Downloaded(Option<String>),
+    /// The server answered 304: the local DB is already the published one.
+    NotModified,
+}
+
+/// HTTP validators of an artifact, kept in `<path>.validators` next to the
+/// installed DB (for conditional updates) and next to a partial download (so a
+/// resume only continues the same artifact).
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct Validators {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    etag: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    last_modified: Option<String>,
+}
+
+impl Validators {
+    fn path(path: &str) -> String {
+        format!("{path}.validators")
+    }
+
+    fn load(path: &str) -> Self {
+        fs::read_to_string(Self::path(path))
+            .ok()
+            .and_then(|text| serde_json::from_str(&text).ok())
+            .unwrap_or_default()
+    }
+
+    fn from_headers(headers: &HeaderMap) -> Self {
+        let header = |name| {
+            headers
+                .get(name)
+                .and_then(|value: &header::HeaderValue| value.to_str().ok())
+                .map(str::to_string)
+        };
+        Self {
+            etag: header(header::ETAG),
+            last_modified: header(header::LAST_MODIFIED),
+        }
+    }
+
+    fn store(&self, path: &str) -> Result<()> {
+        if *self == Self::default() {
+            return remove_if_exists(&Self::path(path));
+        }
+        fs::write(Self::path(path), serde_json::to_string(self)?)?;
+        Ok(())
+    }
+
+    fn version(&self) -> Option<String> {
+        self.etag.clone().or_else(|| self.last_modified.clone())
+    }
+
+    /// The `If-Range` value: a strong ETag, else Last-Modified.
+    fn if_range(&self) -> Option<&str> {
+        self.etag
+            .as_deref()
+            .filter(|etag| !etag.starts_with("W/"))
+            .or(self.last_modified.as_deref())
+    }
+}
+
+/// How one request for the artifact ended.
+enum Transfer {
+    NotModified,
+    Complete(Validators),
+    /// The connection dropped mid-body; the part file keeps what arrived.
+    Interrupted(anyhow::Error),
+}
+
+/// Downloads and installs the release DB unconditionally.
+pub fn download_db(url: &str, db_path: &str) -> Result<DownloadOutcome> {
+    fetch_db(url, db_path, false)
+}
+
+/// Like `download_db`, but sends the validators of the installed DB
+/// (`If-None-Match`/`If-Modified-Since`) so an unchanged artifact is not
+/// downloaded again.
+pub fn update_db(url: &str, db_path: &str) -> Result<DownloadOutcome> {
+    fetch_db(url, db_path, std::path::Path::new(db_path).is_file())
+}
+
+/// Forgets the validators of `db_path`, once the DB was changed locally and no
+/// longer matches the artifact they describe.
+pub fn discard_validators(db_path: &str) -> Result<()> {
+    remove_if_exists(&Validators::path(db_path))
+}
+
+fn fetch_db(url: &str, db_path: &str, conditional: bool) -> Result<DownloadOutcome> {
+    net::ensure_online("Downloading the DB")?;
+    let key = db_signing_key(
+        PUBKEY.get().and_then(Option::as_ref),
+        ALLOW_UNSIGNED.get().copied().unwrap_or(false),
+    )?;
+    if let Some(parent) = std::path::Path::new(db_path).parent() {
+        if !parent.as_os_str().is_empty() {
+            fs::create_dir_all(parent)?;
+        }
+    }
+
+    let installed = if conditional {
+        Validators::load(db_path)
+    } else {
+        Validators::default()
+    };
+    let part_path = format!("{db_path}.zst.part");
+    let client = net::client_builder().build()?;
+    let resumes = net::policy().retries;
+    let mut attempt = 0;
+    let validators = loop {
+        match transfer(&client, url, &part_path, &installed)? {
+            Transfer::NotModified => return Ok(DownloadOutcome::NotModified),
+            Transfer::Complete(validators) => break validators,
+            Transfer::Interrupted(err) if attempt < resumes => {
+                attempt += 1;
+                warn!(
+                    kind = "db_download",
+                    attempt, "transfer interrupted, resuming: {err}"
+                );
+            }
+            Transfer::Interrupted(err) => {
+                return Err(anyhow::anyhow!(
+                    "Download interrupted: {err}; run again to resume from {part_path}"
+                ))
+            }
+        }
+    };
+
+    let expected = expected_sha256(&client, url, CHECKSUM.get().cloned().unwrap_or_default())?;
+    let verified = verify_db_sha256(&part_path, &expected).and_then(|()| match key {
+        Some(key) => verify_signature(&client, url, &part_path, key, "DB"),
+        None => {
+            warn!(
+                kind = "db_download",
+                url = %url,
+                "DB signature NOT verified (--allow-unsigned-db): only the checksum was checked"
+            );
+            Ok(())
+        }
+    });
+    if let Err(err) = verified {
+        remove_part(&part_path)?;
+        return Err(err);
+    }
+    install(&part_path, db_path)?;
+    remove_part(&part_path)?;
+    validators.store(db_path)?;
+    Ok(DownloadOutcome::Downloaded(validators.version()))
+}
+
+/// Downloads a release artifact other than the DB (the `self-update` binary)
+/// into a new private temp file in `dir`. Both its published `<url>.sha256`
+/// and its `<url>.minisig` are required: an executable is never installed on
+/// a checksum from the same origin alone. The file is removed unless both
+/// checks pass.
+pub fn download_verified(url: &str, dir: &Path) -> Result<NamedTempFile> {
+    let key = PUBKEY.get().and_then(Option::as_ref).ok_or_else(|| {
+        anyhow::anyhow!(
+            "release signature required: no minisign public key is configured (pass --pubkey)"
+        )
+    })?;
+    download_signed(url, dir, key)
+}
+
+fn download_signed(url: &str, dir: &Path, key: &PublicKey) -> Result<NamedTempFile> {
+    net::ensure_online("Downloading a release")?;
+    let client = net::client_builder().build()?;
+    let mut response = net::send_with_retry("release_download", || client.get(url))?;
+    if !response.status().is_success() {
+        return Err(anyhow::anyhow!(
+            "Download from {url} failed: HTTP {}",
+            response.status()
+        ));
+    }
+    // Created exclusively, readable by the owner only.
+    let mut out = tempfile::Builder::new()
+        .prefix(".spotspoof-")
+        .suffix(".download")
+        .tempfile_in(dir)?;
+    std::io::copy(&mut response, out.as_file_mut())?;
+    out.as_file_mut().flush()?;
+    let path = out
+        .path()
+        .to_str()
+        .ok_or_else(|| anyhow::anyhow!("temp path {} is not UTF-8", out.path().display()))?;
+
+    let expected = fetch_sha256(&client, &format!("{url}.sha256"))?;
+    let actual = db::file_sha256(path)?;
+    if actual != expected {
+        return Err(anyhow::anyhow!(
+            "checksum mismatch for {url}: expected {expected}, got {actual}"
+        ));
+    }
+    verify_signature(&client, url, path, key, "release")?;
+    Ok(out)
+}
+
+/// Requests the artifact, continuing `part_path` with a Range request when it
+/// holds an earlier partial transfer of the same artifact.
+fn transfer(
+    client: &Client,
+    url: &str,
+    part_path: &str,
+    installed: &Validators,
+) -> Result<Transfer> {
+    let partial = Validators::load(part_path);
+    let offset = fs::metadata(part_path).map(|meta| meta.len()).unwrap_or(0);
+    let resume = offset > 0 && partial.if_range().is_some();
+
+    let response = net::send_with_retry("db_download", || {
+        let mut request = client.get(url);
+        if let Some(etag) = &installed.etag {
+            request = request.header(header::IF_NONE_MATCH, etag);
+        }
+        if let Some(last_modified) = &installed.last_modified {
+            request = request.header(header::IF_MODIFIED_SINCE, last_modified);
+        }
+        if resume {
+            request = request.header(header::RANGE, format!("bytes={offset}-"));
+            if let Some(validator) = partial.if_range() {
+                request = request.header(header::IF_RANGE, validator);
+            }
+        }
+        request
+    })?;
+
+    let status = response.status();
+    if status == StatusCode::NOT_MODIFIED {
+        return Ok(Transfer::NotModified);
+    }
+    if status == StatusCode::RANGE_NOT_SATISFIABLE {
+        remove_part(part_path)?;
+        return Ok(Transfer::Interrupted(anyhow::anyhow!(
+            "server rejected the resume range"
+        )));
+    }
+    if !status.is_success() {
+        return Err(anyhow::anyhow!("Download failed: HTTP {status}"));
+    }
+
+    let resumed = resume && status == StatusCode::PARTIAL_CONTENT;
+    let validators = if resumed {
+        partial
+    } else {
+        // A full response replaces whatever was there.
+        let validators = Validators::from_headers(response.headers());
+        validators.store(part_path)?;
+        validators
+    };
+    let mut out = OpenOptions::new()
+        .create(true)
+        .write(true)
+        .append(resumed)
+        .truncate(!resumed)
+        .open(part_path)?;
+    let mut received = if resumed { offset } else { 0 };
+    let total = response.content_length().map(|length| length + received);
+    if resumed {
+        info!(kind = "db_download", offset, "resuming download");
+    }
+
+    let mut response = response;
+    let mut buf = vec![0u8; CHUNK_SIZE];
+    let mut last_report = Instant::now();
+    loop {
+        let read = match response.read(&mut buf) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(err) => {
+                out.flush()?;
+                return Ok(Transfer::Interrupted(err.into()));
+            }
+        };
+        out.write_all(&buf[..read])?;
+        received += read as u64;
+        if last_report.elapsed() >= PROGRESS_INTERVAL {
+            last_report = Instant::now();
+            report_progress(received, total);
+        }
+    }
+    out.flush()?;
+    if total.is_some_and(|total| received < total) {
+        return Ok(Transfer::Interrupted(anyhow::anyhow!(
+            "connection closed after {received} bytes"
+        )));
+    }
+    report_progress(received, total);
+    Ok(Transfer::Complete(validators))
+}
+
+fn report_progress(received: u64, total: Option<u64>) {
+    match total {
+        Some(total) if total > 0 => {
+            let percent = received * 100 / total;
+            info!(
+                kind = "db_download",
+                received, total, percent, "downloading DB"
+            );
+        }
+        _ => info!(kind = "db_download", received, "downloading DB"),
+    }
+}
+
+/// Unpacks the verified artifact next to the DB and renames it into place.
+fn install(part_path: &str, db_path: &str) -> Result<()> {
+    let tmp_path = format!("{db_path}.tmp");
+    let mut decoder = zstd::stream::read::Decoder::new(BufReader::new(File::open(part_path)?))?;
+    decoder.window_log_max(ZSTD_WINDOW_LOG_MAX)?;
+    let mut out = File::create(&tmp_path)?;
+    std::io::copy(&mut decoder, &mut out)?;
+    out.flush()?;
+    drop(out);
+    // WAL lets many read-only connections read without blocking one another.
+    Connection::open(&tmp_path)?
+        .pragma_update_and_check(None, "journal_mode", "WAL", |row| row.get::<_, String>(0))?;
+    if cipher::enabled() {
+        cipher::encrypt(&tmp_path)?;
+    }
+    fs::rename(tmp_path, db_path)?;
+    Ok(())
+}
+
+fn remove_part(part_path: &str) -> Result<()> {
+    remove_if_exists(part_path)?;
+    remove_if_exists(&Validators::path(part_path))
+}
+
+fn remove_if_exists(path: &str) -> Result<()> {
+    match fs::remove_file(path) {
+        Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(err.into()),
+        _ => Ok(()),
+    }
+}
+
+fn read_expected_db_sha256() -> Result<String> {
+    let mut candidates = Vec::new();
+    if let Ok(path) = std::env::var("SPOTSPOOF_DB_SHA256_PATH") {
+        candidates.push(path);
+    }
+    if let Some(dir) = paths::data_dir() {
+        candidates.push(dir.join(DB_SHA256_FILE).to_string_lossy().to_string());
+    }
+    if let Ok(exe) = std::env::current_exe() {
+        if let Some(dir) = exe.parent() {
+            candidates.push(
+                dir.join(DEFAULT_DB_SHA256_PATH)
+                    .to_string_lossy()
+                    .to_string(),
+            );
+        }
+    }
+    candidates.push(DEFAULT_DB_SHA256_PATH.to_string());
+
+    let mut last_err = None;
+    let mut contents = String::new();
+    for path in candidates {
+        match File::open(&path) {
+            Ok(mut file) => {
+                file.read_to_string(&mut contents)?;
+                return Ok(contents.trim().to_lowercase());
+            }
+            Err(err) => last_err = Some(err),
+        }
+    }
+    if !EMBEDDED_DB_SHA256.trim().is_empty() {
+        return Ok(EMBEDDED_DB_SHA256.trim().to_lowercase());
+    }
+    let err = last_err.unwrap_or_else(|| {
+        std::io::Error::new(std::io::ErrorKind::NotFound, "DB checksum file not found")
+    });
+    Err(err.into())
+}
+
+/// The checksum `url`'s artifact must match: an explicit override, else the
+/// published `<url>.sha256`, else the local/bundled one.
+fn expected_sha256(client: &Client, url: &str, source: ChecksumSource) -> Result<String> {
+    if let Some(sha256) = source.sha256 {
+        return Ok(sha256);
+    }
+    if let Some(sha256_url) = source.sha256_url {
+        return fetch_sha256(client, &sha256_url);
+    }
+    match fetch_sha256(client, &format!("{url}.sha256")) {
+        Ok(sha256) => Ok(sha256),
+        Err(err) => {
+            debug!(
+                kind = "db_download",
+                "no published checksum ({err}); using the local one"
+            );
+            read_expected_db_sha256()
+        }
+    }
+}
+
+fn fetch_sha256(client: &Client, url: &str) -> Result<String> {
+    let response = net::send_with_retry("db_sha256", || client.get(url))?;
+    if !response.status().is_success() {
+        return Err(anyhow::anyhow!(
+            "Checksum download from {url} failed: HTTP {}",
+            response.status()
+        ));
+    }
+    parse_sha256(&response.text()?)
+}
+
+/// Reads a hex SHA-256 from `sha256sum`-style text (`<hex>  <file name>`).
+fn parse_sha256(text: &str) -> Result<String> {
+    let hex = text.split_whitespace().next().unwrap_or_default();
+    if hex.len() != 64 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(anyhow::anyhow!(
+            "'{}' is not a SHA-256 checksum",
+            text.trim()
+        ));
+    }
+    Ok(hex.to_lowercase())
+}
+
+/// Checks `compressed_path` against the minisign signature published at
+/// `<url>.minisig`. A missing signature is an error once a key is configured.
+/// `what` names the artifact in errors.
+fn verify_signature(
+    client: &Client,
+    url: &str,
+    compressed_path: &str,
+    key: &PublicKey,
+    what: &str,
+) -> Result<()> {
+    let signature_url = format!("{url}.minisig");
+    let response = net::send_with_retry("signature", || client.get(&signature_url))?;
+    if !response.status().is_success() {
+        return Err(anyhow::anyhow!(
+            "{what} signature download from {signature_url} failed: HTTP {}",
+            response.status()
+        ));
+    }
+    let signature = Signature::decode(&response.text()?)
+        .map_err(|err| anyhow::anyhow!("invalid {what} signature: {err}"))?;
+    verify_file_signature(compressed_path, &signature, key, what)
+}
+
+fn verify_file_signature(
+    path: &str,
+    signature: &Signature,
+    key: &PublicKey,
+    what: &str,
+) -> Result<()> {
+    let invalid = |err| anyhow::anyhow!("{what} signature verification failed: {err}");
+    let mut verifier = key.verify_stream(signature).map_err(invalid)?;
+    let mut file = File::open(path)?;
+    let mut buf = vec![0u8; CHUNK_SIZE];
+    loop {
+        let read = file.read(&mut buf)?;
+        if read == 0 {
+            break;
+        }
+        verifier.update(&buf[..read]);
+    }
+    verifier.finalize().map_err(invalid)
+}
+
+fn verify_db_sha256(compressed_path: &str, expected: &str) -> Result<()> {
+    let actual = db::file_sha256(compressed_path)?;
+    if actual != expected {
+        return Err(anyhow::anyhow!(
+            "DB checksum mismatch: expected {expected}, got {actual}"
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sha2::{Digest, Sha256};
+    use std::net::TcpListener;
+    use std::sync::Mutex;
+    use std::thread;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    static ENV_LOCK: Mutex<()> = Mutex::new(());
+
+    fn tmp_path(name: &str) -> String {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+        let mut path = std::env::temp_dir();
+        path.push(format!("spotspoof-cli-{name}-{now}.sqlite"));
+        path.to_string_lossy().to_string()
+    }
+
+    /// Answers successive connections in order. Each response is sent only
+    /// when the (lowercased) request contains the given text, else 400.
+    fn start_server(responses: Vec<(String, Vec<u8>)>) -> (String, thread::JoinHandle<()>) {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("bind");
+        let addr = listener.local_addr().expect("addr");
+        let handle = thread::spawn(move || {
+            for (expected, response) in responses {
+                let Ok((mut stream, _)) = listener.accept() else {
+                    break;
+                };
+                let mut buf = [0u8; 1024];
+                let read = stream.read(&mut buf).unwrap_or(0);
+                let request = String::from_utf8_lossy(&buf[..read]).to_lowercase();
+                if request.contains(&expected) {
+                    let _ = stream.write_all(&response);
+                } else {
+                    let _ =
+                        stream.write_all(b"HTTP/1.1 400 Bad Request\r\nContent-Length: 0\r\n\r\n");
+                }
+            }
+        });
+        (format!("http://{addr}/db.zst"), handle)
+    }
+
+    /// A compressed DB artifact, with its checksum installed as the expected
+    /// one. Returns the artifact and the files to clean up.
+    fn artifact(name: &str) -> (Vec<u8>, Vec<String>) {
+        // The test artifacts are not signed.
+        install_allow_unsigned(true);
+        let source_path = tmp_path(&format!("{name}-source"));
+        Connection::open(&source_path)
+            .and_then(|conn| conn.execute_batch("CREATE TABLE legit_domains (domain TEXT);"))
+            .expect("create source db");
+        let source = fs::read(&source_path).expect("read source db");
+        let body = zstd::stream::encode_all(source.as_slice(), 0).expect("compress");
+        let mut hasher = Sha256::new();
+        hasher.update(&body);
+        let sha_path = tmp_path(&format!("{name}-sha256"));
+        fs::write(&sha_path, format!("{:x}", hasher.finalize())).expect("write sha");
+        std::env::set_var("SPOTSPOOF_DB_SHA256_PATH", &sha_path);
+        (body, vec![source_path, sha_path])
+    }
+
+    fn not_found() -> Vec<u8> {
+        b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n".to_vec()
+    }
+
+    fn cleanup(db_path: &str, files: Vec<String>) {
+        let _ = fs::remove_file(db_path);
+        let _ = fs::remove_file(Validators::path(db_path));
+        for file in files {
+            let _ = fs::remove_file(file);
+        }
+        std::env::remove_var("SPOTSPOOF_DB_SHA256_PATH");
+    }
+
+    #[test]
+    fn download_db_writes_decompressed_file() {
+        let _guard = ENV_LOCK.lock().unwrap();
+        let db_path = tmp_path("download");
+        let (body, files) = artifact("download");
+
+        let response = [
+            format!(
+                "HTTP/1.1 200 OK\r\nETag: \"v1\"\r\nContent-Length: {}\r\n\r\n",
+                body.len()
+            )
+            .into_bytes(),
+            body,
+        ]
+        .concat();
+        let (url, server) = start_server(vec![
+            ("get /db.zst ".to_string(), response),
+            ("get /db.zst.sha256 ".to_string(), not_found()),
+        ]);
+
+        let outcome = download_db(&url, &db_path).expect("download should succeed");
+        server.join().unwrap();
+        assert_eq!(
+            outcome,
+            DownloadOutcome::Downloaded(Some("\"v1\"".to_string()))
+        );
+        let conn = db::open(&db_path, false).expect("open downloaded db");
+        db::verify_schema(&conn).expect("downloaded db keeps its tables");
+        let journal_mode: String = conn
+            .pragma_query_value(None, "journal_mode", |row| row.get(0))
+            .expect("journal mode");
+        assert_eq!(journal_mode, "wal");
+        assert!(conn.execute_batch("CREATE TABLE t (x);").is_err());
+        assert!(!std::path::Path::new(&format!("{db_path}.zst.part")).exists());
+
+        cleanup(&db_path, files);
+    }
+
+    #[test]
+    fn interrupted_downloads_resume_with_a_range_request() {
+        let _guard = ENV_LOCK.lock().unwrap();
+        let db_path = tmp_path("resume");
+        let (body, files) = artifact("resume");
+        let (head, tail) = body.split_at(body.len() / 2);
+
+        let first = [
+            format!(
+                "HTTP/1.1 200 OK\r\nETag: \"v1\"\r\nContent-Length: {}\r\n\r\n",
+                body.len()
+            )
+            .into_bytes(),
+            head.to_vec(),
+        ]
+        .concat();
+        let second = [
+            format!(
+                "HTTP/1.1 206 Partial Content\r\nContent-Range: bytes {}-{}/{}\r\nContent-Length: {}\r\n\r\n",
+                head.len(),
+                body.len() - 1,
+                body.len(),
+                tail.len()
+            )
+            .into_bytes(),
+            tail.to_vec(),
+        ]
+        .concat();
+        let range = format!("range: bytes={}-", head.len());
+        let (url, server) = start_server(vec![
+            ("get /db.zst ".to_string(), first),
+            (range, second),
+            ("get /db.zst.sha256 ".to_string(), not_found()),
+        ]);
+
+        let outcome = download_db(&url, &db_path).expect("download should resume");
+        server.join().unwrap();
+        assert_eq!(
+            outcome,
+            DownloadOutcome::Downloaded(Some("\"v1\"".to_string()))
+        );
+        db::verify_db_file(&db_path, None).expect("resumed db is intact");
+
+        cleanup(&db_path, files);
+    }
+
+    #[test]
+    fn checksum_mismatch_discards_the_partial_download() {
+        let _guard = ENV_LOCK.lock().unwrap();
+        let db_path = tmp_path("mismatch");
+        let (_, files) = artifact("mismatch");
+        let response = b"HTTP/1.1 200 OK\r\nETag: \"v1\"\r\nContent-Length: 4\r\n\r\njunk".to_vec();
+        let (url, server) = start_server(vec![
+            ("get /db.zst ".to_string(), response),
+            ("get /db.zst.sha256 ".to_string(), not_found()),
+        ]);
+
+        let err = download_db(&url, &db_path).unwrap_err();
+        server.join().unwrap();
+        assert!(err.to_string().contains("checksum mismatch"), "{err}");
+        assert!(!std::path::Path::new(&format!("{db_path}.zst.part")).exists());
+        assert!(!std::path::Path::new(&db_path).exists());
+
+        cleanup(&db_path, files);
+    }
+
+    #[test]
+    fn published_checksum_is_preferred_over_the_local_one() {
+        let _guard = ENV_LOCK.lock().unwrap();
+        let db_path = tmp_path("sidecar");
+        let (body, mut files) = artifact("sidecar");
+        let mut hasher = Sha256::new();
+        hasher.update(&body);
+        let sidecar = format!("{:x}  spotspoof.sqlite.zst\n", hasher.finalize());
+        // The local checksum no longer matches the published artifact.
+        let stale_path = tmp_path("sidecar-stale");
+        fs::write(&stale_path, "0".repeat(64)).unwrap();
+        std::env::set_var("SPOTSPOOF_DB_SHA256_PATH", &stale_path);
+        files.push(stale_path);
+
+        let response = [
+            format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n", body.len()).into_bytes(),
+            body,
+        ]
+        .concat();
+        let sidecar = format!(
+            "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{sidecar}",
+            sidecar.len()
+        )
+        .into_bytes();
+        let (url, server) = start_server(vec![
+            ("get /db.zst ".to_string(), response),
+            ("get /db.zst.sha256 ".to_string(), sidecar),
+        ]);
+        download_db(&url, &db_path).expect("published checksum matches");
+        server.join().unwrap();
+
+        let client = Client::new();
+        let explicit = ChecksumSource::new(Some("F".repeat(64)), None).unwrap();
+        assert_eq!(
+            expected_sha256(&client, &url, explicit).unwrap(),
+            "f".repeat(64)
+        );
+        assert!(ChecksumSource::new(Some("abc".to_string()), None).is_err());
+
+        cleanup(&db_path, files);
+    }
+
+    #[test]
+    fn release_downloads_require_their_checksum_and_signature() {
+        let key = parse_pubkey("RWQf6LRCGA9i53mlYecO4IzT51TGPpvWucNSCh1CBM0QTaLn73Y7GFO3").unwrap();
+        let minisig = concat!(
+            "untrusted comment: signature from minisign secret key\n",
+            "RUQf6LRCGA9i559r3g7V1qNyJDApGip8MfqcadIgT9CuhV3EMhHoN1mGTkUidF/",
+            "z7SrlQgXdy8ofjb7bNJJylDOocrCo8KLzZwo=\n",
+            "trusted comment: timestamp:1556193335\tfile:test\n",
+            "y/rUw2y8/hOUYjZU71eHp/Wo1KZ40fGy2VJEDl34XMJM+TX48Ss/17u3IvIfbVR1FkZZSNCisQbuQY+bHwhEBg==",
+        );
+        let ok = |body: &[u8]| {
+            [
+                format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n", body.len()).into_bytes(),
+                body.to_vec(),
+            ]
+            .concat()
+        };
+        let sha256 = format!("{:x}  spotspoof\n", Sha256::digest(b"test"));
+        let (url, server) = start_server(vec![
+            ("get /db.zst ".to_string(), ok(b"test")),
+            ("get /db.zst.sha256 ".to_string(), ok(sha256.as_bytes())),
+            ("get /db.zst.minisig ".to_string(), ok(minisig.as_bytes())),
+            ("get /db.zst ".to_string(), ok(b"tampered")),
+            ("get /db.zst.sha256 ".to_string(), ok(sha256.as_bytes())),
+            ("get /db.zst ".to_string(), ok(b"test")),
+            ("get /db.zst.sha256 ".to_string(), not_found()),
+        ]);
+        let dir = tmp_path("release");
+        fs::create_dir_all(&dir).unwrap();
+        let dir = Path::new(&dir);
+        let staged = download_signed(&url, dir, &key).expect("checksum and signature match");
+        assert_eq!(fs::read(staged.path()).unwrap(), b"test");
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(staged.path()).unwrap().permissions().mode();
+            assert_eq!(mode & 0o077, 0);
+        }
+        drop(staged);
+        let err = download_signed(&url, dir, &key).unwrap_err();
+        assert!(err.to_string().contains("checksum mismatch"));
+        assert!(download_signed(&url, dir, &key).is_err());
+        server.join().unwrap();
+        assert_eq!(fs::read_dir(dir).unwrap().count(), 0);
+        let _ = fs::remove_dir(dir);
+    }
+
+    #[test]
+    fn minisign_signatures_are_verified_against_the_key() {
+        let key = parse_pubkey("RWQf6LRCGA9i53mlYecO4IzT51TGPpvWucNSCh1CBM0QTaLn73Y7GFO3").unwrap();
+        let signature = Signature::decode(concat!(
+            "untrusted comment: signature from minisign secret key\n",
+            "RUQf6LRCGA9i559r3g7V1qNyJDApGip8MfqcadIgT9CuhV3EMhHoN1mGTkUidF/",
+            "z7SrlQgXdy8ofjb7bNJJylDOocrCo8KLzZwo=\n",
+            "trusted comment: timestamp:1556193335\tfile:test\n",
+            "y/rUw2y8/hOUYjZU71eHp/Wo1KZ40fGy2VJEDl34XMJM+TX48Ss/17u3IvIfbVR1FkZZSNCisQbuQY+bHwhEBg==",
+        ))
+        .unwrap();
+        let path = tmp_path("signed");
+        fs::write(&path, b"test").unwrap();
+        verify_file_signature(&path, &signature, &key, "DB").expect("signature matches");
+
+        fs::write(&path, b"tampered").unwrap();
+        let err = verify_file_signature(&path, &signature, &key, "DB").unwrap_err();
+        assert!(err.to_string().contains("signature verification failed"));
+        assert!(parse_pubkey("not a key").is_err());
+        let _ = fs::remove_file(&path);
+
+        assert!(db_signing_key(Some(&key), false).unwrap().is_some());
+        assert!(db_signing_key(None, true).unwrap().is_none());
+        let err = db_signing_key(None, false).unwrap_err();
+        assert!(err.to_string().contains("no minisign public key"));
+    }
+
+    #[test]
+    fn update_db_sends_stored_validators() {
+        let db_path = tmp_path("conditional");
+        fs::write(&db_path, b"installed").unwrap();
+        let validators = Validators {
+            etag: Some("\"v42\"".to_string()),
+            last_modified: None,
+        };
+        validators.store(&db_path).unwrap();
+        assert_eq!(Validators::load(&db_path), validators);
+
+        let (url, server) = start_server(vec![(
+            "if-none-match: \"v42\"".to_string(),
+            b"HTTP/1.1 304 Not Modified\r\nContent-Length: 0\r\n\r\n".to_vec(),
+        )]);
+        let outcome = update_db(&url, &db_path).unwrap();
+        server.join().unwrap();
+        assert_eq!(outcome, DownloadOutcome::NotModified);
+        assert_eq!(fs::read(&db_path).unwrap(), b"installed");
+
+        let _ = fs::remove_file(&db_path);
+        let _ = fs::remove_file(Validators::path(&db_path));
+    }
+
+    #[test]
+    #[ignore = "slow: downloads the release DB and requires network access"]
+    fn release_db_download_matches_sha256() {
+        let _guard = ENV_LOCK.lock().unwrap();
+        std::env::remove_var("SPOTSPOOF_DB_SHA256_PATH");
+
+        let mut response = reqwest::blocking::get(db::DEFAULT_DB_URL).expect("download release db");
+        if !response.status().is_success() {
+            panic!("Download failed: HTTP {}", response.status());
+        }
+
+        let mut hasher = Sha256::new();
+        let mut buf = [0u8; 64 * 1024];
+        let mut total = 0usize;
+        loop {
+            let read = response.read(&mut buf).expect("read response");
+            if read == 0 {
+                break;
+            }
+            total += read;
+            hasher.update(&buf[..read]);
+        }
+
+        let expected = read_expected_db_sha256().expect("read expected sha");
+        let actual = format!("{:x}", hasher.finalize());
+        assert!(total > 0, "expected response body, downloaded 0 bytes");
+        assert_eq!(actual, expected, "release DB checksum mismatch");
+    }
+}
@@ -0,0 +1,275 @@
+/*
+Phishing triage for a single email (`scan-eml`). Pulls the sender domains
+(From, Reply-To, Return-Path) and every host mentioned in the text and HTML
+bodies out of an RFC 5322 message, runs each through the scanner, and sums it
+all up as one verdict with the reasons behind it.
+*/
+use anyhow::Result;
+use mail_parser::{Address, HeaderValue, MessageParser};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::scan::{self, Observations, ScanReport, Scanner};
+use crate::types::{self, LookupResponse};
+
+/// The parts of a message that get looked up.
+#[derive(Debug, Default)]
+pub struct ParsedMessage {
+    pub subject: Option<String>,
+    pub senders: Vec<Sender>,
+    pub links: Observations,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, ToSchema)]
+pub struct Sender {
+    /// `from`, `reply-to`, or `return-path`.
+    pub header: &'static str,
+    pub address: String,
+    pub domain: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Verdict {
+    /// Nothing resembles a known domain.
+    Clean,
+    /// Replies go to a different domain than the sender's.
+    Review,
+    /// A sender or link domain resembles a known domain.
+    Suspicious,
+}
+
+impl Verdict {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Verdict::Clean => "clean",
+            Verdict::Review => "review",
+            Verdict::Suspicious => "suspicious",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct SenderCheck {
+    #[serde(flatten)]
+    pub sender: Sender,
+    /// Present when the domain resembles a known one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lookup: Option<LookupResponse>,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct EmlReport {
+    pub verdict: Verdict,
+    pub reasons: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub subject: Option<String>,
+    pub senders: Vec<SenderCheck>,
+    pub links: ScanReport,
+}
+
+/// Parses a raw message (MIME decoding included).
+pub fn parse(raw: &[u8]) -> Result<ParsedMessage> {
+    let message = MessageParser::default()
+        .parse(raw)
+        .ok_or_else(|| anyhow::anyhow!("not an email message"))?;
+    let mut parsed = ParsedMessage {
+        subject: message.subject().map(str::to_string),
+        ..ParsedMessage::default()
+    };
+    let return_path = match message.return_path() {
+        HeaderValue::Address(address) => first_address(Some(address)),
+        HeaderValue::Text(text) => Some(text.trim_matches(['<', '>', ' ']).to_string()),
+        _ => None,
+    };
+    for (header, address) in [
+        ("from", first_address(message.from())),
+        ("reply-to", first_address(message.reply_to())),
+        ("return-path", return_path),
+    ] {
+        let Some(address) = address else {
+            continue;
+        };
+        let Some((_, host)) = address.rsplit_once('@') else {
+            continue;
+        };
+        if let Ok(domain) = types::parse_domain_input(host) {
+            parsed.senders.push(Sender {
+                header,
+                address,
+                domain,
+            });
+        }
+    }
+    // HTML is scanned as markup, so hosts in href/src attributes count too.
+    for part in message.text_bodies().chain(message.html_bodies()) {
+        if let Some(text) = part.text_contents() {
+            parsed.links.add_text(text);
+        }
+    }
+    Ok(parsed)
+}
+
+fn first_address(address: Option<&Address>) -> Option<String> {
+    address?.first()?.address().map(str::to_string)
+}
+
+/// Looks up every sender and link domain of `message` and decides a verdict.
+pub fn check(message: ParsedMessage, scanner: &Scanner) -> Result<EmlReport> {
+    let mut reasons = Vec::new();
+    let mut senders = Vec::new();
+    for sender in message.senders {
+        let lookup = scanner.lookup(&sender.domain)?.filter(scan::is_lookalike);
+        if let Some(lookup) = &lookup {
+            reasons.push(format!(
+                "{} domain {} {}",
+                header_name(sender.header),
+                sender.domain,
+                scan::resemblance(lookup)
+            ));
+        }
+        senders.push(SenderCheck { sender, lookup });
+    }
+    let mut links = scanner.scan(message.links.into_vec())?;
+    // Known domains that merely look like other known domains are not news.
+    links
+        .findings
+        .retain(|finding| scan::is_lookalike(&finding.lookup));
+    for finding in &links.findings {
+        reasons.push(format!(
+            "link host {} {}",
+            finding.domain,
+            scan::resemblance(&finding.lookup)
+        ));
+    }
+    let lookalikes = !reasons.is_empty();
+
+    // Bounces routinely go to the sending service (Return-Path), so only a
+    // Reply-To elsewhere is worth a look.
+    if let Some(from) = senders.iter().find(|check| check.sender.header == "from") {
+        for check in &senders {
+            if check.sender.header == "reply-to" && check.sender.domain != from.sender.domain {
+                reasons.push(format!(
+                    "{} domain {} differs from From domain {}",
+                    header_name(check.sender.header),
+                    check.sender.domain,
+                    from.sender.domain
+                ));
+            }
+        }
+    }
+    let verdict = if lookalikes {
+        Verdict::Suspicious
+    } else if !reasons.is_empty() {
+        Verdict::Review
+    } else {
+        Verdict::Clean
+    };
+    Ok(EmlReport {
+        verdict,
+        reasons,
+        subject: message.subject,
+        senders,
+        links,
+    })
+}
+
+fn header_name(header: &str) -> &'static str {
+    match header {
+        "from" => "From",
+        "reply-to" => "Reply-To",
+        _ => "Return-Path",
+    }
+}
+
+impl EmlReport {
+    /// The highest risk among the flagged senders and links.
+    pub fn score(&self) -> u8 {
+        self.senders
+            .iter()
+            .filter_map(|check| {
+                let lookup = check.lookup.as_ref()?;
+                Some(scan::risk(&check.sender.domain, lookup))
+            })
+            .chain(self.links.findings.iter().map(|finding| finding.risk))
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ascii_spoof::AsciiOptions;
+
+    const MESSAGE: &str = "Return-Path: <bounce@mailer.example.net>\r\n\
+        From: \"PayPal Support\" <service@paypa1.com>\r\n\
+        Reply-To: help@paypa1-support.com\r\n\
+        To: victim@example.org\r\n\
+        Subject: =?UTF-8?Q?Your_account_is_limited?=\r\n\
+        MIME-Version: 1.0\r\n\
+        Content-Type: multipart/alternative; boundary=\"b1\"\r\n\
+        \r\n\
+        --b1\r\n\
+        Content-Type: text/plain; charset=utf-8\r\n\
+        Content-Transfer-Encoding: quoted-printable\r\n\
+        \r\n\
+        Verify at https://secure.paypa1.com/login or reply to this =\r\n\
+        message.\r\n\
+        --b1\r\n\
+        Content-Type: text/html; charset=utf-8\r\n\
+        Content-Transfer-Encoding: base64\r\n\
+        \r\n\
+        PGEgaHJlZj0iaHR0cHM6Ly9wYXlwYWwuY29tLnZlcmlmeS1hY2NvdW50Lm5ldC8iPlBheVBhbDwvYT4=\r\n\
+        --b1--\r\n";
+
+    #[test]
+    fn senders_and_body_links_are_extracted() {
+        let parsed = parse(MESSAGE.as_bytes()).unwrap();
+        assert_eq!(parsed.subject.as_deref(), Some("Your account is limited"));
+        let senders: Vec<(&str, &str)> = parsed
+            .senders
+            .iter()
+            .map(|sender| (sender.header, sender.domain.as_str()))
+            .collect();
+        assert_eq!(
+            senders,
+            [
+                ("from", "paypa1.com"),
+                ("reply-to", "paypa1-support.com"),
+                ("return-path", "example.net"),
+            ]
+        );
+        let links: Vec<String> = parsed
+            .links
+            .into_vec()
+            .into_iter()
+            .map(|observation| observation.domain)
+            .collect();
+        assert_eq!(links, ["paypa1.com", "verify-account.net"]);
+    }
+
+    #[test]
+    fn verdicts_follow_lookalikes_then_mismatched_senders() {
+        let scanner = Scanner::new(None, AsciiOptions::default(), None)
+            .with_targets(vec!["paypal.com".to_string()]);
+        let report = check(parse(MESSAGE.as_bytes()).unwrap(), &scanner).unwrap();
+        assert_eq!(report.verdict, Verdict::Suspicious);
+        assert!(report.senders[0].lookup.is_some());
+        assert_eq!(report.links.findings.len(), 1);
+        assert!(report.reasons[0].starts_with("From domain paypa1.com resembles paypal.com"));
+
+        let plain = "From: a@example.org\r\nReply-To: b@example.com\r\n\r\nHello\r\n";
+        let report = check(parse(plain.as_bytes()).unwrap(), &scanner).unwrap();
+        assert_eq!(report.verdict, Verdict::Review);
+        assert_eq!(
+            report.reasons,
+            ["Reply-To domain example.com differs from From domain example.org"]
+        );
+
+        let plain = "Return-Path: <bounce@esp.example.net>\r\n\
+            From: a@example.org\r\n\r\nSee https://example.org/\r\n";
+        let report = check(parse(plain.as_bytes()).unwrap(), &scanner).unwrap();
+        assert_eq!(report.verdict, Verdict::Clean);
+    }
+}
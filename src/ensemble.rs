@@ -0,0 +1,341 @@
+/*
+Ensemble scoring. An ASCII similarity is the weighted mean of several scorers,
+each 0-100: plain edit distance, edit distance after folding ASCII lookalikes
+(`rn`/`m`, `0`/`o`, or the pairs of an installed homoglyph weight matrix),
+phonetic similarity, shared character bigrams, and edit distance with typos on
+neighbouring keys counted as half an edit. The weights
+come from the `[scoring]` section of the config file; the defaults keep edit
+distance alone, so results only change once other scorers are weighted in.
+*/
+use serde::Deserialize;
+use std::sync::OnceLock;
+
+use crate::ascii_spoof::similarity_with;
+use crate::candidates;
+use crate::homoglyphs;
+use crate::phonetic;
+use crate::types::{Algorithm, ScoreComponent};
+
+/// QWERTY rows and how far each is shifted right, in keys.
+const KEYBOARD: [(&str, f32); 4] = [
+    ("1234567890", 0.0),
+    ("qwertyuiop", 0.5),
+    ("asdfghjkl", 0.75),
+    ("zxcvbnm", 1.25),
+];
+
+static INSTALLED: OnceLock<Weights> = OnceLock::new();
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scorer {
+    EditDistance,
+    Confusable,
+    Phonetic,
+    Ngram,
+    Keyboard,
+}
+
+pub const SCORERS: [Scorer; 5] = [
+    Scorer::EditDistance,
+    Scorer::Confusable,
+    Scorer::Phonetic,
+    Scorer::Ngram,
+    Scorer::Keyboard,
+];
+
+impl Scorer {
+    /// The scorer's key in `[scoring]` and in breakdowns.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Scorer::EditDistance => "edit_distance",
+            Scorer::Confusable => "confusable",
+            Scorer::Phonetic => "phonetic",
+            Scorer::Ngram => "ngram",
+            Scorer::Keyboard => "keyboard",
+        }
+    }
+
+    /// How similar `a` and `b` are (0-100) by this scorer alone.
+    pub fn similarity(self, algorithm: Algorithm, a: &str, b: &str) -> u8 {
+        match self {
+            Scorer::EditDistance => similarity_with(algorithm, a, b),
+            Scorer::Confusable => match homoglyphs::installed() {
+                Some(weights) => weights.similarity(a, b),
+                None => similarity_with(
+                    algorithm,
+                    &candidates::skeleton(a),
+                    &candidates::skeleton(b),
+                ),
+            },
+            Scorer::Phonetic => phonetic::similarity(a, b),
+            Scorer::Ngram => bigram_similarity(a, b),
+            Scorer::Keyboard => keyboard_similarity(a, b),
+        }
+    }
+}
+
+/// Relative scorer weights (`[scoring]`); a scorer weighted 0 is skipped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Weights {
+    pub edit_distance: u16,
+    pub confusable: u16,
+    pub phonetic: u16,
+    pub ngram: u16,
+    pub keyboard: u16,
+}
+
+impl Default for Weights {
+    fn default() -> Self {
+        Self {
+            edit_distance: 1,
+            confusable: 0,
+            phonetic: 0,
+            ngram: 0,
+            keyboard: 0,
+        }
+    }
+}
+
+impl Weights {
+    pub fn weight(&self, scorer: Scorer) -> u16 {
+        match scorer {
+            Scorer::EditDistance => self.edit_distance,
+            Scorer::Confusable => self.confusable,
+            Scorer::Phonetic => self.phonetic,
+            Scorer::Ngram => self.ngram,
+            Scorer::Keyboard => self.keyboard,
+        }
+    }
+
+    fn total(&self) -> u32 {
+        SCORERS
+            .iter()
+            .map(|scorer| u32::from(self.weight(*scorer)))
+            .sum()
+    }
+
+    /// An error when no scorer has a weight.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.total() == 0 {
+            anyhow::bail!("scoring weights are all 0; give at least one scorer a weight");
+        }
+        Ok(())
+    }
+
+    /// The weighted mean similarity of `a` and `b`, given their already
+    /// computed edit-distance similarity `edit`.
+    pub fn combine(&self, algorithm: Algorithm, edit: u8, a: &str, b: &str) -> u8 {
+        if self.total() == u32::from(self.edit_distance) {
+            return edit;
+        }
+        let weighted: u32 = SCORERS
+            .iter()
+            .filter(|scorer| self.weight(**scorer) > 0)
+            .map(|scorer| {
+                let similarity = match scorer {
+                    Scorer::EditDistance => edit,
+                    _ => scorer.similarity(algorithm, a, b),
+                };
+                u32::from(self.weight(*scorer)) * u32::from(similarity)
+            })
+            .sum();
+        mean(weighted, self.total())
+    }
+
+    /// Every scorer's similarity of `a` and `b` with its weight, for
+    /// `--explain`.
+    pub fn breakdown(&self, algorithm: Algorithm, a: &str, b: &str) -> Vec<ScoreComponent> {
+        SCORERS
+            .iter()
+            .map(|scorer| ScoreComponent {
+                scorer: scorer.as_str().to_string(),
+                weight: self.weight(*scorer),
+                similarity: scorer.similarity(algorithm, a, b),
+            })
+            .collect()
+    }
+
+    /// The weighted mean of a breakdown.
+    pub fn mean_of(breakdown: &[ScoreComponent]) -> u8 {
+        let weighted = breakdown
+            .iter()
+            .map(|part| u32::from(part.weight) * u32::from(part.similarity))
+            .sum();
+        let total = breakdown.iter().map(|part| u32::from(part.weight)).sum();
+        mean(weighted, total)
+    }
+
+    /// The lowest edit-distance similarity that can still combine to `min`,
+    /// with every other scorer at 100. One point lower absorbs rounding.
+    pub fn edit_floor(&self, min: u8) -> u8 {
+        let total = self.total();
+        let edit = u32::from(self.edit_distance);
+        if edit == 0 {
+            return 0;
+        }
+        let needed = (u32::from(min) * total).saturating_sub((total - edit) * 100);
+        let floor = needed.div_ceil(edit).min(100) as u8;
+        if edit == total {
+            floor
+        } else {
+            floor.saturating_sub(1)
+        }
+    }
+}
+
+fn mean(weighted: u32, total: u32) -> u8 {
+    if total == 0 {
+        return 0;
+    }
+    ((weighted + total / 2) / total).min(100) as u8
+}
+
+/// Makes `weights` the process-wide scorer weights. Only the first call takes
+/// effect.
+pub fn install(weights: Weights) {
+    let _ = INSTALLED.set(weights);
+}
+
+/// The installed weights, or the defaults.
+pub fn installed() -> &'static Weights {
+    INSTALLED.get_or_init(Weights::default)
+}
+
+/// Dice coefficient of the character bigrams of `a` and `b`, as a percentage.
+fn bigram_similarity(a: &str, b: &str) -> u8 {
+    if a == b {
+        return 100;
+    }
+    let bigrams = |text: &str| {
+        let chars: Vec<char> = text.chars().collect();
+        chars
+            .windows(2)
+            .map(|pair| (pair[0], pair[1]))
+            .collect::<Vec<_>>()
+    };
+    let (left, mut right) = (bigrams(a), bigrams(b));
+    let total = left.len() + right.len();
+    if total == 0 {
+        return 0;
+    }
+    let mut shared = 0;
+    for bigram in &left {
+        if let Some(index) = right.iter().position(|other| other == bigram) {
+            right.swap_remove(index);
+            shared += 1;
+        }
+    }
+    (200 * shared / total) as u8
+}
+
+/// Levenshtein similarity with substitutions of neighbouring keys costing
+/// half an edit, so fat-finger typos (`goofle`) score above arbitrary ones.
+fn keyboard_similarity(a: &str, b: &str) -> u8 {
+    let (a, b): (Vec<char>, Vec<char>) = (a.chars().collect(), b.chars().collect());
+    let longest = a.len().max(b.len());
+    if longest == 0 {
+        return 100;
+    }
+    let mut prev: Vec<f32> = (0..=b.len()).map(|j| j as f32).collect();
+    let mut curr = vec![0.0; b.len() + 1];
+    for i in 1..=a.len() {
+        curr[0] = i as f32;
+        for j in 1..=b.len() {
+            let cost = if a[i - 1] == b[j - 1] {
+                0.0
+            } else if adjacent_keys(a[i - 1], b[j - 1]) {
+                0.5
+            } else {
+                1.0
+            };
+            curr[j] = (prev[j] + 1.0)
+                .min(curr[j - 1] + 1.0)
+                .min(prev[j - 1] + cost);
+        }
+        std::mem::swap(&mut prev, &mut curr);
+    }
+    let ratio = 1.0 - prev[b.len()] / longest as f32;
+    (100.0 * ratio).round().max(0.0) as u8
+}
+
+fn adjacent_keys(a: char, b: char) -> bool {
+    let position = |c: char| {
+        KEYBOARD
+            .iter()
+            .enumerate()
+            .find_map(|(row, (keys, shift))| {
+                keys.find(c.to_ascii_lowercase())
+                    .map(|column| (row as f32, column as f32 + shift))
+            })
+    };
+    match (position(a), position(b)) {
+        (Some((row_a, x_a)), Some((row_b, x_b))) => {
+            (row_a - row_b).abs() <= 1.0 && (x_a - x_b).abs() <= 1.0 && a != b
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scorers_rate_their_own_kind_of_lookalike_highest() {
+        let score = |scorer: Scorer, a, b| scorer.similarity(Algorithm::Levenshtein, a, b);
+        assert_eq!(
+            score(Scorer::EditDistance, "rnicrosoft.com", "microsoft.com"),
+            86
+        );
+        assert_eq!(
+            score(Scorer::Confusable, "rnicrosoft.com", "microsoft.com"),
+            100
+        );
+        assert_eq!(score(Scorer::Phonetic, "gugel.com", "google.com"), 100);
+        assert_eq!(score(Scorer::Ngram, "example.com", "example.com"), 100);
+        assert!(score(Scorer::Ngram, "examlpe.com", "example.com") > 60);
+        assert_eq!(score(Scorer::Keyboard, "goofle.com", "google.com"), 95);
+        assert_eq!(score(Scorer::Keyboard, "goozle.com", "google.com"), 90);
+        assert!(adjacent_keys('q', 'a') && adjacent_keys('g', 'f') && !adjacent_keys('q', 's'));
+    }
+
+    #[test]
+    fn weights_combine_into_a_mean_and_bound_the_edit_score() {
+        let edit_only = Weights::default();
+        assert_eq!(
+            edit_only.combine(Algorithm::Levenshtein, 60, "gugel.com", "google.com"),
+            60
+        );
+        assert_eq!(edit_only.edit_floor(80), 80);
+
+        let sound = Weights {
+            phonetic: 1,
+            ..Weights::default()
+        };
+        assert_eq!(
+            sound.combine(Algorithm::Levenshtein, 60, "gugel.com", "google.com"),
+            80
+        );
+        assert_eq!(sound.edit_floor(80), 59);
+        let breakdown = sound.breakdown(Algorithm::Levenshtein, "gugel.com", "google.com");
+        assert_eq!(breakdown.len(), SCORERS.len());
+        assert_eq!(breakdown[2].scorer, "phonetic");
+        assert_eq!(Weights::mean_of(&breakdown), 80);
+
+        let none = Weights {
+            edit_distance: 0,
+            ..Weights::default()
+        };
+        assert!(none.validate().is_err());
+        assert_eq!(
+            Weights {
+                edit_distance: 0,
+                ngram: 1,
+                ..Weights::default()
+            }
+            .edit_floor(80),
+            0
+        );
+    }
+}
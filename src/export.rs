@@ -0,0 +1,420 @@
+/*
+Blocking exports for `export-rpz` and `export-blocklist`. Every protected
+domain is expanded into its likely squats (see `permute`), minus the protected
+domains themselves and any legitimate domain in the dataset; blocklists can
+instead take the findings of a `scan-*` report. Zones are written as a DNS
+Response Policy Zone that BIND or Unbound can load, with the SOA serial bumped
+on every rewrite so secondaries pick up the change; blocklists in the hosts,
+Pi-hole, or AdGuard format.
+*/
+use anyhow::Result;
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::fmt::Write as _;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::warn;
+
+use crate::permute::{self, Permutation, Technique};
+use crate::types::RegistrationStatus;
+use crate::{bench, types};
+
+/// A likely squat of a protected domain.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Squat {
+    pub target: String,
+    pub permutation: Permutation,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum TargetEntry {
+    Domain(String),
+    Object { domain: String },
+}
+
+/// Reads protected domains from a JSON array (of domains or `{"domain": ...}`
+/// objects), else one domain per line.
+pub fn read_targets(path: &str) -> Result<Vec<String>> {
+    let contents = std::fs::read_to_string(path)?;
+    let domains = match serde_json::from_str::<Vec<TargetEntry>>(&contents) {
+        Ok(entries) => entries
+            .into_iter()
+            .map(|entry| match entry {
+                TargetEntry::Domain(domain) | TargetEntry::Object { domain } => domain,
+            })
+            .collect(),
+        Err(_) => bench::read_queries(path)?,
+    };
+    let mut seen = HashSet::new();
+    let mut targets = Vec::new();
+    for domain in domains {
+        let domain = types::parse_domain_input(&domain)?;
+        if seen.insert(domain.clone()) {
+            targets.push(domain);
+        }
+    }
+    if targets.is_empty() {
+        return Err(anyhow::anyhow!("no domains found in {path}"));
+    }
+    Ok(targets)
+}
+
+/// The permutations of every target, skipping the targets themselves, repeats,
+/// and domains `is_legit` vouches for.
+pub fn squats(
+    targets: &[String],
+    techniques: &[Technique],
+    mut is_legit: impl FnMut(&str) -> Result<bool>,
+) -> Result<Vec<Squat>> {
+    let mut seen: HashSet<String> = targets.iter().cloned().collect();
+    let mut out = Vec::new();
+    for target in targets {
+        for permutation in permute::permutations(target, techniques) {
+            if seen.contains(&permutation.domain) || is_legit(&permutation.domain)? {
+                continue;
+            }
+            seen.insert(permutation.domain.clone());
+            out.push(Squat {
+                target: target.clone(),
+                permutation,
+            });
+        }
+    }
+    Ok(out)
+}
+
+/// What resolvers answer for a listed name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum RpzAction {
+    /// NXDOMAIN (`CNAME .`)
+    Nxdomain,
+    /// An empty answer (`CNAME *.`)
+    Nodata,
+    /// No answer at all (`CNAME rpz-drop.`)
+    Drop,
+}
+
+impl RpzAction {
+    fn rdata(self) -> &'static str {
+        match self {
+            RpzAction::Nxdomain => ".",
+            RpzAction::Nodata => "*.",
+            RpzAction::Drop => "rpz-drop.",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct RpzOptions {
+    pub ttl: u32,
+    pub action: RpzAction,
+    pub serial: u64,
+}
+
+/// Renders a zone blocking every squat and its subdomains, grouped by target.
+pub fn render_rpz(squats: &[Squat], options: RpzOptions) -> String {
+    let targets: HashSet<&str> = squats.iter().map(|squat| squat.target.as_str()).collect();
+    let mut zone = String::new();
+    let _ = writeln!(
+        zone,
+        "; spotspoof response policy zone: {} lookalikes of {} domains",
+        squats.len(),
+        targets.len()
+    );
+    let _ = writeln!(zone, "$TTL {}", options.ttl);
+    let _ = writeln!(
+        zone,
+        "@ IN SOA localhost. hostmaster.localhost. {} 3600 600 604800 {}",
+        options.serial, options.ttl
+    );
+    let _ = writeln!(zone, "@ IN NS localhost.");
+    let rdata = options.action.rdata();
+    let mut current = None;
+    for squat in squats {
+        if current != Some(squat.target.as_str()) {
+            current = Some(squat.target.as_str());
+            let _ = writeln!(zone, "\n; {}", squat.target);
+        }
+        let domain = &squat.permutation.domain;
+        let _ = writeln!(zone, "{domain} CNAME {rdata}");
+        let _ = writeln!(zone, "*.{domain} CNAME {rdata}");
+    }
+    zone
+}
+
+#[derive(Deserialize)]
+struct FindingsReport {
+    findings: Vec<FindingEntry>,
+}
+
+#[derive(Deserialize)]
+struct FindingEntry {
+    domain: String,
+}
+
+/// Reads the finding domains (in ASCII form) from a `scan-*` JSON report.
+pub fn read_findings(path: &str) -> Result<Vec<String>> {
+    let report: FindingsReport = serde_json::from_str(&std::fs::read_to_string(path)?)
+        .map_err(|err| anyhow::anyhow!("{path} is not a scan report: {err}"))?;
+    let mut seen = HashSet::new();
+    let mut domains = Vec::new();
+    for finding in report.findings {
+        let domain = idna::domain_to_ascii(&types::parse_domain_input(&finding.domain)?)
+            .map_err(|err| anyhow::anyhow!("invalid domain '{}': {err}", finding.domain))?;
+        if seen.insert(domain.clone()) {
+            domains.push(domain);
+        }
+    }
+    Ok(domains)
+}
+
+/// Keeps the domains `check` finds registered, checking on `workers` threads.
+/// Domains whose status is unknown are dropped.
+pub fn only_registered(
+    domains: Vec<String>,
+    workers: usize,
+    check: impl Fn(&str) -> Result<RegistrationStatus> + Sync,
+) -> Result<Vec<String>> {
+    let chunk = domains.len().div_ceil(workers.max(1)).max(1);
+    let statuses = std::thread::scope(|scope| {
+        let handles: Vec<_> = domains
+            .chunks(chunk)
+            .map(|chunk| {
+                let check = &check;
+                scope.spawn(move || {
+                    chunk
+                        .iter()
+                        .map(|domain| check(domain))
+                        .collect::<Result<Vec<_>>>()
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .map(|handle| handle.join().expect("registration check thread panicked"))
+            .collect::<Result<Vec<_>>>()
+    })?;
+    let statuses = statuses.into_iter().flatten();
+    let mut unknown = 0;
+    let mut registered = Vec::new();
+    for (domain, status) in domains.into_iter().zip(statuses) {
+        match status {
+            RegistrationStatus::Registered => registered.push(domain),
+            RegistrationStatus::Unknown => unknown += 1,
+            RegistrationStatus::Unregistered => {}
+        }
+    }
+    if unknown > 0 {
+        warn!(
+            kind = "export",
+            unknown, "dropped domains whose registration could not be checked"
+        );
+    }
+    Ok(registered)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum BlocklistFormat {
+    /// `0.0.0.0 domain` lines for /etc/hosts and hosts-file blockers
+    Hosts,
+    /// One domain per line, for Pi-hole adlists
+    Pihole,
+    /// `||domain^` rules (subdomains included) for AdGuard and AdGuard Home
+    Adguard,
+}
+
+/// Renders `domains` as a blocklist with a comment header.
+pub fn render_blocklist(domains: &[String], format: BlocklistFormat) -> String {
+    let comment = match format {
+        BlocklistFormat::Adguard => '!',
+        BlocklistFormat::Hosts | BlocklistFormat::Pihole => '#',
+    };
+    let mut list = String::new();
+    let _ = writeln!(
+        list,
+        "{comment} spotspoof blocklist: {} lookalike domains",
+        domains.len()
+    );
+    for domain in domains {
+        let _ = match format {
+            BlocklistFormat::Hosts => writeln!(list, "0.0.0.0 {domain}"),
+            BlocklistFormat::Pihole => writeln!(list, "{domain}"),
+            BlocklistFormat::Adguard => writeln!(list, "||{domain}^"),
+        };
+    }
+    list
+}
+
+/// The SOA serial of a zone file, if it has one on the SOA line.
+pub fn zone_serial(zone: &str) -> Option<u64> {
+    zone.lines().find_map(|line| {
+        let line = line.split(';').next().unwrap_or_default();
+        let mut tokens = line
+            .split_whitespace()
+            .filter(|token| *token != "(")
+            .skip_while(|token| !token.eq_ignore_ascii_case("SOA"));
+        tokens.next()?;
+        tokens.nth(2)?.parse().ok()
+    })
+}
+
+/// A `YYYYMMDDnn` serial for `today` (`YYYYMMDD`), above `previous`.
+pub fn next_serial(previous: Option<u64>, today: u64) -> u64 {
+    let first = today * 100;
+    match previous {
+        Some(previous) if previous >= first => previous + 1,
+        _ => first,
+    }
+}
+
+/// Today's UTC date as `YYYYMMDD`.
+pub fn today() -> u64 {
+    let days = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+        / 86_400;
+    let (year, month, day) = civil_from_days(days as i64);
+    year as u64 * 10_000 + month as u64 * 100 + day as u64
+}
+
+/// Converts days since 1970-01-01 to a proleptic Gregorian date (Howard
+/// Hinnant's algorithm).
+pub(crate) fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// Writes `contents` to `path` through a temporary file, so a resolver
+/// reloading the zone never sees half of it.
+pub fn write_atomic(path: &str, contents: &str) -> Result<()> {
+    let tmp_path = format!("{path}.tmp");
+    std::fs::write(&tmp_path, contents)?;
+    std::fs::rename(&tmp_path, path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn targets_are_read_from_json_or_lines() {
+        let dir = std::env::temp_dir().join(format!("spotspoof-export-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let json = dir.join("targets.json");
+        std::fs::write(
+            &json,
+            r#"["PayPal.com", {"domain": "https://www.apple.com/"}, "paypal.com"]"#,
+        )
+        .unwrap();
+        assert_eq!(
+            read_targets(json.to_str().unwrap()).unwrap(),
+            ["paypal.com", "apple.com"]
+        );
+        let text = dir.join("targets.txt");
+        std::fs::write(&text, "# brands\npaypal.com\n\nstripe.com\n").unwrap();
+        assert_eq!(
+            read_targets(text.to_str().unwrap()).unwrap(),
+            ["paypal.com", "stripe.com"]
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn zone_lists_squats_and_their_subdomains() {
+        let targets = vec!["paypal.com".to_string()];
+        let squats = squats(&targets, &[Technique::Omission], |domain| {
+            Ok(domain == "paypl.com")
+        })
+        .unwrap();
+        let domains: Vec<&str> = squats
+            .iter()
+            .map(|squat| squat.permutation.domain.as_str())
+            .collect();
+        assert_eq!(
+            domains,
+            [
+                "aypal.com",
+                "pypal.com",
+                "papal.com",
+                "payal.com",
+                "paypa.com"
+            ]
+        );
+
+        let zone = render_rpz(
+            &squats,
+            RpzOptions {
+                ttl: 300,
+                action: RpzAction::Nxdomain,
+                serial: 2026101700,
+            },
+        );
+        assert!(zone.contains("$TTL 300\n"));
+        assert!(zone.contains("\n; paypal.com\naypal.com CNAME .\n*.aypal.com CNAME .\n"));
+        assert!(!zone.contains("paypl.com"));
+        assert_eq!(zone_serial(&zone), Some(2026101700));
+    }
+
+    #[test]
+    fn blocklists_keep_registered_findings_in_each_format() {
+        let path =
+            std::env::temp_dir().join(format!("spotspoof-findings-{}.json", std::process::id()));
+        std::fs::write(
+            &path,
+            r#"{"scanned": 9, "findings": [
+                {"domain": "paypa1.com", "occurrences": 2, "lookup": {}},
+                {"domain": "аpple.com", "occurrences": 1, "lookup": {}},
+                {"domain": "paypa1.com", "occurrences": 1, "lookup": {}},
+                {"domain": "paypai.com", "occurrences": 1, "lookup": {}}
+            ]}"#,
+        )
+        .unwrap();
+        let findings = read_findings(path.to_str().unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(findings, ["paypa1.com", "xn--pple-43d.com", "paypai.com"]);
+
+        let registered = only_registered(findings, 2, |domain| {
+            Ok(match domain {
+                "paypa1.com" => RegistrationStatus::Registered,
+                "paypai.com" => RegistrationStatus::Unknown,
+                _ => RegistrationStatus::Unregistered,
+            })
+        })
+        .unwrap();
+        assert_eq!(registered, ["paypa1.com"]);
+
+        assert_eq!(
+            render_blocklist(&registered, BlocklistFormat::Hosts),
+            "# spotspoof blocklist: 1 lookalike domains\n0.0.0.0 paypa1.com\n"
+        );
+        assert!(render_blocklist(&registered, BlocklistFormat::Pihole).ends_with("\npaypa1.com\n"));
+        assert_eq!(
+            render_blocklist(&registered, BlocklistFormat::Adguard),
+            "! spotspoof blocklist: 1 lookalike domains\n||paypa1.com^\n"
+        );
+    }
+
+    #[test]
+    fn serials_increase_within_and_across_days() {
+        assert_eq!(next_serial(None, 20261017), 2026101700);
+        assert_eq!(next_serial(Some(2026101700), 20261017), 2026101701);
+        assert_eq!(next_serial(Some(2026101699), 20261018), 2026101800);
+        assert_eq!(
+            zone_serial("@ IN SOA ns. host. ( 2026010203 3600 600 86400 60 )"),
+            Some(2026010203)
+        );
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(civil_from_days(20_743), (2026, 10, 17));
+        assert_eq!(civil_from_days(11_016), (2000, 2, 29));
+    }
+}
@@ -0,0 +1,105 @@
+/*
+C ABI for embedding the detection engine in-process (the `ffi` feature). Built
+as a cdylib with `cargo rustc --release --lib --features ffi --crate-type
+cdylib`; the declarations are generated into `include/spotspoof.h`. A lookup
+takes a NUL-terminated domain and returns the same JSON as `spotspoof lookup`,
+or `{"error": ...}`, as a string the caller releases with
+`spotspoof_string_free`. ASCII lookups use the DB named by `SPOTSPOOF_DB` and
+find nothing without it; IDN lookups check registration over DoH like the CLI.
+*/
+use anyhow::Result;
+use std::ffi::{c_char, CStr, CString};
+use std::panic::{self, AssertUnwindSafe};
+
+use crate::ascii_spoof::{self, AsciiOptions};
+use crate::idn;
+use crate::types::{self, LookupResponse};
+
+/// The environment variable naming the SQLite DB for ASCII lookups.
+pub const DB_ENV: &str = "SPOTSPOOF_DB";
+
+/// Looks up `domain` (ASCII or IDN, auto-detected) and returns the response as
+/// JSON. Returns NULL only when `domain` is NULL. Free the result with
+/// `spotspoof_string_free`.
+///
+/// # Safety
+///
+/// `domain` must be NULL or point to a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn spotspoof_lookup_json(domain: *const c_char) -> *mut c_char {
+    if domain.is_null() {
+        return std::ptr::null_mut();
+    }
+    let domain = CStr::from_ptr(domain).to_string_lossy().into_owned();
+    let json = match panic::catch_unwind(AssertUnwindSafe(|| lookup_json(&domain))) {
+        Ok(Ok(json)) => json,
+        Ok(Err(err)) => error_json(&err.to_string()),
+        Err(_) => error_json("lookup panicked"),
+    };
+    // serde_json escapes control characters, so the JSON holds no NUL.
+    CString::new(json).map_or(std::ptr::null_mut(), CString::into_raw)
+}
+
+/// Releases a string returned by this library. NULL is ignored.
+///
+/// # Safety
+///
+/// `value` must be NULL or a string returned by this library that has not
+/// been freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn spotspoof_string_free(value: *mut c_char) {
+    if !value.is_null() {
+        drop(CString::from_raw(value));
+    }
+}
+
+fn lookup_json(domain: &str) -> Result<String> {
+    let domain = types::parse_domain_input(domain)?;
+    let response = if types::is_idn(&domain) {
+        LookupResponse::Idn(idn::lookup_idn(&domain)?)
+    } else {
+        LookupResponse::Ascii(match std::env::var(DB_ENV) {
+            Ok(db_path) => {
+                ascii_spoof::lookup_ascii_with_options(&domain, &db_path, &AsciiOptions::default())?
+            }
+            Err(_) => types::empty_ascii_response(&domain),
+        })
+    };
+    Ok(serde_json::to_string(&response)?)
+}
+
+fn error_json(message: &str) -> String {
+    serde_json::json!({ "error": message }).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lookup(domain: &CStr) -> serde_json::Value {
+        unsafe {
+            let json = spotspoof_lookup_json(domain.as_ptr());
+            let value = serde_json::from_str(CStr::from_ptr(json).to_str().unwrap()).unwrap();
+            spotspoof_string_free(json);
+            value
+        }
+    }
+
+    #[test]
+    fn lookups_return_json_or_an_error_object() {
+        std::env::remove_var(DB_ENV);
+        let response = lookup(c"WWW.Example.com");
+        assert_eq!(response["q"], "example.com");
+        assert_eq!(response["results"], serde_json::json!([]));
+
+        assert!(lookup(c"not a domain")["error"]
+            .as_str()
+            .unwrap()
+            .starts_with("invalid domain"));
+
+        unsafe {
+            assert!(spotspoof_lookup_json(std::ptr::null()).is_null());
+            spotspoof_string_free(std::ptr::null_mut());
+        }
+    }
+}
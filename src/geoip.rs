@@ -0,0 +1,438 @@
+/*
+GeoIP and ASN enrichment for `--resolve`. The addresses a finding resolves to
+are looked up in local MaxMind DB files (GeoLite2/GeoIP2 Country, City, ASN,
+or ISP; `[geoip]` in the config file, else the GeoLite2 files geoipupdate
+installs), so a report tells a lookalike on a bulletproof VPS from one parked
+at a registrar. The MMDB format (a binary search tree over address bits and a
+self-describing data section) is read here directly; every file is loaded
+into memory once, on first use.
+*/
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::net::{IpAddr, Ipv4Addr};
+use std::sync::OnceLock;
+use tracing::info;
+use utoipa::ToSchema;
+
+/// Where geoipupdate puts its files on Linux distributions and Homebrew.
+const DEFAULT_DIRS: [&str; 4] = [
+    "/usr/share/GeoIP",
+    "/var/lib/GeoIP",
+    "/usr/local/share/GeoIP",
+    "/opt/homebrew/var/GeoIP",
+];
+const DEFAULT_FILES: [&str; 3] = [
+    "GeoLite2-City.mmdb",
+    "GeoLite2-Country.mmdb",
+    "GeoLite2-ASN.mmdb",
+];
+const METADATA_MARKER: &[u8] = b"\xab\xcd\xefMaxMind.com";
+/// Zero bytes between the search tree and the data section.
+const DATA_SECTION_SEPARATOR: usize = 16;
+/// Nesting deeper than this is a corrupt (or hostile) file.
+const MAX_DEPTH: usize = 32;
+
+static CONFIGURED: OnceLock<Vec<String>> = OnceLock::new();
+static LOADED: OnceLock<Vec<Mmdb>> = OnceLock::new();
+
+/// The `[geoip]` section of the config file.
+#[derive(Debug, Clone, Default, Deserialize, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct GeoIpConfig {
+    /// MMDB files, searched in order; the first with a field wins. Default:
+    /// the GeoLite2 City, Country, and ASN files geoipupdate installs, where
+    /// present.
+    pub databases: Vec<String>,
+}
+
+/// One address a finding resolved to, and where it is hosted.
+#[derive(Debug, Clone, Default, Serialize, ToSchema, PartialEq, Eq)]
+pub struct Address {
+    pub ip: String,
+    /// ISO 3166-1 code of the country the address is located in.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub country: Option<String>,
+    /// The autonomous system announcing the address.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub asn: Option<u32>,
+    /// The organization behind `asn`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub org: Option<String>,
+}
+
+/// Records the MMDB files `--resolve` reads.
+pub fn configure(config: &GeoIpConfig) {
+    let _ = CONFIGURED.set(config.databases.clone());
+}
+
+/// Opens the configured MMDB files (once) and returns how many there are. An
+/// explicitly configured file that cannot be read is an error; missing
+/// default files are skipped.
+pub fn load() -> Result<usize> {
+    if let Some(loaded) = LOADED.get() {
+        return Ok(loaded.len());
+    }
+    let configured = CONFIGURED.get().cloned().unwrap_or_default();
+    let paths = if configured.is_empty() {
+        DEFAULT_DIRS
+            .iter()
+            .flat_map(|dir| {
+                DEFAULT_FILES
+                    .iter()
+                    .map(move |file| format!("{dir}/{file}"))
+            })
+            .filter(|path| std::path::Path::new(path).is_file())
+            .collect()
+    } else {
+        configured
+    };
+    let mut databases = Vec::new();
+    for path in &paths {
+        let bytes =
+            std::fs::read(path).map_err(|err| anyhow::anyhow!("cannot read {path}: {err}"))?;
+        let mmdb = Mmdb::new(bytes).map_err(|err| anyhow::anyhow!("{path}: {err}"))?;
+        info!(kind = "geoip", path = %path, database_type = %mmdb.database_type);
+        databases.push(mmdb);
+    }
+    Ok(LOADED.get_or_init(|| databases).len())
+}
+
+/// `ip` with the country and AS the loaded MMDB files know for it.
+pub fn describe(ip: Ipv4Addr) -> Address {
+    let mut address = Address {
+        ip: ip.to_string(),
+        ..Address::default()
+    };
+    for mmdb in LOADED.get().into_iter().flatten() {
+        let Some(record) = mmdb.lookup(IpAddr::V4(ip)) else {
+            continue;
+        };
+        let text = |value: &Value| value.as_str().map(str::to_string);
+        if address.country.is_none() {
+            address.country = text(&record["country"]["iso_code"])
+                .or_else(|| text(&record["registered_country"]["iso_code"]));
+        }
+        if address.asn.is_none() {
+            address.asn = record["autonomous_system_number"]
+                .as_u64()
+                .and_then(|asn| u32::try_from(asn).ok());
+        }
+        if address.org.is_none() {
+            address.org = text(&record["autonomous_system_organization"])
+                .or_else(|| text(&record["isp"]))
+                .or_else(|| text(&record["organization"]));
+        }
+    }
+    address
+}
+
+/// A MaxMind DB file held in memory.
+struct Mmdb {
+    bytes: Vec<u8>,
+    node_count: usize,
+    record_size: usize,
+    ip_version: u64,
+    database_type: String,
+    data_start: usize,
+    /// Where IPv4 lookups start in an IPv6 tree (`::a.b.c.d`).
+    ipv4_start: usize,
+}
+
+impl Mmdb {
+    fn new(bytes: Vec<u8>) -> Result<Self> {
+        let marker = bytes
+            .windows(METADATA_MARKER.len())
+            .rposition(|window| window == METADATA_MARKER)
+            .ok_or_else(|| anyhow::anyhow!("not a MaxMind DB file"))?;
+        let metadata_start = marker + METADATA_MARKER.len();
+        let (metadata, _) = Decoder {
+            bytes: &bytes[metadata_start..],
+        }
+        .decode(0, 0)?;
+        let field = |name: &str| {
+            metadata[name]
+                .as_u64()
+                .ok_or_else(|| anyhow::anyhow!("MaxMind DB metadata lacks {name}"))
+        };
+        let node_count = field("node_count")? as usize;
+        let record_size = field("record_size")? as usize;
+        let ip_version = field("ip_version")?;
+        if !matches!(record_size, 24 | 28 | 32) {
+            anyhow::bail!("unsupported MaxMind DB record size {record_size}");
+        }
+        let data_start = node_count * record_size / 4 + DATA_SECTION_SEPARATOR;
+        if data_start > marker {
+            anyhow::bail!("truncated MaxMind DB file");
+        }
+        let mut mmdb = Self {
+            bytes,
+            node_count,
+            record_size,
+            ip_version,
+            database_type: metadata["database_type"]
+                .as_str()
+                .unwrap_or("unknown")
+                .to_string(),
+            data_start,
+            ipv4_start: 0,
+        };
+        if ip_version == 6 {
+            let mut node = 0;
+            for _ in 0..96 {
+                if node >= node_count {
+                    break;
+                }
+                node = mmdb.record(node, false);
+            }
+            mmdb.ipv4_start = node;
+        }
+        Ok(mmdb)
+    }
+
+    /// The left (`right == false`) or right record of `node`.
+    fn record(&self, node: usize, right: bool) -> usize {
+        let bytes = &self.bytes;
+        let be = |at: usize, len: usize| {
+            bytes[at..at + len]
+                .iter()
+                .fold(0usize, |value, byte| value << 8 | usize::from(*byte))
+        };
+        match self.record_size {
+            24 => be(node * 6 + usize::from(right) * 3, 3),
+            28 => {
+                let at = node * 7;
+                let middle = usize::from(bytes[at + 3]);
+                if right {
+                    (middle & 0x0f) << 24 | be(at + 4, 3)
+                } else {
+                    (middle & 0xf0) << 20 | be(at, 3)
+                }
+            }
+            _ => be(node * 8 + usize::from(right) * 4, 4),
+        }
+    }
+
+    /// The data record for `ip`, if the tree has one.
+    fn lookup(&self, ip: IpAddr) -> Option<Value> {
+        let (bits, mut node) = match (ip, self.ip_version) {
+            (IpAddr::V4(ip), 4) => (u32::from(ip).to_be_bytes().to_vec(), 0),
+            (IpAddr::V4(ip), _) => (u32::from(ip).to_be_bytes().to_vec(), self.ipv4_start),
+            (IpAddr::V6(ip), 6) => (ip.octets().to_vec(), 0),
+            (IpAddr::V6(_), _) => return None,
+        };
+        for bit in 0..bits.len() * 8 {
+            if node >= self.node_count {
+                break;
+            }
+            let right = bits[bit / 8] >> (7 - bit % 8) & 1 == 1;
+            node = self.record(node, right);
+        }
+        if node <= self.node_count {
+            return None;
+        }
+        let offset = node - self.node_count - DATA_SECTION_SEPARATOR;
+        let decoder = Decoder {
+            bytes: self.bytes.get(self.data_start..)?,
+        };
+        decoder.decode(offset, 0).ok().map(|(value, _)| value)
+    }
+}
+
+/// Reads values from a data (or metadata) section.
+struct Decoder<'a> {
+    bytes: &'a [u8],
+}
+
+impl Decoder<'_> {
+    /// The value at `offset`, and the offset after it.
+    fn decode(&self, offset: usize, depth: usize) -> Result<(Value, usize)> {
+        if depth > MAX_DEPTH {
+            anyhow::bail!("MaxMind DB data nests too deep");
+        }
+        let control = usize::from(self.byte(offset)?);
+        let mut at = offset + 1;
+        let mut kind = control >> 5;
+        if kind == 1 {
+            let pointer = self.pointer(control, &mut at)?;
+            let (value, _) = self.decode(pointer, depth + 1)?;
+            return Ok((value, at));
+        }
+        if kind == 0 {
+            kind = 7 + usize::from(self.byte(at)?);
+            at += 1;
+        }
+        let size = match control & 0x1f {
+            29 => 29 + self.uint(at, 1)?,
+            30 => 285 + self.uint(at, 2)?,
+            31 => 65_821 + self.uint(at, 3)?,
+            size => size,
+        };
+        at += match control & 0x1f {
+            size @ 29..=31 => size - 28,
+            _ => 0,
+        };
+        let value = match kind {
+            // UTF-8 string
+            2 => Value::from(String::from_utf8_lossy(self.slice(at, size)?).into_owned()),
+            // double
+            3 => Value::from(f64::from_be_bytes(self.slice(at, 8)?.try_into()?)),
+            // bytes
+            4 => Value::from(self.slice(at, size)?.to_vec()),
+            // uint16, uint32, uint64
+            5 | 6 | 9 => Value::from(self.uint(at, size)? as u64),
+            // int32
+            8 => Value::from(self.uint(at, size)? as u32 as i32),
+            // uint128, kept as text
+            10 => Value::from(
+                self.slice(at, size)?
+                    .iter()
+                    .fold(0u128, |value, byte| value << 8 | u128::from(*byte))
+                    .to_string(),
+            ),
+            // float
+            15 => Value::from(f32::from_be_bytes(self.slice(at, 4)?.try_into()?)),
+            // map
+            7 => {
+                let mut map = Map::new();
+                for _ in 0..size {
+                    let (key, next) = self.decode(at, depth + 1)?;
+                    let (value, next) = self.decode(next, depth + 1)?;
+                    at = next;
+                    map.insert(key.as_str().unwrap_or_default().to_string(), value);
+                }
+                return Ok((Value::Object(map), at));
+            }
+            // array
+            11 => {
+                let mut items = Vec::with_capacity(size.min(1024));
+                for _ in 0..size {
+                    let (item, next) = self.decode(at, depth + 1)?;
+                    at = next;
+                    items.push(item);
+                }
+                return Ok((Value::Array(items), at));
+            }
+            // boolean, whose value is its size
+            14 => return Ok((Value::from(size != 0), at)),
+            kind => anyhow::bail!("unsupported MaxMind DB data type {kind}"),
+        };
+        let width = match kind {
+            3 => 8,
+            15 => 4,
+            _ => size,
+        };
+        Ok((value, at + width))
+    }
+
+    fn pointer(&self, control: usize, at: &mut usize) -> Result<usize> {
+        let high = control & 0x07;
+        let (len, base) = match (control >> 3) & 0x03 {
+            0 => (1, 0),
+            1 => (2, 2_048),
+            2 => (3, 526_336),
+            _ => (4, 0),
+        };
+        let low = self.uint(*at, len)?;
+        *at += len;
+        Ok(match len {
+            4 => low,
+            _ => (high << (8 * len) | low) + base,
+        })
+    }
+
+    fn byte(&self, at: usize) -> Result<u8> {
+        self.bytes
+            .get(at)
+            .copied()
+            .ok_or_else(|| anyhow::anyhow!("MaxMind DB data ends early"))
+    }
+
+    fn slice(&self, at: usize, len: usize) -> Result<&[u8]> {
+        self.bytes
+            .get(at..at + len)
+            .ok_or_else(|| anyhow::anyhow!("MaxMind DB data ends early"))
+    }
+
+    fn uint(&self, at: usize, len: usize) -> Result<usize> {
+        if len > 8 {
+            anyhow::bail!("MaxMind DB integer is too wide");
+        }
+        Ok(self
+            .slice(at, len)?
+            .iter()
+            .fold(0usize, |value, byte| value << 8 | usize::from(*byte)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn string(text: &str) -> Vec<u8> {
+        // Sizes from 29 on take an extra byte.
+        let mut out = match text.len() {
+            len @ 0..=28 => vec![2 << 5 | len as u8],
+            len => vec![2 << 5 | 29, (len - 29) as u8],
+        };
+        out.extend_from_slice(text.as_bytes());
+        out
+    }
+
+    fn uint(kind: u8, value: u32) -> Vec<u8> {
+        let bytes: Vec<u8> = value
+            .to_be_bytes()
+            .into_iter()
+            .skip_while(|byte| *byte == 0)
+            .collect();
+        let mut out = vec![kind << 5 | bytes.len() as u8];
+        out.extend(bytes);
+        out
+    }
+
+    fn map(pairs: &[(&str, Vec<u8>)]) -> Vec<u8> {
+        let mut out = vec![7 << 5 | pairs.len() as u8];
+        for (key, value) in pairs {
+            out.extend(string(key));
+            out.extend_from_slice(value);
+        }
+        out
+    }
+
+    /// An IPv4 tree of one node: 0.0.0.0/1 has a record, 128.0.0.0/1 none.
+    fn tiny_mmdb() -> Vec<u8> {
+        let data = map(&[
+            ("country", map(&[("iso_code", string("NL"))])),
+            ("autonomous_system_number", uint(6, 9009)),
+            // A pointer back to the "NL" string at offset 19.
+            ("autonomous_system_organization", vec![1 << 5, 19]),
+        ]);
+        let node_count = 1u32;
+        let left = node_count + DATA_SECTION_SEPARATOR as u32;
+        let mut bytes = left.to_be_bytes()[1..].to_vec();
+        bytes.extend(&node_count.to_be_bytes()[1..]);
+        bytes.extend([0; DATA_SECTION_SEPARATOR]);
+        bytes.extend(data);
+        bytes.extend(METADATA_MARKER);
+        bytes.extend(map(&[
+            ("node_count", uint(6, node_count)),
+            ("record_size", uint(5, 24)),
+            ("ip_version", uint(5, 4)),
+            ("database_type", string("Test-ASN")),
+        ]));
+        bytes
+    }
+
+    #[test]
+    fn mmdb_records_resolve_by_address_prefix() {
+        let mmdb = Mmdb::new(tiny_mmdb()).unwrap();
+        assert_eq!(mmdb.database_type, "Test-ASN");
+        let record = mmdb.lookup("45.83.1.2".parse().unwrap()).unwrap();
+        assert_eq!(record["country"]["iso_code"], "NL");
+        assert_eq!(record["autonomous_system_number"], 9009);
+        assert_eq!(record["autonomous_system_organization"], "NL");
+        assert!(mmdb.lookup("203.0.113.7".parse().unwrap()).is_none());
+        assert!(mmdb.lookup("2001:db8::1".parse().unwrap()).is_none());
+        assert!(Mmdb::new(b"not a database".to_vec()).is_err());
+    }
+}
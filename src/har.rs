@@ -0,0 +1,99 @@
+/*
+Browser session captures for `scan-har`. A HAR file (exported from the network
+panel of Chrome, Firefox, Safari, or a proxy such as mitmproxy) lists every
+request a browsing session made. The host of each request URL, and of each
+redirect target, is deduped into `scan::Observations` with its request count,
+so a whole session can be swept for lookalike domains in one step.
+*/
+use anyhow::Result;
+use serde::Deserialize;
+
+use crate::scan::Observations;
+
+#[derive(Deserialize)]
+struct Har {
+    log: Log,
+}
+
+#[derive(Deserialize)]
+struct Log {
+    #[serde(default)]
+    entries: Vec<Entry>,
+}
+
+#[derive(Deserialize)]
+struct Entry {
+    request: Request,
+    #[serde(default)]
+    response: Option<Response>,
+}
+
+#[derive(Deserialize)]
+struct Request {
+    url: String,
+}
+
+#[derive(Deserialize)]
+struct Response {
+    #[serde(default, rename = "redirectURL")]
+    redirect_url: String,
+}
+
+/// The hosts a HAR capture requested, in first-request order, and the number
+/// of requests.
+pub fn read(bytes: &[u8]) -> Result<(Observations, usize)> {
+    let har: Har =
+        serde_json::from_slice(bytes).map_err(|err| anyhow::anyhow!("not a HAR file: {err}"))?;
+    let mut observations = Observations::default();
+    for entry in &har.log.entries {
+        add_host(&mut observations, &entry.request.url);
+        if let Some(response) = &entry.response {
+            add_host(&mut observations, &response.redirect_url);
+        }
+    }
+    Ok((observations, har.log.entries.len()))
+}
+
+fn add_host(observations: &mut Observations, url: &str) {
+    if let Some(host) = url::Url::parse(url)
+        .ok()
+        .and_then(|url| url.host_str().map(str::to_string))
+    {
+        observations.add(&host, None);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn requests_and_redirects_are_counted_per_host() {
+        let har = r#"{"log": {"version": "1.2", "creator": {"name": "WebInspector"}, "entries": [
+            {"request": {"method": "GET", "url": "https://mail.example.com/inbox"},
+             "response": {"status": 200, "redirectURL": ""}},
+            {"request": {"method": "GET", "url": "http://bit.ly/3xYz"},
+             "response": {"status": 301, "redirectURL": "https://login.paypa1.com/signin"}},
+            {"request": {"method": "POST", "url": "https://login.paypa1.com/signin"},
+             "response": {"status": 200}},
+            {"request": {"method": "GET", "url": "data:image/png;base64,AAAA"}},
+            {"request": {"method": "GET", "url": "https://203.0.113.7/beacon"}}
+        ]}}"#;
+        let (observations, requests) = read(har.as_bytes()).unwrap();
+        assert_eq!(requests, 5);
+        let hosts: Vec<(String, usize)> = observations
+            .into_vec()
+            .into_iter()
+            .map(|observation| (observation.domain, observation.occurrences))
+            .collect();
+        assert_eq!(
+            hosts,
+            [
+                ("example.com".to_string(), 1),
+                ("bit.ly".to_string(), 1),
+                ("paypa1.com".to_string(), 2),
+            ]
+        );
+        assert!(read(b"{\"entries\": []}").is_err());
+    }
+}
@@ -0,0 +1,441 @@
+/*
+Optional lookup history. With `--history`, every lookup from the CLI, the
+server, or MCP is recorded (query, time, top result, score, source) in a small
+writable SQLite file next to the default DB, and `spotspoof history` lists,
+searches, and exports it. Rows older than the retention period are dropped when
+the file is opened. Recording never fails a lookup; errors are only logged.
+The same rows feed `stats`: most-queried domains, most-impersonated brands, and
+detection rates over recent windows (`GET /stats` on the server).
+*/
+use anyhow::Result;
+use rusqlite::Connection;
+use serde::Serialize;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::warn;
+use utoipa::ToSchema;
+
+use crate::db;
+use crate::types::{AsciiResponse, IdnResponse};
+
+pub const DEFAULT_HISTORY_FILE: &str = "spotspoof-history.sqlite";
+const SELECT: &str = "SELECT id, query, strftime('%Y-%m-%dT%H:%M:%SZ', looked_up_at, 'unixepoch'),
+	kind, top_result, score, source FROM lookup_history";
+
+/// The windows `stats` reports detection rates over.
+const STATS_WINDOWS: [(&str, Duration); 3] = [
+    ("1h", Duration::from_secs(3600)),
+    ("24h", Duration::from_secs(24 * 3600)),
+    ("7d", Duration::from_secs(7 * 24 * 3600)),
+];
+
+static INSTALLED: OnceLock<LookupHistory> = OnceLock::new();
+
+/// Where a lookup came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Source {
+    Cli,
+    Http,
+    Mcp,
+}
+
+impl Source {
+    fn as_str(self) -> &'static str {
+        match self {
+            Source::Cli => "cli",
+            Source::Http => "http",
+            Source::Mcp => "mcp",
+        }
+    }
+}
+
+/// One recorded lookup.
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct Entry {
+    pub id: i64,
+    pub query: String,
+    /// RFC 3339, UTC.
+    pub timestamp: String,
+    /// `ascii` or `idn`.
+    pub kind: String,
+    /// The best ASCII match, or the first registered IDN variant.
+    pub top_result: Option<String>,
+    /// Similarity of the ASCII top result.
+    pub score: Option<u8>,
+    pub source: String,
+}
+
+/// A summary of the recorded lookups.
+#[derive(Debug, Clone, Serialize, ToSchema, PartialEq)]
+pub struct Stats {
+    /// Lookups in the history (within its retention).
+    pub lookups: u64,
+    /// Most-queried domains, most first.
+    pub top_queries: Vec<DomainCount>,
+    /// Domains most often reported as the top match (the brands being
+    /// impersonated), most first.
+    pub top_brands: Vec<DomainCount>,
+    /// Lookups and detections over the last hour, day, and week.
+    pub windows: Vec<WindowStats>,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema, PartialEq, Eq)]
+pub struct DomainCount {
+    pub domain: String,
+    pub count: u64,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema, PartialEq)]
+pub struct WindowStats {
+    /// `1h`, `24h`, or `7d`.
+    pub window: String,
+    pub lookups: u64,
+    /// Lookups with at least one match.
+    pub detections: u64,
+    /// `detections / lookups`, 0 without lookups.
+    pub detection_rate: f64,
+}
+
+pub struct LookupHistory {
+    conn: Mutex<Connection>,
+}
+
+impl LookupHistory {
+    /// Opens (creating if needed) the history at `path` and drops rows older
+    /// than `retention`.
+    pub fn open(path: &str, retention: Option<Duration>) -> Result<Self> {
+        Self::init(db::open_shared_store(path)?, retention)
+    }
+
+    #[cfg(test)]
+    pub fn open_in_memory(retention: Option<Duration>) -> Result<Self> {
+        Self::init(Connection::open_in_memory()?, retention)
+    }
+
+    fn init(conn: Connection, retention: Option<Duration>) -> Result<Self> {
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS lookup_history (
+				id INTEGER PRIMARY KEY,
+				query TEXT NOT NULL,
+				looked_up_at INTEGER NOT NULL,
+				kind TEXT NOT NULL,
+				top_result TEXT,
+				score INTEGER,
+				source TEXT NOT NULL
+			);
+			CREATE INDEX IF NOT EXISTS lookup_history_time ON lookup_history (looked_up_at);",
+        )?;
+        if let Some(retention) = retention {
+            conn.execute(
+                "DELETE FROM lookup_history WHERE looked_up_at < ?1",
+                [now() - retention.as_secs() as i64],
+            )?;
+        }
+        Ok(Self {
+            conn: Mutex::new(conn),
+        })
+    }
+
+    pub fn record_ascii(&self, source: Source, response: &AsciiResponse) -> Result<()> {
+        let top = response
+            .results
+            .iter()
+            .max_by_key(|result| result.similarity);
+        self.insert(
+            source,
+            &response.q,
+            "ascii",
+            top.map(|result| result.domain.as_str()),
+            top.map(|result| result.similarity),
+        )
+    }
+
+    pub fn record_idn(&self, source: Source, response: &IdnResponse) -> Result<()> {
+        let top = response
+            .results
+            .iter()
+            .find(|result| result.is_registered)
+            .or(response.results.first());
+        self.insert(
+            source,
+            &response.q,
+            "idn",
+            top.map(|result| result.domain.as_str()),
+            None,
+        )
+    }
+
+    fn insert(
+        &self,
+        source: Source,
+        query: &str,
+        kind: &str,
+        top_result: Option<&str>,
+        score: Option<u8>,
+    ) -> Result<()> {
+        let conn = self.conn.lock().unwrap_or_else(|p| p.into_inner());
+        conn.prepare_cached(
+            "INSERT INTO lookup_history (query, looked_up_at, kind, top_result, score, source)
+			VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        )?
+        .execute((query, now(), kind, top_result, score, source.as_str()))?;
+        Ok(())
+    }
+
+    /// The most recent `limit` lookups, newest first.
+    pub fn list(&self, limit: usize) -> Result<Vec<Entry>> {
+        self.query(
+            &format!("{SELECT} ORDER BY looked_up_at DESC, id DESC LIMIT ?1"),
+            (limit as i64,),
+        )
+    }
+
+    /// Lookups whose query or top result contains `pattern`, newest first.
+    pub fn search(&self, pattern: &str, limit: usize) -> Result<Vec<Entry>> {
+        self.query(
+            &format!(
+                "{SELECT} WHERE instr(query, ?1) > 0 OR instr(top_result, ?1) > 0
+				ORDER BY looked_up_at DESC, id DESC LIMIT ?2"
+            ),
+            (pattern.to_lowercase(), limit as i64),
+        )
+    }
+
+    /// Every lookup since `since` ago (all of them without it), oldest first.
+    pub fn export(&self, since: Option<Duration>) -> Result<Vec<Entry>> {
+        let from = since.map_or(0, |since| now() - since.as_secs() as i64);
+        self.query(
+            &format!("{SELECT} WHERE looked_up_at >= ?1 ORDER BY looked_up_at, id"),
+            (from,),
+        )
+    }
+
+    /// Lookup counts, the `top` most-queried domains and most-matched brands,
+    /// and detection rates per window.
+    pub fn stats(&self, top: usize) -> Result<Stats> {
+        let conn = self.conn.lock().unwrap_or_else(|p| p.into_inner());
+        let counts = |column: &str| -> Result<Vec<DomainCount>> {
+            let mut stmt = conn.prepare(&format!(
+                "SELECT {column}, count(*) AS n FROM lookup_history WHERE {column} IS NOT NULL
+				GROUP BY {column} ORDER BY n DESC, {column} LIMIT ?1"
+            ))?;
+            let rows = stmt.query_map([top as i64], |row| {
+                Ok(DomainCount {
+                    domain: row.get(0)?,
+                    count: row.get(1)?,
+                })
+            })?;
+            Ok(rows.collect::<rusqlite::Result<_>>()?)
+        };
+        let top_queries = counts("query")?;
+        let top_brands = counts("top_result")?;
+
+        let mut window_stmt = conn.prepare(
+            "SELECT count(*), count(top_result) FROM lookup_history WHERE looked_up_at >= ?1",
+        )?;
+        let mut windows = Vec::new();
+        for (window, length) in STATS_WINDOWS {
+            let (lookups, detections): (u64, u64) = window_stmt
+                .query_row([now() - length.as_secs() as i64], |row| {
+                    Ok((row.get(0)?, row.get(1)?))
+                })?;
+            windows.push(WindowStats {
+                window: window.to_string(),
+                lookups,
+                detections,
+                detection_rate: if lookups == 0 {
+                    0.0
+                } else {
+                    detections as f64 / lookups as f64
+                },
+            });
+        }
+        let lookups =
+            conn.query_row("SELECT count(*) FROM lookup_history", [], |row| row.get(0))?;
+        Ok(Stats {
+            lookups,
+            top_queries,
+            top_brands,
+            windows,
+        })
+    }
+
+    fn query(&self, sql: &str, params: impl rusqlite::Params) -> Result<Vec<Entry>> {
+        let conn = self.conn.lock().unwrap_or_else(|p| p.into_inner());
+        let mut stmt = conn.prepare(sql)?;
+        let rows = stmt.query_map(params, |row| {
+            Ok(Entry {
+                id: row.get(0)?,
+                query: row.get(1)?,
+                timestamp: row.get(2)?,
+                kind: row.get(3)?,
+                top_result: row.get(4)?,
+                score: row.get(5)?,
+                source: row.get(6)?,
+            })
+        })?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+}
+
+/// Makes `history` the process-wide lookup history. Only the first call takes
+/// effect.
+pub fn install(history: LookupHistory) {
+    let _ = INSTALLED.set(history);
+}
+
+/// Whether lookups are being recorded.
+pub fn enabled() -> bool {
+    INSTALLED.get().is_some()
+}
+
+/// The installed history, if lookups are being recorded.
+pub fn installed() -> Option<&'static LookupHistory> {
+    INSTALLED.get()
+}
+
+/// Records an ASCII lookup if history is enabled.
+pub fn record_ascii(source: Source, response: &AsciiResponse) {
+    if let Some(history) = INSTALLED.get() {
+        if let Err(err) = history.record_ascii(source, response) {
+            warn!(kind = "history", "could not record lookup: {err}");
+        }
+    }
+}
+
+/// Records an IDN lookup if history is enabled.
+pub fn record_idn(source: Source, response: &IdnResponse) {
+    if let Some(history) = INSTALLED.get() {
+        if let Err(err) = history.record_idn(source, response) {
+            warn!(kind = "history", "could not record lookup: {err}");
+        }
+    }
+}
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs() as i64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::AsciiResult;
+
+    fn ascii(q: &str, results: &[(&str, u8)]) -> AsciiResponse {
+        AsciiResponse {
+            q: q.into(),
+            ascii: true,
+            puny: false,
+            exact_match: false,
+            protected_brand: None,
+            results: results
+                .iter()
+                .map(|(domain, similarity)| AsciiResult {
+                    domain: domain.to_string(),
+                    similarity: *similarity,
+                    breakdown: None,
+                })
+                .collect(),
+            dataset: None,
+            total_candidates_considered: None,
+            passive_dns: None,
+        }
+    }
+
+    #[test]
+    fn lookups_are_listed_searched_and_exported() {
+        let history = LookupHistory::open_in_memory(None).unwrap();
+        history
+            .record_ascii(
+                Source::Cli,
+                &ascii("gooogle.com", &[("goggle.com", 80), ("google.com", 91)]),
+            )
+            .unwrap();
+        history
+            .record_ascii(Source::Http, &ascii("examp1e.com", &[]))
+            .unwrap();
+
+        let listed = history.list(10).unwrap();
+        assert_eq!(listed.len(), 2);
+        assert_eq!(listed[0].query, "examp1e.com");
+        assert_eq!(listed[0].source, "http");
+        assert_eq!(listed[0].top_result, None);
+        assert_eq!(listed[1].top_result.as_deref(), Some("google.com"));
+        assert_eq!(listed[1].score, Some(91));
+        assert!(listed[1].timestamp.ends_with('Z'));
+        assert_eq!(history.list(1).unwrap().len(), 1);
+
+        let found = history.search("GOOGLE", 10).unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].query, "gooogle.com");
+
+        let exported = history.export(Some(Duration::from_secs(60))).unwrap();
+        assert_eq!(exported[0].query, "gooogle.com");
+        assert_eq!(exported.len(), 2);
+    }
+
+    #[test]
+    fn stats_count_queries_brands_and_windows() {
+        let history = LookupHistory::open_in_memory(None).unwrap();
+        for (query, results) in [
+            ("gooogle.com", &[("google.com", 91)][..]),
+            ("gooogle.com", &[("google.com", 91)][..]),
+            ("paypa1.com", &[("paypal.com", 90)][..]),
+            ("example.org", &[][..]),
+        ] {
+            history
+                .record_ascii(Source::Http, &ascii(query, results))
+                .unwrap();
+        }
+        history
+            .conn
+            .lock()
+            .unwrap()
+            .execute(
+                "UPDATE lookup_history SET looked_up_at = looked_up_at - 7200
+				WHERE query = 'example.org'",
+                [],
+            )
+            .unwrap();
+
+        let stats = history.stats(1).unwrap();
+        assert_eq!(stats.lookups, 4);
+        assert_eq!(
+            stats.top_queries,
+            [DomainCount {
+                domain: "gooogle.com".to_string(),
+                count: 2
+            }]
+        );
+        assert_eq!(stats.top_brands[0].domain, "google.com");
+        let hour = &stats.windows[0];
+        assert_eq!(
+            (hour.window.as_str(), hour.lookups, hour.detections),
+            ("1h", 3, 3)
+        );
+        assert_eq!(hour.detection_rate, 1.0);
+        let day = &stats.windows[1];
+        assert_eq!((day.lookups, day.detections), (4, 3));
+        assert_eq!(day.detection_rate, 0.75);
+    }
+
+    #[test]
+    fn retention_drops_old_rows_on_open() {
+        let conn = Connection::open_in_memory().unwrap();
+        let history = LookupHistory::init(conn, None).unwrap();
+        history
+            .record_ascii(Source::Cli, &ascii("a.com", &[]))
+            .unwrap();
+        let conn = history.conn.into_inner().unwrap();
+        conn.execute(
+            "UPDATE lookup_history SET looked_up_at = looked_up_at - 7200",
+            [],
+        )
+        .unwrap();
+        let history = LookupHistory::init(conn, Some(Duration::from_secs(3600))).unwrap();
+        assert!(history.list(10).unwrap().is_empty());
+    }
+}
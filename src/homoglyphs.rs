@@ -0,0 +1,229 @@
+/*
+A user-supplied homoglyph weight matrix (`--homoglyph-weights`), so regional
+deployments can emphasize the confusables of their own scripts. Each entry
+rates from 0 to 1 how convincingly a string passes for an ASCII one:
+
+    {"ascii": {"rn": {"m": 1.0}, "1": {"l": 0.6}},
+     "unicode": {"а": {"a": 1.0}, "ı": {"i": 0.9}}}
+
+With a matrix installed, the `confusable` ensemble scorer becomes an edit
+distance where a listed substitution costs `1 - weight` (the ASCII folds of
+`candidates::skeleton` start at 1.0 and may be overridden), and IDN lookups
+enumerate each character's replacements highest weight first, dropping those
+weighted 0. Unlisted replacements weigh `NEUTRAL_WEIGHT`.
+*/
+use anyhow::Result;
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::OnceLock;
+
+/// The weight of a replacement the matrix does not list.
+pub const NEUTRAL_WEIGHT: f32 = 0.5;
+
+/// The folds `candidates::skeleton` applies, as matrix entries.
+const SKELETON_FOLDS: [(&str, &str); 11] = [
+    ("rn", "m"),
+    ("vv", "w"),
+    ("cl", "d"),
+    ("0", "o"),
+    ("1", "l"),
+    ("i", "l"),
+    ("|", "l"),
+    ("3", "e"),
+    ("5", "s"),
+    ("$", "s"),
+    ("@", "a"),
+];
+
+static INSTALLED: OnceLock<HomoglyphWeights> = OnceLock::new();
+
+type Matrix = BTreeMap<String, BTreeMap<String, f32>>;
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MatrixFile {
+    #[serde(default)]
+    ascii: Matrix,
+    #[serde(default)]
+    unicode: Matrix,
+}
+
+/// One listed substitution, in one direction.
+#[derive(Debug, Clone, PartialEq)]
+struct Substitution {
+    from: Vec<char>,
+    to: Vec<char>,
+    cost: f32,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct HomoglyphWeights {
+    weights: HashMap<(String, String), f32>,
+    /// Substitutions in both directions, by the last character of `from`.
+    by_last: HashMap<char, Vec<Substitution>>,
+}
+
+impl HomoglyphWeights {
+    /// Parses a JSON matrix. Weights must be between 0 and 1, `ascii` entries
+    /// must be ASCII on both sides, and `unicode` keys single non-ASCII
+    /// characters that pass for ASCII.
+    pub fn parse(json: &str) -> Result<Self> {
+        let file: MatrixFile = serde_json::from_str(json)?;
+        let mut weights = HashMap::new();
+        for (from, to) in SKELETON_FOLDS {
+            weights.insert((from.to_string(), to.to_string()), 1.0);
+        }
+        for (section, matrix) in [("ascii", &file.ascii), ("unicode", &file.unicode)] {
+            for (from, targets) in matrix {
+                let mut chars = from.chars();
+                let valid_from = match section {
+                    "ascii" => !from.is_empty() && from.is_ascii(),
+                    _ => matches!((chars.next(), chars.next()), (Some(c), None) if !c.is_ascii()),
+                };
+                if !valid_from {
+                    anyhow::bail!("homoglyph weights: invalid {section} key {from:?}");
+                }
+                for (to, weight) in targets {
+                    if to.is_empty() || !to.is_ascii() {
+                        anyhow::bail!("homoglyph weights: {from:?} must map to ASCII, not {to:?}");
+                    }
+                    if !(0.0..=1.0).contains(weight) {
+                        anyhow::bail!(
+                            "homoglyph weights: {from:?} -> {to:?} is {weight}; weights are 0 to 1"
+                        );
+                    }
+                    weights.insert((from.to_lowercase(), to.to_lowercase()), *weight);
+                }
+            }
+        }
+
+        let mut by_last: HashMap<char, Vec<Substitution>> = HashMap::new();
+        for ((from, to), weight) in &weights {
+            for (from, to) in [(from, to), (to, from)] {
+                let from: Vec<char> = from.chars().collect();
+                by_last
+                    .entry(*from.last().expect("keys are not empty"))
+                    .or_default()
+                    .push(Substitution {
+                        from,
+                        to: to.chars().collect(),
+                        cost: 1.0 - weight,
+                    });
+            }
+        }
+        Ok(Self { weights, by_last })
+    }
+
+    /// How convincingly `from` passes for `to`, if the matrix says.
+    pub fn weight(&self, from: &str, to: &str) -> Option<f32> {
+        self.weights
+            .get(&(from.to_string(), to.to_string()))
+            .copied()
+    }
+
+    /// Levenshtein similarity (0-100) with listed substitutions costing
+    /// `1 - weight`, in either direction.
+    pub fn similarity(&self, a: &str, b: &str) -> u8 {
+        let (a, b): (Vec<char>, Vec<char>) = (
+            a.to_lowercase().chars().collect(),
+            b.to_lowercase().chars().collect(),
+        );
+        let longest = a.len().max(b.len());
+        if longest == 0 {
+            return 100;
+        }
+        let mut dist = vec![vec![0.0f32; b.len() + 1]; a.len() + 1];
+        for (i, row) in dist.iter_mut().enumerate() {
+            row[0] = i as f32;
+        }
+        for (j, cell) in dist[0].iter_mut().enumerate() {
+            *cell = j as f32;
+        }
+        for i in 1..=a.len() {
+            for j in 1..=b.len() {
+                let substitution = if a[i - 1] == b[j - 1] { 0.0 } else { 1.0 };
+                let mut best = (dist[i - 1][j] + 1.0)
+                    .min(dist[i][j - 1] + 1.0)
+                    .min(dist[i - 1][j - 1] + substitution);
+                for listed in self.by_last.get(&a[i - 1]).into_iter().flatten() {
+                    if a[..i].ends_with(&listed.from) && b[..j].ends_with(&listed.to) {
+                        best = best
+                            .min(dist[i - listed.from.len()][j - listed.to.len()] + listed.cost);
+                    }
+                }
+                dist[i][j] = best;
+            }
+        }
+        let ratio = 1.0 - dist[a.len()][b.len()] / longest as f32;
+        (100.0 * ratio).round().max(0.0) as u8
+    }
+
+    /// Orders each character's replacements by descending weight (stable, so
+    /// ties keep the mappings' order) and drops those weighted 0.
+    pub fn prioritize(&self, mappings: &mut HashMap<String, Vec<String>>) {
+        for (unicode, targets) in mappings.iter_mut() {
+            let weight = |target: &String| self.weight(unicode, target).unwrap_or(NEUTRAL_WEIGHT);
+            targets.retain(|target| target == unicode || weight(target) > 0.0);
+            targets.sort_by(|a, b| weight(b).total_cmp(&weight(a)));
+        }
+    }
+}
+
+/// Makes `weights` the process-wide matrix. Only the first call takes effect,
+/// and IDN lookups only see it before their first lookup.
+pub fn install(weights: HomoglyphWeights) {
+    let _ = INSTALLED.set(weights);
+}
+
+pub fn installed() -> Option<&'static HomoglyphWeights> {
+    INSTALLED.get()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matrix_weights_substitutions_and_validates() {
+        let weights = HomoglyphWeights::parse(
+            r#"{"ascii": {"1": {"l": 0.2}, "nn": {"m": 0.8}},
+                "unicode": {"ı": {"i": 1.0}}}"#,
+        )
+        .unwrap();
+        assert_eq!(weights.weight("ı", "i"), Some(1.0));
+        assert_eq!(weights.weight("rn", "m"), Some(1.0));
+        assert_eq!(weights.weight("1", "l"), Some(0.2));
+
+        assert_eq!(weights.similarity("rnicrosoft.com", "microsoft.com"), 100);
+        assert_eq!(weights.similarity("gooıe.com", "gooie.com"), 100);
+        assert_eq!(weights.similarity("nnicrosoft.com", "microsoft.com"), 99);
+        // A weak pair is barely better than any other typo.
+        assert_eq!(weights.similarity("paypa1.com", "paypal.com"), 92);
+        assert_eq!(weights.similarity("paypa2.com", "paypal.com"), 90);
+
+        for invalid in [
+            r#"{"ascii": {"1": {"l": 1.5}}}"#,
+            r#"{"unicode": {"ab": {"a": 1.0}}}"#,
+            r#"{"unicode": {"а": {"б": 1.0}}}"#,
+            r#"{"cyrillic": {}}"#,
+        ] {
+            assert!(HomoglyphWeights::parse(invalid).is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn prioritize_orders_and_drops_replacements() {
+        let weights =
+            HomoglyphWeights::parse(r#"{"unicode": {"ð": {"o": 0.9, "0": 0.0}}}"#).unwrap();
+        let mut mappings = HashMap::from([
+            (
+                "ð".to_string(),
+                vec!["d".to_string(), "o".to_string(), "0".to_string()],
+            ),
+            ("y".to_string(), vec!["y".to_string()]),
+        ]);
+        weights.prioritize(&mut mappings);
+        assert_eq!(mappings["ð"], ["o", "d"]);
+        assert_eq!(mappings["y"], ["y"]);
+    }
+}
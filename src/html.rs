@@ -0,0 +1,261 @@
+/*
+Link sweeps of web pages (`scan-url`, `scan-html`). Takes the host of every URL
+a page references: anchors and image maps (`href`), forms (`action`,
+`formaction`), scripts, frames, images and other embeds (`src`, `srcset`,
+`data`), meta refreshes, and `mailto:` addresses. The page's own host (and,
+for a fetched page, where it redirected to) comes first. The hosts go through
+the same scanner as `scan-file`, so a phishing page or a compromised
+newsletter pointing at spoofed domains shows up in one report.
+*/
+use anyhow::Result;
+use regex::Regex;
+use reqwest::header;
+use std::io::Read;
+use std::sync::OnceLock;
+use url::Url;
+
+use crate::net;
+use crate::scan::Observations;
+
+/// Pages larger than this are cut off; phishing pages are small.
+const MAX_PAGE_BYTES: u64 = 10 * 1024 * 1024;
+pub const DEFAULT_USER_AGENT: &str = concat!("spotspoof/", env!("CARGO_PKG_VERSION"));
+
+/// The hosts a page references.
+#[derive(Debug, Default)]
+pub struct Page {
+    pub hosts: Observations,
+    pub links: LinkCounts,
+}
+
+/// How many URLs of each kind a page references.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct LinkCounts {
+    pub anchors: usize,
+    pub forms: usize,
+    pub scripts: usize,
+    pub other: usize,
+}
+
+impl LinkCounts {
+    fn count(&mut self, tag: &str, attribute: &str) {
+        match (tag, attribute) {
+            ("a" | "area", "href") => self.anchors += 1,
+            (_, "action" | "formaction") => self.forms += 1,
+            ("script", "src") => self.scripts += 1,
+            _ => self.other += 1,
+        }
+    }
+}
+
+/// Fetches `url` (following redirects) and extracts its links, resolving
+/// relative ones against where the page ended up.
+pub fn fetch(url: &str, user_agent: &str) -> Result<Page> {
+    net::ensure_online("scan-url")?;
+    let requested = Url::parse(url).map_err(|err| anyhow::anyhow!("invalid URL {url}: {err}"))?;
+    if !matches!(requested.scheme(), "http" | "https") {
+        anyhow::bail!("scan-url only fetches http:// and https:// URLs");
+    }
+    let client = net::client_builder().build()?;
+    let response = net::send_with_retry("scan-url", || {
+        client
+            .get(requested.clone())
+            .header(header::USER_AGENT, user_agent)
+    })?;
+    if !response.status().is_success() {
+        anyhow::bail!("fetching {url} failed: HTTP {}", response.status());
+    }
+    let landed = response.url().clone();
+    let mut body = Vec::new();
+    response.take(MAX_PAGE_BYTES).read_to_end(&mut body)?;
+
+    let mut page = Page::default();
+    for page_url in [&requested, &landed] {
+        if let Some(host) = page_url.host_str() {
+            page.hosts.add(host, None);
+        }
+    }
+    page.add_links(&String::from_utf8_lossy(&body), Some(&landed));
+    Ok(page)
+}
+
+/// Extracts the links of an HTML document served from `base`, whose host
+/// comes first. Relative links are resolved against `base`, or skipped
+/// without one.
+pub fn extract(html: &str, base: Option<&Url>) -> Page {
+    let mut page = Page::default();
+    if let Some(host) = base.and_then(Url::host_str) {
+        page.hosts.add(host, None);
+    }
+    page.add_links(html, base);
+    page
+}
+
+impl Page {
+    fn add_links(&mut self, html: &str, base: Option<&Url>) {
+        static TAG: OnceLock<Regex> = OnceLock::new();
+        static ATTRIBUTE: OnceLock<Regex> = OnceLock::new();
+        let tag_re = TAG.get_or_init(|| Regex::new(r"<([a-zA-Z][a-zA-Z0-9]*)\b([^>]*)>").unwrap());
+        let attribute_re = ATTRIBUTE.get_or_init(|| {
+            Regex::new(r#"([a-zA-Z-]+)\s*=\s*(?:"([^"]*)"|'([^']*)'|([^\s"'>]+))"#).unwrap()
+        });
+        // A <base href> changes what relative links resolve against.
+        let mut base = base.cloned();
+        for tag in tag_re.captures_iter(html) {
+            let name = tag[1].to_ascii_lowercase();
+            for attribute in attribute_re.captures_iter(&tag[2]) {
+                let key = attribute[1].to_ascii_lowercase();
+                let value = decode_entities(
+                    attribute
+                        .get(2)
+                        .or_else(|| attribute.get(3))
+                        .or_else(|| attribute.get(4))
+                        .map_or("", |value| value.as_str()),
+                );
+                let urls: Vec<&str> = match key.as_str() {
+                    "href" | "src" | "action" | "formaction" | "data" | "poster" => {
+                        vec![value.trim()]
+                    }
+                    // `url 1x, url 2x`
+                    "srcset" => value
+                        .split(',')
+                        .filter_map(|candidate| candidate.split_whitespace().next())
+                        .collect(),
+                    // <meta http-equiv="refresh" content="0; url=...">
+                    "content" if name == "meta" => value
+                        .split_once(';')
+                        .and_then(|(_, rest)| {
+                            let rest = rest.trim();
+                            rest.get(..4)
+                                .filter(|prefix| prefix.eq_ignore_ascii_case("url="))
+                                .map(|_| rest[4..].trim_matches(['\'', '"', ' ']))
+                        })
+                        .into_iter()
+                        .collect(),
+                    _ => continue,
+                };
+                for url in urls {
+                    let Some(resolved) = resolve(url, base.as_ref()) else {
+                        continue;
+                    };
+                    if name == "base" && key == "href" {
+                        base = Some(resolved.clone());
+                    }
+                    let host = match resolved.scheme() {
+                        "mailto" => resolved
+                            .path()
+                            .rsplit_once('@')
+                            .map(|(_, host)| host.split('?').next().unwrap_or(host).to_string()),
+                        _ => resolved.host_str().map(str::to_string),
+                    };
+                    if let Some(host) = host {
+                        if self.hosts.add(&host, None) {
+                            self.links.count(&name, &key);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn resolve(url: &str, base: Option<&Url>) -> Option<Url> {
+    if url.is_empty() || url.starts_with('#') {
+        return None;
+    }
+    match Url::parse(url) {
+        Ok(url) => Some(url),
+        Err(url::ParseError::RelativeUrlWithoutBase) => base?.join(url).ok(),
+        Err(_) => None,
+    }
+}
+
+/// Decodes `&amp;` and numeric character references, which pages use to hide
+/// hosts from naive scanners (`paypa1&#46;com`).
+fn decode_entities(value: &str) -> String {
+    if !value.contains('&') {
+        return value.to_string();
+    }
+    let mut out = String::with_capacity(value.len());
+    let mut rest = value;
+    while let Some(start) = rest.find('&') {
+        out.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let decoded = rest.find(';').and_then(|end| {
+            let entity = &rest[1..end];
+            let c = match entity.strip_prefix('#') {
+                Some(hex) if hex.starts_with(['x', 'X']) => {
+                    char::from_u32(u32::from_str_radix(&hex[1..], 16).ok()?)?
+                }
+                Some(decimal) => char::from_u32(decimal.parse().ok()?)?,
+                None if entity == "amp" => '&',
+                None => return None,
+            };
+            Some((c, end + 1))
+        });
+        match decoded {
+            Some((c, len)) => {
+                out.push(c);
+                rest = &rest[len..];
+            }
+            None => {
+                out.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hosts_come_from_links_forms_scripts_and_refreshes() {
+        let html = r#"<html><head>
+            <meta http-equiv="refresh" content="5; URL='https://redirect.example.net/go'">
+            <script src="https://cdn.paypa1.com/app.js"></script>
+            <link rel=stylesheet href=//static.example.org/site.css>
+            </head><body>
+            <a href="/account">Account</a>
+            <A HREF='https://www.paypa1&#46;com/signin?x=1&amp;y=2'>Sign in</A>
+            <form method="post" action="https://collect.examp1e.com/post"></form>
+            <img srcset="https://img.example.com/a.png 1x, https://img2.example.com/a.png 2x">
+            <a href="mailto:support@paypal-help.com?subject=hi">Mail us</a>
+            <a href="javascript:void(0)">noop</a>
+            </body></html>"#;
+        let base = Url::parse("https://newsletter.example.com/issue/7").unwrap();
+        let page = extract(html, Some(&base));
+        let hosts: Vec<String> = page
+            .hosts
+            .into_vec()
+            .into_iter()
+            .map(|observation| observation.domain)
+            .collect();
+        assert_eq!(
+            hosts,
+            [
+                "example.com",
+                "example.net",
+                "paypa1.com",
+                "example.org",
+                "examp1e.com",
+                "paypal-help.com"
+            ]
+        );
+        assert_eq!(
+            page.links,
+            LinkCounts {
+                anchors: 3,
+                forms: 1,
+                scripts: 1,
+                other: 4,
+            }
+        );
+        assert!(extract("<a href=\"/relative\">x</a>", None)
+            .hosts
+            .is_empty());
+    }
+}
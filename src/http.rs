@@ -1,43 +1,423 @@
 /*
 HTTP API server for SpotSpoof. Exposes lookup endpoints, a health check,
 and OpenAPI/Swagger docs for integration with SOAR and automation systems.
+Every request runs under an `X-Request-Id` (the caller's, or a generated one)
+that is echoed on the response, attached to its log lines, and included in
+error bodies.
 */
 use anyhow::Result;
 use axum::{
-    extract::State,
-    http::StatusCode,
-    response::{Html, IntoResponse, Response},
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        Path as UrlPath, Query, Request, State,
+    },
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    middleware::{self, Next},
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        Html, IntoResponse, Response,
+    },
     routing::{get, post},
-    Json, Router,
+    Extension, Json, Router,
 };
+use futures_util::SinkExt;
+use notify::{RecursiveMode, Watcher};
+use serde::Deserialize;
 use serde_json::json;
+use std::collections::BTreeMap;
+use std::convert::Infallible;
 use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Arc, RwLock};
+use std::time::Duration;
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_stream::StreamExt;
+use tower_http::compression::CompressionLayer;
+use tracing::{info, info_span, warn, Instrument};
 use utoipa::OpenApi;
 use utoipa_swagger_ui::SwaggerUi;
 
-use crate::ascii_spoof;
-use crate::idn;
-use crate::types::{AsciiResponse, HealthzResponse, IdnResponse, LookupRequest, LookupResponse};
+use crate::ascii_spoof::{self, AsciiOptions};
+use crate::cache::{CacheKind, LookupCache};
+use crate::db;
+use crate::detections::{self, TriageState};
+use crate::download;
+use crate::history;
+use crate::idn::{self, IdnOptions};
+use crate::index::{FstIndex, SqlIndex};
+use crate::kafka;
+use crate::keys::{self, Scope};
+use crate::passive_dns;
+use crate::redis_cache::RedisCache;
+use crate::release;
+use crate::scan;
+use crate::systemd;
+use crate::tenants::{self, Tenant};
+use crate::types::{
+    AsciiResponse, DatasetInfo, HealthzResponse, IdnResponse, IdnResult, LookupRequest,
+    LookupResponse, ReloadRequest, ReloadResponse, VersionResponse,
+};
+
+#[cfg(feature = "grpc")]
+mod grpc;
+
+const WATCH_DEBOUNCE: Duration = Duration::from_millis(500);
+const MIN_SIMILARITY_FLOOR: u8 = 50;
+const MAX_RESULTS_CEILING: usize = 50;
+const MAX_VARIANTS_CEILING: usize = 10_000;
+const MAX_CHECKS_CEILING: usize = 500;
+const MAX_OFFSET: usize = 1000;
+/// Domains listed per ranking in `GET /stats`.
+const STATS_TOP: usize = 10;
+/// Detections listed by `GET /detections` without a `limit`.
+const DETECTIONS_LIMIT: usize = 50;
+const WHOIS_TIMEOUT_MS_RANGE: std::ops::RangeInclusive<u64> = 100..=10_000;
+const API_KEY_HEADER: &str = "x-api-key";
+/// Routes reachable without an API key when serve has a key store: the
+/// index, health check, and API docs.
+const PUBLIC_PATHS: [&str; 2] = ["/", "/healthz"];
+const PUBLIC_PREFIXES: [&str; 2] = ["/docs", "/api-doc/"];
+const REQUEST_ID_HEADER: &str = "x-request-id";
+/// Longer incoming request ids are replaced with a generated one.
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+tokio::task_local! {
+    /// The id of the request being handled, for error bodies.
+    static REQUEST_ID: String;
+}
+
+pub struct ServeOptions {
+    pub host: String,
+    pub port: u16,
+    pub db_path: String,
+    pub use_db: bool,
+    pub admin_token: Option<String>,
+    pub watch_db: bool,
+    pub auto_update: Option<Duration>,
+    pub cache_size: usize,
+    pub cache_ttl: Duration,
+    pub grpc_port: Option<u16>,
+    pub tenant_db: Option<String>,
+    /// API key store; when set, requests need `Authorization: Bearer <key>`.
+    pub keys_db: Option<String>,
+    pub db_pool_size: u32,
+    pub in_memory: bool,
+    pub candidate_strategies: db::CandidateStrategies,
+    /// Shared-affix prefilter for DB candidates (`AsciiOptions::min_affix`).
+    pub min_affix: usize,
+    /// Named datasets (name, DB path) served next to the default DB.
+    pub datasets: BTreeMap<String, String>,
+    /// Shared cache behind the in-memory one, for instances behind a load
+    /// balancer.
+    pub redis: Option<RedisCache>,
+    /// Refuse every mutating endpoint, for a public lookup tier.
+    pub read_only: bool,
+}
 
 #[derive(Clone)]
 struct AppState {
-    db_path: String,
+    db: Arc<RwLock<DbHandle>>,
     use_db: bool,
+    admin_token: Option<String>,
+    cache: Arc<LookupCache>,
+    tenant_db: Option<String>,
+    keys_db: Option<String>,
+    candidate_strategies: db::CandidateStrategies,
+    min_affix: usize,
+    datasets: Arc<BTreeMap<String, NamedDataset>>,
+    read_only: bool,
+}
+
+struct DbHandle {
+    path: String,
+    generation: u64,
+    pool: Option<db::Pool>,
+    immutable: bool,
+    in_memory: bool,
+    indexes: PreparedDb,
+}
+
+/// A `[datasets]` DB opened at startup and selected per request. Reloads,
+/// `--watch-db`, and `--auto-update` only cover the default DB.
+#[derive(Clone)]
+struct NamedDataset {
+    pool: db::Pool,
+    indexes: PreparedDb,
+}
+
+/// Lookup structures built from a verified DB file before it is swapped in.
+#[derive(Clone, Default)]
+struct PreparedDb {
+    memory: Option<Arc<db::MemoryIndex>>,
+    fst: Option<Arc<FstIndex>>,
+    sql_index: Option<SqlIndex>,
+    dataset: Option<DatasetInfo>,
+}
+
+impl AppState {
+    fn new(db_path: String, use_db: bool) -> Self {
+        Self {
+            db: Arc::new(RwLock::new(DbHandle {
+                pool: use_db.then(|| db::pool(&db_path, db::DEFAULT_POOL_SIZE, false)),
+                path: db_path,
+                generation: 0,
+                immutable: false,
+                in_memory: false,
+                indexes: PreparedDb::default(),
+            })),
+            use_db,
+            admin_token: None,
+            cache: Arc::new(LookupCache::new(
+                crate::cache::DEFAULT_CACHE_SIZE,
+                crate::cache::DEFAULT_CACHE_TTL,
+            )),
+            tenant_db: None,
+            keys_db: None,
+            candidate_strategies: db::CandidateStrategies::default(),
+            min_affix: 0,
+            datasets: Arc::default(),
+            read_only: false,
+        }
+    }
+
+    /// Rebuilds the pool; `immutable` opens the DB without locking (see `db::open`).
+    fn with_pool(self, max_size: u32, immutable: bool) -> Self {
+        {
+            let mut handle = self
+                .db
+                .write()
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+            if handle.pool.is_some() {
+                handle.pool = Some(db::pool(&handle.path, max_size, immutable));
+                handle.immutable = immutable;
+            }
+        }
+        self
+    }
+
+    /// Serves ASCII candidates from a RAM copy of `legit_domains` instead of
+    /// the pool. The index is built by `prepare_db` and replaced on every swap.
+    fn with_in_memory(self, in_memory: bool) -> Self {
+        self.db
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .in_memory = in_memory;
+        self
+    }
+
+    /// Verifies a DB file and loads its FST index (if built) or, in in-memory
+    /// mode without one, its RAM table, and notes which index table it carries.
+    /// Blocking; the result is handed to `swap_db` (or `set_indexes` at startup).
+    fn prepare_db(&self, path: &str, sha256: Option<&str>) -> Result<PreparedDb> {
+        db::verify_db_file(path, sha256)?;
+        let conn = db::open(path, false)?;
+        let in_memory = self
+            .db
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .in_memory;
+        let fst = FstIndex::open_for_db(path)?.map(Arc::new);
+        // Lookups go to the FST when there is one, so the RAM table would
+        // never be read.
+        let memory = match (in_memory, &fst) {
+            (true, Some(_)) => {
+                warn!(
+                    kind = "in_memory",
+                    db_path = %path,
+                    "--in-memory ignored: the FST index of this DB answers lookups instead"
+                );
+                None
+            }
+            (true, None) => Some(Arc::new(db::MemoryIndex::load(&conn)?)),
+            (false, _) => None,
+        };
+        let sql_index = SqlIndex::detect(&conn)?;
+        let dataset = release::LocalDataset::from_conn(&conn)?.info();
+        Ok(PreparedDb {
+            memory,
+            fst,
+            sql_index,
+            dataset: Some(dataset),
+        })
+    }
+
+    fn set_indexes(&self, indexes: PreparedDb) {
+        self.db
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .indexes = indexes;
+    }
+
+    fn db_indexes(&self) -> PreparedDb {
+        self.db
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .indexes
+            .clone()
+    }
+
+    /// The pool and indexes of the named dataset, or of the default DB.
+    fn lookup_db(&self, dataset: Option<&str>) -> Result<(Option<db::Pool>, PreparedDb)> {
+        match dataset {
+            Some(name) => {
+                let dataset = self
+                    .datasets
+                    .get(name)
+                    .ok_or_else(|| anyhow::anyhow!("unknown dataset {name}"))?;
+                Ok((Some(dataset.pool.clone()), dataset.indexes.clone()))
+            }
+            None => Ok((self.db_pool(), self.db_indexes())),
+        }
+    }
+
+    /// Rejects a request for a dataset this server does not have.
+    fn check_dataset(&self, dataset: Option<&str>) -> Result<(), String> {
+        match dataset {
+            Some(name) if !self.datasets.contains_key(name) => {
+                Err(format!("unknown dataset {name}"))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// A cache key for a lookup of `key` against the named dataset. It
+    /// carries the dataset's version and size, so instances sharing a Redis
+    /// cache never answer from a different DB.
+    fn cache_key(&self, key: String, dataset: Option<&str>) -> String {
+        match self.dataset_info(dataset) {
+            Some(info) => format!(
+                "{key}|{}:{}",
+                info.version.as_deref().unwrap_or("-"),
+                info.domains
+            ),
+            None => key,
+        }
+    }
+
+    /// The cached response for `key`; Redis is only asked off the async
+    /// workers.
+    async fn cached(&self, kind: CacheKind, key: &str) -> Option<LookupResponse> {
+        if !self.cache.is_shared() {
+            return self.cache.get(kind, key);
+        }
+        let (cache, key) = (self.cache.clone(), key.to_string());
+        tokio::task::spawn_blocking(move || cache.get(kind, &key))
+            .await
+            .ok()
+            .flatten()
+    }
+
+    async fn cache_response(&self, kind: CacheKind, key: String, response: LookupResponse) {
+        if !self.cache.is_shared() {
+            return self.cache.insert(kind, &key, response);
+        }
+        let cache = self.cache.clone();
+        let _ = tokio::task::spawn_blocking(move || cache.insert(kind, &key, response)).await;
+    }
+
+    /// The metadata of the named dataset, or of the default DB.
+    fn dataset_info(&self, dataset: Option<&str>) -> Option<DatasetInfo> {
+        match dataset {
+            Some(name) => self
+                .datasets
+                .get(name)
+                .and_then(|dataset| dataset.indexes.dataset.clone()),
+            None => self.dataset(),
+        }
+    }
+
+    fn dataset(&self) -> Option<DatasetInfo> {
+        self.db
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .indexes
+            .dataset
+            .clone()
+    }
+
+    fn db_path(&self) -> String {
+        self.db
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .path
+            .clone()
+    }
+
+    fn db_pool(&self) -> Option<db::Pool> {
+        self.db
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .pool
+            .clone()
+    }
+
+    fn db_generation(&self) -> u64 {
+        self.db
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .generation
+    }
+
+    fn swap_db(&self, path: String, indexes: PreparedDb) -> u64 {
+        let mut handle = self
+            .db
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        handle.indexes = indexes;
+        if let Some(pool) = &handle.pool {
+            handle.pool = Some(db::pool(&path, pool.max_size(), handle.immutable));
+        }
+        handle.path = path;
+        handle.generation += 1;
+        self.cache.clear();
+        handle.generation
+    }
 }
 
 #[derive(OpenApi)]
 #[openapi(
-	paths(healthz, lookup, ascii, idn_lookup),
+	paths(
+		healthz,
+		version,
+		metrics,
+		stats,
+		lookup,
+		ascii,
+		idn_lookup,
+		idn_stream,
+		admin_reload,
+		admin_usage,
+		list_detections,
+		triage_detection
+	),
 	components(
 		schemas(
 			LookupRequest,
+			VersionResponse,
+			DatasetInfo,
+			ReloadRequest,
+			ReloadResponse,
 			HealthzResponse,
 			LookupResponse,
 			AsciiResponse,
 			IdnResponse,
 			crate::types::AsciiResult,
+			crate::types::ScoreComponent,
 			crate::types::IdnResult,
-			crate::types::PunyMapping
+			crate::types::IdnaError,
+			crate::types::PassiveDns,
+			crate::types::PunyMapping,
+			crate::types::RegistrationStatus,
+			crate::types::Algorithm,
+			history::Stats,
+			history::DomainCount,
+			history::WindowStats,
+			keys::KeyUsage,
+			keys::Scope,
+			detections::Detection,
+			detections::Severity,
+			detections::TriageState,
+			TriageRequest
 		)
 	),
 	tags(
@@ -46,22 +426,195 @@ struct AppState {
 )]
 struct ApiDoc;
 
-pub async fn serve(host: String, port: u16, db_path: String, use_db: bool) -> Result<()> {
-    let state = AppState { db_path, use_db };
+pub async fn serve(options: ServeOptions) -> Result<()> {
+    let ServeOptions {
+        host,
+        port,
+        db_path,
+        use_db,
+        admin_token,
+        watch_db,
+        auto_update,
+        cache_size,
+        cache_ttl,
+        grpc_port,
+        tenant_db,
+        keys_db,
+        db_pool_size,
+        in_memory,
+        candidate_strategies,
+        min_affix,
+        datasets,
+        redis,
+        read_only,
+    } = options;
+    if let Some(path) = &tenant_db {
+        tenants::open(path)?;
+    }
+    if let Some(path) = &keys_db {
+        keys::open(path)?;
+    }
+    let mut state = AppState {
+        admin_token,
+        tenant_db,
+        keys_db,
+        candidate_strategies,
+        min_affix,
+        read_only,
+        cache: Arc::new(match redis {
+            Some(redis) => LookupCache::new(cache_size, cache_ttl).with_shared(redis),
+            None => LookupCache::new(cache_size, cache_ttl),
+        }),
+        ..AppState::new(db_path, use_db)
+    }
+    // Downloaded artifacts are only ever replaced by rename, so they can be
+    // opened immutable; --watch-db also covers tools that rewrite in place.
+    .with_pool(db_pool_size, !watch_db)
+    .with_in_memory(in_memory && use_db);
+    if use_db {
+        let loader = state.clone();
+        let indexes =
+            tokio::task::spawn_blocking(move || loader.prepare_db(&loader.db_path(), None))
+                .await??;
+        if let Some(memory) = &indexes.memory {
+            info!(kind = "in_memory", domains = memory.len());
+        }
+        if let Some(fst) = &indexes.fst {
+            info!(kind = "index", fst_domains = fst.len());
+        }
+        state.set_indexes(indexes);
+
+        let mut named = BTreeMap::new();
+        for (name, path) in datasets {
+            let loader = state.clone();
+            let prepare_path = path.clone();
+            let indexes =
+                tokio::task::spawn_blocking(move || loader.prepare_db(&prepare_path, None))
+                    .await?
+                    .map_err(|err| anyhow::anyhow!("dataset {name} ({path}): {err}"))?;
+            info!(kind = "dataset", name = %name, db_path = %path, domains = indexes.dataset.as_ref().map_or(0, |dataset| dataset.domains));
+            let pool = db::pool(&path, db_pool_size, !watch_db);
+            named.insert(name, NamedDataset { pool, indexes });
+        }
+        state.datasets = Arc::new(named);
+    }
+    if use_db && watch_db {
+        spawn_db_watcher(state.clone())?;
+    }
+    if let Some(period) = auto_update.filter(|_| use_db) {
+        tokio::spawn(auto_update_loop(
+            state.clone(),
+            db::DEFAULT_DB_URL.to_string(),
+            period,
+        ));
+    }
+    let grpc_server: Option<tokio::task::JoinHandle<Result<()>>> = match grpc_port {
+        #[cfg(feature = "grpc")]
+        Some(grpc_port) => {
+            let addr: SocketAddr = format!("{host}:{grpc_port}").parse()?;
+            Some(tokio::spawn(grpc::serve(state.clone(), addr)))
+        }
+        #[cfg(not(feature = "grpc"))]
+        Some(_) => {
+            return Err(anyhow::anyhow!(
+                "--grpc-port requires a build with the `grpc` feature"
+            ))
+        }
+        None => None,
+    };
+    let watchdog = systemd::watchdog_interval().map(|interval| (state.clone(), interval));
+    let app = router(state);
+
+    let listener = match systemd::listener()? {
+        Some(listener) => {
+            let listener = tokio::net::TcpListener::from_std(listener)?;
+            println!(
+                "Listening on http://{} (socket activation)",
+                listener.local_addr()?
+            );
+            listener
+        }
+        None => {
+            let addr: SocketAddr = format!("{host}:{port}").parse()?;
+            println!("Listening on http://{addr}");
+            tokio::net::TcpListener::bind(addr).await?
+        }
+    };
+    systemd::notify("READY=1");
+    if let Some((state, interval)) = watchdog {
+        info!(kind = "systemd", watchdog_secs = interval.as_secs_f64());
+        tokio::spawn(watchdog_loop(state, interval));
+    }
+    match grpc_server {
+        Some(grpc_server) => {
+            tokio::select! {
+                result = axum::serve(listener, app) => result?,
+                result = grpc_server => result??,
+            }
+        }
+        None => axum::serve(listener, app).await?,
+    }
+    Ok(())
+}
+
+fn router(state: AppState) -> Router {
     let openapi = ApiDoc::openapi();
-    let app = Router::new()
+    Router::new()
         .route("/", get(index))
         .route("/healthz", get(healthz))
+        .route("/version", get(version))
+        .route("/metrics", get(metrics))
+        .route("/stats", get(stats))
         .route("/lookup", post(lookup))
         .route("/ascii", post(ascii))
         .route("/idn", post(idn_lookup))
-        .with_state(state)
-        .merge(SwaggerUi::new("/docs").url("/api-doc/openapi.json", openapi));
+        .route("/idn/stream", post(idn_stream))
+        .route("/ws", get(ws_upgrade))
+        .route("/admin/reload", post(admin_reload))
+        .route("/admin/usage", get(admin_usage))
+        .route("/detections", get(list_detections))
+        .route("/detections/:id", post(triage_detection))
+        .with_state(state.clone())
+        .merge(SwaggerUi::new("/docs").url("/api-doc/openapi.json", openapi))
+        .layer(middleware::from_fn_with_state(state, authenticate))
+        .layer(middleware::from_fn(request_id))
+        .layer(CompressionLayer::new().gzip(true).br(true))
+}
 
-    let addr: SocketAddr = format!("{host}:{port}").parse()?;
-    println!("Listening on http://{addr}");
-    axum::serve(tokio::net::TcpListener::bind(addr).await?, app).await?;
-    Ok(())
+/// Runs the request under the caller's `X-Request-Id`, or a generated one
+/// when it is missing or unusable, and echoes the id on the response.
+async fn request_id(request: Request, next: Next) -> Response {
+    let id = request
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .filter(|id| !id.is_empty() && id.len() <= MAX_REQUEST_ID_LEN)
+        .map(str::to_string)
+        .unwrap_or_else(new_request_id);
+    let span = info_span!(
+        "request",
+        request_id = %id,
+        method = %request.method(),
+        path = %request.uri().path()
+    );
+    let mut response = REQUEST_ID
+        .scope(id.clone(), next.run(request))
+        .instrument(span.clone())
+        .await;
+    let status = response.status();
+    if status.is_server_error() {
+        span.in_scope(|| warn!(kind = "http", status = status.as_u16()));
+    }
+    if let Ok(value) = HeaderValue::from_str(&id) {
+        response.headers_mut().insert(REQUEST_ID_HEADER, value);
+    }
+    response
+}
+
+fn new_request_id() -> String {
+    let mut bytes = [0u8; 16];
+    rand::RngCore::fill_bytes(&mut rand::thread_rng(), &mut bytes);
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
 }
 
 async fn index() -> Html<String> {
@@ -76,9 +629,18 @@ async fn index() -> Html<String> {
     <p>Available routes:</p>
     <ul>
       <li>GET /healthz</li>
+      <li>GET /version</li>
+      <li>GET /metrics</li>
+      <li>GET /stats</li>
       <li>POST /lookup</li>
       <li>POST /ascii</li>
       <li>POST /idn</li>
+      <li>POST /idn/stream</li>
+      <li>GET /ws (WebSocket)</li>
+      <li>POST /admin/reload</li>
+      <li>GET /admin/usage</li>
+      <li>GET /detections</li>
+      <li>POST /detections/{id}</li>
       <li>GET /docs</li>
     </ul>
   </body>
@@ -96,82 +658,858 @@ async fn healthz() -> Json<HealthzResponse> {
     Json(HealthzResponse { ok: true })
 }
 
+#[utoipa::path(
+	get,
+	path = "/version",
+	tag = "spotspoof",
+	responses((status = 200, body = VersionResponse))
+)]
+async fn version(State(state): State<AppState>) -> Json<VersionResponse> {
+    Json(VersionResponse {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        db_path: state.db_path(),
+        db_generation: state.db_generation(),
+        dataset: state.dataset(),
+        datasets: state
+            .datasets
+            .iter()
+            .filter_map(|(name, dataset)| Some((name.clone(), dataset.indexes.dataset.clone()?)))
+            .collect(),
+    })
+}
+
+#[utoipa::path(
+	get,
+	path = "/metrics",
+	tag = "spotspoof",
+	responses((status = 200, description = "Prometheus text exposition", content_type = "text/plain"))
+)]
+async fn metrics(State(state): State<AppState>) -> Response {
+    let mut body = format!(
+        "# HELP spotspoof_cache_hits_total Lookup responses served from the cache.\n\
+         # TYPE spotspoof_cache_hits_total counter\n\
+         spotspoof_cache_hits_total {}\n\
+         # HELP spotspoof_cache_misses_total Lookups that were not served from the cache.\n\
+         # TYPE spotspoof_cache_misses_total counter\n\
+         spotspoof_cache_misses_total {}\n\
+         # HELP spotspoof_cache_entries Lookup responses currently cached.\n\
+         # TYPE spotspoof_cache_entries gauge\n\
+         spotspoof_cache_entries {}\n\
+         # HELP spotspoof_cache_shared_hits_total Lookup responses served from the shared Redis cache.\n\
+         # TYPE spotspoof_cache_shared_hits_total counter\n\
+         spotspoof_cache_shared_hits_total {}\n\
+         # HELP spotspoof_db_generation Number of times the DB has been reloaded.\n\
+         # TYPE spotspoof_db_generation gauge\n\
+         spotspoof_db_generation {}\n",
+        state.cache.hits(),
+        state.cache.misses(),
+        state.cache.len(),
+        state.cache.shared_hits(),
+        state.db_generation(),
+    );
+    let indexes = state.db_indexes();
+    if let Some(memory) = &indexes.memory {
+        body.push_str(&format!(
+            "# HELP spotspoof_db_in_memory_domains Domains held by the in-memory index.\n\
+             # TYPE spotspoof_db_in_memory_domains gauge\n\
+             spotspoof_db_in_memory_domains {}\n",
+            memory.len(),
+        ));
+    }
+    if let Some(fst) = &indexes.fst {
+        body.push_str(&format!(
+            "# HELP spotspoof_db_fst_domains Domains in the FST fuzzy index.\n\
+             # TYPE spotspoof_db_fst_domains gauge\n\
+             spotspoof_db_fst_domains {}\n",
+            fst.len(),
+        ));
+    }
+    if let Some(pool) = state.db_pool() {
+        let pool_state = pool.state();
+        body.push_str(&format!(
+            "# HELP spotspoof_db_pool_max_connections Configured DB connection pool size.\n\
+             # TYPE spotspoof_db_pool_max_connections gauge\n\
+             spotspoof_db_pool_max_connections {}\n\
+             # HELP spotspoof_db_pool_connections DB connections currently open.\n\
+             # TYPE spotspoof_db_pool_connections gauge\n\
+             spotspoof_db_pool_connections {}\n\
+             # HELP spotspoof_db_pool_idle_connections Open DB connections not in use.\n\
+             # TYPE spotspoof_db_pool_idle_connections gauge\n\
+             spotspoof_db_pool_idle_connections {}\n",
+            pool.max_size(),
+            pool_state.connections,
+            pool_state.idle_connections,
+        ));
+    }
+    (
+        StatusCode::OK,
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        body,
+    )
+        .into_response()
+}
+
+#[utoipa::path(
+	get,
+	path = "/stats",
+	tag = "spotspoof",
+	responses(
+		(status = 200, body = history::Stats),
+		(status = 404, description = "Lookup history is disabled")
+	)
+)]
+async fn stats() -> Response {
+    let Some(history) = history::installed() else {
+        return error_response(
+            StatusCode::NOT_FOUND,
+            "lookup history is disabled; start serve with --history",
+        );
+    };
+    match tokio::task::spawn_blocking(move || history.stats(STATS_TOP)).await {
+        Ok(Ok(stats)) => (StatusCode::OK, Json(stats)).into_response(),
+        Ok(Err(err)) => server_error(err),
+        Err(err) => server_error(err),
+    }
+}
+
 #[utoipa::path(
 	post,
 	path = "/lookup",
 	tag = "spotspoof",
 	request_body = LookupRequest,
-	responses((status = 200, body = LookupResponse))
+	params(
+		("X-API-Key" = Option<String>, Header, description = "Tenant API key; adds the tenant's brands and allowlist to scoring")
+	),
+	responses(
+		(status = 200, body = LookupResponse),
+		(status = 400, description = "Invalid request parameters"),
+		(status = 401, description = "Unknown tenant API key")
+	)
 )]
-async fn lookup(State(state): State<AppState>, Json(payload): Json<LookupRequest>) -> Response {
+async fn lookup(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(payload): Json<LookupRequest>,
+) -> Response {
+    let overrides = match request_overrides(&state, &payload) {
+        Ok(overrides) => overrides,
+        Err(message) => return error_response(StatusCode::BAD_REQUEST, message),
+    };
+    let tenant = match request_tenant(&state, &headers).await {
+        Ok(tenant) => tenant,
+        Err((status, message)) => return error_response(status, message),
+    };
     let domain = crate::types::normalize_domain_input(&payload.domain);
-    let is_idn = domain.starts_with("xn--") || domain.chars().any(|c| c as u32 > 127);
-    if is_idn {
-        let response = tokio::task::spawn_blocking(move || idn::lookup_idn(&domain)).await;
-        match response {
-            Ok(Ok(result)) => {
-                let wrapped = LookupResponse::Idn(result);
-                if let Err(err) = crate::types::validate_lookup_response(&wrapped) {
-                    return server_error(err);
+    let is_idn = crate::types::is_idn(&domain);
+    let result = if is_idn {
+        idn_result(&state, domain, overrides.idn, overrides.dataset)
+            .await
+            .map(|mut response| {
+                page(&mut response.results, overrides.offset);
+                LookupResponse::Idn(response)
+            })
+    } else {
+        ascii_result(&state, domain, overrides.ascii, overrides.dataset)
+            .await
+            .map(|mut response| {
+                if let Some(tenant) = &tenant {
+                    tenants::apply(tenant, &mut response, &overrides.ascii);
                 }
-                (StatusCode::OK, Json(wrapped)).into_response()
+                page(&mut response.results, overrides.offset);
+                LookupResponse::Ascii(response)
+            })
+    };
+
+    match result {
+        Ok(wrapped) => {
+            if let Err(err) = crate::types::validate_lookup_response(&wrapped) {
+                return server_error(err);
             }
-            Ok(Err(err)) => server_error(err),
-            Err(err) => server_error(err),
+            (StatusCode::OK, Json(wrapped)).into_response()
         }
-    } else {
-        if !state.use_db {
-            let wrapped = LookupResponse::Ascii(crate::types::empty_ascii_response(&domain));
-            if let Err(err) = crate::types::validate_lookup_response(&wrapped) {
+        Err(err) => server_error(err),
+    }
+}
+
+#[utoipa::path(
+	post,
+	path = "/ascii",
+	tag = "spotspoof",
+	request_body = LookupRequest,
+	params(
+		("X-API-Key" = Option<String>, Header, description = "Tenant API key; adds the tenant's brands and allowlist to scoring")
+	),
+	responses(
+		(status = 200, body = AsciiResponse),
+		(status = 400, description = "Invalid request parameters"),
+		(status = 401, description = "Unknown tenant API key")
+	)
+)]
+async fn ascii(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(payload): Json<LookupRequest>,
+) -> Response {
+    let overrides = match request_overrides(&state, &payload) {
+        Ok(overrides) => overrides,
+        Err(message) => return error_response(StatusCode::BAD_REQUEST, message),
+    };
+    let tenant = match request_tenant(&state, &headers).await {
+        Ok(tenant) => tenant,
+        Err((status, message)) => return error_response(status, message),
+    };
+    let domain = crate::types::normalize_domain_input(&payload.domain);
+    match ascii_result(&state, domain, overrides.ascii, overrides.dataset).await {
+        Ok(mut response) => {
+            if let Some(tenant) = &tenant {
+                tenants::apply(tenant, &mut response, &overrides.ascii);
+            }
+            page(&mut response.results, overrides.offset);
+            if let Err(err) = crate::types::validate_ascii_response(&response) {
                 return server_error(err);
             }
-            return (StatusCode::OK, Json(wrapped)).into_response();
+            (StatusCode::OK, Json(response)).into_response()
         }
+        Err(err) => server_error(err),
+    }
+}
 
-        let db_path = state.db_path.clone();
-        let response =
-            tokio::task::spawn_blocking(move || ascii_spoof::lookup_ascii(&domain, &db_path)).await;
-        match response {
-            Ok(Ok(result)) => {
-                let wrapped = LookupResponse::Ascii(result);
-                if let Err(err) = crate::types::validate_lookup_response(&wrapped) {
-                    return server_error(err);
-                }
-                (StatusCode::OK, Json(wrapped)).into_response()
+#[utoipa::path(
+	post,
+	path = "/idn",
+	tag = "spotspoof",
+	request_body = LookupRequest,
+	responses(
+		(status = 200, body = IdnResponse),
+		(status = 400, description = "Invalid request parameters")
+	)
+)]
+async fn idn_lookup(State(state): State<AppState>, Json(payload): Json<LookupRequest>) -> Response {
+    let overrides = match request_overrides(&state, &payload) {
+        Ok(overrides) => overrides,
+        Err(message) => return error_response(StatusCode::BAD_REQUEST, message),
+    };
+    let domain = crate::types::normalize_domain_input(&payload.domain);
+    match idn_result(&state, domain, overrides.idn, overrides.dataset).await {
+        Ok(mut response) => {
+            page(&mut response.results, overrides.offset);
+            if let Err(err) = crate::types::validate_idn_response(&response) {
+                return server_error(err);
             }
-            Ok(Err(err)) => server_error(err),
-            Err(err) => server_error(err),
+            (StatusCode::OK, Json(response)).into_response()
         }
+        Err(err) => server_error(err),
     }
 }
 
 #[utoipa::path(
 	post,
-	path = "/ascii",
+	path = "/idn/stream",
 	tag = "spotspoof",
 	request_body = LookupRequest,
-	responses((status = 200, body = AsciiResponse))
+	responses(
+		(status = 200, description = "Server-Sent Events: one `result` event per confirmed variant (IdnResult), then a `summary` event (IdnResponse) or an `error` event", content_type = "text/event-stream"),
+		(status = 400, description = "Invalid request parameters")
+	)
 )]
-async fn ascii(State(state): State<AppState>, Json(payload): Json<LookupRequest>) -> Response {
+async fn idn_stream(State(state): State<AppState>, Json(payload): Json<LookupRequest>) -> Response {
+    let overrides = match request_overrides(&state, &payload) {
+        Ok(overrides) => overrides,
+        Err(message) => return error_response(StatusCode::BAD_REQUEST, message),
+    };
+    // Variants stream as they are confirmed, before their rank is known.
+    if overrides.offset > 0 {
+        return error_response(
+            StatusCode::BAD_REQUEST,
+            "offset is not supported on /idn/stream".to_string(),
+        );
+    }
     let domain = crate::types::normalize_domain_input(&payload.domain);
+    let (tx, rx) = tokio::sync::mpsc::channel::<Event>(32);
+
+    tokio::spawn(async move {
+        let progress = tx.clone();
+        let result = idn_result_streaming(
+            &state,
+            domain,
+            overrides.idn,
+            overrides.dataset,
+            move |result| {
+                if let Ok(event) = Event::default().event("result").json_data(result) {
+                    let _ = progress.blocking_send(event);
+                }
+            },
+        )
+        .await;
+        let last = match result.and_then(|response| {
+            crate::types::validate_idn_response(&response)?;
+            Ok(Event::default().event("summary").json_data(&response)?)
+        }) {
+            Ok(event) => event,
+            Err(err) => Event::default().event("error").data(err.to_string()),
+        };
+        let _ = tx.send(last).await;
+    });
+
+    let stream = ReceiverStream::new(rx).map(Ok::<_, Infallible>);
+    Sse::new(stream)
+        .keep_alive(KeepAlive::default())
+        .into_response()
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum WsMode {
+    Lookup,
+    Ascii,
+    Idn,
+}
+
+/// One WebSocket request: a `LookupRequest` plus an optional client-chosen
+/// `id` echoed on every reply, and the analysis `mode` (default `lookup`).
+#[derive(Deserialize)]
+struct WsRequest {
+    #[serde(default)]
+    id: Option<serde_json::Value>,
+    #[serde(default)]
+    mode: Option<WsMode>,
+    #[serde(flatten)]
+    request: LookupRequest,
+}
+
+/// The tenant is resolved once, from the upgrade request's `X-API-Key`, and
+/// applied to every ASCII lookup of the session as on /ascii.
+async fn ws_upgrade(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    ws: WebSocketUpgrade,
+) -> Response {
+    let tenant = match request_tenant(&state, &headers).await {
+        Ok(tenant) => tenant,
+        Err((status, message)) => return error_response(status, message),
+    };
+    ws.on_upgrade(move |socket| ws_session(state, tenant, socket))
+}
+
+async fn ws_session(state: AppState, tenant: Option<Tenant>, socket: WebSocket) {
+    let (mut sink, mut stream) = futures_util::StreamExt::split(socket);
+    let (tx, mut rx) = tokio::sync::mpsc::channel::<String>(32);
+    let forward = tokio::spawn(async move {
+        while let Some(text) = rx.recv().await {
+            if sink.send(Message::Text(text)).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    while let Some(Ok(message)) = stream.next().await {
+        match message {
+            Message::Text(text) => handle_ws_text(&state, tenant.as_ref(), &text, &tx).await,
+            Message::Close(_) => break,
+            _ => {}
+        }
+    }
+    drop(tx);
+    let _ = forward.await;
+}
+
+/// Handles one text frame, sending `progress` replies for IDN variants as they
+/// are confirmed, then a single `response` (or `error`) reply.
+async fn handle_ws_text(
+    state: &AppState,
+    tenant: Option<&Tenant>,
+    text: &str,
+    out: &tokio::sync::mpsc::Sender<String>,
+) {
+    let reply = |id: &Option<serde_json::Value>, kind: &str, body: serde_json::Value| {
+        let mut reply = json!({ "type": kind });
+        if let Some(id) = id {
+            reply["id"] = id.clone();
+        }
+        let field = if kind == "error" { "error" } else { "data" };
+        reply[field] = body;
+        reply.to_string()
+    };
+
+    let WsRequest { id, mode, request } = match serde_json::from_str::<WsRequest>(text) {
+        Ok(parsed) => parsed,
+        Err(err) => {
+            let _ = out
+                .send(reply(&None, "error", json!(err.to_string())))
+                .await;
+            return;
+        }
+    };
+    let overrides = match request_overrides(state, &request) {
+        Ok(overrides) => overrides,
+        Err(message) => {
+            let _ = out.send(reply(&id, "error", json!(message))).await;
+            return;
+        }
+    };
+    let domain = crate::types::normalize_domain_input(&request.domain);
+    let is_idn = match mode.unwrap_or(WsMode::Lookup) {
+        WsMode::Lookup => crate::types::is_idn(&domain),
+        WsMode::Ascii => false,
+        WsMode::Idn => true,
+    };
+
+    let result = if is_idn {
+        let progress = out.clone();
+        let progress_id = id.clone();
+        idn_result_streaming(
+            state,
+            domain,
+            overrides.idn,
+            overrides.dataset,
+            move |result| {
+                let body = serde_json::to_value(result).unwrap_or_default();
+                let _ = progress.blocking_send(reply(&progress_id, "progress", body));
+            },
+        )
+        .await
+        .map(|mut response| {
+            page(&mut response.results, overrides.offset);
+            LookupResponse::Idn(response)
+        })
+    } else {
+        ascii_result(state, domain, overrides.ascii, overrides.dataset)
+            .await
+            .map(|mut response| {
+                if let Some(tenant) = tenant {
+                    tenants::apply(tenant, &mut response, &overrides.ascii);
+                }
+                page(&mut response.results, overrides.offset);
+                LookupResponse::Ascii(response)
+            })
+    };
+
+    let message = match result.and_then(|response| Ok(serde_json::to_value(response)?)) {
+        Ok(body) => reply(&id, "response", body),
+        Err(err) => reply(&id, "error", json!(err.to_string())),
+    };
+    let _ = out.send(message).await;
+}
+
+/// Per-request tuning from `LookupRequest`, validated against server-side
+/// bounds and layered over the defaults (and IDN env knobs).
+pub(crate) struct LookupOverrides {
+    pub(crate) ascii: AsciiOptions,
+    pub(crate) idn: IdnOptions,
+    pub(crate) dataset: Option<String>,
+    /// Ranked results before the requested page; the lookup limits count
+    /// them too, so each page is ranked against everything before it.
+    pub(crate) offset: usize,
+}
+
+impl LookupOverrides {
+    pub(crate) fn from_request(payload: &LookupRequest) -> Result<Self, String> {
+        crate::types::validate_domain_input(&payload.domain).map_err(|err| err.to_string())?;
+        let mut ascii = AsciiOptions::default();
+        let mut idn = IdnOptions::from_env();
+
+        if let Some(min_similarity) = payload.min_similarity {
+            if !(MIN_SIMILARITY_FLOOR..=100).contains(&min_similarity) {
+                return Err(format!(
+                    "min_similarity must be between {MIN_SIMILARITY_FLOOR} and 100"
+                ));
+            }
+            ascii.min_similarity = min_similarity;
+        }
+        if let Some(max_results) = payload.max_results {
+            if !(1..=MAX_RESULTS_CEILING).contains(&max_results) {
+                return Err(format!(
+                    "max_results must be between 1 and {MAX_RESULTS_CEILING}"
+                ));
+            }
+            ascii.max_results = max_results;
+            idn.max_results = max_results;
+            idn.max_results_timeout = idn.max_results_timeout.min(max_results);
+        }
+        if let Some(limit) = payload.limit {
+            if !(1..=MAX_RESULTS_CEILING).contains(&limit) {
+                return Err(format!("limit must be between 1 and {MAX_RESULTS_CEILING}"));
+            }
+            ascii.max_results = limit;
+            idn.max_results = limit;
+            idn.max_results_timeout = idn.max_results_timeout.min(limit);
+        }
+        let offset = payload.offset.unwrap_or(0);
+        if offset > MAX_OFFSET {
+            return Err(format!("offset must be between 0 and {MAX_OFFSET}"));
+        }
+        ascii.max_results += offset;
+        idn.max_results += offset;
+        idn.max_results_timeout += offset;
+        if let Some(algorithm) = payload.algorithm {
+            ascii.algorithm = algorithm;
+        }
+        if let Some(leet) = payload.leet {
+            ascii.leet = leet;
+        }
+        if let Some(phonetic) = payload.phonetic {
+            ascii.phonetic = phonetic;
+        }
+        if let Some(check_registration) = payload.check_registration {
+            idn.check_registration = check_registration;
+        }
+        if let Some(max_variants) = payload.max_variants {
+            if !(1..=MAX_VARIANTS_CEILING).contains(&max_variants) {
+                return Err(format!(
+                    "max_variants must be between 1 and {MAX_VARIANTS_CEILING}"
+                ));
+            }
+            idn.max_normalized = max_variants;
+        }
+        if let Some(max_checks) = payload.max_checks {
+            if !(1..=MAX_CHECKS_CEILING).contains(&max_checks) {
+                return Err(format!(
+                    "max_checks must be between 1 and {MAX_CHECKS_CEILING}"
+                ));
+            }
+            idn.max_whois_checks = max_checks;
+        }
+        if let Some(timeout_ms) = payload.whois_timeout_ms {
+            if !WHOIS_TIMEOUT_MS_RANGE.contains(&timeout_ms) {
+                return Err(format!(
+                    "whois_timeout_ms must be between {} and {}",
+                    WHOIS_TIMEOUT_MS_RANGE.start(),
+                    WHOIS_TIMEOUT_MS_RANGE.end()
+                ));
+            }
+            idn.whois_timeout_ms = timeout_ms;
+        }
+        if let Some(transitional) = payload.idna_transitional {
+            idn.idna.transitional = transitional;
+        }
+        if let Some(std3_rules) = payload.idna_std3_rules {
+            idn.idna.use_std3_ascii_rules = std3_rules;
+        }
+        if let Some(overflow) = payload.punycode_overflow {
+            idn.idna.punycode_overflow = overflow;
+        }
+
+        Ok(Self {
+            ascii,
+            idn,
+            dataset: payload.dataset.clone(),
+            offset,
+        })
+    }
+}
+
+/// Drops the `offset` ranked results before the requested page.
+pub(crate) fn page<T>(results: &mut Vec<T>, offset: usize) {
+    results.drain(..offset.min(results.len()));
+}
+
+/// `LookupOverrides::from_request`, also rejecting datasets `state` lacks.
+fn request_overrides(state: &AppState, payload: &LookupRequest) -> Result<LookupOverrides, String> {
+    let overrides = LookupOverrides::from_request(payload)?;
+    state.check_dataset(overrides.dataset.as_deref())?;
+    Ok(overrides)
+}
+
+/// Resolves the tenant named by `X-API-Key`. Requests without a key (or when
+/// serve has no tenant DB) are scored against the global dataset only.
+async fn request_tenant(
+    state: &AppState,
+    headers: &HeaderMap,
+) -> Result<Option<Tenant>, (StatusCode, String)> {
+    let (Some(path), Some(key)) = (state.tenant_db.clone(), headers.get(API_KEY_HEADER)) else {
+        return Ok(None);
+    };
+    let key = key
+        .to_str()
+        .map_err(|_| (StatusCode::UNAUTHORIZED, "Invalid API key".to_string()))?
+        .to_string();
+    let found = tokio::task::spawn_blocking(move || {
+        let conn = tenants::open(&path)?;
+        tenants::find_by_key(&conn, &key)
+    })
+    .await;
+
+    match found {
+        Ok(Ok(Some(tenant))) => Ok(Some(tenant)),
+        Ok(Ok(None)) => Err((StatusCode::UNAUTHORIZED, "Invalid API key".to_string())),
+        Ok(Err(err)) => Err((StatusCode::INTERNAL_SERVER_ERROR, err.to_string())),
+        Err(err) => Err((StatusCode::INTERNAL_SERVER_ERROR, err.to_string())),
+    }
+}
+
+async fn ascii_result(
+    state: &AppState,
+    domain: String,
+    options: AsciiOptions,
+    dataset: Option<String>,
+) -> Result<AsciiResponse> {
     if !state.use_db {
-        let response = crate::types::empty_ascii_response(&domain);
-        if let Err(err) = crate::types::validate_ascii_response(&response) {
-            return server_error(err);
+        return Ok(crate::types::empty_ascii_response(&domain));
+    }
+    let options = AsciiOptions {
+        strategies: state.candidate_strategies,
+        min_affix: state.min_affix,
+        ..options
+    };
+    let cache_key = state.cache_key(
+        format!("{domain}|{dataset:?}|{options:?}"),
+        dataset.as_deref(),
+    );
+    if let Some(LookupResponse::Ascii(cached)) = state.cached(CacheKind::Ascii, &cache_key).await {
+        record_history(LookupResponse::Ascii(cached.clone()));
+        return Ok(cached);
+    }
+
+    let (
+        pool,
+        PreparedDb {
+            memory,
+            fst,
+            sql_index,
+            dataset,
+        },
+    ) = state.lookup_db(dataset.as_deref())?;
+    let mut response = tokio::task::spawn_blocking(move || {
+        if let Some(fst) = fst {
+            return ascii_spoof::lookup_ascii_with(&domain, || Ok(fst), &options);
+        }
+        if let Some(memory) = memory {
+            return ascii_spoof::lookup_ascii_with(&domain, || Ok(memory), &options);
         }
-        return (StatusCode::OK, Json(response)).into_response();
+        let pool = pool.ok_or_else(|| anyhow::anyhow!("DB pool is not configured"))?;
+        if let Some(sql_index) = sql_index {
+            return ascii_spoof::lookup_ascii_with(
+                &domain,
+                || Ok(Box::new(sql_index.with(pool.get()?))),
+                &options,
+            );
+        }
+        ascii_spoof::lookup_ascii_with(&domain, || Ok(pool.get()?), &options)
+    })
+    .await??;
+    response.dataset = dataset;
+    if passive_dns::installed() {
+        response = tokio::task::spawn_blocking(move || {
+            passive_dns::enrich_ascii(&mut response);
+            response
+        })
+        .await?;
     }
-    let db_path = state.db_path.clone();
-    let result =
-        tokio::task::spawn_blocking(move || ascii_spoof::lookup_ascii(&domain, &db_path)).await;
+    state
+        .cache_response(
+            CacheKind::Ascii,
+            cache_key,
+            LookupResponse::Ascii(response.clone()),
+        )
+        .await;
+    record_history(LookupResponse::Ascii(response.clone()));
+    Ok(response)
+}
 
-    match result {
-        Ok(Ok(response)) => {
-            if let Err(err) = crate::types::validate_ascii_response(&response) {
-                return server_error(err);
+async fn idn_result(
+    state: &AppState,
+    domain: String,
+    options: IdnOptions,
+    dataset: Option<String>,
+) -> Result<IdnResponse> {
+    idn_result_streaming(state, domain, options, dataset, |_| {}).await
+}
+
+/// Like `idn_result`, but calls `on_result` (on a blocking thread) as each
+/// variant is confirmed, for /idn/stream and WebSocket progress. A cached
+/// response replays its results through it.
+async fn idn_result_streaming<R>(
+    state: &AppState,
+    domain: String,
+    options: IdnOptions,
+    dataset: Option<String>,
+    mut on_result: R,
+) -> Result<IdnResponse>
+where
+    R: FnMut(&IdnResult) + Send + 'static,
+{
+    let cache_key = state.cache_key(
+        format!("{domain}|{dataset:?}|{options:?}"),
+        dataset.as_deref(),
+    );
+    if let Some(LookupResponse::Idn(cached)) = state.cached(CacheKind::Idn, &cache_key).await {
+        record_history(LookupResponse::Idn(cached.clone()));
+        return Ok(tokio::task::spawn_blocking(move || {
+            cached.results.iter().for_each(&mut on_result);
+            cached
+        })
+        .await?);
+    }
+
+    let mut response = tokio::task::spawn_blocking(move || {
+        idn::lookup_idn_streaming(&domain, &options, on_result)
+    })
+    .await??;
+    if state.use_db {
+        response.dataset = state.dataset_info(dataset.as_deref());
+    }
+    if passive_dns::installed() {
+        response = tokio::task::spawn_blocking(move || {
+            passive_dns::enrich_idn(&mut response);
+            response
+        })
+        .await?;
+    }
+    state
+        .cache_response(
+            CacheKind::Idn,
+            cache_key,
+            LookupResponse::Idn(response.clone()),
+        )
+        .await;
+    record_history(LookupResponse::Idn(response.clone()));
+    Ok(response)
+}
+
+/// Records a served lookup off the async workers: in the history, if it is
+/// enabled, and as a detection in the detections store and the Kafka sink, if
+/// it is a lookalike and those are configured.
+fn record_history(response: LookupResponse) {
+    if !history::enabled() && !detections::enabled() && kafka::installed().is_none() {
+        return;
+    }
+    tokio::task::spawn_blocking(move || {
+        let domain = match &response {
+            LookupResponse::Ascii(data) => {
+                history::record_ascii(history::Source::Http, data);
+                &data.q
+            }
+            LookupResponse::Idn(data) => {
+                history::record_idn(history::Source::Http, data);
+                &data.q
             }
+        };
+        kafka::publish_lookup("http", &response);
+        if scan::is_lookalike(&response) {
+            detections::record(detections::Source::Http, domain, &response);
+            kafka::publish_detection("http", domain, &response);
+        }
+    });
+}
+
+#[utoipa::path(
+	post,
+	path = "/admin/reload",
+	tag = "spotspoof",
+	request_body = ReloadRequest,
+	responses(
+		(status = 200, body = ReloadResponse),
+		(status = 401, description = "Missing or invalid admin token"),
+		(status = 403, description = "Admin endpoints are disabled"),
+		(status = 422, description = "Replacement DB failed verification")
+	)
+)]
+async fn admin_reload(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    scope: Option<Extension<Scope>>,
+    payload: Option<Json<ReloadRequest>>,
+) -> Response {
+    if let Some(rejection) = read_only_rejection(&state) {
+        return rejection;
+    }
+    if let Some(rejection) = admin_rejection(&state, &headers, scope.map(|Extension(scope)| scope))
+    {
+        return rejection;
+    }
+    if !state.use_db {
+        return error_response(StatusCode::CONFLICT, "DB usage is disabled (--no-db)");
+    }
+
+    let Json(payload) = payload.unwrap_or_default();
+    let path = payload.path.unwrap_or_else(|| state.db_path());
+    let verify_path = path.clone();
+    let verify_state = state.clone();
+    let verified = tokio::task::spawn_blocking(move || {
+        verify_state.prepare_db(&verify_path, payload.sha256.as_deref())
+    })
+    .await;
+
+    match verified {
+        Ok(Ok(indexes)) => {
+            let generation = state.swap_db(path.clone(), indexes);
+            info!(kind = "reload", db_path = %path, generation);
+            let response = ReloadResponse {
+                db_path: path,
+                generation,
+            };
             (StatusCode::OK, Json(response)).into_response()
         }
+        Ok(Err(err)) => error_response(StatusCode::UNPROCESSABLE_ENTITY, err),
+        Err(err) => server_error(err),
+    }
+}
+
+#[utoipa::path(
+	get,
+	path = "/admin/usage",
+	tag = "spotspoof",
+	responses(
+		(status = 200, body = Vec<keys::KeyUsage>),
+		(status = 401, description = "Missing or invalid admin token"),
+		(status = 403, description = "Admin endpoints are disabled"),
+		(status = 404, description = "API keys are disabled")
+	)
+)]
+async fn admin_usage(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    scope: Option<Extension<Scope>>,
+) -> Response {
+    if let Some(rejection) = admin_rejection(&state, &headers, scope.map(|Extension(scope)| scope))
+    {
+        return rejection;
+    }
+    let Some(path) = state.keys_db.clone() else {
+        return error_response(
+            StatusCode::NOT_FOUND,
+            "API keys are disabled; start serve with --keys-db",
+        );
+    };
+    let usage = tokio::task::spawn_blocking(move || keys::usage(&keys::open(&path)?)).await;
+    match usage {
+        Ok(Ok(usage)) => (StatusCode::OK, Json(usage)).into_response(),
+        Ok(Err(err)) => server_error(err),
+        Err(err) => server_error(err),
+    }
+}
+
+#[derive(Debug, Deserialize, utoipa::IntoParams)]
+struct DetectionsQuery {
+    /// Only detections in this triage state.
+    state: Option<TriageState>,
+    /// At most this many, most recently seen first (default 50).
+    limit: Option<usize>,
+}
+
+#[derive(Debug, Deserialize, utoipa::ToSchema)]
+struct TriageRequest {
+    state: TriageState,
+    note: Option<String>,
+}
+
+#[utoipa::path(
+	get,
+	path = "/detections",
+	tag = "spotspoof",
+	params(DetectionsQuery),
+	responses(
+		(status = 200, body = Vec<detections::Detection>),
+		(status = 404, description = "The detections store is disabled")
+	)
+)]
+async fn list_detections(Query(query): Query<DetectionsQuery>) -> Response {
+    let Some(store) = detections::installed() else {
+        return detections_disabled();
+    };
+    let limit = query.limit.unwrap_or(DETECTIONS_LIMIT);
+    match tokio::task::spawn_blocking(move || store.list(query.state, limit)).await {
+        Ok(Ok(listed)) => (StatusCode::OK, Json(listed)).into_response(),
         Ok(Err(err)) => server_error(err),
         Err(err) => server_error(err),
     }
@@ -179,38 +1517,347 @@ async fn ascii(State(state): State<AppState>, Json(payload): Json<LookupRequest>
 
 #[utoipa::path(
 	post,
-	path = "/idn",
+	path = "/detections/{id}",
 	tag = "spotspoof",
-	request_body = LookupRequest,
-	responses((status = 200, body = IdnResponse))
+	request_body = TriageRequest,
+	params(
+		("id" = i64, Path, description = "Detection id")
+	),
+	responses(
+		(status = 200, body = detections::Detection),
+		(status = 401, description = "Missing or invalid admin token"),
+		(status = 403, description = "Admin endpoints are disabled"),
+		(status = 404, description = "Unknown detection, or the detections store is disabled")
+	)
 )]
-async fn idn_lookup(
-    State(_state): State<AppState>,
-    Json(payload): Json<LookupRequest>,
+async fn triage_detection(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    scope: Option<Extension<Scope>>,
+    UrlPath(id): UrlPath<i64>,
+    Json(request): Json<TriageRequest>,
 ) -> Response {
-    let domain = crate::types::normalize_domain_input(&payload.domain);
-    let result = tokio::task::spawn_blocking(move || idn::lookup_idn(&domain)).await;
+    if let Some(rejection) = read_only_rejection(&state) {
+        return rejection;
+    }
+    if let Some(rejection) = admin_rejection(&state, &headers, scope.map(|Extension(scope)| scope))
+    {
+        return rejection;
+    }
+    let Some(store) = detections::installed() else {
+        return detections_disabled();
+    };
+    let triage_state = request.state;
+    let triaged = tokio::task::spawn_blocking(move || {
+        store.triage(id, triage_state, request.note.as_deref())
+    })
+    .await;
+    match triaged {
+        Ok(Ok(Some(detection))) => {
+            info!(kind = "detections", id, state = triage_state.as_str());
+            (StatusCode::OK, Json(detection)).into_response()
+        }
+        Ok(Ok(None)) => error_response(StatusCode::NOT_FOUND, format!("no detection with id {id}")),
+        Ok(Err(err)) => server_error(err),
+        Err(err) => server_error(err),
+    }
+}
 
-    match result {
-        Ok(Ok(response)) => {
-            if let Err(err) = crate::types::validate_idn_response(&response) {
-                return server_error(err);
+fn detections_disabled() -> Response {
+    error_response(
+        StatusCode::NOT_FOUND,
+        "detections are disabled; start serve with --detections",
+    )
+}
+
+/// Watches the DB's parent directory so replacements that land via rename
+/// (rsync, `mv`) are seen, then re-verifies and bumps the generation once the
+/// file has been quiet for `WATCH_DEBOUNCE`.
+fn spawn_db_watcher(state: AppState) -> Result<()> {
+    let db_path = PathBuf::from(state.db_path());
+    let file_name = db_path
+        .file_name()
+        .map(|name| name.to_os_string())
+        .ok_or_else(|| anyhow::anyhow!("DB path has no file name: {}", db_path.display()))?;
+    let dir = match db_path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+
+    let (tx, rx) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(tx)?;
+    watcher.watch(&dir, RecursiveMode::NonRecursive)?;
+    info!(kind = "watch", dir = %dir.display());
+
+    std::thread::spawn(move || {
+        let _watcher = watcher;
+        let touches_db = |event: &notify::Event| {
+            !event.kind.is_access()
+                && event
+                    .paths
+                    .iter()
+                    .any(|path| path.file_name() == Some(file_name.as_os_str()))
+        };
+        while let Ok(event) = rx.recv() {
+            if !matches!(&event, Ok(event) if touches_db(event)) {
+                continue;
             }
-            (StatusCode::OK, Json(response)).into_response()
+            while rx.recv_timeout(WATCH_DEBOUNCE).is_ok() {}
+            reload_after_change(&state);
+        }
+    });
+    Ok(())
+}
+
+fn reload_after_change(state: &AppState) {
+    let path = state.db_path();
+    if !Path::new(&path).exists() {
+        return;
+    }
+    match state.prepare_db(&path, None) {
+        Ok(indexes) => {
+            let generation = state.swap_db(path.clone(), indexes);
+            info!(kind = "reload", trigger = "watch", db_path = %path, generation);
+        }
+        Err(err) => warn!(kind = "reload", trigger = "watch", db_path = %path, "{err}"),
+    }
+}
+
+/// Sends systemd watchdog keepalives while lookups can still be answered:
+/// the runtime is turning and, with a DB, a pooled connection runs a query in
+/// time. Otherwise keepalives stop and systemd restarts the hung server.
+async fn watchdog_loop(state: AppState, interval: Duration) {
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        ticker.tick().await;
+        let probe = state.clone();
+        let healthy = tokio::time::timeout(
+            interval,
+            tokio::task::spawn_blocking(move || -> Result<()> {
+                if let Some(pool) = probe.db_pool().filter(|_| probe.use_db) {
+                    pool.get()?.query_row("SELECT 1", [], |_| Ok(()))?;
+                }
+                Ok(())
+            }),
+        )
+        .await;
+        match healthy {
+            Ok(Ok(Ok(()))) => systemd::notify("WATCHDOG=1"),
+            Ok(Ok(Err(err))) => {
+                warn!(kind = "systemd", error = %err, "DB probe failed; skipping watchdog keepalive")
+            }
+            _ => warn!(
+                kind = "systemd",
+                "DB probe timed out; skipping watchdog keepalive"
+            ),
         }
+    }
+}
+
+/// Periodically re-downloads the release DB with a conditional request, so an
+/// unchanged artifact costs one 304. `update_db` unpacks to a temp file and
+/// renames it into place, so lookups in flight keep reading the old file until
+/// the generation is bumped.
+async fn auto_update_loop(state: AppState, url: String, period: Duration) {
+    let mut interval = tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+    loop {
+        interval.tick().await;
+        let path = state.db_path();
+        let check_url = url.clone();
+        let update_state = state.clone();
+        let result = tokio::task::spawn_blocking(move || {
+            let version = match download::update_db(&check_url, &path)? {
+                download::DownloadOutcome::NotModified => return Ok(None),
+                download::DownloadOutcome::Downloaded(version) => version,
+            };
+            let indexes = update_state.prepare_db(&path, None)?;
+            Ok::<_, anyhow::Error>(Some((version.unwrap_or_default(), indexes)))
+        })
+        .await;
+
+        match result {
+            Ok(Ok(Some((version, indexes)))) => {
+                let generation = state.swap_db(state.db_path(), indexes);
+                info!(kind = "auto_update", version = %version, generation);
+            }
+            Ok(Ok(None)) => info!(kind = "auto_update", "DB is up to date"),
+            Ok(Err(err)) => warn!(kind = "auto_update", "{err}"),
+            Err(err) => warn!(kind = "auto_update", "{err}"),
+        }
+    }
+}
+
+/// Requires `Authorization: Bearer <key>` with a valid key from `--keys-db`
+/// on every route but the public ones, and counts the request against the
+/// key's quotas. The admin token is let through for the admin endpoints to
+/// check; a key's scope is passed on to them.
+async fn authenticate(State(state): State<AppState>, mut request: Request, next: Next) -> Response {
+    let Some(path) = state.keys_db.clone() else {
+        return next.run(request).await;
+    };
+    let route = request.uri().path();
+    if PUBLIC_PATHS.contains(&route)
+        || PUBLIC_PREFIXES
+            .iter()
+            .any(|prefix| route.starts_with(prefix))
+    {
+        return next.run(request).await;
+    }
+    let Some(key) = bearer_token(request.headers()).map(str::to_string) else {
+        return error_response(StatusCode::UNAUTHORIZED, "Missing API key");
+    };
+    if let Some(token) = state.admin_token.as_deref() {
+        if constant_time_eq(key.as_bytes(), token.as_bytes()) {
+            return next.run(request).await;
+        }
+    }
+    let found = tokio::task::spawn_blocking(move || consume_key(&path, &key)).await;
+    match found {
+        Ok(Ok(Some((_, consumed)))) if !consumed.allowed => {
+            let mut response =
+                error_response(StatusCode::TOO_MANY_REQUESTS, quota_message(&consumed));
+            if let Some(reset_at) = consumed.exhausted().map(|quota| quota.reset_at).max() {
+                let seconds = (reset_at - unix_now()).max(0);
+                response
+                    .headers_mut()
+                    .insert(header::RETRY_AFTER, HeaderValue::from(seconds));
+            }
+            quota_headers(response.headers_mut(), &consumed.quotas);
+            response
+        }
+        Ok(Ok(Some((scope, consumed)))) => {
+            request.extensions_mut().insert(scope);
+            let mut response = next.run(request).await;
+            quota_headers(response.headers_mut(), &consumed.quotas);
+            response
+        }
+        Ok(Ok(None)) => error_response(StatusCode::UNAUTHORIZED, "Invalid or expired API key"),
         Ok(Err(err)) => server_error(err),
         Err(err) => server_error(err),
     }
 }
 
+/// Looks `key` up in the keys DB at `path` and, when it is valid, counts a
+/// request against its quotas; `None` for an unknown, revoked, or expired key.
+fn consume_key(path: &str, key: &str) -> Result<Option<(Scope, keys::Consumed)>> {
+    let mut conn = keys::open(path)?;
+    let Some(key) = keys::authenticate(&conn, key)? else {
+        return Ok(None);
+    };
+    let consumed = keys::consume(&mut conn, &key)?;
+    Ok(Some((key.scope, consumed)))
+}
+
+/// Why a key over its quota was refused.
+fn quota_message(consumed: &keys::Consumed) -> String {
+    let exhausted = consumed
+        .exhausted()
+        .map(|quota| format!("{} quota of {}", quota.period.as_str(), quota.limit))
+        .collect::<Vec<_>>()
+        .join(" and ");
+    format!("API key {exhausted} requests used up")
+}
+
+/// `X-Quota-Daily-Limit`, `-Remaining`, and `-Reset` (unix seconds), and the
+/// same for `Monthly`, for each quota the key has.
+fn quota_headers(headers: &mut HeaderMap, quotas: &[keys::QuotaState]) {
+    for quota in quotas {
+        for (field, value) in [
+            ("limit", quota.limit as i64),
+            ("remaining", quota.remaining() as i64),
+            ("reset", quota.reset_at),
+        ] {
+            let name = format!("x-quota-{}-{field}", quota.period.as_str());
+            if let Ok(name) = header::HeaderName::try_from(name) {
+                headers.insert(name, HeaderValue::from(value));
+            }
+        }
+    }
+}
+
+fn unix_now() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs() as i64)
+}
+
+fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+}
+
+/// Refuses a mutating request on a `--read-only` server, whatever its
+/// credentials.
+fn read_only_rejection(state: &AppState) -> Option<Response> {
+    state.read_only.then(|| {
+        error_response(
+            StatusCode::FORBIDDEN,
+            "This server is read-only (--read-only); send changes to a management instance",
+        )
+    })
+}
+
+/// Admin endpoints accept an `admin` API key or the admin token.
+fn admin_rejection(
+    state: &AppState,
+    headers: &HeaderMap,
+    scope: Option<Scope>,
+) -> Option<Response> {
+    match scope {
+        Some(Scope::Admin) => return None,
+        Some(Scope::Read) => {
+            return Some(error_response(
+                StatusCode::FORBIDDEN,
+                "API key lacks the admin scope",
+            ))
+        }
+        None => {}
+    }
+    let Some(expected) = state.admin_token.as_deref() else {
+        return Some(error_response(
+            StatusCode::FORBIDDEN,
+            "Admin endpoints are disabled; start serve with --admin-token",
+        ));
+    };
+    let provided = bearer_token(headers).unwrap_or_default();
+    if !constant_time_eq(provided.as_bytes(), expected.as_bytes()) {
+        return Some(error_response(
+            StatusCode::UNAUTHORIZED,
+            "Missing or invalid admin token",
+        ));
+    }
+    None
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
 fn server_error<E: std::fmt::Display>(err: E) -> Response {
-    let body = json!({ "error": err.to_string() });
-    (StatusCode::INTERNAL_SERVER_ERROR, Json(body)).into_response()
+    error_response(StatusCode::INTERNAL_SERVER_ERROR, err)
+}
+
+fn error_response<E: std::fmt::Display>(status: StatusCode, err: E) -> Response {
+    let body = match REQUEST_ID.try_with(String::clone) {
+        Ok(id) => json!({ "error": err.to_string(), "request_id": id }),
+        Err(_) => json!({ "error": err.to_string() }),
+    };
+    (status, Json(body)).into_response()
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use axum::body::Body;
+    use axum::http::Request;
+    use tower::ServiceExt;
 
     #[tokio::test]
     async fn healthz_returns_ok() {
@@ -224,12 +1871,846 @@ mod tests {
         assert!(body.contains("SpotSpoof CLI"));
         assert!(body.contains("GET /healthz"));
         assert!(body.contains("POST /lookup"));
+        assert!(body.contains("GET /stats"));
         assert!(body.contains("GET /docs"));
     }
 
+    #[test]
+    fn openapi_idn_schema_matches_serialized_responses() {
+        let openapi = serde_json::to_value(ApiDoc::openapi()).unwrap();
+        let schemas = &openapi["components"]["schemas"];
+        let response = IdnResponse {
+            q: "амаzоn.com".to_string(),
+            ascii: false,
+            puny: true,
+            results: vec![crate::types::IdnResult {
+                domain: "amazon.com".to_string(),
+                mappings: vec![crate::types::PunyMapping {
+                    unicode: "а".to_string(),
+                    ascii: "a".to_string(),
+                }],
+                is_registered: false,
+                registration_checked: true,
+                registration_status: Some(crate::types::RegistrationStatus::Unknown),
+            }],
+            dataset: Some(DatasetInfo {
+                version: Some("2024.06.01".to_string()),
+                date: Some("2024-06-01T08:00:00Z".to_string()),
+                domains: 1_000_000,
+                source: Some("https://example.com/spotspoof.sqlite.zst".to_string()),
+            }),
+            total_candidates_considered: Some(1),
+            idna_errors: vec![crate::types::IdnaError {
+                category: "punycode".to_string(),
+                message: "not valid punycode".to_string(),
+            }],
+            passive_dns: Some(crate::types::PassiveDns {
+                first_seen: Some("2024-06-02T10:00:00Z".to_string()),
+                last_seen: Some("2024-06-03T10:00:00Z".to_string()),
+                ips: vec!["203.0.113.7".to_string()],
+            }),
+        };
+        let value = serde_json::to_value(&response).unwrap();
+        for (schema, object) in [
+            ("IdnResponse", &value),
+            ("IdnaError", &value["idna_errors"][0]),
+            ("IdnResult", &value["results"][0]),
+            ("PunyMapping", &value["results"][0]["mappings"][0]),
+            ("DatasetInfo", &value["dataset"]),
+            ("PassiveDns", &value["passive_dns"]),
+        ] {
+            let properties = schemas[schema]["properties"].as_object().unwrap();
+            for key in object.as_object().unwrap().keys() {
+                assert!(properties.contains_key(key), "{schema} is missing {key}");
+            }
+        }
+        assert_eq!(
+            schemas["RegistrationStatus"]["enum"],
+            json!(["registered", "unregistered", "unknown"])
+        );
+    }
+
     #[test]
     fn server_error_sets_status() {
         let response = server_error("boom");
         assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
     }
+
+    fn tmp_db(name: &str, with_schema: bool) -> String {
+        let mut path = std::env::temp_dir();
+        path.push(format!(
+            "spotspoof-http-{name}-{}.sqlite",
+            std::process::id()
+        ));
+        let path = path.to_string_lossy().to_string();
+        let _ = std::fs::remove_file(&path);
+        let conn = rusqlite::Connection::open(&path).unwrap();
+        if with_schema {
+            conn.execute_batch(
+                "CREATE TABLE legit_domains (domain TEXT, first_char TEXT, length INTEGER);",
+            )
+            .unwrap();
+        } else {
+            conn.execute_batch("CREATE TABLE other (id INTEGER);")
+                .unwrap();
+        }
+        path
+    }
+
+    fn bearer(token: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::AUTHORIZATION,
+            format!("Bearer {token}").parse().unwrap(),
+        );
+        headers
+    }
+
+    #[tokio::test]
+    async fn responses_are_compressed_when_accepted() {
+        let app = router(AppState::new("unused.sqlite".to_string(), false));
+        let request = Request::post("/ascii")
+            .header(header::CONTENT_TYPE, "application/json")
+            .header(header::ACCEPT_ENCODING, "gzip")
+            .body(Body::from(
+                r#"{"domain":"a-fairly-long-domain-name-for-compression.com"}"#,
+            ))
+            .unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers().get(header::CONTENT_ENCODING).unwrap(),
+            "gzip"
+        );
+    }
+
+    fn lookup_request(domain: &str) -> LookupRequest {
+        LookupRequest {
+            domain: domain.to_string(),
+            min_similarity: None,
+            max_results: None,
+            algorithm: None,
+            check_registration: None,
+            max_variants: None,
+            max_checks: None,
+            whois_timeout_ms: None,
+            dataset: None,
+            leet: None,
+            phonetic: None,
+            limit: None,
+            offset: None,
+            idna_transitional: None,
+            idna_std3_rules: None,
+            punycode_overflow: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn lookup_overrides_are_validated() {
+        let state = AppState::new("unused.sqlite".to_string(), false);
+        let payload = LookupRequest {
+            min_similarity: Some(10),
+            ..lookup_request("gooble.com")
+        };
+        let response = ascii(State(state.clone()), HeaderMap::new(), Json(payload)).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let payload = LookupRequest {
+            max_results: Some(500),
+            ..lookup_request("gooble.com")
+        };
+        let response = lookup(State(state.clone()), HeaderMap::new(), Json(payload)).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let response = lookup(
+            State(state),
+            HeaderMap::new(),
+            Json(lookup_request("goo gle..com")),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        for payload in [
+            LookupRequest {
+                max_variants: Some(0),
+                ..lookup_request("амаzоn.com")
+            },
+            LookupRequest {
+                max_checks: Some(501),
+                ..lookup_request("амаzоn.com")
+            },
+            LookupRequest {
+                whois_timeout_ms: Some(60_000),
+                ..lookup_request("амаzоn.com")
+            },
+        ] {
+            assert!(LookupOverrides::from_request(&payload).is_err());
+        }
+        let payload = LookupRequest {
+            max_variants: Some(10),
+            max_checks: Some(5),
+            whois_timeout_ms: Some(500),
+            ..lookup_request("амаzоn.com")
+        };
+        let idn = LookupOverrides::from_request(&payload).unwrap().idn;
+        assert_eq!(
+            (
+                idn.max_normalized,
+                idn.max_whois_checks,
+                idn.whois_timeout_ms
+            ),
+            (10, 5, 500)
+        );
+
+        let payload = LookupRequest {
+            idna_transitional: Some(true),
+            idna_std3_rules: Some(true),
+            punycode_overflow: Some(crate::types::PunycodeOverflow::Keep),
+            ..lookup_request("faß.de")
+        };
+        let idna = LookupOverrides::from_request(&payload).unwrap().idn.idna;
+        assert_eq!(
+            idna,
+            crate::puny::IdnaOptions {
+                transitional: true,
+                use_std3_ascii_rules: true,
+                punycode_overflow: crate::types::PunycodeOverflow::Keep,
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn idn_overrides_skip_registration_checks() {
+        let state = AppState::new("unused.sqlite".to_string(), false);
+        let payload = LookupRequest {
+            max_results: Some(2),
+            check_registration: Some(false),
+            ..lookup_request("амаzоn.com")
+        };
+        let result = idn_result(
+            &state,
+            payload.domain.clone(),
+            LookupOverrides::from_request(&payload).unwrap().idn,
+            None,
+        )
+        .await
+        .unwrap();
+        assert_eq!(result.results.len(), 2);
+        assert!(result.results.iter().all(|r| !r.registration_checked));
+    }
+
+    #[tokio::test]
+    async fn idn_stream_emits_results_then_summary() {
+        let state = AppState::new("unused.sqlite".to_string(), false);
+        let payload = LookupRequest {
+            max_results: Some(2),
+            check_registration: Some(false),
+            ..lookup_request("амаzоn.com")
+        };
+        let response = idn_stream(State(state.clone()), Json(payload)).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        assert_eq!(body.matches("event: result").count(), 2);
+        let summary = body.find("event: summary").expect("summary event");
+        assert!(body.rfind("event: result").unwrap() < summary);
+
+        let unknown = LookupRequest {
+            dataset: Some("nope".to_string()),
+            ..lookup_request("амаzоn.com")
+        };
+        let response = idn_stream(State(state), Json(unknown)).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn ws_replies_with_progress_and_response() {
+        let state = AppState::new("unused.sqlite".to_string(), false);
+        let (tx, mut rx) = tokio::sync::mpsc::channel(32);
+
+        let request = r#"{"id":7,"mode":"idn","domain":"амаzоn.com","check_registration":false,"max_results":2}"#;
+        handle_ws_text(&state, None, request, &tx).await;
+        handle_ws_text(&state, None, "not json", &tx).await;
+        drop(tx);
+
+        let mut replies = Vec::new();
+        while let Some(text) = rx.recv().await {
+            replies.push(serde_json::from_str::<serde_json::Value>(&text).unwrap());
+        }
+        let kinds: Vec<&str> = replies
+            .iter()
+            .map(|r| r["type"].as_str().unwrap())
+            .collect();
+        assert_eq!(kinds, ["progress", "progress", "response", "error"]);
+        assert_eq!(replies[2]["id"], 7);
+        assert_eq!(replies[2]["data"]["results"].as_array().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn streamed_idn_lookups_share_the_result_cache() {
+        let state = AppState::new("unused.sqlite".to_string(), false);
+        let (tx, mut rx) = tokio::sync::mpsc::channel(32);
+        let request =
+            r#"{"mode":"idn","domain":"амаzоn.com","check_registration":false,"max_results":2}"#;
+        handle_ws_text(&state, None, request, &tx).await;
+        drop(tx);
+        while rx.recv().await.is_some() {}
+        assert_eq!(state.cache.len(), 1);
+
+        // /idn/stream replays the cached variants rather than looking again.
+        let payload = LookupRequest {
+            max_results: Some(2),
+            check_registration: Some(false),
+            ..lookup_request("амаzоn.com")
+        };
+        let response = idn_stream(State(state.clone()), Json(payload)).await;
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        assert_eq!(body.matches("event: result").count(), 2);
+        assert!(body.contains("event: summary"));
+        assert_eq!(state.cache.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn ws_lookups_apply_the_tenant() {
+        let state = AppState::new("unused.sqlite".to_string(), false);
+        let tenant = Tenant {
+            name: "acme".to_string(),
+            brands: vec!["acmebank.com".to_string()],
+            allowlist: Default::default(),
+        };
+        let (tx, mut rx) = tokio::sync::mpsc::channel(32);
+        let request = r#"{"id":1,"mode":"ascii","domain":"acmebamk.com"}"#;
+        handle_ws_text(&state, Some(&tenant), request, &tx).await;
+        handle_ws_text(&state, None, request, &tx).await;
+        drop(tx);
+
+        let mut replies = Vec::new();
+        while let Some(text) = rx.recv().await {
+            replies.push(serde_json::from_str::<serde_json::Value>(&text).unwrap());
+        }
+        assert_eq!(replies[0]["data"]["results"][0]["domain"], "acmebank.com");
+        assert!(replies[1]["data"]["results"].as_array().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn ascii_lookups_use_pooled_connections() {
+        let path = tmp_db("pool", true);
+        rusqlite::Connection::open(&path)
+            .unwrap()
+            .execute(
+                "INSERT INTO legit_domains VALUES ('example.com', 'e', 11)",
+                [],
+            )
+            .unwrap();
+        let state = AppState::new(path.clone(), true).with_pool(2, true);
+
+        let response = ascii_result(
+            &state,
+            "exampl3.com".to_string(),
+            AsciiOptions::default(),
+            None,
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.results[0].domain, "example.com");
+
+        let response = metrics(State(state)).await;
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        assert!(body.contains("spotspoof_db_pool_max_connections 2"));
+        assert!(body.contains("spotspoof_db_pool_connections 1"));
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[tokio::test]
+    async fn in_memory_index_serves_without_the_file() {
+        let path = tmp_db("memory", true);
+        rusqlite::Connection::open(&path)
+            .unwrap()
+            .execute_batch(
+                "INSERT INTO legit_domains VALUES ('example.com', 'e', 11);
+                 INSERT INTO legit_domains VALUES ('examples.net', 'e', 12);",
+            )
+            .unwrap();
+        let state = AppState::new(path.clone(), true).with_in_memory(true);
+        let indexes = state.prepare_db(&path, None).unwrap();
+        assert_eq!(indexes.memory.as_ref().map(|memory| memory.len()), Some(2));
+        state.set_indexes(indexes);
+        std::fs::remove_file(&path).unwrap();
+
+        let response = ascii_result(
+            &state,
+            "exampl3.com".to_string(),
+            AsciiOptions::default(),
+            None,
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.results[0].domain, "example.com");
+        assert_eq!(response.dataset.map(|dataset| dataset.domains), Some(2));
+    }
+
+    #[test]
+    fn in_memory_mode_skips_the_table_when_an_fst_exists() {
+        let path = tmp_db("memory-fst", true);
+        rusqlite::Connection::open(&path)
+            .unwrap()
+            .execute_batch("INSERT INTO legit_domains VALUES ('example.com', 'e', 11);")
+            .unwrap();
+        let (fst_path, _) = crate::index::build_fst(&path).unwrap();
+        let state = AppState::new(path.clone(), true).with_in_memory(true);
+        let indexes = state.prepare_db(&path, None).unwrap();
+        assert!(indexes.fst.is_some());
+        assert!(indexes.memory.is_none());
+        let _ = std::fs::remove_file(fst_path);
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[tokio::test]
+    async fn ascii_results_are_paged() {
+        let path = tmp_db("paging", true);
+        rusqlite::Connection::open(&path)
+            .unwrap()
+            .execute_batch(
+                "INSERT INTO legit_domains VALUES ('example.com', 'e', 11);
+                 INSERT INTO legit_domains VALUES ('exampla.com', 'e', 11);
+                 INSERT INTO legit_domains VALUES ('examplb.com', 'e', 11);
+                 INSERT INTO legit_domains VALUES ('examplc.com', 'e', 11);",
+            )
+            .unwrap();
+        let state = AppState::new(path.clone(), true).with_pool(2, true);
+        let page = |offset| {
+            let state = state.clone();
+            async move {
+                let payload = LookupRequest {
+                    limit: Some(2),
+                    offset: Some(offset),
+                    ..lookup_request("exampl3.com")
+                };
+                let response = ascii(State(state), HeaderMap::new(), Json(payload)).await;
+                assert_eq!(response.status(), StatusCode::OK);
+                let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                    .await
+                    .unwrap();
+                serde_json::from_slice::<AsciiResponse>(&body).unwrap()
+            }
+        };
+
+        let first = page(0).await;
+        let second = page(2).await;
+        let domains = |response: &AsciiResponse| -> Vec<String> {
+            response.results.iter().map(|r| r.domain.clone()).collect()
+        };
+        assert_eq!(domains(&first), ["exampla.com", "examplb.com"]);
+        assert_eq!(domains(&second), ["examplc.com", "example.com"]);
+        assert!(page(4).await.results.is_empty());
+        assert!(first.total_candidates_considered.unwrap() >= 4);
+        assert_eq!(
+            first.total_candidates_considered,
+            second.total_candidates_considered
+        );
+
+        for payload in [
+            LookupRequest {
+                limit: Some(0),
+                ..lookup_request("exampl3.com")
+            },
+            LookupRequest {
+                offset: Some(MAX_OFFSET + 1),
+                ..lookup_request("exampl3.com")
+            },
+        ] {
+            assert!(LookupOverrides::from_request(&payload).is_err());
+        }
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[tokio::test]
+    async fn named_datasets_are_served_side_by_side() {
+        let (global, finance) = (tmp_db("global", true), tmp_db("finance", true));
+        for (path, domain) in [(&global, "example.com"), (&finance, "examplebank.com")] {
+            rusqlite::Connection::open(path)
+                .unwrap()
+                .execute(
+                    "INSERT INTO legit_domains VALUES (?1, 'e', length(?1))",
+                    [domain],
+                )
+                .unwrap();
+        }
+        let mut state = AppState::new(global.clone(), true);
+        state.set_indexes(state.prepare_db(&global, None).unwrap());
+        state.datasets = Arc::new(BTreeMap::from([(
+            "finance".to_string(),
+            NamedDataset {
+                pool: db::pool(&finance, 1, false),
+                indexes: state.prepare_db(&finance, None).unwrap(),
+            },
+        )]));
+
+        let lookup_in = |dataset: Option<&str>| {
+            let state = state.clone();
+            let payload = LookupRequest {
+                dataset: dataset.map(str::to_string),
+                ..lookup_request("examp1ebank.com")
+            };
+            async move {
+                let response = ascii(State(state), HeaderMap::new(), Json(payload)).await;
+                let status = response.status();
+                let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                    .await
+                    .unwrap();
+                (
+                    status,
+                    serde_json::from_slice::<serde_json::Value>(&body).ok(),
+                )
+            }
+        };
+        let (status, body) = lookup_in(Some("finance")).await;
+        assert_eq!(status, StatusCode::OK);
+        let body = body.unwrap();
+        assert_eq!(body["results"][0]["domain"], "examplebank.com");
+        assert_eq!(body["dataset"]["domains"], 1);
+        let (_, body) = lookup_in(None).await;
+        assert_eq!(body.unwrap()["results"], json!([]));
+        let (status, _) = lookup_in(Some("internal")).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        let Json(value) = version(State(state)).await;
+        assert_eq!(value.datasets["finance"].domains, 1);
+        for path in [global, finance] {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+
+    #[tokio::test]
+    async fn version_reports_db_generation() {
+        let state = AppState::new("unused.sqlite".to_string(), true);
+        state.swap_db("other.sqlite".to_string(), PreparedDb::default());
+        let Json(value) = version(State(state)).await;
+        assert_eq!(value.version, env!("CARGO_PKG_VERSION"));
+        assert_eq!(value.db_path, "other.sqlite");
+        assert_eq!(value.db_generation, 1);
+    }
+
+    #[test]
+    fn db_watcher_bumps_generation_when_file_is_replaced() {
+        let path = tmp_db("watch", true);
+        let state = AppState::new(path.clone(), true);
+        spawn_db_watcher(state.clone()).unwrap();
+
+        let staged = tmp_db("watch-staged", true);
+        std::fs::rename(&staged, &path).unwrap();
+
+        let deadline = std::time::Instant::now() + Duration::from_secs(10);
+        while state.db_generation() == 0 && std::time::Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(50));
+        }
+        assert!(state.db_generation() >= 1);
+
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[tokio::test]
+    async fn admin_reload_requires_configured_token() {
+        let disabled = AppState::new("unused.sqlite".to_string(), true);
+        let response = admin_reload(State(disabled), bearer("secret"), None, None).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        let state = AppState {
+            admin_token: Some("secret".into()),
+            ..AppState::new("unused.sqlite".to_string(), true)
+        };
+        let response = admin_reload(State(state), bearer("wrong"), None, None).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn detection_routes_need_the_store_and_admin() {
+        let app = router(AppState::new("unused.sqlite".to_string(), true));
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri("/detections?state=new")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let triage = || {
+            Request::builder()
+                .method("POST")
+                .uri("/detections/1")
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(r#"{"state": "false-positive"}"#))
+                .unwrap()
+        };
+        let response = app.oneshot(triage()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        let state = AppState {
+            admin_token: Some("secret".into()),
+            ..AppState::new("unused.sqlite".to_string(), true)
+        };
+        let response = router(state).oneshot(triage()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn read_only_servers_refuse_mutations_even_for_admins() {
+        let state = AppState {
+            admin_token: Some("secret".into()),
+            read_only: true,
+            ..AppState::new("unused.sqlite".to_string(), true)
+        };
+        let response = admin_reload(State(state.clone()), bearer("secret"), None, None).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        let response = router(state)
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri("/detections/1")
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .header(header::AUTHORIZATION, "Bearer secret")
+                    .body(Body::from(r#"{"state": "acknowledged"}"#))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn admin_reload_swaps_verified_db() {
+        let original = tmp_db("reload-original", true);
+        let replacement = tmp_db("reload-replacement", true);
+        let broken = tmp_db("reload-broken", false);
+        let state = AppState {
+            admin_token: Some("secret".into()),
+            ..AppState::new(original.clone(), true)
+        };
+
+        let payload = ReloadRequest {
+            path: Some(broken.clone()),
+            sha256: None,
+        };
+        let response = admin_reload(
+            State(state.clone()),
+            bearer("secret"),
+            None,
+            Some(Json(payload)),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(state.db_path(), original);
+
+        let payload = ReloadRequest {
+            path: Some(replacement.clone()),
+            sha256: Some(db::file_sha256(&replacement).unwrap()),
+        };
+        let response = admin_reload(
+            State(state.clone()),
+            bearer("secret"),
+            None,
+            Some(Json(payload)),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(state.db_path(), replacement);
+        assert_eq!(state.db.read().unwrap().generation, 1);
+
+        for path in [original, replacement, broken] {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+
+    #[tokio::test]
+    async fn request_ids_are_echoed_and_included_in_errors() {
+        let app = router(AppState::new("unused.sqlite".to_string(), false));
+        let response = app
+            .clone()
+            .oneshot(Request::get("/healthz").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let generated = response.headers()[REQUEST_ID_HEADER].to_str().unwrap();
+        assert_eq!(generated.len(), 32);
+
+        let request = Request::post("/ascii")
+            .header(header::CONTENT_TYPE, "application/json")
+            .header(REQUEST_ID_HEADER, "trace-42")
+            .body(Body::from(r#"{"domain":"a.com","min_similarity":5}"#))
+            .unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(response.headers()[REQUEST_ID_HEADER], "trace-42");
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["request_id"], "trace-42");
+    }
+
+    #[tokio::test]
+    async fn api_keys_gate_routes_by_scope() {
+        let keys_db = tmp_db("keys", false);
+        let conn = keys::open(&keys_db).unwrap();
+        let reader = keys::create(
+            &conn,
+            "dashboard",
+            Scope::Read,
+            None,
+            keys::Quotas::default(),
+        )
+        .unwrap();
+        let app = router(AppState {
+            keys_db: Some(keys_db.clone()),
+            admin_token: Some("secret".into()),
+            ..AppState::new("unused.sqlite".to_string(), false)
+        });
+        let status = |method: &str, path: &str, token: Option<&str>| {
+            let mut request = Request::builder().method(method).uri(path);
+            if let Some(token) = token {
+                request = request.header(header::AUTHORIZATION, format!("Bearer {token}"));
+            }
+            let app = app.clone();
+            async move {
+                app.oneshot(request.body(Body::empty()).unwrap())
+                    .await
+                    .unwrap()
+                    .status()
+            }
+        };
+
+        assert_eq!(status("GET", "/healthz", None).await, StatusCode::OK);
+        assert_eq!(
+            status("GET", "/version", None).await,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            status("GET", "/version", Some("wrong")).await,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            status("GET", "/version", Some(&reader)).await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            status("POST", "/admin/reload", Some(&reader)).await,
+            StatusCode::FORBIDDEN
+        );
+        // The admin token still reaches the admin endpoints; --no-db rejects
+        // the reload itself.
+        assert_eq!(
+            status("POST", "/admin/reload", Some("secret")).await,
+            StatusCode::CONFLICT
+        );
+
+        keys::revoke(&conn, "dashboard").unwrap();
+        assert_eq!(
+            status("GET", "/version", Some(&reader)).await,
+            StatusCode::UNAUTHORIZED
+        );
+
+        let _ = std::fs::remove_file(keys_db);
+    }
+
+    #[tokio::test]
+    async fn key_quotas_refuse_requests_and_report_usage() {
+        let keys_db = tmp_db("quotas", false);
+        let conn = keys::open(&keys_db).unwrap();
+        let quotas = keys::Quotas {
+            daily: Some(1),
+            monthly: None,
+        };
+        let batch = keys::create(&conn, "batch", Scope::Read, None, quotas).unwrap();
+        let app = router(AppState {
+            keys_db: Some(keys_db.clone()),
+            admin_token: Some("secret".into()),
+            ..AppState::new("unused.sqlite".to_string(), false)
+        });
+        let get = |path: &str, token: &str| {
+            let request = Request::get(path)
+                .header(header::AUTHORIZATION, format!("Bearer {token}"))
+                .body(Body::empty())
+                .unwrap();
+            app.clone().oneshot(request)
+        };
+
+        let response = get("/version", &batch).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["x-quota-daily-limit"], "1");
+        assert_eq!(response.headers()["x-quota-daily-remaining"], "0");
+        assert!(response.headers().get("x-quota-monthly-limit").is_none());
+
+        let response = get("/version", &batch).await.unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert!(response.headers().contains_key(header::RETRY_AFTER));
+
+        let response = get("/admin/usage", "secret").await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let usage: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(usage[0]["name"], "batch");
+        assert_eq!(usage[0]["today"], 1);
+        assert_eq!(usage[0]["daily_quota"], 1);
+
+        let _ = std::fs::remove_file(keys_db);
+    }
+
+    #[tokio::test]
+    async fn tenant_keys_add_brands_to_ascii_lookups() {
+        let tenant_db = tmp_db("tenants", false);
+        let conn = tenants::open(&tenant_db).unwrap();
+        let key = tenants::create(&conn, "acme").unwrap();
+        tenants::add_brands(&conn, "acme", &["acmebank.com".to_string()]).unwrap();
+        let state = AppState {
+            tenant_db: Some(tenant_db.clone()),
+            ..AppState::new("unused.sqlite".to_string(), false)
+        };
+
+        let response = ascii(
+            State(state.clone()),
+            HeaderMap::new(),
+            Json(lookup_request("acmebamk.com")),
+        )
+        .await;
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let global: AsciiResponse = serde_json::from_slice(&body).unwrap();
+        assert!(global.results.is_empty());
+
+        let mut headers = HeaderMap::new();
+        headers.insert(API_KEY_HEADER, key.parse().unwrap());
+        let response = ascii(
+            State(state.clone()),
+            headers,
+            Json(lookup_request("acmebamk.com")),
+        )
+        .await;
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let scoped: AsciiResponse = serde_json::from_slice(&body).unwrap();
+        assert_eq!(scoped.results[0].domain, "acmebank.com");
+
+        let mut headers = HeaderMap::new();
+        headers.insert(API_KEY_HEADER, "wrong".parse().unwrap());
+        let response = lookup(State(state), headers, Json(lookup_request("acmebamk.com"))).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let _ = std::fs::remove_file(tenant_db);
+    }
 }
@@ -0,0 +1,350 @@
+/*
+Optional gRPC front end (`--features grpc`). Mirrors the HTTP lookup handlers
+using the shared AppState, so both servers share the DB handle and cache. Calls
+are authenticated and metered against `--keys-db` like HTTP requests (Bearer
+key in the `authorization` metadata), and a tenant `x-api-key` applies the
+tenant's brands and allowlist.
+*/
+use anyhow::Result;
+use std::net::SocketAddr;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::{Request, Response, Status};
+
+use super::{
+    ascii_result, bearer_token, constant_time_eq, consume_key, idn_result, page, quota_message,
+    request_overrides, AppState, API_KEY_HEADER,
+};
+use crate::tenants::{self, Tenant};
+use crate::types::{Algorithm, LookupResponse, PassiveDns, PunycodeOverflow};
+
+pub mod proto {
+    tonic::include_proto!("spotspoof.v1");
+}
+
+use proto::spot_spoof_server::{SpotSpoof, SpotSpoofServer};
+
+// Interceptors must return a bare `Status`.
+#[allow(clippy::result_large_err)]
+pub(super) async fn serve(state: AppState, addr: SocketAddr) -> Result<()> {
+    println!("gRPC listening on {addr}");
+    let auth = state.clone();
+    tonic::transport::Server::builder()
+        .add_service(SpotSpoofServer::with_interceptor(
+            GrpcService { state },
+            move |request| authorize(&auth, request),
+        ))
+        .serve(addr)
+        .await?;
+    Ok(())
+}
+
+/// The gRPC side of `authenticate` and `request_tenant`. With `--keys-db`,
+/// every call needs a valid key and counts against its quotas; a known tenant
+/// key is passed on in the request's extensions. Interceptors are synchronous,
+/// so the SQLite lookups run inline.
+#[allow(clippy::result_large_err)]
+fn authorize(state: &AppState, mut request: Request<()>) -> Result<Request<()>, Status> {
+    let headers = request.metadata().clone().into_headers();
+    if let Some(path) = &state.keys_db {
+        let key =
+            bearer_token(&headers).ok_or_else(|| Status::unauthenticated("Missing API key"))?;
+        let admin = state
+            .admin_token
+            .as_deref()
+            .is_some_and(|token| constant_time_eq(key.as_bytes(), token.as_bytes()));
+        if !admin {
+            match consume_key(path, key).map_err(|err| Status::internal(err.to_string()))? {
+                Some((_, consumed)) if !consumed.allowed => {
+                    return Err(Status::resource_exhausted(quota_message(&consumed)))
+                }
+                Some(_) => {}
+                None => return Err(Status::unauthenticated("Invalid or expired API key")),
+            }
+        }
+    }
+    if let (Some(path), Some(key)) = (&state.tenant_db, headers.get(API_KEY_HEADER)) {
+        let key = key
+            .to_str()
+            .map_err(|_| Status::unauthenticated("Invalid API key"))?;
+        let tenant = tenants::open(path)
+            .and_then(|conn| tenants::find_by_key(&conn, key))
+            .map_err(|err| Status::internal(err.to_string()))?
+            .ok_or_else(|| Status::unauthenticated("Invalid API key"))?;
+        request.extensions_mut().insert(tenant);
+    }
+    Ok(request)
+}
+
+struct GrpcService {
+    state: AppState,
+}
+
+#[tonic::async_trait]
+impl SpotSpoof for GrpcService {
+    async fn lookup(
+        &self,
+        request: Request<proto::LookupRequest>,
+    ) -> Result<Response<proto::LookupResponse>, Status> {
+        let tenant = request.extensions().get::<Tenant>().cloned();
+        let response = run_lookup(&self.state, tenant.as_ref(), request.into_inner()).await?;
+        Ok(Response::new(response))
+    }
+
+    type BatchLookupStream = ReceiverStream<Result<proto::LookupResponse, Status>>;
+
+    async fn batch_lookup(
+        &self,
+        request: Request<proto::BatchLookupRequest>,
+    ) -> Result<Response<Self::BatchLookupStream>, Status> {
+        let tenant = request.extensions().get::<Tenant>().cloned();
+        let requests = request.into_inner().requests;
+        let state = self.state.clone();
+        let (tx, rx) = tokio::sync::mpsc::channel(16);
+        tokio::spawn(async move {
+            for request in requests {
+                let domain = request.domain.clone();
+                let response = run_lookup(&state, tenant.as_ref(), request)
+                    .await
+                    .unwrap_or_else(|status| proto::LookupResponse {
+                        q: domain,
+                        error: status.message().to_string(),
+                        ..Default::default()
+                    });
+                if tx.send(Ok(response)).await.is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+}
+
+async fn run_lookup(
+    state: &AppState,
+    tenant: Option<&Tenant>,
+    request: proto::LookupRequest,
+) -> Result<proto::LookupResponse, Status> {
+    let mode = request.mode();
+    let algorithm = request.algorithm();
+    let punycode_overflow = request
+        .punycode_overflow
+        .map(|_| match request.punycode_overflow() {
+            proto::PunycodeOverflow::Reject => PunycodeOverflow::Reject,
+            proto::PunycodeOverflow::Keep => PunycodeOverflow::Keep,
+        });
+    let payload = crate::types::LookupRequest {
+        domain: request.domain,
+        min_similarity: request
+            .min_similarity
+            .map(|value| u8::try_from(value).unwrap_or(u8::MAX)),
+        max_results: request.max_results.map(|value| value as usize),
+        algorithm: Some(match algorithm {
+            proto::Algorithm::Levenshtein => Algorithm::Levenshtein,
+            proto::Algorithm::DamerauLevenshtein => Algorithm::DamerauLevenshtein,
+        }),
+        check_registration: request.check_registration,
+        max_variants: request.max_variants.map(|value| value as usize),
+        max_checks: request.max_checks.map(|value| value as usize),
+        whois_timeout_ms: request.whois_timeout_ms.map(u64::from),
+        dataset: request.dataset,
+        leet: request.leet,
+        phonetic: request.phonetic,
+        limit: request.limit.map(|value| value as usize),
+        offset: request.offset.map(|value| value as usize),
+        idna_transitional: request.idna_transitional,
+        idna_std3_rules: request.idna_std3_rules,
+        punycode_overflow,
+    };
+    let overrides = request_overrides(state, &payload).map_err(Status::invalid_argument)?;
+    let domain = crate::types::normalize_domain_input(&payload.domain);
+    let is_idn = match mode {
+        proto::Mode::Auto => crate::types::is_idn(&domain),
+        proto::Mode::Ascii => false,
+        proto::Mode::Idn => true,
+    };
+
+    let result = if is_idn {
+        idn_result(state, domain, overrides.idn, overrides.dataset)
+            .await
+            .map(|mut response| {
+                page(&mut response.results, overrides.offset);
+                LookupResponse::Idn(response)
+            })
+    } else {
+        ascii_result(state, domain, overrides.ascii, overrides.dataset)
+            .await
+            .map(|mut response| {
+                if let Some(tenant) = tenant {
+                    tenants::apply(tenant, &mut response, &overrides.ascii);
+                }
+                page(&mut response.results, overrides.offset);
+                LookupResponse::Ascii(response)
+            })
+    };
+    result
+        .map(to_proto)
+        .map_err(|err| Status::internal(err.to_string()))
+}
+
+fn to_proto(response: LookupResponse) -> proto::LookupResponse {
+    match response {
+        LookupResponse::Ascii(data) => proto::LookupResponse {
+            q: data.q,
+            ascii: data.ascii,
+            puny: data.puny,
+            exact_match: data.exact_match,
+            protected_brand: data.protected_brand,
+            total_candidates_considered: data.total_candidates_considered.map(|count| count as u64),
+            ascii_results: data
+                .results
+                .into_iter()
+                .map(|result| proto::AsciiResult {
+                    domain: result.domain,
+                    similarity: u32::from(result.similarity),
+                })
+                .collect(),
+            passive_dns: data.passive_dns.map(passive_dns_to_proto),
+            ..Default::default()
+        },
+        LookupResponse::Idn(data) => proto::LookupResponse {
+            q: data.q,
+            ascii: data.ascii,
+            puny: data.puny,
+            total_candidates_considered: data.total_candidates_considered.map(|count| count as u64),
+            idn_results: data
+                .results
+                .into_iter()
+                .map(|result| proto::IdnResult {
+                    domain: result.domain,
+                    mappings: result
+                        .mappings
+                        .into_iter()
+                        .map(|mapping| proto::PunyMapping {
+                            unicode: mapping.unicode,
+                            ascii: mapping.ascii,
+                        })
+                        .collect(),
+                    is_registered: result.is_registered,
+                    registration_checked: result.registration_checked,
+                    registration_status: result
+                        .registration_status
+                        .map(|status| status.as_str().to_string())
+                        .unwrap_or_default(),
+                })
+                .collect(),
+            idna_errors: data
+                .idna_errors
+                .into_iter()
+                .map(|error| proto::IdnaError {
+                    category: error.category,
+                    message: error.message,
+                })
+                .collect(),
+            passive_dns: data.passive_dns.map(passive_dns_to_proto),
+            ..Default::default()
+        },
+    }
+}
+
+fn passive_dns_to_proto(history: PassiveDns) -> proto::PassiveDns {
+    proto::PassiveDns {
+        first_seen: history.first_seen.unwrap_or_default(),
+        last_seen: history.last_seen.unwrap_or_default(),
+        ips: history.ips,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn batch_lookup_streams_each_response() {
+        let service = GrpcService {
+            state: AppState::new("unused.sqlite".to_string(), false),
+        };
+        let request = proto::BatchLookupRequest {
+            requests: vec![
+                proto::LookupRequest {
+                    domain: "gooble.com".to_string(),
+                    mode: proto::Mode::Ascii as i32,
+                    ..Default::default()
+                },
+                proto::LookupRequest {
+                    domain: "gooble.com".to_string(),
+                    min_similarity: Some(5),
+                    ..Default::default()
+                },
+            ],
+        };
+        let mut stream = service
+            .batch_lookup(Request::new(request))
+            .await
+            .unwrap()
+            .into_inner()
+            .into_inner();
+
+        let first = stream.recv().await.unwrap().unwrap();
+        assert_eq!(first.q, "gooble.com");
+        assert!(first.ascii && first.error.is_empty());
+        let second = stream.recv().await.unwrap().unwrap();
+        assert!(second.error.contains("min_similarity"));
+        assert!(stream.recv().await.is_none());
+    }
+
+    #[test]
+    fn calls_need_a_key_with_quota_left() {
+        use crate::keys::{self, Scope};
+        use tonic::Code;
+
+        let keys_db = std::env::temp_dir()
+            .join(format!("spotspoof-grpc-keys-{}.sqlite", std::process::id()))
+            .to_string_lossy()
+            .into_owned();
+        let _ = std::fs::remove_file(&keys_db);
+        let quotas = keys::Quotas {
+            daily: Some(1),
+            monthly: None,
+        };
+        let conn = keys::open(&keys_db).unwrap();
+        let key = keys::create(&conn, "grpc", Scope::Read, None, quotas).unwrap();
+        let state = AppState {
+            keys_db: Some(keys_db.clone()),
+            ..AppState::new("unused.sqlite".to_string(), false)
+        };
+        let call = |key: Option<&str>| {
+            let mut request = Request::new(());
+            if let Some(key) = key {
+                let value = format!("Bearer {key}").parse().unwrap();
+                request.metadata_mut().insert("authorization", value);
+            }
+            authorize(&state, request)
+                .map(|_| ())
+                .map_err(|status| status.code())
+        };
+        assert_eq!(call(None), Err(Code::Unauthenticated));
+        assert_eq!(call(Some("not-a-key")), Err(Code::Unauthenticated));
+        assert_eq!(call(Some(&key)), Ok(()));
+        assert_eq!(call(Some(&key)), Err(Code::ResourceExhausted));
+        let _ = std::fs::remove_file(keys_db);
+    }
+
+    #[tokio::test]
+    async fn lookups_apply_the_tenant() {
+        let state = AppState::new("unused.sqlite".to_string(), false);
+        let tenant = Tenant {
+            name: "acme".to_string(),
+            brands: vec!["acmebank.com".to_string()],
+            allowlist: Default::default(),
+        };
+        let request = || proto::LookupRequest {
+            domain: "acmebamk.com".to_string(),
+            mode: proto::Mode::Ascii as i32,
+            ..Default::default()
+        };
+        let response = run_lookup(&state, Some(&tenant), request()).await.unwrap();
+        assert_eq!(response.ascii_results[0].domain, "acmebank.com");
+        let response = run_lookup(&state, None, request()).await.unwrap();
+        assert!(response.ascii_results.is_empty());
+    }
+}
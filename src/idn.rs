@@ -3,12 +3,14 @@ IDN and punycode lookup logic. Expands Unicode confusables to ASCII candidates,
 checks registration status, and returns mappings that explain character swaps.
 */
 use anyhow::Result;
-use idna::domain_to_unicode;
 use once_cell::sync::Lazy;
 use std::collections::HashMap;
+use tracing::debug;
 
-use crate::types::{IdnResponse, IdnResult, PunyMapping};
-use crate::whois;
+use crate::puny::IdnaOptions;
+use crate::types::{self, IdnResponse, IdnResult, IdnaError, PunyMapping, RegistrationStatus};
+use crate::whois::{self, RegistrationCheck};
+use crate::{homoglyphs, mappings, net};
 
 const DEFAULT_MAX_NORMALIZED: usize = 2000;
 const DEFAULT_MAX_WHOIS_CHECKS: usize = 200;
@@ -16,67 +18,218 @@ const DEFAULT_MAX_RESULTS: usize = 50;
 const DEFAULT_MAX_RESULTS_TIMEOUT: usize = 5;
 const DEFAULT_WHOIS_TIMEOUT_MS: u64 = 2500;
 
+/// The installed mappings file (see `spotspoof mappings update`), or the
+/// compiled-in copy, in the priority of the installed homoglyph weights.
 static MAPPINGS: Lazy<HashMap<String, Vec<String>>> = Lazy::new(|| {
-    let data = include_str!("../data/puny-mappings.json");
-    serde_json::from_str(data).expect("puny-mappings.json must be valid JSON")
+    let mut mappings = mappings::active();
+    if let Some(weights) = homoglyphs::installed() {
+        weights.prioritize(&mut mappings);
+    }
+    mappings
 });
 
+/// The inverse of `MAPPINGS`: for each ASCII character, the non-ASCII
+/// characters that pass for it, sorted.
+static HOMOGLYPHS: Lazy<HashMap<char, Vec<char>>> = Lazy::new(|| {
+    let mut homoglyphs: HashMap<char, Vec<char>> = HashMap::new();
+    for (unicode, asciis) in MAPPINGS.iter() {
+        let mut chars = unicode.chars();
+        let (Some(unicode), None) = (chars.next(), chars.next()) else {
+            continue;
+        };
+        if unicode.is_ascii() {
+            continue;
+        }
+        for ascii in asciis {
+            let mut chars = ascii.chars();
+            if let (Some(ascii), None) = (chars.next(), chars.next()) {
+                homoglyphs.entry(ascii).or_default().push(unicode);
+            }
+        }
+    }
+    for chars in homoglyphs.values_mut() {
+        chars.sort_unstable();
+    }
+    homoglyphs
+});
+
+/// Non-ASCII characters that are confusable with `ascii`.
+pub fn homoglyphs_of(ascii: char) -> &'static [char] {
+    HOMOGLYPHS.get(&ascii).map_or(&[], Vec::as_slice)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IdnOptions {
+    pub max_normalized: usize,
+    pub max_whois_checks: usize,
+    pub max_results: usize,
+    pub max_results_timeout: usize,
+    pub whois_timeout_ms: u64,
+    pub check_registration: bool,
+    /// How the query is decoded to Unicode.
+    pub idna: IdnaOptions,
+}
+
+impl Default for IdnOptions {
+    fn default() -> Self {
+        Self {
+            max_normalized: DEFAULT_MAX_NORMALIZED,
+            max_whois_checks: DEFAULT_MAX_WHOIS_CHECKS,
+            max_results: DEFAULT_MAX_RESULTS,
+            max_results_timeout: DEFAULT_MAX_RESULTS_TIMEOUT,
+            whois_timeout_ms: DEFAULT_WHOIS_TIMEOUT_MS,
+            check_registration: true,
+            idna: IdnaOptions::default(),
+        }
+    }
+}
+
+impl IdnOptions {
+    pub fn from_env() -> Self {
+        Self {
+            max_normalized: env_usize("PUNY_MAX_NORMALIZED", DEFAULT_MAX_NORMALIZED),
+            max_whois_checks: env_usize("WHOIS_MAX_CHECKS", DEFAULT_MAX_WHOIS_CHECKS),
+            max_results: env_usize("PUNY_MAX_RESULTS", DEFAULT_MAX_RESULTS),
+            max_results_timeout: env_usize("PUNY_MAX_RESULTS_TIMEOUT", DEFAULT_MAX_RESULTS_TIMEOUT),
+            whois_timeout_ms: env_u64("WHOIS_TIMEOUT_MS", DEFAULT_WHOIS_TIMEOUT_MS),
+            check_registration: true,
+            idna: IdnaOptions::default(),
+        }
+    }
+}
+
 pub fn lookup_idn(domain: &str) -> Result<IdnResponse> {
-    let results = puny2url(domain)?;
+    lookup_idn_with_options(domain, &IdnOptions::from_env())
+}
+
+pub fn lookup_idn_with_options(domain: &str, options: &IdnOptions) -> Result<IdnResponse> {
+    lookup_idn_streaming(domain, options, |_| {})
+}
+
+/// Like `lookup_idn_with_options`, but calls `on_result` as each variant is
+/// confirmed so callers can report progress before the scan finishes.
+pub fn lookup_idn_streaming<R>(
+    domain: &str,
+    options: &IdnOptions,
+    on_result: R,
+) -> Result<IdnResponse>
+where
+    R: FnMut(&IdnResult),
+{
+    let (results, considered) =
+        puny2url_streaming(domain, options, whois::check_domain_registration, on_result)?;
+    let (total_candidates_considered, idna_errors) = match considered {
+        Ok(considered) => (Some(considered), Vec::new()),
+        Err(errors) => (None, errors),
+    };
     Ok(IdnResponse {
         q: domain.to_string(),
         ascii: false,
         puny: true,
         results,
+        dataset: None,
+        total_candidates_considered,
+        idna_errors,
+        passive_dns: None,
     })
 }
 
-fn puny2url(idn_domain: &str) -> Result<Vec<IdnResult>> {
-    puny2url_with_checker(idn_domain, whois::check_domain_registration)
+#[cfg(test)]
+fn puny2url_with_checker<F>(
+    idn_domain: &str,
+    options: &IdnOptions,
+    checker: F,
+) -> Result<Vec<IdnResult>>
+where
+    F: Fn(&str, u64) -> Result<RegistrationCheck>,
+{
+    Ok(puny2url_streaming(idn_domain, options, checker, |_| {})?.0)
 }
 
-fn puny2url_with_checker<F>(idn_domain: &str, checker: F) -> Result<Vec<IdnResult>>
+/// How many variants of a query were enumerated, or the IDNA errors that kept
+/// it from decoding.
+type Considered = Result<usize, Vec<IdnaError>>;
+
+/// The confirmed variants of `idn_domain`, and how many were enumerated.
+fn puny2url_streaming<F, R>(
+    idn_domain: &str,
+    options: &IdnOptions,
+    checker: F,
+    mut on_result: R,
+) -> Result<(Vec<IdnResult>, Considered)>
 where
-    F: Fn(&str, u64) -> Result<(bool, bool)>,
+    F: Fn(&str, u64) -> Result<RegistrationCheck>,
+    R: FnMut(&IdnResult),
 {
-    let unicode_domain = decode_idn_to_unicode(idn_domain);
-    let Some(unicode_domain) = unicode_domain else {
-        return Ok(Vec::new());
+    let unicode_domain = match options.idna.to_unicode(idn_domain) {
+        Ok(unicode_domain) => unicode_domain,
+        Err(errors) => return Ok((Vec::new(), Err(errors))),
     };
 
-    let max_normalized = env_usize("PUNY_MAX_NORMALIZED", DEFAULT_MAX_NORMALIZED);
-    let max_whois_checks = env_usize("WHOIS_MAX_CHECKS", DEFAULT_MAX_WHOIS_CHECKS);
-    let max_results = env_usize("PUNY_MAX_RESULTS", DEFAULT_MAX_RESULTS);
-    let max_results_timeout = env_usize("PUNY_MAX_RESULTS_TIMEOUT", DEFAULT_MAX_RESULTS_TIMEOUT);
-    let whois_timeout = env_u64("WHOIS_TIMEOUT_MS", DEFAULT_WHOIS_TIMEOUT_MS);
+    let normalized_domains = variants(&unicode_domain, options.max_normalized);
+    let considered = Ok(normalized_domains.len());
+
+    // Offline mode lists variants without checking them, as if asked not to.
+    if !options.check_registration || net::offline() {
+        let results = normalized_domains
+            .into_iter()
+            .take(options.max_results)
+            .map(|domain| {
+                let result = IdnResult {
+                    mappings: map_unicode_to_ascii(&unicode_domain, &domain),
+                    domain,
+                    is_registered: false,
+                    registration_checked: false,
+                    registration_status: None,
+                };
+                on_result(&result);
+                result
+            })
+            .collect();
+        return Ok((results, considered));
+    }
 
-    let normalized_domains = normalize_domain(&unicode_domain, &MAPPINGS, max_normalized);
     let mut results: Vec<IdnResult> = Vec::new();
     let mut timed_out = false;
 
     for (checks, domain) in normalized_domains.into_iter().enumerate() {
-        if checks >= max_whois_checks {
+        if checks >= options.max_whois_checks {
             break;
         }
-        if !timed_out && results.len() >= max_results {
+        if !timed_out && results.len() >= options.max_results {
             break;
         }
-        if timed_out && results.len() >= max_results_timeout {
+        if timed_out && results.len() >= options.max_results_timeout {
             break;
         }
 
-        let (registered, lookup_timed_out) = checker(&domain, whois_timeout)?;
-
-        if lookup_timed_out {
+        let check = checker(&domain, options.whois_timeout_ms)?;
+        debug!(
+            kind = "whois",
+            domain = %domain,
+            status = ?check.status,
+            method = ?check.method,
+            rcode = ?check.rcode,
+            latency_ms = check.latency.as_millis() as u64,
+            timed_out = check.timed_out,
+            error = ?check.error,
+        );
+
+        if check.timed_out {
             timed_out = true;
         }
 
-        if registered {
-            results.push(IdnResult {
+        // Unknown answers are reported too: the variant may well be live.
+        if check.status != RegistrationStatus::Unregistered {
+            let result = IdnResult {
                 domain: domain.clone(),
                 mappings: map_unicode_to_ascii(&unicode_domain, &domain),
-                is_registered: true,
-            });
+                is_registered: check.status == RegistrationStatus::Registered,
+                registration_checked: true,
+                registration_status: Some(check.status),
+            };
+            on_result(&result);
+            results.push(result);
         }
 
         if timed_out {
@@ -84,22 +237,42 @@ where
         }
     }
 
-    if timed_out && results.len() > max_results_timeout {
-        results.truncate(max_results_timeout);
+    if timed_out && results.len() > options.max_results_timeout {
+        results.truncate(options.max_results_timeout);
     }
 
-    Ok(results)
+    rank_results(&mut results);
+    Ok((results, considered))
 }
 
-fn decode_idn_to_unicode(idn_domain: &str) -> Option<String> {
-    if idn_domain.chars().any(|c| c.is_whitespace()) {
-        return None;
-    }
-    let (unicode, errors) = domain_to_unicode(idn_domain);
-    if errors.is_err() {
-        return None;
+/// Orders confirmed variants like ASCII results: registered before unknown
+/// (the closest IDN has to a score), then by rank, the order the mapping table
+/// generates variants in. Variants are unique, so that order is total and no
+/// domain tie-break is needed. Streamed results still arrive in check order.
+fn rank_results(results: &mut [IdnResult]) {
+    let score = |result: &IdnResult| match result.registration_status {
+        Some(RegistrationStatus::Registered) => 0,
+        Some(RegistrationStatus::Unknown) => 1,
+        _ => 2,
+    };
+    // Stable, so variants keep their rank within a status.
+    results.sort_by_key(score);
+}
+
+/// The ASCII variants of `unicode_domain`. An internationalized TLD is the
+/// registry's rather than a homoglyph, so only the labels under it are
+/// expanded and it is kept, in punycode.
+fn variants(unicode_domain: &str, max_normalized: usize) -> Vec<String> {
+    match types::split_tld(unicode_domain) {
+        Some((name, tld)) if types::is_idn_tld(tld) => {
+            let tld = idna::domain_to_ascii(tld).unwrap_or_else(|_| tld.to_string());
+            normalize_domain(name, &MAPPINGS, max_normalized)
+                .into_iter()
+                .map(|name| format!("{name}.{tld}"))
+                .collect()
+        }
+        _ => normalize_domain(unicode_domain, &MAPPINGS, max_normalized),
     }
-    Some(unicode)
 }
 
 fn normalize_domain(
@@ -138,6 +311,11 @@ fn normalize_domain(
 }
 
 fn map_unicode_to_ascii(unicode_domain: &str, normalized_domain: &str) -> Vec<PunyMapping> {
+    // An internationalized TLD is kept rather than mapped (see `variants`).
+    let unicode_domain = match types::split_tld(unicode_domain) {
+        Some((name, tld)) if types::is_idn_tld(tld) => name,
+        _ => unicode_domain,
+    };
     let unicode_chars: Vec<char> = unicode_domain.chars().collect();
     let ascii_chars: Vec<char> = normalized_domain.chars().collect();
     let mut mappings = Vec::new();
@@ -185,7 +363,10 @@ mod tests {
         let domain = "амаzоn.com";
         let result = lookup_idn_with_checker(domain, |candidate, _| {
             let registered = matches!(candidate, "amazon.com" | "amaz0n.com");
-            Ok((registered, false))
+            Ok(check(
+                RegistrationStatus::from_registered(registered),
+                false,
+            ))
         })
         .expect("lookup should succeed");
 
@@ -197,6 +378,8 @@ mod tests {
                 {
                     "domain": "amazon.com",
                     "is_registered": true,
+                    "registration_checked": true,
+                    "registration_status": "registered",
                     "mappings": [
                         { "ascii": "a", "unicode": "а" },
                         { "ascii": "m", "unicode": "м" },
@@ -207,6 +390,8 @@ mod tests {
                 {
                     "domain": "amaz0n.com",
                     "is_registered": true,
+                    "registration_checked": true,
+                    "registration_status": "registered",
                     "mappings": [
                         { "ascii": "a", "unicode": "а" },
                         { "ascii": "m", "unicode": "м" },
@@ -221,8 +406,97 @@ mod tests {
     }
 
     #[test]
-    fn decode_idn_to_unicode_rejects_invalid() {
-        assert!(decode_idn_to_unicode("bad domain").is_none());
+    fn skipping_registration_returns_unchecked_variants() {
+        let options = IdnOptions {
+            max_results: 2,
+            check_registration: false,
+            ..IdnOptions::default()
+        };
+        let results = puny2url_with_checker("амаzоn.com", &options, |_, _| {
+            panic!("checker must not be called")
+        })
+        .unwrap();
+        assert_eq!(results.len(), 2);
+        assert!(results
+            .iter()
+            .all(|r| !r.is_registered && !r.registration_checked));
+    }
+
+    #[test]
+    fn unknown_checks_are_reported_rather_than_dropped() {
+        let results =
+            puny2url_with_checker("амаzоn.com", &IdnOptions::default(), |candidate, _| {
+                Ok(match candidate {
+                    "amazon.com" => check(RegistrationStatus::Unknown, false),
+                    "amaz0n.com" => check(RegistrationStatus::Unknown, true),
+                    _ => check(RegistrationStatus::Unregistered, false),
+                })
+            })
+            .unwrap();
+        let statuses: Vec<_> = results
+            .iter()
+            .map(|r| (r.domain.as_str(), r.is_registered, r.registration_status))
+            .collect();
+        assert_eq!(
+            statuses,
+            vec![
+                ("amazon.com", false, Some(RegistrationStatus::Unknown)),
+                ("amaz0n.com", false, Some(RegistrationStatus::Unknown)),
+            ]
+        );
+    }
+
+    #[test]
+    fn registered_variants_rank_ahead_of_unknown_ones() {
+        let results =
+            puny2url_with_checker("амаzоn.com", &IdnOptions::default(), |candidate, _| {
+                Ok(match candidate {
+                    "amazon.com" => check(RegistrationStatus::Unknown, false),
+                    "amaz0n.com" => check(RegistrationStatus::Registered, false),
+                    _ => check(RegistrationStatus::Unregistered, false),
+                })
+            })
+            .unwrap();
+        // amazon.com is generated (and checked) first.
+        let domains: Vec<_> = results.iter().map(|r| r.domain.as_str()).collect();
+        assert_eq!(domains, vec!["amaz0n.com", "amazon.com"]);
+    }
+
+    #[test]
+    fn undecodable_queries_report_idna_errors() {
+        let options = IdnOptions::default();
+        let (results, considered) = puny2url_streaming(
+            "bad domain",
+            &options,
+            |_, _| panic!("nothing to check"),
+            |_| {},
+        )
+        .unwrap();
+        assert!(results.is_empty());
+        assert_eq!(considered.unwrap_err()[0].category, "whitespace");
+
+        let (_, considered) =
+            puny2url_streaming("xn--zz.com", &options, |_, _| panic!(), |_| {}).unwrap();
+        assert_eq!(considered.unwrap_err()[0].category, "punycode");
+    }
+
+    #[test]
+    fn internationalized_tlds_are_kept_rather_than_expanded() {
+        let options = IdnOptions {
+            check_registration: false,
+            ..IdnOptions::default()
+        };
+        let (results, _) =
+            puny2url_streaming("xn--pple-43d.xn--p1ai", &options, |_, _| panic!(), |_| {}).unwrap();
+        assert!(results
+            .iter()
+            .all(|result| result.domain.ends_with(".xn--p1ai")));
+        let apple = results
+            .iter()
+            .find(|result| result.domain == "apple.xn--p1ai")
+            .unwrap();
+        assert_eq!(apple.mappings.len(), 1);
+        assert_eq!(apple.mappings[0].unicode, "а");
     }
 
     #[test]
@@ -265,16 +539,31 @@ mod tests {
         std::env::remove_var("WHOIS_TIMEOUT_MS");
     }
 
+    fn check(status: RegistrationStatus, timed_out: bool) -> RegistrationCheck {
+        RegistrationCheck {
+            status,
+            method: whois::CheckMethod::Doh,
+            rcode: None,
+            latency: std::time::Duration::ZERO,
+            timed_out,
+            error: None,
+        }
+    }
+
     fn lookup_idn_with_checker<F>(domain: &str, checker: F) -> Result<IdnResponse>
     where
-        F: Fn(&str, u64) -> Result<(bool, bool)>,
+        F: Fn(&str, u64) -> Result<RegistrationCheck>,
     {
-        let results = puny2url_with_checker(domain, checker)?;
+        let results = puny2url_with_checker(domain, &IdnOptions::default(), checker)?;
         Ok(IdnResponse {
             q: domain.to_string(),
             ascii: false,
             puny: true,
             results,
+            dataset: None,
+            total_candidates_considered: None,
+            passive_dns: None,
+            idna_errors: Vec::new(),
         })
     }
 }
@@ -0,0 +1,412 @@
+/*
+Fuzzy candidate indexes built by `spotspoof db index`. An FST stored next to
+the SQLite file lets lookups walk a Levenshtein automaton; SymSpell deletes and
+trigram tables inside the DB answer the same question with indexed SQL. Any of
+them replaces the first-character/length band scan.
+*/
+use anyhow::Result;
+use fst::automaton::Levenshtein;
+use fst::{IntoStreamer, Set, SetBuilder, Streamer};
+use rusqlite::Connection;
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::ops::Deref;
+use std::path::Path;
+use tracing::warn;
+
+use crate::cipher;
+use crate::db::{self, CandidateQuery, CandidateSource};
+
+/// SymSpell only indexes this many leading characters, which bounds the deletes
+/// generated per domain (at most 29 for two edits).
+const SYMSPELL_PREFIX_LEN: usize = 7;
+const SYMSPELL_MAX_DISTANCE: u32 = 2;
+/// Levenshtein automata grow quickly with distance; past two edits the FST
+/// walk costs more than it saves.
+const FST_MAX_DISTANCE: u32 = 2;
+
+pub fn fst_path(db_path: &str) -> String {
+    format!("{db_path}.fst")
+}
+
+pub struct FstIndex {
+    set: Set<Vec<u8>>,
+}
+
+impl FstIndex {
+    pub fn open(path: &str) -> Result<Self> {
+        Ok(Self {
+            set: Set::new(fs::read(path)?)?,
+        })
+    }
+
+    /// Opens the FST next to `db_path`, if there is one. An index older than
+    /// the DB is ignored (with a warning) since it would miss new domains.
+    pub fn open_for_db(db_path: &str) -> Result<Option<Self>> {
+        let path = fst_path(db_path);
+        let Ok(index_meta) = fs::metadata(&path) else {
+            return Ok(None);
+        };
+        let db_modified = fs::metadata(db_path)?.modified()?;
+        if index_meta.modified()? < db_modified {
+            warn!(kind = "index", path = %path, "FST index is older than the DB; ignoring it");
+            return Ok(None);
+        }
+        Self::open(&path).map(Some)
+    }
+
+    pub fn len(&self) -> usize {
+        self.set.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.set.is_empty()
+    }
+}
+
+impl CandidateSource for FstIndex {
+    fn candidates(&self, query: &CandidateQuery) -> Result<Vec<String>> {
+        let automaton = Levenshtein::new(query.domain, query.max_distance.min(FST_MAX_DISTANCE))?;
+        let mut stream = self.set.search(automaton).into_stream();
+        let mut out = Vec::new();
+        while let Some(key) = stream.next() {
+            out.push(String::from_utf8_lossy(key).into_owned());
+            if out.len() >= query.limit {
+                break;
+            }
+        }
+        Ok(out)
+    }
+}
+
+/// Writes `<db>.fst` from `legit_domains` and returns its path and key count.
+pub fn build_fst(db_path: &str) -> Result<(String, usize)> {
+    let conn = db::open(db_path, false)?;
+    db::verify_schema(&conn)?;
+    let path = fst_path(db_path);
+    let tmp_path = format!("{path}.tmp");
+
+    // SQLite's BINARY collation orders by bytes, which is the order FST keys need.
+    let mut stmt = conn.prepare("SELECT DISTINCT domain FROM legit_domains ORDER BY domain")?;
+    let mut rows = stmt.query([])?;
+    let mut builder = SetBuilder::new(BufWriter::new(File::create(&tmp_path)?))?;
+    let mut count = 0;
+    while let Some(row) = rows.next()? {
+        builder.insert(row.get::<_, String>(0)?)?;
+        count += 1;
+    }
+    builder.finish()?;
+    fs::rename(&tmp_path, Path::new(&path))?;
+    Ok((path, count))
+}
+
+/// Candidate index tables that can live inside the SQLite DB.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SqlIndex {
+    SymSpell,
+    Trigram,
+}
+
+impl SqlIndex {
+    /// The index table present in the DB, preferring SymSpell over trigrams.
+    pub fn detect(conn: &Connection) -> Result<Option<Self>> {
+        Ok(Self::detect_all(conn)?.first().copied())
+    }
+
+    /// Every index table present in the DB, in preference order.
+    pub fn detect_all(conn: &Connection) -> Result<Vec<Self>> {
+        let mut found = Vec::new();
+        for (index, table) in [
+            (SqlIndex::SymSpell, "symspell_deletes"),
+            (SqlIndex::Trigram, "domain_trigrams"),
+        ] {
+            let mut stmt = conn.prepare_cached(
+                "SELECT name FROM sqlite_master WHERE type='table' AND name=?1 LIMIT 1",
+            )?;
+            if stmt.exists([table])? {
+                found.push(index);
+            }
+        }
+        Ok(found)
+    }
+
+    pub fn with<C>(self, conn: C) -> IndexedConnection<C> {
+        IndexedConnection { index: self, conn }
+    }
+}
+
+/// A DB connection whose candidates come from one of its index tables.
+pub struct IndexedConnection<C> {
+    index: SqlIndex,
+    conn: C,
+}
+
+impl<C: Deref<Target = Connection> + Send> CandidateSource for IndexedConnection<C> {
+    fn candidates(&self, query: &CandidateQuery) -> Result<Vec<String>> {
+        match self.index {
+            SqlIndex::SymSpell => symspell_candidates(&self.conn, query),
+            SqlIndex::Trigram => trigram_candidates(&self.conn, query),
+        }
+    }
+}
+
+fn symspell_candidates(conn: &Connection, query: &CandidateQuery) -> Result<Vec<String>> {
+    let variants: Vec<String> =
+        deletes(query.domain, query.max_distance.min(SYMSPELL_MAX_DISTANCE))
+            .into_iter()
+            .collect();
+    let placeholders = vec!["?"; variants.len()].join(", ");
+    let sql = format!(
+        "SELECT DISTINCT d.domain FROM symspell_deletes s
+		JOIN legit_domains d ON d.rowid = s.domain_id
+		WHERE s.variant IN ({placeholders}) LIMIT {}",
+        query.limit
+    );
+    let mut stmt = conn.prepare_cached(&sql)?;
+    let rows = stmt.query_map(rusqlite::params_from_iter(variants.iter()), |row| {
+        row.get::<_, String>(0)
+    })?;
+
+    let mut out = Vec::new();
+    for row in rows {
+        out.push(row?);
+    }
+    Ok(out)
+}
+
+/// Domains in the length band sharing enough trigrams with the query. By the
+/// q-gram lemma each edit destroys at most three trigrams, so a domain within
+/// `max_distance` edits shares at least `n - 3 * max_distance` of them.
+fn trigram_candidates(conn: &Connection, query: &CandidateQuery) -> Result<Vec<String>> {
+    let grams: Vec<String> = trigrams(query.domain).into_iter().collect();
+    if grams.is_empty() {
+        return Ok(Vec::new());
+    }
+    let min_shared = grams
+        .len()
+        .saturating_sub(3 * query.max_distance as usize)
+        .max(1);
+    let placeholders = vec!["?"; grams.len()].join(", ");
+    let sql = format!(
+        "SELECT d.domain FROM domain_trigrams t
+		JOIN legit_domains d ON d.rowid = t.domain_id
+		WHERE t.trigram IN ({placeholders}) AND d.length BETWEEN {} AND {}
+		GROUP BY t.domain_id HAVING COUNT(*) >= {min_shared}
+		ORDER BY COUNT(*) DESC LIMIT {}",
+        query.min_len, query.max_len, query.limit
+    );
+    let mut stmt = conn.prepare_cached(&sql)?;
+    let rows = stmt.query_map(rusqlite::params_from_iter(grams.iter()), |row| {
+        row.get::<_, String>(0)
+    })?;
+
+    let mut out = Vec::new();
+    for row in rows {
+        out.push(row?);
+    }
+    Ok(out)
+}
+
+/// (Re)builds the `symspell_deletes` table inside the DB and returns the number
+/// of rows written.
+pub fn build_symspell(db_path: &str) -> Result<usize> {
+    let mut conn = cipher::open(db_path)?;
+    db::verify_schema(&conn)?;
+    let tx = conn.transaction()?;
+    tx.execute_batch(
+        "DROP TABLE IF EXISTS symspell_deletes;
+		CREATE TABLE symspell_deletes (
+			variant TEXT NOT NULL,
+			domain_id INTEGER NOT NULL,
+			PRIMARY KEY (variant, domain_id)
+		) WITHOUT ROWID;",
+    )?;
+    let mut count = 0;
+    {
+        let mut select = tx.prepare("SELECT rowid, domain FROM legit_domains")?;
+        let mut insert = tx.prepare(
+            "INSERT OR IGNORE INTO symspell_deletes (variant, domain_id) VALUES (?1, ?2)",
+        )?;
+        let mut rows = select.query([])?;
+        while let Some(row) = rows.next()? {
+            let id: i64 = row.get(0)?;
+            let domain: String = row.get(1)?;
+            for variant in deletes(&domain, SYMSPELL_MAX_DISTANCE) {
+                count += insert.execute((variant, id))?;
+            }
+        }
+    }
+    tx.commit()?;
+    Ok(count)
+}
+
+/// (Re)builds the `domain_trigrams` table inside the DB and returns the number
+/// of rows written.
+pub fn build_trigrams(db_path: &str) -> Result<usize> {
+    let mut conn = cipher::open(db_path)?;
+    db::verify_schema(&conn)?;
+    let tx = conn.transaction()?;
+    tx.execute_batch(
+        "DROP TABLE IF EXISTS domain_trigrams;
+		CREATE TABLE domain_trigrams (
+			trigram TEXT NOT NULL,
+			domain_id INTEGER NOT NULL,
+			PRIMARY KEY (trigram, domain_id)
+		) WITHOUT ROWID;",
+    )?;
+    let mut count = 0;
+    {
+        let mut select = tx.prepare("SELECT rowid, domain FROM legit_domains")?;
+        let mut insert = tx.prepare(
+            "INSERT OR IGNORE INTO domain_trigrams (trigram, domain_id) VALUES (?1, ?2)",
+        )?;
+        let mut rows = select.query([])?;
+        while let Some(row) = rows.next()? {
+            let id: i64 = row.get(0)?;
+            let domain: String = row.get(1)?;
+            for gram in trigrams(&domain) {
+                count += insert.execute((gram, id))?;
+            }
+        }
+    }
+    tx.commit()?;
+    Ok(count)
+}
+
+fn trigrams(term: &str) -> HashSet<String> {
+    let chars: Vec<char> = term.chars().collect();
+    chars
+        .windows(3)
+        .map(|window| window.iter().collect())
+        .collect()
+}
+
+/// Every string reachable from the term's prefix by deleting up to
+/// `max_distance` characters, including the prefix itself.
+fn deletes(term: &str, max_distance: u32) -> HashSet<String> {
+    let prefix: String = term.chars().take(SYMSPELL_PREFIX_LEN).collect();
+    let mut out = HashSet::from([prefix.clone()]);
+    let mut frontier = vec![prefix];
+    for _ in 0..max_distance {
+        let mut next = Vec::new();
+        for word in &frontier {
+            for (idx, _) in word.char_indices() {
+                let mut variant = word.clone();
+                variant.remove(idx);
+                if out.insert(variant.clone()) {
+                    next.push(variant);
+                }
+            }
+        }
+        frontier = next;
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rusqlite::Connection;
+
+    #[test]
+    fn fst_index_finds_domains_within_distance() {
+        let mut path = std::env::temp_dir();
+        path.push(format!("spotspoof-index-{}.sqlite", std::process::id()));
+        let db_path = path.to_string_lossy().to_string();
+        let _ = fs::remove_file(&db_path);
+        Connection::open(&db_path)
+            .unwrap()
+            .execute_batch(
+                "CREATE TABLE legit_domains (domain TEXT, first_char TEXT, length INTEGER);
+                 INSERT INTO legit_domains VALUES ('google.com', 'g', 10);
+                 INSERT INTO legit_domains VALUES ('github.com', 'g', 10);
+                 INSERT INTO legit_domains VALUES ('google.com', 'g', 10);",
+            )
+            .unwrap();
+
+        let (index_path, count) = build_fst(&db_path).unwrap();
+        assert_eq!(count, 2);
+        let index = FstIndex::open_for_db(&db_path).unwrap().unwrap();
+        assert_eq!(index.len(), 2);
+
+        // The first character is substituted, which the band scan cannot find.
+        let query = CandidateQuery {
+            domain: "qoogle.com",
+            first_char: 'q',
+            min_len: 8,
+            max_len: 12,
+            max_distance: 1,
+            limit: 10,
+            strategies: Default::default(),
+            min_affix: 0,
+        };
+        assert_eq!(index.candidates(&query).unwrap(), vec!["google.com"]);
+
+        let _ = fs::remove_file(&db_path);
+        let _ = fs::remove_file(&index_path);
+    }
+
+    #[test]
+    fn deletes_cover_prefix_within_distance() {
+        let variants = deletes("google.com", 2);
+        assert!(variants.contains("google."));
+        assert!(variants.contains("oogle."));
+        assert!(variants.contains("ogle."));
+        assert!(!variants.iter().any(|variant| variant.contains("com")));
+        assert!(variants.len() <= 29);
+    }
+
+    #[test]
+    fn sql_indexes_find_first_character_substitutions() {
+        let mut path = std::env::temp_dir();
+        path.push(format!("spotspoof-symspell-{}.sqlite", std::process::id()));
+        let db_path = path.to_string_lossy().to_string();
+        let _ = fs::remove_file(&db_path);
+        Connection::open(&db_path)
+            .unwrap()
+            .execute_batch(
+                "CREATE TABLE legit_domains (domain TEXT, first_char TEXT, length INTEGER);
+                 INSERT INTO legit_domains VALUES ('google.com', 'g', 10);
+                 INSERT INTO legit_domains VALUES ('amazon.com', 'a', 10);",
+            )
+            .unwrap();
+
+        assert_eq!(
+            SqlIndex::detect(&db::open(&db_path, false).unwrap()).unwrap(),
+            None
+        );
+        assert!(build_trigrams(&db_path).unwrap() > 0);
+        let conn = db::open(&db_path, false).unwrap();
+        assert_eq!(SqlIndex::detect(&conn).unwrap(), Some(SqlIndex::Trigram));
+
+        let query = CandidateQuery {
+            domain: "qoogle.com",
+            first_char: 'q',
+            min_len: 8,
+            max_len: 12,
+            max_distance: 2,
+            limit: 10,
+            strategies: Default::default(),
+            min_affix: 0,
+        };
+        // Best trigram overlap first; scoring drops the weaker matches.
+        let candidates = SqlIndex::Trigram
+            .with(Box::new(conn))
+            .candidates(&query)
+            .unwrap();
+        assert_eq!(candidates[0], "google.com");
+
+        assert!(build_symspell(&db_path).unwrap() > 0);
+        let conn = db::open(&db_path, false).unwrap();
+        assert_eq!(SqlIndex::detect(&conn).unwrap(), Some(SqlIndex::SymSpell));
+        assert_eq!(
+            SqlIndex::SymSpell
+                .with(Box::new(conn))
+                .candidates(&query)
+                .unwrap(),
+            vec!["google.com"]
+        );
+        let _ = fs::remove_file(&db_path);
+    }
+}
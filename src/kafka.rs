@@ -0,0 +1,375 @@
+/*
+Optional Kafka sink (`[kafka]` in the config file; publishing needs a build
+with the `kafka` feature). The detections that the scan commands, `tail`, and
+`serve` find, and with `lookups = true` every lookup `serve` and the CLI
+answer, are published to one topic as `KafkaEvent` records keyed by domain.
+`format = "json"` writes them as JSON (`spotspoof schema kafka-event` prints
+the JSON Schema); `format = "avro"` writes Avro binary against
+`data/kafka-event.avsc`, prefixed with the Confluent wire header when a
+`schema_id` is configured. Records are queued and sent by librdkafka in the
+background; a failed delivery is logged and never fails a lookup.
+*/
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::OnceLock;
+#[cfg(feature = "kafka")]
+use std::time::Duration;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::warn;
+use utoipa::ToSchema;
+
+use crate::detections::Severity;
+use crate::scan::{self, ScanReport};
+use crate::types::LookupResponse;
+
+/// The Avro schema `format = "avro"` records are written with.
+pub const AVRO_SCHEMA: &str = include_str!("../data/kafka-event.avsc");
+/// How long queued records may take to deliver when the CLI exits.
+#[cfg(feature = "kafka")]
+const FLUSH_TIMEOUT: Duration = Duration::from_secs(10);
+
+static INSTALLED: OnceLock<KafkaSink> = OnceLock::new();
+
+/// The `[kafka]` section of the config file.
+#[derive(Debug, Clone, Default, Deserialize, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct KafkaConfig {
+    /// Bootstrap brokers (`host:port`); the sink is off without any.
+    pub brokers: Vec<String>,
+    pub topic: String,
+    pub format: KafkaFormat,
+    /// Schema registry id of `data/kafka-event.avsc`, written in the
+    /// Confluent wire header of Avro records.
+    pub schema_id: Option<u32>,
+    /// Also publish every lookup, not only detections.
+    pub lookups: bool,
+    /// Extra librdkafka settings (`"security.protocol" = "SASL_SSL"`, ...).
+    pub properties: BTreeMap<String, String>,
+}
+
+impl KafkaConfig {
+    pub fn enabled(&self) -> bool {
+        !self.brokers.is_empty()
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum KafkaFormat {
+    #[default]
+    Json,
+    Avro,
+}
+
+/// Why an event was published.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum EventKind {
+    /// A lookalike found by a scan, `tail`, or `serve`.
+    Detection,
+    /// A lookup answered by `serve` or the CLI (`lookups = true`).
+    Lookup,
+}
+
+/// One record on the topic.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct KafkaEvent {
+    pub kind: EventKind,
+    /// Unix milliseconds.
+    pub timestamp_ms: i64,
+    /// What produced it: `scan`, `tail`, `http`, or `cli`.
+    pub source: String,
+    pub domain: String,
+    /// The best match, if there is one.
+    pub target: Option<String>,
+    /// The match score weighted by the domain's TLD, 0-100.
+    pub risk: u8,
+    /// Set for lookalikes.
+    pub severity: Option<Severity>,
+    pub lookup: LookupResponse,
+}
+
+impl KafkaEvent {
+    pub fn new(kind: EventKind, source: &str, domain: &str, lookup: &LookupResponse) -> Self {
+        let risk = scan::risk(domain, lookup);
+        Self {
+            kind,
+            timestamp_ms: now_ms(),
+            source: source.to_string(),
+            domain: domain.to_string(),
+            target: scan::top_match(lookup).map(str::to_string),
+            risk,
+            severity: scan::is_lookalike(lookup).then(|| Severity::from_risk(risk)),
+            lookup: lookup.clone(),
+        }
+    }
+
+    /// The record value in `format`.
+    pub fn encode(&self, format: KafkaFormat, schema_id: Option<u32>) -> Result<Vec<u8>> {
+        match format {
+            KafkaFormat::Json => Ok(serde_json::to_vec(self)?),
+            KafkaFormat::Avro => self.to_avro(schema_id),
+        }
+    }
+
+    /// Avro binary encoding, fields in `AVRO_SCHEMA` order; `lookup` is
+    /// carried as a JSON string.
+    fn to_avro(&self, schema_id: Option<u32>) -> Result<Vec<u8>> {
+        let mut out = Vec::new();
+        if let Some(id) = schema_id {
+            out.push(0);
+            out.extend_from_slice(&id.to_be_bytes());
+        }
+        avro_long(&mut out, self.kind as i64);
+        avro_long(&mut out, self.timestamp_ms);
+        avro_string(&mut out, &self.source);
+        avro_string(&mut out, &self.domain);
+        match &self.target {
+            Some(target) => {
+                avro_long(&mut out, 1);
+                avro_string(&mut out, target);
+            }
+            None => avro_long(&mut out, 0),
+        }
+        avro_long(&mut out, i64::from(self.risk));
+        match self.severity {
+            Some(severity) => {
+                avro_long(&mut out, 1);
+                avro_long(&mut out, severity as i64);
+            }
+            None => avro_long(&mut out, 0),
+        }
+        avro_string(&mut out, &serde_json::to_string(&self.lookup)?);
+        Ok(out)
+    }
+}
+
+/// Zigzag varint, as Avro encodes `int`, `long`, enum, and union indexes.
+fn avro_long(out: &mut Vec<u8>, value: i64) {
+    let mut zigzag = ((value << 1) ^ (value >> 63)) as u64;
+    while zigzag >= 0x80 {
+        out.push((zigzag as u8) | 0x80);
+        zigzag >>= 7;
+    }
+    out.push(zigzag as u8);
+}
+
+fn avro_string(out: &mut Vec<u8>, value: &str) {
+    avro_long(out, value.len() as i64);
+    out.extend_from_slice(value.as_bytes());
+}
+
+#[cfg(feature = "kafka")]
+struct LoggingContext;
+
+#[cfg(feature = "kafka")]
+impl rdkafka::ClientContext for LoggingContext {}
+
+#[cfg(feature = "kafka")]
+impl rdkafka::producer::ProducerContext for LoggingContext {
+    type DeliveryOpaque = ();
+
+    fn delivery(&self, result: &rdkafka::producer::DeliveryResult<'_>, _: ()) {
+        if let Err((err, _)) = result {
+            warn!(kind = "kafka", "delivery failed: {err}");
+        }
+    }
+}
+
+pub struct KafkaSink {
+    #[cfg(feature = "kafka")]
+    producer: rdkafka::producer::ThreadedProducer<LoggingContext>,
+    topic: String,
+    format: KafkaFormat,
+    schema_id: Option<u32>,
+    lookups: bool,
+}
+
+impl KafkaSink {
+    #[cfg(feature = "kafka")]
+    pub fn connect(config: &KafkaConfig) -> Result<Self> {
+        Self::check(config)?;
+        let mut client = rdkafka::ClientConfig::new();
+        client.set("bootstrap.servers", config.brokers.join(","));
+        for (key, value) in &config.properties {
+            client.set(key, value);
+        }
+        Ok(Self {
+            producer: client.create_with_context(LoggingContext)?,
+            topic: config.topic.clone(),
+            format: config.format,
+            schema_id: config.schema_id,
+            lookups: config.lookups,
+        })
+    }
+
+    #[cfg(not(feature = "kafka"))]
+    pub fn connect(config: &KafkaConfig) -> Result<Self> {
+        Self::check(config)?;
+        anyhow::bail!("[kafka] requires a build with the `kafka` feature")
+    }
+
+    fn check(config: &KafkaConfig) -> Result<()> {
+        if config.topic.is_empty() {
+            anyhow::bail!("[kafka] needs a topic");
+        }
+        if config.schema_id.is_some() && config.format != KafkaFormat::Avro {
+            anyhow::bail!("[kafka] schema_id only applies to format = \"avro\"");
+        }
+        Ok(())
+    }
+
+    /// Queues `event` for delivery.
+    pub fn publish(&self, event: &KafkaEvent) -> Result<()> {
+        let payload = event.encode(self.format, self.schema_id)?;
+        self.send(&event.domain, &payload)
+    }
+
+    #[cfg(feature = "kafka")]
+    fn send(&self, key: &str, payload: &[u8]) -> Result<()> {
+        use rdkafka::producer::BaseRecord;
+        self.producer
+            .send(BaseRecord::to(&self.topic).key(key).payload(payload))
+            .map_err(|(err, _)| anyhow::anyhow!("cannot queue record for {}: {err}", self.topic))
+    }
+
+    #[cfg(not(feature = "kafka"))]
+    fn send(&self, _key: &str, _payload: &[u8]) -> Result<()> {
+        anyhow::bail!("cannot publish to {}: built without Kafka", self.topic)
+    }
+
+    /// Waits for queued records to be delivered.
+    pub fn flush(&self) -> Result<()> {
+        #[cfg(feature = "kafka")]
+        rdkafka::producer::Producer::flush(&self.producer, FLUSH_TIMEOUT)?;
+        Ok(())
+    }
+}
+
+/// Makes `sink` the process-wide Kafka sink. Only the first call takes
+/// effect.
+pub fn install(sink: KafkaSink) {
+    let _ = INSTALLED.set(sink);
+}
+
+pub fn installed() -> Option<&'static KafkaSink> {
+    INSTALLED.get()
+}
+
+/// Publishes a lookalike `source` found, if the sink is configured.
+pub fn publish_detection(source: &str, domain: &str, lookup: &LookupResponse) {
+    publish(EventKind::Detection, source, domain, lookup);
+}
+
+/// Publishes a lookup, if the sink is configured with `lookups = true`.
+pub fn publish_lookup(source: &str, lookup: &LookupResponse) {
+    if installed().is_some_and(|sink| sink.lookups) {
+        let domain = match lookup {
+            LookupResponse::Ascii(data) => &data.q,
+            LookupResponse::Idn(data) => &data.q,
+        };
+        publish(EventKind::Lookup, source, domain, lookup);
+    }
+}
+
+/// Publishes every finding of a scan as a detection.
+pub fn publish_findings(report: &ScanReport) {
+    for finding in &report.findings {
+        publish_detection("scan", &finding.domain, &finding.lookup);
+    }
+}
+
+fn publish(kind: EventKind, source: &str, domain: &str, lookup: &LookupResponse) {
+    let Some(sink) = installed() else {
+        return;
+    };
+    if let Err(err) = sink.publish(&KafkaEvent::new(kind, source, domain, lookup)) {
+        warn!(kind = "kafka", domain = %domain, "could not publish: {err:#}");
+    }
+}
+
+/// Delivers what is still queued, before the process exits.
+pub fn flush() {
+    if let Some(sink) = installed() {
+        if let Err(err) = sink.flush() {
+            warn!(kind = "kafka", "could not flush queued records: {err:#}");
+        }
+    }
+}
+
+fn now_ms() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as i64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{empty_ascii_response, AsciiResponse, AsciiResult};
+
+    fn event() -> KafkaEvent {
+        let lookup = LookupResponse::Ascii(AsciiResponse {
+            results: vec![AsciiResult {
+                domain: "paypal.com".to_string(),
+                similarity: 90,
+                breakdown: None,
+            }],
+            ..empty_ascii_response("paypa1.com")
+        });
+        KafkaEvent {
+            timestamp_ms: 1_700_000_000_000,
+            ..KafkaEvent::new(EventKind::Detection, "tail", "paypa1.com", &lookup)
+        }
+    }
+
+    #[test]
+    fn avro_schema_matches_the_json_events() {
+        let event = event();
+        assert_eq!(event.target.as_deref(), Some("paypal.com"));
+        assert_eq!(event.severity, Some(Severity::High));
+
+        let schema: serde_json::Value = serde_json::from_str(AVRO_SCHEMA).unwrap();
+        let fields: Vec<&str> = schema["fields"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|field| field["name"].as_str().unwrap())
+            .collect();
+        let json = serde_json::to_value(&event).unwrap();
+        let keys: Vec<&str> = json
+            .as_object()
+            .unwrap()
+            .keys()
+            .map(String::as_str)
+            .collect();
+        let mut sorted = fields.clone();
+        sorted.sort_unstable();
+        assert_eq!(sorted, keys);
+        assert_eq!(
+            schema["fields"][6]["type"][1]["symbols"],
+            serde_json::json!(["low", "medium", "high", "critical"])
+        );
+    }
+
+    #[test]
+    fn avro_records_use_zigzag_varints_and_the_confluent_header() {
+        let mut out = Vec::new();
+        for value in [0, -1, 1, 64, -65] {
+            avro_long(&mut out, value);
+        }
+        assert_eq!(out, [0x00, 0x01, 0x02, 0x80, 0x01, 0x81, 0x01]);
+
+        let record = event().encode(KafkaFormat::Avro, Some(7)).unwrap();
+        assert_eq!(record[..5], [0, 0, 0, 0, 7]);
+        // kind `detection`, then the timestamp.
+        assert_eq!(record[5], 0x00);
+        let plain = event().encode(KafkaFormat::Avro, None).unwrap();
+        assert_eq!(plain, record[5..]);
+        let source_at = 1 + 6; // kind, then a 6-byte timestamp varint
+        assert_eq!(plain[source_at], 8); // "tail", zigzag length 4
+        assert_eq!(&plain[source_at + 1..source_at + 5], b"tail");
+    }
+}
@@ -0,0 +1,478 @@
+/*
+API keys for serve. Keys are named, scoped (`read` for lookups, `admin` for
+lookups and the /admin endpoints), and may expire; only their SHA-256 hashes are
+stored. Revoked keys are kept so `keys list` still shows them. Serve checks
+every request against this file, so keys can be created and revoked while it
+runs.
+
+Every authenticated request is counted per key and UTC day. A key can carry a
+daily and a monthly quota; once either is used up, requests are refused until
+it resets at the next UTC midnight or first of the month.
+
+These keys (`Authorization: Bearer`) govern access and quotas. The tenant keys
+of `tenants` (`X-API-Key`) only pick whose brands and allowlist a lookup is
+scored with; both are generated and hashed here.
+*/
+use anyhow::Result;
+use rand::RngCore;
+use rusqlite::{Connection, OptionalExtension, TransactionBehavior};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use utoipa::ToSchema;
+
+use crate::cipher;
+
+const KEY_BYTES: usize = 24;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Scope {
+    /// Lookups, stats, and metrics
+    Read,
+    /// Everything `read` allows, plus the /admin endpoints
+    Admin,
+}
+
+impl Scope {
+    fn as_str(self) -> &'static str {
+        match self {
+            Scope::Read => "read",
+            Scope::Admin => "admin",
+        }
+    }
+
+    fn parse(value: &str) -> Option<Self> {
+        match value {
+            "read" => Some(Scope::Read),
+            "admin" => Some(Scope::Admin),
+            _ => None,
+        }
+    }
+}
+
+/// Requests a key may make per UTC day and calendar month; `None` is
+/// unlimited.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Quotas {
+    pub daily: Option<u64>,
+    pub monthly: Option<u64>,
+}
+
+/// A valid key, as found by `authenticate`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ApiKey {
+    id: i64,
+    pub name: String,
+    pub scope: Scope,
+    pub quotas: Quotas,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Period {
+    Daily,
+    Monthly,
+}
+
+impl Period {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Period::Daily => "daily",
+            Period::Monthly => "monthly",
+        }
+    }
+}
+
+/// Where a key stands against one of its quotas after a request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QuotaState {
+    pub period: Period,
+    pub limit: u64,
+    pub used: u64,
+    /// Unix seconds when the period ends.
+    pub reset_at: i64,
+}
+
+impl QuotaState {
+    pub fn remaining(&self) -> u64 {
+        self.limit.saturating_sub(self.used)
+    }
+}
+
+/// The outcome of counting a request against a key's quotas.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Consumed {
+    /// False when a quota was already used up; the request was not counted.
+    pub allowed: bool,
+    pub quotas: Vec<QuotaState>,
+}
+
+impl Consumed {
+    /// The quotas that are used up.
+    pub fn exhausted(&self) -> impl Iterator<Item = &QuotaState> {
+        self.quotas.iter().filter(|quota| quota.remaining() == 0)
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct KeySummary {
+    pub name: String,
+    pub scope: Scope,
+    /// RFC 3339, UTC.
+    pub created_at: String,
+    pub expires_at: Option<String>,
+    pub revoked_at: Option<String>,
+    pub daily_quota: Option<u64>,
+    pub monthly_quota: Option<u64>,
+}
+
+/// A key's requests so far today and this month (UTC).
+#[derive(Debug, Clone, Serialize, ToSchema, PartialEq, Eq)]
+pub struct KeyUsage {
+    pub name: String,
+    pub scope: Scope,
+    pub revoked: bool,
+    pub daily_quota: Option<u64>,
+    pub monthly_quota: Option<u64>,
+    pub today: u64,
+    pub this_month: u64,
+}
+
+pub fn open(path: &str) -> Result<Connection> {
+    let conn = cipher::open(path)?;
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS api_keys (
+			id INTEGER PRIMARY KEY,
+			name TEXT NOT NULL UNIQUE,
+			key_sha256 TEXT NOT NULL UNIQUE,
+			scope TEXT NOT NULL,
+			created_at INTEGER NOT NULL,
+			expires_at INTEGER,
+			revoked_at INTEGER,
+			daily_quota INTEGER,
+			monthly_quota INTEGER
+		);
+		CREATE TABLE IF NOT EXISTS api_key_usage (
+			key_id INTEGER NOT NULL REFERENCES api_keys(id),
+			day TEXT NOT NULL,
+			requests INTEGER NOT NULL,
+			PRIMARY KEY (key_id, day)
+		);",
+    )?;
+    // Key stores created before quotas existed.
+    for column in ["daily_quota", "monthly_quota"] {
+        let present: bool = conn.query_row(
+            "SELECT count(*) > 0 FROM pragma_table_info('api_keys') WHERE name = ?1",
+            [column],
+            |row| row.get(0),
+        )?;
+        if !present {
+            conn.execute_batch(&format!("ALTER TABLE api_keys ADD COLUMN {column} INTEGER"))?;
+        }
+    }
+    Ok(conn)
+}
+
+/// Creates a key and returns it; only its hash is stored, so it cannot be
+/// shown again.
+pub fn create(
+    conn: &Connection,
+    name: &str,
+    scope: Scope,
+    expires_in: Option<Duration>,
+    quotas: Quotas,
+) -> Result<String> {
+    let key = generate_key();
+    let created_at = now();
+    let expires_at = expires_in.map(|ttl| created_at + ttl.as_secs() as i64);
+    conn.execute(
+        "INSERT INTO api_keys (name, key_sha256, scope, created_at, expires_at, daily_quota,
+			monthly_quota)
+		VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        (
+            name,
+            key_sha256(&key),
+            scope.as_str(),
+            created_at,
+            expires_at,
+            quotas.daily,
+            quotas.monthly,
+        ),
+    )
+    .map_err(|err| match err {
+        rusqlite::Error::SqliteFailure(code, _)
+            if code.code == rusqlite::ErrorCode::ConstraintViolation =>
+        {
+            anyhow::anyhow!("An API key named {name} already exists")
+        }
+        err => err.into(),
+    })?;
+    Ok(key)
+}
+
+pub fn list(conn: &Connection) -> Result<Vec<KeySummary>> {
+    let mut stmt = conn.prepare(
+        "SELECT name, scope,
+			strftime('%Y-%m-%dT%H:%M:%SZ', created_at, 'unixepoch'),
+			strftime('%Y-%m-%dT%H:%M:%SZ', expires_at, 'unixepoch'),
+			strftime('%Y-%m-%dT%H:%M:%SZ', revoked_at, 'unixepoch'),
+			daily_quota, monthly_quota
+		FROM api_keys ORDER BY name",
+    )?;
+    let rows = stmt.query_map([], |row| {
+        Ok(KeySummary {
+            name: row.get(0)?,
+            scope: Scope::parse(&row.get::<_, String>(1)?).unwrap_or(Scope::Read),
+            created_at: row.get(2)?,
+            expires_at: row.get(3)?,
+            revoked_at: row.get(4)?,
+            daily_quota: row.get(5)?,
+            monthly_quota: row.get(6)?,
+        })
+    })?;
+
+    let mut out = Vec::new();
+    for row in rows {
+        out.push(row?);
+    }
+    Ok(out)
+}
+
+/// Revokes the named key; an error when there is no such key.
+pub fn revoke(conn: &Connection, name: &str) -> Result<()> {
+    let updated = conn.execute(
+        "UPDATE api_keys SET revoked_at = coalesce(revoked_at, ?2) WHERE name = ?1",
+        (name, now()),
+    )?;
+    if updated == 0 {
+        anyhow::bail!("Unknown API key: {name}");
+    }
+    Ok(())
+}
+
+/// Replaces the named key's quotas; an error when there is no such key.
+pub fn set_quotas(conn: &Connection, name: &str, quotas: Quotas) -> Result<()> {
+    let updated = conn.execute(
+        "UPDATE api_keys SET daily_quota = ?2, monthly_quota = ?3 WHERE name = ?1",
+        (name, quotas.daily, quotas.monthly),
+    )?;
+    if updated == 0 {
+        anyhow::bail!("Unknown API key: {name}");
+    }
+    Ok(())
+}
+
+/// `key` when it exists, is not revoked, and has not expired.
+pub fn authenticate(conn: &Connection, key: &str) -> Result<Option<ApiKey>> {
+    let found = conn
+        .query_row(
+            "SELECT id, name, scope, daily_quota, monthly_quota FROM api_keys
+				WHERE key_sha256 = ?1 AND revoked_at IS NULL
+				AND (expires_at IS NULL OR expires_at > ?2)",
+            (key_sha256(key), now()),
+            |row| {
+                let key = ApiKey {
+                    id: row.get(0)?,
+                    name: row.get(1)?,
+                    scope: Scope::Read,
+                    quotas: Quotas {
+                        daily: row.get(3)?,
+                        monthly: row.get(4)?,
+                    },
+                };
+                Ok((row.get::<_, String>(2)?, key))
+            },
+        )
+        .optional()?;
+    Ok(found.and_then(|(scope, key)| {
+        Some(ApiKey {
+            scope: Scope::parse(&scope)?,
+            ..key
+        })
+    }))
+}
+
+/// Counts a request by `key` unless one of its quotas is used up.
+pub fn consume(conn: &mut Connection, key: &ApiKey) -> Result<Consumed> {
+    consume_at(conn, key, now())
+}
+
+fn consume_at(conn: &mut Connection, key: &ApiKey, at: i64) -> Result<Consumed> {
+    // Immediate, so concurrent requests cannot both take the last one.
+    let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+    let (today, this_month, day_reset, month_reset): (u64, u64, i64, i64) = tx.query_row(
+        "SELECT
+			coalesce((SELECT requests FROM api_key_usage
+				WHERE key_id = ?1 AND day = date(?2, 'unixepoch')), 0),
+			coalesce((SELECT sum(requests) FROM api_key_usage
+				WHERE key_id = ?1 AND substr(day, 1, 7) = strftime('%Y-%m', ?2, 'unixepoch')), 0),
+			CAST(strftime('%s', date(?2, 'unixepoch', '+1 day')) AS INTEGER),
+			CAST(strftime('%s', date(?2, 'unixepoch', 'start of month', '+1 month')) AS INTEGER)",
+        (key.id, at),
+        |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)),
+    )?;
+    let mut quotas: Vec<QuotaState> = [
+        (Period::Daily, key.quotas.daily, today, day_reset),
+        (Period::Monthly, key.quotas.monthly, this_month, month_reset),
+    ]
+    .into_iter()
+    .filter_map(|(period, limit, used, reset_at)| {
+        Some(QuotaState {
+            period,
+            limit: limit?,
+            used,
+            reset_at,
+        })
+    })
+    .collect();
+    let allowed = quotas.iter().all(|quota| quota.used < quota.limit);
+    if allowed {
+        tx.execute(
+            "INSERT INTO api_key_usage (key_id, day, requests) VALUES (?1, date(?2, 'unixepoch'), 1)
+			ON CONFLICT (key_id, day) DO UPDATE SET requests = requests + 1",
+            (key.id, at),
+        )?;
+        for quota in &mut quotas {
+            quota.used += 1;
+        }
+    }
+    tx.commit()?;
+    Ok(Consumed { allowed, quotas })
+}
+
+/// Every key's requests today and this month, with its quotas.
+pub fn usage(conn: &Connection) -> Result<Vec<KeyUsage>> {
+    usage_at(conn, now())
+}
+
+fn usage_at(conn: &Connection, at: i64) -> Result<Vec<KeyUsage>> {
+    let mut stmt = conn.prepare(
+        "SELECT name, scope, revoked_at IS NOT NULL, daily_quota, monthly_quota,
+			coalesce((SELECT requests FROM api_key_usage
+				WHERE key_id = api_keys.id AND day = date(?1, 'unixepoch')), 0),
+			coalesce((SELECT sum(requests) FROM api_key_usage
+				WHERE key_id = api_keys.id AND substr(day, 1, 7) = strftime('%Y-%m', ?1, 'unixepoch')), 0)
+		FROM api_keys ORDER BY name",
+    )?;
+    let rows = stmt.query_map([at], |row| {
+        Ok(KeyUsage {
+            name: row.get(0)?,
+            scope: Scope::parse(&row.get::<_, String>(1)?).unwrap_or(Scope::Read),
+            revoked: row.get(2)?,
+            daily_quota: row.get(3)?,
+            monthly_quota: row.get(4)?,
+            today: row.get(5)?,
+            this_month: row.get(6)?,
+        })
+    })?;
+
+    let mut out = Vec::new();
+    for row in rows {
+        out.push(row?);
+    }
+    Ok(out)
+}
+
+/// A new random key, as hex. Tenant keys (see `tenants`) are made the same way.
+pub(crate) fn generate_key() -> String {
+    let mut bytes = [0u8; KEY_BYTES];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+/// How a key is stored and looked up: the hex SHA-256, never the key itself.
+pub(crate) fn key_sha256(key: &str) -> String {
+    format!("{:x}", Sha256::digest(key.as_bytes()))
+}
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs() as i64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys_authenticate_until_revoked_or_expired() {
+        let conn = open(":memory:").unwrap();
+        let reader = create(&conn, "dashboard", Scope::Read, None, Quotas::default()).unwrap();
+        let admin = create(
+            &conn,
+            "ops",
+            Scope::Admin,
+            Some(Duration::from_secs(3600)),
+            Quotas::default(),
+        )
+        .unwrap();
+        assert!(create(&conn, "ops", Scope::Read, None, Quotas::default()).is_err());
+
+        assert_eq!(
+            authenticate(&conn, &reader).unwrap().map(|key| key.scope),
+            Some(Scope::Read)
+        );
+        assert_eq!(
+            authenticate(&conn, &admin).unwrap().map(|key| key.scope),
+            Some(Scope::Admin)
+        );
+        assert_eq!(authenticate(&conn, "not-a-key").unwrap(), None);
+
+        revoke(&conn, "dashboard").unwrap();
+        assert_eq!(authenticate(&conn, &reader).unwrap(), None);
+        assert!(revoke(&conn, "missing").is_err());
+
+        conn.execute("UPDATE api_keys SET expires_at = 0 WHERE name = 'ops'", [])
+            .unwrap();
+        assert_eq!(authenticate(&conn, &admin).unwrap(), None);
+
+        let listed = list(&conn).unwrap();
+        assert_eq!(listed.len(), 2);
+        assert_eq!(listed[0].name, "dashboard");
+        assert!(listed[0].revoked_at.is_some() && listed[0].expires_at.is_none());
+        assert_eq!(listed[1].scope, Scope::Admin);
+        assert_eq!(
+            listed[1].expires_at.as_deref(),
+            Some("1970-01-01T00:00:00Z")
+        );
+    }
+
+    #[test]
+    fn quotas_refuse_requests_until_the_period_resets() {
+        let mut conn = open(":memory:").unwrap();
+        let quotas = Quotas {
+            daily: Some(2),
+            monthly: Some(3),
+        };
+        let token = create(&conn, "team-a", Scope::Read, None, quotas).unwrap();
+        let key = authenticate(&conn, &token).unwrap().unwrap();
+        assert_eq!(key.quotas, quotas);
+
+        // Noon UTC on 2024-03-30, 2024-03-31, and 2024-04-01.
+        let (day_one, day_two, next_month) = (1_711_800_000, 1_711_886_400, 1_711_972_800);
+        let first = consume_at(&mut conn, &key, day_one).unwrap();
+        assert!(first.allowed);
+        assert_eq!(first.quotas[0].period, Period::Daily);
+        assert_eq!(first.quotas[0].remaining(), 1);
+        assert_eq!(first.quotas[0].reset_at, 1_711_843_200);
+        assert_eq!(first.quotas[1].reset_at, 1_711_929_600);
+        assert!(consume_at(&mut conn, &key, day_one).unwrap().allowed);
+        let refused = consume_at(&mut conn, &key, day_one).unwrap();
+        assert!(!refused.allowed);
+        assert_eq!(refused.quotas[0].remaining(), 0);
+
+        assert!(consume_at(&mut conn, &key, day_two).unwrap().allowed);
+        let refused = consume_at(&mut conn, &key, day_two).unwrap();
+        assert!(!refused.allowed);
+        assert_eq!(
+            (refused.quotas[0].remaining(), refused.quotas[1].remaining()),
+            (1, 0)
+        );
+
+        assert!(consume_at(&mut conn, &key, next_month).unwrap().allowed);
+        let usage = usage_at(&conn, next_month).unwrap();
+        assert_eq!((usage[0].today, usage[0].this_month), (1, 1));
+        assert_eq!(usage_at(&conn, day_two).unwrap()[0].this_month, 3);
+    }
+}
@@ -0,0 +1,122 @@
+/*
+The SpotSpoof detection engine and the services built on it, shared by the
+`spotspoof` binary and the embedding interfaces. Everything that needs SQLite,
+the network or a runtime sits behind the default `native` feature; without it
+only the string-analysis core remains, which also builds for WebAssembly.
+*/
+#[cfg(feature = "native")]
+pub mod alerts;
+pub mod ascii_spoof;
+#[cfg(feature = "native")]
+pub mod bench;
+pub mod brand;
+#[cfg(feature = "native")]
+pub mod browser;
+#[cfg(feature = "native")]
+pub mod cache;
+pub mod candidates;
+#[cfg(feature = "native")]
+pub mod cipher;
+#[cfg(feature = "native")]
+pub mod cohosted;
+#[cfg(feature = "native")]
+pub mod compare;
+#[cfg(feature = "native")]
+pub mod config;
+#[cfg(feature = "native")]
+pub mod db;
+#[cfg(feature = "native")]
+pub mod delta;
+#[cfg(feature = "native")]
+pub mod detections;
+#[cfg(feature = "native")]
+pub mod dmarc;
+#[cfg(feature = "native")]
+pub mod dns_cache;
+#[cfg(feature = "native")]
+pub mod dnslog;
+#[cfg(feature = "native")]
+pub mod dnswire;
+#[cfg(feature = "native")]
+pub mod doh;
+#[cfg(feature = "native")]
+pub mod download;
+#[cfg(feature = "native")]
+pub mod eml;
+pub mod ensemble;
+#[cfg(feature = "native")]
+pub mod export;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+#[cfg(feature = "native")]
+pub mod geoip;
+#[cfg(feature = "native")]
+pub mod har;
+#[cfg(feature = "native")]
+pub mod history;
+pub mod homoglyphs;
+#[cfg(feature = "native")]
+pub mod html;
+#[cfg(feature = "native")]
+pub mod http;
+#[cfg(feature = "native")]
+pub mod idn;
+#[cfg(feature = "native")]
+pub mod index;
+#[cfg(feature = "native")]
+pub mod kafka;
+#[cfg(feature = "native")]
+pub mod keys;
+#[cfg(feature = "native")]
+pub mod mappings;
+#[cfg(feature = "native")]
+pub mod mcp;
+#[cfg(feature = "native")]
+pub mod milter;
+#[cfg(feature = "native")]
+pub mod monitor;
+#[cfg(feature = "native")]
+pub mod net;
+#[cfg(feature = "native")]
+pub mod pack;
+#[cfg(feature = "native")]
+pub mod passive_dns;
+#[cfg(feature = "native")]
+pub mod paths;
+#[cfg(feature = "pcap")]
+pub mod pcap;
+#[cfg(feature = "native")]
+pub mod permute;
+pub mod phonetic;
+#[cfg(feature = "native")]
+pub mod puny;
+#[cfg(feature = "native")]
+pub mod redis_cache;
+#[cfg(feature = "native")]
+pub mod release;
+#[cfg(feature = "native")]
+pub mod repl;
+#[cfg(feature = "native")]
+pub mod scan;
+#[cfg(feature = "native")]
+pub mod schema;
+#[cfg(feature = "native")]
+pub mod screenshot;
+#[cfg(feature = "native")]
+pub mod self_update;
+#[cfg(feature = "native")]
+pub mod skeleton;
+#[cfg(feature = "native")]
+pub mod systemd;
+#[cfg(feature = "native")]
+pub mod tail;
+#[cfg(feature = "native")]
+pub mod tenants;
+pub mod tld_risk;
+pub mod types;
+#[cfg(feature = "native")]
+pub mod visual;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+#[cfg(feature = "native")]
+pub mod whois;
@@ -1,13 +1,18 @@
-mod ascii_spoof;
-mod db;
-mod http;
-mod idn;
-mod types;
-mod whois;
+#[cfg(feature = "pcap")]
+use spotspoof_cli::pcap;
+use spotspoof_cli::{
+    alerts, ascii_spoof, bench, browser, cache, cipher, cohosted, compare, config, db, delta,
+    detections, dmarc, dns_cache, dnslog, doh, download, eml, ensemble, export, geoip, har,
+    history, homoglyphs, html, http, idn, index, kafka, keys, mappings, mcp, milter, monitor, net,
+    pack, passive_dns, paths, permute, puny, redis_cache, release, repl, scan, schema, screenshot,
+    self_update, skeleton, tail, tenants, tld_risk, types, whois,
+};
 
 use anyhow::Result;
 use clap::{Args, Parser, Subcommand, ValueEnum};
-use tracing::{error, info};
+use std::collections::BTreeMap;
+use std::io::{IsTerminal, Write};
+use tracing::{error, info, warn};
 use tracing_subscriber::fmt::writer::BoxMakeWriter;
 use tracing_subscriber::prelude::*;
 
@@ -18,7 +23,7 @@ use crate::types::{AsciiResponse, IdnResponse};
     name = "spotspoof",
     version,
     about = "SpotSpoof CLI",
-    after_help = "Server:\n  spotspoof serve --host 127.0.0.1 --port 8080 --db spotspoof.sqlite [--no-db]\n  Routes: GET /, GET /healthz, POST /lookup, POST /ascii, POST /idn, GET /docs\n\nDB:\n  --no-db (lookup/ascii/serve) skips DB usage and returns empty ASCII results\n\nOutput:\n  (default) JSON\n  -t, --text\n  --csv\n  -o, --outfile <path>\n\nLogging:\n  --log-format <plain|json>\n  --log-destination <stdout|stderr|file>\n  --log-file <path> (required when --log-destination=file)"
+    after_help = "Server:\n  spotspoof serve --host 127.0.0.1 --port 8080 --db spotspoof.sqlite [--no-db]\n  Routes: GET /, GET /healthz, GET /version, GET /metrics, POST /lookup, POST /ascii, POST /idn, POST /idn/stream, GET /ws, POST /admin/reload, GET /docs\n\nDB:\n  --no-db (lookup/ascii/serve) skips DB usage and returns empty ASCII results\n\nOutput:\n  (default) JSON\n  -t, --text\n  --csv\n  -o, --outfile <path>\n\nLogging:\n  --log-format <plain|json>\n  --log-destination <stdout|stderr|file>\n  --log-file <path> (required when --log-destination=file)"
 )]
 struct Cli {
     #[command(subcommand)]
@@ -39,6 +44,142 @@ struct Cli {
     log_destination: LogDestination,
     #[arg(long, help = "Log file path (required when --log-destination=file)")]
     log_file: Option<String>,
+    #[arg(
+        long,
+        env = "SPOTSPOOF_CONFIG",
+        help = "Config file (default: spotspoof/config.toml in the user config directory)"
+    )]
+    config: Option<String>,
+    #[arg(
+        long,
+        env = "SPOTSPOOF_DATA_DIR",
+        help = "Directory of the default DB, history, detections, alerts, DNS cache and mappings files (default: spotspoof in the user data directory)"
+    )]
+    data_dir: Option<String>,
+    #[arg(
+        long,
+        env = "SPOTSPOOF_DATASET",
+        help = "Named dataset from the config's [datasets] to use as the DB (an explicit --db wins)"
+    )]
+    dataset: Option<String>,
+    #[arg(
+        long,
+        env = "SPOTSPOOF_OFFLINE",
+        help = "Make no network calls: skip IDN registration checks and DB downloads"
+    )]
+    offline: bool,
+    #[arg(
+        long,
+        env = "SPOTSPOOF_RETRIES",
+        default_value_t = net::DEFAULT_RETRIES,
+        help = "Retries for failed DoH and download requests (exponential backoff with jitter)"
+    )]
+    retries: u32,
+    #[arg(
+        long,
+        env = "SPOTSPOOF_RETRY_BASE_MS",
+        default_value_t = net::DEFAULT_RETRY_BASE_MS,
+        help = "Initial retry delay in milliseconds; doubles on each retry"
+    )]
+    retry_base_ms: u64,
+    #[arg(
+        long,
+        env = "SPOTSPOOF_DOH_RATE",
+        default_value_t = net::DEFAULT_DOH_RATE,
+        help = "Maximum DoH registration queries per second across all lookups (0 = unlimited)"
+    )]
+    doh_rate: u32,
+    #[arg(
+        long,
+        env = "SPOTSPOOF_DOH_BURST",
+        help = "DoH queries allowed at once before --doh-rate pacing applies (default: the rate)"
+    )]
+    doh_burst: Option<u32>,
+    #[arg(
+        long,
+        env = "SPOTSPOOF_DB_SHA256",
+        value_name = "HEX",
+        help = "Expected SHA-256 of the compressed DB download (default: the published <url>.sha256, else the bundled checksum)"
+    )]
+    sha256: Option<String>,
+    #[arg(
+        long,
+        env = "SPOTSPOOF_DB_SHA256_URL",
+        value_name = "URL",
+        conflicts_with = "sha256",
+        help = "Fetch the DB download's expected SHA-256 from this URL"
+    )]
+    sha256_url: Option<String>,
+    #[arg(
+        long,
+        env = "SPOTSPOOF_DB_PUBKEY",
+        value_name = "KEY",
+        help = "Minisign public key (base64, or a .pub file) the DB download must be signed with (default: the bundled release key, if any)"
+    )]
+    pubkey: Option<String>,
+    #[arg(
+        long,
+        env = "SPOTSPOOF_ALLOW_UNSIGNED_DB",
+        help = "Accept a DB download on its checksum alone when no public key is configured"
+    )]
+    allow_unsigned_db: bool,
+    #[arg(
+        long,
+        env = "SPOTSPOOF_HISTORY",
+        help = "Record every lookup (CLI, server, MCP) in the lookup history"
+    )]
+    history: bool,
+    #[arg(
+        long,
+        env = "SPOTSPOOF_HISTORY_DB",
+        help = "Lookup history file (default: spotspoof-history.sqlite next to the default DB)"
+    )]
+    history_db: Option<String>,
+    #[arg(
+        long,
+        env = "SPOTSPOOF_HISTORY_RETENTION",
+        value_parser = parse_duration,
+        default_value = "90d",
+        help = "Drop history entries older than this"
+    )]
+    history_retention: std::time::Duration,
+    #[arg(
+        long,
+        env = "SPOTSPOOF_MAPPINGS",
+        help = "Confusable mappings file from `mappings update` (default: spotspoof-mappings.json next to the default DB, when present)"
+    )]
+    mappings: Option<String>,
+    #[arg(
+        long,
+        env = "SPOTSPOOF_HOMOGLYPH_WEIGHTS",
+        help = "JSON matrix of confusable substitution weights (0-1) for the confusable scorer and IDN variant order"
+    )]
+    homoglyph_weights: Option<String>,
+    #[arg(
+        long,
+        env = "SPOTSPOOF_DETECTIONS",
+        help = "Record scan, tail, and server findings in the detections store; scans and tail skip triaged ones"
+    )]
+    detections: bool,
+    #[arg(
+        long,
+        env = "SPOTSPOOF_DETECTIONS_DB",
+        help = "Detections store (default: spotspoof-detections.sqlite next to the default DB)"
+    )]
+    detections_db: Option<String>,
+    #[arg(
+        long,
+        env = "SPOTSPOOF_ALERTS_DB",
+        help = "Alert outbox (default: spotspoof-alerts.sqlite next to the default DB)"
+    )]
+    alerts_db: Option<String>,
+    #[arg(
+        long,
+        env = "SPOTSPOOF_DB_KEY",
+        hide_env_values = true,
+        help = "Encrypt the DB and every other SQLite file (history, detections, tenants, keys, ...) with SQLCipher using this key (requires the `sqlcipher` feature)"
+    )]
+    db_key: Option<String>,
 }
 
 #[derive(Subcommand)]
@@ -50,6 +191,43 @@ enum Commands {
         db: Option<String>,
         #[arg(long, help = "Do not use or download the SQLite DB")]
         no_db: bool,
+        #[arg(
+            long = "strategy",
+            value_enum,
+            value_delimiter = ',',
+            help = "Candidate selection strategies for the DB scan (default: first-char)"
+        )]
+        strategies: Vec<db::CandidateStrategy>,
+        #[arg(
+            long,
+            value_enum,
+            default_value_t = LookupMode::Auto,
+            help = "Which analyzer to run"
+        )]
+        mode: LookupMode,
+        #[arg(
+            long,
+            help = "Also score with leet substitutions undone (0→o, 1→l, 3→e, 4→a)"
+        )]
+        leet: bool,
+        #[arg(
+            long,
+            help = "Blend in phonetic similarity (Soundex/Metaphone) to catch sound-alikes"
+        )]
+        phonetic: bool,
+        #[arg(
+            long,
+            help = "Show each ASCII result's per-scorer similarity and weight"
+        )]
+        explain: bool,
+        #[arg(
+            long,
+            default_value_t = 0,
+            help = "Skip DB candidates sharing no prefix or suffix this long with the query, before scoring (0 = off)"
+        )]
+        min_affix: usize,
+        #[command(flatten)]
+        dns_cache: DnsCacheArgs,
         #[command(flatten)]
         output: OutputArgs,
     },
@@ -60,15 +238,447 @@ enum Commands {
         db: Option<String>,
         #[arg(long, help = "Do not use or download the SQLite DB")]
         no_db: bool,
+        #[arg(
+            long = "strategy",
+            value_enum,
+            value_delimiter = ',',
+            help = "Candidate selection strategies for the DB scan (default: first-char)"
+        )]
+        strategies: Vec<db::CandidateStrategy>,
+        #[arg(
+            long,
+            help = "Also score with leet substitutions undone (0→o, 1→l, 3→e, 4→a)"
+        )]
+        leet: bool,
+        #[arg(
+            long,
+            help = "Blend in phonetic similarity (Soundex/Metaphone) to catch sound-alikes"
+        )]
+        phonetic: bool,
+        #[arg(
+            long,
+            help = "Show each ASCII result's per-scorer similarity and weight"
+        )]
+        explain: bool,
+        #[arg(
+            long,
+            default_value_t = 0,
+            help = "Skip DB candidates sharing no prefix or suffix this long with the query, before scoring (0 = off)"
+        )]
+        min_affix: usize,
         #[command(flatten)]
         output: OutputArgs,
     },
     /// IDN lookup
     Idn {
         domain: String,
+        #[arg(
+            long,
+            help = "Maximum variants to enumerate [env: PUNY_MAX_NORMALIZED, default 2000]"
+        )]
+        max_variants: Option<usize>,
+        #[arg(
+            long,
+            help = "Maximum variants checked for registration [env: WHOIS_MAX_CHECKS, default 200]"
+        )]
+        max_checks: Option<usize>,
+        #[arg(
+            long,
+            help = "Maximum registered variants to return [env: PUNY_MAX_RESULTS, default 50]"
+        )]
+        max_results: Option<usize>,
+        #[arg(
+            long,
+            value_name = "MS",
+            help = "Per-check DNS timeout in milliseconds [env: WHOIS_TIMEOUT_MS, default 2500]"
+        )]
+        whois_timeout: Option<u64>,
+        #[arg(
+            long,
+            help = "Map ß, ς, ZWJ and ZWNJ as IDNA 2003 did instead of keeping them"
+        )]
+        idna_transitional: bool,
+        #[arg(
+            long,
+            help = "Reject ASCII characters hostnames may not contain (_, *, ...)"
+        )]
+        idna_std3_rules: bool,
+        #[arg(
+            long,
+            value_enum,
+            default_value_t,
+            help = "What to do with labels that are not valid punycode"
+        )]
+        punycode_overflow: types::PunycodeOverflow,
+        #[command(flatten)]
+        dns_cache: DnsCacheArgs,
+        #[command(flatten)]
+        output: OutputArgs,
+    },
+    /// Compare two specific domains: edits, confusables, skeletons, registration, verdict
+    Compare {
+        #[arg(help = "The legitimate domain")]
+        first: String,
+        #[arg(help = "The suspect domain")]
+        second: String,
+        #[arg(long, help = "Skip the DNS registration checks of both domains")]
+        no_check: bool,
+        #[arg(
+            long,
+            help = "Also render both names with a bundled font and score how alike they look (slower)"
+        )]
+        visual: bool,
+        #[arg(
+            long,
+            value_name = "MS",
+            help = "Per-check DNS timeout in milliseconds [env: WHOIS_TIMEOUT_MS, default 2500]"
+        )]
+        whois_timeout: Option<u64>,
+        #[command(flatten)]
+        dns_cache: DnsCacheArgs,
+        #[command(flatten)]
+        output: OutputArgs,
+    },
+    /// Print the confusable skeletons and per-character scripts of a domain
+    Skeleton {
+        #[arg(help = "Domain or URL")]
+        domain: String,
+        #[command(flatten)]
+        output: OutputArgs,
+    },
+    /// Convert a domain between its Unicode and punycode (xn--) forms
+    Puny {
+        #[command(subcommand)]
+        action: PunyAction,
+    },
+    /// Maintain the confusable mappings IDN lookups expand queries with
+    Mappings {
+        #[command(subcommand)]
+        action: MappingsAction,
+    },
+    /// Extract domains from a text or log file and report the lookalikes among them
+    ScanFile {
+        #[arg(help = "File to scan (- for stdin)")]
+        path: String,
+        #[command(flatten)]
+        scan: ScanArgs,
+        #[command(flatten)]
+        output: OutputArgs,
+    },
+    /// Fetch a web page and report the lookalikes among the hosts it links to
+    ScanUrl {
+        #[arg(help = "Page to fetch (http:// or https://)")]
+        url: String,
+        #[arg(
+            long,
+            default_value = html::DEFAULT_USER_AGENT,
+            help = "User-Agent to fetch with (phishing kits often hide from non-browser agents)"
+        )]
+        user_agent: String,
+        #[command(flatten)]
+        scan: ScanArgs,
+        #[command(flatten)]
+        output: OutputArgs,
+    },
+    /// Report the lookalikes among the hosts a saved HTML page links to
+    ScanHtml {
+        #[arg(help = "HTML file to scan (- for stdin)")]
+        path: String,
+        #[arg(
+            long,
+            help = "URL the page was served from, to resolve relative links against (its host is scanned too)"
+        )]
+        base_url: Option<url::Url>,
+        #[command(flatten)]
+        scan: ScanArgs,
+        #[command(flatten)]
+        output: OutputArgs,
+    },
+    /// Score every host a browser session contacted, from a HAR capture
+    ScanHar {
+        #[arg(help = "HAR file exported from the browser's network panel (- for stdin)")]
+        path: String,
+        #[command(flatten)]
+        scan: ScanArgs,
+        #[command(flatten)]
+        output: OutputArgs,
+    },
+    /// Score the hosts in a Chrome or Firefox profile's history and bookmarks
+    ScanBrowser {
+        #[arg(
+            long,
+            required = true,
+            help = "Profile directory, or its History, Bookmarks, or places.sqlite file (repeatable)"
+        )]
+        profile: Vec<String>,
+        #[arg(
+            long,
+            value_parser = parse_duration,
+            help = "Only count history visited within this long (e.g. 7d); skips bookmarks"
+        )]
+        since: Option<std::time::Duration>,
+        #[command(flatten)]
+        scan: ScanArgs,
+        #[command(flatten)]
+        output: OutputArgs,
+    },
+    /// Triage an email: score its sender domains and link hosts, with a verdict
+    ScanEml {
+        #[arg(help = "Message file (.eml, - for stdin)")]
+        path: String,
+        #[command(flatten)]
+        scan: ScanArgs,
+        #[command(flatten)]
+        output: OutputArgs,
+    },
+    /// Find lookalike domains sending as your brand in DMARC aggregate reports
+    ScanDmarc {
+        #[arg(
+            required = true,
+            help = "Aggregate reports (.xml, .xml.gz, or .zip; - for stdin)"
+        )]
+        paths: Vec<String>,
+        #[command(flatten)]
+        scan: ScanArgs,
+        #[command(flatten)]
+        output: OutputArgs,
+    },
+    /// Score the names queried in a Zeek dns.log or Suricata eve.json
+    ScanZeek {
+        #[arg(help = "Log file to scan (- for stdin)")]
+        path: String,
+        #[arg(long, value_enum, default_value = "zeek")]
+        format: dnslog::LogFormat,
+        #[command(flatten)]
+        scan: ScanArgs,
+        #[command(flatten)]
+        output: OutputArgs,
+    },
+    /// Follow a growing log file and alert on lookalike domains in new lines
+    Tail {
+        #[arg(long, help = "File to follow; rotation and truncation are handled")]
+        file: String,
+        #[arg(
+            long,
+            help = "Pattern locating domains in a line: its `domain` group, else its first group, else the whole match (default: every domain-like token)"
+        )]
+        regex: Option<regex::Regex>,
+        #[arg(
+            long,
+            help = "Read the existing content first instead of only new lines"
+        )]
+        from_start: bool,
+        #[arg(long, value_name = "MS", default_value_t = tail::DEFAULT_POLL_MS, help = "How often to check the file for new lines")]
+        interval: u64,
+        #[arg(long, default_value_t = tail::DEFAULT_DEDUP_SIZE, help = "Checked domains remembered so each is looked up once")]
+        dedup_size: usize,
+        #[arg(short = 't', long, help = "Plain text alerts instead of JSON lines")]
+        text: bool,
+        #[command(flatten)]
+        scan: ScanArgs,
+    },
+    /// Score the DNS names queried in a packet capture (requires the `pcap` feature)
+    ScanPcap {
+        #[arg(help = "pcap or pcapng capture to scan (- for stdin)")]
+        path: String,
+        #[command(flatten)]
+        scan: ScanArgs,
+        #[command(flatten)]
+        output: OutputArgs,
+    },
+    /// Find and score the other domains hosted on a spoof domain's addresses
+    Cohosted {
+        #[arg(help = "Confirmed spoof domain")]
+        domain: String,
+        #[arg(
+            long,
+            value_enum,
+            default_value_t = cohosted::Source::Auto,
+            help = "Reverse-IP source (auto: passive DNS when [passive_dns] is configured, else HackerTarget)"
+        )]
+        source: cohosted::Source,
+        #[command(flatten)]
+        scan: ScanArgs,
         #[command(flatten)]
         output: OutputArgs,
     },
+    /// Save a PNG screenshot of a live spoof page as takedown evidence (requires the `screenshot` feature)
+    Screenshot {
+        #[arg(help = "Domain (its HTTPS, then HTTP root is loaded) or URL")]
+        target: String,
+        #[arg(long, help = "PNG file to write (default: <domain>.png)")]
+        out: Option<String>,
+    },
+    /// Filter mail inline as a Sendmail/Postfix milter
+    Milter {
+        #[arg(
+            long,
+            default_value = "inet:8891@localhost",
+            help = "Socket to listen on: inet:PORT[@HOST] or unix:PATH"
+        )]
+        listen: String,
+        #[arg(
+            long,
+            default_value_t = 0,
+            value_parser = clap::value_parser!(u8).range(0..=100),
+            help = "Only add the X-SpotSpoof header from this score (0 marks every message)"
+        )]
+        header_at: u8,
+        #[arg(
+            long,
+            value_parser = clap::value_parser!(u8).range(1..=100),
+            help = "Reject suspicious messages from this score (550 5.7.1)"
+        )]
+        reject_at: Option<u8>,
+        #[command(flatten)]
+        scan: ScanArgs,
+    },
+    /// Write a DNS Response Policy Zone blocking likely squats of your domains
+    ExportRpz {
+        #[arg(
+            long,
+            help = "Protected domains: a JSON array (of domains or {\"domain\"} objects) or one per line"
+        )]
+        targets: String,
+        #[arg(
+            long,
+            help = "Zone file to write; an existing one has its SOA serial bumped"
+        )]
+        out: String,
+        #[arg(
+            long = "technique",
+            value_enum,
+            value_delimiter = ',',
+            help = "Permutation techniques to use (default: all)"
+        )]
+        techniques: Vec<permute::Technique>,
+        #[arg(
+            long,
+            value_enum,
+            default_value = "nxdomain",
+            help = "Policy for listed names"
+        )]
+        action: export::RpzAction,
+        #[arg(long, default_value_t = 300, help = "TTL of the zone's records")]
+        ttl: u32,
+        #[arg(long)]
+        db: Option<String>,
+        #[arg(
+            long,
+            help = "Do not use or download the SQLite DB (legitimate domains are then not excluded)"
+        )]
+        no_db: bool,
+    },
+    /// Write a hosts, Pi-hole, or AdGuard blocklist of likely squats or scan findings
+    #[command(group(clap::ArgGroup::new("source").required(true).args(["targets", "findings"])))]
+    ExportBlocklist {
+        #[arg(
+            long,
+            help = "Protected domains to permute: a JSON array (of domains or {\"domain\"} objects) or one per line"
+        )]
+        targets: Option<String>,
+        #[arg(long, help = "List the findings of a scan-* JSON report instead")]
+        findings: Option<String>,
+        #[arg(long, value_enum, default_value = "hosts")]
+        format: export::BlocklistFormat,
+        #[arg(long, help = "File to write (default: stdout)")]
+        out: Option<String>,
+        #[arg(
+            long = "technique",
+            value_enum,
+            value_delimiter = ',',
+            help = "Permutation techniques to use (default: all)"
+        )]
+        techniques: Vec<permute::Technique>,
+        #[arg(
+            long,
+            help = "Only list domains that are registered (checked over DNS-over-HTTPS)"
+        )]
+        only_registered: bool,
+        #[arg(
+            long,
+            value_name = "MS",
+            default_value_t = 2500,
+            help = "Per-check DNS timeout in milliseconds"
+        )]
+        whois_timeout: u64,
+        #[arg(
+            long,
+            default_value_t = 8,
+            value_parser = clap::value_parser!(u32).range(1..=64),
+            help = "Registration checks run in parallel"
+        )]
+        concurrency: u32,
+        #[arg(long)]
+        db: Option<String>,
+        #[arg(
+            long,
+            help = "Do not use or download the SQLite DB (legitimate domains are then not excluded)"
+        )]
+        no_db: bool,
+        #[command(flatten)]
+        dns_cache: DnsCacheArgs,
+    },
+    /// Forward DNS-over-HTTPS queries upstream, flagging lookalikes of watched domains
+    DohProxy {
+        #[arg(long, default_value = "127.0.0.1")]
+        host: String,
+        #[arg(long, default_value_t = 8053)]
+        port: u16,
+        #[arg(
+            long,
+            default_value = "https://cloudflare-dns.com/dns-query",
+            help = "Upstream DoH resolver (RFC 8484 POST)"
+        )]
+        upstream: String,
+        #[arg(
+            long,
+            value_enum,
+            default_value = "log",
+            help = "What to do with queries for lookalikes"
+        )]
+        action: doh::DohAction,
+        #[arg(
+            long,
+            default_value = "0.0.0.0",
+            help = "Sinkhole address for --action redirect (IPv4 answers A queries, IPv6 AAAA)"
+        )]
+        redirect_to: std::net::IpAddr,
+        #[arg(
+            long,
+            value_name = "MS",
+            default_value_t = 5000,
+            help = "Upstream request timeout in milliseconds"
+        )]
+        upstream_timeout: u64,
+        #[command(flatten)]
+        scan: ScanArgs,
+    },
+    /// Serve lookups as Model Context Protocol tools over stdio
+    Mcp {
+        #[arg(long)]
+        db: Option<String>,
+        #[arg(long, help = "Do not use or download the SQLite DB")]
+        no_db: bool,
+        #[command(flatten)]
+        dns_cache: DnsCacheArgs,
+    },
+    /// Interactive prompt: type domains, tune settings with :set, review :history
+    Repl {
+        #[arg(long)]
+        db: Option<String>,
+        #[arg(long, help = "Do not use or download the SQLite DB")]
+        no_db: bool,
+        #[arg(
+            long = "strategy",
+            value_enum,
+            value_delimiter = ',',
+            help = "Candidate selection strategies for the DB scan (default: first-char)"
+        )]
+        strategies: Vec<db::CandidateStrategy>,
+        #[command(flatten)]
+        dns_cache: DnsCacheArgs,
+    },
     /// Run an HTTP server for lookups
     Serve {
         #[arg(long, default_value = "127.0.0.1")]
@@ -79,7 +689,492 @@ enum Commands {
         db: Option<String>,
         #[arg(long, help = "Do not use or download the SQLite DB")]
         no_db: bool,
+        #[arg(
+            long,
+            env = "SPOTSPOOF_ADMIN_TOKEN",
+            hide_env_values = true,
+            help = "Bearer token required by /admin endpoints (disabled when unset)"
+        )]
+        admin_token: Option<String>,
+        #[arg(long, help = "Reload the DB automatically when the file is replaced")]
+        watch_db: bool,
+        #[arg(
+            long,
+            value_parser = parse_duration,
+            help = "Check for a newer release DB on this interval and hot-swap it (e.g. 24h, 30m)"
+        )]
+        auto_update: Option<std::time::Duration>,
+        #[arg(
+            long,
+            default_value_t = cache::DEFAULT_CACHE_SIZE,
+            help = "Maximum cached lookup responses (0 disables the in-memory cache)"
+        )]
+        cache_size: usize,
+        #[arg(
+            long,
+            value_parser = parse_duration,
+            default_value = "5m",
+            help = "How long cached lookup responses stay valid"
+        )]
+        cache_ttl: std::time::Duration,
+        #[arg(
+            long,
+            help = "Also serve the gRPC API on this port (requires the `grpc` feature)"
+        )]
+        grpc_port: Option<u16>,
+        #[arg(
+            long,
+            help = "Tenant store; requests with X-API-Key are also scored against that tenant's brands"
+        )]
+        tenant_db: Option<String>,
+        #[arg(
+            long,
+            help = "API key store (see `keys`); requests then need Authorization: Bearer <key>"
+        )]
+        keys_db: Option<String>,
+        #[arg(
+            long,
+            default_value_t = db::DEFAULT_POOL_SIZE,
+            value_parser = clap::value_parser!(u32).range(1..),
+            help = "Maximum pooled SQLite connections"
+        )]
+        db_pool_size: u32,
+        #[arg(
+            long,
+            conflicts_with = "no_db",
+            help = "Load the domain table into RAM at startup for disk-free ASCII lookups"
+        )]
+        in_memory: bool,
+        #[arg(
+            long = "strategy",
+            value_enum,
+            value_delimiter = ',',
+            help = "Candidate selection strategies for the DB scan (default: first-char)"
+        )]
+        strategies: Vec<db::CandidateStrategy>,
+        #[arg(
+            long,
+            default_value_t = 0,
+            help = "Skip DB candidates sharing no prefix or suffix this long with the query, before scoring (0 = off)"
+        )]
+        min_affix: usize,
+        #[arg(
+            long,
+            env = "SPOTSPOOF_REDIS_URL",
+            help = "Share cached lookup responses and DNS checks with other instances through this Redis (redis://host:port)"
+        )]
+        redis_url: Option<String>,
+        #[arg(
+            long,
+            conflicts_with = "auto_update",
+            help = "Refuse mutating endpoints (admin reload, detection triage) and never download or replace the DB"
+        )]
+        read_only: bool,
+        #[command(flatten)]
+        dns_cache: DnsCacheArgs,
+    },
+    /// Print JSON Schema documents for the JSON output
+    #[command(group(clap::ArgGroup::new("which").required(true).args(["kind", "out_dir"])))]
+    Schema {
+        #[arg(value_enum, help = "Document to print")]
+        kind: Option<schema::SchemaKind>,
+        #[arg(
+            long,
+            conflicts_with = "kind",
+            help = "Write every document to <DIR>/<Name>.schema.json"
+        )]
+        out_dir: Option<String>,
+    },
+    /// Measure ASCII lookup throughput and latency for a query file
+    Bench {
+        #[arg(long)]
+        db: Option<String>,
+        #[arg(long, help = "File with one domain per line (# starts a comment)")]
+        queries: String,
+        #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..), help = "Times to replay the query file")]
+        iterations: u32,
+        #[arg(
+            long,
+            value_enum,
+            default_value = "auto",
+            help = "Candidate source to benchmark"
+        )]
+        index: bench::IndexChoice,
+        #[arg(long, value_enum, default_value = "levenshtein")]
+        algorithm: types::Algorithm,
+        #[arg(
+            long = "strategy",
+            value_enum,
+            value_delimiter = ',',
+            help = "Candidate selection strategies for the DB scan (default: first-char)"
+        )]
+        strategies: Vec<db::CandidateStrategy>,
+        #[arg(
+            long,
+            help = "How far candidate lengths may differ from the query's (default 2)"
+        )]
+        length_band: Option<usize>,
+        #[arg(long, help = "Most candidates fetched per lookup (default 5000)")]
+        max_candidates: Option<usize>,
+        #[arg(
+            long,
+            default_value_t = 0,
+            help = "Skip candidates sharing no prefix or suffix this long with the query, before scoring (0 = off)"
+        )]
+        min_affix: usize,
+    },
+    /// Download a newer release DB, if there is one
+    Update {
+        #[arg(long)]
+        db: Option<String>,
+        #[arg(long, default_value = db::DEFAULT_DB_URL, help = "Release DB artifact URL")]
+        url: String,
+        #[arg(
+            long,
+            help = "Apply published delta files instead of downloading the whole DB (falls back to a full download)"
+        )]
+        delta: bool,
+        #[arg(
+            long,
+            requires = "delta",
+            help = "Delta manifest URL (default: spotspoof.deltas.json next to --url)"
+        )]
+        manifest_url: Option<String>,
+        #[arg(
+            long,
+            help = "GitHub releases API URL of the latest release (default: this project's, when --url is the default)"
+        )]
+        releases_url: Option<String>,
+        #[arg(long, help = "Only report whether a newer DB is available")]
+        check: bool,
+    },
+    /// Replace this binary with the latest release, if it is newer
+    SelfUpdate {
+        #[arg(long, default_value = release::DEFAULT_RELEASES_URL, help = "GitHub releases API URL of the latest release")]
+        releases_url: String,
+        #[arg(long, help = "Only report whether a newer release is available")]
+        check: bool,
+    },
+    /// Maintain the SQLite DB and its lookup indexes
+    Db {
+        #[command(subcommand)]
+        action: DbAction,
+    },
+    /// List, search, or export the lookup history (see --history)
+    History {
+        #[command(subcommand)]
+        action: HistoryAction,
+    },
+    /// List and triage recorded detections (see --detections)
+    Detections {
+        #[command(subcommand)]
+        action: DetectionAction,
+    },
+    /// Inspect and retry the alert outbox (see [alerts] in the config file)
+    Alerts {
+        #[command(subcommand)]
+        action: AlertsAction,
+    },
+    /// Dry-run monitoring sweeps of protected domains
+    Monitor {
+        #[command(subcommand)]
+        action: MonitorAction,
+    },
+    /// Manage tenants (API keys, brand lists, allowlists) for multi-tenant serve
+    Tenant {
+        #[arg(long, default_value = "spotspoof-tenants.sqlite")]
+        tenant_db: String,
+        #[command(subcommand)]
+        action: TenantAction,
+    },
+    /// Create, list, or revoke the API keys serve --keys-db requires
+    Keys {
+        #[arg(long, default_value = "spotspoof-keys.sqlite")]
+        keys_db: String,
+        #[command(subcommand)]
+        action: KeyAction,
+    },
+}
+
+#[derive(Subcommand)]
+enum PunyAction {
+    /// Unicode to punycode, with full IDNA processing
+    Encode {
+        domain: String,
+        #[command(flatten)]
+        output: OutputArgs,
+    },
+    /// Punycode to Unicode
+    Decode {
+        domain: String,
+        #[command(flatten)]
+        output: OutputArgs,
+    },
+}
+
+#[derive(Subcommand)]
+enum MappingsAction {
+    /// Merge Unicode's confusables.txt into the curated mappings and write them
+    /// to the mappings file
+    Update {
+        #[arg(long, default_value = mappings::CONFUSABLES_URL, help = "confusables.txt URL")]
+        url: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum DbAction {
+    /// Build a fuzzy index used for edit-distance candidate search
+    Index {
+        #[arg(long)]
+        db: Option<String>,
+        #[arg(long, value_enum, default_value = "fst")]
+        kind: IndexKind,
+    },
+    /// VACUUM, stamp metadata into, and zstd-compress a DB for publishing
+    Pack {
+        #[arg(long)]
+        db: Option<String>,
+        #[arg(long, default_value = "spotspoof.sqlite.zst")]
+        out: String,
+        #[arg(
+            long,
+            help = "Dataset version to record (default: the DB's current one)"
+        )]
+        version: Option<String>,
+        #[arg(
+            long,
+            help = "Where the domains came from, e.g. a list name or URL (default: the DB's current one)"
+        )]
+        source: Option<String>,
+        #[arg(long, default_value_t = pack::DEFAULT_LEVEL, value_parser = clap::value_parser!(i32).range(1..=22), help = "zstd compression level")]
+        level: i32,
+    },
+    /// Write the delta (added/removed domains) between two DB releases
+    Delta {
+        #[arg(long, help = "The previous release DB")]
+        old: String,
+        #[arg(long, help = "The new release DB")]
+        new: String,
+        #[arg(long, help = "Where to write the zstd-compressed delta file")]
+        out: String,
+        #[arg(long, help = "Version of --old (default: its meta table)")]
+        from_version: Option<String>,
+        #[arg(long, help = "Version of --new (default: its meta table)")]
+        to_version: Option<String>,
+        #[arg(
+            long,
+            help = "Add the delta to this manifest (created if missing) and mark its version as latest"
+        )]
+        manifest: Option<String>,
+    },
+    /// Encrypt plaintext SQLite files in place with --db-key
+    Encrypt {
+        #[arg(
+            help = "Files to encrypt: the DB or the history, detections, tenant, key, alert, and DNS cache stores (default: the DB)"
+        )]
+        paths: Vec<String>,
+    },
+}
+
+#[derive(Copy, Clone, ValueEnum)]
+enum IndexKind {
+    /// FST with Levenshtein automata, written to <db>.fst
+    Fst,
+    /// SymSpell deletes table, written into the DB
+    Symspell,
+    /// Domain trigram table, written into the DB
+    Trigram,
+}
+
+#[derive(Subcommand)]
+enum HistoryAction {
+    /// Show the most recent lookups
+    List {
+        #[arg(long, default_value_t = 20)]
+        limit: usize,
+    },
+    /// Find lookups whose query or top result contains a string
+    Search {
+        pattern: String,
+        #[arg(long, default_value_t = 20)]
+        limit: usize,
+    },
+    /// Write the history as JSON or CSV, oldest first
+    Export {
+        #[arg(long, value_enum, default_value = "json")]
+        format: HistoryFormat,
+        #[arg(long, value_parser = parse_duration, help = "Only lookups from this long ago (e.g. 7d)")]
+        since: Option<std::time::Duration>,
+        #[arg(long, help = "Write to this file instead of stdout")]
+        outfile: Option<String>,
+    },
+}
+
+#[derive(Subcommand)]
+enum AlertsAction {
+    /// Show the newest outbox entries
+    List {
+        #[arg(long, value_enum, help = "Only entries in this delivery state")]
+        state: Option<alerts::DeliveryState>,
+        #[arg(long, default_value_t = 20)]
+        limit: usize,
+    },
+    /// Deliver every queued alert now instead of waiting for its backoff
+    Retry {
+        #[arg(long, help = "Also retry dead-lettered alerts")]
+        dead: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum MonitorAction {
+    /// Print what a sweep would do (permutations, registration checks, pacing, notifiers) without touching the network
+    Plan {
+        #[arg(
+            long,
+            help = "Protected domains: a JSON array (of domains or {\"domain\"} objects) or one per line"
+        )]
+        targets: String,
+        #[arg(
+            long = "technique",
+            value_enum,
+            value_delimiter = ',',
+            help = "Permutation techniques to use (default: all)"
+        )]
+        techniques: Vec<permute::Technique>,
+        #[arg(long)]
+        db: Option<String>,
+        #[arg(
+            long,
+            help = "Do not use the SQLite DB (legitimate domains are then not excluded)"
+        )]
+        no_db: bool,
+        #[arg(long, help = "Print a summary instead of JSON")]
+        text: bool,
+        #[command(flatten)]
+        dns_cache: DnsCacheArgs,
+    },
+}
+
+#[derive(Subcommand)]
+enum DetectionAction {
+    /// Show the most recently seen detections
+    List {
+        #[arg(long, value_enum, help = "Only detections in this triage state")]
+        state: Option<detections::TriageState>,
+        #[arg(long, default_value_t = 20)]
+        limit: usize,
+        #[arg(
+            long,
+            value_enum,
+            help = "Aggregate the listed detections by impersonated brand instead"
+        )]
+        group_by: Option<scan::GroupBy>,
+    },
+    /// Mark a detection acknowledged
+    Ack {
+        id: i64,
+        #[arg(long)]
+        note: Option<String>,
+    },
+    /// Mark a detection escalated
+    Escalate {
+        id: i64,
+        #[arg(long)]
+        note: Option<String>,
+    },
+    /// Mark a detection a false positive
+    FalsePositive {
+        id: i64,
+        #[arg(long)]
+        note: Option<String>,
+    },
+    /// Move a detection back to new, so it alerts again
+    Reopen {
+        id: i64,
+        #[arg(long)]
+        note: Option<String>,
+    },
+}
+
+#[derive(Copy, Clone, ValueEnum)]
+enum HistoryFormat {
+    Json,
+    Csv,
+}
+
+#[derive(Subcommand)]
+enum TenantAction {
+    /// Create a tenant and print its API key
+    Create { name: String },
+    /// Add protected brand domains to a tenant
+    AddBrand {
+        name: String,
+        #[arg(required = true)]
+        domains: Vec<String>,
+    },
+    /// Add domains that are never reported for a tenant
+    Allow {
+        name: String,
+        #[arg(required = true)]
+        domains: Vec<String>,
+    },
+    /// List tenants with brand and allowlist counts
+    List,
+}
+
+#[derive(Subcommand)]
+enum KeyAction {
+    /// Create a named key and print it (it cannot be shown again)
+    Create {
+        name: String,
+        #[arg(long, value_enum, default_value_t = keys::Scope::Read)]
+        scope: keys::Scope,
+        #[arg(long, value_parser = parse_duration, help = "Expire the key after this long (e.g. 90d)")]
+        expires_in: Option<std::time::Duration>,
+        #[command(flatten)]
+        quotas: QuotaArgs,
+    },
+    /// List keys with their scope, quotas, expiry, and revocation time
+    List,
+    /// Revoke a key by name
+    Revoke { name: String },
+    /// Replace a key's quotas (an omitted quota becomes unlimited)
+    SetQuota {
+        name: String,
+        #[command(flatten)]
+        quotas: QuotaArgs,
     },
+    /// Requests per key today and this month (UTC)
+    Usage,
+}
+
+#[derive(Args)]
+struct QuotaArgs {
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..), help = "Requests allowed per UTC day")]
+    daily_quota: Option<u64>,
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..), help = "Requests allowed per calendar month")]
+    monthly_quota: Option<u64>,
+}
+
+impl From<QuotaArgs> for keys::Quotas {
+    fn from(args: QuotaArgs) -> Self {
+        Self {
+            daily: args.daily_quota,
+            monthly: args.monthly_quota,
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum)]
+enum LookupMode {
+    /// IDN when any label is punycode or non-ASCII, else ASCII
+    Auto,
+    Ascii,
+    Idn,
+    /// Run both analyzers
+    Both,
 }
 
 #[derive(Copy, Clone, ValueEnum)]
@@ -110,120 +1205,1539 @@ struct OutputArgs {
     outfile: Option<String>,
 }
 
-#[derive(Copy, Clone)]
-enum OutputFormat {
-    Json,
-    Text,
-    Csv,
+/// Lookup settings shared by the `scan-*` commands.
+#[derive(Args, Clone)]
+struct ScanArgs {
+    #[arg(long)]
+    db: Option<String>,
+    #[arg(long, help = "Do not use or download the SQLite DB")]
+    no_db: bool,
+    #[arg(
+        long = "strategy",
+        value_enum,
+        value_delimiter = ',',
+        help = "Candidate selection strategies for the DB scan (default: first-char)"
+    )]
+    strategies: Vec<db::CandidateStrategy>,
+    #[arg(
+        long,
+        help = "Skip IDN domains instead of checking their variants over DNS"
+    )]
+    ascii_only: bool,
+    #[arg(
+        long,
+        value_delimiter = ',',
+        help = "Only score against these protected domains or brand names instead of the whole dataset"
+    )]
+    watch: Vec<String>,
+    #[arg(
+        long,
+        help = "File with watched domains or brand names, one per line (# starts a comment)"
+    )]
+    watch_file: Option<String>,
+    #[arg(
+        long,
+        help = "Also score with leet substitutions undone (0→o, 1→l, 3→e, 4→a)"
+    )]
+    leet: bool,
+    #[arg(
+        long,
+        help = "Blend in phonetic similarity (Soundex/Metaphone) to catch sound-alikes"
+    )]
+    phonetic: bool,
+    #[arg(
+        long,
+        default_value_t = 0,
+        help = "Skip DB candidates sharing no prefix or suffix this long with the domain, before scoring (0 = off)"
+    )]
+    min_affix: usize,
+    #[arg(
+        long,
+        help = "Resolve each finding's A records and add their country and ASN from the [geoip] MaxMind DBs"
+    )]
+    resolve: bool,
+    #[arg(
+        long,
+        value_enum,
+        help = "Aggregate the findings by impersonated brand, with counts, max risk, and first sighting"
+    )]
+    group_by: Option<scan::GroupBy>,
+    #[command(flatten)]
+    dns_cache: DnsCacheArgs,
+}
+
+#[derive(Args, Clone)]
+struct DnsCacheArgs {
+    #[arg(
+        long,
+        help = "Registration-check cache file (default: spotspoof-dns-cache.sqlite next to the default DB)"
+    )]
+    dns_cache: Option<String>,
+    #[arg(
+        long,
+        value_parser = parse_duration,
+        default_value = "24h",
+        help = "How long cached registration checks stay valid"
+    )]
+    dns_cache_ttl: std::time::Duration,
+    #[arg(
+        long,
+        conflicts_with = "dns_cache",
+        help = "Always query DNS; do not read or write the cache"
+    )]
+    no_dns_cache: bool,
+}
+
+#[derive(Copy, Clone)]
+enum OutputFormat {
+    Json,
+    Text,
+    Csv,
+}
+
+/// Exit status for inputs rejected before any lookup runs.
+const EXIT_INVALID_INPUT: i32 = 2;
+
+// Built once per command and printed, so the variants' sizes do not matter.
+#[allow(clippy::large_enum_variant)]
+#[derive(Clone)]
+enum OutputData {
+    Ascii(AsciiResponse),
+    Idn(IdnResponse),
+    Both {
+        ascii: AsciiResponse,
+        idn: IdnResponse,
+    },
+    Scan(scan::ScanReport),
+    Eml(eml::EmlReport),
+    Dmarc(dmarc::DmarcReport),
+    Compare(compare::Comparison),
+    Skeleton(skeleton::SkeletonReport),
+    Puny(puny::PunyReport),
+    Cohosted(cohosted::CohostedReport),
+}
+
+fn main() -> Result<()> {
+    let cli = Cli::parse();
+    // MCP speaks JSON-RPC on stdout, the REPL draws its prompt there, and
+    // tail streams alerts to it, so logs must never share it.
+    let log_destination = match (&cli.command, cli.log_destination) {
+        (
+            Commands::Mcp { .. } | Commands::Repl { .. } | Commands::Tail { .. },
+            LogDestination::Stdout,
+        ) => LogDestination::Stderr,
+        (_, destination) => destination,
+    };
+    init_logging(cli.log_format, log_destination, cli.log_file.as_deref())?;
+    net::install(net::RetryPolicy {
+        retries: cli.retries,
+        base_delay: std::time::Duration::from_millis(cli.retry_base_ms),
+        ..Default::default()
+    });
+    net::install_doh_limit(cli.doh_rate, cli.doh_burst.unwrap_or(cli.doh_rate));
+
+    let result = run(cli);
+    kafka::flush();
+    match result {
+        Ok(()) => Ok(()),
+        Err(err) => {
+            error!("{err}");
+            if err.is::<crate::types::InvalidDomain>() {
+                eprintln!("Error: {err}");
+                std::process::exit(EXIT_INVALID_INPUT);
+            }
+            Err(err)
+        }
+    }
+}
+
+fn run(cli: Cli) -> Result<()> {
+    let config = config::load(cli.config.as_deref())?;
+    if let Some(dir) = &cli.data_dir {
+        paths::install_data_dir(dir);
+    }
+    if let Some(key) = &cli.db_key {
+        cipher::install(key.clone())?;
+    }
+    if let Some(name) = &cli.dataset {
+        let path = config.dataset_path(name)?;
+        // Never auto-download the global DB over a named dataset's path.
+        if !std::path::Path::new(&path).is_file() {
+            anyhow::bail!("dataset {name}: {path} does not exist");
+        }
+        info!(kind = "dataset", name = %name, db_path = %path);
+        let _ = DATASET_DB.set(path);
+    }
+    net::install_proxy(&config.network)?;
+    tld_risk::install(tld_risk::TldRisk::with_overrides(config.tld_risk.clone()));
+    ensemble::install(config.scoring);
+    if let Some(path) = &cli.homoglyph_weights {
+        let text = std::fs::read_to_string(path)
+            .map_err(|err| anyhow::anyhow!("cannot read {path}: {err}"))?;
+        let weights = homoglyphs::HomoglyphWeights::parse(&text)
+            .map_err(|err| anyhow::anyhow!("invalid homoglyph weights {path}: {err}"))?;
+        info!(kind = "homoglyphs", path = %path);
+        homoglyphs::install(weights);
+    }
+    let offline = cli.offline || config.network.offline;
+    net::install_offline(offline);
+    if offline {
+        info!(kind = "offline", "network calls are disabled");
+    }
+    download::install_checksum(download::ChecksumSource::new(cli.sha256, cli.sha256_url)?);
+    download::install_pubkey(cli.pubkey.as_deref())?;
+    download::install_allow_unsigned(cli.allow_unsigned_db);
+    let mappings_path = cli
+        .mappings
+        .clone()
+        .unwrap_or_else(|| paths::data_path(mappings::DEFAULT_MAPPINGS_FILE));
+    // `mappings update` may be creating the file.
+    if !matches!(cli.command, Commands::Mappings { .. }) {
+        install_mappings(&mappings_path, cli.mappings.is_some())?;
+    }
+    let history_db = cli
+        .history_db
+        .unwrap_or_else(|| paths::data_path(history::DEFAULT_HISTORY_FILE));
+    if cli.history {
+        info!(kind = "history", path = %history_db, retention_secs = cli.history_retention.as_secs());
+        history::install(history::LookupHistory::open(
+            &history_db,
+            Some(cli.history_retention),
+        )?);
+    }
+    let detections_db = cli
+        .detections_db
+        .unwrap_or_else(|| paths::data_path(detections::DEFAULT_DETECTIONS_FILE));
+    if cli.detections {
+        info!(kind = "detections", path = %detections_db);
+        detections::install(detections::DetectionStore::open(&detections_db)?);
+    }
+    let alerts_db = cli
+        .alerts_db
+        .unwrap_or_else(|| paths::data_path(alerts::DEFAULT_ALERTS_FILE));
+    let max_attempts = config
+        .alerts
+        .max_attempts
+        .unwrap_or(alerts::DEFAULT_MAX_ATTEMPTS);
+    // A monitor plan must not open the outbox or connect to Kafka.
+    let plan_only = matches!(cli.command, Commands::Monitor { .. });
+    if !config.alerts.channels().is_empty()
+        && !matches!(cli.command, Commands::Alerts { .. })
+        && !plan_only
+    {
+        info!(kind = "alerts", path = %alerts_db, channels = config.alerts.channels().len());
+        let mut queue = alerts::Alerts::new(
+            alerts::Outbox::open(&alerts_db, max_attempts)?,
+            &config.alerts,
+        )?;
+        if config.alerts.screenshots {
+            queue = queue.with_screenshots(&config.screenshot)?;
+        }
+        alerts::install(queue);
+    }
+    if config.kafka.enabled() && !plan_only {
+        info!(kind = "kafka", brokers = %config.kafka.brokers.join(","), topic = %config.kafka.topic);
+        kafka::install(kafka::KafkaSink::connect(&config.kafka)?);
+    }
+    if config.passive_dns.enabled() {
+        info!(kind = "passive_dns", provider = ?config.passive_dns.provider);
+        passive_dns::install(passive_dns::PassiveDnsClient::new(&config.passive_dns)?);
+    }
+    geoip::configure(&config.geoip);
+
+    match cli.command {
+        Commands::Lookup {
+            domain,
+            db,
+            no_db,
+            strategies,
+            mode,
+            leet,
+            phonetic,
+            explain,
+            min_affix,
+            dns_cache,
+            output: output_args,
+        } => {
+            let db = resolve_db_path(db);
+            let domain = crate::types::parse_domain_input(&domain)?;
+            let mode = match mode {
+                LookupMode::Auto if crate::types::is_idn(&domain) => LookupMode::Idn,
+                LookupMode::Auto => LookupMode::Ascii,
+                mode => mode,
+            };
+            let is_idn = mode == LookupMode::Idn;
+            info!(kind = "lookup", domain = %domain, is_idn, db_path = %db);
+            let ascii = || -> Result<AsciiResponse> {
+                if no_db {
+                    return Ok(crate::types::empty_ascii_response(&domain));
+                }
+                db::ensure_db(&db, db::DEFAULT_DB_URL)?;
+                let options = ascii_spoof::AsciiOptions {
+                    strategies: candidate_strategies(strategies.clone()),
+                    leet,
+                    phonetic,
+                    explain,
+                    min_affix,
+                    ..Default::default()
+                };
+                let mut response = ascii_spoof::lookup_ascii_with_options(&domain, &db, &options)?;
+                response.dataset = release::dataset_info(&db)?;
+                Ok(response)
+            };
+            let idn = || -> Result<IdnResponse> {
+                install_dns_cache(&dns_cache)?;
+                idn::lookup_idn(&domain)
+            };
+            let results = match mode {
+                LookupMode::Idn => OutputData::Idn(idn()?),
+                LookupMode::Both => OutputData::Both {
+                    ascii: ascii()?,
+                    idn: idn()?,
+                },
+                _ => OutputData::Ascii(ascii()?),
+            };
+            output(
+                output_format(&output_args),
+                results,
+                output_args.outfile.clone(),
+            )?;
+        }
+        Commands::Ascii {
+            domain,
+            db,
+            no_db,
+            strategies,
+            leet,
+            phonetic,
+            explain,
+            min_affix,
+            output: output_args,
+        } => {
+            let db = resolve_db_path(db);
+            let domain = crate::types::parse_domain_input(&domain)?;
+            info!(kind = "ascii", domain = %domain, db_path = %db);
+            let results = if no_db {
+                crate::types::empty_ascii_response(&domain)
+            } else {
+                db::ensure_db(&db, db::DEFAULT_DB_URL)?;
+                let options = ascii_spoof::AsciiOptions {
+                    strategies: candidate_strategies(strategies),
+                    leet,
+                    phonetic,
+                    explain,
+                    min_affix,
+                    ..Default::default()
+                };
+                let mut response = ascii_spoof::lookup_ascii_with_options(&domain, &db, &options)?;
+                response.dataset = release::dataset_info(&db)?;
+                response
+            };
+            output(
+                output_format(&output_args),
+                OutputData::Ascii(results),
+                output_args.outfile.clone(),
+            )?;
+        }
+        Commands::Idn {
+            domain,
+            max_variants,
+            max_checks,
+            max_results,
+            whois_timeout,
+            idna_transitional,
+            idna_std3_rules,
+            punycode_overflow,
+            dns_cache,
+            output: output_args,
+        } => {
+            let domain = crate::types::parse_domain_input(&domain)?;
+            info!(kind = "idn", domain = %domain);
+            install_dns_cache(&dns_cache)?;
+            let defaults = idn::IdnOptions::from_env();
+            let options = idn::IdnOptions {
+                max_normalized: max_variants.unwrap_or(defaults.max_normalized),
+                max_whois_checks: max_checks.unwrap_or(defaults.max_whois_checks),
+                max_results: max_results.unwrap_or(defaults.max_results),
+                whois_timeout_ms: whois_timeout.unwrap_or(defaults.whois_timeout_ms),
+                idna: puny::IdnaOptions {
+                    transitional: idna_transitional,
+                    use_std3_ascii_rules: idna_std3_rules,
+                    punycode_overflow,
+                },
+                ..defaults
+            };
+            let results = idn::lookup_idn_with_options(&domain, &options)?;
+            output(
+                output_format(&output_args),
+                OutputData::Idn(results),
+                output_args.outfile.clone(),
+            )?;
+        }
+        Commands::Compare {
+            first,
+            second,
+            no_check,
+            visual,
+            whois_timeout,
+            dns_cache,
+            output: output_args,
+        } => {
+            info!(kind = "compare", first = %first, second = %second);
+            let timeout_ms = if no_check || net::offline() {
+                None
+            } else {
+                install_dns_cache(&dns_cache)?;
+                Some(whois_timeout.unwrap_or(idn::IdnOptions::from_env().whois_timeout_ms))
+            };
+            let comparison = compare::compare(&first, &second, timeout_ms, visual)?;
+            output(
+                output_format(&output_args),
+                OutputData::Compare(comparison),
+                output_args.outfile.clone(),
+            )?;
+        }
+        Commands::Skeleton {
+            domain,
+            output: output_args,
+        } => {
+            let report = skeleton::analyze(&domain)?;
+            output(
+                output_format(&output_args),
+                OutputData::Skeleton(report),
+                output_args.outfile.clone(),
+            )?;
+        }
+        Commands::Puny { action } => {
+            let (report, output_args) = match action {
+                PunyAction::Encode { domain, output } => (puny::encode(&domain), output),
+                PunyAction::Decode { domain, output } => (puny::decode(&domain), output),
+            };
+            let ok = report.is_ok();
+            output(
+                output_format(&output_args),
+                OutputData::Puny(report),
+                output_args.outfile.clone(),
+            )?;
+            // The report already explains the failure; scripts get the status.
+            if !ok {
+                std::process::exit(EXIT_INVALID_INPUT);
+            }
+        }
+        Commands::Mappings {
+            action: MappingsAction::Update { url },
+        } => {
+            let summary = mappings::update(&url, &mappings_path)?;
+            info!(kind = "mappings", path = %summary.path, entries = summary.entries, added = summary.added);
+            println!("{}", serde_json::to_string_pretty(&summary)?);
+        }
+        Commands::Tail {
+            file,
+            regex,
+            from_start,
+            interval,
+            dedup_size,
+            text,
+            scan,
+        } => {
+            info!(kind = "tail", path = %file, regex = ?regex.as_ref().map(regex::Regex::as_str));
+            let tail = tail::Tail::new(build_scanner(&scan)?, regex, dedup_size);
+            let mut stdout = std::io::stdout().lock();
+            alerts::spawn_retries(alerts::RETRY_INTERVAL);
+            tail::follow(
+                std::path::Path::new(&file),
+                from_start,
+                std::time::Duration::from_millis(interval),
+                tail,
+                |alert| {
+                    alerts::notify("tail", &alert.domain, &alert.lookup);
+                    kafka::publish_detection("tail", &alert.domain, &alert.lookup);
+                    let line = if text {
+                        alert.text()
+                    } else {
+                        serde_json::to_string(alert)?
+                    };
+                    writeln!(stdout, "{line}")?;
+                    Ok(stdout.flush()?)
+                },
+            )?;
+        }
+        Commands::ScanFile {
+            path,
+            scan,
+            output: output_args,
+        } => {
+            let text = read_input(&path)?;
+            let domains = scan::extract_domains(&text);
+            info!(kind = "scan", path = %path, domains = domains.len());
+            let mut report = build_scanner(&scan)?.scan(domains)?;
+            detections::suppress_triaged(&mut report);
+            report.group(scan.group_by);
+            alerts::notify_findings(&report);
+            kafka::publish_findings(&report);
+            output(
+                output_format(&output_args),
+                OutputData::Scan(report),
+                output_args.outfile.clone(),
+            )?;
+        }
+        Commands::ScanUrl {
+            url,
+            user_agent,
+            scan,
+            output: output_args,
+        } => {
+            let page = html::fetch(&url, &user_agent)?;
+            scan_page(&url, page, &scan, &output_args)?;
+        }
+        Commands::ScanHtml {
+            path,
+            base_url,
+            scan,
+            output: output_args,
+        } => {
+            let page = html::extract(&read_input(&path)?, base_url.as_ref());
+            scan_page(&path, page, &scan, &output_args)?;
+        }
+        Commands::ScanHar {
+            path,
+            scan,
+            output: output_args,
+        } => {
+            let (observations, requests) =
+                har::read(&read_bytes(&path)?).map_err(|err| anyhow::anyhow!("{path}: {err}"))?;
+            info!(kind = "scan", path = %path, requests, domains = observations.len());
+            let mut report = build_scanner(&scan)?.scan(observations.into_vec())?;
+            detections::suppress_triaged(&mut report);
+            report.group(scan.group_by);
+            alerts::notify_findings(&report);
+            kafka::publish_findings(&report);
+            output(
+                output_format(&output_args),
+                OutputData::Scan(report),
+                output_args.outfile.clone(),
+            )?;
+        }
+        Commands::ScanBrowser {
+            profile,
+            since,
+            scan,
+            output: output_args,
+        } => {
+            let mut observations = scan::Observations::default();
+            for profile in &profile {
+                let summary = browser::read_profile(profile, since, &mut observations)?;
+                info!(
+                    kind = "scan",
+                    profile = %profile,
+                    history = summary.history,
+                    bookmarks = summary.bookmarks
+                );
+            }
+            info!(kind = "scan", domains = observations.len());
+            let mut report = build_scanner(&scan)?.scan(observations.into_vec())?;
+            detections::suppress_triaged(&mut report);
+            report.group(scan.group_by);
+            alerts::notify_findings(&report);
+            kafka::publish_findings(&report);
+            output(
+                output_format(&output_args),
+                OutputData::Scan(report),
+                output_args.outfile.clone(),
+            )?;
+        }
+        Commands::ScanEml {
+            path,
+            scan,
+            output: output_args,
+        } => {
+            let message = eml::parse(&read_bytes(&path)?)?;
+            info!(
+                kind = "scan",
+                path = %path,
+                senders = message.senders.len(),
+                domains = message.links.len()
+            );
+            let mut report = eml::check(message, &build_scanner(&scan)?)?;
+            report.links.group(scan.group_by);
+            output(
+                output_format(&output_args),
+                OutputData::Eml(report),
+                output_args.outfile.clone(),
+            )?;
+        }
+        Commands::ScanDmarc {
+            paths,
+            mut scan,
+            output: output_args,
+        } => {
+            let mut reports = Vec::new();
+            for path in &paths {
+                reports.extend(
+                    dmarc::read(&read_bytes(path)?)
+                        .map_err(|err| anyhow::anyhow!("{path}: {err}"))?,
+                );
+            }
+            // Sources are scored against the domains the reports protect.
+            let protected = dmarc::protected_domains(&reports);
+            scan.watch.extend(protected.iter().cloned());
+            info!(kind = "scan", reports = reports.len(), protected = ?protected);
+            let report = dmarc::analyze(reports, protected, &build_scanner(&scan)?)?;
+            output(
+                output_format(&output_args),
+                OutputData::Dmarc(report),
+                output_args.outfile.clone(),
+            )?;
+        }
+        Commands::ScanZeek {
+            path,
+            format,
+            scan,
+            output: output_args,
+        } => {
+            let observations = dnslog::read(&read_input(&path)?, format)?;
+            info!(kind = "scan", path = %path, domains = observations.len());
+            let mut report = build_scanner(&scan)?.scan(observations.into_vec())?;
+            detections::suppress_triaged(&mut report);
+            report.group(scan.group_by);
+            alerts::notify_findings(&report);
+            kafka::publish_findings(&report);
+            output(
+                output_format(&output_args),
+                OutputData::Scan(report),
+                output_args.outfile.clone(),
+            )?;
+        }
+        Commands::ScanPcap {
+            path,
+            scan,
+            output: output_args,
+        } => {
+            let observations = read_capture(&path)?;
+            info!(kind = "scan", path = %path, domains = observations.len());
+            let mut report = build_scanner(&scan)?.scan(observations.into_vec())?;
+            detections::suppress_triaged(&mut report);
+            report.group(scan.group_by);
+            alerts::notify_findings(&report);
+            kafka::publish_findings(&report);
+            output(
+                output_format(&output_args),
+                OutputData::Scan(report),
+                output_args.outfile.clone(),
+            )?;
+        }
+        Commands::Screenshot { target, out } => {
+            let png = screenshot::capture(&target, &config.screenshot)?;
+            let out = out.unwrap_or_else(|| {
+                let host = url::Url::parse(&target)
+                    .ok()
+                    .and_then(|url| url.host_str().map(str::to_string))
+                    .unwrap_or_else(|| target.trim_end_matches('.').to_string());
+                format!("{host}.png")
+            });
+            std::fs::write(&out, &png)?;
+            info!(kind = "screenshot", target = %target, path = %out, bytes = png.len());
+        }
+        Commands::Cohosted {
+            domain,
+            source,
+            scan,
+            output: output_args,
+        } => {
+            let domain = domain.trim_end_matches('.').to_ascii_lowercase();
+            let ips = whois::resolve_a(&domain, idn::IdnOptions::from_env().whois_timeout_ms)?;
+            if ips.is_empty() {
+                anyhow::bail!("{domain} has no A records");
+            }
+            let (source, observations) = cohosted::discover(&domain, &ips, source)?;
+            let observations = observations.into_vec();
+            let mut domains: Vec<String> = observations
+                .iter()
+                .map(|observation| observation.domain.clone())
+                .collect();
+            domains.sort();
+            info!(kind = "cohosted", domain = %domain, ips = ips.len(), source = ?source, domains = domains.len());
+            let mut report = build_scanner(&scan)?.scan(observations)?;
+            detections::suppress_triaged(&mut report);
+            report.group(scan.group_by);
+            alerts::notify_findings(&report);
+            kafka::publish_findings(&report);
+            output(
+                output_format(&output_args),
+                OutputData::Cohosted(cohosted::CohostedReport {
+                    domain,
+                    ips: ips.iter().map(ToString::to_string).collect(),
+                    source,
+                    cohosted: domains,
+                    scan: report,
+                }),
+                output_args.outfile.clone(),
+            )?;
+        }
+        Commands::ExportRpz {
+            targets,
+            out,
+            techniques,
+            action,
+            ttl,
+            db,
+            no_db,
+        } => {
+            let targets = export::read_targets(&targets)?;
+            let squats = export_squats(&targets, &techniques, db, no_db)?;
+            let previous = std::fs::read_to_string(&out)
+                .ok()
+                .and_then(|zone| export::zone_serial(&zone));
+            let serial = export::next_serial(previous, export::today());
+            info!(kind = "export", out = %out, targets = targets.len(), squats = squats.len(), serial);
+            let zone = export::render_rpz(
+                &squats,
+                export::RpzOptions {
+                    ttl,
+                    action,
+                    serial,
+                },
+            );
+            export::write_atomic(&out, &zone)?;
+        }
+        Commands::ExportBlocklist {
+            targets,
+            findings,
+            format,
+            out,
+            techniques,
+            only_registered,
+            whois_timeout,
+            concurrency,
+            db,
+            no_db,
+            dns_cache,
+        } => {
+            let mut domains = match (targets, findings) {
+                (_, Some(findings)) => export::read_findings(&findings)?,
+                (Some(targets), None) => {
+                    let targets = export::read_targets(&targets)?;
+                    export_squats(&targets, &techniques, db, no_db)?
+                        .into_iter()
+                        .map(|squat| squat.permutation.domain)
+                        .collect()
+                }
+                (None, None) => unreachable!("clap requires --targets or --findings"),
+            };
+            if only_registered {
+                install_dns_cache(&dns_cache)?;
+                domains = export::only_registered(domains, concurrency as usize, |domain| {
+                    Ok(whois::check_domain_registration(domain, whois_timeout)?.status)
+                })?;
+            }
+            info!(kind = "export", format = ?format, domains = domains.len());
+            let list = export::render_blocklist(&domains, format);
+            match out {
+                Some(out) => export::write_atomic(&out, &list)?,
+                None => print!("{list}"),
+            }
+        }
+        Commands::Milter {
+            listen,
+            header_at,
+            reject_at,
+            scan,
+        } => {
+            // Fail at startup rather than on the first connection.
+            build_scanner(&scan)?;
+            milter::serve(
+                &listen,
+                milter::MilterConfig {
+                    header_at,
+                    reject_at,
+                },
+                move || open_scanner(&scan),
+            )?;
+        }
+        Commands::DohProxy {
+            host,
+            port,
+            upstream,
+            action,
+            redirect_to,
+            upstream_timeout,
+            scan,
+        } => {
+            if scan_targets(&scan)?.is_empty() {
+                return Err(anyhow::anyhow!(
+                    "doh-proxy needs the domains to protect (--watch or --watch-file)"
+                ));
+            }
+            net::ensure_online("doh-proxy")?;
+            let proxy = doh::DohProxy::new(
+                doh::DohProxyOptions {
+                    upstream,
+                    action,
+                    redirect_to,
+                    timeout: std::time::Duration::from_millis(upstream_timeout),
+                },
+                build_scanner(&scan)?,
+            );
+            info!(kind = "doh", host = %host, port, action = ?action);
+            let runtime = tokio::runtime::Runtime::new()?;
+            runtime.block_on(doh::serve(&host, port, proxy))?;
+        }
+        Commands::Mcp {
+            db,
+            no_db,
+            dns_cache,
+        } => {
+            let db = resolve_db_path(db);
+            info!(kind = "mcp", db_path = %db);
+            install_dns_cache(&dns_cache)?;
+            if !no_db {
+                db::ensure_db(&db, db::DEFAULT_DB_URL)?;
+            }
+            mcp::McpServer::new(db, !no_db)
+                .with_datasets(dataset_paths(&config))
+                .run()?;
+        }
+        Commands::Repl {
+            db,
+            no_db,
+            strategies,
+            dns_cache,
+        } => {
+            let db = resolve_db_path(db);
+            info!(kind = "repl", db_path = %db);
+            install_dns_cache(&dns_cache)?;
+            if !no_db {
+                db::ensure_db(&db, db::DEFAULT_DB_URL)?;
+            }
+            let options = ascii_spoof::AsciiOptions {
+                strategies: candidate_strategies(strategies),
+                ..Default::default()
+            };
+            let stdout = std::io::stdout();
+            let color = stdout.is_terminal() && std::env::var_os("NO_COLOR").is_none();
+            repl::Repl::new((!no_db).then_some(db), options, idn::IdnOptions::from_env())
+                .with_color(color)
+                .run(std::io::stdin().lock(), stdout.lock())?;
+        }
+        Commands::Serve {
+            host,
+            port,
+            db,
+            no_db,
+            admin_token,
+            watch_db,
+            auto_update,
+            cache_size,
+            cache_ttl,
+            grpc_port,
+            tenant_db,
+            keys_db,
+            db_pool_size,
+            in_memory,
+            strategies,
+            min_affix,
+            redis_url,
+            read_only,
+            dns_cache,
+        } => {
+            let db = resolve_db_path(db);
+            info!(kind = "serve", host = %host, port, db_path = %db);
+            let redis = redis_url
+                .as_deref()
+                .map(redis_cache::RedisCache::connect)
+                .transpose()?;
+            match &redis {
+                Some(redis) if !dns_cache.no_dns_cache => {
+                    info!(
+                        kind = "dns_cache",
+                        backend = "redis",
+                        ttl_secs = dns_cache.dns_cache_ttl.as_secs()
+                    );
+                    dns_cache::install(dns_cache::RegistrationCache::redis(
+                        redis.clone(),
+                        dns_cache.dns_cache_ttl,
+                    ));
+                }
+                _ => install_dns_cache(&dns_cache)?,
+            }
+            if auto_update.is_some() {
+                net::ensure_online("--auto-update")?;
+            }
+            if !no_db && read_only {
+                if !std::path::Path::new(&db).is_file() {
+                    anyhow::bail!("{db} does not exist; --read-only never downloads the DB");
+                }
+                db::verify_schema(&db::open(&db, false)?)?;
+            } else if !no_db {
+                db::ensure_db(&db, db::DEFAULT_DB_URL)?;
+            }
+            let runtime = tokio::runtime::Runtime::new()?;
+            runtime.block_on(http::serve(http::ServeOptions {
+                host,
+                port,
+                db_path: db,
+                use_db: !no_db,
+                admin_token,
+                watch_db,
+                auto_update,
+                cache_size,
+                cache_ttl,
+                grpc_port,
+                tenant_db,
+                keys_db,
+                db_pool_size,
+                in_memory,
+                candidate_strategies: candidate_strategies(strategies),
+                min_affix,
+                datasets: dataset_paths(&config),
+                redis,
+                read_only,
+            }))?;
+        }
+        Commands::Schema { kind, out_dir } => match (kind, out_dir) {
+            (Some(kind), _) => {
+                println!(
+                    "{}",
+                    serde_json::to_string_pretty(&schema::document(kind)?)?
+                )
+            }
+            (None, Some(dir)) => {
+                for path in schema::write_all(std::path::Path::new(&dir))? {
+                    info!(kind = "schema", path = %path, "written");
+                }
+            }
+            (None, None) => unreachable!("clap requires a document or --out-dir"),
+        },
+        Commands::Bench {
+            db,
+            queries,
+            iterations,
+            index,
+            algorithm,
+            strategies,
+            length_band,
+            max_candidates,
+            min_affix,
+        } => {
+            let db = resolve_db_path(db);
+            db::ensure_db(&db, db::DEFAULT_DB_URL)?;
+            let queries = bench::read_queries(&queries)?;
+            let defaults = ascii_spoof::AsciiOptions::default();
+            let options = ascii_spoof::AsciiOptions {
+                algorithm,
+                strategies: candidate_strategies(strategies),
+                length_band: length_band.unwrap_or(defaults.length_band),
+                max_candidates: max_candidates.unwrap_or(defaults.max_candidates),
+                min_affix,
+                ..defaults
+            };
+            info!(kind = "bench", db_path = %db, queries = queries.len(), iterations);
+            let source = bench::open_source(&db, index)?;
+            let report = bench::run(
+                source.as_ref(),
+                &queries,
+                iterations as usize,
+                index,
+                &options,
+            )?;
+            println!("{}", serde_json::to_string_pretty(&report)?);
+        }
+        Commands::Update {
+            db,
+            url,
+            delta,
+            manifest_url,
+            releases_url,
+            check,
+        } => {
+            let db = resolve_db_path(db);
+            info!(kind = "update", db_path = %db, url = %url, delta);
+            // Only the default artifact is published as a GitHub release.
+            let releases_url = releases_url.or_else(|| {
+                (url == db::DEFAULT_DB_URL).then(|| release::DEFAULT_RELEASES_URL.to_string())
+            });
+            update_db(UpdateArgs {
+                db,
+                url,
+                delta,
+                manifest_url,
+                releases_url,
+                check,
+            })?;
+        }
+        Commands::SelfUpdate {
+            releases_url,
+            check,
+        } => {
+            info!(kind = "self_update", releases_url = %releases_url, check);
+            let latest = release::latest(&releases_url)?;
+            let current = self_update::VERSION;
+            if !self_update::is_newer(&latest, current)? {
+                println!("spotspoof {current} is up to date (latest release {latest})");
+                return Ok(());
+            }
+            println!("Newer spotspoof available: {current} -> {latest}");
+            if !check {
+                let exe = self_update::install(&latest)?;
+                println!("{} updated to {}", exe.display(), latest.version());
+            }
+        }
+        Commands::Db { action } => match action {
+            DbAction::Index { db, kind } => {
+                let db = resolve_db_path(db);
+                db::ensure_db(&db, db::DEFAULT_DB_URL)?;
+                match kind {
+                    IndexKind::Fst => {
+                        let (path, domains) = index::build_fst(&db)?;
+                        info!(kind = "index", index = "fst", path = %path, domains);
+                        println!("{path}");
+                    }
+                    IndexKind::Symspell => {
+                        let rows = index::build_symspell(&db)?;
+                        info!(kind = "index", index = "symspell", db_path = %db, rows);
+                        println!("{db}");
+                    }
+                    IndexKind::Trigram => {
+                        let rows = index::build_trigrams(&db)?;
+                        info!(kind = "index", index = "trigram", db_path = %db, rows);
+                        println!("{db}");
+                    }
+                }
+            }
+            DbAction::Pack {
+                db,
+                out,
+                version,
+                source,
+                level,
+            } => {
+                let db = resolve_db_path(db);
+                let summary = pack::pack(&db, &out, version.as_deref(), source.as_deref(), level)?;
+                info!(kind = "pack", db_path = %db, path = %out, version = %summary.version, domains = summary.domains);
+                println!("{}", serde_json::to_string_pretty(&summary)?);
+            }
+            DbAction::Delta {
+                old,
+                new,
+                out,
+                from_version,
+                to_version,
+                manifest,
+            } => {
+                let delta = delta::diff(&old, &new, from_version, to_version)?;
+                let sha256 = delta::publish(&delta, &out, manifest.as_deref())?;
+                info!(
+                    kind = "delta",
+                    from = %delta.from,
+                    to = %delta.to,
+                    added = delta.added.len(),
+                    removed = delta.removed.len(),
+                    path = %out
+                );
+                println!("{sha256}  {out}");
+            }
+            DbAction::Encrypt { paths } => {
+                if !cipher::enabled() {
+                    anyhow::bail!("db encrypt needs --db-key");
+                }
+                let paths = if paths.is_empty() {
+                    vec![resolve_db_path(None)]
+                } else {
+                    paths
+                };
+                for path in paths {
+                    cipher::encrypt(&path)?;
+                    info!(kind = "encrypt", path = %path);
+                    println!("{path}");
+                }
+            }
+        },
+        Commands::History { action } => {
+            let store = history::LookupHistory::open(&history_db, Some(cli.history_retention))?;
+            match action {
+                HistoryAction::List { limit } => {
+                    println!("{}", serde_json::to_string_pretty(&store.list(limit)?)?);
+                }
+                HistoryAction::Search { pattern, limit } => {
+                    println!(
+                        "{}",
+                        serde_json::to_string_pretty(&store.search(&pattern, limit)?)?
+                    );
+                }
+                HistoryAction::Export {
+                    format,
+                    since,
+                    outfile,
+                } => {
+                    let entries = store.export(since)?;
+                    let rendered = match format {
+                        HistoryFormat::Json => serde_json::to_string_pretty(&entries)?,
+                        HistoryFormat::Csv => {
+                            let mut writer = csv::Writer::from_writer(Vec::new());
+                            for entry in &entries {
+                                writer.serialize(entry)?;
+                            }
+                            String::from_utf8(writer.into_inner()?)?
+                                .trim_end()
+                                .to_string()
+                        }
+                    };
+                    write_output(rendered, outfile)?;
+                }
+            }
+        }
+        Commands::Tenant { tenant_db, action } => {
+            let conn = tenants::open(&tenant_db)?;
+            match action {
+                TenantAction::Create { name } => {
+                    let key = tenants::create(&conn, &name)?;
+                    info!(kind = "tenant", action = "create", name = %name);
+                    println!("{key}");
+                }
+                TenantAction::AddBrand { name, domains } => {
+                    let added = tenants::add_brands(&conn, &name, &domains)?;
+                    info!(kind = "tenant", action = "add_brand", name = %name, added);
+                }
+                TenantAction::Allow { name, domains } => {
+                    let added = tenants::add_allowlist(&conn, &name, &domains)?;
+                    info!(kind = "tenant", action = "allow", name = %name, added);
+                }
+                TenantAction::List => {
+                    println!("{}", serde_json::to_string_pretty(&tenants::list(&conn)?)?);
+                }
+            }
+        }
+        Commands::Alerts { action } => {
+            let queue = alerts::Alerts::new(
+                alerts::Outbox::open(&alerts_db, max_attempts)?,
+                &config.alerts,
+            )?;
+            match action {
+                AlertsAction::List { state, limit } => {
+                    let listed = queue.outbox.list(state, limit)?;
+                    println!("{}", serde_json::to_string_pretty(&listed)?);
+                }
+                AlertsAction::Retry { dead } => {
+                    let requeued = queue.outbox.requeue(dead)?;
+                    let summary = queue.deliver()?;
+                    info!(kind = "alerts", requeued, delivered = summary.delivered);
+                    println!("{}", serde_json::to_string_pretty(&summary)?);
+                }
+            }
+        }
+        Commands::Monitor {
+            action:
+                MonitorAction::Plan {
+                    targets,
+                    techniques,
+                    db,
+                    no_db,
+                    text,
+                    dns_cache,
+                },
+        } => {
+            let targets = export::read_targets(&targets)?;
+            // Only files already on disk: nothing is downloaded or created.
+            let db = resolve_db_path(db);
+            let conn = if !no_db && std::path::Path::new(&db).is_file() {
+                Some(db::open(&db, true)?)
+            } else {
+                None
+            };
+            let cache_path = dns_cache
+                .dns_cache
+                .clone()
+                .unwrap_or_else(|| paths::data_path(dns_cache::DEFAULT_CACHE_FILE));
+            let cache = if !dns_cache.no_dns_cache && std::path::Path::new(&cache_path).is_file() {
+                Some(dns_cache::RegistrationCache::open(
+                    &cache_path,
+                    dns_cache.dns_cache_ttl,
+                )?)
+            } else {
+                None
+            };
+            let plan = monitor::plan(
+                &targets,
+                &techniques,
+                monitor::PlanSources {
+                    is_legit: conn
+                        .as_ref()
+                        .map(|conn| |domain: &str| db::is_legit_domain(conn, domain)),
+                    is_cached: |domain: &str| match &cache {
+                        Some(cache) => Ok(cache.get(domain)?.is_some()),
+                        None => Ok(false),
+                    },
+                },
+                monitor::DohLimit {
+                    rate: cli.doh_rate,
+                    burst: cli.doh_burst.unwrap_or(cli.doh_rate),
+                },
+                &config.alerts,
+                &config.kafka,
+            )?;
+            if text {
+                println!("{}", format_monitor_plan_text(&plan));
+            } else {
+                println!("{}", serde_json::to_string_pretty(&plan)?);
+            }
+        }
+        Commands::Detections { action } => {
+            let store = detections::DetectionStore::open(&detections_db)?;
+            let (id, state, note) = match action {
+                DetectionAction::List {
+                    state,
+                    limit,
+                    group_by,
+                } => {
+                    let listed = store.list(state, limit)?;
+                    let rendered = match group_by {
+                        Some(scan::GroupBy::Brand) => {
+                            serde_json::to_string_pretty(&detections::group_by_brand(&listed))?
+                        }
+                        None => serde_json::to_string_pretty(&listed)?,
+                    };
+                    println!("{rendered}");
+                    return Ok(());
+                }
+                DetectionAction::Ack { id, note } => {
+                    (id, detections::TriageState::Acknowledged, note)
+                }
+                DetectionAction::Escalate { id, note } => {
+                    (id, detections::TriageState::Escalated, note)
+                }
+                DetectionAction::FalsePositive { id, note } => {
+                    (id, detections::TriageState::FalsePositive, note)
+                }
+                DetectionAction::Reopen { id, note } => (id, detections::TriageState::New, note),
+            };
+            let detection = store
+                .triage(id, state, note.as_deref())?
+                .ok_or_else(|| anyhow::anyhow!("no detection with id {id}"))?;
+            info!(kind = "detections", id, state = state.as_str());
+            println!("{}", serde_json::to_string_pretty(&detection)?);
+        }
+        Commands::Keys { keys_db, action } => {
+            let conn = keys::open(&keys_db)?;
+            match action {
+                KeyAction::Create {
+                    name,
+                    scope,
+                    expires_in,
+                    quotas,
+                } => {
+                    let key = keys::create(&conn, &name, scope, expires_in, quotas.into())?;
+                    info!(kind = "keys", action = "create", name = %name, scope = ?scope);
+                    println!("{key}");
+                }
+                KeyAction::List => {
+                    println!("{}", serde_json::to_string_pretty(&keys::list(&conn)?)?);
+                }
+                KeyAction::Revoke { name } => {
+                    keys::revoke(&conn, &name)?;
+                    info!(kind = "keys", action = "revoke", name = %name);
+                }
+                KeyAction::SetQuota { name, quotas } => {
+                    keys::set_quotas(&conn, &name, quotas.into())?;
+                    info!(kind = "keys", action = "set_quota", name = %name);
+                }
+                KeyAction::Usage => {
+                    println!("{}", serde_json::to_string_pretty(&keys::usage(&conn)?)?);
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+struct UpdateArgs {
+    db: String,
+    url: String,
+    delta: bool,
+    manifest_url: Option<String>,
+    releases_url: Option<String>,
+    check: bool,
+}
+
+/// `spotspoof update`: compares the latest release with the local dataset,
+/// downloads (or applies deltas) only when it is newer, and prints what changed.
+fn update_db(args: UpdateArgs) -> Result<()> {
+    let db = &args.db;
+    let before = release::LocalDataset::read(db)?;
+    let latest = match &args.releases_url {
+        Some(api_url) => match release::latest(api_url) {
+            Ok(latest) => Some(latest),
+            Err(err) => {
+                warn!(kind = "update", "release check failed: {err}");
+                None
+            }
+        },
+        None => None,
+    };
+    if let Some(latest) = &latest {
+        let newer = before
+            .as_ref()
+            .map_or(Some(true), |local| local.is_older_than(latest));
+        match (newer, &before) {
+            (Some(false), Some(local)) => {
+                println!("{db} is up to date ({local}; latest release {latest})");
+                return Ok(());
+            }
+            (Some(true), Some(local)) => println!("Newer DB available: {local} -> {latest}"),
+            (Some(true), None) => println!("No DB at {db}; latest release {latest}"),
+            _ => println!("Latest release {latest}; {db} records no dataset version"),
+        }
+    }
+    if args.check {
+        if latest.is_none() {
+            println!("Could not check for a newer release of {db}");
+        }
+        return Ok(());
+    }
+
+    let outcome = if args.delta {
+        let manifest_url = args
+            .manifest_url
+            .unwrap_or_else(|| delta::manifest_url(&args.url));
+        delta::update(&args.url, &manifest_url, db)?
+    } else {
+        delta::UpdateOutcome::Downloaded(download::update_db(&args.url, db)?)
+    };
+    match outcome {
+        delta::UpdateOutcome::UpToDate(_)
+        | delta::UpdateOutcome::Downloaded(download::DownloadOutcome::NotModified) => {
+            println!("{db} is up to date");
+            return Ok(());
+        }
+        delta::UpdateOutcome::Applied { added, removed, .. } => {
+            println!("Applied deltas: +{added} -{removed} domains");
+        }
+        delta::UpdateOutcome::Downloaded(download::DownloadOutcome::Downloaded(_)) => {
+            release::record(db, latest.as_ref(), &args.url)?;
+        }
+    }
+    let after = release::LocalDataset::read(db)?.unwrap_or_default();
+    match before {
+        Some(before) => println!("{db} updated: {before} -> {after}"),
+        None => println!("{db} installed: {after}"),
+    }
+    Ok(())
+}
+
+/// Loads the mappings file for IDN lookups. A missing or unreadable default
+/// file falls back to the compiled-in mappings; an explicit one must load.
+fn install_mappings(path: &str, explicit: bool) -> Result<()> {
+    if !explicit && !std::path::Path::new(path).is_file() {
+        return Ok(());
+    }
+    match mappings::MappingFile::load(path) {
+        Ok(file) => {
+            info!(kind = "mappings", path = %path, unicode_version = ?file.unicode_version, entries = file.mappings.len());
+            mappings::install(file);
+        }
+        Err(err) if !explicit => {
+            warn!(kind = "mappings", path = %path, "using the compiled-in mappings: {err:#}");
+        }
+        Err(err) => return Err(err),
+    }
+    Ok(())
+}
+
+fn install_dns_cache(args: &DnsCacheArgs) -> Result<()> {
+    if args.no_dns_cache {
+        return Ok(());
+    }
+    let path = args
+        .dns_cache
+        .clone()
+        .unwrap_or_else(|| paths::data_path(dns_cache::DEFAULT_CACHE_FILE));
+    info!(kind = "dns_cache", path = %path, ttl_secs = args.dns_cache_ttl.as_secs());
+    dns_cache::install(dns_cache::RegistrationCache::open(
+        &path,
+        args.dns_cache_ttl,
+    )?);
+    Ok(())
+}
+
+/// Scans the hosts of a page swept by `scan-url` or `scan-html`.
+fn scan_page(
+    source: &str,
+    page: html::Page,
+    scan: &ScanArgs,
+    output_args: &OutputArgs,
+) -> Result<()> {
+    let links = page.links;
+    info!(
+        kind = "scan",
+        source = %source,
+        domains = page.hosts.len(),
+        anchors = links.anchors,
+        forms = links.forms,
+        scripts = links.scripts,
+        other = links.other
+    );
+    let mut report = build_scanner(scan)?.scan(page.hosts.into_vec())?;
+    detections::suppress_triaged(&mut report);
+    report.group(scan.group_by);
+    alerts::notify_findings(&report);
+    kafka::publish_findings(&report);
+    output(
+        output_format(output_args),
+        OutputData::Scan(report),
+        output_args.outfile.clone(),
+    )
+}
+
+fn build_scanner(args: &ScanArgs) -> Result<scan::Scanner> {
+    if !args.no_db && scan_targets(args)?.is_empty() {
+        db::ensure_db(&resolve_db_path(args.db.clone()), db::DEFAULT_DB_URL)?;
+    }
+    if !args.ascii_only {
+        install_dns_cache(&args.dns_cache)?;
+    }
+    if args.resolve {
+        net::ensure_online("--resolve")?;
+        if geoip::load()? == 0 {
+            warn!(
+                kind = "geoip",
+                "no MaxMind DB found; --resolve reports addresses without country or ASN"
+            );
+        }
+    }
+    open_scanner(args)
+}
+
+/// The permutations of `targets`, without the dataset's legitimate domains
+/// unless `no_db` is set.
+fn export_squats(
+    targets: &[String],
+    techniques: &[permute::Technique],
+    db: Option<String>,
+    no_db: bool,
+) -> Result<Vec<export::Squat>> {
+    let conn = if no_db {
+        None
+    } else {
+        let db = resolve_db_path(db);
+        db::ensure_db(&db, db::DEFAULT_DB_URL)?;
+        Some(db::open(&db, true)?)
+    };
+    export::squats(targets, techniques, |domain| match &conn {
+        Some(conn) => db::is_legit_domain(conn, domain),
+        None => Ok(false),
+    })
+}
+
+/// The `--watch` and `--watch-file` domains.
+fn scan_targets(args: &ScanArgs) -> Result<Vec<String>> {
+    let mut targets = args.watch.clone();
+    if let Some(path) = &args.watch_file {
+        targets.extend(bench::read_queries(path)?);
+    }
+    Ok(targets)
+}
+
+/// Like `build_scanner`, without downloading the DB or installing the DNS
+/// cache (for scanners opened per connection).
+fn open_scanner(args: &ScanArgs) -> Result<scan::Scanner> {
+    let targets = scan_targets(args)?;
+    let source = if args.no_db || !targets.is_empty() {
+        None
+    } else {
+        Some(ascii_spoof::open_candidate_source(&resolve_db_path(
+            args.db.clone(),
+        ))?)
+    };
+    let ascii = ascii_spoof::AsciiOptions {
+        strategies: candidate_strategies(args.strategies.clone()),
+        leet: args.leet,
+        phonetic: args.phonetic,
+        min_affix: args.min_affix,
+        ..Default::default()
+    };
+    let idn = (!args.ascii_only).then(idn::IdnOptions::from_env);
+    Ok(scan::Scanner::new(source, ascii, idn)
+        .with_targets(targets)
+        .with_resolve(args.resolve))
 }
 
-#[derive(Clone)]
-enum OutputData {
-    Ascii(AsciiResponse),
-    Idn(IdnResponse),
+/// Reads the DNS names from a pcap/pcapng capture (`-` for stdin).
+#[cfg(feature = "pcap")]
+fn read_capture(path: &str) -> Result<scan::Observations> {
+    pcap::read(path)
 }
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    let cli = Cli::parse();
-    init_logging(cli.log_format, cli.log_destination, cli.log_file.as_deref())?;
+#[cfg(not(feature = "pcap"))]
+fn read_capture(_path: &str) -> Result<scan::Observations> {
+    Err(anyhow::anyhow!(
+        "scan-pcap requires a build with the `pcap` feature"
+    ))
+}
 
-    match run(cli).await {
-        Ok(()) => Ok(()),
-        Err(err) => {
-            error!("{err}");
-            Err(err)
-        }
+/// Reads a file, or stdin for `-`, tolerating invalid UTF-8 (logs often have it).
+fn read_input(path: &str) -> Result<String> {
+    Ok(String::from_utf8_lossy(&read_bytes(path)?).into_owned())
+}
+
+fn read_bytes(path: &str) -> Result<Vec<u8>> {
+    if path == "-" {
+        let mut bytes = Vec::new();
+        std::io::Read::read_to_end(&mut std::io::stdin(), &mut bytes)?;
+        return Ok(bytes);
     }
+    std::fs::read(path).map_err(|err| anyhow::anyhow!("cannot read {path}: {err}"))
 }
 
-async fn run(cli: Cli) -> Result<()> {
-    match cli.command {
-        Commands::Lookup {
-            domain,
-            db,
-            no_db,
-            output: output_args,
-        } => {
-            let db = resolve_db_path(db);
-            let domain = crate::types::normalize_domain_input(&domain);
-            let is_idn = domain.starts_with("xn--") || domain.chars().any(|c| c as u32 > 127);
-            info!(kind = "lookup", domain = %domain, is_idn, db_path = %db);
-            if is_idn {
-                let results = idn::lookup_idn(&domain)?;
-                output(
-                    output_format(&output_args),
-                    OutputData::Idn(results),
-                    output_args.outfile.clone(),
-                )?;
-            } else {
-                let results = if no_db {
-                    crate::types::empty_ascii_response(&domain)
-                } else {
-                    db::ensure_db(&db, db::DEFAULT_DB_URL)?;
-                    ascii_spoof::lookup_ascii(&domain, &db)?
-                };
-                output(
-                    output_format(&output_args),
-                    OutputData::Ascii(results),
-                    output_args.outfile.clone(),
-                )?;
-            }
+fn candidate_strategies(strategies: Vec<db::CandidateStrategy>) -> db::CandidateStrategies {
+    if strategies.is_empty() {
+        return db::CandidateStrategies::default();
+    }
+    strategies.into_iter().collect()
+}
+
+fn output(format: OutputFormat, mut payload: OutputData, outfile: Option<String>) -> Result<()> {
+    validate_output(&payload)?;
+    match &mut payload {
+        OutputData::Ascii(data) => passive_dns::enrich_ascii(data),
+        OutputData::Idn(data) => passive_dns::enrich_idn(data),
+        OutputData::Both { ascii, idn } => {
+            passive_dns::enrich_ascii(ascii);
+            passive_dns::enrich_idn(idn);
         }
-        Commands::Ascii {
-            domain,
-            db,
-            no_db,
-            output: output_args,
-        } => {
-            let db = resolve_db_path(db);
-            let domain = crate::types::normalize_domain_input(&domain);
-            info!(kind = "ascii", domain = %domain, db_path = %db);
-            let results = if no_db {
-                crate::types::empty_ascii_response(&domain)
-            } else {
-                db::ensure_db(&db, db::DEFAULT_DB_URL)?;
-                ascii_spoof::lookup_ascii(&domain, &db)?
-            };
-            output(
-                output_format(&output_args),
-                OutputData::Ascii(results),
-                output_args.outfile.clone(),
-            )?;
+        // Scan findings are enriched by the scanner.
+        OutputData::Scan(_)
+        | OutputData::Eml(_)
+        | OutputData::Dmarc(_)
+        | OutputData::Compare(_)
+        | OutputData::Skeleton(_)
+        | OutputData::Puny(_)
+        | OutputData::Cohosted(_) => {}
+    }
+    // Every CLI lookup is printed through here.
+    match &payload {
+        OutputData::Ascii(data) => {
+            history::record_ascii(history::Source::Cli, data);
+            kafka::publish_lookup("cli", &types::LookupResponse::Ascii(data.clone()));
         }
-        Commands::Idn {
-            domain,
-            output: output_args,
-        } => {
-            let domain = crate::types::normalize_domain_input(&domain);
-            info!(kind = "idn", domain = %domain);
-            let results = idn::lookup_idn(&domain)?;
-            output(
-                output_format(&output_args),
-                OutputData::Idn(results),
-                output_args.outfile.clone(),
-            )?;
+        OutputData::Idn(data) => {
+            history::record_idn(history::Source::Cli, data);
+            kafka::publish_lookup("cli", &types::LookupResponse::Idn(data.clone()));
         }
-        Commands::Serve {
-            host,
-            port,
-            db,
-            no_db,
-        } => {
-            let db = resolve_db_path(db);
-            info!(kind = "serve", host = %host, port, db_path = %db);
-            if !no_db {
-                db::ensure_db(&db, db::DEFAULT_DB_URL)?;
-            }
-            http::serve(host, port, db, !no_db).await?;
+        OutputData::Both { ascii, idn } => {
+            history::record_ascii(history::Source::Cli, ascii);
+            history::record_idn(history::Source::Cli, idn);
+            kafka::publish_lookup("cli", &types::LookupResponse::Ascii(ascii.clone()));
+            kafka::publish_lookup("cli", &types::LookupResponse::Idn(idn.clone()));
         }
+        // Scans sweep whole logs; only direct lookups are audited.
+        OutputData::Scan(_)
+        | OutputData::Eml(_)
+        | OutputData::Dmarc(_)
+        | OutputData::Compare(_)
+        | OutputData::Skeleton(_)
+        | OutputData::Puny(_)
+        | OutputData::Cohosted(_) => {}
     }
-
-    Ok(())
-}
-
-fn output(format: OutputFormat, payload: OutputData, outfile: Option<String>) -> Result<()> {
-    validate_output(&payload)?;
     let rendered = format_output(format, payload)?;
     write_output(rendered, outfile)?;
     Ok(())
@@ -242,6 +2756,31 @@ fn validate_output(payload: &OutputData) -> Result<()> {
     match payload {
         OutputData::Ascii(data) => crate::types::validate_ascii_response(data),
         OutputData::Idn(data) => crate::types::validate_idn_response(data),
+        OutputData::Both { ascii, idn } => {
+            crate::types::validate_ascii_response(ascii)?;
+            crate::types::validate_idn_response(idn)
+        }
+        OutputData::Scan(report) => report
+            .findings
+            .iter()
+            .try_for_each(|finding| crate::types::validate_lookup_response(&finding.lookup)),
+        OutputData::Eml(report) => report
+            .senders
+            .iter()
+            .filter_map(|check| check.lookup.as_ref())
+            .chain(report.links.findings.iter().map(|finding| &finding.lookup))
+            .try_for_each(crate::types::validate_lookup_response),
+        OutputData::Dmarc(report) => report
+            .sources
+            .iter()
+            .flat_map(|source| &source.lookalikes)
+            .try_for_each(|lookalike| crate::types::validate_lookup_response(&lookalike.lookup)),
+        OutputData::Cohosted(report) => report
+            .scan
+            .findings
+            .iter()
+            .try_for_each(|finding| crate::types::validate_lookup_response(&finding.lookup)),
+        OutputData::Compare(_) | OutputData::Skeleton(_) | OutputData::Puny(_) => Ok(()),
     }
 }
 
@@ -250,6 +2789,16 @@ fn format_output(format: OutputFormat, payload: OutputData) -> Result<String> {
         OutputFormat::Json => match payload {
             OutputData::Ascii(data) => Ok(serde_json::to_string_pretty(&data)?),
             OutputData::Idn(data) => Ok(serde_json::to_string_pretty(&data)?),
+            OutputData::Both { ascii, idn } => Ok(serde_json::to_string_pretty(
+                &serde_json::json!({ "ascii": ascii, "idn": idn }),
+            )?),
+            OutputData::Scan(report) => Ok(serde_json::to_string_pretty(&report)?),
+            OutputData::Eml(report) => Ok(serde_json::to_string_pretty(&report)?),
+            OutputData::Dmarc(report) => Ok(serde_json::to_string_pretty(&report)?),
+            OutputData::Compare(comparison) => Ok(serde_json::to_string_pretty(&comparison)?),
+            OutputData::Skeleton(report) => Ok(serde_json::to_string_pretty(&report)?),
+            OutputData::Puny(report) => Ok(serde_json::to_string_pretty(&report)?),
+            OutputData::Cohosted(report) => Ok(serde_json::to_string_pretty(&report)?),
         },
         OutputFormat::Text => format_text(payload),
         OutputFormat::Csv => format_csv(payload),
@@ -258,8 +2807,20 @@ fn format_output(format: OutputFormat, payload: OutputData) -> Result<String> {
 
 fn format_text(payload: OutputData) -> Result<String> {
     match payload {
-        OutputData::Ascii(data) => Ok(format_ascii_text(&data.results)),
-        OutputData::Idn(data) => Ok(format_idn_text(&data.results)),
+        OutputData::Ascii(data) => Ok(format_ascii_text(&data)),
+        OutputData::Idn(data) => Ok(format_idn_text(&data)),
+        OutputData::Both { ascii, idn } => Ok(format!(
+            "ASCII: {}\nIDN: {}",
+            format_ascii_text(&ascii),
+            format_idn_text(&idn)
+        )),
+        OutputData::Scan(report) => Ok(format_scan_text(&report)),
+        OutputData::Eml(report) => Ok(format_eml_text(&report)),
+        OutputData::Dmarc(report) => Ok(format_dmarc_text(&report)),
+        OutputData::Compare(comparison) => Ok(format_compare_text(&comparison)),
+        OutputData::Skeleton(report) => Ok(format_skeleton_text(&report)),
+        OutputData::Puny(report) => Ok(format_puny_text(&report)),
+        OutputData::Cohosted(report) => Ok(format_cohosted_text(&report)),
     }
 }
 
@@ -267,30 +2828,657 @@ fn format_csv(payload: OutputData) -> Result<String> {
     match payload {
         OutputData::Ascii(data) => format_ascii_csv(&data.results),
         OutputData::Idn(data) => format_idn_csv(&data.results),
+        OutputData::Both { ascii, idn } => Ok(format!(
+            "{}\n\n{}",
+            format_ascii_csv(&ascii.results)?,
+            format_idn_csv(&idn.results)?
+        )),
+        OutputData::Scan(report) => format_scan_csv(&report),
+        OutputData::Eml(report) => format_eml_csv(&report),
+        OutputData::Dmarc(report) => format_dmarc_csv(&report),
+        OutputData::Compare(comparison) => format_compare_csv(&comparison),
+        OutputData::Skeleton(report) => format_skeleton_csv(&report),
+        OutputData::Puny(report) => format_puny_csv(&report),
+        OutputData::Cohosted(report) => format_scan_csv(&report.scan),
     }
 }
 
-fn format_ascii_text(results: &[crate::types::AsciiResult]) -> String {
-    if results.is_empty() {
-        return "No results".to_string();
+fn format_puny_text(report: &puny::PunyReport) -> String {
+    let mut lines = vec![format!(
+        "{} -> {}",
+        report.input,
+        report.output.as_deref().unwrap_or("(failed)")
+    )];
+    let describe = |errors: &[puny::IdnaError]| {
+        errors
+            .iter()
+            .map(|error| format!("{} ({})", error.category, error.message))
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    for label in report
+        .labels
+        .iter()
+        .filter(|label| !label.errors.is_empty())
+    {
+        lines.push(format!(
+            "  label {:?}: {}",
+            label.input,
+            describe(&label.errors)
+        ));
+    }
+    let labelled: Vec<&str> = report
+        .labels
+        .iter()
+        .flat_map(|label| &label.errors)
+        .map(|error| error.category.as_str())
+        .collect();
+    let whole: Vec<puny::IdnaError> = report
+        .errors
+        .iter()
+        .filter(|error| !labelled.contains(&error.category.as_str()))
+        .cloned()
+        .collect();
+    if !whole.is_empty() {
+        lines.push(format!("  domain: {}", describe(&whole)));
+    }
+    lines.join("\n")
+}
+
+fn format_puny_csv(report: &puny::PunyReport) -> Result<String> {
+    let mut writer = csv::WriterBuilder::new().from_writer(vec![]);
+    writer.write_record(["label", "output", "errors"])?;
+    for label in &report.labels {
+        let errors: Vec<&str> = label
+            .errors
+            .iter()
+            .map(|error| error.category.as_str())
+            .collect();
+        writer.write_record([
+            label.input.as_str(),
+            label.output.as_deref().unwrap_or(""),
+            &errors.join(";"),
+        ])?;
+    }
+    Ok(String::from_utf8(writer.into_inner()?)?)
+}
+
+fn format_skeleton_text(report: &skeleton::SkeletonReport) -> String {
+    let mut lines = vec![match &report.unicode {
+        Some(unicode) => format!("{} ({unicode})", report.domain),
+        None => report.domain.clone(),
+    }];
+    lines.push(format!("skeleton: {}", report.skeleton));
+    lines.push(format!("folded: {}", report.folded));
+    lines.push(format!(
+        "scripts: {}{}",
+        report.scripts.join(", "),
+        if report.mixed_script { " (mixed)" } else { "" }
+    ));
+    for info in &report.characters {
+        let skeleton = if info.skeleton == info.char.to_string() {
+            String::new()
+        } else {
+            format!(" -> {}", info.skeleton)
+        };
+        lines.push(format!(
+            "  {}  {}  {}{skeleton}",
+            info.char, info.codepoint, info.script
+        ));
+    }
+    lines.join("\n")
+}
+
+fn format_skeleton_csv(report: &skeleton::SkeletonReport) -> Result<String> {
+    let mut writer = csv::WriterBuilder::new().from_writer(vec![]);
+    writer.write_record(["char", "codepoint", "script", "skeleton"])?;
+    for info in &report.characters {
+        writer.write_record([
+            info.char.to_string().as_str(),
+            &info.codepoint,
+            info.script,
+            &info.skeleton,
+        ])?;
+    }
+    Ok(String::from_utf8(writer.into_inner()?)?)
+}
+
+fn format_compare_text(comparison: &compare::Comparison) -> String {
+    let describe = |info: &compare::DomainInfo| {
+        let mut line = info.domain.clone();
+        if let Some(unicode) = &info.unicode {
+            line.push_str(&format!(" ({unicode})"));
+        }
+        line.push_str(&format!(", skeleton {}", info.skeleton));
+        if let Some(registration) = info.registration {
+            line.push_str(&format!(", {}", registration.as_str()));
+        }
+        line
+    };
+    let mut lines = vec![
+        describe(&comparison.first),
+        describe(&comparison.second),
+        format!(
+            "similarity {}, distance {}, skeletons {}{}",
+            comparison.similarity,
+            comparison.distance,
+            if comparison.skeleton_match {
+                "match"
+            } else {
+                "differ"
+            },
+            comparison
+                .visual_similarity
+                .map(|visual| format!(", visual similarity {visual}"))
+                .unwrap_or_default()
+        ),
+    ];
+    for op in &comparison.operations {
+        let confusable = op
+            .confusable
+            .map(|kind| format!(" (confusable, {})", kind.as_str()))
+            .unwrap_or_default();
+        lines.push(format!(
+            "  {} at {}: {:?} -> {:?}{confusable}",
+            op.op.as_str(),
+            op.position,
+            op.from,
+            op.to
+        ));
+    }
+    lines.push(format!("confidence: {}", comparison.confidence.as_str()));
+    lines.extend(
+        comparison
+            .reasons
+            .iter()
+            .map(|reason| format!("  {reason}")),
+    );
+    lines.join("\n")
+}
+
+fn format_compare_csv(comparison: &compare::Comparison) -> Result<String> {
+    let mut writer = csv::WriterBuilder::new().from_writer(vec![]);
+    writer.write_record(["op", "position", "from", "to", "confusable"])?;
+    for op in &comparison.operations {
+        writer.write_record([
+            op.op.as_str(),
+            &op.position.to_string(),
+            &op.from,
+            &op.to,
+            op.confusable.map(|kind| kind.as_str()).unwrap_or(""),
+        ])?;
+    }
+    Ok(String::from_utf8(writer.into_inner()?)?)
+}
+
+fn format_dmarc_text(report: &dmarc::DmarcReport) -> String {
+    let mut lines = vec![format!(
+        "{} reports, {} messages for {}; {} sources flagged",
+        report.reports,
+        report.messages,
+        report.protected.join(", "),
+        report.flagged
+    )];
+    for source in report
+        .sources
+        .iter()
+        .filter(|source| !source.lookalikes.is_empty())
+    {
+        let lookalikes = source
+            .lookalikes
+            .iter()
+            .map(|lookalike| {
+                format!(
+                    "{} {}",
+                    lookalike.domain,
+                    scan::resemblance(&lookalike.lookup)
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("; ");
+        lines.push(format!(
+            "{}{} ({} messages, DMARC {}, From {}): {lookalikes}",
+            if source.flagged { "FLAGGED " } else { "" },
+            source.source_ip,
+            source.count,
+            source.dmarc,
+            source.header_from
+        ));
     }
-    results
+    lines.join("\n")
+}
+
+fn format_dmarc_csv(report: &dmarc::DmarcReport) -> Result<String> {
+    let mut writer = csv::WriterBuilder::new().from_writer(vec![]);
+    writer.write_record([
+        "source_ip",
+        "count",
+        "header_from",
+        "envelope_from",
+        "dmarc",
+        "flagged",
+        "domain",
+        "match",
+        "similarity",
+    ])?;
+    for source in &report.sources {
+        let count = source.count.to_string();
+        let flagged = source.flagged.to_string();
+        let envelope_from = source.envelope_from.as_deref().unwrap_or("");
+        for lookalike in &source.lookalikes {
+            let matches: Vec<(String, String)> = match &lookalike.lookup {
+                crate::types::LookupResponse::Ascii(data) => data
+                    .results
+                    .iter()
+                    .map(|result| (result.domain.clone(), result.similarity.to_string()))
+                    .collect(),
+                crate::types::LookupResponse::Idn(data) => data
+                    .results
+                    .iter()
+                    .map(|result| (result.domain.clone(), String::new()))
+                    .collect(),
+            };
+            for (domain, similarity) in matches {
+                writer.write_record([
+                    source.source_ip.as_str(),
+                    &count,
+                    &source.header_from,
+                    envelope_from,
+                    source.dmarc,
+                    &flagged,
+                    &lookalike.domain,
+                    &domain,
+                    &similarity,
+                ])?;
+            }
+        }
+    }
+    let data = writer.into_inner()?;
+    Ok(String::from_utf8(data)
+        .unwrap_or_default()
+        .trim_end()
+        .to_string())
+}
+
+fn format_eml_text(report: &eml::EmlReport) -> String {
+    let mut lines = vec![format!("Verdict: {}", report.verdict.as_str())];
+    if let Some(subject) = &report.subject {
+        lines.push(format!("Subject: {subject}"));
+    }
+    for check in &report.senders {
+        lines.push(format!(
+            "{}: {} ({})",
+            check.sender.header, check.sender.address, check.sender.domain
+        ));
+    }
+    lines.push(format!("Link hosts: {}", report.links.scanned));
+    lines.extend(report.reasons.iter().map(|reason| format!("- {reason}")));
+    lines.join("\n")
+}
+
+fn format_eml_csv(report: &eml::EmlReport) -> Result<String> {
+    let mut writer = csv::WriterBuilder::new().from_writer(vec![]);
+    writer.write_record(["verdict", "source", "domain", "match", "similarity"])?;
+    let verdict = report.verdict.as_str();
+    let sources = report
+        .senders
+        .iter()
+        .filter_map(|check| {
+            Some((
+                check.sender.header,
+                &check.sender.domain,
+                check.lookup.as_ref()?,
+            ))
+        })
+        .chain(
+            report
+                .links
+                .findings
+                .iter()
+                .map(|finding| ("link", &finding.domain, &finding.lookup)),
+        );
+    let mut rows = 0;
+    for (source, domain, lookup) in sources {
+        match lookup {
+            crate::types::LookupResponse::Ascii(data) => {
+                for result in &data.results {
+                    writer.write_record([
+                        verdict,
+                        source,
+                        domain,
+                        &result.domain,
+                        &result.similarity.to_string(),
+                    ])?;
+                    rows += 1;
+                }
+            }
+            crate::types::LookupResponse::Idn(data) => {
+                for result in &data.results {
+                    writer.write_record([verdict, source, domain, &result.domain, ""])?;
+                    rows += 1;
+                }
+            }
+        }
+    }
+    // A clean message still reports its verdict.
+    if rows == 0 {
+        writer.write_record([verdict, "", "", "", ""])?;
+    }
+    let data = writer.into_inner()?;
+    Ok(String::from_utf8(data)
+        .unwrap_or_default()
+        .trim_end()
+        .to_string())
+}
+
+fn format_scan_text(report: &scan::ScanReport) -> String {
+    if report.findings.is_empty() {
+        return format!("No matches in {} domains", report.scanned);
+    }
+    if !report.brands.is_empty() {
+        return report
+            .brands
+            .iter()
+            .map(|group| {
+                let first_seen = group
+                    .first_seen
+                    .as_ref()
+                    .map(|seen| format!(", first seen {seen}"))
+                    .unwrap_or_default();
+                format!(
+                    "{}: {} lookalikes (max risk {} {}, seen {}x{first_seen}): {}",
+                    group.brand,
+                    group.count,
+                    group.max_risk,
+                    group.severity.as_str(),
+                    group.occurrences,
+                    group.domains.join(", ")
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+    }
+    report
+        .findings
+        .iter()
+        .map(|finding| {
+            let results = match &finding.lookup {
+                crate::types::LookupResponse::Ascii(data) => format_ascii_text(data),
+                crate::types::LookupResponse::Idn(data) => format_idn_text(data),
+            };
+            let clients = if finding.clients.is_empty() {
+                String::new()
+            } else {
+                format!(" by {}", finding.clients.join(", "))
+            };
+            format!(
+                "{} (risk {} {}, seen {}x{clients}): {results}{}{}",
+                finding.domain,
+                finding.risk,
+                finding.severity.as_str(),
+                finding.occurrences,
+                format_passive_dns(finding.passive_dns.as_ref()),
+                format_addresses(&finding.addresses)
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn format_monitor_plan_text(plan: &monitor::MonitorPlan) -> String {
+    let mut lines: Vec<String> = plan
+        .targets
+        .iter()
+        .map(|target| {
+            let techniques = target
+                .techniques
+                .iter()
+                .filter_map(|count| {
+                    let name = count.technique.to_possible_value()?;
+                    Some(format!("{} {}", name.get_name(), count.permutations))
+                })
+                .collect::<Vec<_>>()
+                .join(", ");
+            format!(
+                "{}: {} permutations ({techniques})",
+                target.domain, target.permutations
+            )
+        })
+        .collect();
+    let dataset = if plan.dataset {
+        format!("{} legitimate domains dropped", plan.legitimate)
+    } else {
+        "no local DB, legitimate domains not dropped".to_string()
+    };
+    lines.push(format!("{} squats to check ({dataset})", plan.squats));
+    let pacing = if plan.doh_rate == 0 {
+        "no DoH rate limit".to_string()
+    } else {
+        format!(
+            "at least {}s at {}/s, burst {}",
+            plan.rate_limit_secs, plan.doh_rate, plan.doh_burst
+        )
+    };
+    lines.push(format!(
+        "{} registration checks, {} answered by the cache ({pacing})",
+        plan.registration_checks, plan.cached
+    ));
+    if plan.notifiers.is_empty() {
+        lines.push("No notifiers configured".to_string());
+    }
+    for notifier in &plan.notifiers {
+        let severity = notifier
+            .min_severity
+            .map(|severity| format!(" ({} and above)", severity.as_str()))
+            .unwrap_or_default();
+        lines.push(format!(
+            "Notifies {} {}{severity}",
+            notifier.kind, notifier.target
+        ));
+    }
+    lines.join("\n")
+}
+
+fn format_cohosted_text(report: &cohosted::CohostedReport) -> String {
+    let mut lines = vec![format!(
+        "{} resolves to {}; {} other domains on those addresses",
+        report.domain,
+        report.ips.join(", "),
+        report.cohosted.len()
+    )];
+    if !report.cohosted.is_empty() {
+        lines.push(format_scan_text(&report.scan));
+    }
+    lines.join("\n")
+}
+
+fn format_scan_csv(report: &scan::ScanReport) -> Result<String> {
+    let mut writer = csv::WriterBuilder::new().from_writer(vec![]);
+    if !report.brands.is_empty() {
+        writer.write_record([
+            "brand",
+            "count",
+            "occurrences",
+            "max_risk",
+            "severity",
+            "first_seen",
+            "domains",
+        ])?;
+        for group in &report.brands {
+            writer.write_record([
+                group.brand.as_str(),
+                &group.count.to_string(),
+                &group.occurrences.to_string(),
+                &group.max_risk.to_string(),
+                group.severity.as_str(),
+                group.first_seen.as_deref().unwrap_or(""),
+                &group.domains.join("|"),
+            ])?;
+        }
+        let data = writer.into_inner()?;
+        return Ok(String::from_utf8(data)
+            .unwrap_or_default()
+            .trim_end()
+            .to_string());
+    }
+    writer.write_record([
+        "domain",
+        "occurrences",
+        "clients",
+        "match",
+        "similarity",
+        "mappings",
+        "risk",
+        "severity",
+    ])?;
+    for finding in &report.findings {
+        let severity = finding.severity.as_str();
+        let occurrences = finding.occurrences.to_string();
+        let risk = finding.risk.to_string();
+        let clients = finding.clients.join("|");
+        match &finding.lookup {
+            crate::types::LookupResponse::Ascii(data) => {
+                for result in &data.results {
+                    writer.write_record([
+                        finding.domain.as_str(),
+                        &occurrences,
+                        &clients,
+                        &result.domain,
+                        &result.similarity.to_string(),
+                        "",
+                        &risk,
+                        severity,
+                    ])?;
+                }
+            }
+            crate::types::LookupResponse::Idn(data) => {
+                for result in &data.results {
+                    let mapping_text = result
+                        .mappings
+                        .iter()
+                        .map(|mapping| format!("{}->{}", mapping.ascii, mapping.unicode))
+                        .collect::<Vec<_>>()
+                        .join("|");
+                    writer.write_record([
+                        finding.domain.as_str(),
+                        &occurrences,
+                        &clients,
+                        &result.domain,
+                        "",
+                        &mapping_text,
+                        &risk,
+                        severity,
+                    ])?;
+                }
+            }
+        }
+    }
+    let data = writer.into_inner()?;
+    Ok(String::from_utf8(data)
+        .unwrap_or_default()
+        .trim_end()
+        .to_string())
+}
+
+fn format_ascii_text(data: &AsciiResponse) -> String {
+    let exact = if let Some(brand) = &data.protected_brand {
+        format!("Protected brand: {} belongs to {brand}; ", data.q)
+    } else if data.exact_match {
+        format!("Exact match: {} is a known legitimate domain; ", data.q)
+    } else {
+        String::new()
+    };
+    if data.results.is_empty() {
+        return format!("{exact}No results");
+    }
+    let results = data
+        .results
         .iter()
         .map(|result| {
+            let breakdown = match &result.breakdown {
+                Some(breakdown) => {
+                    let parts = breakdown
+                        .iter()
+                        .map(|part| format!("{} {} x{}", part.scorer, part.similarity, part.weight))
+                        .collect::<Vec<_>>()
+                        .join(", ");
+                    format!(" ({parts})")
+                }
+                None => String::new(),
+            };
             format!(
-                "Domain: {}, Similarity: {}",
+                "Domain: {}, Similarity: {}{breakdown}",
                 result.domain, result.similarity
             )
         })
         .collect::<Vec<_>>()
-        .join("; ")
+        .join("; ");
+    format!(
+        "{exact}{results}{}",
+        format_passive_dns(data.passive_dns.as_ref())
+    )
+}
+
+/// "; hosted on 203.0.113.7 (NL, AS64500 Example VPS)", for `--resolve`.
+fn format_addresses(addresses: &[geoip::Address]) -> String {
+    if addresses.is_empty() {
+        return String::new();
+    }
+    let hosts: Vec<String> = addresses
+        .iter()
+        .map(|address| {
+            let details: Vec<String> = address
+                .country
+                .clone()
+                .into_iter()
+                .chain(address.asn.map(|asn| match &address.org {
+                    Some(org) => format!("AS{asn} {org}"),
+                    None => format!("AS{asn}"),
+                }))
+                .collect();
+            if details.is_empty() {
+                address.ip.clone()
+            } else {
+                format!("{} ({})", address.ip, details.join(", "))
+            }
+        })
+        .collect();
+    format!("; hosted on {}", hosts.join(", "))
+}
+
+/// `; Passive DNS: ...` for a lookup or finding that has a history.
+fn format_passive_dns(history: Option<&types::PassiveDns>) -> String {
+    let Some(history) = history else {
+        return String::new();
+    };
+    let Some(first_seen) = &history.first_seen else {
+        return "; Passive DNS: never seen resolving".to_string();
+    };
+    let date = |timestamp: &str| timestamp.get(..10).unwrap_or(timestamp).to_string();
+    let mut text = format!("; Passive DNS: first seen {}", date(first_seen));
+    if let Some(last_seen) = &history.last_seen {
+        text.push_str(&format!(", last seen {}", date(last_seen)));
+    }
+    if !history.ips.is_empty() {
+        text.push_str(&format!(", resolved to {}", history.ips.join(", ")));
+    }
+    text
 }
 
-fn format_idn_text(results: &[crate::types::IdnResult]) -> String {
-    if results.is_empty() {
+fn format_idn_text(response: &IdnResponse) -> String {
+    if !response.idna_errors.is_empty() {
+        let errors: Vec<String> = response
+            .idna_errors
+            .iter()
+            .map(|error| format!("{} ({})", error.category, error.message))
+            .collect();
+        return format!("Does not decode: {}", errors.join(", "));
+    }
+    if response.results.is_empty() {
         return "No results".to_string();
     }
-    results
+    response
+        .results
         .iter()
         .map(|result| {
             let mapping_text = if result.mappings.is_empty() {
@@ -307,6 +3495,7 @@ fn format_idn_text(results: &[crate::types::IdnResult]) -> String {
         })
         .collect::<Vec<_>>()
         .join("; ")
+        + &format_passive_dns(response.passive_dns.as_ref())
 }
 
 fn format_ascii_csv(results: &[crate::types::AsciiResult]) -> Result<String> {
@@ -324,7 +3513,7 @@ fn format_ascii_csv(results: &[crate::types::AsciiResult]) -> Result<String> {
 
 fn format_idn_csv(results: &[crate::types::IdnResult]) -> Result<String> {
     let mut writer = csv::WriterBuilder::new().from_writer(vec![]);
-    writer.write_record(["domain", "mappings"])?;
+    writer.write_record(["domain", "mappings", "registration_status"])?;
     for result in results {
         let mapping_text = result
             .mappings
@@ -332,7 +3521,11 @@ fn format_idn_csv(results: &[crate::types::IdnResult]) -> Result<String> {
             .map(|mapping| format!("{}->{}", mapping.ascii, mapping.unicode))
             .collect::<Vec<_>>()
             .join("|");
-        writer.write_record([result.domain.as_str(), mapping_text.as_str()])?;
+        let status = result
+            .registration_status
+            .map(|status| status.as_str())
+            .unwrap_or("unchecked");
+        writer.write_record([result.domain.as_str(), mapping_text.as_str(), status])?;
     }
     let data = writer.into_inner()?;
     Ok(String::from_utf8(data)
@@ -341,41 +3534,52 @@ fn format_idn_csv(results: &[crate::types::IdnResult]) -> Result<String> {
         .to_string())
 }
 
+/// The DB of the `--dataset` profile, when one was selected.
+static DATASET_DB: std::sync::OnceLock<String> = std::sync::OnceLock::new();
+
 fn resolve_db_path(db: Option<String>) -> String {
-    if let Some(path) = db {
-        return path;
-    }
-    if let Some(path) = default_db_path() {
+    if let Some(path) = db.or_else(|| DATASET_DB.get().cloned()) {
         return path;
     }
-    if let Ok(exe) = std::env::current_exe() {
-        if let Some(dir) = exe.parent() {
-            return dir.join("spotspoof.sqlite").to_string_lossy().to_string();
-        }
-    }
-    "spotspoof.sqlite".to_string()
+    paths::data_path("spotspoof.sqlite")
 }
 
-fn default_db_path() -> Option<String> {
-    if cfg!(target_os = "windows") {
-        let base = std::env::var_os("LOCALAPPDATA").or_else(|| std::env::var_os("APPDATA"))?;
-        let mut path = std::path::PathBuf::from(base);
-        path.push("spotspoof");
-        path.push("spotspoof.sqlite");
-        return Some(path.to_string_lossy().to_string());
-    }
-    let home = std::env::var_os("HOME")?;
-    let mut path = std::path::PathBuf::from(home);
-    if cfg!(target_os = "macos") {
-        path.push("Library");
-        path.push("Application Support");
-    } else {
-        path.push(".local");
-        path.push("share");
+/// The `[datasets]` of the config as (name, DB path) pairs.
+fn dataset_paths(config: &config::Config) -> BTreeMap<String, String> {
+    config
+        .datasets
+        .iter()
+        .map(|(name, dataset)| (name.clone(), dataset.db.clone()))
+        .collect()
+}
+
+fn parse_duration(value: &str) -> Result<std::time::Duration, String> {
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (digits, unit) = value.split_at(split);
+    let amount: u64 = digits
+        .parse()
+        .map_err(|_| format!("invalid duration '{value}'"))?;
+    let unit_secs = match unit {
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 60 * 60 * 24,
+        _ => {
+            return Err(format!(
+                "invalid duration unit in '{value}' (use s, m, h, or d)"
+            ))
+        }
+    };
+    let seconds = amount
+        .checked_mul(unit_secs)
+        .ok_or_else(|| format!("duration too large: '{value}'"))?;
+    if seconds == 0 {
+        return Err("duration must be greater than zero".to_string());
     }
-    path.push("spotspoof");
-    path.push("spotspoof.sqlite");
-    Some(path.to_string_lossy().to_string())
+    Ok(std::time::Duration::from_secs(seconds))
 }
 
 fn output_format(args: &OutputArgs) -> OutputFormat {
@@ -442,19 +3646,95 @@ mod tests {
             .contains("--log-file is required when --log-destination=file"));
     }
 
+    #[test]
+    fn parse_duration_accepts_units() {
+        assert_eq!(parse_duration("90").unwrap().as_secs(), 90);
+        assert_eq!(parse_duration("30m").unwrap().as_secs(), 1800);
+        assert_eq!(parse_duration("24h").unwrap().as_secs(), 86400);
+        assert_eq!(parse_duration("2d").unwrap().as_secs(), 172800);
+        assert!(parse_duration("0h").is_err());
+        assert!(parse_duration("5w").is_err());
+        assert!(parse_duration("h").is_err());
+        let err = parse_duration("999999999999999999d").unwrap_err();
+        assert!(err.contains("duration too large"), "{err}");
+    }
+
     #[test]
     fn format_ascii_text_output() {
         let payload = OutputData::Ascii(AsciiResponse {
             q: "gooble.com".to_string(),
             ascii: true,
             puny: false,
+            exact_match: false,
+            protected_brand: None,
             results: vec![crate::types::AsciiResult {
                 domain: "google.com".to_string(),
                 similarity: 90,
+                breakdown: None,
             }],
+            dataset: None,
+            total_candidates_considered: None,
+            passive_dns: None,
         });
         let text = format_output(OutputFormat::Text, payload).unwrap();
         assert_eq!(text, "Domain: google.com, Similarity: 90");
+
+        let mut response = ascii_spoof::lookup_most_phished(
+            "gooble.com",
+            &ascii_spoof::AsciiOptions {
+                explain: true,
+                ..Default::default()
+            },
+        );
+        response.results.truncate(1);
+        let text = format_output(OutputFormat::Text, OutputData::Ascii(response)).unwrap();
+        assert_eq!(
+            text,
+            "Domain: google.com, Similarity: 90 (edit_distance 90 x1, confusable 90 x0, phonetic 75 x0, ngram 77 x0, keyboard 95 x0)"
+        );
+
+        let payload = OutputData::Ascii(AsciiResponse {
+            exact_match: true,
+            ..crate::types::empty_ascii_response("google.com")
+        });
+        let text = format_output(OutputFormat::Text, payload).unwrap();
+        assert_eq!(
+            text,
+            "Exact match: google.com is a known legitimate domain; No results"
+        );
+
+        let payload = OutputData::Ascii(ascii_spoof::lookup_most_phished(
+            "accounts.google.com",
+            &ascii_spoof::AsciiOptions::default(),
+        ));
+        let text = format_output(OutputFormat::Text, payload).unwrap();
+        assert_eq!(
+            text,
+            "Protected brand: accounts.google.com belongs to google.com; No results"
+        );
+    }
+
+    #[test]
+    fn format_both_output_keeps_each_analyzer_separate() {
+        let payload = || OutputData::Both {
+            ascii: crate::types::empty_ascii_response("xn--ggle-0nda.com"),
+            idn: IdnResponse {
+                q: "xn--ggle-0nda.com".to_string(),
+                ascii: false,
+                puny: true,
+                results: Vec::new(),
+                dataset: None,
+                total_candidates_considered: None,
+                passive_dns: None,
+                idna_errors: Vec::new(),
+            },
+        };
+        let text = format_output(OutputFormat::Text, payload()).unwrap();
+        assert_eq!(text, "ASCII: No results\nIDN: No results");
+        let json: serde_json::Value =
+            serde_json::from_str(&format_output(OutputFormat::Json, payload()).unwrap()).unwrap();
+        assert_eq!(json["ascii"]["q"], "xn--ggle-0nda.com");
+        assert_eq!(json["idn"]["puny"], true);
     }
 
     #[test]
@@ -466,14 +3746,36 @@ mod tests {
             results: vec![crate::types::IdnResult {
                 domain: "amazon.com".to_string(),
                 is_registered: true,
+                registration_checked: true,
+                registration_status: Some(crate::types::RegistrationStatus::Registered),
                 mappings: vec![crate::types::PunyMapping {
                     ascii: "a".to_string(),
                     unicode: "а".to_string(),
                 }],
             }],
+            dataset: None,
+            total_candidates_considered: None,
+            passive_dns: None,
+            idna_errors: Vec::new(),
         });
         let text = format_output(OutputFormat::Text, payload).unwrap();
         assert_eq!(text, "Domain: amazon.com, Mappings: a -> а");
+
+        let undecodable = OutputData::Idn(IdnResponse {
+            q: "xn--zz.com".to_string(),
+            ascii: false,
+            puny: true,
+            results: Vec::new(),
+            dataset: None,
+            total_candidates_considered: None,
+            passive_dns: None,
+            idna_errors: vec![puny::IdnaError {
+                category: "punycode".to_string(),
+                message: "not valid punycode".to_string(),
+            }],
+        });
+        let text = format_output(OutputFormat::Text, undecodable).unwrap();
+        assert_eq!(text, "Does not decode: punycode (not valid punycode)");
     }
 
     #[test]
@@ -482,16 +3784,23 @@ mod tests {
             q: "gooble.com".to_string(),
             ascii: true,
             puny: false,
+            exact_match: false,
+            protected_brand: None,
             results: vec![
                 crate::types::AsciiResult {
                     domain: "google.com".to_string(),
                     similarity: 90,
+                    breakdown: None,
                 },
                 crate::types::AsciiResult {
                     domain: "g00gle.com".to_string(),
                     similarity: 88,
+                    breakdown: None,
                 },
             ],
+            dataset: None,
+            total_candidates_considered: None,
+            passive_dns: None,
         });
         let csv = format_output(OutputFormat::Csv, payload).unwrap();
         assert_eq!(csv, "domain,similarity\ngoogle.com,90\ng00gle.com,88");
@@ -0,0 +1,271 @@
+/*
+`spotspoof mappings update`: rebuilds the confusable mappings IDN lookups
+expand queries with. Unicode's confusables.txt (UTS #39) is downloaded, its
+single-character lowercase sources that pass for short ASCII strings are kept,
+and they are merged into the hand-curated `data/puny-mappings.json` (curated
+targets keep their order and come first). The result is written as a versioned
+local file that `idn` loads in place of the compiled-in copy.
+*/
+use anyhow::{Context, Result};
+use reqwest::header;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::sync::OnceLock;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::net;
+
+pub const DEFAULT_MAPPINGS_FILE: &str = "spotspoof-mappings.json";
+pub const CONFUSABLES_URL: &str = "https://www.unicode.org/Public/security/latest/confusables.txt";
+/// The layout of `MappingFile`; files of another version are refused.
+const FORMAT_VERSION: u32 = 1;
+/// Longest ASCII string a confusable may stand for (`æ` → `ae`).
+const MAX_TARGET_LEN: usize = 2;
+
+static INSTALLED: OnceLock<MappingFile> = OnceLock::new();
+
+/// Unicode character to the ASCII strings it passes for.
+pub type Mappings = BTreeMap<String, Vec<String>>;
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct MappingFile {
+    pub format_version: u32,
+    /// The confusables.txt version merged in, e.g. `15.1.0`.
+    pub unicode_version: Option<String>,
+    pub source: String,
+    /// Unix seconds.
+    pub generated_at: i64,
+    pub mappings: Mappings,
+}
+
+impl MappingFile {
+    pub fn load(path: &str) -> Result<Self> {
+        let text = fs::read_to_string(path).with_context(|| format!("cannot read {path}"))?;
+        let file: MappingFile =
+            serde_json::from_str(&text).with_context(|| format!("invalid mappings file {path}"))?;
+        if file.format_version != FORMAT_VERSION {
+            anyhow::bail!(
+                "{path} has mappings format {}, expected {FORMAT_VERSION}",
+                file.format_version
+            );
+        }
+        Ok(file)
+    }
+
+    /// Writes the file next to `path` first, so readers never see it half
+    /// written.
+    pub fn write(&self, path: &str) -> Result<()> {
+        let tmp_path = format!("{path}.tmp");
+        fs::write(&tmp_path, serde_json::to_string_pretty(self)?)?;
+        fs::rename(tmp_path, path)?;
+        Ok(())
+    }
+}
+
+/// What `update` wrote.
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct UpdateSummary {
+    pub path: String,
+    pub unicode_version: Option<String>,
+    pub entries: usize,
+    /// Characters confusables.txt added to the curated mappings.
+    pub added: usize,
+}
+
+/// Makes `file` the mappings IDN lookups use. Only the first call takes
+/// effect, and only before the first lookup.
+pub fn install(file: MappingFile) {
+    let _ = INSTALLED.set(file);
+}
+
+pub fn installed() -> Option<&'static MappingFile> {
+    INSTALLED.get()
+}
+
+/// The hand-curated mappings compiled into the binary.
+pub fn bundled() -> Mappings {
+    serde_json::from_str(include_str!("../data/puny-mappings.json"))
+        .expect("puny-mappings.json must be valid JSON")
+}
+
+/// The installed mappings, or the compiled-in ones.
+pub fn active() -> HashMap<String, Vec<String>> {
+    match installed() {
+        Some(file) => file.mappings.clone().into_iter().collect(),
+        None => bundled().into_iter().collect(),
+    }
+}
+
+/// Downloads confusables.txt from `url`, merges it into the curated
+/// mappings, and writes the result to `path`.
+pub fn update(url: &str, path: &str) -> Result<UpdateSummary> {
+    net::ensure_online("Updating the confusable mappings")?;
+    let client = net::client_builder().build()?;
+    let response = net::send_with_retry("mappings", || {
+        client.get(url).header(
+            header::USER_AGENT,
+            concat!("spotspoof/", env!("CARGO_PKG_VERSION")),
+        )
+    })?;
+    if !response.status().is_success() {
+        anyhow::bail!("download of {url} failed: HTTP {}", response.status());
+    }
+    let (unicode_version, confusables) = parse_confusables(&response.text()?)?;
+    let curated = bundled();
+    let mappings = merge(&curated, &confusables);
+    let file = MappingFile {
+        format_version: FORMAT_VERSION,
+        unicode_version,
+        source: url.to_string(),
+        generated_at: now(),
+        mappings,
+    };
+    file.write(path)?;
+    Ok(UpdateSummary {
+        path: path.to_string(),
+        unicode_version: file.unicode_version,
+        entries: file.mappings.len(),
+        added: file.mappings.len() - curated.len(),
+    })
+}
+
+/// The `# Version:` of a confusables.txt and the mappings it yields.
+fn parse_confusables(text: &str) -> Result<(Option<String>, Mappings)> {
+    let mut version = None;
+    let mut mappings = Mappings::new();
+    for line in text.lines() {
+        let line = line.trim_start_matches('\u{FEFF}');
+        if let Some(rest) = line.strip_prefix("# Version:") {
+            version = Some(rest.trim().to_string());
+            continue;
+        }
+        let data = line.split('#').next().unwrap_or_default().trim();
+        if data.is_empty() {
+            continue;
+        }
+        let mut fields = data.split(';').map(str::trim);
+        let (Some(source), Some(target)) = (fields.next(), fields.next()) else {
+            anyhow::bail!("malformed confusables line: {line}");
+        };
+        let source = code_points(source).with_context(|| format!("in line: {line}"))?;
+        let target = code_points(target).with_context(|| format!("in line: {line}"))?;
+        let mut chars = source.chars();
+        let (Some(unicode), None) = (chars.next(), chars.next()) else {
+            continue;
+        };
+        // IDNA lowercases names before lookup, and ASCII needs no mapping.
+        if unicode.is_ascii() || !unicode.to_lowercase().eq(std::iter::once(unicode)) {
+            continue;
+        }
+        let target = target.to_ascii_lowercase();
+        let ascii = target
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-');
+        if !ascii || target.is_empty() || target.len() > MAX_TARGET_LEN {
+            continue;
+        }
+        let targets = mappings.entry(source).or_default();
+        if !targets.contains(&target) {
+            targets.push(target);
+        }
+    }
+    if mappings.is_empty() {
+        anyhow::bail!("no confusables found; is this confusables.txt?");
+    }
+    Ok((version, mappings))
+}
+
+/// Space-separated hex code points (`0061 0301`) as a string.
+fn code_points(field: &str) -> Result<String> {
+    field
+        .split_whitespace()
+        .map(|hex| {
+            u32::from_str_radix(hex, 16)
+                .ok()
+                .and_then(char::from_u32)
+                .ok_or_else(|| anyhow::anyhow!("invalid code point {hex}"))
+        })
+        .collect()
+}
+
+/// `curated` with the targets of `confusables` appended where missing.
+fn merge(curated: &Mappings, confusables: &Mappings) -> Mappings {
+    let mut merged = curated.clone();
+    for (unicode, targets) in confusables {
+        let entry = merged.entry(unicode.clone()).or_default();
+        for target in targets {
+            if !entry.contains(target) {
+                entry.push(target.clone());
+            }
+        }
+    }
+    merged
+}
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs() as i64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE: &str = "\u{FEFF}# confusables.txt
+# Date: 2023-08-11, 17:46:40 GMT
+# Version: 15.1.0
+
+0430 ;\t0061 ;\tMA\t# ( а → a ) CYRILLIC SMALL LETTER A → LATIN SMALL LETTER A
+0410 ;\t0041 ;\tMA\t# ( А → A ) CYRILLIC CAPITAL LETTER A → LATIN CAPITAL LETTER A
+0261 ;\t0067 ;\tMA\t# ( ɡ → g ) LATIN SMALL LETTER SCRIPT G → LATIN SMALL LETTER G
+00E6 ;\t0061 0065 ;\tMA\t# ( æ → ae )
+0031 ;\t006C ;\tMA\t# ( 1 → l ) DIGIT ONE → LATIN SMALL LETTER L
+2488 ;\t0031 002E ;\tMA\t# ( ⒈ → 1. )
+FB03 ;\t0066 0066 0069 ;\tMA\t# ( ﬃ → ffi )
+";
+
+    #[test]
+    fn parses_lowercase_ascii_confusables() {
+        let (version, mappings) = parse_confusables(SAMPLE).unwrap();
+        assert_eq!(version.as_deref(), Some("15.1.0"));
+        let keys: Vec<&str> = mappings.keys().map(String::as_str).collect();
+        assert_eq!(keys, ["æ", "ɡ", "а"]);
+        assert_eq!(mappings["æ"], ["ae"]);
+        assert!(parse_confusables("# Version: 1.0\n").is_err());
+        assert!(parse_confusables("zz ;\t0061 ;\tMA\n").is_err());
+    }
+
+    #[test]
+    fn merge_keeps_curated_targets_first() {
+        let (_, confusables) = parse_confusables(SAMPLE).unwrap();
+        let curated = bundled();
+        let merged = merge(&curated, &confusables);
+        assert_eq!(merged["æ"], ["a", "e", "ae"]);
+        assert_eq!(merged["ɡ"], ["g"]);
+        assert!(merged.len() >= curated.len());
+
+        let mut path = std::env::temp_dir();
+        path.push(format!("spotspoof-mappings-{}.json", std::process::id()));
+        let path = path.to_string_lossy().to_string();
+        let file = MappingFile {
+            format_version: FORMAT_VERSION,
+            unicode_version: Some("15.1.0".to_string()),
+            source: CONFUSABLES_URL.to_string(),
+            generated_at: 0,
+            mappings: merged,
+        };
+        file.write(&path).unwrap();
+        assert_eq!(MappingFile::load(&path).unwrap(), file);
+        MappingFile {
+            format_version: FORMAT_VERSION + 1,
+            ..file
+        }
+        .write(&path)
+        .unwrap();
+        assert!(MappingFile::load(&path).is_err());
+        let _ = fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,261 @@
+/*
+Model Context Protocol server over stdio. Exposes the lookup engines as MCP
+tools (newline-delimited JSON-RPC 2.0) with input schemas generated from the
+same utoipa definitions used for the HTTP API.
+*/
+use anyhow::Result;
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::io::{BufRead, Write};
+use utoipa::ToSchema;
+
+use crate::ascii_spoof;
+use crate::history;
+use crate::http::LookupOverrides;
+use crate::idn;
+use crate::release;
+use crate::types::{LookupRequest, LookupResponse};
+
+const PROTOCOL_VERSION: &str = "2024-11-05";
+const PARSE_ERROR: i64 = -32700;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+
+#[derive(Clone, Copy)]
+enum Tool {
+    Lookup,
+    Ascii,
+    Idn,
+}
+
+impl Tool {
+    const ALL: [Tool; 3] = [Tool::Lookup, Tool::Ascii, Tool::Idn];
+
+    fn name(self) -> &'static str {
+        match self {
+            Tool::Lookup => "lookup",
+            Tool::Ascii => "ascii_lookup",
+            Tool::Idn => "idn_lookup",
+        }
+    }
+
+    fn description(self) -> &'static str {
+        match self {
+            Tool::Lookup => {
+                "Detect lookalike/spoofed domains, auto-selecting ASCII or IDN analysis"
+            }
+            Tool::Ascii => "Find legitimate domains that an ASCII domain imitates (typosquatting)",
+            Tool::Idn => {
+                "Decode an IDN/punycode domain and list registered ASCII targets it imitates"
+            }
+        }
+    }
+}
+
+pub struct McpServer {
+    db_path: String,
+    use_db: bool,
+    datasets: BTreeMap<String, String>,
+}
+
+impl McpServer {
+    pub fn new(db_path: String, use_db: bool) -> Self {
+        Self {
+            db_path,
+            use_db,
+            datasets: BTreeMap::new(),
+        }
+    }
+
+    /// Named datasets (name, DB path) tools can select with `dataset`.
+    pub fn with_datasets(self, datasets: BTreeMap<String, String>) -> Self {
+        Self { datasets, ..self }
+    }
+
+    pub fn run(&self) -> Result<()> {
+        let stdin = std::io::stdin();
+        let mut stdout = std::io::stdout().lock();
+        for line in stdin.lock().lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            if let Some(reply) = self.handle_line(&line) {
+                writeln!(stdout, "{reply}")?;
+                stdout.flush()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the reply for one JSON-RPC message; notifications get none.
+    fn handle_line(&self, line: &str) -> Option<Value> {
+        let message: Value = match serde_json::from_str(line) {
+            Ok(message) => message,
+            Err(err) => return Some(rpc_error(Value::Null, PARSE_ERROR, err.to_string())),
+        };
+        let id = message.get("id").cloned()?;
+        let method = message["method"].as_str().unwrap_or_default();
+
+        let result = match method {
+            "initialize" => Ok(json!({
+                "protocolVersion": PROTOCOL_VERSION,
+                "capabilities": { "tools": {} },
+                "serverInfo": { "name": "spotspoof", "version": env!("CARGO_PKG_VERSION") }
+            })),
+            "ping" => Ok(json!({})),
+            "tools/list" => Ok(json!({ "tools": tool_definitions() })),
+            "tools/call" => self.call_tool(&message["params"]),
+            _ => Err((METHOD_NOT_FOUND, format!("Method not found: {method}"))),
+        };
+
+        Some(match result {
+            Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+            Err((code, message)) => rpc_error(id, code, message),
+        })
+    }
+
+    fn call_tool(&self, params: &Value) -> Result<Value, (i64, String)> {
+        let name = params["name"].as_str().unwrap_or_default();
+        let tool = Tool::ALL
+            .into_iter()
+            .find(|tool| tool.name() == name)
+            .ok_or_else(|| (INVALID_PARAMS, format!("Unknown tool: {name}")))?;
+        let request: LookupRequest = serde_json::from_value(params["arguments"].clone())
+            .map_err(|err| (INVALID_PARAMS, err.to_string()))?;
+        let overrides =
+            LookupOverrides::from_request(&request).map_err(|message| (INVALID_PARAMS, message))?;
+        if let Some(name) = &overrides.dataset {
+            if !self.datasets.contains_key(name) {
+                return Err((INVALID_PARAMS, format!("unknown dataset {name}")));
+            }
+        }
+
+        Ok(match self.run_tool(tool, &request.domain, &overrides) {
+            Ok(response) => json!({
+                "content": [{ "type": "text", "text": serde_json::to_string(&response).unwrap_or_default() }],
+                "isError": false
+            }),
+            Err(err) => json!({
+                "content": [{ "type": "text", "text": err.to_string() }],
+                "isError": true
+            }),
+        })
+    }
+
+    fn run_tool(
+        &self,
+        tool: Tool,
+        domain: &str,
+        overrides: &LookupOverrides,
+    ) -> Result<LookupResponse> {
+        let domain = crate::types::normalize_domain_input(domain);
+        let is_idn = match tool {
+            Tool::Lookup => crate::types::is_idn(&domain),
+            Tool::Ascii => false,
+            Tool::Idn => true,
+        };
+        let response = if is_idn {
+            LookupResponse::Idn(idn::lookup_idn_with_options(&domain, &overrides.idn)?)
+        } else if self.use_db {
+            let db_path = overrides
+                .dataset
+                .as_ref()
+                .and_then(|name| self.datasets.get(name))
+                .unwrap_or(&self.db_path);
+            let mut response =
+                ascii_spoof::lookup_ascii_with_options(&domain, db_path, &overrides.ascii)?;
+            response.dataset = release::dataset_info(db_path)?;
+            LookupResponse::Ascii(response)
+        } else {
+            LookupResponse::Ascii(crate::types::empty_ascii_response(&domain))
+        };
+        crate::types::validate_lookup_response(&response)?;
+        match &response {
+            LookupResponse::Ascii(data) => history::record_ascii(history::Source::Mcp, data),
+            LookupResponse::Idn(data) => history::record_idn(history::Source::Mcp, data),
+        }
+        Ok(response)
+    }
+}
+
+fn tool_definitions() -> Vec<Value> {
+    let (_, schema) = LookupRequest::schema();
+    let input_schema = serde_json::to_value(schema).unwrap_or_default();
+    Tool::ALL
+        .into_iter()
+        .map(|tool| {
+            json!({
+                "name": tool.name(),
+                "description": tool.description(),
+                "inputSchema": input_schema
+            })
+        })
+        .collect()
+}
+
+fn rpc_error(id: Value, code: i64, message: String) -> Value {
+    json!({ "jsonrpc": "2.0", "id": id, "error": { "code": code, "message": message } })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn server() -> McpServer {
+        McpServer::new("unused.sqlite".to_string(), false)
+    }
+
+    #[test]
+    fn initialize_and_list_tools() {
+        let server = server();
+        let reply = server
+            .handle_line(r#"{"jsonrpc":"2.0","id":1,"method":"initialize","params":{}}"#)
+            .unwrap();
+        assert_eq!(reply["result"]["protocolVersion"], PROTOCOL_VERSION);
+
+        assert!(server
+            .handle_line(r#"{"jsonrpc":"2.0","method":"notifications/initialized"}"#)
+            .is_none());
+
+        let reply = server
+            .handle_line(r#"{"jsonrpc":"2.0","id":2,"method":"tools/list"}"#)
+            .unwrap();
+        let tools = reply["result"]["tools"].as_array().unwrap();
+        assert_eq!(tools.len(), 3);
+        let schema = &tools[0]["inputSchema"];
+        assert_eq!(schema["type"], "object");
+        assert_eq!(schema["required"], json!(["domain"]));
+        assert!(schema["properties"]["algorithm"].get("$ref").is_none());
+    }
+
+    #[test]
+    fn call_tool_returns_lookup_json() {
+        let reply = server()
+            .handle_line(
+                r#"{"jsonrpc":"2.0","id":3,"method":"tools/call","params":{"name":"ascii_lookup","arguments":{"domain":"https://www.gooble.com/x"}}}"#,
+            )
+            .unwrap();
+        assert_eq!(reply["result"]["isError"], false);
+        let text = reply["result"]["content"][0]["text"].as_str().unwrap();
+        let response: Value = serde_json::from_str(text).unwrap();
+        assert_eq!(response["q"], "gooble.com");
+    }
+
+    #[test]
+    fn invalid_calls_return_rpc_errors() {
+        let server = server();
+        let reply = server
+            .handle_line(r#"{"jsonrpc":"2.0","id":4,"method":"tools/call","params":{"name":"nope","arguments":{}}}"#)
+            .unwrap();
+        assert_eq!(reply["error"]["code"], INVALID_PARAMS);
+
+        let reply = server
+            .handle_line(r#"{"jsonrpc":"2.0","id":5,"method":"resources/list"}"#)
+            .unwrap();
+        assert_eq!(reply["error"]["code"], METHOD_NOT_FOUND);
+
+        let reply = server.handle_line("{not json").unwrap();
+        assert_eq!(reply["error"]["code"], PARSE_ERROR);
+    }
+}
@@ -0,0 +1,444 @@
+/*
+Inline mail filtering over the Sendmail/Postfix milter protocol (`spotspoof
+milter`). Each MTA connection gets its own thread and scanner. The envelope
+sender, headers, and body of every message are collected, reassembled, and put
+through the same checks as `scan-eml`. At end of message the filter stamps an
+`X-SpotSpoof` header with the verdict and score, or rejects the message when the
+score reaches `--reject-at`. Lookup errors never block mail; the message is
+accepted and the error logged.
+*/
+use anyhow::Result;
+use std::io::{BufReader, Read, Write};
+use std::sync::Arc;
+use tracing::{info, warn};
+
+use crate::eml;
+use crate::scan::Scanner;
+
+pub const HEADER: &str = "X-SpotSpoof";
+const PROTOCOL_VERSION: u32 = 6;
+/// Add headers, change (here: remove) headers.
+const SMFIF_ADDHDRS: u32 = 0x01;
+const SMFIF_CHGHDRS: u32 = 0x10;
+/// Steps the filter asks the MTA to skip: connect, HELO, RCPT, unknown, DATA.
+const SKIPPED_STEPS: u32 = 0x01 | 0x02 | 0x08 | 0x100 | 0x200;
+/// Larger packets are a protocol error (the MTA sends body chunks of 64 KiB).
+const MAX_PACKET: usize = 1 << 20;
+/// Body bytes kept per message; links past this are not scanned.
+const MAX_BODY: usize = 4 << 20;
+/// Header bytes and headers kept per message; past either, the message is
+/// tempfailed rather than held in memory.
+const MAX_HEADER_BYTES: usize = 1 << 20;
+const MAX_HEADERS: usize = 1000;
+const MAX_REASONS: usize = 3;
+
+/// When to mark and when to reject.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MilterConfig {
+    /// Minimum score for the header to be added (0 adds it to every message).
+    pub header_at: u8,
+    /// Minimum score of a suspicious message to reject it.
+    pub reject_at: Option<u8>,
+}
+
+/// Listens on a milter socket spec (`inet:PORT[@HOST]`, `unix:PATH`) and
+/// filters each connection on its own thread with a scanner from `scanner`.
+pub fn serve<F>(listen: &str, config: MilterConfig, scanner: F) -> Result<()>
+where
+    F: Fn() -> Result<Scanner> + Send + Sync + 'static,
+{
+    let scanner = Arc::new(scanner);
+    let spawn = |stream: Box<dyn Stream>, peer: String| {
+        let scanner = Arc::clone(&scanner);
+        std::thread::spawn(move || {
+            let result = scanner().and_then(|scanner| Session::new(stream, &scanner, config).run());
+            if let Err(err) = result {
+                warn!(kind = "milter", peer = %peer, "connection failed: {err}");
+            }
+        });
+    };
+    if let Some(spec) = listen.strip_prefix("inet:") {
+        let (port, host) = spec.split_once('@').unwrap_or((spec, "0.0.0.0"));
+        let listener = std::net::TcpListener::bind((host, port.parse::<u16>()?))?;
+        info!(kind = "milter", listen = %listener.local_addr()?, "listening");
+        for stream in listener.incoming() {
+            let stream = stream?;
+            let peer = stream.peer_addr().map(|addr| addr.to_string());
+            spawn(Box::new(stream), peer.unwrap_or_default());
+        }
+        return Ok(());
+    }
+    #[cfg(unix)]
+    if let Some(path) = listen
+        .strip_prefix("unix:")
+        .or_else(|| listen.strip_prefix("local:"))
+    {
+        use std::os::unix::fs::FileTypeExt;
+        // A socket left behind by an earlier run would make bind fail.
+        if std::fs::symlink_metadata(path).is_ok_and(|meta| meta.file_type().is_socket()) {
+            std::fs::remove_file(path)?;
+        }
+        let listener = std::os::unix::net::UnixListener::bind(path)?;
+        info!(kind = "milter", listen = %path, "listening");
+        for stream in listener.incoming() {
+            spawn(Box::new(stream?), path.to_string());
+        }
+        return Ok(());
+    }
+    Err(anyhow::anyhow!(
+        "unsupported milter socket {listen}; use inet:PORT[@HOST] or unix:PATH"
+    ))
+}
+
+trait Stream: Read + Write + Send {}
+impl<T: Read + Write + Send> Stream for T {}
+
+/// One MTA connection, which may carry many messages.
+struct Session<'a, S: Read + Write> {
+    stream: BufReader<S>,
+    scanner: &'a Scanner,
+    config: MilterConfig,
+    message: Message,
+}
+
+#[derive(Default)]
+struct Message {
+    queue_id: Option<String>,
+    mail_from: Option<String>,
+    headers: Vec<u8>,
+    header_count: usize,
+    /// `X-SpotSpoof` headers the sender added, removed before ours goes in.
+    forged_headers: u32,
+    body: Vec<u8>,
+}
+
+/// The decision at end of message.
+#[derive(Debug, PartialEq, Eq)]
+enum Action {
+    Continue { header: Option<String> },
+    Reject { reason: String },
+}
+
+impl<'a, S: Read + Write> Session<'a, S> {
+    fn new(stream: S, scanner: &'a Scanner, config: MilterConfig) -> Self {
+        Self {
+            stream: BufReader::new(stream),
+            scanner,
+            config,
+            message: Message::default(),
+        }
+    }
+
+    fn run(&mut self) -> Result<()> {
+        while let Some((command, data)) = self.read_packet()? {
+            match command {
+                b'O' => {
+                    let field = |idx: usize| -> u32 {
+                        data.get(idx * 4..idx * 4 + 4)
+                            .map_or(0, |bytes| u32::from_be_bytes(bytes.try_into().unwrap()))
+                    };
+                    let mut reply = PROTOCOL_VERSION.min(field(0)).to_be_bytes().to_vec();
+                    reply.extend_from_slice(
+                        &(field(1) & (SMFIF_ADDHDRS | SMFIF_CHGHDRS)).to_be_bytes(),
+                    );
+                    reply.extend_from_slice(&(field(2) & SKIPPED_STEPS).to_be_bytes());
+                    self.write_packet(b'O', &reply)?;
+                }
+                b'D' => {
+                    let mut fields = data.get(1..).unwrap_or_default().split(|byte| *byte == 0);
+                    while let (Some(name), Some(value)) = (fields.next(), fields.next()) {
+                        if name == b"i" || name == b"{i}" {
+                            self.message.queue_id =
+                                Some(String::from_utf8_lossy(value).into_owned());
+                        }
+                    }
+                }
+                b'M' => {
+                    let sender = data.split(|byte| *byte == 0).next().unwrap_or_default();
+                    let sender = String::from_utf8_lossy(sender);
+                    self.message.mail_from = Some(sender.trim_matches(['<', '>']).to_string());
+                    self.write_packet(b'c', &[])?;
+                }
+                b'L' => {
+                    let mut fields = data.split(|byte| *byte == 0);
+                    let name = fields.next().unwrap_or_default();
+                    let value = fields.next().unwrap_or_default();
+                    let message = &self.message;
+                    if message.header_count >= MAX_HEADERS
+                        || message.headers.len() + name.len() + value.len() + 4 > MAX_HEADER_BYTES
+                    {
+                        let queue_id = message.queue_id.clone().unwrap_or_default();
+                        warn!(
+                            kind = "milter",
+                            queue_id = %queue_id,
+                            "message tempfailed: headers over the limit"
+                        );
+                        self.message = Message::default();
+                        self.write_packet(b't', &[])?;
+                        continue;
+                    }
+                    self.message.header_count += 1;
+                    if name.eq_ignore_ascii_case(HEADER.as_bytes()) {
+                        self.message.forged_headers += 1;
+                    }
+                    let headers = &mut self.message.headers;
+                    headers.extend_from_slice(name);
+                    headers.extend_from_slice(b": ");
+                    headers.extend_from_slice(value);
+                    headers.extend_from_slice(b"\r\n");
+                    self.write_packet(b'c', &[])?;
+                }
+                b'B' => {
+                    let room = MAX_BODY.saturating_sub(self.message.body.len());
+                    self.message
+                        .body
+                        .extend_from_slice(&data[..data.len().min(room)]);
+                    self.write_packet(b'c', &[])?;
+                }
+                b'E' => {
+                    let message = std::mem::take(&mut self.message);
+                    self.end_of_message(message)?;
+                }
+                // Abort and new-connection reset the message; neither is answered.
+                b'A' | b'K' => self.message = Message::default(),
+                b'Q' => break,
+                _ => self.write_packet(b'c', &[])?,
+            }
+        }
+        Ok(())
+    }
+
+    fn end_of_message(&mut self, message: Message) -> Result<()> {
+        let queue_id = message.queue_id.clone().unwrap_or_default();
+        for index in 1..=message.forged_headers {
+            let mut change = index.to_be_bytes().to_vec();
+            change.extend_from_slice(HEADER.as_bytes());
+            change.extend_from_slice(b"\0\0");
+            self.write_packet(b'm', &change)?;
+        }
+        let action = match evaluate(&message, self.scanner, self.config) {
+            Ok(action) => action,
+            Err(err) => {
+                warn!(kind = "milter", queue_id = %queue_id, "message accepted unchecked: {err}");
+                Action::Continue { header: None }
+            }
+        };
+        match action {
+            Action::Continue { header } => {
+                if let Some(header) = header {
+                    info!(kind = "milter", queue_id = %queue_id, verdict = %header);
+                    let mut add = HEADER.as_bytes().to_vec();
+                    add.push(0);
+                    add.extend_from_slice(header.as_bytes());
+                    add.push(0);
+                    self.write_packet(b'h', &add)?;
+                }
+                self.write_packet(b'c', &[])
+            }
+            Action::Reject { reason } => {
+                info!(kind = "milter", queue_id = %queue_id, "rejected: {reason}");
+                let mut reply = format!("550 5.7.1 {reason}").into_bytes();
+                reply.push(0);
+                self.write_packet(b'y', &reply)
+            }
+        }
+    }
+
+    /// The next packet, or `None` when the MTA hung up.
+    fn read_packet(&mut self) -> Result<Option<(u8, Vec<u8>)>> {
+        let mut len = [0; 4];
+        match self.stream.read_exact(&mut len) {
+            Ok(()) => {}
+            Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(err) => return Err(err.into()),
+        }
+        let len = u32::from_be_bytes(len) as usize;
+        if len == 0 || len > MAX_PACKET {
+            return Err(anyhow::anyhow!("invalid milter packet length {len}"));
+        }
+        let mut packet = vec![0; len];
+        self.stream.read_exact(&mut packet)?;
+        let data = packet.split_off(1);
+        Ok(Some((packet[0], data)))
+    }
+
+    fn write_packet(&mut self, command: u8, data: &[u8]) -> Result<()> {
+        let stream = self.stream.get_mut();
+        stream.write_all(&(data.len() as u32 + 1).to_be_bytes())?;
+        stream.write_all(&[command])?;
+        stream.write_all(data)?;
+        stream.flush()?;
+        Ok(())
+    }
+}
+
+fn evaluate(message: &Message, scanner: &Scanner, config: MilterConfig) -> Result<Action> {
+    let mut raw = Vec::new();
+    if let Some(sender) = &message.mail_from {
+        raw.extend_from_slice(format!("Return-Path: <{sender}>\r\n").as_bytes());
+    }
+    raw.extend_from_slice(&message.headers);
+    raw.extend_from_slice(b"\r\n");
+    raw.extend_from_slice(&message.body);
+    let report = eml::check(eml::parse(&raw)?, scanner)?;
+    let score = report.score();
+    let reasons = report
+        .reasons
+        .iter()
+        .take(MAX_REASONS)
+        .map(|reason| reason.replace(['\r', '\n', ';'], " "))
+        .collect::<Vec<_>>();
+    if report.verdict == eml::Verdict::Suspicious
+        && config.reject_at.is_some_and(|reject_at| score >= reject_at)
+    {
+        return Ok(Action::Reject {
+            reason: format!("Message rejected: {}", reasons.join(", ")),
+        });
+    }
+    if score < config.header_at {
+        return Ok(Action::Continue { header: None });
+    }
+    let mut header = format!("{}; score={score}", report.verdict.as_str());
+    for reason in reasons {
+        header.push_str("; ");
+        header.push_str(&reason);
+    }
+    Ok(Action::Continue {
+        header: Some(header),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ascii_spoof::AsciiOptions;
+    use std::io::Cursor;
+
+    /// Scripted MTA input; the filter's replies are collected.
+    struct Mta {
+        input: Cursor<Vec<u8>>,
+        output: Vec<u8>,
+    }
+
+    impl Read for Mta {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            self.input.read(buf)
+        }
+    }
+
+    impl Write for Mta {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.output.write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn packet(command: u8, data: &[u8]) -> Vec<u8> {
+        let mut packet = (data.len() as u32 + 1).to_be_bytes().to_vec();
+        packet.push(command);
+        packet.extend_from_slice(data);
+        packet
+    }
+
+    fn replies(mut output: &[u8]) -> Vec<(u8, Vec<u8>)> {
+        let mut replies = Vec::new();
+        while output.len() >= 5 {
+            let len = u32::from_be_bytes(output[..4].try_into().unwrap()) as usize;
+            replies.push((output[4], output[5..4 + len].to_vec()));
+            output = &output[4 + len..];
+        }
+        replies
+    }
+
+    fn filter(from: &str, config: MilterConfig) -> Vec<(u8, Vec<u8>)> {
+        let mut optneg = 6u32.to_be_bytes().to_vec();
+        optneg.extend_from_slice(&0x1ffu32.to_be_bytes());
+        optneg.extend_from_slice(&0x1fffffu32.to_be_bytes());
+        let mut input = packet(b'O', &optneg);
+        input.extend(packet(b'D', b"Ei\0ABC123\0"));
+        input.extend(packet(b'M', format!("<bounce@{from}>\0").as_bytes()));
+        input.extend(packet(
+            b'L',
+            format!("From\0Support <help@{from}>\0").as_bytes(),
+        ));
+        input.extend(packet(b'L', b"X-SpotSpoof\0clean; score=0\0"));
+        input.extend(packet(b'N', b""));
+        input.extend(packet(b'B', b"Sign in at https://example.org/\r\n"));
+        input.extend(packet(b'E', b""));
+        input.extend(packet(b'Q', b""));
+        session_replies(input, config)
+    }
+
+    fn session_replies(input: Vec<u8>, config: MilterConfig) -> Vec<(u8, Vec<u8>)> {
+        let mta = Mta {
+            input: Cursor::new(input),
+            output: Vec::new(),
+        };
+        let scanner = Scanner::new(None, AsciiOptions::default(), None)
+            .with_targets(vec!["paypal.com".to_string()]);
+        let mut session = Session::new(mta, &scanner, config);
+        session.run().unwrap();
+        replies(&session.stream.into_inner().output)
+    }
+
+    #[test]
+    fn lookalike_senders_are_marked_and_forged_headers_removed() {
+        let replies = filter("paypa1.com", MilterConfig::default());
+        let commands: Vec<u8> = replies.iter().map(|(command, _)| *command).collect();
+        // Negotiation, MAIL, two headers, EOH, body, then the EOB actions.
+        assert_eq!(commands, b"Occcccmhc");
+        assert_eq!(replies[0].1[4..], [0, 0, 0, 0x11, 0, 0, 0x03, 0x0b]);
+        assert_eq!(replies[6].1, b"\0\0\0\x01X-SpotSpoof\0\0");
+        let header = String::from_utf8(replies[7].1.clone()).unwrap();
+        assert!(
+            header.starts_with(
+                "X-SpotSpoof\0suspicious; score=90; From domain paypa1.com resembles paypal.com"
+            ),
+            "{header}"
+        );
+    }
+
+    #[test]
+    fn thresholds_reject_or_skip_the_header() {
+        let config = MilterConfig {
+            header_at: 50,
+            reject_at: Some(85),
+        };
+        let replies = filter("paypa1.com", config);
+        let (command, reply) = replies.last().unwrap();
+        assert_eq!(*command, b'y');
+        assert!(String::from_utf8_lossy(reply)
+            .starts_with("550 5.7.1 Message rejected: From domain paypa1.com"));
+
+        // Below --header-at, clean mail passes untouched.
+        let replies = filter("example.org", config);
+        let commands: Vec<u8> = replies.iter().map(|(command, _)| *command).collect();
+        assert_eq!(commands, b"Occcccmc");
+    }
+
+    #[test]
+    fn header_floods_are_tempfailed() {
+        let mut input = packet(b'M', b"<bounce@example.org>\0");
+        for _ in 0..=MAX_HEADERS {
+            input.extend(packet(b'L', b"X-Filler\0x\0"));
+        }
+        input.extend(packet(b'Q', b""));
+        let replies = session_replies(input, MilterConfig::default());
+        assert_eq!(replies.len(), MAX_HEADERS + 2);
+        assert_eq!(replies.last().unwrap().0, b't');
+
+        let value = vec![b'x'; MAX_PACKET - 16];
+        let mut input = Vec::new();
+        for _ in 0..MAX_HEADER_BYTES / value.len() + 1 {
+            let mut header = b"X-Filler\0".to_vec();
+            header.extend_from_slice(&value);
+            header.push(0);
+            input.extend(packet(b'L', &header));
+        }
+        let replies = session_replies(input, MilterConfig::default());
+        assert_eq!(replies.last().unwrap().0, b't');
+    }
+}
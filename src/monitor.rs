@@ -0,0 +1,239 @@
+/*
+Dry runs of monitoring sweeps (`spotspoof monitor plan`). A sweep permutes the
+protected domains (see `permute`), drops the ones the dataset vouches for, and
+checks the rest for registration over DoH, paced by `--doh-rate`, as
+`export-blocklist --only-registered` does; `tail` and the scan commands alert
+through `[alerts]` and the Kafka sink. The plan works all that out from local
+files only: checks the registration cache can answer are not counted, and
+the notifiers are read from the config without connecting to them.
+*/
+use anyhow::Result;
+use serde::Serialize;
+
+use crate::alerts::AlertsConfig;
+use crate::detections::Severity;
+use crate::export;
+use crate::kafka::KafkaConfig;
+use crate::permute::{self, Technique};
+
+/// What a sweep of the protected domains would do.
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct MonitorPlan {
+    pub targets: Vec<TargetPlan>,
+    /// Distinct permutations left to check, across all targets.
+    pub squats: usize,
+    /// Permutations dropped as legitimate domains of the dataset.
+    pub legitimate: usize,
+    /// Whether a local DB was there to drop legitimate domains with.
+    pub dataset: bool,
+    /// Squats the registration cache already answers.
+    pub cached: usize,
+    /// DoH queries the sweep would send.
+    pub registration_checks: usize,
+    /// `--doh-rate` (0 = unlimited) and `--doh-burst`.
+    pub doh_rate: u32,
+    pub doh_burst: u32,
+    /// The least time the DoH rate limit stretches the checks over.
+    pub rate_limit_secs: u64,
+    pub notifiers: Vec<Notifier>,
+}
+
+/// The permutations of one protected domain, by technique.
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct TargetPlan {
+    pub domain: String,
+    pub permutations: usize,
+    pub techniques: Vec<TechniqueCount>,
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct TechniqueCount {
+    pub technique: Technique,
+    pub permutations: usize,
+}
+
+/// A destination findings would be delivered to.
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct Notifier {
+    /// `webhook`, `slack`, `email`, or `kafka`.
+    pub kind: &'static str,
+    /// Webhook URLs are cut to their origin, as their paths often carry tokens.
+    pub target: String,
+    /// Findings below this severity are not sent.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_severity: Option<Severity>,
+}
+
+/// Where a plan looks things up.
+pub struct PlanSources<L, C> {
+    /// Whether a domain is legitimate; `None` without a local DB.
+    pub is_legit: Option<L>,
+    /// Whether the registration cache holds a fresh answer for a domain.
+    pub is_cached: C,
+}
+
+/// DoH pacing of the checks (`--doh-rate`, `--doh-burst`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DohLimit {
+    pub rate: u32,
+    pub burst: u32,
+}
+
+/// The plan of a sweep of `targets` by `techniques` (all of them when empty).
+pub fn plan<L, C>(
+    targets: &[String],
+    techniques: &[Technique],
+    sources: PlanSources<L, C>,
+    limit: DohLimit,
+    alerts: &AlertsConfig,
+    kafka: &KafkaConfig,
+) -> Result<MonitorPlan>
+where
+    L: FnMut(&str) -> Result<bool>,
+    C: FnMut(&str) -> Result<bool>,
+{
+    let PlanSources {
+        mut is_legit,
+        mut is_cached,
+    } = sources;
+    let mut legitimate = 0;
+    let squats = export::squats(targets, techniques, |domain| {
+        let legit = match &mut is_legit {
+            Some(is_legit) => is_legit(domain)?,
+            None => false,
+        };
+        legitimate += usize::from(legit);
+        Ok(legit)
+    })?;
+    let mut cached = 0;
+    for squat in &squats {
+        cached += usize::from(is_cached(&squat.permutation.domain)?);
+    }
+    let registration_checks = squats.len() - cached;
+    Ok(MonitorPlan {
+        targets: targets
+            .iter()
+            .map(|target| target_plan(target, techniques))
+            .collect(),
+        squats: squats.len(),
+        legitimate,
+        dataset: is_legit.is_some(),
+        cached,
+        registration_checks,
+        doh_rate: limit.rate,
+        doh_burst: limit.burst,
+        rate_limit_secs: rate_limit_secs(registration_checks, limit),
+        notifiers: notifiers(alerts, kafka),
+    })
+}
+
+fn target_plan(domain: &str, techniques: &[Technique]) -> TargetPlan {
+    let permutations = permute::permutations(domain, techniques);
+    let techniques = Technique::ALL
+        .into_iter()
+        .filter_map(|technique| {
+            let count = permutations
+                .iter()
+                .filter(|permutation| permutation.technique == technique)
+                .count();
+            (count > 0).then_some(TechniqueCount {
+                technique,
+                permutations: count,
+            })
+        })
+        .collect();
+    TargetPlan {
+        domain: domain.to_string(),
+        permutations: permutations.len(),
+        techniques,
+    }
+}
+
+/// Seconds the token bucket needs to let `checks` queries through: the
+/// burst goes at once, the rest at `rate` per second.
+fn rate_limit_secs(checks: usize, limit: DohLimit) -> u64 {
+    if limit.rate == 0 {
+        return 0;
+    }
+    let paced = checks.saturating_sub(limit.burst.max(1) as usize) as u64;
+    paced.div_ceil(u64::from(limit.rate))
+}
+
+fn notifiers(alerts: &AlertsConfig, kafka: &KafkaConfig) -> Vec<Notifier> {
+    let min_severity = Some(alerts.min_severity.unwrap_or(Severity::Low));
+    let mut out: Vec<Notifier> = alerts
+        .channels()
+        .into_iter()
+        .map(|channel| Notifier {
+            kind: channel.kind(),
+            target: match channel {
+                crate::alerts::Channel::Email(address) => address,
+                crate::alerts::Channel::Webhook(url) | crate::alerts::Channel::Slack(url) => {
+                    origin(&url)
+                }
+            },
+            min_severity,
+        })
+        .collect();
+    if kafka.enabled() {
+        out.push(Notifier {
+            kind: "kafka",
+            target: kafka.topic.clone(),
+            min_severity: None,
+        });
+    }
+    out
+}
+
+fn origin(url: &str) -> String {
+    url::Url::parse(url)
+        .map(|url| url.origin().ascii_serialization())
+        .unwrap_or_else(|_| "(invalid URL)".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plans_count_squats_checks_and_pacing() {
+        let alerts = AlertsConfig {
+            webhook: vec!["https://hooks.example.com/T000/secret".to_string()],
+            email: vec!["soc@example.com".to_string()],
+            min_severity: Some(Severity::High),
+            ..AlertsConfig::default()
+        };
+        let targets = ["paypal.com".to_string()];
+        let techniques = [Technique::Omission, Technique::Repetition];
+        let plan = plan(
+            &targets,
+            &techniques,
+            PlanSources {
+                is_legit: Some(|domain: &str| Ok(domain == "paypl.com")),
+                is_cached: |domain: &str| Ok(domain == "aypal.com"),
+            },
+            DohLimit { rate: 2, burst: 1 },
+            &alerts,
+            &KafkaConfig::default(),
+        )
+        .unwrap();
+
+        let counts: Vec<(Technique, usize)> = plan.targets[0]
+            .techniques
+            .iter()
+            .map(|count| (count.technique, count.permutations))
+            .collect();
+        assert_eq!(
+            counts,
+            [(Technique::Omission, 6), (Technique::Repetition, 6)]
+        );
+        assert_eq!(plan.targets[0].permutations, 12);
+        assert_eq!((plan.squats, plan.legitimate, plan.cached), (11, 1, 1));
+        // One check goes in the burst, the other nine at two per second.
+        assert_eq!(plan.registration_checks, 10);
+        assert_eq!(plan.rate_limit_secs, 5);
+        assert_eq!(plan.notifiers[0].target, "https://hooks.example.com");
+        assert_eq!(plan.notifiers[1].kind, "email");
+        assert_eq!(plan.notifiers[1].min_severity, Some(Severity::High));
+    }
+}
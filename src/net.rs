@@ -0,0 +1,341 @@
+/*
+Shared outbound HTTP helpers. Builds clients with the configured proxy and
+wraps blocking requests in retries with exponential backoff and jitter,
+honouring `Retry-After` on 429 responses, so DoH checks and DB downloads ride
+out transient failures. DoH queries also share one token bucket per process,
+so a burst of IDN lookups cannot get the egress IP throttled by the resolver.
+*/
+use anyhow::Result;
+use rand::Rng;
+use reqwest::blocking::{ClientBuilder, RequestBuilder, Response};
+use reqwest::StatusCode;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+use tracing::debug;
+
+use crate::config::NetworkConfig;
+
+pub const DEFAULT_RETRIES: u32 = 3;
+pub const DEFAULT_RETRY_BASE_MS: u64 = 250;
+pub const DEFAULT_DOH_RATE: u32 = 50;
+const MAX_DELAY: Duration = Duration::from_secs(30);
+
+static POLICY: OnceLock<RetryPolicy> = OnceLock::new();
+static PROXY: OnceLock<reqwest::Proxy> = OnceLock::new();
+static OFFLINE: OnceLock<bool> = OnceLock::new();
+static DOH_LIMITER: OnceLock<TokenBucket> = OnceLock::new();
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Extra attempts after the first one.
+    pub retries: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            retries: DEFAULT_RETRIES,
+            base_delay: Duration::from_millis(DEFAULT_RETRY_BASE_MS),
+            max_delay: MAX_DELAY,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Delay before retry number `retry` (1-based): the exponential step,
+    /// halved and topped up with random jitter so clients do not retry in
+    /// lockstep.
+    fn backoff(&self, retry: u32) -> Duration {
+        let step = self
+            .base_delay
+            .saturating_mul(1 << (retry - 1).min(16))
+            .min(self.max_delay);
+        let half = step / 2;
+        half + rand::thread_rng().gen_range(Duration::ZERO..=half)
+    }
+}
+
+/// A token bucket refilled at `rate` tokens per second up to `burst`. Waiting
+/// callers reserve their token up front, so they are served in arrival order.
+#[derive(Debug)]
+pub struct TokenBucket {
+    rate: f64,
+    burst: f64,
+    /// Tokens left (negative while reserved by waiting callers) and when
+    /// they were counted.
+    state: Mutex<(f64, Instant)>,
+}
+
+impl TokenBucket {
+    pub fn new(rate: u32, burst: u32) -> Self {
+        let burst = f64::from(burst.max(1));
+        Self {
+            rate: f64::from(rate.max(1)),
+            burst,
+            state: Mutex::new((burst, Instant::now())),
+        }
+    }
+
+    /// Takes a token, sleeping until it is due. Takes nothing and returns
+    /// false when that would be longer than `max_wait`.
+    pub fn acquire(&self, max_wait: Duration) -> bool {
+        let wait = {
+            let mut state = self.state.lock().unwrap_or_else(|p| p.into_inner());
+            let (tokens, counted) = &mut *state;
+            let now = Instant::now();
+            *tokens =
+                (*tokens + now.duration_since(*counted).as_secs_f64() * self.rate).min(self.burst);
+            *counted = now;
+            let wait = Duration::from_secs_f64(((1.0 - *tokens) / self.rate).max(0.0));
+            if wait > max_wait {
+                return false;
+            }
+            *tokens -= 1.0;
+            wait
+        };
+        if !wait.is_zero() {
+            std::thread::sleep(wait);
+        }
+        true
+    }
+}
+
+/// Limits DoH queries to `rate` per second with bursts of up to `burst`;
+/// a rate of 0 leaves them unlimited. Only the first call takes effect.
+pub fn install_doh_limit(rate: u32, burst: u32) {
+    if rate > 0 {
+        let _ = DOH_LIMITER.set(TokenBucket::new(rate, burst));
+    }
+}
+
+/// Waits up to `max_wait` for a DoH query slot; false when none frees up.
+pub fn acquire_doh(max_wait: Duration) -> bool {
+    DOH_LIMITER
+        .get()
+        .is_none_or(|limiter| limiter.acquire(max_wait))
+}
+
+/// Makes `policy` the process-wide retry policy. Only the first call takes
+/// effect.
+pub fn install(policy: RetryPolicy) {
+    let _ = POLICY.set(policy);
+}
+
+/// Turns off all outbound traffic for the rest of the process.
+pub fn install_offline(offline: bool) {
+    let _ = OFFLINE.set(offline);
+}
+
+pub fn offline() -> bool {
+    OFFLINE.get().copied().unwrap_or(false)
+}
+
+/// Fails when offline mode forbids the network call described by `what`.
+pub fn ensure_online(what: &str) -> Result<()> {
+    if offline() {
+        return Err(anyhow::anyhow!(
+            "{what} needs network access, but offline mode is on"
+        ));
+    }
+    Ok(())
+}
+
+/// Routes every client from `client_builder` through the configured proxy.
+/// Without one, reqwest falls back to the standard proxy env vars
+/// (`HTTP_PROXY`, `HTTPS_PROXY`, `ALL_PROXY`, `NO_PROXY`).
+pub fn install_proxy(network: &NetworkConfig) -> Result<()> {
+    let Some(url) = network.proxy.as_deref() else {
+        return Ok(());
+    };
+    let proxy = proxy(url, network.no_proxy.as_deref())?;
+    let _ = PROXY.set(proxy);
+    Ok(())
+}
+
+fn proxy(url: &str, no_proxy: Option<&str>) -> Result<reqwest::Proxy> {
+    let scheme = url.split_once("://").map(|(scheme, _)| scheme);
+    if !matches!(scheme, Some("http" | "https" | "socks5" | "socks5h")) {
+        return Err(anyhow::anyhow!(
+            "unsupported proxy URL '{url}' (use http://, https://, socks5://, or socks5h://)"
+        ));
+    }
+    Ok(reqwest::Proxy::all(url)?.no_proxy(no_proxy.and_then(reqwest::NoProxy::from_string)))
+}
+
+/// A blocking client builder that uses the installed proxy, if any.
+pub fn client_builder() -> ClientBuilder {
+    let builder = reqwest::blocking::Client::builder();
+    match PROXY.get() {
+        Some(proxy) => builder.proxy(proxy.clone()),
+        None => builder,
+    }
+}
+
+/// The installed retry policy (the default if none was installed).
+pub fn policy() -> RetryPolicy {
+    POLICY.get().copied().unwrap_or_default()
+}
+
+/// Sends the request built by `request` under the installed retry policy.
+/// `what` names the call in logs.
+pub fn send_with_retry<F>(what: &str, request: F) -> reqwest::Result<Response>
+where
+    F: Fn() -> RequestBuilder,
+{
+    send_with(&policy(), what, request)
+}
+
+fn send_with<F>(policy: &RetryPolicy, what: &str, request: F) -> reqwest::Result<Response>
+where
+    F: Fn() -> RequestBuilder,
+{
+    let attempts = policy.retries + 1;
+    let mut attempt = 1;
+    loop {
+        let result = request().send();
+        let retry_after = match &result {
+            Ok(response) if is_transient_status(response.status()) => {
+                Some(retry_after(response).unwrap_or_else(|| policy.backoff(attempt)))
+            }
+            Err(err) if is_transient_error(err) => Some(policy.backoff(attempt)),
+            _ => None,
+        };
+        let outcome = match &result {
+            Ok(response) => response.status().to_string(),
+            Err(err) => err.to_string(),
+        };
+        debug!(kind = "net", call = what, attempt, attempts, outcome = %outcome);
+        match retry_after {
+            Some(delay) if attempt < attempts => {
+                std::thread::sleep(delay.min(policy.max_delay));
+                attempt += 1;
+            }
+            _ => return result,
+        }
+    }
+}
+
+fn is_transient_status(status: StatusCode) -> bool {
+    status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
+}
+
+fn is_transient_error(err: &reqwest::Error) -> bool {
+    err.is_timeout() || err.is_connect() || err.is_request()
+}
+
+/// `Retry-After` in delta-seconds form on a 429 response.
+fn retry_after(response: &Response) -> Option<Duration> {
+    if response.status() != StatusCode::TOO_MANY_REQUESTS {
+        return None;
+    }
+    let seconds = response
+        .headers()
+        .get(reqwest::header::RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim()
+        .parse()
+        .ok()?;
+    Some(Duration::from_secs(seconds))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    /// Serves `responses` to successive connections and reports how many
+    /// requests arrived.
+    fn start_server(responses: Vec<&'static [u8]>) -> (String, thread::JoinHandle<usize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("bind");
+        let addr = listener.local_addr().expect("addr");
+        let handle = thread::spawn(move || {
+            let mut served = 0;
+            for response in responses {
+                let Ok((mut stream, _)) = listener.accept() else {
+                    break;
+                };
+                let mut buf = [0u8; 1024];
+                let _ = stream.read(&mut buf);
+                let _ = stream.write_all(response);
+                served += 1;
+            }
+            served
+        });
+        (format!("http://{addr}/"), handle)
+    }
+
+    fn fast_policy(retries: u32) -> RetryPolicy {
+        RetryPolicy {
+            retries,
+            base_delay: Duration::from_millis(1),
+            max_delay: Duration::from_millis(5),
+        }
+    }
+
+    #[test]
+    fn retries_transient_statuses_until_success() {
+        let (url, server) = start_server(vec![
+            b"HTTP/1.1 503 Service Unavailable\r\nContent-Length: 0\r\n\r\n",
+            b"HTTP/1.1 429 Too Many Requests\r\nRetry-After: 0\r\nContent-Length: 0\r\n\r\n",
+            b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok",
+        ]);
+        let client = reqwest::blocking::Client::new();
+        let response = send_with(&fast_policy(3), "test", || client.get(&url)).unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(server.join().unwrap(), 3);
+    }
+
+    #[test]
+    fn gives_up_after_the_configured_retries() {
+        let (url, server) = start_server(vec![
+            b"HTTP/1.1 500 Internal Server Error\r\nContent-Length: 0\r\n\r\n",
+            b"HTTP/1.1 500 Internal Server Error\r\nContent-Length: 0\r\n\r\n",
+        ]);
+        let client = reqwest::blocking::Client::new();
+        let response = send_with(&fast_policy(1), "test", || client.get(&url)).unwrap();
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(server.join().unwrap(), 2);
+    }
+
+    #[test]
+    fn proxy_urls_must_use_a_supported_scheme() {
+        assert!(proxy("http://proxy.corp:3128", None).is_ok());
+        assert!(proxy("socks5h://127.0.0.1:1080", Some("localhost")).is_ok());
+        assert!(proxy("ftp://proxy.corp", None).is_err());
+        assert!(proxy("proxy.corp:3128", None).is_err());
+    }
+
+    #[test]
+    fn backoff_grows_and_stays_capped() {
+        let policy = RetryPolicy {
+            retries: 10,
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(1),
+        };
+        for _ in 0..20 {
+            let first = policy.backoff(1);
+            assert!((Duration::from_millis(50)..=Duration::from_millis(100)).contains(&first));
+            let late = policy.backoff(10);
+            assert!((Duration::from_millis(500)..=Duration::from_secs(1)).contains(&late));
+        }
+    }
+
+    #[test]
+    fn token_bucket_allows_a_burst_then_paces() {
+        let bucket = TokenBucket::new(100, 2);
+        assert!(bucket.acquire(Duration::ZERO));
+        assert!(bucket.acquire(Duration::ZERO));
+        assert!(!bucket.acquire(Duration::ZERO));
+
+        let started = Instant::now();
+        assert!(bucket.acquire(Duration::from_secs(1)));
+        assert!(started.elapsed() >= Duration::from_millis(5));
+        // A caller that cannot wait long enough takes nothing.
+        assert!(!bucket.acquire(Duration::from_micros(100)));
+    }
+}
@@ -0,0 +1,171 @@
+/*
+`spotspoof db pack`: the publishing side of `download`. Stamps the dataset
+metadata (version, build date, row count, source) into the DB's `meta` table, writes a
+VACUUMed copy, compresses it with zstd, and writes the matching `.sha256` file
+in `sha256sum` format, so organisations can publish their own datasets for
+`spotspoof update --url` to fetch.
+*/
+use anyhow::Result;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Write};
+use std::path::Path;
+
+use crate::{cipher, db, release};
+
+pub const DEFAULT_LEVEL: i32 = 19;
+pub const DOMAINS_KEY: &str = "domains";
+
+/// What `pack` wrote.
+#[derive(Debug, Clone, Serialize)]
+pub struct PackSummary {
+    pub path: String,
+    pub sha256_path: String,
+    pub version: String,
+    pub date: String,
+    pub domains: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+    pub bytes: u64,
+    pub sha256: String,
+}
+
+/// Packs `db_path` into `out_path`. `version` and `source` default to the
+/// ones already recorded in the DB.
+pub fn pack(
+    db_path: &str,
+    out_path: &str,
+    version: Option<&str>,
+    source: Option<&str>,
+    level: i32,
+) -> Result<PackSummary> {
+    let conn = cipher::open(db_path)?;
+    db::verify_schema(&conn)?;
+    let version = match version {
+        Some(version) => version.to_string(),
+        None => db::dataset_version(&conn)?.ok_or_else(|| {
+            anyhow::anyhow!("{db_path} records no dataset version; pass --version")
+        })?,
+    };
+    let date: String =
+        conn.query_row("SELECT strftime('%Y-%m-%dT%H:%M:%SZ', 'now')", [], |row| {
+            row.get(0)
+        })?;
+    let domains: u64 =
+        conn.query_row("SELECT COUNT(*) FROM legit_domains", [], |row| row.get(0))?;
+    db::set_dataset_version(&conn, &version)?;
+    db::set_meta(&conn, release::DATE_KEY, &date)?;
+    db::set_meta(&conn, DOMAINS_KEY, &domains.to_string())?;
+    if let Some(source) = source {
+        db::set_meta(&conn, release::SOURCE_KEY, source)?;
+    }
+    let source = db::meta(&conn, release::SOURCE_KEY)?;
+
+    let vacuumed = format!("{out_path}.sqlite.tmp");
+    let _ = fs::remove_file(&vacuumed);
+    conn.execute("VACUUM INTO ?1", [&vacuumed])?;
+    drop(conn);
+    let compressed = compress(&vacuumed, out_path, level);
+    let _ = fs::remove_file(&vacuumed);
+    let (bytes, sha256) = compressed?;
+
+    let sha256_path = format!("{out_path}.sha256");
+    let file_name = Path::new(out_path)
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_else(|| out_path.to_string());
+    fs::write(&sha256_path, format!("{sha256}  {file_name}\n"))?;
+    Ok(PackSummary {
+        path: out_path.to_string(),
+        sha256_path,
+        version,
+        date,
+        domains,
+        source,
+        bytes,
+        sha256,
+    })
+}
+
+/// Compresses `src` to `dst` and returns the compressed size and SHA-256.
+fn compress(src: &str, dst: &str, level: i32) -> Result<(u64, String)> {
+    let tmp_path = format!("{dst}.tmp");
+    let mut encoder =
+        zstd::stream::write::Encoder::new(BufWriter::new(File::create(&tmp_path)?), level)?;
+    encoder.include_checksum(true)?;
+    std::io::copy(&mut BufReader::new(File::open(src)?), &mut encoder)?;
+    encoder.finish()?.flush()?;
+    fs::rename(&tmp_path, dst)?;
+
+    let mut hasher = Sha256::new();
+    let bytes = std::io::copy(&mut File::open(dst)?, &mut hasher)?;
+    Ok((bytes, format!("{:x}", hasher.finalize())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rusqlite::Connection;
+
+    #[test]
+    fn packed_db_round_trips_with_metadata_and_checksum() {
+        let dir = std::env::temp_dir();
+        let name = |suffix: &str| {
+            dir.join(format!("spotspoof-pack-{}{suffix}", std::process::id()))
+                .to_string_lossy()
+                .to_string()
+        };
+        let (db_path, out_path) = (name(".sqlite"), name(".sqlite.zst"));
+        let conn = Connection::open(&db_path).unwrap();
+        conn.execute_batch(
+            "CREATE TABLE legit_domains (domain TEXT, first_char TEXT, length INTEGER);
+             INSERT INTO legit_domains VALUES ('google.com', 'g', 10);
+             INSERT INTO legit_domains VALUES ('example.com', 'e', 11);",
+        )
+        .unwrap();
+        drop(conn);
+
+        assert!(pack(&db_path, &out_path, None, None, 3).is_err());
+        let summary = pack(
+            &db_path,
+            &out_path,
+            Some("2024.06.01"),
+            Some("tranco top 1M"),
+            3,
+        )
+        .unwrap();
+        assert_eq!(summary.domains, 2);
+        assert_eq!(summary.source.as_deref(), Some("tranco top 1M"));
+        assert_eq!(
+            fs::read_to_string(&summary.sha256_path).unwrap(),
+            format!(
+                "{}  {}\n",
+                summary.sha256,
+                Path::new(&out_path).file_name().unwrap().to_string_lossy()
+            )
+        );
+        assert_eq!(db::file_sha256(&out_path).unwrap(), summary.sha256);
+
+        let unpacked = name(".unpacked.sqlite");
+        let raw = zstd::stream::decode_all(File::open(&out_path).unwrap()).unwrap();
+        fs::write(&unpacked, raw).unwrap();
+        let conn = Connection::open(&unpacked).unwrap();
+        assert_eq!(
+            db::dataset_version(&conn).unwrap().as_deref(),
+            Some("2024.06.01")
+        );
+        assert_eq!(db::meta(&conn, DOMAINS_KEY).unwrap().as_deref(), Some("2"));
+        assert_eq!(
+            db::meta(&conn, release::DATE_KEY).unwrap(),
+            Some(summary.date)
+        );
+        let dataset = release::LocalDataset::from_conn(&conn).unwrap();
+        assert_eq!(dataset.info().source.as_deref(), Some("tranco top 1M"));
+        assert_eq!(dataset.info().domains, 2);
+        drop(conn);
+        for path in [&db_path, &out_path, &summary.sha256_path, &unpacked] {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
@@ -0,0 +1,395 @@
+/*
+Passive DNS enrichment (`[passive_dns]` in the config file). A lookalike first
+seen resolving yesterday is a live campaign; one that has resolved to the same
+parking addresses for years can wait. With a provider configured, lookups that
+find a lookalike (`lookup`, and `/lookup`, `/ascii`, `/idn`, and gRPC under
+`serve`) and the findings of the scan commands carry the domain's passive DNS
+history: when it was first and last seen resolving, and the addresses it
+resolved to. Providers speak the Passive DNS Common Output Format
+(`provider = "cof"`, e.g. CIRCL) or the Farsight DNSDB v2 API
+(`provider = "dnsdb"`). Answers are cached for an hour; a failed query is
+logged and never fails a lookup.
+*/
+use anyhow::Result;
+use lru::LruCache;
+use reqwest::{header, StatusCode};
+use serde::Deserialize;
+use std::num::NonZeroUsize;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant, UNIX_EPOCH};
+use tracing::warn;
+
+use crate::net;
+use crate::tail::utc_timestamp;
+use crate::types::{AsciiResponse, IdnResponse, PassiveDns};
+
+pub const DEFAULT_TIMEOUT_MS: u64 = 5_000;
+pub const DEFAULT_MAX_LOOKUPS: usize = 50;
+/// Env var read for `api_key` when the config leaves it out.
+const API_KEY_ENV: &str = "SPOTSPOOF_PASSIVE_DNS_KEY";
+const CACHE_SIZE: usize = 4_096;
+const CACHE_TTL: Duration = Duration::from_secs(60 * 60);
+/// Addresses kept per domain; fast-flux domains rotate through hundreds.
+const MAX_IPS: usize = 20;
+
+static INSTALLED: OnceLock<PassiveDnsClient> = OnceLock::new();
+
+/// The `[passive_dns]` section of the config file.
+#[derive(Debug, Clone, Default, Deserialize, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct PassiveDnsConfig {
+    /// Provider endpoint; enrichment is off without one. `cof` providers are
+    /// queried at `<url>/<domain>`, DNSDB (`https://api.dnsdb.info/dnsdb/v2`)
+    /// at `<url>/lookup/rrset/name/<domain>`.
+    pub url: Option<String>,
+    pub provider: Provider,
+    /// Sent as `X-API-Key` (DNSDB). Falls back to `SPOTSPOOF_PASSIVE_DNS_KEY`.
+    pub api_key: Option<String>,
+    /// HTTP basic auth credentials (CIRCL).
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// Per-query timeout (default 5000).
+    pub timeout_ms: Option<u64>,
+    /// Findings enriched per scan report, riskiest first (default 50).
+    pub max_lookups: Option<usize>,
+}
+
+impl PassiveDnsConfig {
+    pub fn enabled(&self) -> bool {
+        self.url.is_some()
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Provider {
+    /// Passive DNS Common Output Format: one JSON record per line.
+    #[default]
+    Cof,
+    /// Farsight DNSDB API v2: records wrapped in `{"obj": ...}` lines.
+    Dnsdb,
+}
+
+impl Provider {
+    fn query_url(self, base: &str, domain: &str) -> String {
+        let base = base.trim_end_matches('/');
+        match self {
+            Self::Cof => format!("{base}/{domain}"),
+            Self::Dnsdb => format!("{base}/lookup/rrset/name/{domain}"),
+        }
+    }
+
+    /// Where the names that resolved to `ip` are listed.
+    fn reverse_url(self, base: &str, ip: &str) -> String {
+        let base = base.trim_end_matches('/');
+        match self {
+            Self::Cof => format!("{base}/{ip}"),
+            Self::Dnsdb => format!("{base}/lookup/rdata/ip/{ip}"),
+        }
+    }
+
+    /// The records in a response body.
+    fn records(self, body: &str) -> Result<Vec<Record>> {
+        // Some COF servers answer with one JSON array instead of lines.
+        if self == Self::Cof && body.trim_start().starts_with('[') {
+            return Ok(serde_json::from_str(body)?);
+        }
+        let mut records = Vec::new();
+        for line in body.lines().filter(|line| !line.trim().is_empty()) {
+            match self {
+                Self::Cof => records.push(serde_json::from_str(line)?),
+                // `{"cond": "begin"}` and friends frame the stream.
+                Self::Dnsdb => records.extend(serde_json::from_str::<Envelope>(line)?.obj),
+            }
+        }
+        Ok(records)
+    }
+}
+
+#[derive(Deserialize)]
+struct Envelope {
+    obj: Option<Record>,
+}
+
+/// One RRset as both formats describe it. Times are Unix seconds; records
+/// learned from zone files carry `zone_time_*` instead.
+#[derive(Debug, Deserialize)]
+struct Record {
+    #[serde(default)]
+    rrname: String,
+    #[serde(default)]
+    rrtype: String,
+    #[serde(default)]
+    rdata: Rdata,
+    time_first: Option<u64>,
+    time_last: Option<u64>,
+    zone_time_first: Option<u64>,
+    zone_time_last: Option<u64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(untagged)]
+enum Rdata {
+    One(String),
+    Many(Vec<String>),
+    #[default]
+    None,
+}
+
+impl Record {
+    fn first(&self) -> Option<u64> {
+        self.time_first.or(self.zone_time_first)
+    }
+
+    fn last(&self) -> Option<u64> {
+        self.time_last.or(self.zone_time_last)
+    }
+}
+
+/// Folds the RRsets of a domain into its history.
+fn summarize(mut records: Vec<Record>) -> PassiveDns {
+    let timestamp = |secs: u64| utc_timestamp(UNIX_EPOCH + Duration::from_secs(secs));
+    records.sort_by_key(|record| std::cmp::Reverse(record.last()));
+    let mut ips: Vec<String> = Vec::new();
+    for record in records
+        .iter()
+        .filter(|record| matches!(record.rrtype.as_str(), "A" | "AAAA"))
+    {
+        let rdata = match &record.rdata {
+            Rdata::One(ip) => std::slice::from_ref(ip),
+            Rdata::Many(ips) => ips.as_slice(),
+            Rdata::None => &[],
+        };
+        for ip in rdata {
+            if ips.len() < MAX_IPS && !ips.contains(ip) {
+                ips.push(ip.clone());
+            }
+        }
+    }
+    PassiveDns {
+        first_seen: records
+            .iter()
+            .filter_map(Record::first)
+            .min()
+            .map(timestamp),
+        last_seen: records.iter().filter_map(Record::last).max().map(timestamp),
+        ips,
+    }
+}
+
+/// The names with A or AAAA records among `records`, most recently seen first.
+fn address_owners(mut records: Vec<Record>) -> Vec<String> {
+    records.sort_by_key(|record| std::cmp::Reverse(record.last()));
+    let mut names: Vec<String> = Vec::new();
+    for record in records
+        .iter()
+        .filter(|record| matches!(record.rrtype.as_str(), "A" | "AAAA"))
+    {
+        let name = record.rrname.trim_end_matches('.').to_ascii_lowercase();
+        if !name.is_empty() && !names.contains(&name) {
+            names.push(name);
+        }
+    }
+    names
+}
+
+pub struct PassiveDnsClient {
+    client: reqwest::blocking::Client,
+    url: String,
+    provider: Provider,
+    api_key: Option<String>,
+    basic_auth: Option<(String, Option<String>)>,
+    max_lookups: usize,
+    cache: Mutex<LruCache<String, (Instant, PassiveDns)>>,
+}
+
+impl PassiveDnsClient {
+    pub fn new(config: &PassiveDnsConfig) -> Result<Self> {
+        let url = config
+            .url
+            .clone()
+            .ok_or_else(|| anyhow::anyhow!("[passive_dns] needs a url"))?;
+        if !url.starts_with("http://") && !url.starts_with("https://") {
+            anyhow::bail!("[passive_dns] url must be http:// or https://");
+        }
+        let timeout = Duration::from_millis(config.timeout_ms.unwrap_or(DEFAULT_TIMEOUT_MS));
+        Ok(Self {
+            client: net::client_builder().timeout(timeout).build()?,
+            url,
+            provider: config.provider,
+            api_key: config
+                .api_key
+                .clone()
+                .or_else(|| std::env::var(API_KEY_ENV).ok()),
+            basic_auth: config
+                .username
+                .clone()
+                .map(|username| (username, config.password.clone())),
+            max_lookups: config.max_lookups.unwrap_or(DEFAULT_MAX_LOOKUPS),
+            cache: Mutex::new(LruCache::new(
+                NonZeroUsize::new(CACHE_SIZE).expect("cache size is not zero"),
+            )),
+        })
+    }
+
+    fn lookup(&self, domain: &str) -> Option<PassiveDns> {
+        let domain = domain.trim_end_matches('.').to_ascii_lowercase();
+        if let Some((at, history)) = lock(&self.cache).get(&domain) {
+            if at.elapsed() < CACHE_TTL {
+                return Some(history.clone());
+            }
+        }
+        match self.query(&domain) {
+            Ok(history) => {
+                lock(&self.cache).put(domain, (Instant::now(), history.clone()));
+                Some(history)
+            }
+            Err(err) => {
+                warn!(kind = "passive_dns", domain = %domain, "query failed: {err}");
+                None
+            }
+        }
+    }
+
+    fn query(&self, domain: &str) -> Result<PassiveDns> {
+        Ok(summarize(
+            self.records(&self.provider.query_url(&self.url, domain))?,
+        ))
+    }
+
+    fn records(&self, url: &str) -> Result<Vec<Record>> {
+        let response = net::send_with_retry("passive DNS", || {
+            let mut request = self
+                .client
+                .get(url)
+                .header(header::ACCEPT, "application/x-ndjson, application/json");
+            if let Some(key) = &self.api_key {
+                request = request.header("X-API-Key", key);
+            }
+            if let Some((username, password)) = &self.basic_auth {
+                request = request.basic_auth(username, password.as_ref());
+            }
+            request
+        })?;
+        match response.status() {
+            // Both APIs answer 404 for names they never saw.
+            StatusCode::NOT_FOUND => return Ok(Vec::new()),
+            status if !status.is_success() => anyhow::bail!("HTTP {status}"),
+            _ => {}
+        }
+        self.provider.records(&response.text()?)
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Enriches lookups and scan findings from now on.
+pub fn install(client: PassiveDnsClient) {
+    let _ = INSTALLED.set(client);
+}
+
+pub fn installed() -> bool {
+    INSTALLED.get().is_some()
+}
+
+/// The passive DNS history of `domain`, or `None` without a provider, when
+/// offline, or when the query failed.
+pub fn lookup(domain: &str) -> Option<PassiveDns> {
+    let client = INSTALLED.get()?;
+    if net::offline() {
+        return None;
+    }
+    client.lookup(domain)
+}
+
+/// The names passive DNS saw resolving to `ip`, or `None` without a
+/// provider.
+pub fn reverse(ip: &str) -> Option<Result<Vec<String>>> {
+    let client = INSTALLED.get()?;
+    Some(
+        client
+            .records(&client.provider.reverse_url(&client.url, ip))
+            .map(address_owners),
+    )
+}
+
+/// How many findings of a scan report to enrich.
+pub fn max_lookups() -> usize {
+    INSTALLED.get().map_or(0, |client| client.max_lookups)
+}
+
+/// Adds the query's passive DNS history to an ASCII lookup that found a
+/// lookalike.
+pub fn enrich_ascii(response: &mut AsciiResponse) {
+    if installed() && !response.exact_match && !response.results.is_empty() {
+        response.passive_dns = lookup(&response.q);
+    }
+}
+
+/// Adds the query's passive DNS history, under its punycode name, to an IDN
+/// lookup that found a lookalike.
+pub fn enrich_idn(response: &mut IdnResponse) {
+    if installed() && !response.results.is_empty() {
+        let name = idna::domain_to_ascii(&response.q).unwrap_or_else(|_| response.q.clone());
+        response.passive_dns = lookup(&name);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cof_and_dnsdb_answers_fold_into_a_history() {
+        let cof = r#"{"rrname": "paypa1.com", "rrtype": "A", "rdata": "203.0.113.7", "time_first": 1717228800, "time_last": 1717315200, "count": 12}
+{"rrname": "paypa1.com", "rrtype": "NS", "rdata": "ns1.parking.example", "time_first": 1700000000, "time_last": 1717315200}
+{"rrname": "paypa1.com", "rrtype": "A", "rdata": "198.51.100.9", "time_first": 1717228800, "time_last": 1717401600}
+"#;
+        let history = summarize(Provider::Cof.records(cof).unwrap());
+        assert_eq!(
+            history,
+            PassiveDns {
+                first_seen: Some("2023-11-14T22:13:20Z".to_string()),
+                last_seen: Some("2024-06-03T08:00:00Z".to_string()),
+                ips: vec!["198.51.100.9".to_string(), "203.0.113.7".to_string()],
+            }
+        );
+
+        let dnsdb = r#"{"cond":"begin"}
+{"obj":{"count":3,"zone_time_first":1717228800,"zone_time_last":1717315200,"rrname":"paypa1.com.","rrtype":"AAAA","bailiwick":"com.","rdata":["2001:db8::7","2001:db8::8"]}}
+{"cond":"succeeded"}
+"#;
+        let history = summarize(Provider::Dnsdb.records(dnsdb).unwrap());
+        assert_eq!(history.first_seen.as_deref(), Some("2024-06-01T08:00:00Z"));
+        assert_eq!(history.ips, ["2001:db8::7", "2001:db8::8"]);
+        assert_eq!(summarize(Vec::new()), PassiveDns::default());
+
+        assert_eq!(
+            Provider::Dnsdb.query_url("https://api.dnsdb.info/dnsdb/v2/", "paypa1.com"),
+            "https://api.dnsdb.info/dnsdb/v2/lookup/rrset/name/paypa1.com"
+        );
+        assert!(Provider::Cof.records("not json").is_err());
+    }
+
+    #[test]
+    fn reverse_answers_list_the_names_on_an_address() {
+        let dnsdb = r#"{"cond":"begin"}
+{"obj":{"count":9,"time_first":1717228800,"time_last":1717315200,"rrname":"paypa1.com.","rrtype":"A","rdata":"203.0.113.7"}}
+{"obj":{"count":4,"time_first":1717228800,"time_last":1717401600,"rrname":"www.amaz0n-login.com.","rrtype":"A","rdata":"203.0.113.7"}}
+{"obj":{"count":2,"time_first":1717228800,"time_last":1717401600,"rrname":"WWW.amaz0n-login.com.","rrtype":"A","rdata":"203.0.113.7"}}
+{"cond":"succeeded"}
+"#;
+        assert_eq!(
+            address_owners(Provider::Dnsdb.records(dnsdb).unwrap()),
+            ["www.amaz0n-login.com", "paypa1.com"]
+        );
+        assert_eq!(
+            Provider::Dnsdb.reverse_url("https://api.dnsdb.info/dnsdb/v2", "203.0.113.7"),
+            "https://api.dnsdb.info/dnsdb/v2/lookup/rdata/ip/203.0.113.7"
+        );
+    }
+}
@@ -0,0 +1,135 @@
+/*
+Per-user directories. Data files (the DB and its indexes and checksum, the
+lookup history, detections, alert outbox, DNS cache, and mappings) default to
+`--data-dir`, else `$XDG_DATA_HOME/spotspoof` (`~/.local/share/spotspoof`),
+`~/Library/Application Support/spotspoof` on macOS, or
+`%LOCALAPPDATA%\spotspoof` (else `%APPDATA%`) on Windows. The config file lives
+in the matching config directory. Only without a home directory do data files
+fall back to the executable's directory, which package-managed installs cannot
+write to.
+*/
+use std::ffi::OsString;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+const APP_DIR: &str = "spotspoof";
+
+static DATA_DIR: OnceLock<PathBuf> = OnceLock::new();
+
+/// Makes `dir` the data directory (`--data-dir`). Only the first call takes
+/// effect.
+pub fn install_data_dir(dir: impl Into<PathBuf>) {
+    let _ = DATA_DIR.set(dir.into());
+}
+
+/// The directory data files default to; `None` when neither `--data-dir` nor
+/// a home directory is known.
+pub fn data_dir() -> Option<PathBuf> {
+    DATA_DIR
+        .get()
+        .cloned()
+        .or_else(|| user_data_dir(|name| std::env::var_os(name)))
+}
+
+/// The directory the config file defaults to.
+pub fn config_dir() -> Option<PathBuf> {
+    user_config_dir(|name| std::env::var_os(name))
+}
+
+/// Where the data file `file_name` lives by default: the data directory, else
+/// next to the executable, else the current directory.
+pub fn data_path(file_name: &str) -> String {
+    let dir = data_dir().or_else(|| {
+        std::env::current_exe()
+            .ok()
+            .and_then(|exe| exe.parent().map(PathBuf::from))
+    });
+    match dir {
+        Some(dir) => dir.join(file_name).to_string_lossy().to_string(),
+        None => file_name.to_string(),
+    }
+}
+
+fn user_data_dir(var: impl Fn(&str) -> Option<OsString>) -> Option<PathBuf> {
+    let mut path = if cfg!(target_os = "windows") {
+        PathBuf::from(var("LOCALAPPDATA").or_else(|| var("APPDATA"))?)
+    } else if cfg!(target_os = "macos") {
+        application_support(&var)?
+    } else {
+        xdg_dir(&var, "XDG_DATA_HOME", &[".local", "share"])?
+    };
+    path.push(APP_DIR);
+    Some(path)
+}
+
+fn user_config_dir(var: impl Fn(&str) -> Option<OsString>) -> Option<PathBuf> {
+    let mut path = if cfg!(target_os = "windows") {
+        PathBuf::from(var("APPDATA")?)
+    } else if cfg!(target_os = "macos") {
+        application_support(&var)?
+    } else {
+        xdg_dir(&var, "XDG_CONFIG_HOME", &[".config"])?
+    };
+    path.push(APP_DIR);
+    Some(path)
+}
+
+fn application_support(var: &impl Fn(&str) -> Option<OsString>) -> Option<PathBuf> {
+    let mut path = PathBuf::from(var("HOME")?);
+    path.push("Library");
+    path.push("Application Support");
+    Some(path)
+}
+
+/// `$<name>`, else `$HOME/<fallback>`. Relative values are ignored, as the
+/// XDG spec requires.
+fn xdg_dir(
+    var: &impl Fn(&str) -> Option<OsString>,
+    name: &str,
+    fallback: &[&str],
+) -> Option<PathBuf> {
+    if let Some(dir) = var(name).map(PathBuf::from).filter(|dir| dir.is_absolute()) {
+        return Some(dir);
+    }
+    let mut path = PathBuf::from(var("HOME")?);
+    path.extend(fallback);
+    Some(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(all(unix, not(target_os = "macos")))]
+    #[test]
+    fn xdg_directories_win_over_the_home_fallbacks() {
+        let env = |vars: &'static [(&'static str, &'static str)]| {
+            move |name: &str| {
+                vars.iter()
+                    .find(|(key, _)| *key == name)
+                    .map(|(_, value)| OsString::from(value))
+            }
+        };
+        let home = env(&[("HOME", "/home/me")]);
+        assert_eq!(
+            user_data_dir(home),
+            Some(PathBuf::from("/home/me/.local/share/spotspoof"))
+        );
+        assert_eq!(
+            user_config_dir(home),
+            Some(PathBuf::from("/home/me/.config/spotspoof"))
+        );
+
+        let xdg = env(&[
+            ("HOME", "/home/me"),
+            ("XDG_DATA_HOME", "/data"),
+            ("XDG_CONFIG_HOME", "relative"),
+        ]);
+        assert_eq!(user_data_dir(xdg), Some(PathBuf::from("/data/spotspoof")));
+        assert_eq!(
+            user_config_dir(xdg),
+            Some(PathBuf::from("/home/me/.config/spotspoof"))
+        );
+        assert_eq!(user_data_dir(env(&[])), None);
+    }
+}
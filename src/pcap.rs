@@ -0,0 +1,351 @@
+/*
+DNS extraction from packet captures for `scan-pcap` (the `pcap` feature).
+Reads pcap or pcapng, walks Ethernet/SLL/raw IP down to DNS over UDP or TCP
+port 53, and records each question name with the client that asked: the
+source of a query, or the destination of a response. A response whose query was
+also captured is not counted twice.
+*/
+use anyhow::Result;
+use pcap_parser::traits::PcapNGPacketBlock;
+use pcap_parser::{Block, Linktype, PcapBlockOwned, PcapError};
+use std::collections::HashSet;
+use std::io::Read;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use crate::dnswire::{be16, read_name};
+use crate::scan::Observations;
+
+const BUFFER_SIZE: usize = 1 << 20;
+const DNS_PORT: u16 = 53;
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const ETHERTYPE_IPV6: u16 = 0x86dd;
+const ETHERTYPE_VLAN: [u16; 2] = [0x8100, 0x88a8];
+const PROTO_TCP: u8 = 6;
+const PROTO_UDP: u8 = 17;
+
+/// Reads the DNS question names from a capture file (`-` for stdin).
+pub fn read(path: &str) -> Result<Observations> {
+    if path == "-" {
+        return read_from(std::io::stdin().lock());
+    }
+    let file =
+        std::fs::File::open(path).map_err(|err| anyhow::anyhow!("cannot read {path}: {err}"))?;
+    read_from(file)
+}
+
+pub fn read_from(input: impl Read) -> Result<Observations> {
+    let mut reader = pcap_parser::create_reader(BUFFER_SIZE, input)
+        .map_err(|err| anyhow::anyhow!("not a pcap or pcapng capture: {err}"))?;
+    // One link type per pcapng interface; a legacy pcap has exactly one.
+    let mut linktypes: Vec<Linktype> = Vec::new();
+    let mut capture = Capture::default();
+    loop {
+        match reader.next() {
+            Ok((offset, block)) => {
+                match block {
+                    PcapBlockOwned::LegacyHeader(header) => linktypes = vec![header.network],
+                    PcapBlockOwned::Legacy(packet) => {
+                        if let Some(linktype) = linktypes.first() {
+                            capture.frame(*linktype, packet.data);
+                        }
+                    }
+                    PcapBlockOwned::NG(Block::SectionHeader(_)) => linktypes.clear(),
+                    PcapBlockOwned::NG(Block::InterfaceDescription(interface)) => {
+                        linktypes.push(interface.linktype)
+                    }
+                    PcapBlockOwned::NG(Block::EnhancedPacket(packet)) => {
+                        if let Some(linktype) = linktypes.get(packet.if_id as usize) {
+                            capture.frame(*linktype, packet.packet_data());
+                        }
+                    }
+                    PcapBlockOwned::NG(Block::SimplePacket(packet)) => {
+                        if let Some(linktype) = linktypes.first() {
+                            capture.frame(*linktype, packet.packet_data());
+                        }
+                    }
+                    PcapBlockOwned::NG(_) => {}
+                }
+                reader.consume(offset);
+            }
+            Err(PcapError::Eof) => break,
+            Err(PcapError::Incomplete(_)) => reader
+                .refill()
+                .map_err(|err| anyhow::anyhow!("cannot read capture: {err}"))?,
+            Err(err) => return Err(anyhow::anyhow!("cannot read capture: {err}")),
+        }
+    }
+    Ok(capture.observations)
+}
+
+#[derive(Default)]
+struct Capture {
+    observations: Observations,
+    /// Queries awaiting their response: (client, client port, id, name).
+    pending: HashSet<(IpAddr, u16, u16, String)>,
+}
+
+impl Capture {
+    fn frame(&mut self, linktype: Linktype, data: &[u8]) {
+        let Some(packet) = ip_packet(linktype, data) else {
+            return;
+        };
+        let Some(segment) = transport(packet) else {
+            return;
+        };
+        let Some(message) = dns_message(&segment) else {
+            return;
+        };
+        for name in message.questions {
+            let (client, port) = if message.response {
+                (segment.dst, segment.dst_port)
+            } else {
+                (segment.src, segment.src_port)
+            };
+            let key = (client, port, message.id, name);
+            if message.response {
+                if self.pending.remove(&key) {
+                    continue;
+                }
+            } else {
+                self.pending.insert(key.clone());
+            }
+            self.observations.add(&key.3, Some(&client.to_string()));
+        }
+    }
+}
+
+/// The IP packet inside a link-layer frame.
+fn ip_packet(linktype: Linktype, data: &[u8]) -> Option<&[u8]> {
+    match linktype {
+        Linktype::ETHERNET => {
+            let mut offset = 12;
+            let mut ethertype = be16(data, offset)?;
+            while ETHERTYPE_VLAN.contains(&ethertype) {
+                offset += 4;
+                ethertype = be16(data, offset)?;
+            }
+            is_ip(ethertype).then(|| data.get(offset + 2..))?
+        }
+        Linktype::LINUX_SLL => is_ip(be16(data, 14)?).then(|| data.get(16..))?,
+        Linktype::LINUX_SLL2 => is_ip(be16(data, 0)?).then(|| data.get(20..))?,
+        Linktype::NULL | Linktype::LOOP => data.get(4..),
+        Linktype::RAW | Linktype::IPV4 | Linktype::IPV6 => Some(data),
+        _ => None,
+    }
+}
+
+fn is_ip(ethertype: u16) -> bool {
+    ethertype == ETHERTYPE_IPV4 || ethertype == ETHERTYPE_IPV6
+}
+
+/// A UDP datagram or TCP segment to or from port 53.
+struct Segment<'a> {
+    src: IpAddr,
+    dst: IpAddr,
+    src_port: u16,
+    dst_port: u16,
+    tcp: bool,
+    payload: &'a [u8],
+}
+
+fn transport(packet: &[u8]) -> Option<Segment<'_>> {
+    let (src, dst, protocol, body) = match packet.first()? >> 4 {
+        4 => {
+            let header_len = usize::from(packet[0] & 0x0f) * 4;
+            // Only the first fragment carries the transport header.
+            if be16(packet, 6)? & 0x1fff != 0 {
+                return None;
+            }
+            let total_len = usize::from(be16(packet, 2)?).min(packet.len());
+            let src: [u8; 4] = packet.get(12..16)?.try_into().ok()?;
+            let dst: [u8; 4] = packet.get(16..20)?.try_into().ok()?;
+            (
+                IpAddr::from(Ipv4Addr::from(src)),
+                IpAddr::from(Ipv4Addr::from(dst)),
+                packet[9],
+                packet.get(header_len..total_len)?,
+            )
+        }
+        6 => {
+            let src: [u8; 16] = packet.get(8..24)?.try_into().ok()?;
+            let dst: [u8; 16] = packet.get(24..40)?.try_into().ok()?;
+            let end = (40 + usize::from(be16(packet, 4)?)).min(packet.len());
+            (
+                IpAddr::from(Ipv6Addr::from(src)),
+                IpAddr::from(Ipv6Addr::from(dst)),
+                packet[6],
+                packet.get(40..end)?,
+            )
+        }
+        _ => return None,
+    };
+    let (header_len, tcp) = match protocol {
+        PROTO_UDP => (8, false),
+        PROTO_TCP => (usize::from(body.get(12)? >> 4) * 4, true),
+        _ => return None,
+    };
+    let segment = Segment {
+        src,
+        dst,
+        src_port: be16(body, 0)?,
+        dst_port: be16(body, 2)?,
+        tcp,
+        payload: body.get(header_len..)?,
+    };
+    (segment.src_port == DNS_PORT || segment.dst_port == DNS_PORT).then_some(segment)
+}
+
+struct Message {
+    id: u16,
+    response: bool,
+    questions: Vec<String>,
+}
+
+fn dns_message(segment: &Segment) -> Option<Message> {
+    let mut message = segment.payload;
+    if segment.tcp {
+        // DNS over TCP prefixes each message with its length; only messages
+        // that start a segment are read.
+        let len = usize::from(be16(message, 0)?);
+        message = message.get(2..2 + len)?;
+    }
+    let id = be16(message, 0)?;
+    let response = be16(message, 2)? & 0x8000 != 0;
+    let count = be16(message, 4)?;
+    let mut offset = 12;
+    let mut questions = Vec::new();
+    for _ in 0..count {
+        let (name, end) = read_name(message, offset)?;
+        // QTYPE and QCLASS follow each name.
+        offset = end + 4;
+        if offset > message.len() {
+            return None;
+        }
+        questions.push(name);
+    }
+    Some(Message {
+        id,
+        response,
+        questions,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dns(id: u16, response: bool, name: &str) -> Vec<u8> {
+        let mut message = id.to_be_bytes().to_vec();
+        message.extend_from_slice(&[
+            if response { 0x81 } else { 0x01 },
+            0x00,
+            0,
+            1,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+        ]);
+        for label in name.split('.') {
+            message.push(label.len() as u8);
+            message.extend_from_slice(label.as_bytes());
+        }
+        message.extend_from_slice(&[0, 0, 1, 0, 1]);
+        message
+    }
+
+    /// An Ethernet/IPv4/UDP frame (checksums are not verified).
+    fn udp_frame(src: [u8; 4], dst: [u8; 4], sport: u16, dport: u16, payload: &[u8]) -> Vec<u8> {
+        let mut frame = vec![0; 12];
+        frame.extend_from_slice(&ETHERTYPE_IPV4.to_be_bytes());
+        let total = (20 + 8 + payload.len()) as u16;
+        frame.extend_from_slice(&[0x45, 0]);
+        frame.extend_from_slice(&total.to_be_bytes());
+        frame.extend_from_slice(&[0, 0, 0, 0, 64, PROTO_UDP, 0, 0]);
+        frame.extend_from_slice(&src);
+        frame.extend_from_slice(&dst);
+        frame.extend_from_slice(&sport.to_be_bytes());
+        frame.extend_from_slice(&dport.to_be_bytes());
+        frame.extend_from_slice(&((8 + payload.len()) as u16).to_be_bytes());
+        frame.extend_from_slice(&[0, 0]);
+        frame.extend_from_slice(payload);
+        frame
+    }
+
+    fn pcap(frames: &[Vec<u8>]) -> Vec<u8> {
+        let mut file = Vec::new();
+        for value in [0xa1b2_c3d4u32, 0x0004_0002, 0, 0, 65535, 1] {
+            file.extend_from_slice(&value.to_le_bytes());
+        }
+        for frame in frames {
+            for value in [0u32, 0, frame.len() as u32, frame.len() as u32] {
+                file.extend_from_slice(&value.to_le_bytes());
+            }
+            file.extend_from_slice(frame);
+        }
+        file
+    }
+
+    #[test]
+    fn queries_and_responses_are_paired_per_client() {
+        let (client, other, resolver) = ([10, 0, 0, 5], [10, 0, 0, 6], [10, 0, 0, 1]);
+        let capture = pcap(&[
+            udp_frame(
+                client,
+                resolver,
+                40000,
+                53,
+                &dns(7, false, "login.paypa1.com"),
+            ),
+            udp_frame(
+                resolver,
+                client,
+                53,
+                40000,
+                &dns(7, true, "login.paypa1.com"),
+            ),
+            // A response whose query was not captured still counts.
+            udp_frame(resolver, other, 53, 40001, &dns(9, true, "paypa1.com")),
+            udp_frame(
+                client,
+                resolver,
+                40002,
+                53,
+                &dns(8, false, "1.0.0.10.in-addr.arpa"),
+            ),
+            udp_frame(client, resolver, 40003, 123, b"not dns"),
+        ]);
+        let observations = read_from(capture.as_slice()).unwrap().into_vec();
+        assert_eq!(observations.len(), 1);
+        assert_eq!(observations[0].domain, "paypa1.com");
+        assert_eq!(observations[0].occurrences, 2);
+        assert_eq!(
+            observations[0].clients.iter().collect::<Vec<_>>(),
+            ["10.0.0.5", "10.0.0.6"]
+        );
+        assert!(read_from(&b"not a capture file at all"[..]).is_err());
+    }
+
+    #[test]
+    fn compressed_names_are_followed() {
+        // Header, then "example.com" at 12 and a pointer back to it.
+        let mut message = dns(1, false, "example.com");
+        message[5] = 2;
+        message.extend_from_slice(&[0xc0, 12, 0, 1, 0, 1]);
+        let segment = Segment {
+            src: Ipv4Addr::LOCALHOST.into(),
+            dst: Ipv4Addr::LOCALHOST.into(),
+            src_port: 5000,
+            dst_port: DNS_PORT,
+            tcp: false,
+            payload: &message,
+        };
+        let parsed = dns_message(&segment).unwrap();
+        assert_eq!(parsed.questions, ["example.com", "example.com"]);
+
+        // A pointer loop is rejected instead of spinning.
+        assert!(read_name(&[0xc0, 0], 0).is_none());
+    }
+}
@@ -0,0 +1,349 @@
+/*
+Lookalike permutations of a protected domain, for exports that block squats
+before they are used. The first label of the registrable domain is typo'd
+(omission, repetition, transposition, keyboard replacement and insertion,
+bit flips, vowel swaps, hyphens, dots), dressed up with ASCII and Unicode
+homoglyphs, or put under other common TLDs. Results are valid, deduped
+hostnames (IDN ones in punycode), in a stable order. Punycode input is
+permuted as it reads, so `xn--e1afmkfd.xn--p1ai` is typo'd as `пример.рф`.
+*/
+use serde::Serialize;
+use std::collections::HashSet;
+
+use crate::{idn, types};
+
+const ALPHABET: &str = "abcdefghijklmnopqrstuvwxyz0123456789";
+const VOWELS: &str = "aeiou";
+/// ASCII sequences that read like one another.
+const ASCII_HOMOGLYPHS: [(&str, &str); 14] = [
+    ("o", "0"),
+    ("0", "o"),
+    ("l", "1"),
+    ("1", "l"),
+    ("i", "1"),
+    ("i", "l"),
+    ("l", "i"),
+    ("m", "rn"),
+    ("rn", "m"),
+    ("m", "nn"),
+    ("w", "vv"),
+    ("vv", "w"),
+    ("d", "cl"),
+    ("g", "q"),
+];
+/// TLDs squatters favour, tried in place of the brand's own.
+const TLDS: [&str; 24] = [
+    "com", "net", "org", "info", "biz", "co", "io", "app", "xyz", "online", "site", "top", "shop",
+    "store", "live", "support", "services", "click", "link", "us", "uk", "de", "ru", "cn",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum Technique {
+    /// A character appended (`paypala.com`)
+    Addition,
+    /// One bit flipped in a character (`pa9pal.com`)
+    Bitsquatting,
+    /// ASCII lookalikes (`paypa1.com`, `rnicrosoft.com`)
+    Homoglyph,
+    /// A hyphen inserted (`pay-pal.com`)
+    Hyphenation,
+    /// A Unicode homoglyph, in punycode (`pаypal.com`)
+    Idn,
+    /// A neighbouring key inserted (`paypwal.com`)
+    Insertion,
+    /// A character dropped (`paypl.com`)
+    Omission,
+    /// A character doubled (`paypall.com`)
+    Repetition,
+    /// A character replaced by a neighbouring key (`paypak.com`)
+    Replacement,
+    /// A dot inserted (`pay.pal.com`)
+    Subdomain,
+    /// Another TLD (`paypal.shop`)
+    Tld,
+    /// Two neighbouring characters swapped (`papyal.com`)
+    Transposition,
+    /// A vowel replaced by another (`paypel.com`)
+    VowelSwap,
+}
+
+impl Technique {
+    pub const ALL: [Technique; 13] = [
+        Technique::Addition,
+        Technique::Bitsquatting,
+        Technique::Homoglyph,
+        Technique::Hyphenation,
+        Technique::Idn,
+        Technique::Insertion,
+        Technique::Omission,
+        Technique::Repetition,
+        Technique::Replacement,
+        Technique::Subdomain,
+        Technique::Tld,
+        Technique::Transposition,
+        Technique::VowelSwap,
+    ];
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Permutation {
+    /// ASCII (punycode) form.
+    pub domain: String,
+    pub technique: Technique,
+    /// Display form of IDN permutations.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub unicode: Option<String>,
+}
+
+/// Permutations of `domain` by `techniques` (all of them when empty), without
+/// `domain` itself.
+pub fn permutations(domain: &str, techniques: &[Technique]) -> Vec<Permutation> {
+    let domain = types::normalize_domain_input(domain);
+    let unicode = idna::domain_to_unicode(&domain).0;
+    let Some((name, tld)) = unicode.split_once('.') else {
+        return Vec::new();
+    };
+    let techniques = if techniques.is_empty() {
+        &Technique::ALL[..]
+    } else {
+        techniques
+    };
+    let chars: Vec<char> = name.chars().collect();
+    let mut seen = HashSet::from([idna::domain_to_ascii(&domain).unwrap_or(domain)]);
+    let mut out = Vec::new();
+    for &technique in techniques {
+        for candidate in names(technique, &chars) {
+            let (candidate, tld) = match technique {
+                Technique::Tld => (name.to_string(), candidate),
+                _ => (candidate, tld.to_string()),
+            };
+            let unicode = format!("{candidate}.{tld}");
+            let Some(ascii) = to_ascii(&unicode) else {
+                continue;
+            };
+            if seen.insert(ascii.clone()) {
+                out.push(Permutation {
+                    unicode: (ascii != unicode).then_some(unicode),
+                    domain: ascii,
+                    technique,
+                });
+            }
+        }
+    }
+    out
+}
+
+/// The permuted first labels (or TLDs, for `Tld`), unvalidated.
+fn names(technique: Technique, chars: &[char]) -> Vec<String> {
+    let join = |parts: &[&[char]]| -> String { parts.concat().into_iter().collect() };
+    let len = chars.len();
+    let mut out = Vec::new();
+    match technique {
+        Technique::Addition => {
+            out.extend(ALPHABET.chars().map(|c| join(&[chars, &[c]])));
+        }
+        Technique::Bitsquatting => {
+            for (idx, &c) in chars.iter().enumerate() {
+                for bit in 0..8 {
+                    let flipped = char::from(c as u8 ^ (1 << bit));
+                    if ALPHABET.contains(flipped) || flipped == '-' {
+                        out.push(join(&[&chars[..idx], &[flipped], &chars[idx + 1..]]));
+                    }
+                }
+            }
+        }
+        Technique::Homoglyph => {
+            let name: String = chars.iter().collect();
+            for (from, to) in ASCII_HOMOGLYPHS {
+                for (idx, _) in name.match_indices(from) {
+                    out.push(format!("{}{to}{}", &name[..idx], &name[idx + from.len()..]));
+                }
+            }
+        }
+        Technique::Hyphenation => {
+            for idx in 1..len {
+                out.push(join(&[&chars[..idx], &['-'], &chars[idx..]]));
+            }
+        }
+        Technique::Idn => {
+            for (idx, &c) in chars.iter().enumerate() {
+                for &glyph in idn::homoglyphs_of(c) {
+                    out.push(join(&[&chars[..idx], &[glyph], &chars[idx + 1..]]));
+                }
+            }
+        }
+        Technique::Insertion => {
+            for (idx, &c) in chars.iter().enumerate() {
+                for key in neighbours(c).chars() {
+                    out.push(join(&[&chars[..idx], &[key, c], &chars[idx + 1..]]));
+                    out.push(join(&[&chars[..idx], &[c, key], &chars[idx + 1..]]));
+                }
+            }
+        }
+        Technique::Omission => {
+            for idx in 0..len {
+                out.push(join(&[&chars[..idx], &chars[idx + 1..]]));
+            }
+        }
+        Technique::Repetition => {
+            for (idx, &c) in chars.iter().enumerate() {
+                out.push(join(&[&chars[..idx], &[c, c], &chars[idx + 1..]]));
+            }
+        }
+        Technique::Replacement => {
+            for (idx, &c) in chars.iter().enumerate() {
+                for key in neighbours(c).chars() {
+                    out.push(join(&[&chars[..idx], &[key], &chars[idx + 1..]]));
+                }
+            }
+        }
+        Technique::Subdomain => {
+            for idx in 1..len {
+                out.push(join(&[&chars[..idx], &['.'], &chars[idx..]]));
+            }
+        }
+        Technique::Tld => out.extend(TLDS.iter().map(|tld| tld.to_string())),
+        Technique::Transposition => {
+            for idx in 0..len.saturating_sub(1) {
+                if chars[idx] != chars[idx + 1] {
+                    let mut swapped = chars.to_vec();
+                    swapped.swap(idx, idx + 1);
+                    out.push(swapped.into_iter().collect());
+                }
+            }
+        }
+        Technique::VowelSwap => {
+            for (idx, &c) in chars.iter().enumerate() {
+                if VOWELS.contains(c) {
+                    for vowel in VOWELS.chars().filter(|vowel| *vowel != c) {
+                        out.push(join(&[&chars[..idx], &[vowel], &chars[idx + 1..]]));
+                    }
+                }
+            }
+        }
+    }
+    out
+}
+
+/// The ASCII form of `candidate`, if it is a valid hostname.
+fn to_ascii(candidate: &str) -> Option<String> {
+    types::validate_domain_input(candidate).ok()?;
+    let ascii = idna::domain_to_ascii(candidate).ok()?;
+    // Characters that UTS 46 maps back to ASCII are not homoglyphs.
+    if !candidate.is_ascii() && !ascii.contains("xn--") {
+        return None;
+    }
+    Some(ascii)
+}
+
+/// Neighbouring keys on a QWERTY keyboard.
+fn neighbours(c: char) -> &'static str {
+    match c {
+        '1' => "2q",
+        '2' => "3wq1",
+        '3' => "4ew2",
+        '4' => "5re3",
+        '5' => "6tr4",
+        '6' => "7yt5",
+        '7' => "8uy6",
+        '8' => "9iu7",
+        '9' => "0oi8",
+        '0' => "po9",
+        'q' => "12wa",
+        'w' => "3esaq2",
+        'e' => "4rdsw3",
+        'r' => "5tfde4",
+        't' => "6ygfr5",
+        'y' => "7uhgt6",
+        'u' => "8ijhy7",
+        'i' => "9okju8",
+        'o' => "0plki9",
+        'p' => "lo0",
+        'a' => "qwsz",
+        's' => "edxzaw",
+        'd' => "rfcxse",
+        'f' => "tgvcdr",
+        'g' => "yhbvft",
+        'h' => "ujnbgy",
+        'j' => "ikmnhu",
+        'k' => "olmji",
+        'l' => "kop",
+        'z' => "asx",
+        'x' => "zsdc",
+        'c' => "xdfv",
+        'v' => "cfgb",
+        'b' => "vghn",
+        'n' => "bhjm",
+        'm' => "njk",
+        _ => "",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn domains(domain: &str, technique: Technique) -> Vec<String> {
+        permutations(domain, &[technique])
+            .into_iter()
+            .map(|permutation| permutation.domain)
+            .collect()
+    }
+
+    #[test]
+    fn typo_techniques_permute_the_first_label() {
+        assert_eq!(
+            domains("www.abc.com", Technique::Omission),
+            ["bc.com", "ac.com", "ab.com"]
+        );
+        assert_eq!(
+            domains("abc.com", Technique::Transposition),
+            ["bac.com", "acb.com"]
+        );
+        assert_eq!(domains("ab.com", Technique::Hyphenation), ["a-b.com"]);
+        assert_eq!(domains("ab.com", Technique::Subdomain), ["a.b.com"]);
+        assert!(domains("paypal.com", Technique::Homoglyph).contains(&"paypa1.com".to_string()));
+        assert!(domains("paypal.com", Technique::Replacement).contains(&"paypak.com".to_string()));
+        assert!(domains("paypal.com", Technique::Tld).contains(&"paypal.shop".to_string()));
+        assert!(!domains("paypal.com", Technique::Tld).contains(&"paypal.com".to_string()));
+        // A leading hyphen is not a valid label.
+        assert!(!domains("ab.com", Technique::Bitsquatting)
+            .iter()
+            .any(|domain| domain.starts_with('-')));
+    }
+
+    #[test]
+    fn idn_permutations_are_punycode_with_their_display_form() {
+        let permutations = permutations("apple.com", &[Technique::Idn]);
+        let cyrillic = permutations
+            .iter()
+            .find(|permutation| permutation.unicode.as_deref() == Some("аpple.com"))
+            .unwrap();
+        assert_eq!(cyrillic.domain, "xn--pple-43d.com");
+        assert!(permutations
+            .iter()
+            .all(|permutation| permutation.domain.contains("xn--")));
+    }
+
+    #[test]
+    fn internationalized_names_are_permuted_as_they_read() {
+        let omissions = permutations("xn--e1afmkfd.xn--p1ai", &[Technique::Omission]);
+        let first = &omissions[0];
+        assert_eq!(first.unicode.as_deref(), Some("ример.рф"));
+        assert!(first.domain.ends_with(".xn--p1ai"));
+        assert!(domains("пример.рф", Technique::Tld).contains(&"xn--e1afmkfd.com".to_string()));
+        assert!(!permutations("пример.рф", &[])
+            .iter()
+            .any(|permutation| permutation.domain == "xn--e1afmkfd.xn--p1ai"));
+    }
+
+    #[test]
+    fn permutations_are_unique_and_exclude_the_original() {
+        let all = permutations("google.com", &[]);
+        let unique: HashSet<&str> = all.iter().map(|p| p.domain.as_str()).collect();
+        assert_eq!(unique.len(), all.len());
+        assert!(!unique.contains("google.com"));
+        assert!(all.len() > 300);
+    }
+}
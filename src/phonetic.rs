@@ -0,0 +1,222 @@
+/*
+Phonetic similarity of domains. Squatters also register names that sound like
+a brand rather than look like it (`fasebook.com`, `gugel.com`), which can sit
+several edits away. The second-level label of each domain is reduced to its
+Soundex and Metaphone codes, and two domains are as similar as their closest
+pair of codes. The ASCII scorer blends this into the edit-distance score when
+phonetic scoring is on.
+*/
+use crate::ascii_spoof::similarity_with;
+use crate::types::Algorithm;
+
+/// Labels that sit under a country code as a registry's own second level
+/// (`co.uk`, `com.au`), so the label before them is the name.
+const SECOND_LEVELS: [&str; 7] = ["co", "com", "net", "org", "ac", "gov", "edu"];
+
+/// Similarity (0-100) of how `a` and `b` sound: the better of their Soundex
+/// and Metaphone code similarities. Names without letters score 0.
+pub fn similarity(a: &str, b: &str) -> u8 {
+    let (a, b) = (sld(a), sld(b));
+    let code_similarity = |a: String, b: String| {
+        if a.is_empty() || b.is_empty() {
+            0
+        } else {
+            similarity_with(Algorithm::Levenshtein, &a, &b)
+        }
+    };
+    code_similarity(soundex(a), soundex(b)).max(code_similarity(metaphone(a), metaphone(b)))
+}
+
+/// The label a domain is named by: `google` for `mail.google.co.uk`.
+pub fn sld(domain: &str) -> &str {
+    let labels: Vec<&str> = domain.trim_end_matches('.').split('.').collect();
+    match labels.as_slice() {
+        [.., name, second, tld] if tld.len() == 2 && SECOND_LEVELS.contains(second) => name,
+        [.., name, _] => name,
+        [name] => name,
+        [] => "",
+    }
+}
+
+/// American Soundex: the first letter, then up to three digits for the
+/// consonant groups that follow. Non-letters are ignored.
+pub fn soundex(word: &str) -> String {
+    let letters: Vec<char> = word
+        .chars()
+        .filter(char::is_ascii_alphabetic)
+        .map(|c| c.to_ascii_uppercase())
+        .collect();
+    let Some(&first) = letters.first() else {
+        return String::new();
+    };
+    let digit = |c: char| match c {
+        'B' | 'F' | 'P' | 'V' => Some('1'),
+        'C' | 'G' | 'J' | 'K' | 'Q' | 'S' | 'X' | 'Z' => Some('2'),
+        'D' | 'T' => Some('3'),
+        'L' => Some('4'),
+        'M' | 'N' => Some('5'),
+        'R' => Some('6'),
+        _ => None,
+    };
+    let mut code = String::from(first);
+    let mut last = digit(first);
+    for &c in &letters[1..] {
+        let current = digit(c);
+        if current.is_some() && current != last {
+            code.extend(current);
+        }
+        // `H` and `W` do not separate letters with the same code; vowels do.
+        if !matches!(c, 'H' | 'W') {
+            last = current;
+        }
+        if code.len() == 4 {
+            break;
+        }
+    }
+    format!("{code:0<4}")
+}
+
+/// Original Metaphone: a consonant skeleton of how an English reader would say
+/// `word` (`0` stands for `th`). Vowels are kept only at the start, and
+/// non-letters are ignored.
+pub fn metaphone(word: &str) -> String {
+    let mut letters: Vec<char> = word
+        .chars()
+        .filter(char::is_ascii_alphabetic)
+        .map(|c| c.to_ascii_uppercase())
+        .collect();
+    letters.dedup_by(|b, a| a == b && *a != 'C');
+    match letters.as_slice() {
+        ['A', 'E', ..] | ['G' | 'K' | 'P', 'N', ..] | ['W', 'R', ..] => {
+            letters.remove(0);
+        }
+        ['X', ..] => letters[0] = 'S',
+        ['W', 'H', ..] => {
+            letters.remove(1);
+        }
+        _ => {}
+    }
+
+    let at = |i: usize| letters.get(i).copied();
+    let is_vowel = |c: Option<char>| matches!(c, Some('A' | 'E' | 'I' | 'O' | 'U'));
+    let is_front = |c: Option<char>| matches!(c, Some('E' | 'I' | 'Y'));
+    let mut code = String::new();
+    for (i, &c) in letters.iter().enumerate() {
+        let (prev, next, after) = (i.checked_sub(1).and_then(at), at(i + 1), at(i + 2));
+        match c {
+            'A' | 'E' | 'I' | 'O' | 'U' => {
+                if i == 0 {
+                    code.push(c);
+                }
+            }
+            'B' => {
+                if !(prev == Some('M') && next.is_none()) {
+                    code.push('B');
+                }
+            }
+            'C' => {
+                if next == Some('I') && after == Some('A') {
+                    code.push('X');
+                } else if next == Some('H') {
+                    code.push(if prev == Some('S') { 'K' } else { 'X' });
+                } else if is_front(next) {
+                    if prev != Some('S') {
+                        code.push('S');
+                    }
+                } else {
+                    code.push('K');
+                }
+            }
+            'D' => code.push(if next == Some('G') && is_front(after) {
+                'J'
+            } else {
+                'T'
+            }),
+            'G' => {
+                if next == Some('H') && !is_vowel(after) && after.is_some() {
+                    // Silent, as in `night`.
+                } else if next == Some('N')
+                    && (after.is_none() || (after == Some('E') && at(i + 3) == Some('D')))
+                {
+                    // Silent, as in `sign`.
+                } else if prev == Some('D') && is_front(next) {
+                    // Already sounded by the `D`, as in `edge`.
+                } else if is_front(next) {
+                    code.push('J');
+                } else {
+                    code.push('K');
+                }
+            }
+            'H' => {
+                // Silent in digraphs (`ch`, `sh`, ...) and after a vowel
+                // unless another follows (`ah`).
+                let after_digraph = matches!(prev, Some('C' | 'S' | 'P' | 'T' | 'G'));
+                let trailing = is_vowel(prev) && !is_vowel(next);
+                if !after_digraph && !trailing {
+                    code.push('H');
+                }
+            }
+            'K' => {
+                if prev != Some('C') {
+                    code.push('K');
+                }
+            }
+            'P' => code.push(if next == Some('H') { 'F' } else { 'P' }),
+            'Q' => code.push('K'),
+            'S' => {
+                if next == Some('H') || (next == Some('I') && matches!(after, Some('O' | 'A'))) {
+                    code.push('X');
+                } else {
+                    code.push('S');
+                }
+            }
+            'T' => {
+                if next == Some('I') && matches!(after, Some('O' | 'A')) {
+                    code.push('X');
+                } else if next == Some('H') {
+                    code.push('0');
+                } else if !(next == Some('C') && after == Some('H')) {
+                    code.push('T');
+                }
+            }
+            'V' => code.push('F'),
+            'W' | 'Y' => {
+                if is_vowel(next) {
+                    code.push(c);
+                }
+            }
+            'X' => code.push_str("KS"),
+            'Z' => code.push('S'),
+            _ => code.push(c),
+        }
+    }
+    code
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn codes_match_for_sound_alikes() {
+        assert_eq!(soundex("Robert"), "R163");
+        assert_eq!(soundex("Ashcraft"), "A261");
+        assert_eq!(soundex("gugel"), soundex("google"));
+        assert_eq!(metaphone("facebook"), "FSBK");
+        assert_eq!(metaphone("fasebook"), "FSBK");
+        assert_eq!(metaphone("school"), "SKL");
+        assert_eq!(metaphone("knight"), "NT");
+        assert_eq!(metaphone("thumb"), "0M");
+        assert_eq!(soundex("1234"), "");
+    }
+
+    #[test]
+    fn similarity_compares_second_level_labels() {
+        assert_eq!(sld("mail.google.co.uk"), "google");
+        assert_eq!(sld("gugel.com"), "gugel");
+        assert_eq!(similarity("gugel.com", "google.com"), 100);
+        assert_eq!(similarity("fasebook.com", "facebook.co.uk"), 100);
+        assert!(similarity("amazon.com", "google.com") < 50);
+        assert_eq!(similarity("123.com", "google.com"), 0);
+    }
+}
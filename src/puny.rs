@@ -0,0 +1,320 @@
+/*
+`spotspoof puny encode|decode`: converts a domain between its Unicode and
+`xn--` forms with full UTS #46 processing (mapping, normalization, the bidi and
+hyphen rules, STD3 and DNS length checks). Each label is also processed on its
+own, so a failure names the label at fault and every IDNA error category it
+hit, where `idna::domain_to_ascii` alone only says that something failed.
+`IdnaOptions` decodes IDN lookup queries the same way, with the processing
+choices (transitional, STD3, undecodable punycode) left to the caller.
+*/
+use serde::Serialize;
+
+pub use crate::types::IdnaError;
+use crate::types::PunycodeOverflow;
+
+/// The separators UTS #46 treats as label dots.
+const DOTS: [char; 4] = ['.', '\u{3002}', '\u{FF0E}', '\u{FF61}'];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// Unicode to `xn--`.
+    Encode,
+    /// `xn--` to Unicode.
+    Decode,
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct PunyReport {
+    pub input: String,
+    /// The converted domain; `None` when encoding failed. Decoding always
+    /// yields a best-effort form, with undecodable labels left as they were.
+    pub output: Option<String>,
+    pub labels: Vec<LabelReport>,
+    /// Errors of the whole name, including those no single label has (such as
+    /// a name over 253 characters).
+    pub errors: Vec<IdnaError>,
+}
+
+impl PunyReport {
+    pub fn is_ok(&self) -> bool {
+        self.errors.is_empty()
+    }
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct LabelReport {
+    pub input: String,
+    pub output: Option<String>,
+    pub errors: Vec<IdnaError>,
+}
+
+/// UTS #46 processing choices for decoding an IDN lookup query. The defaults
+/// match `idna::domain_to_unicode`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct IdnaOptions {
+    /// Map the deviation characters (`ß`, `ς`, ZWJ, ZWNJ) as IDNA 2003 did
+    /// instead of keeping them.
+    pub transitional: bool,
+    /// Reject ASCII characters hostnames may not contain (`_`, `*`, ...).
+    pub use_std3_ascii_rules: bool,
+    pub punycode_overflow: PunycodeOverflow,
+}
+
+impl IdnaOptions {
+    /// The Unicode form of `domain`, or every IDNA error category it hits.
+    pub fn to_unicode(&self, domain: &str) -> Result<String, Vec<IdnaError>> {
+        if domain.chars().any(char::is_whitespace) {
+            return Err(vec![error("whitespace")]);
+        }
+        let config = idna::Config::default()
+            .transitional_processing(self.transitional)
+            .use_std3_ascii_rules(self.use_std3_ascii_rules);
+        let (unicode, result) = config.to_unicode(domain);
+        let Err(errors) = result else {
+            return Ok(unicode);
+        };
+        let errors = categories(&errors);
+        if self.punycode_overflow == PunycodeOverflow::Reject
+            || !errors.iter().any(|error| error.category == "punycode")
+        {
+            return Err(errors);
+        }
+
+        // Decode label by label, keeping the ones that do not decode.
+        let mut labels = Vec::new();
+        let mut remaining: Vec<IdnaError> = Vec::new();
+        for label in domain.split(DOTS) {
+            let (unicode, result) = config.to_unicode(label);
+            let errors = result.err().map(|errors| categories(&errors));
+            match errors {
+                Some(errors) if errors.iter().any(|error| error.category == "punycode") => {
+                    labels.push(label.to_ascii_lowercase());
+                }
+                Some(errors) => {
+                    labels.push(unicode);
+                    for error in errors {
+                        if !remaining.contains(&error) {
+                            remaining.push(error);
+                        }
+                    }
+                }
+                None => labels.push(unicode),
+            }
+        }
+        if remaining.is_empty() {
+            Ok(labels.join("."))
+        } else {
+            Err(remaining)
+        }
+    }
+}
+
+pub fn encode(input: &str) -> PunyReport {
+    convert(input, Direction::Encode)
+}
+
+pub fn decode(input: &str) -> PunyReport {
+    convert(input, Direction::Decode)
+}
+
+pub fn convert(input: &str, direction: Direction) -> PunyReport {
+    let input = input.trim().to_string();
+    let domain = input.strip_suffix(DOTS).unwrap_or(&input);
+    let labels: Vec<LabelReport> = domain
+        .split(DOTS)
+        .map(|label| {
+            let (output, errors) = process(label, direction);
+            LabelReport {
+                input: label.to_string(),
+                output,
+                errors,
+            }
+        })
+        .collect();
+    let (mut output, errors) = process(domain, direction);
+    if direction == Direction::Decode {
+        // Built from the labels, so undecodable ones stay as they were.
+        let decoded: Vec<&str> = labels
+            .iter()
+            .map(|label| label.output.as_deref().unwrap_or(&label.input))
+            .collect();
+        output = Some(decoded.join("."));
+    }
+    PunyReport {
+        input,
+        output,
+        labels,
+        errors,
+    }
+}
+
+fn process(text: &str, direction: Direction) -> (Option<String>, Vec<IdnaError>) {
+    let config = idna::Config::default()
+        .use_std3_ascii_rules(true)
+        .check_hyphens(true)
+        .verify_dns_length(true);
+    match direction {
+        Direction::Encode => match config.to_ascii(text) {
+            Ok(ascii) => (Some(ascii), Vec::new()),
+            Err(errors) => (None, categories(&errors)),
+        },
+        Direction::Decode => {
+            let (unicode, result) = config.to_unicode(text);
+            let mut errors = result
+                .err()
+                .map(|errors| categories(&errors))
+                .unwrap_or_default();
+            if errors.iter().any(|error| error.category == "punycode") {
+                return (Some(text.to_string()), errors);
+            }
+            // `to_unicode` skips the DNS length checks; decoding should still
+            // flag names that could never resolve.
+            if let Err(length) = idna::Config::default()
+                .verify_dns_length(true)
+                .to_ascii(text)
+            {
+                for error in categories(&length) {
+                    if error.category.ends_with("_for_dns") && !errors.contains(&error) {
+                        errors.push(error);
+                    }
+                }
+            }
+            (Some(unicode), errors)
+        }
+    }
+}
+
+/// The categories set in `errors`. `idna::Errors` keeps its flags private and
+/// only lists the set ones in its `Debug` form, `Errors { punycode, nfc }`.
+fn categories(errors: &idna::Errors) -> Vec<IdnaError> {
+    format!("{errors:?}")
+        .trim_start_matches("Errors {")
+        .trim_end_matches('}')
+        .split(',')
+        .map(str::trim)
+        .filter(|category| !category.is_empty())
+        .map(error)
+        .collect()
+}
+
+fn error(category: &str) -> IdnaError {
+    IdnaError {
+        category: category.to_string(),
+        message: describe(category).to_string(),
+    }
+}
+
+fn describe(category: &str) -> &'static str {
+    match category {
+        "punycode" => "not valid punycode",
+        "check_hyphens" => "hyphen at the start or end, or in the 3rd and 4th positions",
+        "check_bidi" => "right-to-left characters break the bidi rule",
+        "start_combining_mark" => "starts with a combining mark",
+        "invalid_mapping" => "has a character IDNA maps to nothing valid",
+        "nfc" => "not in Unicode normalization form C",
+        "disallowed_by_std3_ascii_rules" => "has an ASCII character not allowed in hostnames",
+        "disallowed_mapped_in_std3" => "has a character that maps to one not allowed in hostnames",
+        "disallowed_character" => "has a character IDNA disallows",
+        "too_long_for_dns" => "longer than DNS allows (63 per label, 253 in all)",
+        "too_short_for_dns" => "empty label",
+        "disallowed_in_idna_2008" => "has a character IDNA 2008 disallows",
+        "whitespace" => "has whitespace",
+        _ => "IDNA processing failed",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn categories_of(errors: &[IdnaError]) -> Vec<&str> {
+        errors.iter().map(|error| error.category.as_str()).collect()
+    }
+
+    #[test]
+    fn round_trips_between_unicode_and_punycode() {
+        let encoded = encode("pаypal.com");
+        assert!(encoded.is_ok());
+        assert_eq!(encoded.output.as_deref(), Some("xn--pypal-4ve.com"));
+        assert_eq!(encoded.labels[0].output.as_deref(), Some("xn--pypal-4ve"));
+
+        let decoded = decode("XN--PYPAL-4VE.com.");
+        assert!(decoded.is_ok());
+        assert_eq!(decoded.output.as_deref(), Some("pаypal.com"));
+        assert_eq!(decoded.labels.len(), 2);
+
+        // Full UTS #46 mapping: case folding and ideographic dots.
+        assert_eq!(
+            encode("Bücher。DE").output.as_deref(),
+            Some("xn--bcher-kva.de")
+        );
+    }
+
+    #[test]
+    fn failures_name_the_label_and_categories() {
+        let encoded = encode("ok.-bad-.com");
+        assert!(!encoded.is_ok());
+        assert_eq!(encoded.output, None);
+        assert!(encoded.labels[0].errors.is_empty());
+        assert_eq!(categories_of(&encoded.labels[1].errors), ["check_hyphens"]);
+        assert_eq!(categories_of(&encoded.errors), ["check_hyphens"]);
+
+        let decoded = decode("xn--zz.com");
+        assert!(!decoded.is_ok());
+        assert_eq!(categories_of(&decoded.labels[0].errors), ["punycode"]);
+        assert_eq!(decoded.output.as_deref(), Some("xn--zz.com"));
+
+        let long = format!("{}.com", "a".repeat(64));
+        assert_eq!(
+            categories_of(&decode(&long).labels[0].errors),
+            ["too_long_for_dns"]
+        );
+        assert_eq!(
+            categories_of(&encode("a..com").labels[1].errors),
+            ["too_short_for_dns"]
+        );
+    }
+
+    #[test]
+    fn idna_options_control_decoding() {
+        let defaults = IdnaOptions::default();
+        assert_eq!(
+            defaults.to_unicode("xn--pypal-4ve.com").unwrap(),
+            "pаypal.com"
+        );
+        assert_eq!(defaults.to_unicode("faß.de").unwrap(), "faß.de");
+        let transitional = IdnaOptions {
+            transitional: true,
+            ..defaults
+        };
+        assert_eq!(transitional.to_unicode("faß.de").unwrap(), "fass.de");
+
+        assert!(defaults.to_unicode("my_host.com").is_ok());
+        let std3 = IdnaOptions {
+            use_std3_ascii_rules: true,
+            ..defaults
+        };
+        assert_eq!(
+            categories_of(&std3.to_unicode("my_host.com").unwrap_err()),
+            ["invalid_mapping", "disallowed_by_std3_ascii_rules"]
+        );
+
+        let overflow = "xn--99999999999999999999a.xn--pypal-4ve.com";
+        assert_eq!(
+            categories_of(&defaults.to_unicode(overflow).unwrap_err()),
+            ["punycode"]
+        );
+        let keep = IdnaOptions {
+            punycode_overflow: PunycodeOverflow::Keep,
+            ..defaults
+        };
+        assert_eq!(
+            keep.to_unicode(overflow).unwrap(),
+            "xn--99999999999999999999a.pаypal.com"
+        );
+        assert_eq!(
+            categories_of(&defaults.to_unicode("bad domain").unwrap_err()),
+            ["whitespace"]
+        );
+    }
+}
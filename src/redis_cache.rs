@@ -0,0 +1,133 @@
+/*
+Shared Redis cache for horizontally scaled `serve` (`--redis-url`). Instances
+behind a load balancer keep their in-memory LRU, but fall back to Redis on a
+local miss and write every new lookup response and DNS registration check to
+it with a TTL, so an identical query answered by one instance is not redone by
+the others. Keys are namespaced under `spotspoof:`; lookup keys include the
+DB's dataset version, so instances on different DBs never share answers.
+Redis errors are logged and treated as misses; they never fail a lookup.
+*/
+use anyhow::{Context, Result};
+use redis::Commands;
+use std::time::Duration;
+
+const KEY_PREFIX: &str = "spotspoof:";
+const POOL_SIZE: u32 = 8;
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(2);
+/// Reads and writes give up after this, so a slow Redis only costs a miss.
+const IO_TIMEOUT: Duration = Duration::from_millis(500);
+
+#[derive(Clone)]
+pub struct RedisCache {
+    pool: r2d2::Pool<redis::Client>,
+}
+
+impl RedisCache {
+    /// Connects to `url` (`redis://[:password@]host:port[/db]`); fails if
+    /// Redis cannot be reached.
+    pub fn connect(url: &str) -> Result<Self> {
+        let client = redis::Client::open(url).context("invalid --redis-url")?;
+        let pool = r2d2::Pool::builder()
+            .max_size(POOL_SIZE)
+            .min_idle(Some(1))
+            .connection_timeout(CONNECT_TIMEOUT)
+            .build(client)
+            .context("cannot connect to Redis")?;
+        Ok(Self { pool })
+    }
+
+    fn connection(&self) -> Result<r2d2::PooledConnection<redis::Client>> {
+        let conn = self.pool.get()?;
+        conn.set_read_timeout(Some(IO_TIMEOUT))?;
+        conn.set_write_timeout(Some(IO_TIMEOUT))?;
+        Ok(conn)
+    }
+
+    /// The value stored under `key`, if it has not expired.
+    pub fn get(&self, key: &str) -> Result<Option<String>> {
+        Ok(self.connection()?.get(format!("{KEY_PREFIX}{key}"))?)
+    }
+
+    /// Stores `value` under `key` for `ttl` (at least a second).
+    pub fn set(&self, key: &str, value: &str, ttl: Duration) -> Result<()> {
+        let secs = ttl.as_secs().max(1);
+        self.connection()?
+            .set_ex::<_, _, ()>(format!("{KEY_PREFIX}{key}"), value, secs)?;
+        Ok(())
+    }
+}
+
+/// A minimal in-process Redis speaking enough RESP for the cache (`GET`,
+/// `SETEX`, `PING`), for tests.
+#[cfg(test)]
+pub fn fake_server() -> String {
+    use std::collections::HashMap;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+
+    fn read_command(reader: &mut impl BufRead) -> Option<Vec<String>> {
+        let mut line = String::new();
+        reader.read_line(&mut line).ok().filter(|read| *read > 0)?;
+        let count: usize = line.trim_end().strip_prefix('*')?.parse().ok()?;
+        let mut args = Vec::with_capacity(count);
+        for _ in 0..count {
+            line.clear();
+            reader.read_line(&mut line).ok()?;
+            let len: usize = line.trim_end().strip_prefix('$')?.parse().ok()?;
+            let mut arg = vec![0; len + 2];
+            reader.read_exact(&mut arg).ok()?;
+            arg.truncate(len);
+            args.push(String::from_utf8(arg).ok()?);
+        }
+        Some(args)
+    }
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("redis://{}", listener.local_addr().unwrap());
+    let store: Arc<Mutex<HashMap<String, String>>> = Arc::default();
+    std::thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let store = store.clone();
+            std::thread::spawn(move || {
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut writer = stream;
+                while let Some(args) = read_command(&mut reader) {
+                    let mut store = store.lock().unwrap();
+                    let reply = match args.first().map(|cmd| cmd.to_ascii_uppercase()) {
+                        Some(cmd) if cmd == "PING" => "+PONG\r\n".to_string(),
+                        Some(cmd) if cmd == "GET" => match store.get(&args[1]) {
+                            Some(value) => format!("${}\r\n{value}\r\n", value.len()),
+                            None => "$-1\r\n".to_string(),
+                        },
+                        Some(cmd) if cmd == "SETEX" && args.len() == 4 => {
+                            store.insert(args[1].clone(), args[3].clone());
+                            "+OK\r\n".to_string()
+                        }
+                        _ => "-ERR unknown command\r\n".to_string(),
+                    };
+                    if writer.write_all(reply.as_bytes()).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+    });
+    url
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn values_round_trip_until_their_ttl() {
+        let cache = RedisCache::connect(&fake_server()).unwrap();
+        assert_eq!(cache.get("dns:example.com").unwrap(), None);
+        cache
+            .set("dns:example.com", "1", Duration::from_secs(60))
+            .unwrap();
+        assert_eq!(cache.get("dns:example.com").unwrap().as_deref(), Some("1"));
+        assert!(RedisCache::connect("not a url").is_err());
+    }
+}
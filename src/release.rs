@@ -0,0 +1,256 @@
+/*
+Release checks against the GitHub releases API. `spotspoof update` compares the
+latest published release with the dataset version and date recorded in the
+local DB's `meta` table, so the DB is only downloaded when a newer one exists;
+`spotspoof self-update` compares it with the running binary's version.
+*/
+use anyhow::Result;
+use reqwest::header;
+use rusqlite::Connection;
+use serde::Deserialize;
+use std::cmp::Ordering;
+use std::path::Path;
+
+use crate::types::DatasetInfo;
+use crate::{cipher, db, net, pack};
+
+pub const DEFAULT_RELEASES_URL: &str =
+    "https://api.github.com/repos/slimpagey/spotspoof-cli/releases/latest";
+/// The `meta` key holding when the installed dataset was published.
+pub const DATE_KEY: &str = "date";
+/// The `meta` key holding where the dataset's domains came from.
+pub const SOURCE_KEY: &str = "source";
+
+/// The parts of a GitHub release `update` and `self-update` look at.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+pub struct Release {
+    pub tag_name: String,
+    #[serde(default)]
+    pub published_at: Option<String>,
+    #[serde(default)]
+    pub assets: Vec<Asset>,
+}
+
+/// A file attached to a release.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+pub struct Asset {
+    pub name: String,
+    pub browser_download_url: String,
+}
+
+impl Release {
+    /// The dataset version a release publishes: its tag without a leading `v`.
+    pub fn version(&self) -> &str {
+        self.tag_name.strip_prefix('v').unwrap_or(&self.tag_name)
+    }
+
+    /// The attached file called `name`.
+    pub fn asset(&self, name: &str) -> Option<&Asset> {
+        self.assets.iter().find(|asset| asset.name == name)
+    }
+}
+
+impl std::fmt::Display for Release {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "version {}", self.version())?;
+        if let Some(published_at) = &self.published_at {
+            write!(f, " (published {published_at})")?;
+        }
+        Ok(())
+    }
+}
+
+/// What the local DB says about itself.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LocalDataset {
+    pub version: Option<String>,
+    pub date: Option<String>,
+    pub domains: u64,
+    pub source: Option<String>,
+}
+
+impl LocalDataset {
+    /// Reads the dataset metadata of `db_path`; `None` when there is no DB.
+    pub fn read(db_path: &str) -> Result<Option<Self>> {
+        if !Path::new(db_path).is_file() {
+            return Ok(None);
+        }
+        let conn = db::open(db_path, false)?;
+        db::verify_schema(&conn)?;
+        Self::from_conn(&conn).map(Some)
+    }
+
+    pub fn from_conn(conn: &Connection) -> Result<Self> {
+        Ok(Self {
+            version: db::meta(conn, "version")?,
+            date: db::meta(conn, DATE_KEY)?,
+            domains: match db::meta(conn, pack::DOMAINS_KEY)?.and_then(|n| n.parse().ok()) {
+                Some(domains) => domains,
+                None => {
+                    conn.query_row("SELECT COUNT(*) FROM legit_domains", [], |row| row.get(0))?
+                }
+            },
+            source: db::meta(conn, SOURCE_KEY)?,
+        })
+    }
+
+    /// The `dataset` object of lookup and version responses.
+    pub fn info(&self) -> DatasetInfo {
+        DatasetInfo {
+            version: self.version.clone(),
+            date: self.date.clone(),
+            domains: self.domains,
+            source: self.source.clone(),
+        }
+    }
+
+    /// Whether `release` is newer than this dataset, or `None` when the DB
+    /// records neither a version nor a date to compare against.
+    pub fn is_older_than(&self, release: &Release) -> Option<bool> {
+        if let Some(version) = &self.version {
+            if let Some(order) = compare_versions(release.version(), version) {
+                return Some(order == Ordering::Greater);
+            }
+        }
+        // RFC 3339 timestamps in the same format order as strings.
+        if let (Some(published), Some(date)) = (&release.published_at, &self.date) {
+            return Some(published.as_str() > date.as_str());
+        }
+        self.version
+            .as_ref()
+            .map(|version| version != release.version())
+    }
+}
+
+impl std::fmt::Display for LocalDataset {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "version {}",
+            self.version.as_deref().unwrap_or("unknown")
+        )?;
+        if let Some(date) = &self.date {
+            write!(f, " (published {date})")?;
+        }
+        write!(f, ", {} domains", self.domains)?;
+        if let Some(source) = &self.source {
+            write!(f, " from {source}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Fetches the latest release from a GitHub releases API URL.
+pub fn latest(api_url: &str) -> Result<Release> {
+    net::ensure_online("Checking for a new release")?;
+    let client = net::client_builder().build()?;
+    let response = net::send_with_retry("release_check", || {
+        client
+            .get(api_url)
+            // The GitHub API rejects requests without a User-Agent.
+            .header(
+                header::USER_AGENT,
+                concat!("spotspoof/", env!("CARGO_PKG_VERSION")),
+            )
+            .header(header::ACCEPT, "application/vnd.github+json")
+    })?;
+    if !response.status().is_success() {
+        return Err(anyhow::anyhow!(
+            "release check at {api_url} failed: HTTP {}",
+            response.status()
+        ));
+    }
+    Ok(response.json()?)
+}
+
+/// The `dataset` object for lookups against `db_path`; `None` when there is
+/// no DB.
+pub fn dataset_info(db_path: &str) -> Result<Option<DatasetInfo>> {
+    Ok(LocalDataset::read(db_path)?.map(|dataset| dataset.info()))
+}
+
+/// Records the release and URL a freshly downloaded DB came from, unless the
+/// DB already carries its own version and source.
+pub fn record(db_path: &str, release: Option<&Release>, url: &str) -> Result<()> {
+    let conn = cipher::open(db_path)?;
+    if let Some(release) = release {
+        if db::meta(&conn, "version")?.is_none() {
+            db::set_meta(&conn, "version", release.version())?;
+            if let Some(published_at) = &release.published_at {
+                db::set_meta(&conn, DATE_KEY, published_at)?;
+            }
+        }
+    }
+    if db::meta(&conn, SOURCE_KEY)?.is_none() {
+        db::set_meta(&conn, SOURCE_KEY, url)?;
+    }
+    Ok(())
+}
+
+/// Compares dotted numeric versions (`2024.06.01`, `1.2.10`); `None` when
+/// either is not one.
+pub(crate) fn compare_versions(a: &str, b: &str) -> Option<Ordering> {
+    let parse = |version: &str| -> Option<Vec<u64>> {
+        version
+            .split(['.', '-'])
+            .map(|part| part.parse().ok())
+            .collect()
+    };
+    Some(parse(a)?.cmp(&parse(b)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn release(tag: &str, published_at: Option<&str>) -> Release {
+        Release {
+            tag_name: tag.into(),
+            published_at: published_at.map(str::to_string),
+            assets: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn newer_releases_are_detected_by_version_then_date() {
+        let local = LocalDataset {
+            version: Some("1.2.9".into()),
+            date: Some("2024-05-01T00:00:00Z".into()),
+            domains: 0,
+            source: None,
+        };
+        assert_eq!(local.is_older_than(&release("v1.2.10", None)), Some(true));
+        assert_eq!(local.is_older_than(&release("v1.2.9", None)), Some(false));
+        assert_eq!(local.is_older_than(&release("1.1.0", None)), Some(false));
+
+        let unversioned = LocalDataset {
+            version: None,
+            ..local.clone()
+        };
+        assert_eq!(
+            unversioned.is_older_than(&release("latest", Some("2024-06-01T00:00:00Z"))),
+            Some(true)
+        );
+        assert_eq!(
+            unversioned.is_older_than(&release("latest", Some("2024-04-01T00:00:00Z"))),
+            Some(false)
+        );
+        assert_eq!(
+            LocalDataset::default().is_older_than(&release("v2", None)),
+            None
+        );
+    }
+
+    #[test]
+    fn releases_parse_from_the_github_api() {
+        let release: Release = serde_json::from_str(
+            r#"{"tag_name": "v2024.06.01", "published_at": "2024-06-01T08:00:00Z", "assets": []}"#,
+        )
+        .unwrap();
+        assert_eq!(release.version(), "2024.06.01");
+        assert_eq!(
+            release.published_at.as_deref(),
+            Some("2024-06-01T08:00:00Z")
+        );
+    }
+}
@@ -0,0 +1,422 @@
+/*
+`spotspoof repl`: an interactive prompt for triage. Each line is either a
+domain, looked up with the session's settings, or a `:command` that changes
+them (`:set threshold 75`, `:mode idn`) or reviews the lookups made so far.
+Results are colored by risk when stdout is a terminal.
+*/
+use anyhow::Result;
+use clap::ValueEnum;
+use std::io::{BufRead, Write};
+
+use crate::ascii_spoof::{self, AsciiOptions};
+use crate::history;
+use crate::idn::{self, IdnOptions};
+use crate::release;
+use crate::types::{self, Algorithm, AsciiResponse, IdnResponse, RegistrationStatus};
+
+const PROMPT: &str = "spotspoof> ";
+const HELP: &str = "\
+Type a domain (or URL) to look it up, or a command:
+  :mode auto|ascii|idn        which analyzer to run (auto picks by domain)
+  :set                        show the current settings
+  :set threshold <0-100>      minimum ASCII similarity
+  :set max-results <N>        results shown per lookup
+  :set algorithm <NAME>       levenshtein or damerau-levenshtein
+  :set check on|off           check IDN variants for registration
+  :set leet on|off            also score with leet substitutions undone
+  :set phonetic on|off        blend in how alike the names sound
+  :history                    lookups made in this session
+  :help                       this help
+  :quit                       leave (also :q, :exit, Ctrl-D)";
+
+const RED: &str = "\x1b[31m";
+const YELLOW: &str = "\x1b[33m";
+const GREEN: &str = "\x1b[32m";
+const DIM: &str = "\x1b[2m";
+const RESET: &str = "\x1b[0m";
+
+/// ASCII similarities at or above these are shown in red / yellow.
+const HIGH_SIMILARITY: u8 = 90;
+const MEDIUM_SIMILARITY: u8 = 75;
+
+/// Which analyzer a lookup runs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Mode {
+    Auto,
+    Ascii,
+    Idn,
+}
+
+/// Whether the prompt should keep reading after a line.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Step {
+    Continue,
+    Quit,
+}
+
+/// One lookup of the session, as listed by `:history`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Entry {
+    query: String,
+    kind: &'static str,
+    results: usize,
+    top: Option<String>,
+}
+
+pub struct Repl {
+    db_path: Option<String>,
+    mode: Mode,
+    ascii: AsciiOptions,
+    idn: IdnOptions,
+    color: bool,
+    history: Vec<Entry>,
+}
+
+impl Repl {
+    /// A session looking ASCII candidates up in `db_path` (none without a DB).
+    pub fn new(db_path: Option<String>, ascii: AsciiOptions, idn: IdnOptions) -> Self {
+        Self {
+            db_path,
+            mode: Mode::Auto,
+            ascii,
+            idn,
+            color: false,
+            history: Vec::new(),
+        }
+    }
+
+    pub fn with_color(self, color: bool) -> Self {
+        Self { color, ..self }
+    }
+
+    /// Reads lines from `input` until `:quit` or end of input.
+    pub fn run(&mut self, mut input: impl BufRead, mut out: impl Write) -> Result<()> {
+        writeln!(
+            out,
+            "SpotSpoof {} - :help for commands",
+            env!("CARGO_PKG_VERSION")
+        )?;
+        let mut line = String::new();
+        loop {
+            write!(out, "{PROMPT}")?;
+            out.flush()?;
+            line.clear();
+            if input.read_line(&mut line)? == 0 {
+                writeln!(out)?;
+                return Ok(());
+            }
+            if self.handle(&line, &mut out)? == Step::Quit {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Handles one input line. Bad input is reported on `out`; only write
+    /// errors are returned.
+    pub fn handle(&mut self, line: &str, out: &mut impl Write) -> Result<Step> {
+        let line = line.trim();
+        if line.is_empty() {
+            return Ok(Step::Continue);
+        }
+        let outcome = match line.strip_prefix(':') {
+            Some(command) => self.command(command, out),
+            None => self.lookup(line, out),
+        };
+        match outcome {
+            Ok(step) => Ok(step),
+            Err(err) => {
+                writeln!(out, "{}", self.paint(RED, &format!("error: {err}")))?;
+                Ok(Step::Continue)
+            }
+        }
+    }
+
+    fn command(&mut self, command: &str, out: &mut impl Write) -> Result<Step> {
+        let words: Vec<&str> = command.split_whitespace().collect();
+        match words.as_slice() {
+            ["q" | "quit" | "exit"] => return Ok(Step::Quit),
+            ["help" | "h"] => writeln!(out, "{HELP}")?,
+            ["mode"] => writeln!(out, "mode {}", mode_name(self.mode))?,
+            ["mode", mode] => {
+                self.mode = Mode::from_str(mode, true)
+                    .map_err(|_| anyhow::anyhow!("unknown mode {mode} (auto, ascii, idn)"))?;
+                writeln!(out, "mode {}", mode_name(self.mode))?;
+            }
+            ["set"] => self.show_settings(out)?,
+            ["set", key, value] => {
+                self.set(key, value)?;
+                self.show_settings(out)?;
+            }
+            ["history"] => self.show_history(out)?,
+            _ => anyhow::bail!("unknown command :{command} (:help lists them)"),
+        }
+        Ok(Step::Continue)
+    }
+
+    fn set(&mut self, key: &str, value: &str) -> Result<()> {
+        match key {
+            "threshold" => {
+                self.ascii.min_similarity = value
+                    .parse()
+                    .ok()
+                    .filter(|threshold| *threshold <= 100)
+                    .ok_or_else(|| anyhow::anyhow!("threshold must be 0-100"))?;
+            }
+            "max-results" => {
+                let max_results = value
+                    .parse()
+                    .ok()
+                    .filter(|max_results| *max_results > 0)
+                    .ok_or_else(|| anyhow::anyhow!("max-results must be a positive number"))?;
+                self.ascii.max_results = max_results;
+                self.idn.max_results = max_results;
+                self.idn.max_results_timeout = self.idn.max_results_timeout.min(max_results);
+            }
+            "algorithm" => {
+                self.ascii.algorithm = Algorithm::from_str(value, true).map_err(|_| {
+                    anyhow::anyhow!("unknown algorithm {value} (levenshtein, damerau-levenshtein)")
+                })?;
+            }
+            "check" => self.idn.check_registration = on_off(key, value)?,
+            "leet" => self.ascii.leet = on_off(key, value)?,
+            "phonetic" => self.ascii.phonetic = on_off(key, value)?,
+            _ => anyhow::bail!(
+                "unknown setting {key} (threshold, max-results, algorithm, check, leet, phonetic)"
+            ),
+        }
+        Ok(())
+    }
+
+    fn show_settings(&self, out: &mut impl Write) -> Result<()> {
+        let algorithm = self
+            .ascii
+            .algorithm
+            .to_possible_value()
+            .map(|value| value.get_name().to_string())
+            .unwrap_or_default();
+        writeln!(
+            out,
+            "mode {}, threshold {}, max-results {}, algorithm {algorithm}, check {}, leet {}, phonetic {}, db {}",
+            mode_name(self.mode),
+            self.ascii.min_similarity,
+            self.ascii.max_results,
+            on_off_name(self.idn.check_registration),
+            on_off_name(self.ascii.leet),
+            on_off_name(self.ascii.phonetic),
+            self.db_path.as_deref().unwrap_or("none"),
+        )?;
+        Ok(())
+    }
+
+    fn show_history(&self, out: &mut impl Write) -> Result<()> {
+        if self.history.is_empty() {
+            writeln!(out, "{}", self.paint(DIM, "no lookups yet"))?;
+        }
+        for (index, entry) in self.history.iter().enumerate() {
+            let top = match &entry.top {
+                Some(top) => format!(" (top: {top})"),
+                None => String::new(),
+            };
+            writeln!(
+                out,
+                "{:>3}  {:<5} {}  {} result(s){top}",
+                index + 1,
+                entry.kind,
+                entry.query,
+                entry.results,
+            )?;
+        }
+        Ok(())
+    }
+
+    fn lookup(&mut self, input: &str, out: &mut impl Write) -> Result<Step> {
+        let domain = types::parse_domain_input(input)?;
+        let is_idn = match self.mode {
+            Mode::Auto => types::is_idn(&domain),
+            Mode::Ascii => false,
+            Mode::Idn => true,
+        };
+        if is_idn {
+            let response = idn::lookup_idn_with_options(&domain, &self.idn)?;
+            history::record_idn(history::Source::Cli, &response);
+            self.print_idn(&response, out)?;
+            self.history.push(Entry {
+                query: domain,
+                kind: "idn",
+                results: response.results.len(),
+                top: response.results.first().map(|result| result.domain.clone()),
+            });
+        } else {
+            let response = match &self.db_path {
+                Some(db_path) => {
+                    let mut response =
+                        ascii_spoof::lookup_ascii_with_options(&domain, db_path, &self.ascii)?;
+                    response.dataset = release::dataset_info(db_path)?;
+                    response
+                }
+                None => types::empty_ascii_response(&domain),
+            };
+            history::record_ascii(history::Source::Cli, &response);
+            self.print_ascii(&response, out)?;
+            self.history.push(Entry {
+                query: domain,
+                kind: "ascii",
+                results: response.results.len(),
+                top: response.results.first().map(|result| result.domain.clone()),
+            });
+        }
+        Ok(Step::Continue)
+    }
+
+    fn print_ascii(&self, response: &AsciiResponse, out: &mut impl Write) -> Result<()> {
+        if let Some(brand) = &response.protected_brand {
+            let message = format!("{} is the protected brand {brand}", response.q);
+            writeln!(out, "{}", self.paint(GREEN, &message))?;
+        } else if response.exact_match {
+            let message = format!("{} is a known legitimate domain", response.q);
+            writeln!(out, "{}", self.paint(GREEN, &message))?;
+        }
+        if response.results.is_empty() {
+            writeln!(out, "{}", self.paint(DIM, "no lookalike targets found"))?;
+        }
+        for result in &response.results {
+            let color = match result.similarity {
+                similarity if similarity >= HIGH_SIMILARITY => RED,
+                similarity if similarity >= MEDIUM_SIMILARITY => YELLOW,
+                _ => "",
+            };
+            let line = format!("{:>4}%  {}", result.similarity, result.domain);
+            writeln!(out, "{}", self.paint(color, &line))?;
+        }
+        Ok(())
+    }
+
+    fn print_idn(&self, response: &IdnResponse, out: &mut impl Write) -> Result<()> {
+        if response.results.is_empty() {
+            writeln!(out, "{}", self.paint(DIM, "no lookalike targets found"))?;
+        }
+        for result in &response.results {
+            let (status, color) = match result.registration_status {
+                Some(RegistrationStatus::Registered) => ("registered", RED),
+                Some(RegistrationStatus::Unknown) => ("unknown", YELLOW),
+                _ if !result.registration_checked => ("unchecked", DIM),
+                _ => ("unregistered", ""),
+            };
+            let mappings = result
+                .mappings
+                .iter()
+                .map(|mapping| format!("{} -> {}", mapping.unicode, mapping.ascii))
+                .collect::<Vec<_>>()
+                .join(", ");
+            let line = format!("{status:<12}  {}", result.domain);
+            writeln!(out, "{}  {mappings}", self.paint(color, &line))?;
+        }
+        Ok(())
+    }
+
+    fn paint(&self, color: &str, text: &str) -> String {
+        if self.color && !color.is_empty() {
+            format!("{color}{text}{RESET}")
+        } else {
+            text.to_string()
+        }
+    }
+}
+
+fn on_off(key: &str, value: &str) -> Result<bool> {
+    match value {
+        "on" | "true" | "yes" => Ok(true),
+        "off" | "false" | "no" => Ok(false),
+        _ => anyhow::bail!("{key} must be on or off"),
+    }
+}
+
+fn on_off_name(value: bool) -> &'static str {
+    if value {
+        "on"
+    } else {
+        "off"
+    }
+}
+
+fn mode_name(mode: Mode) -> &'static str {
+    match mode {
+        Mode::Auto => "auto",
+        Mode::Ascii => "ascii",
+        Mode::Idn => "idn",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn session() -> Repl {
+        Repl::new(None, AsciiOptions::default(), IdnOptions::default())
+    }
+
+    fn run(repl: &mut Repl, input: &str) -> String {
+        let mut out = Vec::new();
+        repl.run(input.as_bytes(), &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn commands_change_the_session_settings() {
+        let mut repl = session();
+        let output = run(
+            &mut repl,
+            ":set threshold 75\n:set algorithm damerau-levenshtein\n:mode idn\n:set check off\n:set leet on\n:set phonetic on\n:set threshold 150\n:bogus\n",
+        );
+        assert!(repl.ascii.leet);
+        assert!(repl.ascii.phonetic);
+        assert_eq!(repl.ascii.min_similarity, 75);
+        assert_eq!(repl.ascii.algorithm, Algorithm::DamerauLevenshtein);
+        assert_eq!(repl.mode, Mode::Idn);
+        assert!(!repl.idn.check_registration);
+        assert!(output.contains("error: threshold must be 0-100"));
+        assert!(output.contains("error: unknown command :bogus"));
+        assert_eq!(output.matches(PROMPT).count(), 9);
+    }
+
+    #[test]
+    fn lookups_are_listed_in_the_session_history() {
+        let mut repl = session();
+        let output = run(
+            &mut repl,
+            "example.com\nnot a domain\n:mode idn\n:set check off\nxn--pypal-4ve.com\n:history\n:quit\nignored.com\n",
+        );
+        assert!(output.contains("no lookalike targets found"));
+        assert!(output.contains("error: invalid domain"));
+        assert_eq!(repl.history.len(), 2);
+        assert_eq!(repl.history[0].kind, "ascii");
+        assert_eq!(repl.history[1].kind, "idn");
+        assert_eq!(repl.history[1].top.as_deref(), Some("paypal.com"));
+        assert!(output.contains("  1  ascii example.com  0 result(s)"));
+        assert!(!output.contains("ignored.com"));
+    }
+
+    #[test]
+    fn results_are_colored_only_when_enabled() {
+        let response = AsciiResponse {
+            results: vec![types::AsciiResult {
+                domain: "google.com".to_string(),
+                similarity: 90,
+                breakdown: None,
+            }],
+            ..types::empty_ascii_response("gooogle.com")
+        };
+        let mut plain = Vec::new();
+        session().print_ascii(&response, &mut plain).unwrap();
+        assert_eq!(String::from_utf8(plain).unwrap(), "  90%  google.com\n");
+        let mut colored = Vec::new();
+        session()
+            .with_color(true)
+            .print_ascii(&response, &mut colored)
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(colored).unwrap(),
+            format!("{RED}  90%  google.com{RESET}\n")
+        );
+    }
+}
@@ -0,0 +1,667 @@
+/*
+Batch scanning of arbitrary text. A tolerant extractor pulls domain-like tokens
+(bare hosts, URL hosts, email domains, defanged `example[.]com`) out of logs or
+raw messages, dedupes them by registrable domain, and runs each through the
+same lookups as `lookup` (or only against `--watch` targets), keeping only the
+domains that matched something. DNS telemetry (see `dnslog`) feeds the same
+scanner with the clients that asked for each name.
+*/
+use anyhow::Result;
+use serde::Serialize;
+use std::collections::{BTreeSet, HashMap};
+use tracing::warn;
+use unicode_normalization::char::is_combining_mark;
+use utoipa::ToSchema;
+
+use crate::ascii_spoof::{self, AsciiOptions};
+use crate::db::CandidateSource;
+use crate::detections::Severity;
+use crate::geoip::{self, Address};
+use crate::idn::{self, IdnOptions};
+use crate::passive_dns;
+use crate::tld_risk;
+use crate::types::{self, LookupResponse, PassiveDns};
+use crate::whois;
+
+/// Defanged dots seen in threat-intel write-ups and abuse reports.
+const DEFANGED_DOTS: [&str; 4] = ["[.]", "(.)", "{.}", "[dot]"];
+/// Suffixes that end file names far more often than hosts in logs. Real TLDs
+/// that phishers use (`.zip`, `.mov`) are deliberately not listed.
+const FILE_EXTENSIONS: [&str; 30] = [
+    "html", "htm", "php", "asp", "aspx", "jsp", "js", "css", "json", "xml", "txt", "log", "exe",
+    "dll", "png", "jpg", "jpeg", "gif", "svg", "ico", "pdf", "gz", "tar", "csv", "yml", "yaml",
+    "conf", "cfg", "ini", "tmp",
+];
+const MAX_TLD_LEN: usize = 24;
+/// ASCII domains handed to the candidate source per batch by `Scanner::scan`.
+const BATCH_SIZE: usize = 10_000;
+
+/// A registrable domain seen in the input.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Observation {
+    pub domain: String,
+    pub occurrences: usize,
+    /// Client addresses that queried it, when the input records them.
+    pub clients: BTreeSet<String>,
+}
+
+/// Deduplicates observed names in first-seen order.
+#[derive(Debug, Default)]
+pub struct Observations {
+    index: HashMap<String, usize>,
+    items: Vec<Observation>,
+}
+
+impl Observations {
+    /// Records `name` (any host name, reduced to its registrable domain) if it
+    /// looks like a domain. Returns whether it was kept.
+    pub fn add(&mut self, name: &str, client: Option<&str>) -> bool {
+        self.add_seen(name, client, 1)
+    }
+
+    /// Like `add`, for a name seen `count` times (at least once) at once.
+    pub fn add_count(&mut self, name: &str, count: usize) -> bool {
+        self.add_seen(name, None, count.max(1))
+    }
+
+    fn add_seen(&mut self, name: &str, client: Option<&str>, count: usize) -> bool {
+        let Some(domain) = domain_token(name) else {
+            return false;
+        };
+        let idx = *self.index.entry(domain.clone()).or_insert_with(|| {
+            self.items.push(Observation {
+                domain,
+                ..Observation::default()
+            });
+            self.items.len() - 1
+        });
+        let item = &mut self.items[idx];
+        item.occurrences += count;
+        if let Some(client) = client {
+            item.clients.insert(client.to_string());
+        }
+        true
+    }
+
+    /// Records every domain-like token in `text`, including defanged ones.
+    pub fn add_text(&mut self, text: &str) {
+        let mut text = text.to_string();
+        for defanged in DEFANGED_DOTS {
+            text = text.replace(defanged, ".");
+        }
+        for token in text
+            .split(|c: char| !(c.is_alphanumeric() || is_combining_mark(c) || c == '.' || c == '-'))
+        {
+            self.add(token, None);
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    pub fn into_vec(self) -> Vec<Observation> {
+        self.items
+    }
+}
+
+/// One scanned domain that matched, with how often it appeared.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct Finding {
+    pub domain: String,
+    /// The match score weighted by the domain's TLD (see `risk`).
+    pub risk: u8,
+    pub severity: Severity,
+    pub occurrences: usize,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub clients: Vec<String>,
+    pub lookup: LookupResponse,
+    /// The domain's passive DNS history, when `[passive_dns]` is configured.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub passive_dns: Option<PassiveDns>,
+    /// The domain's A records and where they are hosted, with `--resolve`.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub addresses: Vec<Address>,
+    /// When the domain was first recorded (RFC 3339, UTC), with `--detections`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub first_seen: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct ScanReport {
+    /// Unique domains looked up.
+    pub scanned: usize,
+    /// Riskiest first; equal risks stay in first-seen order.
+    pub findings: Vec<Finding>,
+    /// Findings left out because they were already triaged (`--detections`).
+    #[serde(skip_serializing_if = "is_zero")]
+    pub suppressed: usize,
+    /// The findings aggregated by impersonated brand, with `--group-by brand`.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub brands: Vec<BrandGroup>,
+}
+
+/// The lookalikes of one impersonated domain, for `--group-by brand`.
+#[derive(Debug, Clone, Serialize, ToSchema, PartialEq, Eq)]
+pub struct BrandGroup {
+    /// The domain being impersonated (the lookalikes' best match).
+    pub brand: String,
+    /// How many lookalikes target it.
+    pub count: usize,
+    /// Their occurrences (sightings, for detections) added up.
+    pub occurrences: u64,
+    pub max_risk: u8,
+    pub severity: Severity,
+    /// The earliest first sighting among them (RFC 3339, UTC), when recorded.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub first_seen: Option<String>,
+    /// In the order they were added.
+    pub domains: Vec<String>,
+}
+
+/// How `--group-by` aggregates findings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum GroupBy {
+    /// By the domain each finding impersonates
+    Brand,
+}
+
+impl ScanReport {
+    /// Fills `brands` when grouping by brand. Call it after dropping triaged
+    /// findings, so the groups match what is reported.
+    pub fn group(&mut self, group_by: Option<GroupBy>) {
+        if group_by == Some(GroupBy::Brand) {
+            self.brands = group_by_brand(&self.findings);
+        }
+    }
+}
+
+/// Accumulates `BrandGroup`s keyed by brand.
+#[derive(Debug, Default)]
+pub struct BrandGroups {
+    index: HashMap<String, usize>,
+    groups: Vec<BrandGroup>,
+}
+
+impl BrandGroups {
+    /// Counts `domain`, a lookalike of `brand`, towards its group.
+    pub fn add(
+        &mut self,
+        brand: &str,
+        domain: &str,
+        risk: u8,
+        occurrences: u64,
+        first_seen: Option<&str>,
+    ) {
+        let idx = *self.index.entry(brand.to_string()).or_insert_with(|| {
+            self.groups.push(BrandGroup {
+                brand: brand.to_string(),
+                count: 0,
+                occurrences: 0,
+                max_risk: 0,
+                severity: Severity::from_risk(0),
+                first_seen: None,
+                domains: Vec::new(),
+            });
+            self.groups.len() - 1
+        });
+        let group = &mut self.groups[idx];
+        group.count += 1;
+        group.occurrences += occurrences;
+        group.max_risk = group.max_risk.max(risk);
+        group.severity = Severity::from_risk(group.max_risk);
+        if let Some(first_seen) = first_seen {
+            // RFC 3339 UTC timestamps sort as strings.
+            if group
+                .first_seen
+                .as_deref()
+                .is_none_or(|seen| first_seen < seen)
+            {
+                group.first_seen = Some(first_seen.to_string());
+            }
+        }
+        group.domains.push(domain.to_string());
+    }
+
+    /// Riskiest brand first, then the most targeted; ties keep their order.
+    pub fn into_vec(mut self) -> Vec<BrandGroup> {
+        self.groups
+            .sort_by_key(|group| std::cmp::Reverse((group.max_risk, group.count)));
+        self.groups
+    }
+}
+
+/// `findings` grouped by the domain each one impersonates.
+pub fn group_by_brand(findings: &[Finding]) -> Vec<BrandGroup> {
+    let mut groups = BrandGroups::default();
+    for finding in findings {
+        if let Some(brand) = top_match(&finding.lookup) {
+            groups.add(
+                brand,
+                &finding.domain,
+                finding.risk,
+                finding.occurrences as u64,
+                finding.first_seen.as_deref(),
+            );
+        }
+    }
+    groups.into_vec()
+}
+
+/// Domain-like tokens in `text`, reduced to registrable domains and deduped in
+/// first-seen order, with their occurrence counts.
+pub fn extract_domains(text: &str) -> Vec<Observation> {
+    let mut observations = Observations::default();
+    observations.add_text(text);
+    observations.into_vec()
+}
+
+pub(crate) fn domain_token(token: &str) -> Option<String> {
+    let token = token.trim_matches(|c| c == '.' || c == '-').to_lowercase();
+    let (_, tld) = token.rsplit_once('.')?;
+    // Reverse-DNS names are addresses, not domains.
+    if tld == "arpa" {
+        return None;
+    }
+    // Internationalized TLDs may carry combining marks (the virama of `.भारतम्`).
+    let tld_ok = tld.starts_with("xn--")
+        || (tld.chars().count() >= 2
+            && tld.chars().count() <= MAX_TLD_LEN
+            && tld
+                .chars()
+                .all(|c| c.is_alphabetic() || is_combining_mark(c)));
+    if !tld_ok || FILE_EXTENSIONS.contains(&tld) {
+        return None;
+    }
+    types::parse_domain_input(&token).ok()
+}
+
+/// Runs lookups for many domains against one opened candidate source.
+pub struct Scanner {
+    source: Option<Box<dyn CandidateSource>>,
+    ascii: AsciiOptions,
+    /// `None` skips IDN domains (no DNS traffic).
+    idn: Option<IdnOptions>,
+    /// When set, domains are only scored against these.
+    targets: Vec<String>,
+    /// Resolve findings and locate their addresses (`--resolve`).
+    resolve: bool,
+}
+
+impl Scanner {
+    /// Without a `source`, ASCII lookups find nothing (as with `--no-db`).
+    pub fn new(
+        source: Option<Box<dyn CandidateSource>>,
+        ascii: AsciiOptions,
+        idn: Option<IdnOptions>,
+    ) -> Self {
+        Self {
+            source,
+            ascii,
+            idn,
+            targets: Vec::new(),
+            resolve: false,
+        }
+    }
+
+    /// Scores domains only against `targets` (e.g. `--watch` brands) instead
+    /// of the whole dataset.
+    pub fn with_targets(mut self, targets: Vec<String>) -> Self {
+        self.targets = targets
+            .iter()
+            .map(|target| types::normalize_domain_input(target))
+            .collect();
+        self
+    }
+
+    /// Resolves each finding's A records and adds where they are hosted.
+    pub fn with_resolve(mut self, resolve: bool) -> Self {
+        self.resolve = resolve;
+        self
+    }
+
+    /// Looks up `domain`; `None` when nothing matched or it was skipped.
+    pub fn lookup(&self, domain: &str) -> Result<Option<LookupResponse>> {
+        if !self.targets.is_empty() {
+            return self.lookup_targets(domain);
+        }
+        if types::is_idn(domain) {
+            let Some(options) = &self.idn else {
+                return Ok(None);
+            };
+            let response = idn::lookup_idn_with_options(domain, options)?;
+            return Ok((!response.results.is_empty()).then_some(LookupResponse::Idn(response)));
+        }
+        let Some(source) = &self.source else {
+            return Ok(None);
+        };
+        let response = ascii_spoof::lookup_ascii_with(domain, || Ok(&**source), &self.ascii)?;
+        Ok((!response.results.is_empty()).then_some(LookupResponse::Ascii(response)))
+    }
+
+    fn lookup_targets(&self, domain: &str) -> Result<Option<LookupResponse>> {
+        if self.targets.iter().any(|target| target == domain) {
+            return Ok(None);
+        }
+        if types::is_idn(domain) {
+            let Some(options) = &self.idn else {
+                return Ok(None);
+            };
+            // The ASCII lookalikes are the targets themselves, so there is
+            // nothing to confirm over DNS.
+            let options = IdnOptions {
+                check_registration: false,
+                ..options.clone()
+            };
+            let mut response = idn::lookup_idn_with_options(domain, &options)?;
+            response
+                .results
+                .retain(|result| self.targets.contains(&result.domain));
+            return Ok((!response.results.is_empty()).then_some(LookupResponse::Idn(response)));
+        }
+        let mut response = types::empty_ascii_response(domain);
+        response.results = ascii_spoof::score_targets(domain, &self.targets, &self.ascii);
+        Ok((!response.results.is_empty()).then_some(LookupResponse::Ascii(response)))
+    }
+
+    /// `lookup` for each observation, in order. ASCII domains are looked up
+    /// `BATCH_SIZE` at a time so the source can share one scan between
+    /// domains with the same first character and length.
+    fn lookup_all(&self, observations: &[Observation]) -> Result<Vec<Option<LookupResponse>>> {
+        let Some(source) = self.source.as_ref().filter(|_| self.targets.is_empty()) else {
+            return observations
+                .iter()
+                .map(|observation| self.lookup(&observation.domain))
+                .collect();
+        };
+        let mut lookups = Vec::with_capacity(observations.len());
+        for chunk in observations.chunks(BATCH_SIZE) {
+            let ascii: Vec<String> = chunk
+                .iter()
+                .map(|observation| &observation.domain)
+                .filter(|domain| !types::is_idn(domain))
+                .cloned()
+                .collect();
+            let mut responses =
+                ascii_spoof::lookup_ascii_batch(&ascii, &**source, &self.ascii)?.into_iter();
+            for observation in chunk {
+                let lookup = if types::is_idn(&observation.domain) {
+                    self.lookup(&observation.domain)?
+                } else {
+                    responses
+                        .next()
+                        .filter(|response| !response.results.is_empty())
+                        .map(LookupResponse::Ascii)
+                };
+                lookups.push(lookup);
+            }
+        }
+        Ok(lookups)
+    }
+
+    pub fn scan(&self, observations: Vec<Observation>) -> Result<ScanReport> {
+        let scanned = observations.len();
+        let lookups = self.lookup_all(&observations)?;
+        let mut findings = Vec::new();
+        for (observation, lookup) in observations.into_iter().zip(lookups) {
+            if let Some(lookup) = lookup {
+                let risk = risk(&observation.domain, &lookup);
+                findings.push(Finding {
+                    risk,
+                    severity: Severity::from_risk(risk),
+                    domain: observation.domain,
+                    occurrences: observation.occurrences,
+                    clients: observation.clients.into_iter().collect(),
+                    lookup,
+                    passive_dns: None,
+                    addresses: Vec::new(),
+                    first_seen: None,
+                });
+            }
+        }
+        findings.sort_by_key(|finding| std::cmp::Reverse(finding.risk));
+        for finding in findings.iter_mut().take(passive_dns::max_lookups()) {
+            finding.passive_dns = passive_dns::lookup(&finding.domain);
+        }
+        if self.resolve {
+            let timeout_ms = IdnOptions::from_env().whois_timeout_ms;
+            for finding in &mut findings {
+                match whois::resolve_a(&finding.domain, timeout_ms) {
+                    Ok(ips) => finding.addresses = ips.into_iter().map(geoip::describe).collect(),
+                    Err(err) => {
+                        warn!(kind = "resolve", domain = %finding.domain, "resolving failed: {err}")
+                    }
+                }
+            }
+        }
+        Ok(ScanReport {
+            scanned,
+            findings,
+            suppressed: 0,
+            brands: Vec::new(),
+        })
+    }
+}
+
+fn is_zero(count: &usize) -> bool {
+    *count == 0
+}
+
+/// Whether `lookup` found a lookalike rather than a known domain that merely
+/// resembles other known domains.
+pub fn is_lookalike(lookup: &LookupResponse) -> bool {
+    match lookup {
+        LookupResponse::Ascii(data) => !data.exact_match && !data.results.is_empty(),
+        LookupResponse::Idn(data) => !data.results.is_empty(),
+    }
+}
+
+/// How close the best match of `lookup` is, 0-100. Homoglyph matches look
+/// identical, so they score 100.
+pub fn score(lookup: &LookupResponse) -> u8 {
+    match lookup {
+        LookupResponse::Ascii(data) => data
+            .results
+            .iter()
+            .map(|result| result.similarity)
+            .max()
+            .unwrap_or(0),
+        LookupResponse::Idn(data) => {
+            if data.results.is_empty() {
+                0
+            } else {
+                100
+            }
+        }
+    }
+}
+
+/// The composite risk of `domain`: the `score` of its lookup weighted by the
+/// installed TLD risk table, so a lookalike on `.top` outranks the same string
+/// on `.gov`.
+pub fn risk(domain: &str, lookup: &LookupResponse) -> u8 {
+    tld_risk::installed().apply(score(lookup), domain)
+}
+
+/// The best match of `lookup`: the top ASCII result, or the first registered
+/// IDN variant.
+pub fn top_match(lookup: &LookupResponse) -> Option<&str> {
+    match lookup {
+        LookupResponse::Ascii(data) => data
+            .results
+            .iter()
+            .max_by_key(|result| result.similarity)
+            .map(|result| result.domain.as_str()),
+        LookupResponse::Idn(data) => data
+            .results
+            .iter()
+            .find(|result| result.is_registered)
+            .or(data.results.first())
+            .map(|result| result.domain.as_str()),
+    }
+}
+
+/// "resembles paypal.com (similarity 90)" for the best match of `lookup`.
+pub fn resemblance(lookup: &LookupResponse) -> String {
+    match lookup {
+        LookupResponse::Ascii(data) => data
+            .results
+            .iter()
+            .max_by_key(|result| result.similarity)
+            .map(|result| {
+                format!(
+                    "resembles {} (similarity {})",
+                    result.domain, result.similarity
+                )
+            })
+            .unwrap_or_default(),
+        LookupResponse::Idn(data) => data
+            .results
+            .first()
+            .map(|result| format!("is a homoglyph of {}", result.domain))
+            .unwrap_or_default(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extracts_hosts_from_urls_emails_and_defanged_text() {
+        let text = "GET https://login.paypa1.com:8443/signin?next=/ HTTP/1.1\n\
+            From: \"Support\" <help@Micros0ft-support.com>\n\
+            see hxxp://evil[.]example[.]org and login.paypa1.com again\n\
+            served /static/app.js and report.pdf from 10.0.0.1, version 1.2.3\n\
+            -- bare-word. trailing.dot.net.";
+        let found: Vec<(String, usize)> = extract_domains(text)
+            .into_iter()
+            .map(|observation| (observation.domain, observation.occurrences))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                ("paypa1.com".to_string(), 2),
+                ("micros0ft-support.com".to_string(), 1),
+                ("example.org".to_string(), 1),
+                ("dot.net".to_string(), 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn keeps_idn_and_phishing_tlds() {
+        let domains: Vec<String> =
+            extract_domains("xn--pple-43d.com аррle.com invoice.zip пример.рф सरकार.भारतम्")
+                .into_iter()
+                .map(|observation| observation.domain)
+                .collect();
+        assert_eq!(
+            domains,
+            [
+                "xn--pple-43d.com",
+                "аррle.com",
+                "invoice.zip",
+                "пример.рф",
+                "सरकार.भारतम्"
+            ]
+        );
+    }
+
+    #[test]
+    fn scanner_reports_only_matches() {
+        let conn = crate::db::test_db(&["example.com"]);
+        let scanner = Scanner::new(Some(Box::new(conn)), AsciiOptions::default(), None);
+        let report = scanner
+            .scan(extract_domains(
+                "exampel.com exampel.com unrelated-site.net xn--pple-43d.com exampie.com",
+            ))
+            .unwrap();
+        assert_eq!(report.scanned, 4);
+        // Both typos share one batched scan of the `e` bucket.
+        let found: Vec<(&str, usize)> = report
+            .findings
+            .iter()
+            .map(|finding| (finding.domain.as_str(), finding.occurrences))
+            .collect();
+        assert!(found.contains(&("exampel.com", 2)));
+        assert!(found.contains(&("exampie.com", 1)));
+        assert_eq!(found.len(), 2);
+    }
+
+    #[test]
+    fn watch_targets_replace_the_dataset() {
+        let scanner = Scanner::new(None, AsciiOptions::default(), None)
+            .with_targets(vec!["example.com".to_string()]);
+        let mut observations = Observations::default();
+        for (name, client) in [
+            ("www.exampel.com", "10.0.0.2"),
+            ("exampel.com", "10.0.0.1"),
+            ("example.com", "10.0.0.1"),
+            ("4.3.2.1.in-addr.arpa", "10.0.0.1"),
+        ] {
+            observations.add(name, Some(client));
+        }
+        assert_eq!(observations.len(), 2);
+        let report = scanner.scan(observations.into_vec()).unwrap();
+        assert_eq!(report.findings.len(), 1);
+        assert_eq!(report.findings[0].domain, "exampel.com");
+        assert_eq!(report.findings[0].clients, ["10.0.0.1", "10.0.0.2"]);
+    }
+
+    #[test]
+    fn findings_are_ordered_by_tld_weighted_risk() {
+        let targets = ["paypal.gov", "paypal.com", "paypal.top"];
+        let scanner = Scanner::new(None, AsciiOptions::default(), None)
+            .with_targets(targets.iter().map(|target| target.to_string()).collect());
+        let report = scanner
+            .scan(extract_domains("paypa1.gov paypa1.com paypa1.top"))
+            .unwrap();
+        let ranked: Vec<(&str, u8)> = report
+            .findings
+            .iter()
+            .map(|finding| (finding.domain.as_str(), finding.risk))
+            .collect();
+        assert_eq!(
+            ranked,
+            [("paypa1.top", 100), ("paypa1.com", 90), ("paypa1.gov", 63)]
+        );
+    }
+
+    #[test]
+    fn findings_group_by_impersonated_brand() {
+        let targets = ["paypal.com", "google.com"];
+        let scanner = Scanner::new(None, AsciiOptions::default(), None)
+            .with_targets(targets.iter().map(|target| target.to_string()).collect());
+        let mut report = scanner
+            .scan(extract_domains(
+                "paypa1.com gooogle.com paypai.com paypa1.com",
+            ))
+            .unwrap();
+        report.group(None);
+        assert!(report.brands.is_empty());
+        report.group(Some(GroupBy::Brand));
+        let groups: Vec<(&str, usize, u64, &[String])> = report
+            .brands
+            .iter()
+            .map(|group| {
+                (
+                    group.brand.as_str(),
+                    group.count,
+                    group.occurrences,
+                    &group.domains[..],
+                )
+            })
+            .collect();
+        assert_eq!(groups.len(), 2);
+        let paypal = groups.iter().find(|group| group.0 == "paypal.com").unwrap();
+        assert_eq!((paypal.1, paypal.2), (2, 3));
+        assert_eq!(paypal.3, ["paypa1.com", "paypai.com"]);
+        // The riskiest brand leads, like the riskiest finding.
+        assert_eq!(report.brands[0].max_risk, report.findings[0].risk);
+    }
+}
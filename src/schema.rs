@@ -0,0 +1,275 @@
+/*
+JSON Schema documents for SpotSpoof's JSON output (`spotspoof schema`), so
+downstream systems can validate it and generate clients. The schemas come from
+the same utoipa definitions as the OpenAPI spec of `serve`, rewritten from
+OpenAPI 3.0 to JSON Schema 2020-12: component references point into `$defs`,
+and `nullable` becomes a `null` type.
+*/
+use anyhow::Result;
+use serde_json::{json, Map, Value};
+use std::path::Path;
+use utoipa::OpenApi;
+
+use crate::detections::Severity;
+use crate::eml::{EmlReport, Sender, SenderCheck, Verdict};
+use crate::geoip::Address;
+use crate::kafka::{EventKind, KafkaEvent};
+use crate::scan::{BrandGroup, Finding, ScanReport};
+use crate::types::{
+    AsciiResponse, AsciiResult, DatasetInfo, IdnResponse, IdnResult, IdnaError, LookupResponse,
+    PassiveDns, PunyMapping, RegistrationStatus, ScoreComponent,
+};
+
+const DIALECT: &str = "https://json-schema.org/draft/2020-12/schema";
+const OPENAPI_REF: &str = "#/components/schemas/";
+const DEFS_REF: &str = "#/$defs/";
+
+/// The documents `spotspoof schema` can emit, one per top-level output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum SchemaKind {
+    /// `lookup` output (ASCII or IDN)
+    LookupResponse,
+    /// `ascii` output
+    AsciiResponse,
+    /// `idn` output
+    IdnResponse,
+    /// `scan-file`, `scan-url`, `scan-html`, `scan-har`, `scan-browser`, `scan-zeek`, and
+    /// `scan-pcap` output
+    ScanReport,
+    /// `scan-eml` verdicts
+    EmlReport,
+    /// Records of the `[kafka]` sink
+    KafkaEvent,
+}
+
+impl SchemaKind {
+    pub const ALL: [SchemaKind; 6] = [
+        SchemaKind::LookupResponse,
+        SchemaKind::AsciiResponse,
+        SchemaKind::IdnResponse,
+        SchemaKind::ScanReport,
+        SchemaKind::EmlReport,
+        SchemaKind::KafkaEvent,
+    ];
+
+    /// The component (and file) name of the schema.
+    pub fn name(self) -> &'static str {
+        match self {
+            SchemaKind::LookupResponse => "LookupResponse",
+            SchemaKind::AsciiResponse => "AsciiResponse",
+            SchemaKind::IdnResponse => "IdnResponse",
+            SchemaKind::ScanReport => "ScanReport",
+            SchemaKind::EmlReport => "EmlReport",
+            SchemaKind::KafkaEvent => "KafkaEvent",
+        }
+    }
+}
+
+#[derive(OpenApi)]
+#[openapi(components(schemas(
+    LookupResponse,
+    AsciiResponse,
+    AsciiResult,
+    ScoreComponent,
+    DatasetInfo,
+    IdnResponse,
+    IdnResult,
+    IdnaError,
+    PassiveDns,
+    PunyMapping,
+    RegistrationStatus,
+    ScanReport,
+    Finding,
+    BrandGroup,
+    Address,
+    Severity,
+    EmlReport,
+    Verdict,
+    SenderCheck,
+    Sender,
+    KafkaEvent,
+    EventKind
+)))]
+struct SchemaDoc;
+
+/// The JSON Schema document for `kind`, with every schema it references
+/// under `$defs`.
+pub fn document(kind: SchemaKind) -> Result<Value> {
+    let components = components()?;
+    let name = kind.name();
+    let root = components
+        .get(name)
+        .ok_or_else(|| anyhow::anyhow!("no schema for {name}"))?
+        .clone();
+
+    let mut defs = Map::new();
+    let mut pending: Vec<String> = Vec::new();
+    collect_refs(&root, &mut pending);
+    while let Some(reference) = pending.pop() {
+        if defs.contains_key(&reference) {
+            continue;
+        }
+        let schema = components
+            .get(&reference)
+            .ok_or_else(|| anyhow::anyhow!("{name} references unknown schema {reference}"))?;
+        collect_refs(schema, &mut pending);
+        defs.insert(reference, schema.clone());
+    }
+
+    let mut document = Map::new();
+    document.insert("$schema".into(), json!(DIALECT));
+    document.insert("title".into(), json!(name));
+    if let Value::Object(root) = root {
+        document.extend(root);
+    }
+    if !defs.is_empty() {
+        defs.sort_keys();
+        document.insert("$defs".into(), Value::Object(defs));
+    }
+    Ok(Value::Object(document))
+}
+
+/// Writes every document to `<dir>/<Name>.schema.json` and returns the paths.
+pub fn write_all(dir: &Path) -> Result<Vec<String>> {
+    std::fs::create_dir_all(dir)?;
+    let mut written = Vec::new();
+    for kind in SchemaKind::ALL {
+        let path = dir.join(format!("{}.schema.json", kind.name()));
+        let mut body = serde_json::to_string_pretty(&document(kind)?)?;
+        body.push('\n');
+        std::fs::write(&path, body)?;
+        written.push(path.display().to_string());
+    }
+    Ok(written)
+}
+
+/// Every component schema, converted to JSON Schema.
+fn components() -> Result<Map<String, Value>> {
+    let mut openapi = serde_json::to_value(SchemaDoc::openapi())?;
+    let Some(Value::Object(schemas)) = openapi.pointer_mut("/components/schemas").map(Value::take)
+    else {
+        anyhow::bail!("OpenAPI document has no component schemas");
+    };
+    Ok(schemas
+        .into_iter()
+        .map(|(name, schema)| (name, to_json_schema(schema)))
+        .collect())
+}
+
+/// Rewrites an OpenAPI 3.0 schema object (recursively) as JSON Schema.
+fn to_json_schema(value: Value) -> Value {
+    match value {
+        Value::Object(object) => {
+            let mut object: Map<String, Value> = object
+                .into_iter()
+                .map(|(key, value)| match (key.as_str(), value) {
+                    ("$ref", Value::String(reference)) => {
+                        let reference = match reference.strip_prefix(OPENAPI_REF) {
+                            Some(name) => format!("{DEFS_REF}{name}"),
+                            None => reference,
+                        };
+                        (key, Value::String(reference))
+                    }
+                    (_, value) => (key, to_json_schema(value)),
+                })
+                .collect();
+            if object.remove("nullable") != Some(Value::Bool(true)) {
+                return Value::Object(object);
+            }
+            match object.remove("type") {
+                Some(Value::String(kind)) => {
+                    object.insert("type".into(), json!([kind, "null"]));
+                    Value::Object(object)
+                }
+                other => {
+                    if let Some(kind) = other {
+                        object.insert("type".into(), kind);
+                    }
+                    json!({ "anyOf": [Value::Object(object), { "type": "null" }] })
+                }
+            }
+        }
+        Value::Array(items) => Value::Array(items.into_iter().map(to_json_schema).collect()),
+        other => other,
+    }
+}
+
+/// Pushes the names of the components `value` references.
+fn collect_refs(value: &Value, out: &mut Vec<String>) {
+    match value {
+        Value::Object(object) => {
+            for (key, value) in object {
+                match (key.as_str(), value) {
+                    ("$ref", Value::String(reference)) => {
+                        if let Some(name) = reference.strip_prefix(DEFS_REF) {
+                            out.push(name.to_string());
+                        }
+                    }
+                    _ => collect_refs(value, out),
+                }
+            }
+        }
+        Value::Array(items) => items.iter().for_each(|item| collect_refs(item, out)),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn documents_are_self_contained_json_schema() {
+        for kind in SchemaKind::ALL {
+            let document = document(kind).unwrap();
+            assert_eq!(document["$schema"], DIALECT);
+            assert_eq!(document["title"], kind.name());
+            let text = document.to_string();
+            assert!(!text.contains("components/schemas"), "{}", kind.name());
+            assert!(!text.contains("\"nullable\""), "{}", kind.name());
+            let mut refs = Vec::new();
+            collect_refs(&document, &mut refs);
+            for name in refs {
+                assert!(
+                    document["$defs"].get(&name).is_some(),
+                    "{} is missing {name}",
+                    kind.name()
+                );
+            }
+        }
+
+        let eml = document(SchemaKind::EmlReport).unwrap();
+        assert_eq!(
+            eml["$defs"]["Verdict"]["enum"],
+            json!(["clean", "review", "suspicious"])
+        );
+        assert!(eml["$defs"].get("ScanReport").is_some());
+        assert!(eml["$defs"].get("AsciiResponse").is_some());
+
+        let ascii = document(SchemaKind::AsciiResponse).unwrap();
+        assert!(ascii["properties"]["results"].is_object());
+        assert!(ascii
+            .get("$defs")
+            .is_some_and(|defs| defs.get("IdnResult").is_none()));
+    }
+
+    #[test]
+    fn nullable_becomes_a_null_type() {
+        assert_eq!(
+            to_json_schema(json!({ "type": "string", "nullable": true })),
+            json!({ "type": ["string", "null"] })
+        );
+        assert_eq!(
+            to_json_schema(json!({
+                "allOf": [{ "$ref": "#/components/schemas/LookupResponse" }],
+                "nullable": true
+            })),
+            json!({
+                "anyOf": [
+                    { "allOf": [{ "$ref": "#/$defs/LookupResponse" }] },
+                    { "type": "null" }
+                ]
+            })
+        );
+    }
+}
@@ -0,0 +1,168 @@
+/*
+Screenshots of live spoof pages, for evidence (`[screenshot]` in the config
+file; capturing needs a build with the `screenshot` feature and a Chrome or
+Chromium install). Registrars and hosting abuse desks often want to see the
+page before they act, so `spotspoof screenshot` saves one as a PNG, and with
+`[alerts] screenshots = true` every alert carries one. The page is loaded in a
+throwaway headless browser over the DevTools protocol: HTTPS first, then
+plain HTTP when that fails.
+*/
+use anyhow::Result;
+use serde::Deserialize;
+#[cfg(feature = "screenshot")]
+use std::time::Duration;
+
+pub const DEFAULT_TIMEOUT_MS: u64 = 20_000;
+pub const DEFAULT_WIDTH: u32 = 1280;
+pub const DEFAULT_HEIGHT: u32 = 800;
+
+/// The `[screenshot]` section of the config file.
+#[derive(Debug, Clone, Default, Deserialize, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ScreenshotConfig {
+    /// Chrome or Chromium executable (default: found on the `PATH` and in the
+    /// usual install locations).
+    pub chrome: Option<String>,
+    /// How long a page may take to load (default 20000).
+    pub timeout_ms: Option<u64>,
+    /// Viewport size (default 1280x800).
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    /// Run Chrome without its sandbox, as needed when running as root in a
+    /// container.
+    pub no_sandbox: bool,
+}
+
+/// The URLs tried for `target`: itself when it has a scheme, else its HTTPS
+/// and HTTP roots.
+pub fn urls(target: &str) -> Vec<String> {
+    if target.starts_with("http://") || target.starts_with("https://") {
+        return vec![target.to_string()];
+    }
+    let host = target.trim_end_matches('.');
+    vec![format!("https://{host}/"), format!("http://{host}/")]
+}
+
+const UNSUPPORTED: &str = "screenshots require a build with the `screenshot` feature";
+
+/// Checks that this build can take screenshots.
+pub fn check_supported() -> Result<()> {
+    if cfg!(feature = "screenshot") {
+        Ok(())
+    } else {
+        anyhow::bail!(UNSUPPORTED)
+    }
+}
+
+/// A PNG of the first of `urls(target)` that loads.
+pub fn capture(target: &str, config: &ScreenshotConfig) -> Result<Vec<u8>> {
+    check_supported()?;
+    crate::net::ensure_online("Taking screenshots")?;
+    capture_first(urls(target), config)
+}
+
+#[cfg(not(feature = "screenshot"))]
+fn capture_first(_urls: Vec<String>, _config: &ScreenshotConfig) -> Result<Vec<u8>> {
+    anyhow::bail!(UNSUPPORTED)
+}
+
+/// Runs the browser on its own thread and runtime, so this can be called
+/// from async contexts as well.
+#[cfg(feature = "screenshot")]
+fn capture_first(urls: Vec<String>, config: &ScreenshotConfig) -> Result<Vec<u8>> {
+    let config = config.clone();
+    std::thread::spawn(move || {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?
+            .block_on(browse(&urls, &config))
+    })
+    .join()
+    .map_err(|_| anyhow::anyhow!("screenshot thread panicked"))?
+}
+
+/// Loads `urls` in turn in a fresh headless browser until one renders.
+#[cfg(feature = "screenshot")]
+async fn browse(urls: &[String], config: &ScreenshotConfig) -> Result<Vec<u8>> {
+    use chromiumoxide::cdp::browser_protocol::page::CaptureScreenshotFormat;
+    use chromiumoxide::page::ScreenshotParams;
+    use chromiumoxide::{Browser, BrowserConfig};
+    use futures_util::StreamExt;
+
+    let timeout = Duration::from_millis(config.timeout_ms.unwrap_or(DEFAULT_TIMEOUT_MS));
+    let profile = std::env::temp_dir().join(format!(
+        "spotspoof-chrome-{}-{}",
+        std::process::id(),
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos()
+    ));
+    let mut builder = BrowserConfig::builder()
+        .window_size(
+            config.width.unwrap_or(DEFAULT_WIDTH),
+            config.height.unwrap_or(DEFAULT_HEIGHT),
+        )
+        .request_timeout(timeout)
+        .user_data_dir(&profile);
+    if let Some(chrome) = &config.chrome {
+        builder = builder.chrome_executable(chrome);
+    }
+    if config.no_sandbox {
+        builder = builder.no_sandbox();
+    }
+    let browser_config = builder
+        .build()
+        .map_err(|err| anyhow::anyhow!("cannot start Chrome: {err}"))?;
+    let (mut browser, mut handler) = Browser::launch(browser_config)
+        .await
+        .map_err(|err| anyhow::anyhow!("cannot start Chrome: {err}"))?;
+    let events = tokio::spawn(async move { while handler.next().await.is_some() {} });
+    let mut shot = Err(anyhow::anyhow!("no URL to load"));
+    for url in urls {
+        let loaded = tokio::time::timeout(timeout, async {
+            let page = browser.new_page("about:blank").await?;
+            page.goto(url.as_str()).await?;
+            page.screenshot(
+                ScreenshotParams::builder()
+                    .format(CaptureScreenshotFormat::Png)
+                    .build(),
+            )
+            .await
+        })
+        .await;
+        shot = match loaded {
+            Ok(Ok(png)) => Ok(png),
+            Ok(Err(err)) => Err(anyhow::anyhow!("cannot load {url}: {err}")),
+            Err(_) => Err(anyhow::anyhow!(
+                "cannot load {url}: timed out after {}ms",
+                timeout.as_millis()
+            )),
+        };
+        if shot.is_ok() {
+            break;
+        }
+    }
+    let _ = browser.close().await;
+    let _ = browser.wait().await;
+    events.abort();
+    let _ = std::fs::remove_dir_all(&profile);
+    shot
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn domains_are_tried_over_https_then_http() {
+        assert_eq!(
+            urls("paypa1.com."),
+            ["https://paypa1.com/", "http://paypa1.com/"]
+        );
+        assert_eq!(
+            urls("http://paypa1.com/signin"),
+            ["http://paypa1.com/signin"]
+        );
+    }
+}
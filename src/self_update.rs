@@ -0,0 +1,86 @@
+/*
+Self-update of the binary (`spotspoof self-update`). The latest GitHub release
+is compared with the running version and, when newer, the asset built for this
+target (`spotspoof-<target>[.exe]`) is downloaded next to the executable and
+must match its published `.sha256` and carry a valid `.minisig` (unlike a DB
+download, a signature is required) before it replaces the running executable
+with a rename.
+*/
+use anyhow::Result;
+use std::cmp::Ordering;
+use std::path::PathBuf;
+
+use crate::download;
+use crate::release::{self, Release};
+
+/// The version of the running binary.
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+/// The target triple the binary was built for (set by `build.rs`).
+pub const TARGET: &str = env!("SPOTSPOOF_TARGET");
+
+/// The release asset built for this target.
+pub fn asset_name() -> String {
+    format!("spotspoof-{TARGET}{}", std::env::consts::EXE_SUFFIX)
+}
+
+/// Whether `release` is newer than `current`; an error when its tag is not a
+/// version.
+pub fn is_newer(release: &Release, current: &str) -> Result<bool> {
+    release::compare_versions(release.version(), current)
+        .map(|order| order == Ordering::Greater)
+        .ok_or_else(|| {
+            anyhow::anyhow!(
+                "cannot compare release {} with version {current}",
+                release.tag_name
+            )
+        })
+}
+
+/// Downloads this target's asset of `release` and swaps it in for the running
+/// executable, whose path is returned.
+pub fn install(release: &Release) -> Result<PathBuf> {
+    let name = asset_name();
+    let asset = release.asset(&name).ok_or_else(|| {
+        anyhow::anyhow!(
+            "release {} has no {name} asset for this platform",
+            release.tag_name
+        )
+    })?;
+    let exe = std::env::current_exe()?;
+    let dir = exe
+        .parent()
+        .ok_or_else(|| anyhow::anyhow!("{} has no parent directory", exe.display()))?;
+    // Staged in a private temp file next to the executable, which only its
+    // owner can plant a binary in.
+    let staged = download::download_verified(&asset.browser_download_url, dir)?;
+    // Copied next to the executable and renamed over it, keeping its
+    // permissions, so an interrupted update leaves the old binary in place.
+    self_replace::self_replace(staged.path())
+        .map_err(|err| anyhow::anyhow!("cannot replace {}: {err}", exe.display()))?;
+    Ok(exe)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::release::Asset;
+
+    #[test]
+    fn releases_are_compared_with_the_running_version() {
+        let release = |tag: &str| Release {
+            tag_name: tag.into(),
+            published_at: None,
+            assets: vec![Asset {
+                name: asset_name(),
+                browser_download_url: "https://example.com/spotspoof".into(),
+            }],
+        };
+        assert!(is_newer(&release("v1.0.4"), "1.0.3").unwrap());
+        assert!(is_newer(&release("v1.1.0"), "1.0.10").unwrap());
+        assert!(!is_newer(&release("v1.0.3"), "1.0.3").unwrap());
+        assert!(!is_newer(&release("1.0.2"), "1.0.3").unwrap());
+        assert!(is_newer(&release("latest"), "1.0.3").is_err());
+        assert!(release("v1.0.4").asset(&asset_name()).is_some());
+        assert!(asset_name().starts_with("spotspoof-"));
+    }
+}
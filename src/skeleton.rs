@@ -0,0 +1,145 @@
+/*
+`spotspoof skeleton`: how a domain reads once confusable characters are
+collapsed, without running a lookup. Two skeletons are reported: the UTS #39
+one (Unicode's confusables data, case and script sensitive) and the folded
+form SpotSpoof itself buckets by (homoglyphs replaced by the ASCII they pass
+for, then ASCII lookalikes such as `rn`/`m` and `1`/`l` merged). Each
+character is listed with its code point and script, so mixed-script names
+stand out.
+*/
+use anyhow::Result;
+use idna::domain_to_unicode;
+use serde::Serialize;
+use unicode_script::{Script, UnicodeScript};
+
+use crate::candidates;
+use crate::idn;
+use crate::types;
+
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct SkeletonReport {
+    /// The name as looked up (punycode for IDNs).
+    pub domain: String,
+    /// The Unicode form, for IDNs.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub unicode: Option<String>,
+    /// The UTS #39 confusable skeleton of the Unicode form.
+    pub skeleton: String,
+    /// The ASCII fold SpotSpoof matches lookalikes by.
+    pub folded: String,
+    /// The scripts of the letters, `Common` and `Inherited` left out.
+    pub scripts: Vec<&'static str>,
+    pub mixed_script: bool,
+    pub characters: Vec<CharInfo>,
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct CharInfo {
+    pub char: char,
+    /// `U+XXXX`.
+    pub codepoint: String,
+    pub script: &'static str,
+    /// The character's UTS #39 skeleton.
+    pub skeleton: String,
+}
+
+/// Describes the skeletons and scripts of `input`, a domain or URL.
+pub fn analyze(input: &str) -> Result<SkeletonReport> {
+    let domain = types::parse_domain_input(input)?;
+    let unicode = unicode_form(&domain);
+    let name = unicode.as_deref().unwrap_or(&domain);
+    let characters: Vec<CharInfo> = name
+        .chars()
+        .map(|c| CharInfo {
+            char: c,
+            codepoint: format!("U+{:04X}", c as u32),
+            script: c.script().full_name(),
+            skeleton: uts39(&c.to_string()),
+        })
+        .collect();
+    let mut scripts: Vec<&'static str> = Vec::new();
+    for script in name.chars().map(|c| c.script()) {
+        if !matches!(script, Script::Common | Script::Inherited)
+            && !scripts.contains(&script.full_name())
+        {
+            scripts.push(script.full_name());
+        }
+    }
+    Ok(SkeletonReport {
+        skeleton: uts39(name),
+        folded: fold(name),
+        mixed_script: scripts.len() > 1,
+        scripts,
+        characters,
+        unicode,
+        domain,
+    })
+}
+
+/// The Unicode form of an IDN, `None` when it reads the same as `domain`.
+pub(crate) fn unicode_form(domain: &str) -> Option<String> {
+    types::has_idn_label(domain)
+        .then(|| domain_to_unicode(domain).0)
+        .filter(|unicode| unicode != domain)
+}
+
+/// The UTS #39 confusable skeleton of `text`.
+pub fn uts39(text: &str) -> String {
+    unicode_security::skeleton(text).collect()
+}
+
+/// Homoglyphs replaced by the ASCII they pass for, then ASCII lookalikes
+/// folded together by `candidates::skeleton`.
+pub fn fold(domain: &str) -> String {
+    let ascii: String = domain.chars().map(ascii_of).collect();
+    candidates::skeleton(&ascii)
+}
+
+fn ascii_of(c: char) -> char {
+    if c.is_ascii() {
+        return c;
+    }
+    let lower = c.to_lowercase().next().unwrap_or(c);
+    ('a'..='z')
+        .chain('0'..='9')
+        .find(|ascii| idn::homoglyphs_of(*ascii).contains(&lower))
+        .unwrap_or(c)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn idn_reports_scripts_and_both_skeletons() {
+        let report = analyze("https://xn--pypal-4ve.com/login").unwrap();
+        assert_eq!(report.domain, "xn--pypal-4ve.com");
+        assert_eq!(report.unicode.as_deref(), Some("pаypal.com"));
+        // UTS #39 prototypes are not always readable: `m` becomes `rn`.
+        assert_eq!(report.skeleton, "paypal.corn");
+        assert_eq!(report.skeleton, uts39("paypal.com"));
+        assert_eq!(report.folded, "paypal.com");
+        assert_eq!(report.scripts, ["Latin", "Cyrillic"]);
+        assert!(report.mixed_script);
+        assert_eq!(
+            report.characters[1],
+            CharInfo {
+                char: 'а',
+                codepoint: "U+0430".to_string(),
+                script: "Cyrillic",
+                skeleton: "a".to_string(),
+            }
+        );
+        assert_eq!(report.characters[6].script, "Common");
+    }
+
+    #[test]
+    fn ascii_lookalikes_fold_together() {
+        let report = analyze("rnicr0soft.com").unwrap();
+        assert_eq!(report.unicode, None);
+        assert_eq!(report.folded, fold("microsoft.com"));
+        assert_eq!(report.scripts, ["Latin"]);
+        assert!(!report.mixed_script);
+        assert_eq!(analyze("paypa1.com").unwrap().skeleton, uts39("paypal.com"));
+    }
+}
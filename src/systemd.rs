@@ -0,0 +1,168 @@
+/*
+systemd integration for `serve`. When socket-activated (a `.socket` unit passing
+LISTEN_FDS), the HTTP API is served on the inherited socket instead of binding
+--host/--port. Under `Type=notify`, READY=1 is sent once the DB is loaded and
+the socket is listening, and with `WatchdogSec=` keepalives are sent at half
+the watchdog interval while the server can still answer lookups, so systemd
+restarts a hung process. Outside systemd everything here is a no-op.
+*/
+use anyhow::Result;
+use std::ffi::OsStr;
+use std::net::TcpListener;
+use std::time::Duration;
+use tracing::warn;
+
+/// The first descriptor passed by socket activation (`SD_LISTEN_FDS_START`).
+#[cfg(unix)]
+const LISTEN_FDS_START: i32 = 3;
+
+/// The socket systemd passed to this process, if it was socket-activated.
+/// Only the first one is used. The activation variables are cleared, so child
+/// processes do not claim the socket too.
+pub fn listener() -> Result<Option<TcpListener>> {
+    let fds = listen_fds(
+        std::env::var("LISTEN_PID").ok().as_deref(),
+        std::env::var("LISTEN_FDS").ok().as_deref(),
+        std::process::id(),
+    );
+    for name in ["LISTEN_PID", "LISTEN_FDS", "LISTEN_FDNAMES"] {
+        std::env::remove_var(name);
+    }
+    if fds == 0 {
+        return Ok(None);
+    }
+    if fds > 1 {
+        warn!(
+            kind = "systemd",
+            fds, "several sockets were passed; serving HTTP on the first"
+        );
+    }
+    take_listener().map(Some)
+}
+
+#[cfg(unix)]
+fn take_listener() -> Result<TcpListener> {
+    use std::os::fd::FromRawFd;
+    // SAFETY: socket activation hands this process the descriptors from
+    // LISTEN_FDS_START on, and nothing else in it uses them.
+    let listener = unsafe { TcpListener::from_raw_fd(LISTEN_FDS_START) };
+    listener
+        .local_addr()
+        .map_err(|_| anyhow::anyhow!("the socket passed by systemd is not a TCP socket"))?;
+    listener.set_nonblocking(true)?;
+    Ok(listener)
+}
+
+#[cfg(not(unix))]
+fn take_listener() -> Result<TcpListener> {
+    anyhow::bail!("socket activation is only supported on Unix")
+}
+
+/// How many sockets were passed, given LISTEN_PID and LISTEN_FDS. They only
+/// count when LISTEN_PID names this process.
+fn listen_fds(pid: Option<&str>, fds: Option<&str>, own_pid: u32) -> usize {
+    match (
+        pid.and_then(|pid| pid.parse::<u32>().ok()),
+        fds.and_then(|fds| fds.parse().ok()),
+    ) {
+        (Some(pid), Some(fds)) if pid == own_pid => fds,
+        _ => 0,
+    }
+}
+
+/// Sends `state` (`READY=1`, `WATCHDOG=1`, ...) to the service manager, if
+/// there is one. A failed send is logged, never fatal.
+pub fn notify(state: &str) {
+    let Some(path) = std::env::var_os("NOTIFY_SOCKET") else {
+        return;
+    };
+    if let Err(err) = send(&path, state) {
+        warn!(kind = "systemd", error = %err, state, "sd_notify failed");
+    }
+}
+
+#[cfg(unix)]
+fn send(path: &OsStr, state: &str) -> std::io::Result<()> {
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::net::UnixDatagram;
+
+    let socket = UnixDatagram::unbound()?;
+    match path.as_bytes().strip_prefix(b"@") {
+        // A leading `@` names a socket in the abstract namespace.
+        #[cfg(target_os = "linux")]
+        Some(name) => {
+            use std::os::linux::net::SocketAddrExt;
+            let addr = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+            socket.send_to_addr(state.as_bytes(), &addr)?;
+        }
+        _ => {
+            socket.send_to(state.as_bytes(), path)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn send(_path: &OsStr, _state: &str) -> std::io::Result<()> {
+    Ok(())
+}
+
+/// How often to send `WATCHDOG=1`: half the `WatchdogSec=` systemd set for
+/// this process, or `None` when it expects no keepalives.
+pub fn watchdog_interval() -> Option<Duration> {
+    watchdog(
+        std::env::var("WATCHDOG_USEC").ok().as_deref(),
+        std::env::var("WATCHDOG_PID").ok().as_deref(),
+        std::process::id(),
+    )
+}
+
+fn watchdog(usec: Option<&str>, pid: Option<&str>, own_pid: u32) -> Option<Duration> {
+    if let Some(pid) = pid {
+        if pid.parse::<u32>().ok()? != own_pid {
+            return None;
+        }
+    }
+    let usec: u64 = usec?.parse().ok().filter(|usec| *usec > 0)?;
+    Some(Duration::from_micros(usec / 2))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn activation_variables_only_count_for_this_process() {
+        assert_eq!(listen_fds(Some("42"), Some("2"), 42), 2);
+        assert_eq!(listen_fds(Some("41"), Some("2"), 42), 0);
+        assert_eq!(listen_fds(None, Some("1"), 42), 0);
+        assert_eq!(listen_fds(Some("42"), Some("x"), 42), 0);
+
+        assert_eq!(
+            watchdog(Some("30000000"), None, 42),
+            Some(Duration::from_secs(15))
+        );
+        assert_eq!(
+            watchdog(Some("30000000"), Some("42"), 42),
+            Some(Duration::from_secs(15))
+        );
+        assert_eq!(watchdog(Some("30000000"), Some("41"), 42), None);
+        assert_eq!(watchdog(Some("0"), None, 42), None);
+        assert_eq!(watchdog(None, None, 42), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn notifications_reach_the_socket() {
+        use std::os::unix::net::UnixDatagram;
+
+        let path = std::env::temp_dir().join(format!("spotspoof-notify-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let socket = UnixDatagram::bind(&path).unwrap();
+        send(path.as_os_str(), "READY=1").unwrap();
+        let mut buf = [0; 64];
+        let len = socket.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"READY=1");
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,328 @@
+/*
+`spotspoof tail`: follows a growing log file like `tail -F`, pulls domains out
+of each new line (with a `--regex`, or the same tolerant extractor as
+`scan-file`), and emits an alert for every lookalike not already triaged in
+the detections store. Each domain is looked up once; a bounded LRU of checked
+domains keeps memory flat on busy resolvers.
+Rotation (the path now names a new file) and truncation are detected on every
+poll, so it keeps working across logrotate without a restart.
+*/
+use anyhow::Result;
+use lru::LruCache;
+use regex::Regex;
+use serde::Serialize;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::{info, warn};
+
+use crate::detections::{self, Severity};
+use crate::export;
+use crate::scan::{self, Observations, Scanner};
+use crate::types::LookupResponse;
+
+pub const DEFAULT_POLL_MS: u64 = 500;
+pub const DEFAULT_DEDUP_SIZE: usize = 100_000;
+/// The capture group `--regex` patterns name their domain with.
+pub const DOMAIN_GROUP: &str = "domain";
+
+/// A lookalike seen in the followed file.
+#[derive(Debug, Clone, Serialize)]
+pub struct Alert {
+    /// When the line was read, as RFC 3339 UTC.
+    pub timestamp: String,
+    pub domain: String,
+    pub line: String,
+    pub severity: Severity,
+    pub lookup: LookupResponse,
+}
+
+impl Alert {
+    /// `<timestamp> <domain> resembles <target> (similarity N)`.
+    pub fn text(&self) -> String {
+        format!(
+            "{} {} {}",
+            self.timestamp,
+            self.domain,
+            scan::resemblance(&self.lookup)
+        )
+    }
+}
+
+/// Reads the lines appended to a file, reopening it when it is rotated or
+/// truncated.
+pub struct Follower {
+    path: PathBuf,
+    file: Option<File>,
+    identity: Option<(u64, u64)>,
+    offset: u64,
+    partial: Vec<u8>,
+    from_start: bool,
+}
+
+impl Follower {
+    /// Follows `path`, starting at its current end unless `from_start`. The
+    /// file need not exist yet.
+    pub fn new(path: impl Into<PathBuf>, from_start: bool) -> Self {
+        Self {
+            path: path.into(),
+            file: None,
+            identity: None,
+            offset: 0,
+            partial: Vec::new(),
+            from_start,
+        }
+    }
+
+    /// The complete lines written since the last poll.
+    pub fn poll(&mut self) -> Result<Vec<String>> {
+        let mut lines = Vec::new();
+        let metadata = std::fs::metadata(&self.path).ok();
+        let current = metadata.as_ref().and_then(file_identity);
+        if self.file.is_some() && current.is_some() && current != self.identity {
+            // Rotated: finish the old file, then read the new one from its start.
+            self.read_available(&mut lines)?;
+            info!(kind = "tail", path = %self.path.display(), "file rotated");
+            self.file = None;
+            self.partial.clear();
+        }
+        if self.file.is_none() {
+            let Some(metadata) = metadata else {
+                return Ok(lines);
+            };
+            let Ok(file) = File::open(&self.path) else {
+                return Ok(lines);
+            };
+            self.offset = if self.from_start { 0 } else { metadata.len() };
+            self.identity = file_identity(&metadata);
+            self.file = Some(file);
+            // Only the first open may skip existing content; rotated files
+            // are new, so all of them is unread.
+            self.from_start = true;
+        } else if metadata.is_some_and(|metadata| metadata.len() < self.offset) {
+            info!(kind = "tail", path = %self.path.display(), "file truncated");
+            self.offset = 0;
+            self.partial.clear();
+        }
+        self.read_available(&mut lines)?;
+        Ok(lines)
+    }
+
+    fn read_available(&mut self, lines: &mut Vec<String>) -> Result<()> {
+        let Some(file) = &mut self.file else {
+            return Ok(());
+        };
+        file.seek(SeekFrom::Start(self.offset))?;
+        let mut bytes = Vec::new();
+        self.offset += file.read_to_end(&mut bytes)? as u64;
+        self.partial.extend_from_slice(&bytes);
+        let Some(end) = self.partial.iter().rposition(|byte| *byte == b'\n') else {
+            return Ok(());
+        };
+        let rest = self.partial.split_off(end + 1);
+        lines.extend(
+            String::from_utf8_lossy(&self.partial)
+                .lines()
+                .map(|line| line.trim_end_matches('\r').to_string()),
+        );
+        self.partial = rest;
+        Ok(())
+    }
+}
+
+#[cfg(unix)]
+fn file_identity(metadata: &std::fs::Metadata) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    Some((metadata.dev(), metadata.ino()))
+}
+
+/// Without inodes only truncation is detected.
+#[cfg(not(unix))]
+fn file_identity(_metadata: &std::fs::Metadata) -> Option<(u64, u64)> {
+    None
+}
+
+/// Checks the domains of followed lines, once each.
+pub struct Tail {
+    scanner: Scanner,
+    regex: Option<Regex>,
+    checked: LruCache<String, ()>,
+}
+
+impl Tail {
+    /// `regex` picks the domains out of a line: its `domain` group, else its
+    /// first group, else the whole match. Without it every domain-like token
+    /// counts. Up to `dedup_size` checked domains are remembered.
+    pub fn new(scanner: Scanner, regex: Option<Regex>, dedup_size: usize) -> Self {
+        Self {
+            scanner,
+            regex,
+            checked: LruCache::new(NonZeroUsize::new(dedup_size).unwrap_or(NonZeroUsize::MIN)),
+        }
+    }
+
+    /// Alerts for the lookalikes on `line` that were not checked before.
+    pub fn check_line(&mut self, line: &str) -> Vec<Alert> {
+        let mut alerts = Vec::new();
+        for domain in self.line_domains(line) {
+            if self.checked.get(&domain).is_some() {
+                continue;
+            }
+            match self.scanner.lookup(&domain) {
+                Ok(lookup) => {
+                    self.checked.put(domain.clone(), ());
+                    let lookup = lookup.filter(scan::is_lookalike).filter(|lookup| {
+                        detections::record(detections::Source::Tail, &domain, lookup)
+                    });
+                    if let Some(lookup) = lookup {
+                        alerts.push(Alert {
+                            timestamp: utc_timestamp(SystemTime::now()),
+                            severity: Severity::from_risk(scan::risk(&domain, &lookup)),
+                            domain,
+                            line: line.to_string(),
+                            lookup,
+                        });
+                    }
+                }
+                // Left unchecked, so the next sighting retries it.
+                Err(err) => warn!(kind = "tail", domain = %domain, "lookup failed: {err}"),
+            }
+        }
+        alerts
+    }
+
+    fn line_domains(&self, line: &str) -> Vec<String> {
+        let mut observations = Observations::default();
+        match &self.regex {
+            Some(regex) => {
+                for captures in regex.captures_iter(line) {
+                    let found = captures
+                        .name(DOMAIN_GROUP)
+                        .or_else(|| captures.get(1))
+                        .or_else(|| captures.get(0));
+                    if let Some(found) = found {
+                        observations.add(found.as_str(), None);
+                    }
+                }
+            }
+            None => observations.add_text(line),
+        }
+        observations
+            .into_vec()
+            .into_iter()
+            .map(|observation| observation.domain)
+            .collect()
+    }
+}
+
+/// Follows `path` forever, polling every `poll` and handing each alert to
+/// `on_alert`.
+pub fn follow(
+    path: &Path,
+    from_start: bool,
+    poll: Duration,
+    mut tail: Tail,
+    mut on_alert: impl FnMut(&Alert) -> Result<()>,
+) -> Result<()> {
+    let mut follower = Follower::new(path, from_start);
+    loop {
+        for line in follower.poll()? {
+            for alert in tail.check_line(&line) {
+                on_alert(&alert)?;
+            }
+        }
+        std::thread::sleep(poll);
+    }
+}
+
+/// `time` as RFC 3339 UTC with second precision.
+pub(crate) fn utc_timestamp(time: SystemTime) -> String {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let (year, month, day) = export::civil_from_days((secs / 86_400) as i64);
+    let secs_of_day = secs % 86_400;
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z",
+        secs_of_day / 3_600,
+        secs_of_day / 60 % 60,
+        secs_of_day % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ascii_spoof::AsciiOptions;
+    use std::io::Write;
+
+    fn watching(targets: &[&str]) -> Scanner {
+        Scanner::new(None, AsciiOptions::default(), None)
+            .with_targets(targets.iter().map(|target| target.to_string()).collect())
+    }
+
+    #[test]
+    fn follower_reads_appended_lines_across_truncation_and_rotation() {
+        let path = std::env::temp_dir().join(format!("spotspoof-tail-{}.log", std::process::id()));
+        std::fs::write(&path, "old line\n").unwrap();
+        let mut follower = Follower::new(&path, false);
+        assert!(follower.poll().unwrap().is_empty());
+
+        let append = |text: &str| {
+            let mut file = std::fs::OpenOptions::new()
+                .append(true)
+                .open(&path)
+                .unwrap();
+            file.write_all(text.as_bytes()).unwrap();
+        };
+        append("first\nsecond\r\nthi");
+        assert_eq!(follower.poll().unwrap(), ["first", "second"]);
+        append("rd\n");
+        assert_eq!(follower.poll().unwrap(), ["third"]);
+
+        std::fs::write(&path, "after truncate\n").unwrap();
+        assert_eq!(follower.poll().unwrap(), ["after truncate"]);
+
+        let rotated = path.with_extension("log.1");
+        append("last of old\n");
+        std::fs::rename(&path, &rotated).unwrap();
+        std::fs::write(&path, "new file\n").unwrap();
+        assert_eq!(follower.poll().unwrap(), ["last of old", "new file"]);
+
+        for path in [&path, &rotated] {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+
+    #[test]
+    fn lines_alert_once_per_lookalike() {
+        let mut tail = Tail::new(watching(&["paypal.com"]), None, 16);
+        let alerts = tail.check_line("client 10.0.0.7 query: paypa1.com IN A +");
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(alerts[0].domain, "paypa1.com");
+        assert!(alerts[0]
+            .text()
+            .ends_with("paypa1.com resembles paypal.com (similarity 90)"));
+        assert!(tail.check_line("query: www.paypa1.com IN AAAA").is_empty());
+        assert!(tail.check_line("query: paypal.com IN A").is_empty());
+    }
+
+    #[test]
+    fn regex_selects_the_domain_group() {
+        let regex = Regex::new(r"query: (?P<domain>\S+) IN").unwrap();
+        let mut tail = Tail::new(watching(&["paypal.com"]), Some(regex), 16);
+        assert!(tail
+            .check_line("referer paypa1.com query: example.org IN A")
+            .is_empty());
+        assert_eq!(tail.check_line("query: paypa1.com IN A").len(), 1);
+    }
+
+    #[test]
+    fn timestamps_are_rfc3339_utc() {
+        let time = UNIX_EPOCH + Duration::from_secs(20_743 * 86_400 + 3_723);
+        assert_eq!(utc_timestamp(time), "2026-10-17T01:02:03Z");
+    }
+}
@@ -0,0 +1,241 @@
+/*
+Tenant store for multi-tenant serve. Keeps API keys (as SHA-256 hashes, see
+`keys`) with each tenant's protected brand domains and allowlist in a small
+writable SQLite file, separate from the read-only release DB. A tenant key
+(`X-API-Key`) only selects the tenant; access and quotas are governed by the
+`--keys-db` keys.
+*/
+use anyhow::Result;
+use rusqlite::{Connection, OptionalExtension};
+use serde::Serialize;
+use std::collections::HashSet;
+
+use crate::ascii_spoof::{self, AsciiOptions};
+use crate::cipher;
+use crate::keys::{generate_key, key_sha256};
+use crate::types::{AsciiResponse, AsciiResult};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Tenant {
+    pub name: String,
+    pub brands: Vec<String>,
+    pub allowlist: HashSet<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TenantSummary {
+    pub name: String,
+    pub brands: usize,
+    pub allowlist: usize,
+}
+
+pub fn open(path: &str) -> Result<Connection> {
+    let conn = cipher::open(path)?;
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS tenants (
+			id INTEGER PRIMARY KEY,
+			name TEXT NOT NULL UNIQUE,
+			key_sha256 TEXT NOT NULL UNIQUE
+		);
+		CREATE TABLE IF NOT EXISTS tenant_brands (
+			tenant_id INTEGER NOT NULL REFERENCES tenants(id),
+			domain TEXT NOT NULL,
+			PRIMARY KEY (tenant_id, domain)
+		);
+		CREATE TABLE IF NOT EXISTS tenant_allowlist (
+			tenant_id INTEGER NOT NULL REFERENCES tenants(id),
+			domain TEXT NOT NULL,
+			PRIMARY KEY (tenant_id, domain)
+		);",
+    )?;
+    Ok(conn)
+}
+
+/// Creates a tenant and returns its API key; only the key's hash is stored.
+pub fn create(conn: &Connection, name: &str) -> Result<String> {
+    let key = generate_key();
+    conn.execute(
+        "INSERT INTO tenants (name, key_sha256) VALUES (?1, ?2)",
+        (name, key_sha256(&key)),
+    )?;
+    Ok(key)
+}
+
+pub fn add_brands(conn: &Connection, name: &str, domains: &[String]) -> Result<usize> {
+    insert_domains(conn, "tenant_brands", name, domains)
+}
+
+pub fn add_allowlist(conn: &Connection, name: &str, domains: &[String]) -> Result<usize> {
+    insert_domains(conn, "tenant_allowlist", name, domains)
+}
+
+pub fn list(conn: &Connection) -> Result<Vec<TenantSummary>> {
+    let mut stmt = conn.prepare(
+        "SELECT name,
+			(SELECT COUNT(*) FROM tenant_brands WHERE tenant_id = tenants.id),
+			(SELECT COUNT(*) FROM tenant_allowlist WHERE tenant_id = tenants.id)
+		FROM tenants ORDER BY name",
+    )?;
+    let rows = stmt.query_map([], |row| {
+        Ok(TenantSummary {
+            name: row.get(0)?,
+            brands: row.get::<_, i64>(1)? as usize,
+            allowlist: row.get::<_, i64>(2)? as usize,
+        })
+    })?;
+
+    let mut out = Vec::new();
+    for row in rows {
+        out.push(row?);
+    }
+    Ok(out)
+}
+
+pub fn find_by_key(conn: &Connection, key: &str) -> Result<Option<Tenant>> {
+    let found: Option<(i64, String)> = conn
+        .query_row(
+            "SELECT id, name FROM tenants WHERE key_sha256 = ?1",
+            [key_sha256(key)],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .optional()?;
+    let Some((id, name)) = found else {
+        return Ok(None);
+    };
+
+    Ok(Some(Tenant {
+        name,
+        brands: tenant_domains(conn, "tenant_brands", id)?,
+        allowlist: tenant_domains(conn, "tenant_allowlist", id)?
+            .into_iter()
+            .collect(),
+    }))
+}
+
+/// Layers a tenant's own brands over a global ASCII response: brand matches are
+/// merged in by best similarity, then allowlisted domains are dropped. A query
+/// for an allowlisted domain returns no results, and a query for one of the
+/// tenant's brands is an exact match.
+pub fn apply(tenant: &Tenant, response: &mut AsciiResponse, options: &AsciiOptions) {
+    if tenant.allowlist.contains(&response.q) {
+        response.results.clear();
+        return;
+    }
+    if tenant.brands.contains(&response.q) {
+        response.exact_match = true;
+        response.protected_brand = Some(response.q.clone());
+    }
+
+    // Brand matches rank ahead of global ones at equal similarity.
+    let mut merged: Vec<(usize, AsciiResult)> = Vec::new();
+    let brand_results = ascii_spoof::score_targets(&response.q, &tenant.brands, options);
+    for result in brand_results.into_iter().chain(response.results.drain(..)) {
+        if tenant.allowlist.contains(&result.domain) || result.domain == response.q {
+            continue;
+        }
+        match merged
+            .iter_mut()
+            .find(|(_, seen)| seen.domain == result.domain)
+        {
+            Some((_, seen)) => seen.similarity = seen.similarity.max(result.similarity),
+            None => merged.push((merged.len(), result)),
+        }
+    }
+
+    response.results = ascii_spoof::rank_results(merged, options.max_results);
+}
+
+fn insert_domains(conn: &Connection, table: &str, name: &str, domains: &[String]) -> Result<usize> {
+    let id: i64 = conn
+        .query_row("SELECT id FROM tenants WHERE name = ?1", [name], |row| {
+            row.get(0)
+        })
+        .optional()?
+        .ok_or_else(|| anyhow::anyhow!("Unknown tenant: {name}"))?;
+
+    let sql = format!("INSERT OR IGNORE INTO {table} (tenant_id, domain) VALUES (?1, ?2)");
+    let mut stmt = conn.prepare(&sql)?;
+    let mut inserted = 0;
+    for domain in domains {
+        let domain = crate::types::normalize_domain_input(domain);
+        inserted += stmt.execute((id, domain))?;
+    }
+    Ok(inserted)
+}
+
+fn tenant_domains(conn: &Connection, table: &str, id: i64) -> Result<Vec<String>> {
+    let sql = format!("SELECT domain FROM {table} WHERE tenant_id = ?1 ORDER BY domain");
+    let mut stmt = conn.prepare(&sql)?;
+    let rows = stmt.query_map([id], |row| row.get::<_, String>(0))?;
+
+    let mut out = Vec::new();
+    for row in rows {
+        out.push(row?);
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn store() -> Connection {
+        let conn = open(":memory:").unwrap();
+        create(&conn, "acme").unwrap();
+        conn
+    }
+
+    #[test]
+    fn keys_resolve_to_their_tenant() {
+        let conn = store();
+        let key = create(&conn, "globex").unwrap();
+        add_brands(&conn, "globex", &["https://Globex.com/".to_string()]).unwrap();
+
+        let tenant = find_by_key(&conn, &key).unwrap().unwrap();
+        assert_eq!(tenant.name, "globex");
+        assert_eq!(tenant.brands, vec!["globex.com".to_string()]);
+        assert!(find_by_key(&conn, "not-a-key").unwrap().is_none());
+        assert!(add_brands(&conn, "missing", &["a.com".to_string()]).is_err());
+    }
+
+    #[test]
+    fn apply_merges_brands_and_filters_allowlist() {
+        let conn = store();
+        add_brands(&conn, "acme", &["acmebank.com".to_string()]).unwrap();
+        add_allowlist(&conn, "acme", &["acmebanc.com".to_string()]).unwrap();
+        let summary = &list(&conn).unwrap()[0];
+        assert_eq!((summary.brands, summary.allowlist), (1, 1));
+
+        let tenant = Tenant {
+            name: "acme".to_string(),
+            brands: vec!["acmebank.com".to_string()],
+            allowlist: HashSet::from(["acmebanc.com".to_string()]),
+        };
+        let options = AsciiOptions::default();
+
+        let mut response = crate::types::empty_ascii_response("acmebamk.com");
+        response.results.push(AsciiResult {
+            domain: "acmebanc.com".to_string(),
+            similarity: 92,
+            breakdown: None,
+        });
+        apply(&tenant, &mut response, &options);
+        assert_eq!(response.results.len(), 1);
+        assert_eq!(response.results[0].domain, "acmebank.com");
+
+        let mut response = crate::types::empty_ascii_response("acmebanc.com");
+        response.results.push(AsciiResult {
+            domain: "acmebank.com".to_string(),
+            similarity: 92,
+            breakdown: None,
+        });
+        apply(&tenant, &mut response, &options);
+        assert!(response.results.is_empty());
+
+        let mut response = crate::types::empty_ascii_response("acmebank.com");
+        apply(&tenant, &mut response, &options);
+        assert!(response.exact_match);
+        assert_eq!(response.protected_brand.as_deref(), Some("acmebank.com"));
+        assert!(response.results.is_empty());
+    }
+}
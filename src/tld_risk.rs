@@ -0,0 +1,146 @@
+/*
+TLD risk weights. The same lookalike string is a bigger threat on a free or
+heavily abused TLD (`.top`, `.xyz`, `.icu`) than on one with vetted
+registrations (`.gov`), so match scores are weighted by the TLD of the
+lookalike: a weight is a percentage (100 is neutral) and weighted scores are
+capped at 100. The built-in table can be overridden, or extended, from the
+`[tld_risk]` section of the config file. Internationalized suffixes match in
+either form (`рф` or `xn--p1ai`).
+*/
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+/// The weight of TLDs without an entry.
+pub const NEUTRAL: u16 = 100;
+
+/// Built-in weights: free and cheap TLDs that dominate phishing and abuse
+/// reports score higher, restricted ones lower.
+const DEFAULT_WEIGHTS: [(&str, u16); 32] = [
+    ("top", 120),
+    ("xyz", 120),
+    ("icu", 120),
+    ("tk", 120),
+    ("ml", 120),
+    ("ga", 120),
+    ("cf", 120),
+    ("gq", 120),
+    ("buzz", 120),
+    ("cyou", 120),
+    ("rest", 120),
+    ("sbs", 120),
+    ("cfd", 120),
+    ("bond", 120),
+    ("zip", 120),
+    ("mov", 120),
+    ("click", 110),
+    ("link", 110),
+    ("online", 110),
+    ("site", 110),
+    ("live", 110),
+    ("shop", 110),
+    ("store", 110),
+    ("support", 110),
+    ("info", 110),
+    ("fun", 110),
+    ("monster", 110),
+    ("work", 110),
+    ("gov", 70),
+    ("mil", 70),
+    ("edu", 80),
+    ("int", 80),
+];
+
+static INSTALLED: OnceLock<TldRisk> = OnceLock::new();
+
+/// Weights by public suffix (`top`, `gov.uk`, `xn--p1ai`), in ASCII form and
+/// without a leading dot.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TldRisk {
+    weights: HashMap<String, u16>,
+}
+
+impl Default for TldRisk {
+    fn default() -> Self {
+        Self {
+            weights: DEFAULT_WEIGHTS
+                .iter()
+                .map(|(tld, weight)| (tld.to_string(), *weight))
+                .collect(),
+        }
+    }
+}
+
+impl TldRisk {
+    /// The built-in table with `overrides` replacing or adding entries.
+    pub fn with_overrides(overrides: impl IntoIterator<Item = (String, u16)>) -> Self {
+        let mut risk = Self::default();
+        for (tld, weight) in overrides {
+            risk.weights
+                .insert(ascii_form(tld.trim_start_matches('.')), weight);
+        }
+        risk
+    }
+
+    /// The weight of the longest suffix of `domain` with an entry, so
+    /// `gov.uk` can differ from `uk`.
+    pub fn weight(&self, domain: &str) -> u16 {
+        let domain = ascii_form(domain.trim_end_matches('.'));
+        let mut suffix = domain.as_str();
+        let mut best = None;
+        while let Some((_, rest)) = suffix.split_once('.') {
+            if let Some(weight) = self.weights.get(rest) {
+                best = Some(*weight);
+            }
+            suffix = rest;
+        }
+        best.unwrap_or(NEUTRAL)
+    }
+
+    /// `score` (0-100) weighted by the TLD of `domain`, capped at 100.
+    pub fn apply(&self, score: u8, domain: &str) -> u8 {
+        (u32::from(score) * u32::from(self.weight(domain)) / u32::from(NEUTRAL)).min(100) as u8
+    }
+}
+
+/// `name` lowercased, with internationalized labels in punycode.
+fn ascii_form(name: &str) -> String {
+    idna::domain_to_ascii(name).unwrap_or_else(|_| name.to_ascii_lowercase())
+}
+
+/// Makes `risk` the process-wide table. Only the first call takes effect.
+pub fn install(risk: TldRisk) {
+    let _ = INSTALLED.set(risk);
+}
+
+/// The installed table, or the built-in one.
+pub fn installed() -> &'static TldRisk {
+    INSTALLED.get_or_init(TldRisk::default)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn abused_tlds_outweigh_restricted_ones() {
+        let risk = TldRisk::default();
+        assert_eq!(risk.apply(80, "paypa1.top"), 96);
+        assert_eq!(risk.apply(90, "paypa1.top"), 100);
+        assert_eq!(risk.apply(80, "paypa1.com"), 80);
+        assert_eq!(risk.apply(80, "paypa1.gov"), 56);
+        assert_eq!(risk.weight("login.PAYPA1.XYZ."), 120);
+    }
+
+    #[test]
+    fn overrides_replace_and_add_suffixes() {
+        let risk = TldRisk::with_overrides([("top".to_string(), 150), (".gov.uk".to_string(), 50)]);
+        assert_eq!(risk.weight("paypa1.top"), 150);
+        assert_eq!(risk.weight("hmrc.gov.uk"), 50);
+        assert_eq!(risk.weight("paypa1.co.uk"), NEUTRAL);
+        assert_eq!(risk.weight("paypa1.xyz"), 120);
+
+        let risk = TldRisk::with_overrides([("рф".to_string(), 130)]);
+        assert_eq!(risk.weight("paypa1.рф"), 130);
+        assert_eq!(risk.weight("paypa1.xn--p1ai"), 130);
+    }
+}
@@ -5,12 +5,116 @@ validation helpers to round-trip serialize/deserialize for sanity checks.
 use anyhow::Result;
 use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use url::Url;
 use utoipa::ToSchema;
 
 #[derive(Debug, Clone, Serialize, Deserialize, ToSchema, PartialEq, Eq)]
 pub struct LookupRequest {
     pub domain: String,
+    /// Minimum ASCII similarity (50-100) a candidate needs to be returned.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_similarity: Option<u8>,
+    /// Maximum number of results to return (1-50).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_results: Option<usize>,
+    /// Edit-distance algorithm used for ASCII similarity scoring.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schema(inline)]
+    pub algorithm: Option<Algorithm>,
+    /// Whether IDN variants are checked for registration (default true).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub check_registration: Option<bool>,
+    /// Maximum IDN variants to enumerate (1-10000).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_variants: Option<usize>,
+    /// Maximum IDN variants checked for registration (1-500).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_checks: Option<usize>,
+    /// Per-check DNS timeout in milliseconds (100-10000).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub whois_timeout_ms: Option<u64>,
+    /// Named dataset (a `[datasets]` entry of the server's config) to look
+    /// ASCII candidates up in, instead of the default DB.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dataset: Option<String>,
+    /// Also score the domain with leet substitutions undone (`0`→`o`,
+    /// `1`→`l`, `3`→`e`, `4`→`a`), keeping the better score (default false).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub leet: Option<bool>,
+    /// Blend phonetic similarity (Soundex/Metaphone of the second-level
+    /// label) into the ASCII score (default false).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub phonetic: Option<bool>,
+    /// Results per page (1-50), in place of `max_results`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub limit: Option<usize>,
+    /// Ranked results to skip before the page (0-1000).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub offset: Option<usize>,
+    /// IDN: decode with UTS #46 transitional processing, mapping `ß`, `ς`,
+    /// and the zero-width joiners as IDNA 2003 did (default false).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub idna_transitional: Option<bool>,
+    /// IDN: apply the STD3 rules, rejecting ASCII characters hostnames may
+    /// not contain (default false).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub idna_std3_rules: Option<bool>,
+    /// IDN: what to do with labels whose punycode does not decode, such as
+    /// ones that overflow the decoder (default `reject`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schema(inline)]
+    pub punycode_overflow: Option<PunycodeOverflow>,
+}
+
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    Default,
+    Serialize,
+    Deserialize,
+    ToSchema,
+    PartialEq,
+    Eq,
+    Hash,
+    clap::ValueEnum,
+)]
+#[serde(rename_all = "kebab-case")]
+pub enum Algorithm {
+    #[default]
+    Levenshtein,
+    DamerauLevenshtein,
+}
+
+/// What IDN lookups do with labels whose punycode does not decode.
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    Default,
+    Serialize,
+    Deserialize,
+    ToSchema,
+    PartialEq,
+    Eq,
+    Hash,
+    clap::ValueEnum,
+)]
+#[serde(rename_all = "kebab-case")]
+pub enum PunycodeOverflow {
+    /// Fail the decode with a `punycode` error.
+    #[default]
+    Reject,
+    /// Keep the label in its `xn--` form and decode the rest.
+    Keep,
+}
+
+/// One of the IDNA error categories a name hit, with what it means.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema, PartialEq, Eq)]
+pub struct IdnaError {
+    pub category: String,
+    pub message: String,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, ToSchema, PartialEq, Eq)]
@@ -18,18 +122,95 @@ pub struct HealthzResponse {
     pub ok: bool,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema, PartialEq, Eq)]
+pub struct VersionResponse {
+    pub version: String,
+    pub db_path: String,
+    pub db_generation: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dataset: Option<DatasetInfo>,
+    /// The named datasets served next to the default DB.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub datasets: BTreeMap<String, DatasetInfo>,
+}
+
+/// The domain DB behind a response, as recorded in its `meta` table, so
+/// results can be traced back to the exact dataset that produced them.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, ToSchema, PartialEq, Eq)]
+pub struct DatasetInfo {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    /// When the dataset was built (`db pack`) or published, as RFC 3339.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub date: Option<String>,
+    /// Rows in `legit_domains`.
+    pub domains: u64,
+    /// Where the domains came from (`db pack --source`, or the download URL).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, ToSchema, PartialEq, Eq)]
+pub struct ReloadRequest {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema, PartialEq, Eq)]
+pub struct ReloadResponse {
+    pub db_path: String,
+    pub generation: u64,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, ToSchema, PartialEq, Eq)]
 pub struct AsciiResponse {
     pub q: String,
     pub ascii: bool,
     pub puny: bool,
+    /// The query is itself a known legitimate domain (in the domain DB, the
+    /// most-phished list or under one of its domains, or the tenant's
+    /// brands). It is never listed in `results`.
+    #[serde(default)]
+    pub exact_match: bool,
+    /// The protected brand the query belongs to: a most-phished domain (or
+    /// the tenant's brand) that the query is, or is a host under. Set, the
+    /// query is that brand rather than an impersonation of it; lookalikes
+    /// leave it out and list the brands they imitate in `results`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub protected_brand: Option<String>,
     pub results: Vec<AsciiResult>,
+    /// The DB the results came from; absent when no DB was used.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dataset: Option<DatasetInfo>,
+    /// Domains scored against the query: the most-phished list, then any DB
+    /// candidates. Absent when nothing was scored.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub total_candidates_considered: Option<usize>,
+    /// What the passive DNS provider recorded of the query, when it is a
+    /// lookalike and `[passive_dns]` is configured.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub passive_dns: Option<PassiveDns>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, ToSchema, PartialEq, Eq)]
 pub struct AsciiResult {
     pub domain: String,
     pub similarity: u8,
+    /// Each scorer's similarity and weight, with `--explain`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub breakdown: Option<Vec<ScoreComponent>>,
+}
+
+/// One scorer's part in an ASCII similarity.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema, PartialEq, Eq)]
+pub struct ScoreComponent {
+    /// `edit_distance`, `confusable`, `phonetic`, `ngram`, or `keyboard`.
+    pub scorer: String,
+    /// The scorer's weight from `[scoring]`; 0 when it is not used.
+    pub weight: u16,
+    pub similarity: u8,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, ToSchema, PartialEq, Eq)]
@@ -38,6 +219,21 @@ pub struct IdnResponse {
     pub ascii: bool,
     pub puny: bool,
     pub results: Vec<IdnResult>,
+    /// The DB of the process that answered; IDN lookups do not read it, but
+    /// report it so every response names its dataset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dataset: Option<DatasetInfo>,
+    /// Confusable variants enumerated for the query. Absent when it did not
+    /// decode.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub total_candidates_considered: Option<usize>,
+    /// Why the query did not decode; empty when it did.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub idna_errors: Vec<IdnaError>,
+    /// What the passive DNS provider recorded of the query, when it is a
+    /// lookalike and `[passive_dns]` is configured.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub passive_dns: Option<PassiveDns>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, ToSchema, PartialEq, Eq)]
@@ -45,6 +241,64 @@ pub struct IdnResult {
     pub domain: String,
     pub mappings: Vec<PunyMapping>,
     pub is_registered: bool,
+    pub registration_checked: bool,
+    /// Outcome of the registration check; absent when it was skipped.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schema(inline)]
+    pub registration_status: Option<RegistrationStatus>,
+}
+
+/// `unknown` means DNS gave no usable answer (timeout, resolver error), so
+/// the variant may still be registered.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, ToSchema, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum RegistrationStatus {
+    Registered,
+    Unregistered,
+    Unknown,
+}
+
+impl RegistrationStatus {
+    pub fn from_registered(registered: bool) -> Self {
+        if registered {
+            Self::Registered
+        } else {
+            Self::Unregistered
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Registered => "registered",
+            Self::Unregistered => "unregistered",
+            Self::Unknown => "unknown",
+        }
+    }
+
+    /// `Some(registered)` for a definite answer, `None` for `Unknown`.
+    pub fn registered(self) -> Option<bool> {
+        match self {
+            Self::Registered => Some(true),
+            Self::Unregistered => Some(false),
+            Self::Unknown => None,
+        }
+    }
+}
+
+/// A domain's history in passive DNS. A lookalike first seen resolving
+/// yesterday is a live campaign; one resolving for years is likely parked or
+/// benign. No timestamps and no `ips` means the provider never saw it resolve.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, ToSchema, PartialEq, Eq)]
+pub struct PassiveDns {
+    /// When it was first seen resolving, as RFC 3339.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub first_seen: Option<String>,
+    /// When it was last seen resolving, as RFC 3339.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_seen: Option<String>,
+    /// Addresses (A and AAAA) it resolved to, most recently seen first.
+    #[serde(default)]
+    pub ips: Vec<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, ToSchema, PartialEq, Eq)]
@@ -65,11 +319,101 @@ pub fn empty_ascii_response(domain: &str) -> AsciiResponse {
         q: domain.to_string(),
         ascii: true,
         puny: false,
+        exact_match: false,
+        protected_brand: None,
         results: Vec::new(),
+        dataset: None,
+        total_candidates_considered: None,
+        passive_dns: None,
+    }
+}
+
+const MAX_DOMAIN_LEN: usize = 253;
+const MAX_LABEL_LEN: usize = 63;
+
+/// A lookup input that is not a usable hostname.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidDomain {
+    pub input: String,
+    pub reason: String,
+}
+
+impl std::fmt::Display for InvalidDomain {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "invalid domain '{}': {}", self.input, self.reason)
     }
 }
 
+impl std::error::Error for InvalidDomain {}
+
 pub fn normalize_domain_input(input: &str) -> String {
+    registrable_domain(input_host(input))
+}
+
+/// Validates `input` as a hostname and normalizes it like
+/// `normalize_domain_input`.
+pub fn parse_domain_input(input: &str) -> Result<String, InvalidDomain> {
+    validate_domain_input(input)?;
+    Ok(normalize_domain_input(input))
+}
+
+/// Rejects inputs whose host has whitespace, characters outside letters,
+/// digits, and hyphens, empty labels, or label/name length violations (checked
+/// on the ASCII form, so IDN labels are measured as punycode).
+pub fn validate_domain_input(input: &str) -> Result<(), InvalidDomain> {
+    let invalid = |reason: String| InvalidDomain {
+        input: input.to_string(),
+        reason,
+    };
+    let host = input_host(input);
+    if host.is_empty() {
+        return Err(invalid("no hostname given".to_string()));
+    }
+    if let Some(c) = host.chars().find(|c| c.is_whitespace() || c.is_control()) {
+        return Err(invalid(format!(
+            "contains whitespace or control character {c:?}"
+        )));
+    }
+    if let Some(c) = host
+        .chars()
+        .find(|&c| c.is_ascii() && !(c.is_ascii_alphanumeric() || c == '-' || c == '.'))
+    {
+        return Err(invalid(format!("contains illegal character '{c}'")));
+    }
+    for label in host.split('.') {
+        if label.is_empty() {
+            return Err(invalid("has an empty label".to_string()));
+        }
+        if label.starts_with('-') || label.ends_with('-') {
+            return Err(invalid(format!(
+                "label '{label}' starts or ends with a hyphen"
+            )));
+        }
+    }
+    let ascii = if host.is_ascii() {
+        host.clone()
+    } else {
+        idna::domain_to_ascii(&host)
+            .map_err(|_| invalid("is not a valid internationalized domain name".to_string()))?
+    };
+    if ascii.len() > MAX_DOMAIN_LEN {
+        return Err(invalid(format!(
+            "is longer than {MAX_DOMAIN_LEN} characters"
+        )));
+    }
+    if let Some(label) = ascii.split('.').find(|label| label.len() > MAX_LABEL_LEN) {
+        return Err(invalid(format!(
+            "label '{label}' is longer than {MAX_LABEL_LEN} characters"
+        )));
+    }
+    Ok(())
+}
+
+/// The canonical host part of `input`: scheme, path, query, fragment, a
+/// numeric port, the trailing root dot, and a leading `www.` are removed, and
+/// the rest is lowercased with Unicode case mapping, so `WWW.PayPa1.COM.` and
+/// `paypa1.com` look the same to every analyzer.
+fn input_host(input: &str) -> String {
     let trimmed = input.trim();
     if trimmed.is_empty() {
         return String::new();
@@ -96,6 +440,15 @@ pub fn normalize_domain_input(input: &str) -> String {
         }
     }
 
+    let host = host.strip_suffix('.').unwrap_or(&host).to_lowercase();
+    match host.strip_prefix("www.") {
+        Some(rest) if rest.contains('.') => rest.to_string(),
+        _ => host,
+    }
+}
+
+/// The last two labels of `host`.
+fn registrable_domain(host: String) -> String {
     let parts: Vec<&str> = host.split('.').filter(|p| !p.is_empty()).collect();
     if parts.len() >= 2 {
         format!("{}.{}", parts[parts.len() - 2], parts[parts.len() - 1])
@@ -104,10 +457,143 @@ pub fn normalize_domain_input(input: &str) -> String {
     }
 }
 
+/// Whether `domain` belongs to the IDN analyzer: some label under its TLD is
+/// internationalized (see `has_idn_label`). An internationalized TLD alone
+/// does not count, since its characters are the registry's rather than the
+/// spoofer's: `paypa1.рф` is an ASCII lookalike.
+pub fn is_idn(domain: &str) -> bool {
+    match split_tld(domain) {
+        Some((name, tld)) if is_idn_tld(tld) => has_idn_label(name),
+        _ => has_idn_label(domain),
+    }
+}
+
+/// Whether some label of `domain` is punycode (`xn--`, in any case) or
+/// already contains non-ASCII characters. Every label is checked, not just
+/// the first.
+pub fn has_idn_label(domain: &str) -> bool {
+    domain
+        .split('.')
+        .any(|label| !label.is_ascii() || is_punycode(label))
+}
+
+fn is_punycode(label: &str) -> bool {
+    label
+        .get(..4)
+        .is_some_and(|prefix| prefix.eq_ignore_ascii_case("xn--"))
+}
+
+/// `domain` split into the labels under its TLD and the TLD (`пример`,
+/// `рф`), ignoring a trailing root dot. `None` for a single label.
+pub fn split_tld(domain: &str) -> Option<(&str, &str)> {
+    domain.trim_end_matches('.').rsplit_once('.')
+}
+
+/// Whether `tld` is an internationalized TLD: punycode (`xn--p1ai`) or
+/// written without ASCII at all (`рф`, `中国`). A TLD mixing scripts, like
+/// `соm` with a Cyrillic `с` and `о`, is a homoglyph of an ASCII one instead.
+pub fn is_idn_tld(tld: &str) -> bool {
+    is_punycode(tld) || (!tld.is_empty() && !tld.chars().any(|c| c.is_ascii()))
+}
+
+/// `domain` with an internationalized TLD in its ASCII (punycode) form, so
+/// `paypa1.рф` and `paypa1.xn--p1ai` compare as the same name. Other domains
+/// are returned unchanged.
+pub fn ascii_tld(domain: &str) -> String {
+    match split_tld(domain) {
+        Some((name, tld)) if is_idn_tld(tld) && !tld.is_ascii() => {
+            match idna::domain_to_ascii(tld) {
+                Ok(tld) => format!("{name}.{tld}"),
+                Err(_) => domain.to_string(),
+            }
+        }
+        _ => domain.to_string(),
+    }
+}
+
+fn validate<T: Serialize + DeserializeOwned>(value: &T) -> Result<()> {
+    let json = serde_json::to_value(value)?;
+    let _: T = serde_json::from_value(json)?;
+    Ok(())
+}
+
+pub fn validate_ascii_response(value: &AsciiResponse) -> Result<()> {
+    validate(value)
+}
+
+pub fn validate_idn_response(value: &IdnResponse) -> Result<()> {
+    validate(value)
+}
+
+pub fn validate_lookup_response(value: &LookupResponse) -> Result<()> {
+    validate(value)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn validate_domain_input_rejects_garbage() {
+        for valid in [
+            "google.com",
+            "https://login.g00gle.com:8443/path?q=1",
+            "example.com.",
+            "амаzоn.com",
+            "xn--ggle-0nda.com",
+            "my-site.co.uk",
+        ] {
+            assert_eq!(validate_domain_input(valid), Ok(()), "input={valid}");
+        }
+        let long_label = format!("{}.com", "a".repeat(64));
+        let long_name = vec!["abcdefghi"; 26].join(".");
+        for (invalid, reason) in [
+            ("", "no hostname given"),
+            ("   ", "no hostname given"),
+            ("goo gle.com", "contains whitespace"),
+            ("goo\tgle.com", "contains whitespace"),
+            ("goo\u{7}gle.com", "control character"),
+            ("goo_gle.com", "illegal character '_'"),
+            ("g!oogle.com", "illegal character '!'"),
+            ("google..com", "empty label"),
+            (".google.com", "empty label"),
+            ("-google.com", "hyphen"),
+            ("google-.com", "hyphen"),
+            (long_label.as_str(), "longer than 63"),
+            (long_name.as_str(), "longer than 253"),
+        ] {
+            let err = validate_domain_input(invalid).unwrap_err();
+            assert!(err.reason.contains(reason), "input={invalid:?} err={err}");
+        }
+    }
+
+    #[test]
+    fn is_idn_checks_every_label() {
+        assert!(is_idn("xn--ggle-0nda.com"));
+        assert!(is_idn("login.xn--ggle-0nda.com"));
+        assert!(is_idn("XN--GGLE-0NDA.COM"));
+        assert!(is_idn("gооgle.com"));
+        assert!(is_idn("shop.xn--e1afmkfd.xn--p1ai"));
+        assert!(!is_idn("google.com"));
+        assert!(!is_idn("xn-google.com"));
+        assert!(!is_idn("axn--b.com"));
+        assert!(!is_idn(""));
+    }
+
+    #[test]
+    fn internationalized_tlds_leave_ascii_names_to_the_ascii_analyzer() {
+        assert!(is_idn("пример.рф"));
+        assert!(is_idn("gооgle.中国"));
+        assert!(!is_idn("shop.example.xn--p1ai"));
+        assert!(!is_idn("paypa1.рф"));
+        assert!(is_idn("apple.соm"), "mixed-script TLD");
+        assert!(has_idn_label("paypa1.рф"));
+        assert_eq!(split_tld("пример.рф."), Some(("пример", "рф")));
+        assert_eq!(ascii_tld("paypa1.рф"), "paypa1.xn--p1ai");
+        assert_eq!(ascii_tld("paypa1.中国"), "paypa1.xn--fiqs8s");
+        assert_eq!(ascii_tld("paypa1.com"), "paypa1.com");
+    }
+
     #[test]
     fn normalize_domain_input_strips_scheme_path_and_subdomains() {
         let cases = [
@@ -128,6 +614,10 @@ mod tests {
             ("https://sub.example.com/path", "example.com"),
             ("ftp://a.b.c", "b.c"),
             ("example.com/path", "example.com"),
+            ("WWW.PayPa1.COM.", "paypa1.com"),
+            ("https://www.paypa1.com.:443/login", "paypa1.com"),
+            ("www.com", "www.com"),
+            ("АМАЗОН.com", "амазон.com"),
         ];
 
         for (input, expected) in cases {
@@ -135,21 +625,3 @@ mod tests {
         }
     }
 }
-
-fn validate<T: Serialize + DeserializeOwned>(value: &T) -> Result<()> {
-    let json = serde_json::to_value(value)?;
-    let _: T = serde_json::from_value(json)?;
-    Ok(())
-}
-
-pub fn validate_ascii_response(value: &AsciiResponse) -> Result<()> {
-    validate(value)
-}
-
-pub fn validate_idn_response(value: &IdnResponse) -> Result<()> {
-    validate(value)
-}
-
-pub fn validate_lookup_response(value: &LookupResponse) -> Result<()> {
-    validate(value)
-}
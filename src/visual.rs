@@ -0,0 +1,232 @@
+/*
+Rendered-glyph visual similarity. Confusable tables only know the pairs
+someone listed; what a victim sees is the rendered name. Names are drawn with a
+bundled font (DejaVu Sans, which covers Latin, Greek, and Cyrillic) and
+compared as an edit distance whose substitutions cost what the glyphs look
+like: two renderings are blurred onto a coarse grid and correlated, so `l`
+and `I` or `rn` and `m` (one character drawn as two) cost next to nothing while
+unrelated letters cost a full edit. Rendering costs far more than a plain edit
+distance, so it is only used on request (`compare --visual`).
+*/
+use ab_glyph::{point, Font, FontRef, PxScale, ScaleFont};
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+const FONT: &[u8] = include_bytes!("../data/fonts/DejaVuSans.ttf");
+/// Glyph height in pixels.
+const SCALE: f32 = 32.0;
+/// Side in pixels of the grid cells renderings are blurred onto.
+const CELL: usize = 3;
+/// The correlation that unrelated glyph pairs typically reach; at or below
+/// it, a substitution costs a full edit.
+const CORRELATION_FLOOR: f32 = 0.5;
+
+static PARSED: OnceLock<FontRef<'static>> = OnceLock::new();
+
+fn font() -> &'static FontRef<'static> {
+    PARSED.get_or_init(|| FontRef::try_from_slice(FONT).expect("bundled font parses"))
+}
+
+/// A grayscale rendering: coverage 0 (paper) to 1 (ink), row-major.
+#[derive(Debug, Clone, PartialEq)]
+struct Raster {
+    width: usize,
+    height: usize,
+    pixels: Vec<f32>,
+}
+
+impl Raster {
+    /// The mean coverage of the cell (`col`, `row`) when the raster is cut
+    /// into `cols` x `rows` equal cells.
+    fn cell(&self, col: usize, row: usize, cols: usize, rows: usize) -> f32 {
+        let x0 = col * self.width / cols;
+        let x1 = ((col + 1) * self.width / cols).max(x0 + 1).min(self.width);
+        let y0 = row * self.height / rows;
+        let y1 = ((row + 1) * self.height / rows)
+            .max(y0 + 1)
+            .min(self.height);
+        if x0 >= x1 || y0 >= y1 {
+            return 0.0;
+        }
+        let mut sum = 0.0;
+        for y in y0..y1 {
+            sum += self.pixels[y * self.width + x0..y * self.width + x1]
+                .iter()
+                .sum::<f32>();
+        }
+        sum / ((x1 - x0) * (y1 - y0)) as f32
+    }
+
+    /// The raster centered on a canvas `width` pixels wide.
+    fn centered(&self, width: usize) -> Raster {
+        let offset = width.saturating_sub(self.width) / 2;
+        let mut pixels = vec![0.0; width * self.height];
+        for y in 0..self.height {
+            let row = &self.pixels[y * self.width..(y + 1) * self.width];
+            pixels[y * width + offset..y * width + offset + self.width].copy_from_slice(row);
+        }
+        Raster {
+            width,
+            height: self.height,
+            pixels,
+        }
+    }
+}
+
+/// Draws `text` on one line, kerned.
+fn render(text: &str) -> Raster {
+    let font = font();
+    let scaled = font.as_scaled(PxScale::from(SCALE));
+    let ascent = scaled.ascent();
+    let height = (ascent - scaled.descent()).ceil() as usize;
+    let mut glyphs = Vec::new();
+    let mut x = 0.0f32;
+    let mut previous = None;
+    for c in text.chars() {
+        let id = scaled.glyph_id(c);
+        if let Some(previous) = previous {
+            x += scaled.kern(previous, id);
+        }
+        glyphs.push(id.with_scale_and_position(SCALE, point(x, ascent)));
+        x += scaled.h_advance(id);
+        previous = Some(id);
+    }
+    let width = (x.ceil() as usize).max(1);
+    let mut pixels = vec![0.0f32; width * height];
+    for glyph in glyphs {
+        let Some(outlined) = font.outline_glyph(glyph) else {
+            continue;
+        };
+        let bounds = outlined.px_bounds();
+        outlined.draw(|gx, gy, coverage| {
+            let px = bounds.min.x as i64 + i64::from(gx);
+            let py = bounds.min.y as i64 + i64::from(gy);
+            if (0..width as i64).contains(&px) && (0..height as i64).contains(&py) {
+                let pixel = &mut pixels[py as usize * width + px as usize];
+                *pixel = (*pixel + coverage).min(1.0);
+            }
+        });
+    }
+    Raster {
+        width,
+        height,
+        pixels,
+    }
+}
+
+/// The Pearson correlation of `a` and `b` blurred onto the same grid. With
+/// `stretch`, each fills the grid's width; otherwise both are centered.
+fn correlation(a: &Raster, b: &Raster, stretch: bool) -> f32 {
+    let width = a.width.max(b.width);
+    let (a, b) = if stretch {
+        (a.clone(), b.clone())
+    } else {
+        (a.centered(width), b.centered(width))
+    };
+    let (cols, rows) = ((width / CELL).max(1), (a.height / CELL).max(1));
+    let mut xs = Vec::with_capacity(cols * rows);
+    let mut ys = Vec::with_capacity(cols * rows);
+    for row in 0..rows {
+        for col in 0..cols {
+            xs.push(a.cell(col, row, cols, rows));
+            ys.push(b.cell(col, row, cols, rows));
+        }
+    }
+    let n = xs.len() as f32;
+    let (mean_x, mean_y) = (xs.iter().sum::<f32>() / n, ys.iter().sum::<f32>() / n);
+    let (mut covariance, mut var_x, mut var_y) = (0.0, 0.0, 0.0);
+    for (x, y) in xs.iter().zip(&ys) {
+        covariance += (x - mean_x) * (y - mean_y);
+        var_x += (x - mean_x) * (x - mean_x);
+        var_y += (y - mean_y) * (y - mean_y);
+    }
+    if var_x == 0.0 || var_y == 0.0 {
+        return 0.0;
+    }
+    covariance / (var_x * var_y).sqrt()
+}
+
+/// Renders and compares the short strings of single edits, memoised.
+#[derive(Default)]
+struct Glyphs {
+    rasters: HashMap<String, Raster>,
+    costs: HashMap<(String, String), f32>,
+}
+
+impl Glyphs {
+    fn raster(&mut self, text: &str) -> Raster {
+        self.rasters
+            .entry(text.to_string())
+            .or_insert_with(|| render(text))
+            .clone()
+    }
+
+    /// What replacing `a` with `b` costs, from 0 (same look) to 1 (a full
+    /// edit). Strings of different lengths (`rn` for `m`) are stretched to
+    /// the same width first.
+    fn cost(&mut self, a: &[char], b: &[char]) -> f32 {
+        if a == b {
+            return 0.0;
+        }
+        let key: (String, String) = (a.iter().collect(), b.iter().collect());
+        if let Some(cost) = self.costs.get(&key) {
+            return *cost;
+        }
+        let (raster_a, raster_b) = (self.raster(&key.0), self.raster(&key.1));
+        let likeness = (correlation(&raster_a, &raster_b, a.len() != b.len()) - CORRELATION_FLOOR)
+            / (1.0 - CORRELATION_FLOOR);
+        let cost = 1.0 - likeness.clamp(0.0, 1.0);
+        self.costs.insert(key, cost);
+        cost
+    }
+}
+
+/// How alike `a` and `b` look when rendered (0-100): 100 minus the visual
+/// edit distance per character of the longer name.
+pub fn similarity(a: &str, b: &str) -> u8 {
+    if a == b {
+        return 100;
+    }
+    let (a, b): (Vec<char>, Vec<char>) = (a.chars().collect(), b.chars().collect());
+    let (n, m) = (a.len(), b.len());
+    let mut glyphs = Glyphs::default();
+    let mut d = vec![vec![0.0f32; m + 1]; n + 1];
+    for (i, row) in d.iter_mut().enumerate() {
+        row[0] = i as f32;
+    }
+    for (j, cell) in d[0].iter_mut().enumerate() {
+        *cell = j as f32;
+    }
+    for i in 1..=n {
+        for j in 1..=m {
+            let mut cell = (d[i - 1][j] + 1.0)
+                .min(d[i][j - 1] + 1.0)
+                .min(d[i - 1][j - 1] + glyphs.cost(&a[i - 1..i], &b[j - 1..j]));
+            // Two characters drawn as one, either way round.
+            if i > 1 {
+                cell = cell.min(d[i - 2][j - 1] + glyphs.cost(&a[i - 2..i], &b[j - 1..j]));
+            }
+            if j > 1 {
+                cell = cell.min(d[i - 1][j - 2] + glyphs.cost(&a[i - 1..i], &b[j - 2..j]));
+            }
+            d[i][j] = cell;
+        }
+    }
+    (100.0 * (1.0 - d[n][m] / n.max(m) as f32)).round().max(0.0) as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lookalike_renderings_score_above_unrelated_names() {
+        assert_eq!(similarity("paypal.com", "paypal.com"), 100);
+        assert_eq!(similarity("аpple.com", "apple.com"), 100, "Cyrillic а");
+        assert!(similarity("rnicrosoft.com", "microsoft.com") >= 95);
+        assert!(similarity("vvikipedia.org", "wikipedia.org") >= 95);
+        assert!(similarity("paypa1.com", "paypal.com") >= 90);
+        assert!(similarity("paypal.com", "google.com") <= 70);
+        assert_eq!(similarity("", "x"), 0);
+    }
+}
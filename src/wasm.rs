@@ -0,0 +1,127 @@
+/*
+WebAssembly exports of the string-analysis core (the `wasm` feature), so a
+browser extension can score domains locally before asking a SpotSpoof server.
+Built as a cdylib with `cargo rustc --lib --release --target
+wasm32-unknown-unknown --no-default-features --features wasm --crate-type
+cdylib`, then run through `wasm-bindgen` for the JS glue.
+Nothing here reads a DB or the network: lookups check the built-in most-phished
+list and any caller-supplied targets, and IDN domains are left to the server.
+*/
+use clap::ValueEnum;
+use wasm_bindgen::prelude::wasm_bindgen;
+
+use crate::ascii_spoof::{self, AsciiOptions};
+use crate::candidates;
+use crate::types::{self, Algorithm, LookupResponse};
+
+/// The registrable domain of a domain or URL, lowercased; `undefined` when the
+/// input is not a valid domain.
+#[wasm_bindgen(js_name = normalizeDomain)]
+pub fn normalize_domain(input: &str) -> Option<String> {
+    types::parse_domain_input(input).ok()
+}
+
+/// Whether `domain` has a non-ASCII or punycode label under its TLD.
+#[wasm_bindgen(js_name = isIdn)]
+pub fn is_idn(domain: &str) -> bool {
+    types::is_idn(domain)
+}
+
+/// Similarity percentage of two domains under `algorithm` (`levenshtein`, the
+/// default, or `damerau-levenshtein`); `undefined` for an unknown algorithm.
+#[wasm_bindgen]
+pub fn similarity(a: &str, b: &str, algorithm: Option<String>) -> Option<u8> {
+    let algorithm = match algorithm {
+        Some(name) => Algorithm::from_str(&name, true).ok()?,
+        None => Algorithm::default(),
+    };
+    Some(ascii_spoof::similarity_with(algorithm, a, b))
+}
+
+/// The ASCII confusable skeleton of `domain` (`rn`/`m`, `0`/`o`, `1`/`l`, ...).
+#[wasm_bindgen]
+pub fn skeleton(domain: &str) -> String {
+    candidates::skeleton(domain)
+}
+
+/// Checks `domain` against the most-phished list and returns the same JSON as
+/// `spotspoof lookup`, or `{"error": ...}`.
+#[wasm_bindgen(js_name = lookupJson)]
+pub fn lookup_json(domain: &str, min_similarity: Option<u8>) -> String {
+    let domain = match types::parse_domain_input(domain) {
+        Ok(domain) => domain,
+        Err(err) => return error_json(&err.to_string()),
+    };
+    if types::is_idn(&domain) {
+        return error_json("IDN lookups need a SpotSpoof server");
+    }
+    let response = LookupResponse::Ascii(ascii_spoof::lookup_most_phished(
+        &domain,
+        &options(min_similarity),
+    ));
+    serde_json::to_string(&response).unwrap_or_else(|err| error_json(&err.to_string()))
+}
+
+/// Scores `domain` against `targets` (e.g. the brands an extension protects)
+/// and returns the matching targets as JSON `[{"domain", "similarity"}]`.
+#[wasm_bindgen(js_name = scoreTargetsJson)]
+pub fn score_targets_json(
+    domain: &str,
+    targets: Vec<String>,
+    min_similarity: Option<u8>,
+) -> String {
+    let results = ascii_spoof::score_targets(domain, &targets, &options(min_similarity));
+    serde_json::to_string(&results).unwrap_or_else(|err| error_json(&err.to_string()))
+}
+
+fn options(min_similarity: Option<u8>) -> AsciiOptions {
+    let defaults = AsciiOptions::default();
+    AsciiOptions {
+        min_similarity: min_similarity.unwrap_or(defaults.min_similarity),
+        ..defaults
+    }
+}
+
+fn error_json(message: &str) -> String {
+    serde_json::json!({ "error": message }).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+
+    #[test]
+    fn exports_work_without_a_db() {
+        assert_eq!(
+            normalize_domain("https://WWW.PayPal.com/login").as_deref(),
+            Some("paypal.com")
+        );
+        assert_eq!(normalize_domain("not a domain"), None);
+        assert_eq!(skeleton("rnicrosoft.com"), skeleton("microsoft.com"));
+        assert_eq!(similarity("googel.com", "google.com", None), Some(80));
+        assert_eq!(
+            similarity(
+                "googel.com",
+                "google.com",
+                Some("damerau-levenshtein".into())
+            ),
+            Some(90)
+        );
+        assert_eq!(similarity("a.com", "b.com", Some("soundex".into())), None);
+
+        let lookup: Value = serde_json::from_str(&lookup_json("paypa1.com", None)).unwrap();
+        assert_eq!(lookup["results"][0]["domain"], "paypal.com");
+        let idn: Value = serde_json::from_str(&lookup_json("xn--pypal-4ve.com", None)).unwrap();
+        assert!(idn["error"].is_string());
+
+        let scored: Value = serde_json::from_str(&score_targets_json(
+            "examp1e.org",
+            vec!["example.org".into(), "unrelated.net".into()],
+            None,
+        ))
+        .unwrap();
+        assert_eq!(scored.as_array().unwrap().len(), 1);
+        assert_eq!(scored[0]["domain"], "example.org");
+    }
+}
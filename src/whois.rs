@@ -4,47 +4,196 @@ to determine whether candidate domains are registered.
 */
 use anyhow::Result;
 use serde::Deserialize;
-use std::time::Duration;
+use std::net::Ipv4Addr;
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+use crate::types::RegistrationStatus;
+use crate::{dns_cache, net};
 
 #[derive(Deserialize)]
 struct DnsResponse {
+    /// DNS response code (0 NOERROR, 2 SERVFAIL, 3 NXDOMAIN, ...).
+    #[serde(rename = "Status")]
+    status: Option<u32>,
     #[serde(rename = "Answer")]
     answer: Option<Vec<serde_json::Value>>,
 }
 
-pub fn check_domain_registration(domain: &str, timeout_ms: u64) -> Result<(bool, bool)> {
-    check_domain_registration_with_url(domain, timeout_ms, "https://dns.google/resolve")
+const DOH_URL: &str = "https://dns.google/resolve";
+const RCODE_NOERROR: u32 = 0;
+const RCODE_NXDOMAIN: u32 = 3;
+const RRTYPE_A: u32 = 1;
+
+/// Where a registration answer came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheckMethod {
+    Doh,
+    Cache,
+}
+
+/// The outcome of one registration check. `Unknown` means the resolver gave no
+/// usable answer (timeout, HTTP error, SERVFAIL, ...), which must not be read
+/// as "unregistered".
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RegistrationCheck {
+    pub status: RegistrationStatus,
+    pub method: CheckMethod,
+    /// DNS response code, when the resolver answered.
+    pub rcode: Option<u32>,
+    pub latency: Duration,
+    pub timed_out: bool,
+    pub error: Option<String>,
+}
+
+impl RegistrationCheck {
+    fn unknown(latency: Duration, timed_out: bool, error: String) -> Self {
+        Self {
+            status: RegistrationStatus::Unknown,
+            method: CheckMethod::Doh,
+            rcode: None,
+            latency,
+            timed_out,
+            error: Some(error),
+        }
+    }
+
+    fn cached(registered: bool) -> Self {
+        Self {
+            status: RegistrationStatus::from_registered(registered),
+            method: CheckMethod::Cache,
+            rcode: None,
+            latency: Duration::ZERO,
+            timed_out: false,
+            error: None,
+        }
+    }
+}
+
+/// Checks whether `domain` has NS records. Definite answers are served from
+/// and stored in the installed `dns_cache`, if any; `Unknown` results are
+/// never cached.
+pub fn check_domain_registration(domain: &str, timeout_ms: u64) -> Result<RegistrationCheck> {
+    net::ensure_online("DNS registration checks")?;
+    let Some(cache) = dns_cache::installed() else {
+        return check_domain_registration_with_url(domain, timeout_ms, DOH_URL);
+    };
+    match cache.get(domain) {
+        Ok(Some(registered)) => return Ok(RegistrationCheck::cached(registered)),
+        Ok(None) => {}
+        Err(err) => warn!(kind = "dns_cache", domain = %domain, "cache read failed: {err}"),
+    }
+    let check = check_domain_registration_with_url(domain, timeout_ms, DOH_URL)?;
+    if let Some(registered) = check.status.registered() {
+        if let Err(err) = cache.put(domain, registered) {
+            warn!(kind = "dns_cache", domain = %domain, "cache write failed: {err}");
+        }
+    }
+    Ok(check)
 }
 
 fn check_domain_registration_with_url(
     domain: &str,
     timeout_ms: u64,
     base_url: &str,
-) -> Result<(bool, bool)> {
-    let client = reqwest::blocking::Client::builder()
+) -> Result<RegistrationCheck> {
+    let client = net::client_builder()
         .timeout(Duration::from_millis(timeout_ms))
         .build()?;
 
+    let started = Instant::now();
+    if !net::acquire_doh(Duration::from_millis(timeout_ms)) {
+        return Ok(RegistrationCheck::unknown(
+            started.elapsed(),
+            true,
+            "outbound DoH rate limit reached".to_string(),
+        ));
+    }
+
     let url = format!("{base_url}?name={domain}&type=NS");
-    let resp = client
-        .get(url)
-        .header("Accept", "application/dns-json")
-        .send();
-
-    match resp {
-        Ok(response) => {
-            if !response.status().is_success() {
-                return Ok((false, false));
-            }
-            let data: DnsResponse = response.json()?;
-            let registered = data.answer.map(|a| !a.is_empty()).unwrap_or(false);
-            Ok((registered, false))
+    let resp = net::send_with_retry("doh", || {
+        client.get(&url).header("Accept", "application/dns-json")
+    });
+
+    let response = match resp {
+        Ok(response) => response,
+        Err(err) => {
+            return Ok(RegistrationCheck::unknown(
+                started.elapsed(),
+                err.is_timeout(),
+                err.to_string(),
+            ))
         }
+    };
+    if !response.status().is_success() {
+        return Ok(RegistrationCheck::unknown(
+            started.elapsed(),
+            false,
+            format!("resolver returned HTTP {}", response.status()),
+        ));
+    }
+    let data: DnsResponse = match response.json() {
+        Ok(data) => data,
         Err(err) => {
-            let timed_out = err.is_timeout();
-            Ok((false, timed_out))
+            return Ok(RegistrationCheck::unknown(
+                started.elapsed(),
+                err.is_timeout(),
+                format!("invalid resolver response: {err}"),
+            ))
         }
+    };
+    let has_answer = data.answer.is_some_and(|answer| !answer.is_empty());
+    let (status, error) = match data.status {
+        Some(RCODE_NXDOMAIN) => (RegistrationStatus::Unregistered, None),
+        Some(RCODE_NOERROR) | None => (RegistrationStatus::from_registered(has_answer), None),
+        Some(rcode) => (
+            RegistrationStatus::Unknown,
+            Some(format!("resolver returned rcode {rcode}")),
+        ),
+    };
+    Ok(RegistrationCheck {
+        status,
+        method: CheckMethod::Doh,
+        rcode: data.status,
+        latency: started.elapsed(),
+        timed_out: false,
+        error,
+    })
+}
+
+/// The IPv4 addresses `domain` resolves to (its A records, after any CNAMEs),
+/// over DoH. Empty for NXDOMAIN or a name without A records.
+pub fn resolve_a(domain: &str, timeout_ms: u64) -> Result<Vec<Ipv4Addr>> {
+    net::ensure_online("resolving")?;
+    resolve_a_with_url(domain, timeout_ms, DOH_URL)
+}
+
+fn resolve_a_with_url(domain: &str, timeout_ms: u64, base_url: &str) -> Result<Vec<Ipv4Addr>> {
+    let client = net::client_builder()
+        .timeout(Duration::from_millis(timeout_ms))
+        .build()?;
+    if !net::acquire_doh(Duration::from_millis(timeout_ms)) {
+        anyhow::bail!("outbound DoH rate limit reached");
+    }
+    let url = format!("{base_url}?name={domain}&type=A");
+    let response = net::send_with_retry("doh", || {
+        client.get(&url).header("Accept", "application/dns-json")
+    })?;
+    if !response.status().is_success() {
+        anyhow::bail!("resolver returned HTTP {}", response.status());
     }
+    let data: DnsResponse = response.json()?;
+    match data.status {
+        Some(RCODE_NOERROR | RCODE_NXDOMAIN) | None => {}
+        Some(rcode) => anyhow::bail!("resolver returned rcode {rcode}"),
+    }
+    Ok(data
+        .answer
+        .unwrap_or_default()
+        .iter()
+        .filter(|record| record["type"] == RRTYPE_A)
+        .filter_map(|record| record["data"].as_str()?.parse().ok())
+        .collect())
 }
 
 #[cfg(test)]
@@ -97,19 +246,58 @@ mod tests {
         )
         .into_bytes();
         let url = start_server(response, "GET", "/resolve?");
-        let (registered, timed_out) =
-            check_domain_registration_with_url("example.com", 1000, &url).unwrap();
-        assert!(registered);
-        assert!(!timed_out);
+        let check = check_domain_registration_with_url("example.com", 1000, &url).unwrap();
+        assert_eq!(check.status, RegistrationStatus::Registered);
+        assert_eq!(check.method, CheckMethod::Doh);
+        assert!(!check.timed_out);
+    }
+
+    #[test]
+    fn check_domain_registration_reads_rcodes() {
+        let respond = |body: &str| {
+            let response = format!(
+                "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{}",
+                body.len(),
+                body
+            );
+            let url = start_server(response.into_bytes(), "GET", "/resolve?");
+            check_domain_registration_with_url("examp1e.com", 1000, &url).unwrap()
+        };
+        let nxdomain = respond(r#"{"Status":3}"#);
+        assert_eq!(nxdomain.status, RegistrationStatus::Unregistered);
+        assert_eq!(nxdomain.rcode, Some(3));
+
+        let servfail = respond(r#"{"Status":2}"#);
+        assert_eq!(servfail.status, RegistrationStatus::Unknown);
+        assert_eq!(servfail.error.as_deref(), Some("resolver returned rcode 2"));
+    }
+
+    #[test]
+    fn resolve_a_follows_cnames_to_addresses() {
+        let body = r#"{"Status":0,"Answer":[{"name":"paypa1.com","type":5,"TTL":300,"data":"edge.example.net."},{"name":"edge.example.net","type":1,"TTL":60,"data":"203.0.113.7"}]}"#;
+        let response = format!(
+            "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{}",
+            body.len(),
+            body
+        );
+        let url = start_server(
+            response.into_bytes(),
+            "GET",
+            "/resolve?name=paypa1.com&type=A",
+        );
+        assert_eq!(
+            resolve_a_with_url("paypa1.com", 1000, &url).unwrap(),
+            [Ipv4Addr::new(203, 0, 113, 7)]
+        );
     }
 
     #[test]
     fn check_domain_registration_handles_non_success() {
         let response = b"HTTP/1.1 500 Internal Server Error\r\nContent-Length: 0\r\n\r\n".to_vec();
         let url = start_server(response, "GET", "/resolve?");
-        let (registered, timed_out) =
-            check_domain_registration_with_url("example.com", 1000, &url).unwrap();
-        assert!(!registered);
-        assert!(!timed_out);
+        let check = check_domain_registration_with_url("example.com", 1000, &url).unwrap();
+        assert_eq!(check.status, RegistrationStatus::Unknown);
+        assert!(!check.timed_out);
+        assert!(check.error.is_some());
     }
 }